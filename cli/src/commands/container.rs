@@ -1,18 +1,27 @@
 use clap::Subcommand;
 use ross_core::ross::container_service_client::ContainerServiceClient;
 use ross_core::ross::{
-    AttachRequest, ContainerConfig, CreateContainerRequest, ExecConfig, ExecRequest,
-    ExecStartRequest, GetLogsRequest, HostConfig, InspectContainerRequest, KillContainerRequest,
-    ListContainersRequest, PauseContainerRequest, PortBinding, RemoveContainerRequest,
-    RenameContainerRequest, RestartContainerRequest, StartContainerRequest, StatsRequest,
-    StopContainerRequest, UnpauseContainerRequest, WaitContainerRequest,
+    AttachRequest, ChangeKind, ContainerConfig, CreateContainerRequest, DiffContainerRequest,
+    ExecConfig, ExecRequest, ExecStartRequest, GetLogsRequest, HostConfig, InspectContainerRequest,
+    KillContainerRequest, ListContainersRequest, PauseContainerRequest, PortBinding,
+    RemoveContainerRequest, RenameContainerRequest, Resources, RestartContainerRequest,
+    RestartPolicy, StartContainerRequest, StatsRequest, StopContainerRequest, TopRequest,
+    UnpauseContainerRequest, UpdateContainerRequest, WaitContainerRequest,
     wait_container_output::Output,
 };
+use std::path::PathBuf;
 use tokio_stream::StreamExt;
 
-use crate::utils::{format_size, format_timestamp};
+use super::run::{create_container, setup_raw_mode};
+use crate::connection::Daemon;
+use crate::detach::{DetachKeys, DetachMatcher};
+use crate::utils::{
+    OutputFormat, entrypoint_args, format_ago, format_size, format_timestamp, merge_env,
+    merge_filters, print_json, print_template,
+};
 
 #[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)]
 pub enum ContainerCommands {
     /// Create a new container
     Create {
@@ -27,13 +36,74 @@ pub enum ContainerCommands {
         #[arg(long, short)]
         env: Vec<String>,
 
+        /// Read environment variables from a file of KEY=VAL lines
+        #[arg(long = "env-file", value_name = "PATH")]
+        env_file: Vec<PathBuf>,
+
+        /// Overwrite the image's entrypoint ("" clears it)
+        #[arg(long, value_name = "COMMAND")]
+        entrypoint: Option<String>,
+
         /// Publish a container's port(s) to the host (HOST:CONTAINER)
         #[arg(long = "publish", short = 'p')]
         publish: Vec<String>,
 
-        /// Bind mount a volume (SRC:DST)
+        /// Mount a volume: a host path (/SRC:DST[:OPTIONS]), a named volume
+        /// (NAME:DST[:OPTIONS]) or an anonymous volume (DST)
         #[arg(long, short)]
         volume: Vec<String>,
+
+        /// Mount the container's root filesystem as read only
+        #[arg(long)]
+        read_only: bool,
+
+        /// Mount a tmpfs (PATH[:OPTIONS])
+        #[arg(long, value_parser = crate::utils::parse_tmpfs)]
+        tmpfs: Vec<(String, String)>,
+
+        /// Network mode (bridge, host or none)
+        #[arg(long, value_name = "MODE")]
+        network: Option<String>,
+
+        /// Set a custom DNS server
+        #[arg(long, value_name = "IP")]
+        dns: Vec<String>,
+
+        /// Set a custom DNS search domain
+        #[arg(long, value_name = "DOMAIN")]
+        dns_search: Vec<String>,
+
+        /// Set a DNS resolver option (e.g. ndots:2)
+        #[arg(long = "dns-option", value_name = "OPTION")]
+        dns_options: Vec<String>,
+
+        /// Add a custom host-to-IP mapping (NAME:IP)
+        #[arg(long = "add-host", value_name = "NAME:IP")]
+        add_host: Vec<String>,
+
+        /// Only allow outgoing connections to this network (CIDR)
+        #[arg(long = "egress-allow", value_name = "CIDR")]
+        egress_allow: Vec<String>,
+
+        /// Refuse outgoing connections to this network (CIDR)
+        #[arg(long = "egress-deny", value_name = "CIDR")]
+        egress_deny: Vec<String>,
+
+        /// Memory limit (e.g. 512m, 1g)
+        #[arg(long, short = 'm', value_parser = crate::utils::parse_memory)]
+        memory: Option<i64>,
+
+        /// Number of CPUs (e.g. 1.5)
+        #[arg(long)]
+        cpus: Option<f64>,
+
+        /// Restart policy (no, always, unless-stopped, on-failure[:N])
+        #[arg(long, value_parser = crate::utils::parse_restart_policy)]
+        restart: Option<RestartPolicy>,
+
+        /// Pull the image before creating the container (always, missing, never)
+        #[arg(long, value_name = "POLICY", default_value = "missing", value_parser = ["always", "missing", "never"])]
+        pull: String,
     },
     /// Start one or more stopped containers
     Start {
@@ -68,6 +138,15 @@ pub enum ContainerCommands {
         /// Show n last created containers (includes all states)
         #[arg(long, short)]
         limit: Option<i32>,
+
+        /// Display the disk usage of each container's writable layer
+        #[arg(long, short)]
+        size: bool,
+
+        /// Filter containers (id=ID, name=NAME, label=KEY[=VALUE],
+        /// status=STATUS, ancestor=IMAGE)
+        #[arg(long, short, value_parser = crate::utils::parse_filter)]
+        filter: Vec<(String, String)>,
     },
     /// Display detailed information on one or more containers
     Inspect {
@@ -114,6 +193,15 @@ pub enum ContainerCommands {
         /// Show timestamps
         #[arg(long, short)]
         timestamps: bool,
+
+        /// Show logs since this time (RFC 3339, a date, Unix seconds, or a
+        /// duration such as 10m)
+        #[arg(long, value_parser = crate::utils::parse_time)]
+        since: Option<prost_types::Timestamp>,
+
+        /// Show logs until this time, in the same forms as --since
+        #[arg(long, value_parser = crate::utils::parse_time)]
+        until: Option<prost_types::Timestamp>,
     },
     /// Run a command in a running container
     Exec {
@@ -128,6 +216,26 @@ pub enum ContainerCommands {
         #[arg(long, short)]
         interactive: bool,
 
+        /// Set environment variables (KEY=VAL)
+        #[arg(long, short)]
+        env: Vec<String>,
+
+        /// Read environment variables from a file of KEY=VAL lines
+        #[arg(long = "env-file", value_name = "PATH")]
+        env_file: Vec<PathBuf>,
+
+        /// Working directory inside the container
+        #[arg(long, short)]
+        workdir: Option<String>,
+
+        /// Username or UID (USER[:GROUP])
+        #[arg(long, short)]
+        user: Option<String>,
+
+        /// Give the command extended privileges
+        #[arg(long)]
+        privileged: bool,
+
         /// Command to execute
         #[arg(last = true, required = true)]
         command: Vec<String>,
@@ -136,6 +244,14 @@ pub enum ContainerCommands {
     Attach {
         /// Container ID or name
         container_id: String,
+
+        /// Do not attach STDIN
+        #[arg(long)]
+        no_stdin: bool,
+
+        /// Key sequence that detaches from the container, leaving it running
+        #[arg(long, value_name = "KEYS", default_value = crate::detach::DEFAULT_DETACH_KEYS, value_parser = crate::detach::parse_detach_keys)]
+        detach_keys: DetachKeys,
     },
     /// Block until one or more containers stop, then print their exit codes
     Wait {
@@ -159,6 +275,33 @@ pub enum ContainerCommands {
         /// New name for the container
         new_name: String,
     },
+    /// Update the resource limits of a container
+    Update {
+        /// Container ID or name
+        container_id: String,
+
+        /// Memory limit (e.g. 512m, 1g)
+        #[arg(long, short = 'm', value_parser = crate::utils::parse_memory)]
+        memory: Option<i64>,
+
+        /// Number of CPUs (e.g. 1.5)
+        #[arg(long)]
+        cpus: Option<f64>,
+    },
+    /// Inspect changes to files or directories on a container's filesystem
+    Diff {
+        /// Container ID or name
+        container_id: String,
+    },
+    /// Display the running processes of a container
+    Top {
+        /// Container ID or name
+        container_id: String,
+
+        /// Options passed to ps, e.g. aux
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        ps_args: Vec<String>,
+    },
     /// Display a live stream of container(s) resource usage statistics
     Stats {
         /// Container ID or name
@@ -171,27 +314,60 @@ pub enum ContainerCommands {
 }
 
 pub async fn handle_container_command(
-    addr: &str,
+    daemon: &Daemon,
     cmd: ContainerCommands,
+    format: &OutputFormat,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = ContainerServiceClient::connect(addr.to_string())
-        .await
-        .map_err(|e| {
-            format!(
-                "Failed to connect to daemon at {}: {}. Is the daemon running?",
-                addr, e
-            )
-        })?;
+    let mut client = ContainerServiceClient::new(daemon.connect().await?);
 
     match cmd {
         ContainerCommands::Create {
             image,
             name,
             env,
+            env_file,
+            entrypoint,
             publish,
             volume,
+            read_only,
+            tmpfs,
+            network,
+            dns,
+            dns_search,
+            dns_options,
+            add_host,
+            egress_allow,
+            egress_deny,
+            memory,
+            cpus,
+            restart,
+            pull,
         } => {
-            container_create(&mut client, &image, name, env, publish, volume).await?;
+            let env = merge_env(&env_file, env)?;
+            container_create(
+                daemon,
+                &mut client,
+                &image,
+                name,
+                env,
+                entrypoint,
+                publish,
+                volume,
+                read_only,
+                tmpfs,
+                network,
+                dns,
+                dns_search,
+                dns_options,
+                add_host,
+                egress_allow,
+                egress_deny,
+                memory,
+                cpus,
+                restart,
+                pull,
+            )
+            .await?;
         }
         ContainerCommands::Start { container_id } => {
             container_start(&mut client, &container_id).await?;
@@ -208,11 +384,16 @@ pub async fn handle_container_command(
         } => {
             container_restart(&mut client, &container_id, timeout).await?;
         }
-        ContainerCommands::List { all, limit } => {
-            container_list(&mut client, all, limit).await?;
+        ContainerCommands::List {
+            all,
+            limit,
+            size,
+            filter,
+        } => {
+            container_list(&mut client, all, limit, size, filter, format).await?;
         }
         ContainerCommands::Inspect { container_id } => {
-            container_inspect(&mut client, &container_id).await?;
+            container_inspect(&mut client, &container_id, format).await?;
         }
         ContainerCommands::Remove {
             container_id,
@@ -232,19 +413,51 @@ pub async fn handle_container_command(
             follow,
             tail,
             timestamps,
+            since,
+            until,
         } => {
-            container_logs(&mut client, &container_id, follow, &tail, timestamps).await?;
+            container_logs(
+                &mut client,
+                &container_id,
+                follow,
+                &tail,
+                timestamps,
+                since,
+                until,
+            )
+            .await?;
         }
         ContainerCommands::Exec {
             container_id,
             tty,
             interactive,
+            env,
+            env_file,
+            workdir,
+            user,
+            privileged,
             command,
         } => {
-            container_exec(&mut client, &container_id, tty, interactive, command).await?;
+            let config = ExecConfig {
+                attach_stdin: interactive,
+                attach_stdout: true,
+                attach_stderr: true,
+                detach_keys: String::new(),
+                tty,
+                env: merge_env(&env_file, env)?,
+                cmd: command,
+                privileged,
+                user: user.unwrap_or_default(),
+                working_dir: workdir.unwrap_or_default(),
+            };
+            container_exec(&mut client, &container_id, config).await?;
         }
-        ContainerCommands::Attach { container_id } => {
-            container_attach(&mut client, &container_id).await?;
+        ContainerCommands::Attach {
+            container_id,
+            no_stdin,
+            detach_keys,
+        } => {
+            container_attach(&mut client, &container_id, !no_stdin, &detach_keys).await?;
         }
         ContainerCommands::Wait { container_id } => {
             container_wait(&mut client, &container_id).await?;
@@ -261,24 +474,56 @@ pub async fn handle_container_command(
         } => {
             container_rename(&mut client, &container_id, &new_name).await?;
         }
+        ContainerCommands::Update {
+            container_id,
+            memory,
+            cpus,
+        } => {
+            container_update(&mut client, &container_id, memory, cpus).await?;
+        }
+        ContainerCommands::Diff { container_id } => {
+            container_diff(&mut client, &container_id).await?;
+        }
+        ContainerCommands::Top {
+            container_id,
+            ps_args,
+        } => {
+            container_top(&mut client, &container_id, &ps_args.join(" ")).await?;
+        }
         ContainerCommands::Stats {
             container_id,
             no_stream,
         } => {
-            container_stats(&mut client, &container_id, no_stream).await?;
+            container_stats(&mut client, &container_id, no_stream, format).await?;
         }
     }
 
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn container_create(
+    daemon: &Daemon,
     client: &mut ContainerServiceClient<tonic::transport::Channel>,
     image: &str,
     name: Option<String>,
     env: Vec<String>,
+    entrypoint: Option<String>,
     publish: Vec<String>,
     volume: Vec<String>,
+    read_only: bool,
+    tmpfs: Vec<(String, String)>,
+    network: Option<String>,
+    dns: Vec<String>,
+    dns_search: Vec<String>,
+    dns_options: Vec<String>,
+    add_host: Vec<String>,
+    egress_allow: Vec<String>,
+    egress_deny: Vec<String>,
+    memory: Option<i64>,
+    cpus: Option<f64>,
+    restart: Option<RestartPolicy>,
+    pull: String,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let port_bindings = publish
         .iter()
@@ -306,26 +551,40 @@ async fn container_create(
     let config = ContainerConfig {
         image: image.to_string(),
         env,
+        entrypoint_set: entrypoint.is_some(),
+        entrypoint: entrypoint_args(entrypoint),
         ..Default::default()
     };
 
     let host_config = HostConfig {
         port_bindings,
         binds,
+        readonly_rootfs: read_only,
+        tmpfs: tmpfs.into_iter().collect(),
+        network_mode: network.unwrap_or_default(),
+        dns,
+        dns_search,
+        dns_options,
+        extra_hosts: add_host,
+        egress_allow,
+        egress_deny,
+        resources: Some(Resources {
+            memory: memory.unwrap_or_default(),
+            nano_cpus: cpus.map(|c| (c * 1e9) as i64).unwrap_or_default(),
+            ..Default::default()
+        }),
+        restart_policy: restart,
         ..Default::default()
     };
 
-    let response = client
-        .create_container(CreateContainerRequest {
-            name: name.unwrap_or_default(),
-            config: Some(config),
-            host_config: Some(host_config),
-            networking_config: None,
-        })
-        .await
-        .map_err(|e| format!("Failed to create container: {}", e))?;
-
-    let result = response.into_inner();
+    let request = CreateContainerRequest {
+        name: name.unwrap_or_default(),
+        config: Some(config),
+        host_config: Some(host_config),
+        networking_config: None,
+        pull,
+    };
+    let result = create_container(daemon, client, request).await?;
     println!("{}", result.id);
 
     if !result.warnings.is_empty() {
@@ -391,29 +650,48 @@ async fn container_list(
     client: &mut ContainerServiceClient<tonic::transport::Channel>,
     all: bool,
     limit: Option<i32>,
+    size: bool,
+    filter: Vec<(String, String)>,
+    format: &OutputFormat,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let response = client
         .list_containers(ListContainersRequest {
             all,
             limit: limit.unwrap_or(0),
-            size: false,
-            filters: Default::default(),
+            size,
+            filters: merge_filters(filter),
         })
         .await
         .map_err(|e| format!("Failed to list containers: {}", e))?;
 
     let containers = response.into_inner().containers;
 
+    match format {
+        OutputFormat::Table => {}
+        OutputFormat::Json => return print_json(&containers),
+        OutputFormat::Template(template) => {
+            for container in &containers {
+                print_template(template, container)?;
+            }
+            return Ok(());
+        }
+    }
+
     if containers.is_empty() {
         println!("No containers found");
         return Ok(());
     }
 
-    println!(
-        "{:<15} {:<20} {:<25} {:<20} {:<20}",
-        "CONTAINER ID", "IMAGE", "COMMAND", "STATUS", "NAMES"
+    print!(
+        "{:<15} {:<20} {:<25} {:<20} {:<20} {:<20}",
+        "CONTAINER ID", "IMAGE", "COMMAND", "CREATED", "STATUS", "NAMES"
     );
+    if size {
+        print!(" SIZE");
+    }
+    println!();
 
+    let now = chrono::Utc::now().timestamp();
     for container in containers {
         let id = if container.id.len() > 12 {
             &container.id[..12]
@@ -427,8 +705,10 @@ async fn container_list(
             container.image.clone()
         };
 
-        let command = if container.command.len() > 23 {
-            format!("\"{}...\"", &container.command[..20])
+        // Commands are arbitrary user input, so truncate on a char boundary.
+        let command = if container.command.chars().count() > 23 {
+            let truncated: String = container.command.chars().take(20).collect();
+            format!("\"{}...\"", truncated)
         } else {
             format!("\"{}\"", container.command)
         };
@@ -440,10 +720,20 @@ async fn container_list(
             names
         };
 
-        println!(
-            "{:<15} {:<20} {:<25} {:<20} {:<20}",
-            id, image, command, container.status, names
+        let created = container
+            .created
+            .as_ref()
+            .map(|ts| format_ago(ts, now))
+            .unwrap_or_default();
+
+        print!(
+            "{:<15} {:<20} {:<25} {:<20} {:<20} {:<20}",
+            id, image, command, created, container.status, names
         );
+        if size {
+            print!(" {}", format_size(container.size_rw as u64));
+        }
+        println!();
     }
 
     Ok(())
@@ -452,6 +742,7 @@ async fn container_list(
 async fn container_inspect(
     client: &mut ContainerServiceClient<tonic::transport::Channel>,
     container_id: &str,
+    format: &OutputFormat,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let response = client
         .inspect_container(InspectContainerRequest {
@@ -463,58 +754,10 @@ async fn container_inspect(
 
     let inspect = response.into_inner();
 
-    println!("[{{");
-    println!("    \"Id\": \"{}\",", container_id);
-    println!("    \"Name\": \"{}\",", inspect.name);
-    println!("    \"Path\": \"{}\",", inspect.path);
-    println!("    \"Args\": {:?},", inspect.args);
-
-    if let Some(state) = inspect.state {
-        println!("    \"State\": {{");
-        println!("        \"Status\": \"{}\",", state.status);
-        println!("        \"Running\": {},", state.running);
-        println!("        \"Paused\": {},", state.paused);
-        println!("        \"Restarting\": {},", state.restarting);
-        println!("        \"OOMKilled\": {},", state.oom_killed);
-        println!("        \"Dead\": {},", state.dead);
-        println!("        \"Pid\": {},", state.pid);
-        println!("        \"ExitCode\": {},", state.exit_code);
-        println!("        \"Error\": \"{}\"", state.error);
-        println!("    }},");
-    }
-
-    if let Some(container) = inspect.container {
-        println!("    \"Image\": \"{}\",", container.image);
-        println!("    \"ImageID\": \"{}\",", container.image_id);
-
-        if !container.labels.is_empty() {
-            println!("    \"Labels\": {{");
-            let labels: Vec<_> = container.labels.iter().collect();
-            for (i, (key, value)) in labels.iter().enumerate() {
-                let comma = if i < labels.len() - 1 { "," } else { "" };
-                println!("        \"{}\": \"{}\"{}", key, value, comma);
-            }
-            println!("    }},");
-        }
-    }
-
-    if let Some(config) = inspect.config {
-        println!("    \"Config\": {{");
-        println!("        \"Hostname\": \"{}\",", config.hostname);
-        println!("        \"User\": \"{}\",", config.user);
-        println!("        \"Env\": {:?},", config.env);
-        println!("        \"Cmd\": {:?},", config.cmd);
-        println!("        \"Image\": \"{}\",", config.image);
-        println!("        \"WorkingDir\": \"{}\"", config.working_dir);
-        println!("    }},");
+    match format {
+        OutputFormat::Table | OutputFormat::Json => print_json(&[inspect]),
+        OutputFormat::Template(template) => print_template(template, &inspect),
     }
-
-    println!("    \"Driver\": \"{}\",", inspect.driver);
-    println!("    \"Platform\": \"{}\",", inspect.platform);
-    println!("    \"RestartCount\": {}", inspect.restart_count);
-    println!("}}]");
-
-    Ok(())
 }
 
 async fn container_remove(
@@ -573,15 +816,23 @@ async fn container_logs(
     follow: bool,
     tail: &str,
     timestamps: bool,
+    since: Option<prost_types::Timestamp>,
+    until: Option<prost_types::Timestamp>,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    if let (Some(since), Some(until)) = (&since, &until)
+        && (since.seconds, since.nanos) > (until.seconds, until.nanos)
+    {
+        return Err("--since must not be later than --until".into());
+    }
+
     let mut stream = client
         .get_logs(GetLogsRequest {
             container_id: container_id.to_string(),
             follow,
             stdout: true,
             stderr: true,
-            since: None,
-            until: None,
+            since,
+            until,
             timestamps,
             tail: tail.to_string(),
         })
@@ -613,22 +864,9 @@ async fn container_logs(
 async fn container_exec(
     client: &mut ContainerServiceClient<tonic::transport::Channel>,
     container_id: &str,
-    tty: bool,
-    interactive: bool,
-    command: Vec<String>,
+    config: ExecConfig,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let config = ExecConfig {
-        attach_stdin: interactive,
-        attach_stdout: true,
-        attach_stderr: true,
-        detach_keys: String::new(),
-        tty,
-        env: vec![],
-        cmd: command,
-        privileged: false,
-        user: String::new(),
-        working_dir: String::new(),
-    };
+    let tty = config.tty;
 
     let exec_response = client
         .exec(ExecRequest {
@@ -650,11 +888,26 @@ async fn container_exec(
         .map_err(|e| format!("Failed to start exec: {}", e))?
         .into_inner();
 
+    let mut exit_code: i64 = 0;
+
     while let Some(output) = stream.next().await {
         match output {
             Ok(o) => {
-                let data = String::from_utf8_lossy(&o.data);
-                print!("{}", data);
+                use std::io::Write;
+                if let Some(result) = o.exit {
+                    exit_code = result.status_code;
+                    if let Some(err) = result.error
+                        && !err.message.is_empty()
+                    {
+                        eprintln!("Error: {}", err.message);
+                    }
+                } else if o.stream == "stderr" {
+                    std::io::stderr().write_all(&o.data)?;
+                    std::io::stderr().flush()?;
+                } else {
+                    std::io::stdout().write_all(&o.data)?;
+                    std::io::stdout().flush()?;
+                }
             }
             Err(e) => {
                 eprintln!("Stream error: {}", e);
@@ -663,38 +916,102 @@ async fn container_exec(
         }
     }
 
+    if exit_code != 0 {
+        std::process::exit(exit_code as i32);
+    }
+
     Ok(())
 }
 
 async fn container_attach(
     client: &mut ContainerServiceClient<tonic::transport::Channel>,
     container_id: &str,
+    stdin: bool,
+    detach_keys: &DetachKeys,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Attaching to container {}...", container_id);
-    println!("(Press Ctrl+C to detach)");
-
-    let request_stream = tokio_stream::iter(vec![AttachRequest {
-        container_id: container_id.to_string(),
-        stream: true,
-        stdin: true,
-        stdout: true,
-        stderr: true,
-        detach_keys: String::new(),
-        logs: false,
-        input: vec![],
-    }]);
+    let (input_tx, input_rx) = tokio::sync::mpsc::channel::<AttachRequest>(32);
+
+    // The first message selects the container and streams. The detach keys
+    // are matched here, and told to the daemon so that it does not take the
+    // default ones for a detach.
+    input_tx
+        .send(AttachRequest {
+            container_id: container_id.to_string(),
+            stream: true,
+            stdin,
+            stdout: true,
+            stderr: true,
+            detach_keys: detach_keys.spec.clone(),
+            logs: false,
+            input: vec![],
+        })
+        .await
+        .map_err(|e| format!("Failed to send attach request: {}", e))?;
 
     let mut stream = client
-        .attach(request_stream)
+        .attach(tokio_stream::wrappers::ReceiverStream::new(input_rx))
         .await
         .map_err(|e| format!("Failed to attach to container: {}", e))?
         .into_inner();
 
-    while let Some(output) = stream.next().await {
+    let _raw_guard = stdin.then(setup_raw_mode);
+
+    let (detach_tx, mut detach_rx) = tokio::sync::mpsc::channel::<()>(1);
+    if stdin {
+        let mut matcher = DetachMatcher::new(detach_keys);
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 1024];
+            loop {
+                let n = unsafe {
+                    libc::read(
+                        libc::STDIN_FILENO,
+                        buf.as_mut_ptr() as *mut libc::c_void,
+                        buf.len(),
+                    )
+                };
+                if n <= 0 {
+                    break;
+                }
+
+                let (input, detached) = matcher.feed(&buf[..n as usize]);
+                if !input.is_empty() {
+                    let msg = AttachRequest {
+                        input,
+                        ..Default::default()
+                    };
+                    if input_tx.blocking_send(msg).is_err() {
+                        break;
+                    }
+                }
+                if detached {
+                    let _ = detach_tx.blocking_send(());
+                    break;
+                }
+            }
+        });
+    }
+
+    loop {
+        use std::io::Write;
+        let output = tokio::select! {
+            output = stream.next() => match output {
+                Some(output) => output,
+                None => break,
+            },
+            Some(()) = detach_rx.recv() => {
+                // The container keeps running; the daemon sees the client go.
+                break;
+            }
+        };
         match output {
             Ok(o) => {
-                let data = String::from_utf8_lossy(&o.data);
-                print!("{}", data);
+                if o.stream == "stderr" {
+                    std::io::stderr().write_all(&o.data)?;
+                    std::io::stderr().flush()?;
+                } else {
+                    std::io::stdout().write_all(&o.data)?;
+                    std::io::stdout().flush()?;
+                }
             }
             Err(e) => {
                 eprintln!("Stream error: {}", e);
@@ -786,10 +1103,97 @@ async fn container_rename(
     Ok(())
 }
 
+async fn container_update(
+    client: &mut ContainerServiceClient<tonic::transport::Channel>,
+    container_id: &str,
+    memory: Option<i64>,
+    cpus: Option<f64>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if memory.is_none() && cpus.is_none() {
+        return Err("Nothing to update: pass --memory or --cpus".into());
+    }
+
+    client
+        .update_container(UpdateContainerRequest {
+            container_id: container_id.to_string(),
+            resources: Some(Resources {
+                memory: memory.unwrap_or_default(),
+                nano_cpus: cpus.map(|c| (c * 1e9) as i64).unwrap_or_default(),
+                ..Default::default()
+            }),
+        })
+        .await
+        .map_err(|e| format!("Failed to update container: {}", e))?;
+
+    println!("{}", container_id);
+    Ok(())
+}
+
+async fn container_diff(
+    client: &mut ContainerServiceClient<tonic::transport::Channel>,
+    container_id: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let response = client
+        .diff(DiffContainerRequest {
+            container_id: container_id.to_string(),
+        })
+        .await
+        .map_err(|e| format!("Failed to diff container: {}", e))?;
+
+    for change in response.into_inner().changes {
+        let kind = match change.kind() {
+            ChangeKind::Added => 'A',
+            ChangeKind::Modified => 'C',
+            ChangeKind::Deleted => 'D',
+        };
+        println!("{} {}", kind, change.path);
+    }
+    Ok(())
+}
+
+async fn container_top(
+    client: &mut ContainerServiceClient<tonic::transport::Channel>,
+    container_id: &str,
+    ps_args: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let top = client
+        .top(TopRequest {
+            container_id: container_id.to_string(),
+            ps_args: ps_args.to_string(),
+        })
+        .await
+        .map_err(|e| format!("Failed to list processes: {}", e))?
+        .into_inner();
+
+    let rows: Vec<&[String]> = std::iter::once(top.titles.as_slice())
+        .chain(top.processes.iter().map(|p| p.fields.as_slice()))
+        .collect();
+    let mut widths = vec![0; top.titles.len()];
+    for row in &rows {
+        for (width, field) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(field.len());
+        }
+    }
+    for row in rows {
+        let line: Vec<String> = row
+            .iter()
+            .enumerate()
+            .map(|(i, field)| match widths.get(i) {
+                // The last column, the command, is not padded.
+                Some(width) if i + 1 < row.len() => format!("{:<width$}", field),
+                _ => field.clone(),
+            })
+            .collect();
+        println!("{}", line.join("   "));
+    }
+    Ok(())
+}
+
 async fn container_stats(
     client: &mut ContainerServiceClient<tonic::transport::Channel>,
     container_id: &str,
     no_stream: bool,
+    format: &OutputFormat,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let mut stream = client
         .stats(StatsRequest {
@@ -801,10 +1205,12 @@ async fn container_stats(
         .map_err(|e| format!("Failed to get stats: {}", e))?
         .into_inner();
 
-    println!(
-        "{:<15} {:<10} {:<25} {:<15} {:<10}",
-        "CONTAINER ID", "CPU %", "MEM USAGE / LIMIT", "MEM %", "PIDS"
-    );
+    if *format == OutputFormat::Table {
+        println!(
+            "{:<15} {:<10} {:<25} {:<15} {:<25} {:<10}",
+            "CONTAINER ID", "CPU %", "MEM USAGE / LIMIT", "MEM %", "NET I/O", "PIDS"
+        );
+    }
 
     let container_id_short = if container_id.len() > 12 {
         &container_id[..12]
@@ -815,18 +1221,30 @@ async fn container_stats(
     while let Some(stats) = stream.next().await {
         match stats {
             Ok(s) => {
-                let cpu_percent = calculate_cpu_percent(&s);
-                let (mem_usage, mem_limit, mem_percent) = calculate_memory(&s);
-                let pids = s.pids_stats.as_ref().map(|p| p.current).unwrap_or(0);
-
-                println!(
-                    "{:<15} {:<10.2} {:<25} {:<15.2} {:<10}",
-                    container_id_short,
-                    cpu_percent,
-                    format!("{} / {}", format_size(mem_usage), format_size(mem_limit)),
-                    mem_percent,
-                    pids
-                );
+                match format {
+                    // One object per sample, so streamed output stays parseable line by line.
+                    OutputFormat::Json => println!("{}", serde_json::to_string(&s)?),
+                    OutputFormat::Template(template) => print_template(template, &s)?,
+                    OutputFormat::Table => {
+                        let cpu_percent = calculate_cpu_percent(&s);
+                        let (mem_usage, mem_limit, mem_percent) = calculate_memory(&s);
+                        let pids = s.pids_stats.as_ref().map(|p| p.current).unwrap_or(0);
+                        let (net_rx, net_tx) = s
+                            .networks
+                            .values()
+                            .fold((0, 0), |(rx, tx), n| (rx + n.rx_bytes, tx + n.tx_bytes));
+
+                        println!(
+                            "{:<15} {:<10.2} {:<25} {:<15.2} {:<25} {:<10}",
+                            container_id_short,
+                            cpu_percent,
+                            format!("{} / {}", format_size(mem_usage), format_size(mem_limit)),
+                            mem_percent,
+                            format!("{} / {}", format_size(net_rx), format_size(net_tx)),
+                            pids
+                        );
+                    }
+                }
 
                 if no_stream {
                     break;
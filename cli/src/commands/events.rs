@@ -0,0 +1,48 @@
+use ross_core::ross::EventsRequest;
+use ross_core::ross::container_service_client::ContainerServiceClient;
+use tokio_stream::StreamExt;
+
+use crate::connection::Daemon;
+use crate::utils::{format_timestamp, merge_filters};
+
+/// Prints container lifecycle events as the daemon reports them, until
+/// interrupted. Several values for one filter key match any of them.
+pub async fn stream_events(
+    daemon: &Daemon,
+    since: Option<prost_types::Timestamp>,
+    filters: Vec<(String, String)>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = ContainerServiceClient::new(daemon.connect().await?);
+
+    let mut stream = client
+        .events(EventsRequest {
+            since,
+            filters: merge_filters(filters),
+        })
+        .await?
+        .into_inner();
+
+    while let Some(event) = stream.next().await {
+        let event = event?;
+        let time = event
+            .time
+            .as_ref()
+            .map(format_timestamp)
+            .unwrap_or_default();
+        let mut attributes: Vec<String> = event
+            .attributes
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        attributes.sort();
+        println!(
+            "{} container {} {} ({})",
+            time,
+            event.action,
+            event.container_id,
+            attributes.join(", ")
+        );
+    }
+
+    Ok(())
+}
@@ -1,13 +1,10 @@
 use ross_core::ross::HealthCheckRequest;
 use ross_core::ross::ross_client::RossClient;
 
-pub async fn health_check(addr: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = RossClient::connect(addr.to_string()).await.map_err(|e| {
-        format!(
-            "Failed to connect to daemon at {}: {}. Is the daemon running?",
-            addr, e
-        )
-    })?;
+use crate::connection::Daemon;
+
+pub async fn health_check(daemon: &Daemon) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = RossClient::new(daemon.connect().await?);
 
     let response = client
         .health_check(HealthCheckRequest {})
@@ -1,13 +1,16 @@
 use clap::Subcommand;
 use ross_core::ross::image_service_client::ImageServiceClient;
 use ross_core::ross::{
-    BuildImageRequest, InspectImageRequest, ListImagesRequest, PullImageProgress, PullImageRequest,
-    PushImageRequest, RemoveImageRequest, SearchImagesRequest, TagImageRequest,
+    BuildImageRequest, ImportImageRequest, InspectImageRequest, ListImagesRequest,
+    LoadImageRequest, PruneImagesRequest, PullImageProgress, PullImageRequest, PushImageRequest,
+    RemoveImageRequest, SaveImageRequest, SearchImagesRequest, TagImageRequest,
 };
 use std::collections::HashMap;
 use std::io::{self, IsTerminal, Write};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio_stream::StreamExt;
 
+use crate::connection::Daemon;
 use crate::utils::format_size;
 
 #[derive(Subcommand)]
@@ -35,6 +38,10 @@ pub enum ImageCommands {
         /// Tag to pull
         #[arg(long, short, default_value = "latest")]
         tag: String,
+
+        /// Platform to pull from a multi-arch image, e.g. linux/arm64
+        #[arg(long)]
+        platform: Option<String>,
     },
     /// Push an image to a registry
     Push {
@@ -73,17 +80,19 @@ pub enum ImageCommands {
         #[arg(long)]
         prune: bool,
     },
+    /// Remove unused images, layers and container snapshots
+    Prune,
     /// Create a tag TARGET_IMAGE that refers to SOURCE_IMAGE
     Tag {
-        /// Source image
+        /// Source image name or ID
         source_image: String,
 
-        /// Target repository
-        repository: String,
+        /// Target image, as REPOSITORY[:TAG]
+        target_image: String,
 
-        /// Tag name
-        #[arg(long, short, default_value = "latest")]
-        tag: String,
+        /// Tag name, overriding the one in the target image
+        #[arg(long, short)]
+        tag: Option<String>,
     },
     /// Search the Docker Hub for images
     Search {
@@ -94,20 +103,45 @@ pub enum ImageCommands {
         #[arg(long, default_value_t = 25)]
         limit: i32,
     },
+    /// Save one or more images to a tar archive (streamed to STDOUT by default)
+    Save {
+        /// Images to save
+        #[arg(required = true)]
+        images: Vec<String>,
+
+        /// Write to a file, instead of STDOUT
+        #[arg(long, short)]
+        output: Option<String>,
+    },
+    /// Load images from a tar archive (read from STDIN by default)
+    Load {
+        /// Read from a tar archive file, instead of STDIN
+        #[arg(long, short)]
+        input: Option<String>,
+    },
+    /// Create a single-layer image from a rootfs tarball
+    Import {
+        /// Tar archive of the root filesystem, gzipped or not ("-" for STDIN)
+        file: String,
+
+        /// Name to tag the image with, as REPOSITORY[:TAG]
+        reference: Option<String>,
+
+        /// Entrypoint of the image, as a JSON array or a space-separated command
+        #[arg(long)]
+        entrypoint: Option<String>,
+
+        /// Default command of the image, as a JSON array or a space-separated command
+        #[arg(long)]
+        cmd: Option<String>,
+    },
 }
 
 pub async fn handle_image_command(
-    addr: &str,
+    daemon: &Daemon,
     cmd: ImageCommands,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = ImageServiceClient::connect(addr.to_string())
-        .await
-        .map_err(|e| {
-            format!(
-                "Failed to connect to daemon at {}: {}. Is the daemon running?",
-                addr, e
-            )
-        })?;
+    let mut client = ImageServiceClient::new(daemon.connect().await?);
 
     match cmd {
         ImageCommands::List { all, digests } => {
@@ -116,8 +150,12 @@ pub async fn handle_image_command(
         ImageCommands::Inspect { image_id } => {
             image_inspect(&mut client, &image_id).await?;
         }
-        ImageCommands::Pull { image_name, tag } => {
-            image_pull(&mut client, &image_name, &tag).await?;
+        ImageCommands::Pull {
+            image_name,
+            tag,
+            platform,
+        } => {
+            image_pull(&mut client, &image_name, &tag, platform).await?;
         }
         ImageCommands::Push { image_name, tag } => {
             image_push(&mut client, &image_name, &tag).await?;
@@ -136,16 +174,47 @@ pub async fn handle_image_command(
         } => {
             image_remove(&mut client, &image_id, force, prune).await?;
         }
+        ImageCommands::Prune => {
+            image_prune(&mut client).await?;
+        }
         ImageCommands::Tag {
             source_image,
-            repository,
+            target_image,
             tag,
         } => {
-            image_tag(&mut client, &source_image, &repository, &tag).await?;
+            image_tag(&mut client, &source_image, &target_image, tag.as_deref()).await?;
         }
         ImageCommands::Search { term, limit } => {
             image_search(&mut client, &term, limit).await?;
         }
+        ImageCommands::Save { images, output } => {
+            image_save(&mut client, images, output.as_deref()).await?;
+        }
+        ImageCommands::Load { input } => {
+            image_load(&mut client, input.as_deref()).await?;
+        }
+        ImageCommands::Import {
+            file,
+            reference,
+            entrypoint,
+            cmd,
+        } => {
+            image_import(
+                &mut client,
+                &file,
+                reference.unwrap_or_default(),
+                entrypoint
+                    .as_deref()
+                    .map(parse_command)
+                    .transpose()?
+                    .unwrap_or_default(),
+                cmd.as_deref()
+                    .map(parse_command)
+                    .transpose()?
+                    .unwrap_or_default(),
+            )
+            .await?;
+        }
     }
 
     Ok(())
@@ -324,7 +393,10 @@ impl PullProgressDisplay {
             return;
         }
 
-        if progress.status.starts_with("Digest:") || progress.status.starts_with("Status:") {
+        if progress.status.starts_with("Digest:")
+            || progress.status.starts_with("Status:")
+            || progress.status.starts_with("Deduplicated:")
+        {
             self.footer_lines
                 .push(format!("{}: {}", id, progress.status));
             if self.is_tty {
@@ -352,8 +424,13 @@ impl PullProgressDisplay {
             || progress.status == "Already exists"
             || !progress.error.is_empty();
 
-        let is_final_state =
-            done || progress.status == "Downloading" || progress.status == "Pulling config";
+        // Byte-level progress is only drawn on a terminal; logs get one line per phase.
+        let is_final_state = progress.progress_detail.is_none()
+            && (done
+                || progress.status == "Downloading"
+                || progress.status == "Download complete"
+                || progress.status == "Extracting"
+                || progress.status == "Pulling config");
 
         if let Some(state) = self.layers.get_mut(id) {
             state.status = if !progress.error.is_empty() {
@@ -361,7 +438,10 @@ impl PullProgressDisplay {
             } else {
                 progress.status.clone()
             };
-            state.progress = progress.progress.clone();
+            state.progress = match &progress.progress_detail {
+                Some(detail) if detail.total > 0 => progress_bar(detail.current, detail.total),
+                _ => progress.progress.clone(),
+            };
             state.done = done;
         }
 
@@ -432,10 +512,31 @@ impl PullProgressDisplay {
     }
 }
 
+/// Renders e.g. `[=========>         ]  12.50MB/25.00MB`.
+fn progress_bar(current: i64, total: i64) -> String {
+    const WIDTH: usize = 30;
+
+    let current = current.clamp(0, total);
+    let filled = (current as f64 / total as f64 * WIDTH as f64) as usize;
+    let bar = if filled >= WIDTH {
+        "=".repeat(WIDTH)
+    } else {
+        format!("{}>{}", "=".repeat(filled), " ".repeat(WIDTH - filled - 1))
+    };
+
+    format!(
+        "[{}] {:>9}/{}",
+        bar,
+        format_size(current as u64),
+        format_size(total as u64)
+    )
+}
+
 async fn image_pull(
     client: &mut ImageServiceClient<tonic::transport::Channel>,
     image_name: &str,
     tag: &str,
+    platform: Option<String>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     println!("Pulling {}:{}", image_name, tag);
 
@@ -444,6 +545,7 @@ async fn image_pull(
             image_name: image_name.to_string(),
             tag: tag.to_string(),
             registry_auth: None,
+            platform: platform.unwrap_or_default(),
         })
         .await
         .map_err(|e| format!("Failed to pull image: {}", e))?
@@ -592,17 +694,39 @@ async fn image_remove(
     Ok(())
 }
 
+async fn image_prune(
+    client: &mut ImageServiceClient<tonic::transport::Channel>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let response = client
+        .prune_images(PruneImagesRequest {})
+        .await
+        .map_err(|e| format!("Failed to prune images: {}", e))?;
+
+    let result = response.into_inner();
+
+    for deleted in &result.deleted {
+        println!("Deleted: {}", deleted);
+    }
+
+    println!(
+        "Total reclaimed space: {}",
+        format_size(result.space_reclaimed as u64)
+    );
+
+    Ok(())
+}
+
 async fn image_tag(
     client: &mut ImageServiceClient<tonic::transport::Channel>,
     source_image: &str,
-    repository: &str,
-    tag: &str,
+    target_image: &str,
+    tag: Option<&str>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let response = client
         .tag_image(TagImageRequest {
             source_image: source_image.to_string(),
-            repository: repository.to_string(),
-            tag: tag.to_string(),
+            repository: target_image.to_string(),
+            tag: tag.unwrap_or_default().to_string(),
         })
         .await
         .map_err(|e| format!("Failed to tag image: {}", e))?;
@@ -610,7 +734,10 @@ async fn image_tag(
     let result = response.into_inner();
 
     if result.success {
-        println!("Tagged {} as {}:{}", source_image, repository, tag);
+        match tag {
+            Some(tag) => println!("Tagged {} as {}:{}", source_image, target_image, tag),
+            None => println!("Tagged {} as {}", source_image, target_image),
+        }
     } else {
         eprintln!("Failed to tag image");
     }
@@ -662,3 +789,181 @@ async fn image_search(
 
     Ok(())
 }
+
+/// Size of the pieces an archive is sent to the daemon in.
+const LOAD_CHUNK_SIZE: usize = 1024 * 1024;
+
+async fn image_save(
+    client: &mut ImageServiceClient<tonic::transport::Channel>,
+    images: Vec<String>,
+    output: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if output.is_none() && io::stdout().is_terminal() {
+        return Err(
+            "Refusing to write an image archive to a terminal. Use -o or redirect the output"
+                .into(),
+        );
+    }
+
+    let mut stream = client
+        .save_image(SaveImageRequest { images })
+        .await
+        .map_err(|e| format!("Failed to save images: {}", e))?
+        .into_inner();
+
+    let mut writer: Box<dyn AsyncWrite + Unpin> = match output {
+        Some(path) => Box::new(
+            tokio::fs::File::create(path)
+                .await
+                .map_err(|e| format!("Failed to create {}: {}", path, e))?,
+        ),
+        None => Box::new(tokio::io::stdout()),
+    };
+
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| format!("Failed to save images: {}", e))?;
+        writer.write_all(&chunk.data).await?;
+    }
+    writer.flush().await?;
+
+    Ok(())
+}
+
+async fn image_load(
+    client: &mut ImageServiceClient<tonic::transport::Channel>,
+    input: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut reader: Box<dyn AsyncRead + Unpin + Send> = match input {
+        Some(path) => Box::new(
+            tokio::fs::File::open(path)
+                .await
+                .map_err(|e| format!("Failed to open {}: {}", path, e))?,
+        ),
+        None => {
+            if io::stdin().is_terminal() {
+                return Err(
+                    "Requested load from STDIN, but STDIN is a terminal. Use -i or pipe an archive in"
+                        .into(),
+                );
+            }
+            Box::new(tokio::io::stdin())
+        }
+    };
+
+    // The sender outlives the request unless the whole input was read:
+    // dropping the request first cancels it, where closing the channel would
+    // end it cleanly and have the daemon load a truncated archive.
+    let (input_tx, input_rx) = tokio::sync::mpsc::channel(4);
+    let read = async {
+        let mut buf = vec![0u8; LOAD_CHUNK_SIZE];
+        loop {
+            let n = reader.read(&mut buf).await?;
+            if n == 0 {
+                return Ok::<(), io::Error>(());
+            }
+            let request = LoadImageRequest {
+                data: buf[..n].to_vec(),
+            };
+            if input_tx.send(request).await.is_err() {
+                return Ok(());
+            }
+        }
+    };
+
+    let load = client.load_image(tokio_stream::wrappers::ReceiverStream::new(input_rx));
+    tokio::pin!(load);
+    let response = tokio::select! {
+        response = &mut load => response,
+        result = read => {
+            result.map_err(|e| format!("Failed to read image archive: {}", e))?;
+            drop(input_tx);
+            load.await
+        }
+    };
+    let loaded = response
+        .map_err(|e| format!("Failed to load images: {}", e))?
+        .into_inner()
+        .loaded;
+
+    for name in loaded {
+        if name.starts_with("sha256:") {
+            println!("Loaded image ID: {}", name);
+        } else {
+            println!("Loaded image: {}", name);
+        }
+    }
+
+    Ok(())
+}
+
+/// Parses a command given as a JSON array, `["sh", "-c"]`, or as words
+/// separated by spaces.
+fn parse_command(s: &str) -> Result<Vec<String>, String> {
+    if s.trim_start().starts_with('[') {
+        return serde_json::from_str(s).map_err(|e| format!("Invalid JSON array {}: {}", s, e));
+    }
+    Ok(s.split_whitespace().map(str::to_string).collect())
+}
+
+async fn image_import(
+    client: &mut ImageServiceClient<tonic::transport::Channel>,
+    file: &str,
+    reference: String,
+    entrypoint: Vec<String>,
+    cmd: Vec<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut reader: Box<dyn AsyncRead + Unpin + Send> = if file == "-" {
+        if io::stdin().is_terminal() {
+            return Err(
+                "Requested import from STDIN, but STDIN is a terminal. Pipe a tarball in".into(),
+            );
+        }
+        Box::new(tokio::io::stdin())
+    } else {
+        Box::new(
+            tokio::fs::File::open(file)
+                .await
+                .map_err(|e| format!("Failed to open {}: {}", file, e))?,
+        )
+    };
+
+    // As with load, a failed read drops the request, cancelling it, before
+    // the sender: closing the channel would import a truncated tarball.
+    let (input_tx, input_rx) = tokio::sync::mpsc::channel(4);
+    let read = async {
+        let mut buf = vec![0u8; LOAD_CHUNK_SIZE];
+        // The first message carries the image's name and config.
+        let mut request = ImportImageRequest {
+            reference,
+            entrypoint,
+            cmd,
+            data: Vec::new(),
+        };
+        loop {
+            let n = reader.read(&mut buf).await?;
+            request.data = buf[..n].to_vec();
+            if input_tx.send(request).await.is_err() || n == 0 {
+                return Ok::<(), io::Error>(());
+            }
+            request = ImportImageRequest::default();
+        }
+    };
+
+    let import = client.import_image(tokio_stream::wrappers::ReceiverStream::new(input_rx));
+    tokio::pin!(import);
+    let response = tokio::select! {
+        response = &mut import => response,
+        result = read => {
+            result.map_err(|e| format!("Failed to read rootfs tarball: {}", e))?;
+            drop(input_tx);
+            import.await
+        }
+    };
+    let id = response
+        .map_err(|e| format!("Failed to import image: {}", e))?
+        .into_inner()
+        .id;
+
+    println!("{}", id);
+    Ok(())
+}
@@ -1,9 +1,15 @@
 pub mod container;
+pub mod events;
 pub mod health;
 pub mod image;
 pub mod run;
+pub mod system;
+pub mod volume;
 
 pub use container::{ContainerCommands, handle_container_command};
+pub use events::stream_events;
 pub use health::health_check;
 pub use image::{ImageCommands, handle_image_command};
 pub use run::run_container;
+pub use system::{SystemCommands, handle_system_command};
+pub use volume::{VolumeCommands, handle_volume_command};
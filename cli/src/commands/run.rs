@@ -1,79 +1,52 @@
 use ross_core::ross::container_service_client::ContainerServiceClient;
 use ross_core::ross::image_service_client::ImageServiceClient;
 use ross_core::ross::{
-    ContainerConfig, CreateContainerRequest, HostConfig, InteractiveInput, InteractiveStart,
-    PortBinding, PullImageRequest, RemoveContainerRequest, StartContainerRequest,
-    WaitContainerRequest, WindowSize, interactive_input, interactive_output,
+    ContainerConfig, CreateContainerRequest, CreateContainerResponse, HostConfig, InteractiveInput,
+    InteractiveStart, PortBinding, PullImageRequest, Resources, RestartPolicy,
+    StartContainerRequest, WaitContainerRequest, WindowSize, interactive_input, interactive_output,
     wait_container_output::Output,
 };
 use std::io::Write;
+use std::path::PathBuf;
 use tokio_stream::StreamExt;
 
+use crate::connection::Daemon;
+use crate::detach::{DetachKeys, DetachMatcher};
+use crate::utils::entrypoint_args;
+
 #[allow(clippy::too_many_arguments)]
 pub async fn run_container(
-    addr: &str,
+    daemon: &Daemon,
     image: &str,
     name: Option<String>,
     rm: bool,
+    cidfile: Option<PathBuf>,
     detach: bool,
     tty: bool,
     interactive: bool,
+    detach_keys: DetachKeys,
     env: Vec<String>,
+    entrypoint: Option<String>,
     publish: Vec<String>,
     volume: Vec<String>,
+    read_only: bool,
+    tmpfs: Vec<(String, String)>,
+    network: Option<String>,
     network_host: bool,
+    dns: Vec<String>,
+    dns_search: Vec<String>,
+    dns_options: Vec<String>,
+    add_host: Vec<String>,
+    egress_allow: Vec<String>,
+    egress_deny: Vec<String>,
+    memory: Option<i64>,
+    cpus: Option<f64>,
+    restart: Option<RestartPolicy>,
+    pull: String,
     command: Vec<String>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut image_client = ImageServiceClient::connect(addr.to_string())
-        .await
-        .map_err(|e| {
-            format!(
-                "Failed to connect to daemon at {}: {}. Is the daemon running?",
-                addr, e
-            )
-        })?;
-
-    let mut container_client = ContainerServiceClient::connect(addr.to_string()).await?;
-
-    let (image_name, tag) = parse_image_reference(image);
-
-    eprintln!("Pulling image {}:{}...", image_name, tag);
-    let mut pull_stream = image_client
-        .pull_image(PullImageRequest {
-            image_name: image_name.clone(),
-            tag: tag.clone(),
-            registry_auth: None,
-        })
-        .await
-        .map_err(|e| format!("Failed to pull image: {}", e))?
-        .into_inner();
-
-    let mut image_id = String::new();
-    while let Some(progress) = pull_stream.next().await {
-        match progress {
-            Ok(p) => {
-                if !p.id.is_empty() {
-                    image_id = p.id.clone();
-                }
-                if !p.status.is_empty() {
-                    if !p.id.is_empty() {
-                        eprintln!("{}: {}", p.id, p.status);
-                    } else {
-                        eprintln!("{}", p.status);
-                    }
-                }
-            }
-            Err(e) => {
-                return Err(format!("Pull failed: {}", e).into());
-            }
-        }
-    }
-
-    if image_id.is_empty() {
-        image_id = format!("{}:{}", image_name, tag);
-    }
-
-    eprintln!("Image pulled: {}", image_id);
+    let channel = daemon.connect().await?;
+    let mut container_client = ContainerServiceClient::new(channel);
 
     let port_bindings = publish
         .iter()
@@ -97,41 +70,72 @@ pub async fn run_container(
         .collect();
 
     let config = ContainerConfig {
-        image: image_id.clone(),
+        image: image.to_string(),
         env,
         cmd: command,
+        entrypoint_set: entrypoint.is_some(),
+        entrypoint: entrypoint_args(entrypoint),
         tty,
         open_stdin: interactive,
+        // As with Docker, the session's end is the end of the container's
+        // stdin, which a detached container keeps open for `attach`.
+        stdin_once: interactive && !detach,
         ..Default::default()
     };
 
     let network_mode = if network_host {
         "host".to_string()
     } else {
-        String::new()
+        network.unwrap_or_default()
     };
 
     let host_config = HostConfig {
         port_bindings,
         binds: volume,
+        readonly_rootfs: read_only,
+        tmpfs: tmpfs.into_iter().collect(),
         auto_remove: rm,
         network_mode,
+        dns,
+        dns_search,
+        dns_options,
+        extra_hosts: add_host,
+        egress_allow,
+        egress_deny,
+        resources: Some(Resources {
+            memory: memory.unwrap_or_default(),
+            nano_cpus: cpus.map(|c| (c * 1e9) as i64).unwrap_or_default(),
+            ..Default::default()
+        }),
+        restart_policy: restart,
         ..Default::default()
     };
 
-    eprintln!("Creating container...");
-    let create_response = container_client
-        .create_container(CreateContainerRequest {
-            name: name.clone().unwrap_or_default(),
-            config: Some(config),
-            host_config: Some(host_config),
-            networking_config: None,
-        })
-        .await
-        .map_err(|e| format!("Failed to create container: {}", e))?;
+    // Claimed before the container is created, so that a file left by
+    // another container fails the run instead of being overwritten.
+    let mut cidfile = cidfile.map(CidFile::claim).transpose()?;
 
-    let container_id = create_response.into_inner().id;
+    eprintln!("Creating container...");
+    let request = CreateContainerRequest {
+        name: name.clone().unwrap_or_default(),
+        config: Some(config),
+        host_config: Some(host_config),
+        networking_config: None,
+        pull,
+    };
+    let container_id = match create_container(daemon, &mut container_client, request).await {
+        Ok(response) => response.id,
+        Err(e) => {
+            if let Some(cidfile) = cidfile {
+                cidfile.remove();
+            }
+            return Err(e);
+        }
+    };
     eprintln!("Container created: {}", container_id);
+    if let Some(cidfile) = &mut cidfile {
+        cidfile.write(&container_id)?;
+    }
 
     if detach {
         // For detached mode, start the container and return immediately
@@ -148,9 +152,17 @@ pub async fn run_container(
         return Ok(());
     }
 
-    let exit_code = if tty && interactive {
-        // Interactive mode with TTY - use bidirectional streaming
-        run_interactive_session(&mut container_client, &container_id).await?
+    let exit_code = if interactive {
+        // Interactive mode - use bidirectional streaming so stdin reaches the container
+        match run_interactive_session(&mut container_client, &container_id, tty, &detach_keys)
+            .await?
+        {
+            Some(exit_code) => exit_code,
+            None => {
+                eprintln!("Detached from container {}", container_id);
+                return Ok(());
+            }
+        }
     } else {
         // Non-interactive mode - use wait which starts and streams output
         run_non_interactive(&mut container_client, &container_id).await?
@@ -158,17 +170,9 @@ pub async fn run_container(
 
     eprintln!("Container exited with code: {}", exit_code);
 
-    if rm {
-        eprintln!("Removing container...");
-        container_client
-            .remove_container(RemoveContainerRequest {
-                container_id: container_id.clone(),
-                force: false,
-                remove_volumes: false,
-                link: false,
-            })
-            .await
-            .map_err(|e| format!("Failed to remove container: {}", e))?;
+    // The daemon removed the container, so the ID in the file is stale.
+    if rm && let Some(cidfile) = cidfile {
+        cidfile.remove();
     }
 
     if exit_code != 0 {
@@ -178,14 +182,117 @@ pub async fn run_container(
     Ok(())
 }
 
-fn parse_image_reference(image: &str) -> (String, String) {
-    if let Some(pos) = image.rfind(':') {
-        let potential_tag = &image[pos + 1..];
-        if !potential_tag.contains('/') {
-            return (image[..pos].to_string(), potential_tag.to_string());
+/// Creates the container `request` describes. The image is pulled here
+/// rather than by the daemon, as the request's pull policy says, so that
+/// the pull's progress is shown.
+pub(crate) async fn create_container(
+    daemon: &Daemon,
+    client: &mut ContainerServiceClient<tonic::transport::Channel>,
+    mut request: CreateContainerRequest,
+) -> Result<CreateContainerResponse, Box<dyn std::error::Error>> {
+    let image = request
+        .config
+        .as_ref()
+        .map(|c| c.image.clone())
+        .unwrap_or_default();
+    let policy = std::mem::replace(&mut request.pull, "never".to_string());
+    if policy == "always" {
+        pull_image(daemon, &image).await?;
+    }
+
+    let created = match client.create_container(request.clone()).await {
+        Err(e) if matches!(policy.as_str(), "" | "missing") && is_image_not_found(&e) => {
+            eprintln!("Unable to find image '{}' locally", image);
+            pull_image(daemon, &image).await?;
+            client.create_container(request).await
+        }
+        created => created,
+    };
+    Ok(created
+        .map_err(|e| format!("Failed to create container: {}", e))?
+        .into_inner())
+}
+
+fn is_image_not_found(status: &tonic::Status) -> bool {
+    status.code() == tonic::Code::NotFound && status.message().starts_with("image not found")
+}
+
+/// Pulls `image`, reporting one line per phase on stderr, and fails on the
+/// first error the pull reports.
+async fn pull_image(daemon: &Daemon, image: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = ImageServiceClient::new(daemon.connect().await?);
+
+    eprintln!("Pulling image {}...", image);
+    let mut stream = client
+        .pull_image(PullImageRequest {
+            image_name: image.to_string(),
+            tag: String::new(),
+            registry_auth: None,
+            platform: String::new(),
+        })
+        .await
+        .map_err(|e| format!("Failed to pull image: {}", e))?
+        .into_inner();
+
+    while let Some(progress) = stream.next().await {
+        let p = progress.map_err(|e| format!("Pull failed: {}", e))?;
+        if !p.error.is_empty() {
+            return Err(format!("Pull failed: {}", p.error).into());
+        }
+        // Skip byte-level download updates; one line per phase is enough here.
+        if !p.status.is_empty() && p.progress_detail.is_none() {
+            if !p.id.is_empty() {
+                eprintln!("{}: {}", p.id, p.status);
+            } else {
+                eprintln!("{}", p.status);
+            }
         }
     }
-    (image.to_string(), "latest".to_string())
+    Ok(())
+}
+
+/// The file `--cidfile` writes the container ID to, for supervisors that
+/// track the container by it.
+struct CidFile {
+    path: PathBuf,
+    file: std::fs::File,
+}
+
+impl CidFile {
+    /// Creates the file, refusing one that exists: the container it names
+    /// may still be running.
+    fn claim(path: PathBuf) -> Result<Self, String> {
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::AlreadyExists => format!(
+                    "Container ID file {} already exists, make sure the other container isn't running or delete it",
+                    path.display()
+                ),
+                _ => format!(
+                    "Failed to create container ID file {}: {}",
+                    path.display(),
+                    e
+                ),
+            })?;
+        Ok(Self { path, file })
+    }
+
+    fn write(&mut self, container_id: &str) -> Result<(), String> {
+        self.file.write_all(container_id.as_bytes()).map_err(|e| {
+            format!(
+                "Failed to write container ID file {}: {}",
+                self.path.display(),
+                e
+            )
+        })
+    }
+
+    fn remove(self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
 }
 
 async fn run_non_interactive(
@@ -238,10 +345,14 @@ async fn run_non_interactive(
     Ok(exit_code)
 }
 
+/// Runs the container with the local terminal attached. Returns its exit
+/// code, or `None` when the user detached, leaving it running.
 async fn run_interactive_session(
     client: &mut ContainerServiceClient<tonic::transport::Channel>,
     container_id: &str,
-) -> Result<i64, Box<dyn std::error::Error>> {
+    tty: bool,
+    detach_keys: &DetachKeys,
+) -> Result<Option<i64>, Box<dyn std::error::Error>> {
     use tokio::io::AsyncWriteExt;
 
     eprintln!("Starting interactive session...");
@@ -254,14 +365,14 @@ async fn run_interactive_session(
         .send(InteractiveInput {
             input: Some(interactive_input::Input::Start(InteractiveStart {
                 container_id: container_id.to_string(),
-                tty: true,
+                tty,
             })),
         })
         .await
         .map_err(|e| format!("Failed to send start message: {}", e))?;
 
     // Get terminal size and send resize
-    if let Some((width, height)) = get_terminal_size() {
+    if tty && let Some((width, height)) = get_terminal_size() {
         let _ = input_tx
             .send(InteractiveInput {
                 input: Some(interactive_input::Input::Resize(WindowSize {
@@ -283,10 +394,14 @@ async fn run_interactive_session(
         .into_inner();
 
     // Set up raw mode for terminal AFTER starting the RPC
-    let _raw_guard = setup_raw_mode();
+    let _raw_guard = tty.then(setup_raw_mode);
 
-    // Spawn a thread to read stdin using libc::read directly
+    // Spawn a thread to read stdin using libc::read directly. Its end is
+    // passed on, which the container sees as EOF when its stdin is only
+    // open once and it has no TTY. Typing the detach keys stops it instead.
     let input_tx_clone = input_tx.clone();
+    let mut matcher = DetachMatcher::new(detach_keys);
+    let (detach_tx, mut detach_rx) = tokio::sync::mpsc::channel::<()>(1);
     std::thread::spawn(move || {
         let mut buf = [0u8; 1024];
 
@@ -300,31 +415,90 @@ async fn run_interactive_session(
             };
 
             if n <= 0 {
+                let _ = input_tx_clone.blocking_send(InteractiveInput {
+                    input: Some(interactive_input::Input::CloseStdin(true)),
+                });
                 break;
             }
 
+            let (forward, detached) = matcher.feed(&buf[..n as usize]);
+            if !forward.is_empty() {
+                let msg = InteractiveInput {
+                    input: Some(interactive_input::Input::Stdin(forward)),
+                };
+                if input_tx_clone.blocking_send(msg).is_err() {
+                    break;
+                }
+            }
+            if detached {
+                let _ = detach_tx.blocking_send(());
+                break;
+            }
+        }
+    });
+
+    // Forward signals sent to the CLI to the container rather than letting
+    // them end the session. With a TTY the terminal is raw, so Ctrl-C arrives
+    // as input and the container's PTY raises SIGINT itself.
+    let signal_task = tokio::spawn(forward_signals(input_tx.clone()));
+
+    // Forward terminal resizes so the container's PTY follows the local terminal
+    let resize_tx = input_tx;
+    let resize_task = tokio::spawn(async move {
+        if !tty {
+            return;
+        }
+
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let Ok(mut winch) = signal(SignalKind::window_change()) else {
+            return;
+        };
+        while winch.recv().await.is_some() {
+            let Some((width, height)) = get_terminal_size() else {
+                continue;
+            };
             let msg = InteractiveInput {
-                input: Some(interactive_input::Input::Stdin(buf[..n as usize].to_vec())),
+                input: Some(interactive_input::Input::Resize(WindowSize {
+                    width: width as u32,
+                    height: height as u32,
+                })),
             };
-            if input_tx_clone.blocking_send(msg).is_err() {
+            if resize_tx.send(msg).await.is_err() {
                 break;
             }
         }
     });
 
     // Process output from container
-    let mut exit_code: i64 = 0;
+    let mut exit_code = Some(0);
     let mut stdout = tokio::io::stdout();
+    let mut stderr = tokio::io::stderr();
 
-    while let Some(result) = output_stream.next().await {
+    loop {
+        let result = tokio::select! {
+            result = output_stream.next() => match result {
+                Some(result) => result,
+                None => break,
+            },
+            Some(()) = detach_rx.recv() => {
+                exit_code = None;
+                break;
+            }
+        };
         match result {
             Ok(msg) => match msg.output {
                 Some(interactive_output::Output::Data(data)) => {
-                    stdout.write_all(&data.data).await?;
-                    stdout.flush().await?;
+                    if data.stream == "stderr" {
+                        stderr.write_all(&data.data).await?;
+                        stderr.flush().await?;
+                    } else {
+                        stdout.write_all(&data.data).await?;
+                        stdout.flush().await?;
+                    }
                 }
                 Some(interactive_output::Output::Exit(result)) => {
-                    exit_code = result.status_code;
+                    exit_code = Some(result.status_code);
                     break;
                 }
                 None => {}
@@ -336,9 +510,43 @@ async fn run_interactive_session(
         }
     }
 
+    resize_task.abort();
+    signal_task.abort();
     Ok(exit_code)
 }
 
+/// Sends the signals the CLI receives to the container's process until the
+/// session ends. The end of stdin is sent as its own message, so this may
+/// keep the request stream open after it.
+async fn forward_signals(input_tx: tokio::sync::mpsc::Sender<InteractiveInput>) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let (Ok(mut interrupt), Ok(mut terminate), Ok(mut hangup), Ok(mut quit)) = (
+        signal(SignalKind::interrupt()),
+        signal(SignalKind::terminate()),
+        signal(SignalKind::hangup()),
+        signal(SignalKind::quit()),
+    ) else {
+        return;
+    };
+
+    loop {
+        let name = tokio::select! {
+            Some(()) = interrupt.recv() => "SIGINT",
+            Some(()) = terminate.recv() => "SIGTERM",
+            Some(()) = hangup.recv() => "SIGHUP",
+            Some(()) = quit.recv() => "SIGQUIT",
+            else => break,
+        };
+        let msg = InteractiveInput {
+            input: Some(interactive_input::Input::Signal(name.to_string())),
+        };
+        if input_tx.send(msg).await.is_err() {
+            break;
+        }
+    }
+}
+
 fn get_terminal_size() -> Option<(u16, u16)> {
     #[cfg(unix)]
     {
@@ -354,7 +562,7 @@ fn get_terminal_size() -> Option<(u16, u16)> {
     None
 }
 
-struct RawModeGuard {
+pub(crate) struct RawModeGuard {
     #[cfg(unix)]
     original: Option<libc::termios>,
 }
@@ -372,7 +580,7 @@ impl Drop for RawModeGuard {
     }
 }
 
-fn setup_raw_mode() -> RawModeGuard {
+pub(crate) fn setup_raw_mode() -> RawModeGuard {
     #[cfg(unix)]
     {
         use std::os::unix::io::AsRawFd;
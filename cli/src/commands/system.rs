@@ -0,0 +1,66 @@
+use clap::Subcommand;
+use ross_core::ross::ross_client::RossClient;
+use ross_core::ross::{DataUsage, SystemDataUsageRequest};
+
+use crate::connection::Daemon;
+use crate::utils::format_size;
+
+#[derive(Subcommand)]
+pub enum SystemCommands {
+    /// Show disk usage of images, containers and volumes
+    Df,
+}
+
+pub async fn handle_system_command(
+    daemon: &Daemon,
+    cmd: SystemCommands,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = RossClient::new(daemon.connect().await?);
+
+    match cmd {
+        SystemCommands::Df => {
+            let usage = client
+                .system_data_usage(SystemDataUsageRequest {})
+                .await
+                .map_err(|e| format!("Failed to get disk usage: {}", e))?
+                .into_inner();
+
+            println!(
+                "{:<15} {:<8} {:<8} {:<12} RECLAIMABLE",
+                "TYPE", "TOTAL", "ACTIVE", "SIZE"
+            );
+            for (kind, usage) in [
+                ("Images", usage.images),
+                ("Containers", usage.containers),
+                ("Local Volumes", usage.volumes),
+            ] {
+                let usage = usage.unwrap_or_default();
+                println!(
+                    "{:<15} {:<8} {:<8} {:<12} {}",
+                    kind,
+                    usage.count,
+                    usage.active,
+                    format_size(usage.size.max(0) as u64),
+                    format_reclaimable(&usage)
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The reclaimable space and its share of the total, as `docker system df`
+/// shows it.
+fn format_reclaimable(usage: &DataUsage) -> String {
+    let percent = if usage.size > 0 {
+        usage.reclaimable * 100 / usage.size
+    } else {
+        0
+    };
+    format!(
+        "{} ({}%)",
+        format_size(usage.reclaimable.max(0) as u64),
+        percent
+    )
+}
@@ -0,0 +1,93 @@
+use clap::Subcommand;
+use ross_core::ross::container_service_client::ContainerServiceClient;
+use ross_core::ross::{CreateVolumeRequest, ListVolumesRequest, RemoveVolumeRequest};
+
+use crate::connection::Daemon;
+
+#[derive(Subcommand)]
+pub enum VolumeCommands {
+    /// Create a volume
+    Create {
+        /// Volume name, generated if omitted
+        name: Option<String>,
+
+        /// Set metadata on the volume (KEY[=VALUE])
+        #[arg(long, value_parser = crate::utils::parse_label)]
+        label: Vec<(String, String)>,
+    },
+    /// List volumes
+    #[command(visible_alias = "ls")]
+    List {
+        /// Only show volume names
+        #[arg(long, short)]
+        quiet: bool,
+    },
+    /// Remove one or more volumes
+    #[command(visible_alias = "rm")]
+    Remove {
+        /// Volume names
+        #[arg(required = true)]
+        names: Vec<String>,
+    },
+}
+
+pub async fn handle_volume_command(
+    daemon: &Daemon,
+    cmd: VolumeCommands,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = ContainerServiceClient::new(daemon.connect().await?);
+
+    match cmd {
+        VolumeCommands::Create { name, label } => {
+            let response = client
+                .create_volume(CreateVolumeRequest {
+                    name: name.unwrap_or_default(),
+                    labels: label.into_iter().collect(),
+                })
+                .await
+                .map_err(|e| format!("Failed to create volume: {}", e))?;
+            if let Some(volume) = response.into_inner().volume {
+                println!("{}", volume.name);
+            }
+        }
+        VolumeCommands::List { quiet } => {
+            let volumes = client
+                .list_volumes(ListVolumesRequest {})
+                .await
+                .map_err(|e| format!("Failed to list volumes: {}", e))?
+                .into_inner()
+                .volumes;
+
+            if quiet {
+                for volume in volumes {
+                    println!("{}", volume.name);
+                }
+                return Ok(());
+            }
+            println!("DRIVER     VOLUME NAME");
+            for volume in volumes {
+                println!("{:<10} {}", volume.driver, volume.name);
+            }
+        }
+        VolumeCommands::Remove { names } => {
+            let mut failed = false;
+            for name in names {
+                match client
+                    .remove_volume(RemoveVolumeRequest { name: name.clone() })
+                    .await
+                {
+                    Ok(_) => println!("{}", name),
+                    Err(e) => {
+                        eprintln!("Error removing volume {}: {}", name, e);
+                        failed = true;
+                    }
+                }
+            }
+            if failed {
+                return Err("Failed to remove one or more volumes".into());
+            }
+        }
+    }
+
+    Ok(())
+}
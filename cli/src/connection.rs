@@ -0,0 +1,94 @@
+//! How the CLI reaches the daemon: plain HTTP/2, or TLS when the daemon
+//! listens with a certificate.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity};
+
+/// How long to wait for the daemon when `--connect-timeout` is not given.
+pub const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 5;
+
+/// How often an open connection pings the daemon, and how long it waits for
+/// the answer before giving the connection up. Long `attach`, `logs -f` and
+/// `events` streams notice a daemon that went away instead of hanging.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+const KEEPALIVE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// TLS settings for connecting to a daemon started with `--tls-cert`.
+pub struct TlsOptions {
+    /// CA certificate the daemon's certificate is verified against.
+    pub ca: PathBuf,
+    /// Client certificate and key, for daemons that require one (`--tls-ca`).
+    pub identity: Option<(PathBuf, PathBuf)>,
+}
+
+/// The daemon the CLI talks to.
+pub struct Daemon {
+    url: String,
+    tls: Option<TlsOptions>,
+    connect_timeout: Duration,
+}
+
+impl Daemon {
+    pub fn new(host: &str, port: u16, tls: Option<TlsOptions>) -> Self {
+        let scheme = if tls.is_some() { "https" } else { "http" };
+        Self {
+            url: format!("{}://{}:{}", scheme, host, port),
+            tls,
+            connect_timeout: Duration::from_secs(DEFAULT_CONNECT_TIMEOUT_SECS),
+        }
+    }
+
+    /// Gives up connecting, TLS handshake included, after `timeout`.
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Opens a channel to the daemon, shared by all the clients of a command.
+    /// The connection is kept alive with HTTP/2 pings for as long as the
+    /// command runs.
+    pub async fn connect(&self) -> Result<Channel, Box<dyn std::error::Error>> {
+        let mut endpoint = Endpoint::from_shared(self.url.clone())?
+            .tcp_nodelay(true)
+            .http2_keep_alive_interval(KEEPALIVE_INTERVAL)
+            .keep_alive_timeout(KEEPALIVE_TIMEOUT)
+            .keep_alive_while_idle(true);
+        if let Some(tls) = &self.tls {
+            endpoint = endpoint.tls_config(self.tls_config(tls)?)?;
+        }
+        match tokio::time::timeout(self.connect_timeout, endpoint.connect()).await {
+            Ok(Ok(channel)) => Ok(channel),
+            Ok(Err(e)) => Err(format!(
+                "Failed to connect to daemon at {}: {}. Is the daemon running?",
+                self, e
+            )
+            .into()),
+            Err(_) => Err(format!(
+                "Timed out after {:?} connecting to daemon at {}. Is the daemon running?",
+                self.connect_timeout, self
+            )
+            .into()),
+        }
+    }
+
+    fn tls_config(&self, tls: &TlsOptions) -> Result<ClientTlsConfig, Box<dyn std::error::Error>> {
+        let ca = read_pem(&tls.ca)?;
+        let mut config = ClientTlsConfig::new().ca_certificate(Certificate::from_pem(ca));
+        if let Some((cert, key)) = &tls.identity {
+            config = config.identity(Identity::from_pem(read_pem(cert)?, read_pem(key)?));
+        }
+        Ok(config)
+    }
+}
+
+impl fmt::Display for Daemon {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.url)
+    }
+}
+
+fn read_pem(path: &Path) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))
+}
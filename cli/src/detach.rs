@@ -0,0 +1,137 @@
+//! The key sequence that detaches the CLI from an interactive container,
+//! leaving it running, as `docker attach` does.
+
+/// Key sequence used when `--detach-keys` is not given.
+pub const DEFAULT_DETACH_KEYS: &str = "ctrl-p,ctrl-q";
+
+/// A parsed `--detach-keys` value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DetachKeys {
+    /// The sequence as given, passed on to the daemon.
+    pub spec: String,
+    /// The bytes a terminal sends for the sequence.
+    pub sequence: Vec<u8>,
+}
+
+impl Default for DetachKeys {
+    fn default() -> Self {
+        parse_detach_keys(DEFAULT_DETACH_KEYS).expect("default detach keys are valid")
+    }
+}
+
+/// Parses a detach key sequence such as `ctrl-p,ctrl-q`. Each
+/// comma-separated key is either a single character or `ctrl-<key>`.
+pub fn parse_detach_keys(s: &str) -> Result<DetachKeys, String> {
+    let spec = match s.trim() {
+        "" => DEFAULT_DETACH_KEYS,
+        spec => spec,
+    };
+    let invalid = || format!("invalid detach keys: {}", spec);
+
+    let mut sequence = Vec::new();
+    for key in spec.split(',') {
+        let key = key.trim();
+        let byte = match key.strip_prefix("ctrl-") {
+            Some(ctrl) => {
+                let [c] = ctrl.as_bytes() else {
+                    return Err(invalid());
+                };
+                match c.to_ascii_lowercase() {
+                    c @ b'a'..=b'z' => c - b'a' + 1,
+                    b'@' => 0,
+                    c @ (b'[' | b'\\' | b']' | b'^' | b'_') => c - b'@',
+                    _ => return Err(invalid()),
+                }
+            }
+            None => match key.as_bytes() {
+                [c] if c.is_ascii() => *c,
+                _ => return Err(invalid()),
+            },
+        };
+        sequence.push(byte);
+    }
+
+    Ok(DetachKeys {
+        spec: spec.to_string(),
+        sequence,
+    })
+}
+
+/// Watches stdin for the detach sequence. Bytes that might start it are
+/// held back until it is completed or broken, so the container never sees
+/// part of a detach.
+pub struct DetachMatcher {
+    sequence: Vec<u8>,
+    matched: usize,
+}
+
+impl DetachMatcher {
+    pub fn new(keys: &DetachKeys) -> Self {
+        Self {
+            sequence: keys.sequence.clone(),
+            matched: 0,
+        }
+    }
+
+    /// Feeds `input` through the matcher, returning the bytes to forward to
+    /// the container and whether the detach sequence was completed. Input
+    /// after the sequence is dropped.
+    pub fn feed(&mut self, input: &[u8]) -> (Vec<u8>, bool) {
+        let mut forward = Vec::with_capacity(input.len());
+
+        for &byte in input {
+            if byte == self.sequence[self.matched] {
+                self.matched += 1;
+                if self.matched == self.sequence.len() {
+                    self.matched = 0;
+                    return (forward, true);
+                }
+                continue;
+            }
+
+            // The held-back prefix was ordinary input after all.
+            forward.extend_from_slice(&self.sequence[..self.matched]);
+            self.matched = 0;
+            if byte == self.sequence[0] {
+                self.matched = 1;
+            } else {
+                forward.push(byte);
+            }
+        }
+
+        (forward, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_detach_keys() {
+        assert_eq!(DetachKeys::default().sequence, vec![0x10, 0x11]);
+        assert_eq!(parse_detach_keys("").unwrap(), DetachKeys::default());
+        let keys = parse_detach_keys("ctrl-a, x").unwrap();
+        assert_eq!(keys.sequence, vec![0x01, b'x']);
+        assert_eq!(keys.spec, "ctrl-a, x");
+        assert_eq!(
+            parse_detach_keys("ctrl-@,ctrl-\\").unwrap().sequence,
+            vec![0x00, 0x1c]
+        );
+        assert!(parse_detach_keys("ctrl-").is_err());
+        assert!(parse_detach_keys("ctrl-1").is_err());
+        assert!(parse_detach_keys("ab").is_err());
+        assert!(parse_detach_keys("ctrl-p,").is_err());
+    }
+
+    #[test]
+    fn test_detach_matcher() {
+        let mut matcher = DetachMatcher::new(&DetachKeys::default());
+
+        assert_eq!(matcher.feed(b"ls\n"), (b"ls\n".to_vec(), false));
+        assert_eq!(matcher.feed(&[b'a', 0x10]), (b"a".to_vec(), false));
+        assert_eq!(matcher.feed(b"b"), (vec![0x10, b'b'], false));
+        assert_eq!(matcher.feed(&[0x10, 0x10]), (vec![0x10], false));
+        assert_eq!(matcher.feed(&[0x11, b'z']), (vec![], true));
+    }
+}
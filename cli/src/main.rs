@@ -1,11 +1,19 @@
 mod commands;
+mod connection;
+mod detach;
 mod utils;
 
 use clap::{Parser, Subcommand};
 use commands::{
-    ContainerCommands, ImageCommands, handle_container_command, handle_image_command, health_check,
-    run_container,
+    ContainerCommands, ImageCommands, SystemCommands, VolumeCommands, handle_container_command,
+    handle_image_command, handle_system_command, handle_volume_command, health_check,
+    run_container, stream_events,
 };
+use connection::{Daemon, TlsOptions};
+use ross_core::ross::RestartPolicy;
+use std::path::PathBuf;
+use std::time::Duration;
+use utils::OutputFormat;
 
 #[derive(Parser)]
 #[command(name = "ross")]
@@ -19,6 +27,27 @@ struct Cli {
     #[arg(long, global = true, default_value_t = 50051)]
     port: u16,
 
+    /// Connect over TLS, verifying the daemon's certificate against this CA
+    #[arg(long, global = true, value_name = "PATH")]
+    tls_ca: Option<PathBuf>,
+
+    /// Client certificate to present to a daemon that requires one
+    #[arg(long, global = true, value_name = "PATH", requires_all = ["tls_ca", "tls_key"])]
+    tls_cert: Option<PathBuf>,
+
+    /// Private key of the client certificate
+    #[arg(long, global = true, value_name = "PATH", requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// Seconds to wait for the daemon to accept the connection
+    #[arg(long, global = true, value_name = "SECONDS", default_value_t = connection::DEFAULT_CONNECT_TIMEOUT_SECS)]
+    connect_timeout: u64,
+
+    /// Output format for ps, inspect and stats: table, json, or a template
+    /// such as '{{.Id}}'
+    #[arg(long, global = true, default_value = "table", value_parser = crate::utils::parse_format)]
+    format: OutputFormat,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -40,6 +69,10 @@ enum Commands {
         #[arg(long)]
         rm: bool,
 
+        /// Write the container ID to the file
+        #[arg(long, value_name = "PATH")]
+        cidfile: Option<PathBuf>,
+
         /// Run container in the background
         #[arg(long, short)]
         detach: bool,
@@ -52,32 +85,142 @@ enum Commands {
         #[arg(long, short)]
         interactive: bool,
 
+        /// Key sequence that detaches from the container, leaving it running
+        #[arg(long, value_name = "KEYS", default_value = crate::detach::DEFAULT_DETACH_KEYS, value_parser = crate::detach::parse_detach_keys)]
+        detach_keys: crate::detach::DetachKeys,
+
         /// Set environment variables (KEY=VAL)
         #[arg(long, short)]
         env: Vec<String>,
 
+        /// Read environment variables from a file of KEY=VAL lines
+        #[arg(long = "env-file", value_name = "PATH")]
+        env_file: Vec<PathBuf>,
+
+        /// Overwrite the image's entrypoint ("" clears it)
+        #[arg(long, value_name = "COMMAND")]
+        entrypoint: Option<String>,
+
         /// Publish a container's port(s) to the host (HOST:CONTAINER)
         #[arg(long = "publish", short = 'p')]
         publish: Vec<String>,
 
-        /// Bind mount a volume (SRC:DST)
+        /// Mount a volume: a host path (/SRC:DST[:OPTIONS]), a named volume
+        /// (NAME:DST[:OPTIONS]) or an anonymous volume (DST)
         #[arg(long, short)]
         volume: Vec<String>,
 
-        /// Use host network
+        /// Mount the container's root filesystem as read only
         #[arg(long)]
+        read_only: bool,
+
+        /// Mount a tmpfs (PATH[:OPTIONS])
+        #[arg(long, value_parser = crate::utils::parse_tmpfs)]
+        tmpfs: Vec<(String, String)>,
+
+        /// Network mode (bridge, host or none)
+        #[arg(long, value_name = "MODE")]
+        network: Option<String>,
+
+        /// Use host network (same as --network host)
+        #[arg(long, conflicts_with = "network")]
         network_host: bool,
 
+        /// Set a custom DNS server
+        #[arg(long, value_name = "IP")]
+        dns: Vec<String>,
+
+        /// Set a custom DNS search domain
+        #[arg(long, value_name = "DOMAIN")]
+        dns_search: Vec<String>,
+
+        /// Set a DNS resolver option (e.g. ndots:2)
+        #[arg(long = "dns-option", value_name = "OPTION")]
+        dns_options: Vec<String>,
+
+        /// Add a custom host-to-IP mapping (NAME:IP)
+        #[arg(long = "add-host", value_name = "NAME:IP")]
+        add_host: Vec<String>,
+
+        /// Only allow outgoing connections to this network (CIDR)
+        #[arg(long = "egress-allow", value_name = "CIDR")]
+        egress_allow: Vec<String>,
+
+        /// Refuse outgoing connections to this network (CIDR)
+        #[arg(long = "egress-deny", value_name = "CIDR")]
+        egress_deny: Vec<String>,
+
+        /// Memory limit (e.g. 512m, 1g)
+        #[arg(long, short = 'm', value_parser = crate::utils::parse_memory)]
+        memory: Option<i64>,
+
+        /// Number of CPUs (e.g. 1.5)
+        #[arg(long)]
+        cpus: Option<f64>,
+
+        /// Restart policy (no, always, unless-stopped, on-failure[:N])
+        #[arg(long, value_parser = crate::utils::parse_restart_policy)]
+        restart: Option<RestartPolicy>,
+
+        /// Pull the image before creating the container (always, missing, never)
+        #[arg(long, value_name = "POLICY", default_value = "missing", value_parser = ["always", "missing", "never"])]
+        pull: String,
+
         /// Command to run
         #[arg(last = true)]
         command: Vec<String>,
     },
+    /// Stream container lifecycle events (create, start, kill, die, stop,
+    /// restart, pause, unpause, rename, update, destroy)
+    Events {
+        /// Filter events (event=ACTION, container=NAME_OR_ID, image=IMAGE)
+        #[arg(long, short, value_parser = crate::utils::parse_filter)]
+        filter: Vec<(String, String)>,
+
+        /// Show past events since this time (RFC 3339, a date, Unix seconds,
+        /// or a duration such as 10m)
+        #[arg(long, value_parser = crate::utils::parse_time)]
+        since: Option<prost_types::Timestamp>,
+    },
+    /// Inspect changes to files or directories on a container's filesystem
+    Diff {
+        /// Container ID or name
+        container_id: String,
+    },
+    /// Display the running processes of a container
+    Top {
+        /// Container ID or name
+        container_id: String,
+
+        /// Options passed to ps, e.g. aux
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        ps_args: Vec<String>,
+    },
+    /// Update the resource limits of a container
+    Update {
+        /// Container ID or name
+        container_id: String,
+
+        /// Memory limit (e.g. 512m, 1g)
+        #[arg(long, short = 'm', value_parser = crate::utils::parse_memory)]
+        memory: Option<i64>,
+
+        /// Number of CPUs (e.g. 1.5)
+        #[arg(long)]
+        cpus: Option<f64>,
+    },
     /// Manage images
     #[command(subcommand)]
     Image(ImageCommands),
     /// Manage containers
     #[command(subcommand)]
     Container(ContainerCommands),
+    /// Manage volumes
+    #[command(subcommand)]
+    Volume(VolumeCommands),
+    /// Manage the daemon's data
+    #[command(subcommand)]
+    System(SystemCommands),
 }
 
 #[tokio::main]
@@ -86,46 +229,120 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let cli = Cli::parse();
 
-    let daemon_addr = format!("http://{}:{}", cli.host, cli.port);
+    let tls = cli.tls_ca.map(|ca| TlsOptions {
+        ca,
+        identity: cli.tls_cert.zip(cli.tls_key),
+    });
+    let daemon = Daemon::new(&cli.host, cli.port, tls)
+        .with_connect_timeout(Duration::from_secs(cli.connect_timeout));
 
     match cli.command {
         Some(Commands::Health) => {
-            health_check(&daemon_addr).await?;
+            health_check(&daemon).await?;
         }
         Some(Commands::Run {
             image,
             name,
             rm,
+            cidfile,
             detach,
             tty,
             interactive,
+            detach_keys,
             env,
+            env_file,
+            entrypoint,
             publish,
             volume,
+            read_only,
+            tmpfs,
+            network,
             network_host,
+            dns,
+            dns_search,
+            dns_options,
+            add_host,
+            egress_allow,
+            egress_deny,
+            memory,
+            cpus,
+            restart,
+            pull,
             command,
         }) => {
+            let env = utils::merge_env(&env_file, env)?;
             run_container(
-                &daemon_addr,
+                &daemon,
                 &image,
                 name,
                 rm,
+                cidfile,
                 detach,
                 tty,
                 interactive,
+                detach_keys,
                 env,
+                entrypoint,
                 publish,
                 volume,
+                read_only,
+                tmpfs,
+                network,
                 network_host,
+                dns,
+                dns_search,
+                dns_options,
+                add_host,
+                egress_allow,
+                egress_deny,
+                memory,
+                cpus,
+                restart,
+                pull,
                 command,
             )
             .await?;
         }
+        Some(Commands::Events { filter, since }) => {
+            stream_events(&daemon, since, filter).await?;
+        }
+        Some(Commands::Diff { container_id }) => {
+            let cmd = ContainerCommands::Diff { container_id };
+            handle_container_command(&daemon, cmd, &cli.format).await?;
+        }
+        Some(Commands::Top {
+            container_id,
+            ps_args,
+        }) => {
+            let cmd = ContainerCommands::Top {
+                container_id,
+                ps_args,
+            };
+            handle_container_command(&daemon, cmd, &cli.format).await?;
+        }
+        Some(Commands::Update {
+            container_id,
+            memory,
+            cpus,
+        }) => {
+            let cmd = ContainerCommands::Update {
+                container_id,
+                memory,
+                cpus,
+            };
+            handle_container_command(&daemon, cmd, &cli.format).await?;
+        }
         Some(Commands::Image(cmd)) => {
-            handle_image_command(&daemon_addr, cmd).await?;
+            handle_image_command(&daemon, cmd).await?;
         }
         Some(Commands::Container(cmd)) => {
-            handle_container_command(&daemon_addr, cmd).await?;
+            handle_container_command(&daemon, cmd, &cli.format).await?;
+        }
+        Some(Commands::Volume(cmd)) => {
+            handle_volume_command(&daemon, cmd).await?;
+        }
+        Some(Commands::System(cmd)) => {
+            handle_system_command(&daemon, cmd).await?;
         }
         None => {
             println!("Ross CLI ready. Daemon address: {}:{}", cli.host, cli.port);
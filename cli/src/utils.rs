@@ -1,3 +1,9 @@
+use ross_core::ross::{FilterValues, RestartPolicy};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
 pub fn format_size(bytes: u64) -> String {
     const KB: u64 = 1024;
     const MB: u64 = KB * 1024;
@@ -14,6 +20,249 @@ pub fn format_size(bytes: u64) -> String {
     }
 }
 
+/// Parses a memory size such as `512m` or `1g` into bytes.
+pub fn parse_memory(s: &str) -> Result<i64, String> {
+    let s = s.trim().to_ascii_lowercase();
+    let s = s.strip_suffix('b').unwrap_or(&s);
+    let (digits, multiplier) = match s.char_indices().last() {
+        Some((i, 'k')) => (&s[..i], 1i64 << 10),
+        Some((i, 'm')) => (&s[..i], 1 << 20),
+        Some((i, 'g')) => (&s[..i], 1 << 30),
+        _ => (s, 1),
+    };
+    digits
+        .parse::<i64>()
+        .ok()
+        .filter(|n| *n > 0)
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid memory size: {}", s))
+}
+
+/// Parses a restart policy such as `always` or `on-failure:3`.
+pub fn parse_restart_policy(s: &str) -> Result<RestartPolicy, String> {
+    let (name, count) = match s.split_once(':') {
+        Some((name, count)) => (name, Some(count)),
+        None => (s, None),
+    };
+    if !matches!(name, "no" | "always" | "unless-stopped" | "on-failure") {
+        return Err(format!("invalid restart policy: {}", s));
+    }
+    let maximum_retry_count = match count {
+        Some(count) if name == "on-failure" => count
+            .parse::<i32>()
+            .ok()
+            .filter(|n| *n >= 0)
+            .ok_or_else(|| format!("invalid maximum retry count: {}", count))?,
+        Some(_) => return Err(format!("restart policy {} takes no retry count", name)),
+        None => 0,
+    };
+    Ok(RestartPolicy {
+        name: name.to_string(),
+        maximum_retry_count,
+    })
+}
+
+/// Reads `KEY=VALUE` lines from an env file. Blank lines and `#` comments are
+/// skipped, a bare `KEY` takes its value from the host environment (and is
+/// dropped when unset there), and a value wrapped in matching quotes is
+/// unquoted.
+pub fn read_env_file(path: &Path) -> Result<Vec<String>, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("failed to read env file {}: {}", path.display(), e))?;
+
+    let mut env = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim_start();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key, Some(value)),
+            None => (line.trim_end(), None),
+        };
+        if key.is_empty() || key.contains(char::is_whitespace) {
+            return Err(format!(
+                "{}:{}: invalid variable name: {:?}",
+                path.display(),
+                i + 1,
+                key
+            ));
+        }
+
+        match value {
+            Some(value) => env.push(format!("{}={}", key, unquote(value))),
+            None => {
+                if let Ok(value) = std::env::var(key) {
+                    env.push(format!("{}={}", key, value));
+                }
+            }
+        }
+    }
+
+    Ok(env)
+}
+
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if let Some(inner) = value
+            .strip_prefix(quote)
+            .and_then(|v| v.strip_suffix(quote))
+        {
+            return inner;
+        }
+    }
+    value
+}
+
+/// Combines the variables from `env_files`, in order, with the explicit `-e`
+/// values. A later entry for a key replaces the earlier one in place, so
+/// `-e` overrides the files. A bare `-e KEY` takes its value from the host
+/// environment, or else from the files, and is dropped when neither has it.
+pub fn merge_env(env_files: &[PathBuf], env: Vec<String>) -> Result<Vec<String>, String> {
+    let files = env_files
+        .iter()
+        .map(|path| read_env_file(path))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .flatten();
+    let env = env.into_iter().map(|entry| {
+        if entry.contains('=') {
+            return entry;
+        }
+        match std::env::var(&entry) {
+            Ok(value) => format!("{}={}", entry, value),
+            Err(_) => entry,
+        }
+    });
+    Ok(ross_core::env::merge_env(Vec::new(), files.chain(env)))
+}
+
+/// Parses a point in time given as RFC 3339, a UTC date or date and time
+/// (`2025-01-01`, `2025-01-01T10:00:00`), Unix seconds, or a duration before
+/// now such as `10m` or `1h30m` (units `s`, `m`, `h`).
+pub fn parse_time(s: &str) -> Result<prost_types::Timestamp, String> {
+    parse_time_at(s, chrono::Utc::now().timestamp())
+}
+
+fn parse_time_at(s: &str, now: i64) -> Result<prost_types::Timestamp, String> {
+    let invalid = || {
+        format!(
+            "invalid time: {} (expected RFC 3339, a date, Unix seconds or a duration such as 10m)",
+            s
+        )
+    };
+    let timestamp = |datetime: chrono::DateTime<chrono::Utc>| prost_types::Timestamp {
+        seconds: datetime.timestamp(),
+        nanos: datetime.timestamp_subsec_nanos() as i32,
+    };
+
+    if let Ok(datetime) = chrono::DateTime::parse_from_rfc3339(s) {
+        return Ok(timestamp(datetime.with_timezone(&chrono::Utc)));
+    }
+    if let Ok(datetime) = chrono::NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f") {
+        return Ok(timestamp(datetime.and_utc()));
+    }
+    if let Ok(date) = chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Ok(timestamp(date.and_time(chrono::NaiveTime::MIN).and_utc()));
+    }
+    if s.starts_with(|c: char| c.is_ascii_digit()) && !s.ends_with(['s', 'm', 'h']) {
+        return parse_unix_time(s).ok_or_else(invalid);
+    }
+
+    if s.is_empty() {
+        return Err(invalid());
+    }
+    let mut ago = 0i64;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let multiplier = match rest[digits..].chars().next() {
+            Some('s') => 1,
+            Some('m') => 60,
+            Some('h') => 60 * 60,
+            _ => return Err(invalid()),
+        };
+        ago = rest[..digits]
+            .parse::<i64>()
+            .ok()
+            .and_then(|n| n.checked_mul(multiplier))
+            .and_then(|n| ago.checked_add(n))
+            .ok_or_else(invalid)?;
+        rest = &rest[digits + 1..];
+    }
+    Ok(prost_types::Timestamp {
+        seconds: now - ago,
+        nanos: 0,
+    })
+}
+
+/// Parses Unix seconds with an optional fraction, `1700000000.5`.
+fn parse_unix_time(s: &str) -> Option<prost_types::Timestamp> {
+    let (seconds, fraction) = s.split_once('.').unwrap_or((s, ""));
+    if fraction.len() > 9 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let nanos = if fraction.is_empty() {
+        0
+    } else {
+        format!("{:0<9}", fraction).parse().ok()?
+    };
+    Some(prost_types::Timestamp {
+        seconds: seconds.parse().ok()?,
+        nanos,
+    })
+}
+
+/// Parses a `KEY=VALUE` filter.
+pub fn parse_filter(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() && !value.is_empty() => {
+            Ok((key.to_string(), value.to_string()))
+        }
+        _ => Err(format!("invalid filter: {} (expected KEY=VALUE)", s)),
+    }
+}
+
+/// Parses a `KEY[=VALUE]` label, whose value defaults to empty.
+pub fn parse_label(s: &str) -> Result<(String, String), String> {
+    let (key, value) = s.split_once('=').unwrap_or((s, ""));
+    if key.is_empty() {
+        return Err(format!("invalid label: {} (expected KEY[=VALUE])", s));
+    }
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Groups the values given for the same filter key, any of which the daemon
+/// accepts.
+pub fn merge_filters(filters: Vec<(String, String)>) -> HashMap<String, FilterValues> {
+    let mut merged: HashMap<String, FilterValues> = HashMap::new();
+    for (key, value) in filters {
+        merged.entry(key).or_default().values.push(value);
+    }
+    merged
+}
+
+/// Parses a `PATH[:OPTIONS]` tmpfs mount into its path and options.
+pub fn parse_tmpfs(s: &str) -> Result<(String, String), String> {
+    let (path, options) = s.split_once(':').unwrap_or((s, ""));
+    if !path.starts_with('/') {
+        return Err(format!(
+            "invalid tmpfs: {} (expected an absolute PATH[:OPTIONS])",
+            s
+        ));
+    }
+    Ok((path.to_string(), options.to_string()))
+}
+
+/// The entrypoint `--entrypoint` sets: the command by itself, or nothing
+/// when empty, which clears the image's.
+pub fn entrypoint_args(entrypoint: Option<String>) -> Vec<String> {
+    entrypoint.into_iter().filter(|e| !e.is_empty()).collect()
+}
+
 pub fn format_timestamp(ts: &prost_types::Timestamp) -> String {
     use std::time::{Duration, UNIX_EPOCH};
 
@@ -27,3 +276,298 @@ pub fn format_timestamp(ts: &prost_types::Timestamp) -> String {
         "invalid timestamp".to_string()
     }
 }
+
+/// How long before `now`, in Unix seconds, `ts` was, worded as `docker ps`
+/// does: `5 minutes ago`.
+pub fn format_ago(ts: &prost_types::Timestamp, now: i64) -> String {
+    format!("{} ago", human_duration(now.saturating_sub(ts.seconds)))
+}
+
+/// Words a duration in seconds, rounding it to its largest unit.
+fn human_duration(secs: i64) -> String {
+    let minutes = secs / 60;
+    let hours = (secs + 1800) / 3600;
+    match secs {
+        ..=0 => "Less than a second".to_string(),
+        1 => "1 second".to_string(),
+        2..60 => format!("{} seconds", secs),
+        _ if minutes == 1 => "About a minute".to_string(),
+        _ if minutes < 60 => format!("{} minutes", minutes),
+        _ if hours == 1 => "About an hour".to_string(),
+        _ if hours < 48 => format!("{} hours", hours),
+        _ if hours < 24 * 7 * 2 => format!("{} days", hours / 24),
+        _ if hours < 24 * 30 * 2 => format!("{} weeks", hours / 24 / 7),
+        _ if hours < 24 * 365 * 2 => format!("{} months", hours / 24 / 30),
+        _ => format!("{} years", secs / 3600 / 24 / 365),
+    }
+}
+
+/// How `ps`, `inspect` and `stats` print their results.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputFormat {
+    Table,
+    Json,
+    /// A Go-template-like format such as `{{.Id}}`, rendered once per item.
+    Template(String),
+}
+
+/// Parses `--format`: `table`, `json`, or a template containing `{{...}}`.
+pub fn parse_format(s: &str) -> Result<OutputFormat, String> {
+    match s {
+        "table" => Ok(OutputFormat::Table),
+        "json" => Ok(OutputFormat::Json),
+        s if s.contains("{{") => Ok(OutputFormat::Template(s.to_string())),
+        _ => Err(format!(
+            "invalid format: {} (expected table, json or a template)",
+            s
+        )),
+    }
+}
+
+pub fn print_json<T: Serialize + ?Sized>(value: &T) -> Result<(), Box<dyn std::error::Error>> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+pub fn print_template<T: Serialize>(
+    template: &str,
+    item: &T,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let value = serde_json::to_value(item)?;
+    println!("{}", render_template(template, &value)?);
+    Ok(())
+}
+
+/// Renders `template` for `value`: `{{.Field.Sub}}` is replaced with the
+/// field's value and `{{json .Field}}` with its JSON encoding. Field names
+/// match ignoring case and underscores, so `.SizeRw` finds `size_rw`.
+pub fn render_template(template: &str, value: &Value) -> Result<String, String> {
+    let mut out = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find("}}")
+            .map(|i| start + i)
+            .ok_or_else(|| format!("unclosed action in template: {}", template))?;
+        let action = rest[start + 2..end].trim();
+        rest = &rest[end + 2..];
+
+        let (json, path) = match action.strip_prefix("json ") {
+            Some(path) => (true, path.trim()),
+            None => (false, action),
+        };
+        let path = path
+            .strip_prefix('.')
+            .ok_or_else(|| format!("unsupported template action: {{{{{}}}}}", action))?;
+
+        let mut field = value;
+        for name in path.split('.').filter(|name| !name.is_empty()) {
+            field = lookup_field(field, name).ok_or_else(|| {
+                format!("no field {} in template action {{{{{}}}}}", name, action)
+            })?;
+        }
+
+        match field {
+            Value::String(s) if !json => out.push_str(s),
+            Value::Null if !json => {}
+            field => out.push_str(&field.to_string()),
+        }
+    }
+    out.push_str(rest);
+
+    Ok(out)
+}
+
+fn lookup_field<'a>(value: &'a Value, name: &str) -> Option<&'a Value> {
+    let normalize = |s: &str| s.replace('_', "").to_ascii_lowercase();
+    let name = normalize(name);
+    value
+        .as_object()?
+        .iter()
+        .find(|(key, _)| normalize(key) == name)
+        .map(|(_, field)| field)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes an env file named `name` into `dir`.
+    fn env_file(dir: &tempfile::TempDir, name: &str, content: &str) -> PathBuf {
+        let path = dir.path().join(name);
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_read_env_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = env_file(
+            &dir,
+            "basic.env",
+            "# a comment\n\n   \nPLAIN=value\n  INDENTED=x=y\nEMPTY=\n",
+        );
+        assert_eq!(
+            read_env_file(&path).unwrap(),
+            vec!["PLAIN=value", "INDENTED=x=y", "EMPTY="]
+        );
+
+        let path = env_file(&dir, "bad.env", "OK=1\nBAD KEY=2\n");
+        let err = read_env_file(&path).unwrap_err();
+        assert!(
+            err.ends_with(":2: invalid variable name: \"BAD KEY\""),
+            "{}",
+            err
+        );
+        assert!(read_env_file(&path.with_file_name("missing.env")).is_err());
+    }
+
+    #[test]
+    fn test_read_env_file_quotes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = env_file(
+            &dir,
+            "quoted.env",
+            "DOUBLE=\"a b\"\nSINGLE='c d'\nMIXED=\"e'\nINNER=f \"g\" h\n",
+        );
+        assert_eq!(
+            read_env_file(&path).unwrap(),
+            vec!["DOUBLE=a b", "SINGLE=c d", "MIXED=\"e'", "INNER=f \"g\" h"]
+        );
+        assert_eq!(unquote("\""), "\"");
+        assert_eq!(unquote("''"), "");
+    }
+
+    #[test]
+    fn test_read_env_file_inherits_bare_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = env_file(&dir, "inherit.env", "PATH\nROSS_TEST_UNSET_VARIABLE\n");
+        let path_value = std::env::var("PATH").unwrap();
+        // A key without a value is taken from the environment, if it is set.
+        assert_eq!(
+            read_env_file(&path).unwrap(),
+            vec![format!("PATH={}", path_value)]
+        );
+    }
+
+    #[test]
+    fn test_merge_env_precedence() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = env_file(&dir, "first.env", "A=file1\nB=file1\n");
+        let second = env_file(&dir, "second.env", "B=file2\nC=file2\n");
+        let merged = merge_env(
+            &[first.clone(), second],
+            vec!["C=flag".to_string(), "D=flag".to_string()],
+        )
+        .unwrap();
+        // Later files override earlier ones and --env overrides the files,
+        // each keeping the position the key first had.
+        assert_eq!(merged, vec!["A=file1", "B=file2", "C=flag", "D=flag"]);
+
+        let merged = merge_env(&[], vec!["A=1".to_string(), "A=2".to_string()]).unwrap();
+        assert_eq!(merged, vec!["A=2"]);
+        assert!(merge_env(&[PathBuf::from("/nonexistent/ross.env")], vec![]).is_err());
+
+        // Bare keys come from the host, then the files, or are dropped.
+        let path_value = std::env::var("PATH").unwrap();
+        let merged = merge_env(
+            &[first],
+            vec![
+                "PATH".to_string(),
+                "A".to_string(),
+                "ROSS_TEST_SURELY_UNSET".to_string(),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            merged,
+            vec![
+                "A=file1".to_string(),
+                "B=file1".to_string(),
+                format!("PATH={}", path_value)
+            ]
+        );
+    }
+
+    fn ts(seconds: i64, nanos: i32) -> prost_types::Timestamp {
+        prost_types::Timestamp { seconds, nanos }
+    }
+
+    #[test]
+    fn test_merge_filters_keeps_values_whole() {
+        let filters = vec![
+            ("label".to_string(), "tiers=web,db".to_string()),
+            ("status".to_string(), "running".to_string()),
+            ("label".to_string(), "team".to_string()),
+        ];
+        let merged = merge_filters(filters);
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged["label"].values, vec!["tiers=web,db", "team"]);
+        assert_eq!(merged["status"].values, vec!["running"]);
+    }
+
+    #[test]
+    fn test_parse_time_absolute() {
+        let now = 1_735_732_800;
+        assert_eq!(
+            parse_time_at("2025-01-01T12:00:00Z", now),
+            Ok(ts(1_735_732_800, 0))
+        );
+        assert_eq!(
+            parse_time_at("2025-01-01T13:00:00.25+01:00", now),
+            Ok(ts(1_735_732_800, 250_000_000))
+        );
+        assert_eq!(
+            parse_time_at("2025-01-01T12:00:00", now),
+            Ok(ts(1_735_732_800, 0))
+        );
+        assert_eq!(parse_time_at("2025-01-01", now), Ok(ts(1_735_689_600, 0)));
+        assert_eq!(parse_time_at("1700000000", now), Ok(ts(1_700_000_000, 0)));
+        assert_eq!(
+            parse_time_at("1700000000.5", now),
+            Ok(ts(1_700_000_000, 500_000_000))
+        );
+    }
+
+    #[test]
+    fn test_format_ago() {
+        let now = 1_735_732_800;
+        let ago = |secs| format_ago(&ts(now - secs, 0), now);
+        assert_eq!(ago(0), "Less than a second ago");
+        assert_eq!(ago(-5), "Less than a second ago");
+        assert_eq!(ago(1), "1 second ago");
+        assert_eq!(ago(45), "45 seconds ago");
+        assert_eq!(ago(90), "About a minute ago");
+        assert_eq!(ago(30 * 60), "30 minutes ago");
+        assert_eq!(ago(80 * 60), "About an hour ago");
+        assert_eq!(ago(5 * 3600), "5 hours ago");
+        assert_eq!(ago(3 * 86400), "3 days ago");
+        assert_eq!(ago(21 * 86400), "3 weeks ago");
+        assert_eq!(ago(90 * 86400), "3 months ago");
+        assert_eq!(ago(3 * 365 * 86400), "3 years ago");
+    }
+
+    #[test]
+    fn test_parse_time_relative() {
+        let now = 1_735_732_800;
+        assert_eq!(parse_time_at("30s", now), Ok(ts(now - 30, 0)));
+        assert_eq!(parse_time_at("10m", now), Ok(ts(now - 600, 0)));
+        assert_eq!(parse_time_at("1h30m", now), Ok(ts(now - 5400, 0)));
+        assert_eq!(parse_time_at("0s", now), Ok(ts(now, 0)));
+
+        for invalid in [
+            "",
+            "m",
+            "10d",
+            "-5m",
+            "1h30",
+            "yesterday",
+            "2025-13-01",
+            "1.5.5",
+        ] {
+            assert!(parse_time_at(invalid, now).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use crate::error::ContainerError;
+
+/// Key sequence that detaches an attached client when none is configured.
+pub(crate) const DEFAULT_DETACH_KEYS: &str = "ctrl-p,ctrl-q";
+
+/// Parses a detach key sequence such as `ctrl-p,ctrl-q` into the bytes a
+/// terminal sends for it. Each comma-separated key is either a single
+/// character or `ctrl-<key>`.
+pub(crate) fn parse_detach_keys(keys: &str) -> Result<Vec<u8>, ContainerError> {
+    let keys = keys.trim();
+    let keys = if keys.is_empty() {
+        DEFAULT_DETACH_KEYS
+    } else {
+        keys
+    };
+
+    let invalid = || ContainerError::InvalidArgument(format!("invalid detach keys: {}", keys));
+
+    let mut sequence = Vec::new();
+    for key in keys.split(',') {
+        let key = key.trim();
+        let byte = match key.strip_prefix("ctrl-") {
+            Some(ctrl) => {
+                let [c] = ctrl.as_bytes() else {
+                    return Err(invalid());
+                };
+                match c.to_ascii_lowercase() {
+                    c @ b'a'..=b'z' => c - b'a' + 1,
+                    b'@' => 0,
+                    c @ (b'[' | b'\\' | b']' | b'^' | b'_') => c - b'@',
+                    _ => return Err(invalid()),
+                }
+            }
+            None => match key.as_bytes() {
+                [c] if c.is_ascii() => *c,
+                _ => return Err(invalid()),
+            },
+        };
+        sequence.push(byte);
+    }
+
+    Ok(sequence)
+}
+
+/// Watches client input for the detach sequence.
+///
+/// Bytes that might start the sequence are held back until it is either
+/// completed or broken, so a partial match is never written to the container
+/// only to turn out to be a detach.
+pub(crate) struct DetachMatcher {
+    sequence: Vec<u8>,
+    matched: usize,
+}
+
+impl DetachMatcher {
+    pub(crate) fn new(sequence: Vec<u8>) -> Self {
+        Self {
+            sequence,
+            matched: 0,
+        }
+    }
+
+    /// Feeds `input` through the matcher, returning the bytes to forward to
+    /// the container and whether the detach sequence was completed.
+    pub(crate) fn feed(&mut self, input: &[u8]) -> (Vec<u8>, bool) {
+        let mut forward = Vec::with_capacity(input.len());
+
+        for &byte in input {
+            if self.sequence.is_empty() {
+                forward.push(byte);
+                continue;
+            }
+
+            if byte == self.sequence[self.matched] {
+                self.matched += 1;
+                if self.matched == self.sequence.len() {
+                    self.matched = 0;
+                    return (forward, true);
+                }
+                continue;
+            }
+
+            // The held-back prefix was ordinary input after all.
+            forward.extend_from_slice(&self.sequence[..self.matched]);
+            self.matched = 0;
+            if byte == self.sequence[0] {
+                self.matched = 1;
+            } else {
+                forward.push(byte);
+            }
+        }
+
+        (forward, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_detach_keys() {
+        assert_eq!(parse_detach_keys("").unwrap(), vec![0x10, 0x11]);
+        assert_eq!(parse_detach_keys("ctrl-a,x").unwrap(), vec![0x01, b'x']);
+        assert_eq!(
+            parse_detach_keys("ctrl-@,ctrl-\\").unwrap(),
+            vec![0x00, 0x1c]
+        );
+        assert!(parse_detach_keys("ctrl-").is_err());
+        assert!(parse_detach_keys("ctrl-1").is_err());
+        assert!(parse_detach_keys("ab").is_err());
+    }
+
+    #[test]
+    fn test_detach_matcher() {
+        let mut matcher = DetachMatcher::new(vec![0x10, 0x11]);
+
+        assert_eq!(matcher.feed(b"ls\n"), (b"ls\n".to_vec(), false));
+        assert_eq!(matcher.feed(&[b'a', 0x10]), (b"a".to_vec(), false));
+        assert_eq!(matcher.feed(b"b"), (vec![0x10, b'b'], false));
+        assert_eq!(matcher.feed(&[0x10, 0x10]), (vec![0x10], false));
+        assert_eq!(matcher.feed(&[0x11, b'z']), (vec![], true));
+    }
+}
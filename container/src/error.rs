@@ -23,6 +23,21 @@ pub enum ContainerError {
     #[error("image not found: {0}")]
     ImageNotFound(String),
 
+    #[error("image content is corrupted: {0}")]
+    ImageCorrupted(String),
+
+    #[error("pull failed: {0}")]
+    PullFailed(String),
+
+    #[error("pull access denied: {0}")]
+    PullDenied(String),
+
+    #[error("volume not found: {0}")]
+    VolumeNotFound(String),
+
+    #[error("volume is in use: {0}")]
+    VolumeInUse(String),
+
     #[error("io error: {0}")]
     Io(#[from] std::io::Error),
 
@@ -0,0 +1,217 @@
+//! Container lifecycle events, fanned out to every subscriber of the event
+//! stream.
+
+use crate::error::ContainerError;
+use crate::types::{ContainerEvent, now_timestamp};
+use prost_types::Timestamp;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+
+/// Events kept for subscribers that ask for past ones with `since`.
+const HISTORY_LEN: usize = 1024;
+/// Events buffered per subscriber before a slow one starts missing some.
+const CHANNEL_CAPACITY: usize = 256;
+
+pub(crate) const ACTION_CREATE: &str = "create";
+pub(crate) const ACTION_START: &str = "start";
+pub(crate) const ACTION_KILL: &str = "kill";
+pub(crate) const ACTION_DIE: &str = "die";
+pub(crate) const ACTION_STOP: &str = "stop";
+pub(crate) const ACTION_RESTART: &str = "restart";
+pub(crate) const ACTION_PAUSE: &str = "pause";
+pub(crate) const ACTION_UNPAUSE: &str = "unpause";
+pub(crate) const ACTION_RENAME: &str = "rename";
+pub(crate) const ACTION_UPDATE: &str = "update";
+pub(crate) const ACTION_DESTROY: &str = "destroy";
+
+const FILTER_KEYS: &[&str] = &["event", "container", "image"];
+
+/// Identifies a container run: its start and finish time and the restart
+/// count, which tells apart runs within the same second.
+pub(crate) type Run = (Option<i64>, Option<i64>, u32);
+
+pub(crate) struct EventBus {
+    sender: broadcast::Sender<ContainerEvent>,
+    history: Mutex<VecDeque<ContainerEvent>>,
+    /// Last run whose exit was seen, per container, so that every run dies
+    /// exactly once whoever notices it first.
+    exits: Mutex<HashMap<String, Run>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        Self {
+            sender,
+            history: Mutex::new(VecDeque::with_capacity(HISTORY_LEN)),
+            exits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn publish(&self, container_id: &str, action: &str, attributes: HashMap<String, String>) {
+        let event = ContainerEvent {
+            container_id: container_id.to_string(),
+            action: action.to_string(),
+            time: now_timestamp(),
+            attributes,
+        };
+        tracing::debug!(container_id = %container_id, action = %action, "Container event");
+
+        let mut history = self.history.lock().unwrap();
+        if history.len() == HISTORY_LEN {
+            history.pop_front();
+        }
+        history.push_back(event.clone());
+        // Nobody listening is fine.
+        let _ = self.sender.send(event);
+    }
+
+    /// Returns the kept events from `since` on, and a receiver for the ones
+    /// published afterwards. Both are taken under the history lock, so no
+    /// event is missed or seen twice.
+    pub fn subscribe(
+        &self,
+        since: Option<&Timestamp>,
+    ) -> (Vec<ContainerEvent>, broadcast::Receiver<ContainerEvent>) {
+        let history = self.history.lock().unwrap();
+        let past = match since {
+            Some(since) => history
+                .iter()
+                .filter(|event| !is_before(&event.time, since))
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        };
+        (past, self.sender.subscribe())
+    }
+
+    /// Records that the container's `run` exited. Returns whether this is
+    /// the first time it was seen.
+    pub fn record_exit(&self, container_id: &str, run: Run) -> bool {
+        self.exits
+            .lock()
+            .unwrap()
+            .insert(container_id.to_string(), run)
+            != Some(run)
+    }
+
+    pub fn forget(&self, container_id: &str) {
+        self.exits.lock().unwrap().remove(container_id);
+    }
+}
+
+/// Rejects filter keys the event stream does not know.
+pub(crate) fn validate_filters(
+    filters: &HashMap<String, Vec<String>>,
+) -> Result<(), ContainerError> {
+    match filters
+        .keys()
+        .find(|key| !FILTER_KEYS.contains(&key.as_str()))
+    {
+        Some(key) => Err(ContainerError::InvalidArgument(format!(
+            "invalid event filter '{}' (expected one of {})",
+            key,
+            FILTER_KEYS.join(", ")
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Whether `event` matches one of the values of every filter key. A
+/// container matches by name or id prefix.
+pub(crate) fn matches(event: &ContainerEvent, filters: &HashMap<String, Vec<String>>) -> bool {
+    filters.iter().all(|(key, values)| {
+        values.is_empty()
+            || values.iter().any(|value| match key.as_str() {
+                "event" => event.action == *value,
+                "container" => {
+                    event.container_id.starts_with(value.as_str())
+                        || event.attributes.get("name") == Some(value)
+                }
+                "image" => event.attributes.get("image") == Some(value),
+                _ => false,
+            })
+    })
+}
+
+fn is_before(time: &Timestamp, other: &Timestamp) -> bool {
+    (time.seconds, time.nanos) < (other.seconds, other.nanos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attributes(name: &str, image: &str) -> HashMap<String, String> {
+        HashMap::from([
+            ("name".to_string(), name.to_string()),
+            ("image".to_string(), image.to_string()),
+        ])
+    }
+
+    #[test]
+    fn test_subscribe_replays_since() {
+        let bus = EventBus::new();
+        bus.publish("abc", ACTION_CREATE, HashMap::new());
+        let (past, _) = bus.subscribe(None);
+        assert!(past.is_empty());
+
+        let (past, mut rx) = bus.subscribe(Some(&Timestamp::default()));
+        assert_eq!(past.len(), 1);
+        assert_eq!(past[0].action, ACTION_CREATE);
+
+        bus.publish("abc", ACTION_START, HashMap::new());
+        assert_eq!(rx.try_recv().unwrap().action, ACTION_START);
+
+        let future = Timestamp {
+            seconds: now_timestamp().seconds + 60,
+            nanos: 0,
+        };
+        let (past, _) = bus.subscribe(Some(&future));
+        assert!(past.is_empty());
+    }
+
+    #[test]
+    fn test_record_exit_once_per_run() {
+        let bus = EventBus::new();
+        assert!(bus.record_exit("abc", (Some(1), Some(2), 0)));
+        assert!(!bus.record_exit("abc", (Some(1), Some(2), 0)));
+        assert!(bus.record_exit("abc", (Some(1), Some(2), 1)));
+        bus.forget("abc");
+        assert!(bus.record_exit("abc", (Some(1), Some(2), 1)));
+    }
+
+    #[test]
+    fn test_matches() {
+        let event = ContainerEvent {
+            container_id: "abcdef".to_string(),
+            action: ACTION_START.to_string(),
+            time: now_timestamp(),
+            attributes: attributes("web", "nginx:latest"),
+        };
+        let filter = |pairs: &[(&str, &[&str])]| -> HashMap<String, Vec<String>> {
+            pairs
+                .iter()
+                .map(|(key, values)| {
+                    (
+                        key.to_string(),
+                        values.iter().map(|v| v.to_string()).collect(),
+                    )
+                })
+                .collect()
+        };
+
+        assert!(matches(&event, &HashMap::new()));
+        assert!(matches(&event, &filter(&[("event", &["die", "start"])])));
+        assert!(!matches(&event, &filter(&[("event", &["die"])])));
+        assert!(matches(&event, &filter(&[("container", &["abc"])])));
+        assert!(matches(&event, &filter(&[("container", &["web"])])));
+        assert!(!matches(
+            &event,
+            &filter(&[("container", &["web"]), ("image", &["alpine"])])
+        ));
+        assert!(validate_filters(&filter(&[("image", &["nginx"])])).is_ok());
+        assert!(validate_filters(&filter(&[("label", &["a=b"])])).is_err());
+    }
+}
@@ -0,0 +1,179 @@
+//! Filters for listing containers, with Docker's semantics: a container must
+//! match one of the values of every filter key.
+
+use crate::error::ContainerError;
+use ross_shim::{ContainerInfo, ContainerState};
+use std::collections::HashMap;
+
+const FILTER_KEYS: &[&str] = &["id", "name", "label", "status", "ancestor"];
+
+/// Statuses a `status` filter accepts. Ross has no restarting, removing or
+/// dead containers, so those match nothing.
+const STATUSES: &[&str] = &[
+    "created",
+    "running",
+    "paused",
+    "exited",
+    "restarting",
+    "removing",
+    "dead",
+];
+
+/// Rejects filter keys and statuses that container listing does not know.
+pub(crate) fn validate_filters(
+    filters: &HashMap<String, Vec<String>>,
+) -> Result<(), ContainerError> {
+    for (key, values) in filters {
+        if !FILTER_KEYS.contains(&key.as_str()) {
+            return Err(ContainerError::InvalidArgument(format!(
+                "invalid filter '{}' (expected one of {})",
+                key,
+                FILTER_KEYS.join(", ")
+            )));
+        }
+        if key == "status"
+            && let Some(status) = values.iter().find(|v| !STATUSES.contains(&v.as_str()))
+        {
+            return Err(ContainerError::InvalidArgument(format!(
+                "invalid filter 'status={}' (expected one of {})",
+                status,
+                STATUSES.join(", ")
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Whether `info` matches one of the values of every filter key.
+pub(crate) fn matches(info: &ContainerInfo, filters: &HashMap<String, Vec<String>>) -> bool {
+    filters.iter().all(|(key, values)| {
+        values.is_empty()
+            || values.iter().any(|value| match key.as_str() {
+                "id" => info.id.starts_with(value.as_str()),
+                "name" => info
+                    .name
+                    .as_deref()
+                    .is_some_and(|name| name.contains(value.as_str())),
+                "label" => match value.split_once('=') {
+                    Some((key, value)) => info.labels.get(key).is_some_and(|v| v == value),
+                    None => info.labels.contains_key(value),
+                },
+                "status" => status(&info.state) == value,
+                "ancestor" => is_ancestor(value, info),
+                _ => false,
+            })
+    })
+}
+
+/// The container's state as the `status` filter names it.
+pub(crate) fn status(state: &ContainerState) -> &'static str {
+    match state {
+        ContainerState::Created => "created",
+        ContainerState::Running => "running",
+        ContainerState::Paused => "paused",
+        ContainerState::Stopped => "exited",
+    }
+}
+
+/// Whether `image` names the image the container was created from: its
+/// reference, with the tag defaulting to `latest`, or its id or an id prefix.
+fn is_ancestor(image: &str, info: &ContainerInfo) -> bool {
+    if image == info.image || format!("{}:latest", image) == info.image {
+        return true;
+    }
+    let id = image.trim_start_matches("sha256:");
+    !id.is_empty() && info.image_id.trim_start_matches("sha256:").starts_with(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info() -> ContainerInfo {
+        ContainerInfo {
+            id: "abcdef".to_string(),
+            name: Some("web-1".to_string()),
+            image: "nginx:latest".to_string(),
+            state: ContainerState::Stopped,
+            pid: None,
+            exit_code: Some(0),
+            created_at: 0,
+            started_at: None,
+            finished_at: None,
+            created_nanos: 0,
+            started_nanos: 0,
+            finished_nanos: 0,
+            bundle_path: String::new(),
+            rootfs_path: String::new(),
+            restart_policy: ross_shim::RestartPolicy::No,
+            restart_count: 0,
+            manually_stopped: false,
+            healthcheck: None,
+            image_id: "sha256:0123456789".to_string(),
+            command: vec![],
+            network_mode: String::new(),
+            oom_killed: false,
+            auto_remove: false,
+            resources: Default::default(),
+            labels: HashMap::from([
+                ("tier".to_string(), "frontend".to_string()),
+                ("team".to_string(), "web".to_string()),
+            ]),
+        }
+    }
+
+    fn filter(pairs: &[(&str, &[&str])]) -> HashMap<String, Vec<String>> {
+        pairs
+            .iter()
+            .map(|(key, values)| {
+                (
+                    key.to_string(),
+                    values.iter().map(|v| v.to_string()).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_matches() {
+        let info = info();
+        assert!(matches(&info, &HashMap::new()));
+        assert!(matches(&info, &filter(&[("label", &["tier"])])));
+        assert!(matches(&info, &filter(&[("label", &["tier=frontend"])])));
+        assert!(!matches(&info, &filter(&[("label", &["tier=backend"])])));
+        assert!(matches(
+            &info,
+            &filter(&[("label", &["tier=backend", "team=web"])])
+        ));
+        assert!(!matches(
+            &info,
+            &filter(&[("label", &["tier"]), ("status", &["running"])])
+        ));
+        assert!(matches(&info, &filter(&[("status", &["exited"])])));
+        assert!(matches(&info, &filter(&[("name", &["web"])])));
+        assert!(!matches(&info, &filter(&[("name", &["db"])])));
+        assert!(matches(&info, &filter(&[("id", &["abc"])])));
+    }
+
+    #[test]
+    fn test_ancestor() {
+        let info = info();
+        for image in ["nginx", "nginx:latest", "sha256:0123", "0123"] {
+            assert!(
+                matches(&info, &filter(&[("ancestor", &[image])])),
+                "{} should match",
+                image
+            );
+        }
+        for image in ["nginx:1.25", "alpine", "4567"] {
+            assert!(!matches(&info, &filter(&[("ancestor", &[image])])));
+        }
+    }
+
+    #[test]
+    fn test_validate_filters() {
+        assert!(validate_filters(&filter(&[("label", &["a=b"]), ("status", &["exited"])])).is_ok());
+        assert!(validate_filters(&filter(&[("status", &["stopped"])])).is_err());
+        assert!(validate_filters(&filter(&[("volume", &["data"])])).is_err());
+    }
+}
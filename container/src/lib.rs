@@ -1,7 +1,16 @@
+mod attach;
 mod error;
+mod events;
+mod filters;
+mod logs;
 mod service;
+mod stats;
+mod top;
 mod types;
+mod volumes;
 
 pub use error::ContainerError;
+pub use ross_shim::{Runtime, ShimError};
+pub use ross_snapshotter::{Change, ChangeKind};
 pub use service::ContainerService;
 pub use types::*;
@@ -0,0 +1,460 @@
+use crate::error::ContainerError;
+use crate::types::LogEntry;
+use prost_types::Timestamp;
+use ross_shim::LogRecord;
+use std::io::SeekFrom;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tokio::fs;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt};
+
+/// Parses the `tail` option. `None` means the whole log.
+pub(crate) fn parse_tail(tail: &str) -> Result<Option<usize>, ContainerError> {
+    let tail = tail.trim();
+    if tail.is_empty() || tail.eq_ignore_ascii_case("all") {
+        return Ok(None);
+    }
+
+    tail.parse::<usize>()
+        .map(Some)
+        .map_err(|_| ContainerError::InvalidArgument(format!("invalid tail value: {}", tail)))
+}
+
+/// Returns true if `ts` falls inside the optional `[since, until]` window.
+pub(crate) fn in_range(
+    ts: &Timestamp,
+    since: Option<&Timestamp>,
+    until: Option<&Timestamp>,
+) -> bool {
+    let key = (ts.seconds, ts.nanos);
+    if let Some(since) = since
+        && key < (since.seconds, since.nanos)
+    {
+        return false;
+    }
+    if let Some(until) = until
+        && key > (until.seconds, until.nanos)
+    {
+        return false;
+    }
+    true
+}
+
+/// Interleaves the entries of several streams by the time they were written,
+/// then keeps the last `tail` of them. Entries written at the same time keep
+/// the order of their stream and of the streams given.
+pub(crate) fn merge_tail(streams: Vec<Vec<LogEntry>>, tail: Option<usize>) -> Vec<LogEntry> {
+    let mut entries: Vec<LogEntry> = streams.into_iter().flatten().collect();
+    entries.sort_by_key(|entry| (entry.timestamp.seconds, entry.timestamp.nanos));
+    let skip = tail.map_or(0, |tail| entries.len().saturating_sub(tail));
+    entries.split_off(skip)
+}
+
+/// Size of the pieces a log is read backwards in when looking for its tail.
+const TAIL_CHUNK_SIZE: usize = 64 * 1024;
+
+/// How every line of a log written as [`LogRecord`]s starts.
+const RECORD_START: &[u8] = b"{\"log\":";
+
+/// Incremental reader over one of the log files the shim writes into the bundle.
+///
+/// Each line of the file is a [`LogRecord`] carrying the time the container
+/// wrote it. Lines of logs from before that hold the raw output and are
+/// stamped with the time they are read at instead.
+///
+/// The file stays open between reads so that, when it is rotated (renamed or
+/// removed and recreated), what was written to the old file is still read
+/// before moving on to the new one.
+pub(crate) struct LogReader {
+    stream: &'static str,
+    path: PathBuf,
+    file: Option<fs::File>,
+    offset: u64,
+    partial: Vec<u8>,
+}
+
+impl LogReader {
+    pub fn new(stream: &'static str, path: PathBuf) -> Self {
+        Self {
+            stream,
+            path,
+            file: None,
+            offset: 0,
+            partial: Vec::new(),
+        }
+    }
+
+    /// Reads the current contents of the log, keeping only the last `tail` lines.
+    ///
+    /// With a `tail`, only the end of the file is read: it is scanned backwards
+    /// for the start of the first line wanted.
+    ///
+    /// Raw lines are stamped with the file's modification time (the time of
+    /// the last write).
+    pub async fn read_existing(
+        &mut self,
+        tail: Option<usize>,
+    ) -> Result<Vec<LogEntry>, ContainerError> {
+        let Some(mut file) = open(&self.path).await? else {
+            return Ok(vec![]);
+        };
+
+        let metadata = file.metadata().await?;
+        let len = metadata.len();
+        let written_at = metadata.modified().unwrap_or_else(|_| SystemTime::now());
+
+        let start = match tail {
+            Some(lines) => tail_offset(&mut file, len, lines).await?,
+            None => 0,
+        };
+        file.seek(SeekFrom::Start(start)).await?;
+        let mut data = Vec::with_capacity((len - start) as usize);
+        (&mut file).take(len - start).read_to_end(&mut data).await?;
+
+        self.file = Some(file);
+        self.offset = start + data.len() as u64;
+        self.partial.clear();
+
+        let mut lines = split_lines(&data, &mut self.partial);
+        // A record still being written is completed by the next read; a raw
+        // line without a newline is all there is of it so far.
+        if !self.partial.is_empty() && !self.partial.starts_with(RECORD_START) {
+            lines.push(String::from_utf8_lossy(&self.partial).into_owned());
+            self.partial.clear();
+        }
+
+        Ok(lines
+            .into_iter()
+            .map(|line| self.entry(line, written_at))
+            .collect())
+    }
+
+    /// Reads complete lines appended since the previous read. Raw lines are
+    /// stamped with the time they were observed.
+    pub async fn read_appended(&mut self) -> Result<Vec<LogEntry>, ContainerError> {
+        let now = SystemTime::now();
+        let mut entries = Vec::new();
+
+        if self.file.is_some() {
+            self.read_open_file(now, &mut entries).await?;
+            if !self.rotated().await? {
+                return Ok(entries);
+            }
+            // Everything written to the old file has been read: a line it
+            // left unterminated ends there.
+            entries.extend(self.flush());
+        }
+
+        // The log did not exist yet or was replaced: follow the new file from
+        // its start.
+        self.file = open(&self.path).await?;
+        self.offset = 0;
+        if self.file.is_some() {
+            self.read_open_file(now, &mut entries).await?;
+        }
+        Ok(entries)
+    }
+
+    /// Returns any trailing data that was not terminated by a newline.
+    pub fn flush(&mut self) -> Option<LogEntry> {
+        if self.partial.is_empty() {
+            return None;
+        }
+        let line = String::from_utf8_lossy(&self.partial).into_owned();
+        self.partial.clear();
+        Some(self.entry(line, SystemTime::now()))
+    }
+
+    /// Reads the complete lines past `offset` in the open file into `entries`.
+    async fn read_open_file(
+        &mut self,
+        now: SystemTime,
+        entries: &mut Vec<LogEntry>,
+    ) -> Result<(), ContainerError> {
+        let Some(file) = self.file.as_mut() else {
+            return Ok(());
+        };
+
+        let len = file.metadata().await?.len();
+        if len < self.offset {
+            // The file was truncated, e.g. because the container was restarted.
+            self.offset = 0;
+            self.partial.clear();
+        }
+        if len == self.offset {
+            return Ok(());
+        }
+
+        file.seek(SeekFrom::Start(self.offset)).await?;
+        let mut buf = Vec::with_capacity((len - self.offset) as usize);
+        file.take(len - self.offset).read_to_end(&mut buf).await?;
+        self.offset += buf.len() as u64;
+
+        for line in split_lines(&buf, &mut self.partial) {
+            entries.push(self.entry(line, now));
+        }
+        Ok(())
+    }
+
+    /// Whether the log's path no longer names the open file. A path that is
+    /// gone, removed and not recreated yet, keeps the open file in use.
+    async fn rotated(&self) -> Result<bool, ContainerError> {
+        let Some(file) = &self.file else {
+            return Ok(false);
+        };
+        let current = match fs::metadata(&self.path).await {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+        let open = file.metadata().await?;
+        Ok((current.dev(), current.ino()) != (open.dev(), open.ino()))
+    }
+
+    /// The entry of a line of the log, stamped with `written_at` unless it
+    /// is a record carrying its own time.
+    fn entry(&self, line: String, written_at: SystemTime) -> LogEntry {
+        let (message, written_at) = match LogRecord::parse(&line) {
+            Some(record) => (record.log, SystemTime::from(record.time)),
+            None => (line, written_at),
+        };
+        LogEntry {
+            timestamp: Timestamp::from(written_at),
+            stream: self.stream.to_string(),
+            message,
+        }
+    }
+}
+
+/// Opens the log at `path`, which the shim may not have created yet.
+async fn open(path: &Path) -> Result<Option<fs::File>, ContainerError> {
+    match fs::File::open(path).await {
+        Ok(file) => Ok(Some(file)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Offset at which the last `lines` lines of a log of `len` bytes start.
+///
+/// The log is read backwards from its end in chunks, counting newlines, so
+/// only as much of it as the tail spans is read however large it is. A final
+/// unterminated line counts as a line.
+async fn tail_offset<R>(reader: &mut R, len: u64, lines: usize) -> Result<u64, ContainerError>
+where
+    R: AsyncRead + AsyncSeek + Unpin,
+{
+    if lines == 0 {
+        return Ok(len);
+    }
+
+    let mut buf = vec![0; TAIL_CHUNK_SIZE];
+    let mut found = 0;
+    let mut end = len;
+    while end > 0 {
+        let start = end.saturating_sub(TAIL_CHUNK_SIZE as u64);
+        let chunk = &mut buf[..(end - start) as usize];
+        reader.seek(SeekFrom::Start(start)).await?;
+        reader.read_exact(chunk).await?;
+
+        for (i, byte) in chunk.iter().enumerate().rev() {
+            let pos = start + i as u64;
+            // The newline ending the log terminates its last line; every
+            // other one ends the line before a line of the tail.
+            if *byte != b'\n' || pos + 1 == len {
+                continue;
+            }
+            found += 1;
+            if found == lines {
+                return Ok(pos + 1);
+            }
+        }
+        end = start;
+    }
+    Ok(0)
+}
+
+/// Appends `data` to `partial` and drains every complete (newline-terminated) line.
+fn split_lines(data: &[u8], partial: &mut Vec<u8>) -> Vec<String> {
+    partial.extend_from_slice(data);
+
+    let mut lines = Vec::new();
+    while let Some(pos) = partial.iter().position(|b| *b == b'\n') {
+        let line: Vec<u8> = partial.drain(..=pos).collect();
+        lines.push(String::from_utf8_lossy(&line).into_owned());
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tail() {
+        assert_eq!(parse_tail("all").unwrap(), None);
+        assert_eq!(parse_tail("").unwrap(), None);
+        assert_eq!(parse_tail("10").unwrap(), Some(10));
+        assert!(parse_tail("-1").is_err());
+        assert!(parse_tail("abc").is_err());
+    }
+
+    #[test]
+    fn test_split_lines_keeps_partial() {
+        let mut partial = Vec::new();
+        let lines = split_lines(b"one\ntwo\nthr", &mut partial);
+        assert_eq!(lines, vec!["one\n", "two\n"]);
+        assert_eq!(partial, b"thr");
+
+        let lines = split_lines(b"ee\n", &mut partial);
+        assert_eq!(lines, vec!["three\n"]);
+        assert!(partial.is_empty());
+    }
+
+    #[test]
+    fn test_in_range() {
+        let ts = Timestamp {
+            seconds: 100,
+            nanos: 0,
+        };
+        let before = Timestamp {
+            seconds: 50,
+            nanos: 0,
+        };
+        let after = Timestamp {
+            seconds: 150,
+            nanos: 0,
+        };
+
+        assert!(in_range(&ts, None, None));
+        assert!(in_range(&ts, Some(&before), Some(&after)));
+        assert!(!in_range(&ts, Some(&after), None));
+        assert!(!in_range(&ts, None, Some(&before)));
+    }
+
+    #[test]
+    fn test_in_range_bounds_are_inclusive() {
+        let at = |seconds, nanos| Timestamp { seconds, nanos };
+        let since = at(100, 500);
+        let until = at(200, 0);
+
+        assert!(in_range(&at(100, 500), Some(&since), Some(&until)));
+        assert!(in_range(&at(200, 0), Some(&since), Some(&until)));
+        assert!(!in_range(&at(100, 499), Some(&since), Some(&until)));
+        assert!(!in_range(&at(200, 1), Some(&since), Some(&until)));
+        // A window of a single instant keeps only entries stamped with it.
+        assert!(in_range(&at(150, 0), Some(&at(150, 0)), Some(&at(150, 0))));
+        assert!(!in_range(&at(150, 1), Some(&at(150, 0)), Some(&at(150, 0))));
+    }
+
+    #[test]
+    fn test_merge_tail() {
+        let entry = |stream: &str, seconds, message: &str| LogEntry {
+            timestamp: Timestamp { seconds, nanos: 0 },
+            stream: stream.to_string(),
+            message: message.to_string(),
+        };
+        let stdout = vec![entry("stdout", 1, "a"), entry("stdout", 4, "d")];
+        let stderr = vec![
+            entry("stderr", 2, "b"),
+            entry("stderr", 3, "c"),
+            entry("stderr", 4, "e"),
+        ];
+
+        let merged = merge_tail(vec![stdout.clone(), stderr.clone()], None);
+        let messages: Vec<_> = merged.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, vec!["a", "b", "c", "d", "e"]);
+
+        // The tail is the last lines of both streams, not of each.
+        let merged = merge_tail(vec![stdout, stderr], Some(3));
+        let messages: Vec<_> = merged.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, vec!["c", "d", "e"]);
+    }
+
+    #[tokio::test]
+    async fn test_read_existing_tail() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stdout.log");
+        std::fs::write(&path, "a\nb\nc\nd").unwrap();
+
+        let mut reader = LogReader::new("stdout", path.clone());
+        let entries = reader.read_existing(Some(2)).await.unwrap();
+        let messages: Vec<_> = entries.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, vec!["c\n", "d"]);
+
+        std::fs::write(&path, "a\nb\nc\nde\nf\n").unwrap();
+        let entries = reader.read_appended().await.unwrap();
+        let messages: Vec<_> = entries.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, vec!["e\n", "f\n"]);
+    }
+
+    #[tokio::test]
+    async fn test_read_existing_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stdout.log");
+        let first = r#"{"log":"a\n","stream":"stdout","time":"1970-01-01T00:01:40.5Z"}"#;
+        let second = r#"{"log":"b\n","stream":"stdout","time":"1970-01-01T00:03:20Z"}"#;
+        let log = format!("{}\n{}", first, &second[..10]);
+        std::fs::write(&path, &log).unwrap();
+
+        let mut reader = LogReader::new("stdout", path.clone());
+        let entries = reader.read_existing(None).await.unwrap();
+        let lines: Vec<_> = entries
+            .iter()
+            .map(|e| (e.message.as_str(), e.timestamp.seconds, e.timestamp.nanos))
+            .collect();
+        // The record being written is not shown half done.
+        assert_eq!(lines, vec![("a\n", 100, 500_000_000)]);
+
+        std::fs::write(&path, format!("{}{}\n", log, &second[10..])).unwrap();
+        let entries = reader.read_appended().await.unwrap();
+        let lines: Vec<_> = entries
+            .iter()
+            .map(|e| (e.message.as_str(), e.timestamp.seconds))
+            .collect();
+        assert_eq!(lines, vec![("b\n", 200)]);
+    }
+
+    #[tokio::test]
+    async fn test_tail_offset() {
+        let log: String = (0..20_000).map(|i| format!("line {}\n", i)).collect();
+        let len = log.len() as u64;
+        let mut reader = std::io::Cursor::new(log.as_bytes());
+
+        let start = tail_offset(&mut reader, len, 3).await.unwrap();
+        assert_eq!(
+            &log[start as usize..],
+            "line 19997\nline 19998\nline 19999\n"
+        );
+        assert_eq!(tail_offset(&mut reader, len, 0).await.unwrap(), len);
+        assert_eq!(tail_offset(&mut reader, len, 20_000).await.unwrap(), 0);
+        assert_eq!(tail_offset(&mut reader, len, 50_000).await.unwrap(), 0);
+
+        let mut reader = std::io::Cursor::new(&b"a\nb\nc"[..]);
+        assert_eq!(tail_offset(&mut reader, 5, 2).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_read_appended_follows_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stdout.log");
+        std::fs::write(&path, "a\n").unwrap();
+
+        let mut reader = LogReader::new("stdout", path.clone());
+        reader.read_existing(None).await.unwrap();
+
+        std::fs::write(dir.path().join("new.log"), "c\n").unwrap();
+        let mut old = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        std::io::Write::write_all(&mut old, b"b").unwrap();
+        std::fs::rename(&path, dir.path().join("stdout.log.1")).unwrap();
+        std::fs::rename(dir.path().join("new.log"), &path).unwrap();
+
+        let entries = reader.read_appended().await.unwrap();
+        let messages: Vec<_> = entries.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, vec!["b", "c\n"]);
+    }
+}
@@ -1,60 +1,236 @@
+use crate::attach::{self, DetachMatcher};
 use crate::error::ContainerError;
+use crate::events::{self, EventBus};
+use crate::filters;
+use crate::logs::{self, LogReader};
+use crate::stats;
+use crate::top;
 use crate::types::*;
+use crate::volumes::VolumeStore;
 use async_stream::stream;
-#[cfg(not(target_os = "macos"))]
-use ross_shim::RuncShim;
-use ross_shim::{CreateContainerOpts, KrunShim, Shim};
-use ross_snapshotter::OverlaySnapshotter;
+use ross_image::{ImageError, ImageService, PullErrorKind};
+use ross_remote::{ImageReference, ManifestList, ManifestV2, Platform, is_index_media_type};
+use ross_shim::{CreateContainerOpts, Runtime, Shim};
+use ross_snapshotter::{Change, OverlaySnapshotter, SnapshotterError, chain_ids};
 use ross_store::FileSystemStore;
-use std::collections::HashMap;
-use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
 use tokio_stream::Stream;
+use tokio_util::sync::CancellationToken;
 
 type BoxStream<T> = Pin<Box<dyn Stream<Item = T> + Send>>;
 
+const LOG_POLL_INTERVAL: Duration = Duration::from_millis(250);
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+/// How often containers are checked for exits to report as `die` events.
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Delay before the first policy restart, doubled on each consecutive one.
+const RESTART_BACKOFF_MIN: Duration = Duration::from_millis(100);
+const RESTART_BACKOFF_MAX: Duration = Duration::from_secs(60);
+/// A container that ran at least this long resets the restart backoff.
+const RESTART_BACKOFF_RESET_SECS: i64 = 10;
+
+const HEALTH_STARTING: &str = "starting";
+const HEALTH_HEALTHY: &str = "healthy";
+const HEALTH_UNHEALTHY: &str = "unhealthy";
+const HEALTH_DEFAULT_INTERVAL: Duration = Duration::from_secs(30);
+const HEALTH_DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+const HEALTH_DEFAULT_RETRIES: u32 = 3;
+/// Number of probe results kept for inspect.
+const HEALTH_LOG_LEN: usize = 5;
+/// Probe output beyond this many bytes is dropped.
+const HEALTH_OUTPUT_MAX: usize = 4096;
+
+/// Snapshot label naming the container that owns a container snapshot.
+const CONTAINER_ID_LABEL: &str = "container.id";
+
 struct ImageConfigInfo {
+    /// Digest of the config blob, which identifies the image.
+    id: String,
+    /// Chain ID of the top layer, keying its snapshot.
     top_layer: Option<String>,
     entrypoint: Vec<String>,
     cmd: Vec<String>,
     env: Vec<String>,
     working_dir: String,
     user: String,
+    healthcheck: Option<HealthConfig>,
+    labels: HashMap<String, String>,
+}
+
+/// Bookkeeping for containers created with auto-remove.
+#[derive(Default)]
+struct AutoRemoval {
+    /// Containers with an auto-remove watcher task running.
+    watched: HashSet<String>,
+    /// Containers being restarted, which must survive the stop.
+    restarting: HashSet<String>,
+}
+
+struct ExecInstance {
+    container_id: String,
+    config: ExecConfig,
+    running: bool,
+    exit_code: Option<i32>,
 }
 
 pub struct ContainerService {
     shim: Arc<dyn Shim + Send + Sync>,
+    execs: Arc<RwLock<HashMap<String, ExecInstance>>>,
+    /// Containers with a restart supervisor task running.
+    supervised: Arc<std::sync::Mutex<HashSet<String>>>,
+    /// Health of containers with a healthcheck monitor task running.
+    health: Arc<std::sync::Mutex<HashMap<String, Health>>>,
+    auto_removal: Arc<std::sync::Mutex<AutoRemoval>>,
     snapshotter: Arc<OverlaySnapshotter>,
+    volumes: Arc<VolumeStore>,
     #[allow(dead_code)]
     store: Arc<FileSystemStore>,
+    /// Pulls the images of containers created with a pull policy.
+    images: Arc<ImageService>,
+    events: Arc<EventBus>,
+    /// Cancelled as the daemon shuts down: the containers it stops are left
+    /// for their restart policies to start on the next daemon start.
+    shutdown: CancellationToken,
 }
 
 impl ContainerService {
     pub async fn new(
         data_dir: &Path,
+        runtime: Runtime,
+        mtu: Option<u16>,
         snapshotter: Arc<OverlaySnapshotter>,
         store: Arc<FileSystemStore>,
+        images: Arc<ImageService>,
     ) -> Result<Self, ContainerError> {
-        // Try KrunShim first (for macOS), fall back to RuncShim
-        let shim: Arc<dyn Shim + Send + Sync> = {
-            #[cfg(target_os = "macos")]
-            {
-                tracing::info!("Using KrunShim for container runtime");
-                Arc::new(KrunShim::new(&data_dir.join("shim")).await?)
-            }
-            #[cfg(not(target_os = "macos"))]
-            {
-                tracing::info!("Using RuncShim for container runtime");
-                Arc::new(RuncShim::new(&data_dir.join("shim")).await?)
-            }
-        };
+        tracing::info!("Using the {} container runtime", runtime);
+        let shim = runtime.create_shim(&data_dir.join("shim"), mtu).await?;
 
-        Ok(Self {
+        let service = Self {
             shim,
+            execs: Arc::new(RwLock::new(HashMap::new())),
+            supervised: Arc::new(std::sync::Mutex::new(HashSet::new())),
+            health: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            auto_removal: Arc::new(std::sync::Mutex::new(AutoRemoval::default())),
             snapshotter,
+            volumes: Arc::new(VolumeStore::new(&data_dir.join("volumes"))?),
             store,
-        })
+            images,
+            events: Arc::new(EventBus::new()),
+            shutdown: CancellationToken::new(),
+        };
+
+        // Exits from before this daemon started are not news.
+        report_exits(service.shim.as_ref(), &service.events, false).await?;
+        spawn_exit_monitor(service.shim.clone(), service.events.clone());
+        service.resume_restart_policies().await?;
+
+        Ok(service)
+    }
+
+    /// Re-attach restart supervisors, health monitors and auto-remove
+    /// watchers after a daemon restart, start the stopped containers whose
+    /// policy asks for it and remove the auto-remove ones that exited.
+    async fn resume_restart_policies(&self) -> Result<(), ContainerError> {
+        for info in self.shim.list().await? {
+            if info.healthcheck.is_some() {
+                self.monitor_health(&info.id);
+            }
+            match info.state {
+                ross_shim::ContainerState::Running | ross_shim::ContainerState::Paused
+                    if info.restart_policy != ross_shim::RestartPolicy::No =>
+                {
+                    self.supervise(&info.id);
+                }
+                ross_shim::ContainerState::Stopped
+                    if info
+                        .restart_policy
+                        .restart_on_daemon_start(info.manually_stopped) =>
+                {
+                    tracing::info!(container_id = %info.id, "Starting container per restart policy");
+                    match self.shim.start(&info.id).await {
+                        Ok(()) => {
+                            self.publish(&info.id, events::ACTION_START, HashMap::new())
+                                .await;
+                            self.supervise(&info.id);
+                        }
+                        Err(e) => {
+                            tracing::warn!(container_id = %info.id, "Failed to start container: {}", e)
+                        }
+                    }
+                }
+                ross_shim::ContainerState::Running | ross_shim::ContainerState::Paused
+                    if info.auto_remove =>
+                {
+                    self.auto_remove(&info.id);
+                }
+                ross_shim::ContainerState::Stopped if info.auto_remove => {
+                    remove_if_auto(
+                        self.shim.as_ref(),
+                        &self.snapshotter,
+                        &self.volumes,
+                        &self.events,
+                        &info.id,
+                    )
+                    .await;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Watch a running container and start it again whenever it exits in a
+    /// way its restart policy covers.
+    fn supervise(&self, container_id: &str) {
+        spawn_supervisor(
+            self.shim.clone(),
+            self.supervised.clone(),
+            self.events.clone(),
+            self.shutdown.clone(),
+            container_id.to_string(),
+        );
+    }
+
+    /// Publish a lifecycle event for the container, tagged with its name and
+    /// image.
+    async fn publish(&self, container_id: &str, action: &str, attributes: HashMap<String, String>) {
+        publish_event(
+            self.shim.as_ref(),
+            &self.events,
+            container_id,
+            action,
+            attributes,
+        )
+        .await;
+    }
+
+    /// Remove the container once it exits, if it was created with
+    /// auto-remove.
+    fn auto_remove(&self, container_id: &str) {
+        spawn_auto_remover(
+            self.shim.clone(),
+            self.snapshotter.clone(),
+            self.volumes.clone(),
+            self.events.clone(),
+            self.auto_removal.clone(),
+            container_id.to_string(),
+        );
+    }
+
+    /// Run the container's healthcheck, if it has one, for as long as the
+    /// container exists.
+    fn monitor_health(&self, container_id: &str) {
+        spawn_health_monitor(
+            self.shim.clone(),
+            self.health.clone(),
+            container_id.to_string(),
+        );
     }
 
     pub async fn create(
@@ -66,19 +242,42 @@ impl ContainerService {
         let image_ref = &params.config.image;
         tracing::info!("Looking up image: {}", image_ref);
 
-        // Get image config (includes top layer and default entrypoint/cmd)
-        let image_config = self.get_image_config(image_ref).await?;
+        // Get image config (includes top layer and default entrypoint/cmd),
+        // pulling the image first as the pull policy says.
+        let image_config = match pull_policy(&params.pull)? {
+            PullPolicy::Always => {
+                self.pull_image(image_ref).await?;
+                self.get_image_config(image_ref).await?
+            }
+            PullPolicy::Missing => match self.get_image_config(image_ref).await {
+                Err(ContainerError::ImageNotFound(e)) => {
+                    tracing::info!(
+                        "Image {} is not in the store ({}), pulling it",
+                        image_ref,
+                        e
+                    );
+                    self.pull_image(image_ref).await?;
+                    self.get_image_config(image_ref).await?
+                }
+                result => result?,
+            },
+            PullPolicy::Never => self.get_image_config(image_ref).await?,
+        };
 
-        let top_layer_digest = image_config
+        let top_layer = image_config
             .top_layer
             .ok_or_else(|| ContainerError::ImageNotFound("Image has no layers".to_string()))?;
-        tracing::info!("Found top layer: {}", top_layer_digest);
+
+        // Image prune waits until the container's snapshot is prepared on
+        // the layers, then sees them in use.
+        let content = self.images.hold_content().await;
+        tracing::info!("Found top layer: {}", top_layer);
 
         // Verify the layer snapshot exists
-        if self.snapshotter.stat(&top_layer_digest).await.is_err() {
+        if self.snapshotter.stat(&top_layer).await.is_err() {
             return Err(ContainerError::ImageNotFound(format!(
                 "Layer snapshot not found: {}. Did you pull the image first?",
-                top_layer_digest
+                top_layer
             )));
         }
 
@@ -91,13 +290,14 @@ impl ContainerService {
         tracing::info!(
             "Creating container snapshot {} from layer {}",
             snapshot_key,
-            top_layer_digest
+            top_layer
         );
 
         let mounts = self
             .snapshotter
-            .prepare(&snapshot_key, Some(&top_layer_digest), labels)
+            .prepare(&snapshot_key, Some(&top_layer), labels)
             .await?;
+        drop(content);
 
         // Convert snapshotter mounts to shim mounts
         let shim_mounts: Vec<ross_shim::SnapshotMount> = mounts
@@ -112,17 +312,12 @@ impl ContainerService {
         tracing::info!("Prepared {} mount(s) for container", shim_mounts.len());
 
         // Merge user config with image config (user config takes precedence)
-        let entrypoint = if params.config.entrypoint.is_empty() {
-            image_config.entrypoint
-        } else {
-            params.config.entrypoint.clone()
-        };
-
-        let cmd = if params.config.cmd.is_empty() {
-            image_config.cmd
-        } else {
-            params.config.cmd.clone()
-        };
+        let (entrypoint, cmd) = merge_command(
+            params.config.entrypoint.clone(),
+            params.config.cmd.clone(),
+            image_config.entrypoint,
+            image_config.cmd,
+        );
 
         let env = if params.config.env.is_empty() {
             image_config.env
@@ -143,6 +338,14 @@ impl ContainerService {
             Some(params.config.working_dir.clone())
         };
 
+        let healthcheck = match &params.config.healthcheck {
+            Some(config) if !config.test.is_empty() => health_check(config)?,
+            _ => match &image_config.healthcheck {
+                Some(config) => health_check(config)?,
+                None => None,
+            },
+        };
+
         let user = if params.config.user.is_empty() {
             if image_config.user.is_empty() {
                 None
@@ -153,6 +356,10 @@ impl ContainerService {
             Some(params.config.user.clone())
         };
 
+        // Image labels, overridden by the user's.
+        let mut labels = image_config.labels;
+        labels.extend(params.config.labels.clone());
+
         tracing::info!("Container entrypoint: {:?}, cmd: {:?}", entrypoint, cmd);
 
         let shim_config = ross_shim::ContainerConfig {
@@ -167,13 +374,38 @@ impl ContainerService {
             cmd,
             entrypoint,
             working_dir,
-            labels: params.config.labels.clone(),
+            labels,
             tty: params.config.tty,
             open_stdin: params.config.open_stdin,
+            stdin_once: params.config.stdin_once,
+            stop_signal: non_empty(&params.config.stop_signal).map(|s| parse_signal(&s)),
+            healthcheck,
+            image_id: image_config.id,
         };
 
+        let (cpu_quota, cpu_period) = cpu_limits(&params.host_config)?;
+        let restart_policy = restart_policy(&params.host_config.restart_policy)?;
+        if params.host_config.auto_remove && restart_policy != ross_shim::RestartPolicy::No {
+            return Err(ContainerError::InvalidArgument(
+                "auto-remove conflicts with a restart policy".to_string(),
+            ));
+        }
+        if let Some(memory) = params.host_config.memory_limit.filter(|m| *m <= 0) {
+            return Err(ContainerError::InvalidArgument(format!(
+                "invalid memory limit: {}",
+                memory
+            )));
+        }
+
+        let port_bindings = params
+            .host_config
+            .port_bindings
+            .iter()
+            .map(port_mapping)
+            .collect::<Result<_, _>>()?;
+        let binds = self.volumes.resolve_binds(&params.host_config.binds)?;
         let shim_host_config = ross_shim::HostConfig {
-            binds: params.host_config.binds.clone(),
+            binds: binds.binds,
             network_mode: if params.host_config.network_mode.is_empty() {
                 None
             } else {
@@ -181,7 +413,19 @@ impl ContainerService {
             },
             privileged: params.host_config.privileged,
             readonly_rootfs: params.host_config.readonly_rootfs,
+            tmpfs: params.host_config.tmpfs.clone(),
             auto_remove: params.host_config.auto_remove,
+            port_bindings,
+            memory_limit: params.host_config.memory_limit,
+            cpu_quota,
+            cpu_period,
+            restart_policy,
+            dns: params.host_config.dns.clone(),
+            dns_search: params.host_config.dns_search.clone(),
+            dns_options: params.host_config.dns_options.clone(),
+            extra_hosts: params.host_config.extra_hosts.clone(),
+            egress_allow: params.host_config.egress_allow.clone(),
+            egress_deny: params.host_config.egress_deny.clone(),
         };
 
         let opts = CreateContainerOpts {
@@ -191,7 +435,37 @@ impl ContainerService {
             mounts: shim_mounts,
         };
 
-        let id = self.shim.create(opts).await?;
+        let id = match self.shim.create(opts).await {
+            Ok(id) => id,
+            Err(e) => {
+                let _ = self.snapshotter.remove(&snapshot_key).await;
+                self.volumes.discard(&binds.anonymous);
+                return Err(match e {
+                    e @ (ross_shim::ShimError::InvalidMount(_)
+                    | ross_shim::ShimError::InvalidNetworkMode(_)
+                    | ross_shim::ShimError::InvalidUser(_)
+                    | ross_shim::ShimError::InvalidDns(_)
+                    | ross_shim::ShimError::InvalidEgress(_)) => {
+                        ContainerError::InvalidArgument(e.to_string())
+                    }
+                    ross_shim::ShimError::ContainerAlreadyExists(name) => {
+                        ContainerError::AlreadyExists(format!("name {} is already in use", name))
+                    }
+                    e => e.into(),
+                });
+            }
+        };
+
+        if let Err(e) = self.volumes.attach(&binds.volumes, &id) {
+            tracing::warn!("Failed to record the volumes of {}: {}", id, e);
+        }
+        let mut labels = HashMap::new();
+        labels.insert(CONTAINER_ID_LABEL.to_string(), id.clone());
+        self.snapshotter
+            .update_labels(&snapshot_key, labels)
+            .await?;
+        self.publish(&id, events::ACTION_CREATE, HashMap::new())
+            .await;
 
         Ok(CreateContainerResult {
             id,
@@ -199,52 +473,110 @@ impl ContainerService {
         })
     }
 
-    async fn get_image_config(&self, image_ref: &str) -> Result<ImageConfigInfo, ContainerError> {
-        let (repository, tag) = parse_image_reference(image_ref);
+    /// Pulls `image_ref` for the daemon's platform, failing with the first
+    /// error the pull reports.
+    async fn pull_image(&self, image_ref: &str) -> Result<(), ContainerError> {
+        use tokio_stream::StreamExt;
 
-        tracing::debug!("Looking up image {}:{}", repository, tag);
+        let mut progress = self
+            .images
+            .pull(image_ref, "", None, None)
+            .map_err(|e| match e {
+                ImageError::InvalidReference(e) => ContainerError::InvalidArgument(e),
+                e => ContainerError::PullFailed(e.to_string()),
+            })?;
+        while let Some(progress) = progress.next().await {
+            if let Some(error) = progress.error {
+                let message = format!("{}: {}", image_ref, error);
+                return Err(match error.kind {
+                    PullErrorKind::NotFound => ContainerError::ImageNotFound(message),
+                    PullErrorKind::Unauthorized => ContainerError::PullDenied(message),
+                    PullErrorKind::Other => ContainerError::PullFailed(message),
+                });
+            }
+        }
+        Ok(())
+    }
 
-        let tags = self.store.list_tags(&repository).await.map_err(|e| {
-            ContainerError::ImageNotFound(format!("Failed to list tags for {}: {}", repository, e))
+    /// Picks the manifest for the daemon's platform out of a stored image index.
+    async fn resolve_platform_manifest(
+        &self,
+        index_digest: &ross_store::Digest,
+    ) -> Result<ross_store::Digest, ContainerError> {
+        let index_bytes = self.store.get_index(index_digest).await.map_err(|e| {
+            ContainerError::ImageNotFound(format!("Failed to get image index: {}", e))
         })?;
 
-        let tag_info = tags.iter().find(|t| t.tag == tag).ok_or_else(|| {
-            ContainerError::ImageNotFound(format!(
-                "Tag {} not found for repository {}",
-                tag, repository
-            ))
+        let index: ManifestList = serde_json::from_slice(&index_bytes).map_err(|e| {
+            ContainerError::ImageNotFound(format!("Failed to parse image index: {}", e))
         })?;
 
-        let manifest_digest = tag_info.digest.as_ref().ok_or_else(|| {
-            ContainerError::ImageNotFound(format!("No digest for tag {}:{}", repository, tag))
+        let platform = Platform::host();
+        let descriptor = index.select(&platform).ok_or_else(|| {
+            ContainerError::ImageNotFound(format!("Image has no manifest for {}", platform))
         })?;
 
-        let (manifest_bytes, _media_type) = self
+        Ok(ross_store::Digest {
+            algorithm: "sha256".to_string(),
+            hash: descriptor.digest.trim_start_matches("sha256:").to_string(),
+        })
+    }
+
+    async fn get_image_config(&self, image_ref: &str) -> Result<ImageConfigInfo, ContainerError> {
+        let reference = ImageReference::parse(image_ref)
+            .map_err(|e| ContainerError::InvalidArgument(e.to_string()))?;
+
+        tracing::debug!("Looking up image {}", reference.full_name());
+
+        let manifest_digest = match &reference.digest {
+            // A digest names the content directly, so the tag table is skipped.
+            Some(digest) => {
+                let digest = ross_store::Digest {
+                    algorithm: "sha256".to_string(),
+                    hash: digest.trim_start_matches("sha256:").to_string(),
+                };
+                if self.store.get_index(&digest).await.is_ok() {
+                    self.resolve_platform_manifest(&digest).await?
+                } else {
+                    digest
+                }
+            }
+            None => {
+                let (repository, tag) = (&reference.repository, reference.tag_or_default());
+                let (tag_digest, media_type) =
+                    self.store.resolve_tag(repository, tag).await.map_err(|e| {
+                        ContainerError::ImageNotFound(format!(
+                            "Tag {} not found for repository {}: {}",
+                            tag, repository, e
+                        ))
+                    })?;
+
+                if is_index_media_type(&media_type) {
+                    self.resolve_platform_manifest(&tag_digest).await?
+                } else {
+                    tag_digest
+                }
+            }
+        };
+
+        let (manifest_bytes, media_type) = self
             .store
-            .get_manifest(manifest_digest)
+            .get_manifest(&manifest_digest)
             .await
             .map_err(|e| ContainerError::ImageNotFound(format!("Failed to get manifest: {}", e)))?;
+        // The digest comes from the reference or the tag, not from the stored
+        // file, so a manifest changed on disk does not go unnoticed.
+        manifest_digest.verify(&manifest_bytes).map_err(|e| {
+            ContainerError::ImageCorrupted(format!(
+                "manifest {}:{}: {}",
+                manifest_digest.algorithm, manifest_digest.hash, e
+            ))
+        })?;
 
-        #[derive(serde::Deserialize)]
-        struct Manifest {
-            config: ConfigDescriptor,
-            layers: Vec<LayerDescriptor>,
-        }
-        #[derive(serde::Deserialize)]
-        struct ConfigDescriptor {
-            digest: String,
-        }
-        #[derive(serde::Deserialize)]
-        struct LayerDescriptor {
-            digest: String,
-        }
-
-        let manifest: Manifest = serde_json::from_slice(&manifest_bytes).map_err(|e| {
+        let manifest = ManifestV2::parse(&manifest_bytes, &media_type).map_err(|e| {
             ContainerError::ImageNotFound(format!("Failed to parse manifest: {}", e))
         })?;
 
-        let top_layer = manifest.layers.last().map(|l| l.digest.clone());
-
         // Get the image config blob
         let config_digest = ross_store::Digest {
             algorithm: "sha256".to_string(),
@@ -262,10 +594,27 @@ impl ContainerService {
             .map_err(|e| {
                 ContainerError::ImageNotFound(format!("Failed to get image config: {}", e))
             })?;
+        if manifest.config.size != config_bytes.len() as i64 {
+            return Err(ContainerError::ImageCorrupted(format!(
+                "config {}: size {} does not match the manifest's {}",
+                manifest.config.digest,
+                config_bytes.len(),
+                manifest.config.size
+            )));
+        }
+        config_digest.verify(&config_bytes).map_err(|e| {
+            ContainerError::ImageCorrupted(format!("config {}: {}", manifest.config.digest, e))
+        })?;
 
         #[derive(serde::Deserialize)]
         struct ImageConfig {
             config: Option<ContainerConfigBlob>,
+            rootfs: Option<RootFsBlob>,
+        }
+        #[derive(serde::Deserialize)]
+        struct RootFsBlob {
+            #[serde(default)]
+            diff_ids: Vec<String>,
         }
         #[derive(serde::Deserialize)]
         struct ContainerConfigBlob {
@@ -279,33 +628,78 @@ impl ContainerService {
             working_dir: Option<String>,
             #[serde(rename = "User")]
             user: Option<String>,
+            #[serde(rename = "Healthcheck")]
+            healthcheck: Option<HealthcheckBlob>,
+            #[serde(rename = "Labels")]
+            labels: Option<HashMap<String, String>>,
+        }
+        #[derive(serde::Deserialize)]
+        struct HealthcheckBlob {
+            #[serde(rename = "Test", default)]
+            test: Vec<String>,
+            #[serde(rename = "Interval", default)]
+            interval: i64,
+            #[serde(rename = "Timeout", default)]
+            timeout: i64,
+            #[serde(rename = "Retries", default)]
+            retries: i32,
+            #[serde(rename = "StartPeriod", default)]
+            start_period: i64,
         }
 
         let image_config: ImageConfig = serde_json::from_slice(&config_bytes).map_err(|e| {
             ContainerError::ImageNotFound(format!("Failed to parse image config: {}", e))
         })?;
 
+        // Layer snapshots are keyed by chain ID; the top one is the parent of
+        // the container's.
+        let diff_ids = image_config.rootfs.map(|r| r.diff_ids).unwrap_or_default();
+        let top_layer = chain_ids(&diff_ids).pop();
+
         let container_config = image_config.config.unwrap_or(ContainerConfigBlob {
             entrypoint: None,
             cmd: None,
             env: None,
             working_dir: None,
             user: None,
+            healthcheck: None,
+            labels: None,
         });
 
         Ok(ImageConfigInfo {
+            id: manifest.config.digest,
             top_layer,
             entrypoint: container_config.entrypoint.unwrap_or_default(),
             cmd: container_config.cmd.unwrap_or_default(),
             env: container_config.env.unwrap_or_default(),
             working_dir: container_config.working_dir.unwrap_or_default(),
             user: container_config.user.unwrap_or_default(),
+            healthcheck: container_config.healthcheck.map(|h| HealthConfig {
+                test: h.test,
+                interval: h.interval,
+                timeout: h.timeout,
+                retries: h.retries,
+                start_period: h.start_period,
+            }),
+            labels: container_config.labels.unwrap_or_default(),
         })
     }
 
+    /// Resolve a user-supplied container reference (full id, name or unique id
+    /// prefix) to the canonical container id.
+    pub async fn resolve(&self, reference: &str) -> Result<String, ContainerError> {
+        resolve_container_id(self.shim.as_ref(), reference).await
+    }
+
     pub async fn start(&self, container_id: &str) -> Result<(), ContainerError> {
         tracing::info!("Starting container: {}", container_id);
-        self.shim.start(container_id).await?;
+        let container_id = self.resolve(container_id).await?;
+        self.shim.start(&container_id).await?;
+        self.publish(&container_id, events::ACTION_START, HashMap::new())
+            .await;
+        self.supervise(&container_id);
+        self.monitor_health(&container_id);
+        self.auto_remove(&container_id);
         Ok(())
     }
 
@@ -315,7 +709,9 @@ impl ContainerService {
             container_id,
             timeout
         );
-        self.shim.stop(container_id, timeout as u32).await?;
+        let container_id = self.resolve(container_id).await?;
+        self.shim.stop(&container_id, timeout as u32, true).await?;
+        self.report_stop(&container_id).await;
         Ok(())
     }
 
@@ -325,11 +721,47 @@ impl ContainerService {
             container_id,
             timeout
         );
-        self.shim.stop(container_id, timeout as u32).await?;
-        self.shim.start(container_id).await?;
+        let container_id = self.resolve(container_id).await?;
+
+        // An auto-remove container is kept through the stop.
+        self.auto_removal
+            .lock()
+            .unwrap()
+            .restarting
+            .insert(container_id.clone());
+        let result = async {
+            self.shim.stop(&container_id, timeout as u32, true).await?;
+            self.report_stop(&container_id).await;
+            self.shim.start(&container_id).await
+        }
+        .await;
+        self.auto_removal
+            .lock()
+            .unwrap()
+            .restarting
+            .remove(&container_id);
+        result?;
+
+        self.publish(&container_id, events::ACTION_START, HashMap::new())
+            .await;
+        self.publish(&container_id, events::ACTION_RESTART, HashMap::new())
+            .await;
+        self.supervise(&container_id);
+        self.monitor_health(&container_id);
+        self.auto_remove(&container_id);
         Ok(())
     }
 
+    /// Publish the `die` of a container that was just stopped ahead of its
+    /// `stop`, rather than whenever the exit monitor gets to it.
+    async fn report_stop(&self, container_id: &str) {
+        if let Err(e) = report_exits(self.shim.as_ref(), &self.events, true).await {
+            tracing::warn!("Failed to check for container exits: {}", e);
+        }
+        self.publish(container_id, events::ACTION_STOP, HashMap::new())
+            .await;
+    }
+
     pub async fn list(
         &self,
         params: ListContainersParams,
@@ -340,25 +772,27 @@ impl ContainerService {
             params.limit
         );
 
+        filters::validate_filters(&params.filters)?;
+        // As with Docker, filtering by status looks at stopped containers too.
+        let all = params.all || params.filters.contains_key("status");
+
         let containers = self.shim.list().await?;
 
         let mut result: Vec<Container> = containers
             .into_iter()
-            .filter(|c| params.all || c.state == ross_shim::ContainerState::Running)
+            .filter(|c| all || c.state == ross_shim::ContainerState::Running)
+            .filter(|c| filters::matches(c, &params.filters))
             .map(|c| Container {
                 id: c.id.clone(),
                 names: c.name.map(|n| vec![n]).unwrap_or_default(),
                 image: c.image.clone(),
-                image_id: String::new(),
-                command: String::new(),
-                created: Some(prost_types::Timestamp {
-                    seconds: c.created_at,
-                    nanos: 0,
-                }),
+                image_id: c.image_id.clone(),
+                command: c.command.join(" "),
+                created: Some(timestamp(c.created_at, c.created_nanos)),
                 state: c.state.to_string(),
                 status: c.state.to_string(),
                 ports: vec![],
-                labels: std::collections::HashMap::new(),
+                labels: c.labels.clone(),
                 size_rw: 0,
                 size_root_fs: 0,
             })
@@ -368,31 +802,65 @@ impl ContainerService {
             result.truncate(params.limit as usize);
         }
 
+        if params.size {
+            let snapshots: HashMap<String, String> = self
+                .snapshotter
+                .list(None)
+                .await?
+                .into_iter()
+                .filter_map(|s| Some((s.labels.get(CONTAINER_ID_LABEL)?.clone(), s.key)))
+                .collect();
+
+            for container in &mut result {
+                if let Some(key) = snapshots.get(&container.id) {
+                    match self.snapshotter.usage(key).await {
+                        Ok(usage) => container.size_rw = usage.size,
+                        Err(e) => tracing::warn!("Failed to get usage of {}: {}", key, e),
+                    }
+                }
+            }
+        }
+
         Ok(result)
     }
 
     pub async fn inspect(&self, container_id: &str) -> Result<ContainerInspection, ContainerError> {
         tracing::info!("Inspecting container: {}", container_id);
 
-        let info = self.shim.get(container_id).await?;
+        let container_id = self.resolve(container_id).await?;
+        let info = self.shim.get(&container_id).await?;
+
+        let exec_ids = self
+            .execs
+            .read()
+            .await
+            .iter()
+            .filter(|(_, exec)| exec.container_id == info.id)
+            .map(|(id, _)| id.clone())
+            .collect();
 
         let state = ContainerState {
             status: info.state.to_string(),
             running: info.state == ross_shim::ContainerState::Running,
             paused: info.state == ross_shim::ContainerState::Paused,
             restarting: false,
-            oom_killed: false,
+            oom_killed: info.oom_killed,
             dead: false,
             pid: info.pid.map(|p| p as i32).unwrap_or(0),
             exit_code: info.exit_code.unwrap_or(0),
             error: String::new(),
-            started_at: info.started_at.map(|t| prost_types::Timestamp {
-                seconds: t,
-                nanos: 0,
-            }),
-            finished_at: info.finished_at.map(|t| prost_types::Timestamp {
-                seconds: t,
-                nanos: 0,
+            started_at: info.started_at.map(|t| timestamp(t, info.started_nanos)),
+            finished_at: info.finished_at.map(|t| timestamp(t, info.finished_nanos)),
+            health: info.healthcheck.as_ref().map(|_| {
+                self.health
+                    .lock()
+                    .unwrap()
+                    .get(&info.id)
+                    .cloned()
+                    .unwrap_or_else(|| Health {
+                        status: HEALTH_STARTING.to_string(),
+                        ..Default::default()
+                    })
             }),
         };
 
@@ -400,16 +868,13 @@ impl ContainerService {
             id: info.id.clone(),
             names: info.name.clone().map(|n| vec![n]).unwrap_or_default(),
             image: info.image.clone(),
-            image_id: String::new(),
-            command: String::new(),
-            created: Some(prost_types::Timestamp {
-                seconds: info.created_at,
-                nanos: 0,
-            }),
+            image_id: info.image_id.clone(),
+            command: info.command.join(" "),
+            created: Some(timestamp(info.created_at, info.created_nanos)),
             state: info.state.to_string(),
             status: info.state.to_string(),
             ports: vec![],
-            labels: std::collections::HashMap::new(),
+            labels: info.labels.clone(),
             size_rw: 0,
             size_root_fs: 0,
         };
@@ -417,22 +882,30 @@ impl ContainerService {
         Ok(ContainerInspection {
             container,
             state,
-            path: String::new(),
-            args: vec![],
+            path: info.command.first().cloned().unwrap_or_default(),
+            args: info.command.iter().skip(1).cloned().collect(),
             resolv_conf_path: String::new(),
             hostname_path: String::new(),
             hosts_path: String::new(),
             log_path: String::new(),
             name: info.name.unwrap_or_default(),
-            restart_count: 0,
+            restart_count: info.restart_count as i64,
             driver: "overlay".to_string(),
             platform: "linux".to_string(),
             mount_label: String::new(),
             process_label: String::new(),
             app_armor_profile: String::new(),
-            exec_ids: vec![],
+            exec_ids,
             config: ContainerConfig::default(),
-            host_config: HostConfig::default(),
+            host_config: HostConfig {
+                network_mode: info.network_mode,
+                auto_remove: info.auto_remove,
+                restart_policy: restart_policy_from_shim(info.restart_policy),
+                memory_limit: info.resources.memory_limit,
+                cpu_quota: info.resources.cpu_quota,
+                cpu_period: info.resources.cpu_period,
+                ..Default::default()
+            },
         })
     }
 
@@ -440,22 +913,186 @@ impl ContainerService {
         &self,
         container_id: &str,
         force: bool,
-        _remove_volumes: bool,
+        remove_volumes: bool,
     ) -> Result<(), ContainerError> {
         tracing::info!("Removing container: {} (force: {})", container_id, force);
-        self.shim.delete(container_id, force).await?;
-        Ok(())
+        let container_id = self.resolve(container_id).await?;
+        remove_container(
+            self.shim.as_ref(),
+            &self.snapshotter,
+            &self.volumes,
+            &self.events,
+            &container_id,
+            force,
+            remove_volumes,
+        )
+        .await
+    }
+
+    /// Lists the paths added, modified or deleted in the container's
+    /// filesystem relative to its image, sorted by path.
+    pub async fn diff(&self, container_id: &str) -> Result<Vec<Change>, ContainerError> {
+        tracing::info!("Diffing container: {}", container_id);
+        let container_id = self.resolve(container_id).await?;
+
+        let key = self
+            .snapshotter
+            .list(None)
+            .await?
+            .into_iter()
+            .find(|s| s.labels.get(CONTAINER_ID_LABEL) == Some(&container_id))
+            .map(|s| s.key)
+            .ok_or_else(|| {
+                SnapshotterError::NotFound(format!("snapshot of container {}", container_id))
+            })?;
+
+        let mut changes = self.snapshotter.diff(&key).await?;
+        changes.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(changes)
+    }
+
+    /// Creates the volume `name`, or an anonymous volume if it is empty.
+    pub async fn create_volume(
+        &self,
+        name: &str,
+        labels: HashMap<String, String>,
+    ) -> Result<Volume, ContainerError> {
+        self.volumes.create(non_empty(name).as_deref(), labels)
+    }
+
+    pub async fn list_volumes(&self) -> Result<Vec<Volume>, ContainerError> {
+        self.volumes.list()
+    }
+
+    /// Deletes a volume and its contents. Fails while a container that
+    /// mounts it exists.
+    pub async fn remove_volume(&self, name: &str) -> Result<(), ContainerError> {
+        let containers: HashSet<String> =
+            self.shim.list().await?.into_iter().map(|c| c.id).collect();
+        self.volumes.remove(name, &containers)
+    }
+
+    /// Returns the keys of container snapshots that must survive a prune:
+    /// those owned by an existing container, and those created before
+    /// snapshots were labelled with their owner.
+    pub async fn snapshots_in_use(&self) -> Result<HashSet<String>, ContainerError> {
+        let containers: HashSet<String> =
+            self.shim.list().await?.into_iter().map(|c| c.id).collect();
+
+        Ok(self
+            .snapshotter
+            .list(None)
+            .await?
+            .into_iter()
+            .filter(|snapshot| snapshot.labels.contains_key("container"))
+            .filter(|snapshot| match snapshot.labels.get(CONTAINER_ID_LABEL) {
+                Some(id) => containers.contains(id),
+                None => true,
+            })
+            .map(|snapshot| snapshot.key)
+            .collect())
+    }
+
+    /// IDs of the images existing containers were created from.
+    pub async fn images_in_use(&self) -> Result<HashSet<String>, ContainerError> {
+        Ok(self
+            .shim
+            .list()
+            .await?
+            .into_iter()
+            .map(|c| c.image_id)
+            .filter(|id| !id.is_empty())
+            .collect())
+    }
+
+    /// Disk space taken by the writable layers of containers. That of the
+    /// containers that are not running is reclaimable.
+    pub async fn container_disk_usage(&self) -> Result<DiskUsage, ContainerError> {
+        let snapshots: HashMap<String, String> = self
+            .snapshotter
+            .list(None)
+            .await?
+            .into_iter()
+            .filter_map(|s| Some((s.labels.get(CONTAINER_ID_LABEL)?.clone(), s.key)))
+            .collect();
+
+        let mut sizes = HashMap::new();
+        for (id, key) in snapshots {
+            match self.snapshotter.usage(&key).await {
+                Ok(usage) => {
+                    sizes.insert(id, usage.size);
+                }
+                Err(e) => tracing::warn!("Failed to get usage of {}: {}", key, e),
+            }
+        }
+        Ok(container_usage(&self.shim.list().await?, &sizes))
+    }
+
+    /// Disk space taken by volumes. That of the volumes no container mounts
+    /// is reclaimable.
+    pub async fn volume_disk_usage(&self) -> Result<DiskUsage, ContainerError> {
+        let containers: HashSet<String> =
+            self.shim.list().await?.into_iter().map(|c| c.id).collect();
+        let volumes = self.volumes.clone();
+        tokio::task::spawn_blocking(move || volumes.disk_usage(&containers))
+            .await
+            .map_err(std::io::Error::other)?
+    }
+
+    /// Prepares for the daemon exiting: stops the running and paused
+    /// containers, all at once, unless `leave_running` is set, then has the
+    /// shim release the sockets and network stacks it holds. Each container
+    /// gets `stop_timeout` seconds to exit on its stop signal before it is
+    /// killed.
+    pub async fn shutdown(&self, leave_running: bool, stop_timeout: u32) {
+        self.shutdown.cancel();
+        if !leave_running {
+            let containers = match self.shim.list().await {
+                Ok(containers) => containers,
+                Err(e) => {
+                    tracing::warn!("Failed to list containers to stop: {}", e);
+                    Vec::new()
+                }
+            };
+            let mut killed = Vec::new();
+            for (container, was_killed) in
+                stop_for_shutdown(self.shim.as_ref(), containers, stop_timeout).await
+            {
+                self.report_stop(&container.id).await;
+                if was_killed {
+                    killed.push(container.name.unwrap_or(container.id));
+                }
+            }
+            if !killed.is_empty() {
+                tracing::warn!(
+                    "Killed {} container(s) that did not stop within {}s: {}",
+                    killed.len(),
+                    stop_timeout,
+                    killed.join(", ")
+                );
+            }
+        }
+
+        if let Err(e) = self.shim.shutdown().await {
+            tracing::warn!("Failed to release container resources: {}", e);
+        }
     }
 
     pub async fn pause(&self, container_id: &str) -> Result<(), ContainerError> {
         tracing::info!("Pausing container: {}", container_id);
-        self.shim.pause(container_id).await?;
+        let container_id = self.resolve(container_id).await?;
+        self.shim.pause(&container_id).await?;
+        self.publish(&container_id, events::ACTION_PAUSE, HashMap::new())
+            .await;
         Ok(())
     }
 
     pub async fn unpause(&self, container_id: &str) -> Result<(), ContainerError> {
         tracing::info!("Unpausing container: {}", container_id);
-        self.shim.resume(container_id).await?;
+        let container_id = self.resolve(container_id).await?;
+        self.shim.resume(&container_id).await?;
+        self.publish(&container_id, events::ACTION_UNPAUSE, HashMap::new())
+            .await;
         Ok(())
     }
 
@@ -466,19 +1103,115 @@ impl ContainerService {
             params.follow
         );
 
+        let shim = self.shim.clone();
+
         let output = stream! {
-            let log_messages = [
-                ("stdout", "Container started"),
-                ("stdout", "Application running"),
-                ("stderr", "Health check passed"),
-            ];
-
-            for (stream_type, message) in log_messages {
-                yield Ok(LogEntry {
-                    timestamp: now_timestamp(),
-                    stream: stream_type.to_string(),
-                    message: message.to_string(),
-                });
+            let tail = match logs::parse_tail(&params.tail) {
+                Ok(tail) => tail,
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            };
+
+            let container_id = match resolve_container_id(shim.as_ref(), &params.container_id).await {
+                Ok(id) => id,
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            };
+
+            let info = match shim.get(&container_id).await {
+                Ok(info) => info,
+                Err(e) => {
+                    yield Err(e.into());
+                    return;
+                }
+            };
+
+            // Neither stream selected means both, matching `docker logs`
+            let (want_stdout, want_stderr) = if !params.stdout && !params.stderr {
+                (true, true)
+            } else {
+                (params.stdout, params.stderr)
+            };
+
+            let bundle_path = PathBuf::from(&info.bundle_path);
+            let mut readers = Vec::new();
+            if want_stdout {
+                readers.push(LogReader::new("stdout", bundle_path.join("stdout.log")));
+            }
+            if want_stderr {
+                readers.push(LogReader::new("stderr", bundle_path.join("stderr.log")));
+            }
+
+            let since = params.since.as_ref();
+            let until = params.until.as_ref();
+
+            // The tail counts lines inside the time window, so with one the
+            // whole log is read and filtered first. Without, each stream's own
+            // tail holds every line of the tail of both.
+            let windowed = since.is_some() || until.is_some();
+            let mut streams = Vec::new();
+            for reader in readers.iter_mut() {
+                match reader.read_existing(if windowed { None } else { tail }).await {
+                    Ok(entries) => streams.push(
+                        entries
+                            .into_iter()
+                            .filter(|entry| logs::in_range(&entry.timestamp, since, until))
+                            .collect(),
+                    ),
+                    Err(e) => {
+                        yield Err(e);
+                        return;
+                    }
+                }
+            }
+            for entry in logs::merge_tail(streams, tail) {
+                yield Ok(entry);
+            }
+
+            if !params.follow {
+                return;
+            }
+
+            loop {
+                tokio::time::sleep(LOG_POLL_INTERVAL).await;
+
+                let running = matches!(
+                    shim.get(&container_id).await.map(|i| i.state),
+                    Ok(ross_shim::ContainerState::Running | ross_shim::ContainerState::Paused)
+                );
+
+                for reader in readers.iter_mut() {
+                    match reader.read_appended().await {
+                        Ok(entries) => {
+                            for entry in entries {
+                                if logs::in_range(&entry.timestamp, since, until) {
+                                    yield Ok(entry);
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            yield Err(e);
+                            return;
+                        }
+                    }
+                }
+
+                let past_until = until.is_some_and(|u| now_timestamp().seconds > u.seconds);
+
+                if !running || past_until {
+                    for reader in readers.iter_mut() {
+                        if let Some(entry) = reader.flush()
+                            && logs::in_range(&entry.timestamp, since, until)
+                        {
+                            yield Ok(entry);
+                        }
+                    }
+                    break;
+                }
             }
         };
 
@@ -495,57 +1228,226 @@ impl ContainerService {
             container_id,
             config.cmd
         );
-        Ok("stub-exec-id".to_string())
+
+        if config.cmd.is_empty() {
+            return Err(ContainerError::InvalidArgument(
+                "exec command must not be empty".to_string(),
+            ));
+        }
+
+        let container_id = self.resolve(container_id).await?;
+        let info = self.shim.get(&container_id).await?;
+        if info.state != ross_shim::ContainerState::Running {
+            return Err(ContainerError::NotRunning(container_id));
+        }
+
+        let exec_id = uuid::Uuid::new_v4().to_string();
+
+        let mut execs = self.execs.write().await;
+        execs.insert(
+            exec_id.clone(),
+            ExecInstance {
+                container_id: info.id,
+                config,
+                running: false,
+                exit_code: None,
+            },
+        );
+
+        Ok(exec_id)
     }
 
     pub fn exec_start(&self, exec_id: &str) -> BoxStream<Result<ExecOutput, ContainerError>> {
+        use futures::StreamExt;
+
         tracing::info!("Starting exec: {}", exec_id);
 
+        let shim = self.shim.clone();
+        let execs = self.execs.clone();
+        let exec_id = exec_id.to_string();
+
         let output = stream! {
-            let outputs = [
-                "Command executed successfully\n",
-                "Output line 1\n",
-                "Output line 2\n",
-            ];
-
-            for data in outputs {
-                yield Ok(ExecOutput {
-                    stream: "stdout".to_string(),
-                    data: data.as_bytes().to_vec(),
-                });
+            let (container_id, opts) = {
+                let mut execs = execs.write().await;
+                let Some(exec) = execs.get_mut(&exec_id) else {
+                    yield Err(ContainerError::ExecNotFound(exec_id.clone()));
+                    return;
+                };
+
+                if exec.running || exec.exit_code.is_some() {
+                    yield Err(ContainerError::InvalidArgument(format!(
+                        "exec {} has already been started",
+                        exec_id
+                    )));
+                    return;
+                }
+                exec.running = true;
+
+                let opts = ross_shim::ExecOpts {
+                    cmd: exec.config.cmd.clone(),
+                    env: exec.config.env.clone(),
+                    user: non_empty(&exec.config.user),
+                    working_dir: non_empty(&exec.config.working_dir),
+                    tty: exec.config.tty,
+                    privileged: exec.config.privileged,
+                    timeout: None,
+                };
+                (exec.container_id.clone(), opts)
+            };
+
+            let mut events = shim.exec(container_id, opts);
+
+            while let Some(event) = events.next().await {
+                match event {
+                    Ok(ross_shim::OutputEvent::Stdout(data)) => {
+                        yield Ok(ExecOutput {
+                            stream: "stdout".to_string(),
+                            data,
+                            exit: None,
+                        });
+                    }
+                    Ok(ross_shim::OutputEvent::Stderr(data)) => {
+                        yield Ok(ExecOutput {
+                            stream: "stderr".to_string(),
+                            data,
+                            exit: None,
+                        });
+                    }
+                    Ok(ross_shim::OutputEvent::Exit(r)) => {
+                        if let Some(exec) = execs.write().await.get_mut(&exec_id) {
+                            exec.running = false;
+                            exec.exit_code = Some(r.exit_code);
+                        }
+                        yield Ok(ExecOutput {
+                            stream: "exit".to_string(),
+                            data: vec![],
+                            exit: Some(WaitResult {
+                                status_code: r.exit_code as i64,
+                                error: r.error,
+                            }),
+                        });
+                    }
+                    Err(e) => {
+                        if let Some(exec) = execs.write().await.get_mut(&exec_id) {
+                            exec.running = false;
+                            exec.exit_code = Some(-1);
+                        }
+                        yield Err(e.into());
+                        return;
+                    }
+                }
             }
         };
 
         Box::pin(output)
     }
 
+    /// Connect a client to a running container's main process. The first
+    /// message names the container and selects the streams; later messages
+    /// carry stdin. Typing the detach keys ends the session and leaves the
+    /// container running.
     pub fn attach<S>(&self, input_stream: S) -> BoxStream<Result<AttachOutput, ContainerError>>
     where
         S: Stream<Item = Result<AttachInput, ContainerError>> + Send + 'static,
     {
+        use tokio::sync::broadcast::error::RecvError;
         use tokio_stream::StreamExt;
-        tracing::info!("Attaching to container");
+
+        let shim = self.shim.clone();
 
         let output = stream! {
             tokio::pin!(input_stream);
-            while let Some(result) = input_stream.next().await {
-                match result {
-                    Ok(attach_input) => {
-                        tracing::info!(
-                            "Received attach input for container: {}, {} bytes",
-                            attach_input.container_id,
-                            attach_input.input.len()
-                        );
-                        yield Ok(AttachOutput {
-                            stream: "stdout".to_string(),
-                            data: attach_input.input,
-                        });
-                    }
-                    Err(e) => {
-                        tracing::warn!("Error receiving attach input: {}", e);
-                        break;
-                    }
+
+            let first = match input_stream.next().await {
+                Some(Ok(first)) => first,
+                Some(Err(e)) => {
+                    yield Err(e);
+                    return;
+                }
+                None => return,
+            };
+
+            let container_id = match resolve_container_id(shim.as_ref(), &first.container_id).await {
+                Ok(id) => id,
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            };
+            let mut matcher = match attach::parse_detach_keys(&first.detach_keys) {
+                Ok(keys) => DetachMatcher::new(keys),
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            };
+            let streams = match shim.attach(&container_id).await {
+                Ok(streams) => streams,
+                Err(e) => {
+                    yield Err(e.into());
+                    return;
                 }
+            };
+
+            tracing::info!(container_id = %container_id, "Attached to container");
+
+            let mut output = streams.output;
+            let stdin = if first.stdin { streams.input } else { None };
+
+            let (forward, mut detached) = matcher.feed(&first.input);
+            forward_stdin(stdin.as_ref(), forward).await;
+            let mut input_open = true;
+
+            while !detached {
+                tokio::select! {
+                    event = output.recv() => match event {
+                        Ok(ross_shim::OutputEvent::Stdout(data)) => {
+                            if first.stdout {
+                                yield Ok(AttachOutput {
+                                    stream: "stdout".to_string(),
+                                    data,
+                                });
+                            }
+                        }
+                        Ok(ross_shim::OutputEvent::Stderr(data)) => {
+                            if first.stderr {
+                                yield Ok(AttachOutput {
+                                    stream: "stderr".to_string(),
+                                    data,
+                                });
+                            }
+                        }
+                        Ok(ross_shim::OutputEvent::Exit(_)) | Err(RecvError::Closed) => break,
+                        Err(RecvError::Lagged(skipped)) => {
+                            tracing::warn!(container_id = %container_id, skipped, "Attached client fell behind, dropped output");
+                        }
+                    },
+                    message = input_stream.next(), if input_open => match message {
+                        Some(Ok(message)) => {
+                            let (forward, matched) = matcher.feed(&message.input);
+                            forward_stdin(stdin.as_ref(), forward).await;
+                            detached = matched;
+                        }
+                        Some(Err(e)) => {
+                            tracing::warn!("Error receiving attach input: {}", e);
+                            break;
+                        }
+                        // The client closed its stdin but still wants output.
+                        None => {
+                            input_open = false;
+                            close_stdin(stdin.as_ref()).await;
+                        }
+                    },
+                }
+            }
+
+            // Leaving is the end of the client's input too, which closes the
+            // container's stdin if it is only open once.
+            if input_open {
+                close_stdin(stdin.as_ref()).await;
+            }
+            if detached {
+                tracing::info!(container_id = %container_id, "Detached from container");
             }
         };
 
@@ -560,20 +1462,70 @@ impl ContainerService {
 
         tracing::info!("Waiting for container (streaming): {}", container_id);
 
-        let stream = self.shim.run_streaming(container_id.to_string());
+        let shim = self.shim.clone();
+        let supervised = self.supervised.clone();
+        let health = self.health.clone();
+        let events = self.events.clone();
+        let shutdown = self.shutdown.clone();
+        let snapshotter = self.snapshotter.clone();
+        let volumes = self.volumes.clone();
+        let reference = container_id.to_string();
+
+        stream! {
+            let container_id = match resolve_container_id(shim.as_ref(), &reference).await {
+                Ok(id) => id,
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            };
+
+            spawn_health_monitor(shim.clone(), health, container_id.clone());
+            publish_event(
+                shim.as_ref(),
+                &events,
+                &container_id,
+                events::ACTION_START,
+                HashMap::new(),
+            )
+            .await;
+
+            // The run is driven by a task of its own so that the container's
+            // exit is still recorded, and it is removed or restarted, when
+            // the client goes away first. Its output is then drained.
+            let (tx, mut rx) = tokio::sync::mpsc::channel(32);
+            tokio::spawn(async move {
+                let mut output = shim.run_streaming(container_id.clone());
+                let mut client = Some(tx);
+                while let Some(result) = output.next().await {
+                    let Some(tx) = &client else {
+                        continue;
+                    };
+                    let result = result
+                        .map(|event| match event {
+                            ross_shim::OutputEvent::Stdout(data) => OutputEvent::Stdout(data),
+                            ross_shim::OutputEvent::Stderr(data) => OutputEvent::Stderr(data),
+                            ross_shim::OutputEvent::Exit(r) => OutputEvent::Exit(WaitResult {
+                                status_code: r.exit_code as i64,
+                                error: r.error,
+                            }),
+                        })
+                        .map_err(ContainerError::from);
+                    if tx.send(result).await.is_err() {
+                        tracing::debug!(container_id = %container_id, "Wait client went away, draining output");
+                        client = None;
+                    }
+                }
 
-        stream.map(|result| {
-            result
-                .map(|event| match event {
-                    ross_shim::OutputEvent::Stdout(data) => OutputEvent::Stdout(data),
-                    ross_shim::OutputEvent::Stderr(data) => OutputEvent::Stderr(data),
-                    ross_shim::OutputEvent::Exit(r) => OutputEvent::Exit(WaitResult {
-                        status_code: r.exit_code as i64,
-                        error: r.error,
-                    }),
-                })
-                .map_err(ContainerError::from)
-        })
+                remove_if_auto(shim.as_ref(), &snapshotter, &volumes, &events, &container_id).await;
+                spawn_supervisor(shim, supervised, events, shutdown, container_id);
+            });
+
+            // The client's stream ends once the container is cleaned up.
+            while let Some(result) = rx.recv().await {
+                yield result;
+            }
+        }
     }
 
     pub async fn kill(&self, container_id: &str, signal: &str) -> Result<(), ContainerError> {
@@ -583,17 +1535,101 @@ impl ContainerService {
             signal
         );
 
+        let container_id = self.resolve(container_id).await?;
         let sig = parse_signal(signal);
-        self.shim.kill(container_id, sig).await?;
+        self.shim.kill(&container_id, sig).await?;
+        let attributes = HashMap::from([("signal".to_string(), sig.to_string())]);
+        self.publish(&container_id, events::ACTION_KILL, attributes)
+            .await;
 
         Ok(())
     }
 
     pub async fn rename(&self, container_id: &str, new_name: &str) -> Result<(), ContainerError> {
         tracing::info!("Renaming container: {} to: {}", container_id, new_name);
+
+        let container_id = self.resolve(container_id).await?;
+        let old_name = self.shim.get(&container_id).await?.name;
+        self.shim
+            .rename(&container_id, new_name)
+            .await
+            .map_err(|e| match e {
+                ross_shim::ShimError::ContainerNotFound(id) => ContainerError::NotFound(id),
+                ross_shim::ShimError::ContainerAlreadyExists(name) => {
+                    ContainerError::AlreadyExists(format!("name {} is already in use", name))
+                }
+                e => e.into(),
+            })?;
+
+        let attributes = HashMap::from([("oldName".to_string(), old_name.unwrap_or_default())]);
+        self.publish(&container_id, events::ACTION_RENAME, attributes)
+            .await;
         Ok(())
     }
 
+    /// Change the resource limits of a container, running or not.
+    pub async fn update(
+        &self,
+        container_id: &str,
+        params: UpdateParams,
+    ) -> Result<(), ContainerError> {
+        tracing::info!("Updating container: {}", container_id);
+
+        let update = resource_update(&params)?;
+        let container_id = self.resolve(container_id).await?;
+        self.shim
+            .update(&container_id, update)
+            .await
+            .map_err(|e| match e {
+                ross_shim::ShimError::ContainerNotFound(id) => ContainerError::NotFound(id),
+                e => e.into(),
+            })?;
+
+        self.publish(&container_id, events::ACTION_UPDATE, HashMap::new())
+            .await;
+        Ok(())
+    }
+
+    /// Stream lifecycle events as they happen, after replaying the recent
+    /// ones from `params.since` on.
+    pub fn events(
+        &self,
+        params: EventsParams,
+    ) -> BoxStream<Result<ContainerEvent, ContainerError>> {
+        tracing::info!("Streaming events (since: {:?})", params.since);
+
+        let bus = self.events.clone();
+        let output = stream! {
+            if let Err(e) = events::validate_filters(&params.filters) {
+                yield Err(e);
+                return;
+            }
+
+            let (past, mut rx) = bus.subscribe(params.since.as_ref());
+            for event in past {
+                if events::matches(&event, &params.filters) {
+                    yield Ok(event);
+                }
+            }
+
+            loop {
+                match rx.recv().await {
+                    Ok(event) => {
+                        if events::matches(&event, &params.filters) {
+                            yield Ok(event);
+                        }
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(missed)) => {
+                        tracing::warn!("Event subscriber fell behind, {} events dropped", missed);
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        };
+
+        Box::pin(output)
+    }
+
     pub fn stats(&self, params: StatsParams) -> BoxStream<Result<ContainerStats, ContainerError>> {
         tracing::info!(
             "Getting stats for container: {} (stream: {})",
@@ -601,41 +1637,160 @@ impl ContainerService {
             params.stream
         );
 
+        let shim = self.shim.clone();
+
         let output = stream! {
-            for i in 0..3u64 {
+            let container_id = match resolve_container_id(shim.as_ref(), &params.container_id).await {
+                Ok(id) => id,
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            };
+
+            // A non-streaming, non-one-shot request still takes a priming sample
+            // so the single result carries a CPU delta.
+            let prime = !params.stream && !params.one_shot;
+            let mut previous: Option<stats::Sample> = None;
+
+            loop {
+                let info = match shim.get(&container_id).await {
+                    Ok(info) => info,
+                    Err(e) => {
+                        if previous.is_none() {
+                            yield Err(e.into());
+                        }
+                        return;
+                    }
+                };
+
+                let running = matches!(
+                    info.state,
+                    ross_shim::ContainerState::Running | ross_shim::ContainerState::Paused
+                );
+                let Some(pid) = info.pid.filter(|_| running) else {
+                    if previous.is_none() {
+                        yield Err(ContainerError::NotRunning(container_id.clone()));
+                    }
+                    return;
+                };
+
+                let sample = match stats::sample(pid).await {
+                    Ok(sample) => sample,
+                    Err(e) => {
+                        // The process going away mid-stream means the container stopped
+                        if previous.is_none() {
+                            yield Err(e);
+                        }
+                        return;
+                    }
+                };
+
+                if prime && previous.is_none() {
+                    previous = Some(sample);
+                    tokio::time::sleep(STATS_INTERVAL).await;
+                    continue;
+                }
+
+                let networks = shim
+                    .network_stats(&container_id)
+                    .await
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|(name, s)| {
+                        let stats = NetworkStats {
+                            rx_bytes: s.rx_bytes,
+                            rx_packets: s.rx_packets,
+                            rx_dropped: s.rx_dropped,
+                            tx_bytes: s.tx_bytes,
+                            tx_packets: s.tx_packets,
+                            tx_dropped: s.tx_dropped,
+                            ..Default::default()
+                        };
+                        (name, stats)
+                    })
+                    .collect();
+
                 yield Ok(ContainerStats {
-                    read: Some(now_timestamp()),
-                    preread: Some(now_timestamp()),
-                    num_procs: 1,
-                    cpu_stats: Some(CpuStats {
-                        cpu_usage: Some(CpuUsage {
-                            total_usage: 1000000 * (i + 1),
-                            percpu_usage: vec![500000 * (i + 1)],
-                            usage_in_kernelmode: 100000 * (i + 1),
-                            usage_in_usermode: 900000 * (i + 1),
-                        }),
-                        system_cpu_usage: 10000000000,
-                        online_cpus: 4,
-                    }),
-                    precpu_stats: None,
-                    memory_stats: Some(MemoryStats {
-                        usage: 52428800 + (i * 1048576),
-                        max_usage: 104857600,
-                        stats: Default::default(),
-                        failcnt: 0,
-                        limit: 1073741824,
-                        commit: 0,
-                        commit_peak: 0,
-                        private_working_set: 0,
-                    }),
-                    networks: Default::default(),
+                    read: Some(sample.read),
+                    preread: previous.as_ref().map(|p| p.read),
+                    num_procs: 0,
+                    cpu_stats: Some(sample.cpu.clone()),
+                    precpu_stats: previous.as_ref().map(|p| p.cpu.clone()),
+                    memory_stats: Some(sample.memory.clone()),
+                    pids_stats: Some(sample.pids.clone()),
+                    networks,
                 });
+
+                if !params.stream {
+                    return;
+                }
+
+                previous = Some(sample);
+                tokio::time::sleep(STATS_INTERVAL).await;
             }
         };
 
         Box::pin(output)
     }
 
+    /// Lists the processes running in the container, as the host's `ps`
+    /// shows them with `ps_args` if given.
+    pub async fn top(
+        &self,
+        container_id: &str,
+        ps_args: &str,
+    ) -> Result<TopResult, ContainerError> {
+        tracing::info!("Listing processes of container: {}", container_id);
+        let container_id = self.resolve(container_id).await?;
+        let pids = match self.shim.processes(&container_id).await {
+            Ok(pids) => pids,
+            Err(ross_shim::ShimError::ContainerNotRunning(id)) => {
+                return Err(ContainerError::NotRunning(id));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        top::top(&pids, ps_args).await
+    }
+
+    /// One reading of the state and resource usage of every container.
+    pub async fn usage(&self) -> Result<Vec<ContainerUsage>, ContainerError> {
+        let containers = self.shim.list().await?;
+        let mut usage = Vec::with_capacity(containers.len());
+        for info in containers {
+            let mut container = ContainerUsage {
+                id: info.id.clone(),
+                name: info.name.clone().unwrap_or_default(),
+                state: filters::status(&info.state).to_string(),
+                ..Default::default()
+            };
+            let running = matches!(
+                info.state,
+                ross_shim::ContainerState::Running | ross_shim::ContainerState::Paused
+            );
+            if !running {
+                usage.push(container);
+                continue;
+            }
+
+            if let Some(pid) = info.pid
+                && let Ok(sample) = stats::sample(pid).await
+            {
+                container.cpu_usage_ns = sample.cpu.cpu_usage.map(|u| u.total_usage);
+                container.memory_usage = Some(sample.memory.usage);
+                container.memory_limit = Some(sample.memory.limit);
+            }
+            let networks = self.shim.network_stats(&info.id).await.unwrap_or_default();
+            for interface in networks.values() {
+                container.rx_bytes += interface.rx_bytes;
+                container.tx_bytes += interface.tx_bytes;
+                container.nat_connections += interface.nat_connections;
+            }
+            usage.push(container);
+        }
+        Ok(usage)
+    }
+
     /// Run a container interactively with bidirectional streaming.
     /// Returns a sender for input events and an output stream.
     pub async fn run_interactive(
@@ -655,6 +1810,9 @@ impl ContainerService {
             tty
         );
 
+        let container_id = self.resolve(&container_id).await?;
+        self.monitor_health(&container_id);
+
         let (input_tx, input_rx) = tokio::sync::mpsc::channel::<InputEvent>(32);
         let (output_tx, mut output_rx) = tokio::sync::mpsc::channel::<ross_shim::OutputEvent>(32);
 
@@ -665,10 +1823,16 @@ impl ContainerService {
         let shim = self.shim.clone();
         let container_id_clone = container_id.clone();
 
+        // The forwarder stops when the output stream is dropped, the client
+        // having gone away. The session itself lives on with the container,
+        // which other clients may still attach to.
+        let cancel = CancellationToken::new();
+        let cancel_on_drop = cancel.clone().drop_guard();
+
         // Forward input events to shim format
         tokio::spawn(async move {
             let mut input_rx = input_rx;
-            while let Some(event) = input_rx.recv().await {
+            while let Some(Some(event)) = cancel.run_until_cancelled(input_rx.recv()).await {
                 tracing::debug!("Forwarding input event to shim");
                 let shim_event = match event {
                     InputEvent::Stdin(data) => {
@@ -678,6 +1842,7 @@ impl ContainerService {
                     InputEvent::Resize { width, height } => {
                         ross_shim::InputEvent::Resize { width, height }
                     }
+                    InputEvent::CloseStdin => ross_shim::InputEvent::CloseStdin,
                 };
                 if shim_input_tx.send(shim_event).await.is_err() {
                     tracing::debug!("Shim input channel closed");
@@ -688,17 +1853,31 @@ impl ContainerService {
         });
 
         // Start the interactive session in the shim
+        self.publish(&container_id, events::ACTION_START, HashMap::new())
+            .await;
+        let snapshotter = self.snapshotter.clone();
+        let volumes = self.volumes.clone();
+        let events = self.events.clone();
         tokio::spawn(async move {
             if let Err(e) = shim
-                .run_interactive(container_id_clone, shim_input_rx, output_tx)
+                .run_interactive(container_id_clone.clone(), shim_input_rx, output_tx)
                 .await
             {
                 tracing::error!("Interactive session error: {}", e);
             }
+            remove_if_auto(
+                shim.as_ref(),
+                &snapshotter,
+                &volumes,
+                &events,
+                &container_id_clone,
+            )
+            .await;
         });
 
         // Create output stream from channel
         let output_stream = stream! {
+            let _cancel_on_drop = cancel_on_drop;
             while let Some(event) = output_rx.recv().await {
                 let result = match event {
                     ross_shim::OutputEvent::Stdout(data) => OutputEvent::Stdout(data),
@@ -716,45 +1895,797 @@ impl ContainerService {
     }
 }
 
-fn parse_image_reference(image: &str) -> (String, String) {
-    let image = image.trim();
+async fn resolve_container_id(
+    shim: &(dyn Shim + Send + Sync),
+    reference: &str,
+) -> Result<String, ContainerError> {
+    let containers = shim.list().await?;
+    resolve_reference(&containers, reference)
+}
 
-    // Extract tag/digest
-    let (name_part, tag) = if let Some(at_idx) = image.rfind('@') {
-        (&image[..at_idx], &image[at_idx + 1..])
-    } else if let Some(colon_idx) = image.rfind(':') {
-        let potential_tag = &image[colon_idx + 1..];
-        if !potential_tag.contains('/') {
-            (&image[..colon_idx], potential_tag)
-        } else {
-            (image, "latest")
+/// Write client input to an attached container, dropping it when the
+/// container has no stdin or the client did not ask to attach it.
+async fn forward_stdin(
+    stdin: Option<&tokio::sync::mpsc::Sender<ross_shim::InputEvent>>,
+    data: Vec<u8>,
+) {
+    if let Some(stdin) = stdin
+        && !data.is_empty()
+        && stdin
+            .send(ross_shim::InputEvent::Stdin(data))
+            .await
+            .is_err()
+    {
+        tracing::debug!("Container stdin closed");
+    }
+}
+
+/// Tell an attached container that the client is done with its stdin.
+async fn close_stdin(stdin: Option<&tokio::sync::mpsc::Sender<ross_shim::InputEvent>>) {
+    if let Some(stdin) = stdin {
+        let _ = stdin.send(ross_shim::InputEvent::CloseStdin).await;
+    }
+}
+
+/// Match a reference against known containers: exact id, then exact name, then
+/// unique id prefix.
+fn resolve_reference(
+    containers: &[ross_shim::ContainerInfo],
+    reference: &str,
+) -> Result<String, ContainerError> {
+    let reference = reference.trim().trim_start_matches('/');
+    if reference.is_empty() {
+        return Err(ContainerError::InvalidArgument(
+            "container reference must not be empty".to_string(),
+        ));
+    }
+
+    if let Some(c) = containers.iter().find(|c| c.id == reference) {
+        return Ok(c.id.clone());
+    }
+
+    if let Some(c) = containers
+        .iter()
+        .find(|c| c.name.as_deref() == Some(reference))
+    {
+        return Ok(c.id.clone());
+    }
+
+    let matches: Vec<&str> = containers
+        .iter()
+        .filter(|c| c.id.starts_with(reference))
+        .map(|c| c.id.as_str())
+        .collect();
+
+    match matches.as_slice() {
+        [] => Err(ContainerError::NotFound(reference.to_string())),
+        [id] => Ok(id.to_string()),
+        _ => Err(ContainerError::InvalidArgument(format!(
+            "container reference {} is ambiguous, it matches {} containers",
+            reference,
+            matches.len()
+        ))),
+    }
+}
+
+/// The entrypoint and command a container runs: the user's where set, the
+/// image's otherwise. As with Docker, setting the entrypoint drops the
+/// image's command, which was written for the image's entrypoint, unless
+/// the entrypoint is cleared.
+fn merge_command(
+    entrypoint: Option<Vec<String>>,
+    cmd: Option<Vec<String>>,
+    image_entrypoint: Vec<String>,
+    image_cmd: Vec<String>,
+) -> (Vec<String>, Vec<String>) {
+    let cmd = match (&entrypoint, cmd) {
+        (_, Some(cmd)) => cmd,
+        (Some(entrypoint), None) if !entrypoint.is_empty() => Vec::new(),
+        _ => image_cmd,
+    };
+    (entrypoint.unwrap_or(image_entrypoint), cmd)
+}
+
+/// Sums the writable layer `sizes` of `containers`, keyed by container ID.
+/// Those of the containers that are neither running nor paused are
+/// reclaimable.
+fn container_usage(
+    containers: &[ross_shim::ContainerInfo],
+    sizes: &HashMap<String, i64>,
+) -> DiskUsage {
+    let mut usage = DiskUsage::default();
+    for container in containers {
+        let size = sizes.get(&container.id).copied().unwrap_or(0);
+        usage.count += 1;
+        usage.size += size;
+        match container.state {
+            ross_shim::ContainerState::Running | ross_shim::ContainerState::Paused => {
+                usage.active += 1
+            }
+            _ => usage.reclaimable += size,
         }
+    }
+    usage
+}
+
+fn non_empty(s: &str) -> Option<String> {
+    if s.is_empty() {
+        None
     } else {
-        (image, "latest")
+        Some(s.to_string())
+    }
+}
+
+/// A time the shim records, in seconds and the nanoseconds past them.
+fn timestamp(seconds: i64, nanos: u32) -> prost_types::Timestamp {
+    prost_types::Timestamp {
+        seconds,
+        nanos: nanos as i32,
+    }
+}
+
+/// Restart the container per its restart policy until the policy gives up,
+/// the user stops it, or it is removed. At most one supervisor runs per
+/// container.
+fn spawn_supervisor(
+    shim: Arc<dyn Shim + Send + Sync>,
+    supervised: Arc<std::sync::Mutex<HashSet<String>>>,
+    events: Arc<EventBus>,
+    shutdown: CancellationToken,
+    container_id: String,
+) {
+    if !supervised.lock().unwrap().insert(container_id.clone()) {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut backoff = RESTART_BACKOFF_MIN;
+        let has_policy = shim
+            .get(&container_id)
+            .await
+            .is_ok_and(|info| info.restart_policy != ross_shim::RestartPolicy::No);
+
+        if has_policy {
+            loop {
+                let Ok(result) = shim.wait(&container_id).await else {
+                    break;
+                };
+                let Ok(info) = shim.get(&container_id).await else {
+                    break;
+                };
+                // Started again by someone else in the meantime; keep watching.
+                if info.state != ross_shim::ContainerState::Stopped {
+                    continue;
+                }
+                if !info.restart_policy.should_restart(
+                    result.exit_code,
+                    info.restart_count,
+                    info.manually_stopped,
+                ) {
+                    break;
+                }
+
+                let ran_for =
+                    info.finished_at.unwrap_or_default() - info.started_at.unwrap_or_default();
+                if ran_for >= RESTART_BACKOFF_RESET_SECS {
+                    backoff = RESTART_BACKOFF_MIN;
+                }
+                // Containers stopped for shutdown are not restarted.
+                tokio::select! {
+                    () = tokio::time::sleep(backoff) => {}
+                    () = shutdown.cancelled() => break,
+                }
+                backoff = (backoff * 2).min(RESTART_BACKOFF_MAX);
+
+                // The container may have been started or removed while we slept.
+                match shim.get(&container_id).await {
+                    Ok(info) if info.state == ross_shim::ContainerState::Stopped => {}
+                    Ok(_) => continue,
+                    Err(_) => break,
+                }
+
+                let restart_count = match shim.record_restart(&container_id).await {
+                    Ok(count) => count,
+                    Err(_) => break,
+                };
+                tracing::info!(
+                    container_id = %container_id,
+                    exit_code = result.exit_code,
+                    restart_count = restart_count,
+                    "Restarting container per restart policy"
+                );
+                if let Err(e) = shim.start(&container_id).await {
+                    tracing::warn!(container_id = %container_id, "Failed to restart container: {}", e);
+                    break;
+                }
+                publish_event(
+                    shim.as_ref(),
+                    &events,
+                    &container_id,
+                    events::ACTION_START,
+                    HashMap::new(),
+                )
+                .await;
+            }
+        }
+
+        supervised.lock().unwrap().remove(&container_id);
+    });
+}
+
+/// Delete the container and the snapshots it owns, and with
+/// `remove_volumes` the anonymous volumes only it mounts.
+async fn remove_container(
+    shim: &(dyn Shim + Send + Sync),
+    snapshotter: &OverlaySnapshotter,
+    volumes: &VolumeStore,
+    events: &EventBus,
+    container_id: &str,
+    force: bool,
+    remove_volumes: bool,
+) -> Result<(), ContainerError> {
+    let attributes = match shim.get(container_id).await {
+        Ok(info) => container_attributes(&info),
+        Err(_) => HashMap::new(),
     };
+    // Report the container's exit while it can still be seen.
+    if let Err(e) = report_exits(shim, events, true).await {
+        tracing::warn!("Failed to check for container exits: {}", e);
+    }
+    shim.delete(container_id, force).await?;
+    events.forget(container_id);
+    events.publish(container_id, events::ACTION_DESTROY, attributes);
+
+    for snapshot in snapshotter.list(None).await? {
+        if snapshot.labels.get(CONTAINER_ID_LABEL).map(String::as_str) == Some(container_id)
+            && let Err(e) = snapshotter.remove(&snapshot.key).await
+        {
+            tracing::warn!("Failed to remove snapshot {}: {}", snapshot.key, e);
+        }
+    }
+    if let Err(e) = volumes.detach(container_id, remove_volumes) {
+        tracing::warn!("Failed to release the volumes of {}: {}", container_id, e);
+    }
 
-    // Determine repository - need to match how the store indexes images
-    // The store uses the format from ImageReference which stores:
-    // - "library/nginx" for "nginx"
-    // - "myuser/myimage" for "myuser/myimage"
-    let repository = if name_part.contains('/') {
-        let first_slash = name_part.find('/').unwrap();
-        let first_part = &name_part[..first_slash];
-
-        // Check if first part is a registry
-        if first_part.contains('.') || first_part.contains(':') || first_part == "localhost" {
-            // Has registry - repository is everything after first /
-            name_part[first_slash + 1..].to_string()
-        } else {
-            // No registry, whole thing is repository
-            name_part.to_string()
+    Ok(())
+}
+
+/// Remove a container created with auto-remove that has exited, along with
+/// its anonymous volumes.
+async fn remove_if_auto(
+    shim: &(dyn Shim + Send + Sync),
+    snapshotter: &OverlaySnapshotter,
+    volumes: &VolumeStore,
+    events: &EventBus,
+    container_id: &str,
+) {
+    let Ok(info) = shim.get(container_id).await else {
+        return;
+    };
+    if !info.auto_remove || info.state != ross_shim::ContainerState::Stopped {
+        return;
+    }
+    tracing::info!(container_id = %container_id, "Removing exited auto-remove container");
+    if let Err(e) = remove_container(
+        shim,
+        snapshotter,
+        volumes,
+        events,
+        container_id,
+        false,
+        true,
+    )
+    .await
+    {
+        tracing::warn!(container_id = %container_id, "Failed to remove container: {}", e);
+    }
+}
+
+/// Remove the container once it exits, unless it is being restarted. Does
+/// nothing for containers without auto-remove. At most one watcher runs per
+/// container.
+fn spawn_auto_remover(
+    shim: Arc<dyn Shim + Send + Sync>,
+    snapshotter: Arc<OverlaySnapshotter>,
+    volumes: Arc<VolumeStore>,
+    events: Arc<EventBus>,
+    auto_removal: Arc<std::sync::Mutex<AutoRemoval>>,
+    container_id: String,
+) {
+    if !auto_removal
+        .lock()
+        .unwrap()
+        .watched
+        .insert(container_id.clone())
+    {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let auto_remove = shim
+            .get(&container_id)
+            .await
+            .is_ok_and(|info| info.auto_remove);
+
+        if auto_remove {
+            loop {
+                if shim.wait(&container_id).await.is_err() {
+                    break;
+                }
+                if auto_removal
+                    .lock()
+                    .unwrap()
+                    .restarting
+                    .contains(&container_id)
+                {
+                    tokio::time::sleep(EXIT_POLL_INTERVAL).await;
+                    continue;
+                }
+                match shim.get(&container_id).await {
+                    Ok(info) if info.state == ross_shim::ContainerState::Stopped => {
+                        remove_if_auto(
+                            shim.as_ref(),
+                            &snapshotter,
+                            &volumes,
+                            &events,
+                            &container_id,
+                        )
+                        .await;
+                        break;
+                    }
+                    // Started again by someone else in the meantime; keep watching.
+                    Ok(_) => continue,
+                    Err(_) => break,
+                }
+            }
         }
-    } else {
-        // Simple name like "nginx" -> "library/nginx"
-        format!("library/{}", name_part)
+
+        auto_removal.lock().unwrap().watched.remove(&container_id);
+    });
+}
+
+/// Publish a lifecycle event for the container, adding its name and image to
+/// `attributes`.
+async fn publish_event(
+    shim: &(dyn Shim + Send + Sync),
+    events: &EventBus,
+    container_id: &str,
+    action: &str,
+    mut attributes: HashMap<String, String>,
+) {
+    if let Ok(info) = shim.get(container_id).await {
+        attributes.extend(container_attributes(&info));
+    }
+    events.publish(container_id, action, attributes);
+}
+
+fn container_attributes(info: &ross_shim::ContainerInfo) -> HashMap<String, String> {
+    let mut attributes = HashMap::from([("image".to_string(), info.image.clone())]);
+    if let Some(name) = &info.name {
+        attributes.insert("name".to_string(), name.clone());
+    }
+    attributes
+}
+
+/// Publish a `die` event for every container run that exited since the last
+/// check, or only take note of them when `publish` is false.
+async fn report_exits(
+    shim: &(dyn Shim + Send + Sync),
+    events: &EventBus,
+    publish: bool,
+) -> Result<(), ContainerError> {
+    for info in shim.list().await? {
+        if info.state != ross_shim::ContainerState::Stopped || info.finished_at.is_none() {
+            continue;
+        }
+        let run = (info.started_at, info.finished_at, info.restart_count);
+        if !events.record_exit(&info.id, run) || !publish {
+            continue;
+        }
+
+        let mut attributes = container_attributes(&info);
+        attributes.insert(
+            "exitCode".to_string(),
+            info.exit_code.unwrap_or_default().to_string(),
+        );
+        if info.oom_killed {
+            attributes.insert("oomKilled".to_string(), "true".to_string());
+        }
+        events.publish(&info.id, events::ACTION_DIE, attributes);
+    }
+    Ok(())
+}
+
+/// Stops the running and paused `containers` at once, each given
+/// `stop_timeout` seconds to exit on its stop signal before it is killed.
+/// Returns those stopped, with whether they had to be killed.
+async fn stop_for_shutdown(
+    shim: &(dyn Shim + Send + Sync),
+    containers: Vec<ross_shim::ContainerInfo>,
+    stop_timeout: u32,
+) -> Vec<(ross_shim::ContainerInfo, bool)> {
+    let stops = containers
+        .into_iter()
+        .filter(|c| {
+            matches!(
+                c.state,
+                ross_shim::ContainerState::Running | ross_shim::ContainerState::Paused
+            )
+        })
+        .map(|c| async move {
+            tracing::info!(container_id = %c.id, "Stopping container for shutdown");
+            if c.state == ross_shim::ContainerState::Paused
+                && let Err(e) = shim.resume(&c.id).await
+            {
+                tracing::warn!(container_id = %c.id, "Failed to unpause container: {}", e);
+            }
+            // Not a stop by the user: `unless-stopped` containers start again
+            // with the daemon.
+            match shim.stop(&c.id, stop_timeout, false).await {
+                Ok(killed) => Some((c, killed)),
+                Err(e) => {
+                    tracing::warn!(container_id = %c.id, "Failed to stop container: {}", e);
+                    None
+                }
+            }
+        });
+    futures::future::join_all(stops)
+        .await
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+/// Watch for container exits, however the container was run, for as long as
+/// the daemon runs.
+fn spawn_exit_monitor(shim: Arc<dyn Shim + Send + Sync>, events: Arc<EventBus>) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(EXIT_POLL_INTERVAL).await;
+            if let Err(e) = report_exits(shim.as_ref(), &events, true).await {
+                tracing::warn!("Failed to check for container exits: {}", e);
+            }
+        }
+    });
+}
+
+/// Probe the container's health on its interval until the container is
+/// removed. At most one monitor runs per container; its health is reset
+/// whenever the container starts again.
+fn spawn_health_monitor(
+    shim: Arc<dyn Shim + Send + Sync>,
+    health: Arc<std::sync::Mutex<HashMap<String, Health>>>,
+    container_id: String,
+) {
+    tokio::spawn(async move {
+        let Some(check) = shim
+            .get(&container_id)
+            .await
+            .ok()
+            .and_then(|info| info.healthcheck)
+        else {
+            return;
+        };
+        {
+            let mut health = health.lock().unwrap();
+            if health.contains_key(&container_id) {
+                return;
+            }
+            health.insert(
+                container_id.clone(),
+                Health {
+                    status: HEALTH_STARTING.to_string(),
+                    ..Default::default()
+                },
+            );
+        }
+
+        let mut started_at = None;
+        loop {
+            tokio::time::sleep(check.interval).await;
+
+            let Ok(info) = shim.get(&container_id).await else {
+                break;
+            };
+            if info.state != ross_shim::ContainerState::Running {
+                continue;
+            }
+            if info.started_at != started_at {
+                started_at = info.started_at;
+                if let Some(state) = health.lock().unwrap().get_mut(&container_id) {
+                    *state = Health {
+                        status: HEALTH_STARTING.to_string(),
+                        ..Default::default()
+                    };
+                }
+            }
+
+            let start = now_timestamp();
+            let (exit_code, output) = run_health_probe(shim.as_ref(), &container_id, &check).await;
+            let end = now_timestamp();
+
+            let running_for = end.seconds - started_at.unwrap_or_default();
+            let in_start_period = running_for < check.start_period.as_secs() as i64;
+
+            let mut states = health.lock().unwrap();
+            let Some(state) = states.get_mut(&container_id) else {
+                break;
+            };
+            let previous = state.status.clone();
+            record_health_probe(
+                state,
+                HealthLog {
+                    start: Some(start),
+                    end: Some(end),
+                    exit_code,
+                    output,
+                },
+                check.retries,
+                in_start_period,
+            );
+            if state.status != previous {
+                tracing::info!(container_id = %container_id, status = %state.status, "Container health changed");
+            }
+        }
+
+        health.lock().unwrap().remove(&container_id);
+    });
+}
+
+/// Run one healthcheck probe, returning its exit code and (truncated) output.
+async fn run_health_probe(
+    shim: &(dyn Shim + Send + Sync),
+    container_id: &str,
+    check: &ross_shim::HealthCheck,
+) -> (i32, String) {
+    use futures::StreamExt;
+
+    // The shim kills a probe running past its timeout; dropping the exec
+    // would leave it running in the container.
+    let opts = ross_shim::ExecOpts {
+        cmd: check.cmd.clone(),
+        timeout: Some(check.timeout),
+        ..Default::default()
+    };
+    let mut events = shim.exec(container_id.to_string(), opts);
+    let mut output = Vec::new();
+
+    let mut exit_code = -1;
+    while let Some(event) = events.next().await {
+        match event {
+            Ok(ross_shim::OutputEvent::Stdout(data) | ross_shim::OutputEvent::Stderr(data)) => {
+                output.extend_from_slice(&data)
+            }
+            Ok(ross_shim::OutputEvent::Exit(r)) => {
+                exit_code = r.exit_code;
+                break;
+            }
+            Err(ross_shim::ShimError::ExecTimedOut(timeout)) => {
+                return (-1, format!("Health check exceeded timeout ({:?})", timeout));
+            }
+            Err(e) => {
+                output = e.to_string().into_bytes();
+                break;
+            }
+        }
+    }
+
+    output.truncate(HEALTH_OUTPUT_MAX);
+    (exit_code, String::from_utf8_lossy(&output).into_owned())
+}
+
+/// Fold one probe result into the container's health. Failures during the
+/// start period only count once a probe has succeeded.
+fn record_health_probe(health: &mut Health, log: HealthLog, retries: u32, in_start_period: bool) {
+    if log.exit_code == 0 {
+        health.status = HEALTH_HEALTHY.to_string();
+        health.failing_streak = 0;
+    } else if !(in_start_period && health.status == HEALTH_STARTING) {
+        health.failing_streak += 1;
+        if health.failing_streak as u32 >= retries {
+            health.status = HEALTH_UNHEALTHY.to_string();
+        }
+    }
+
+    health.log.push(log);
+    if health.log.len() > HEALTH_LOG_LEN {
+        health.log.remove(0);
+    }
+}
+
+/// Validate a healthcheck and resolve its command; `None` when disabled.
+fn health_check(config: &HealthConfig) -> Result<Option<ross_shim::HealthCheck>, ContainerError> {
+    let cmd = match config.test.split_first() {
+        None => return Ok(None),
+        Some((kind, _)) if kind == "NONE" => return Ok(None),
+        Some((kind, args)) if kind == "CMD" => args.to_vec(),
+        Some((kind, args)) if kind == "CMD-SHELL" => {
+            vec!["/bin/sh".to_string(), "-c".to_string(), args.join(" ")]
+        }
+        Some((kind, _)) => {
+            return Err(ContainerError::InvalidArgument(format!(
+                "unknown healthcheck type: {}",
+                kind
+            )));
+        }
+    };
+    if cmd.is_empty() || cmd.last().is_some_and(|c| c.is_empty()) {
+        return Err(ContainerError::InvalidArgument(
+            "healthcheck command must not be empty".to_string(),
+        ));
+    }
+
+    let duration = |name: &str, nanos: i64, default: Duration| match nanos {
+        0 => Ok(default),
+        n if n > 0 => Ok(Duration::from_nanos(n as u64)),
+        n => Err(ContainerError::InvalidArgument(format!(
+            "invalid healthcheck {}: {}",
+            name, n
+        ))),
+    };
+    let retries = match config.retries {
+        0 => HEALTH_DEFAULT_RETRIES,
+        n if n > 0 => n as u32,
+        n => {
+            return Err(ContainerError::InvalidArgument(format!(
+                "invalid healthcheck retries: {}",
+                n
+            )));
+        }
+    };
+
+    Ok(Some(ross_shim::HealthCheck {
+        cmd,
+        interval: duration("interval", config.interval, HEALTH_DEFAULT_INTERVAL)?,
+        timeout: duration("timeout", config.timeout, HEALTH_DEFAULT_TIMEOUT)?,
+        start_period: duration("start period", config.start_period, Duration::ZERO)?,
+        retries,
+    }))
+}
+
+/// When `create` pulls the container's image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PullPolicy {
+    Always,
+    Missing,
+    Never,
+}
+
+fn pull_policy(policy: &str) -> Result<PullPolicy, ContainerError> {
+    match policy {
+        "" | "missing" => Ok(PullPolicy::Missing),
+        "always" => Ok(PullPolicy::Always),
+        "never" => Ok(PullPolicy::Never),
+        policy => Err(ContainerError::InvalidArgument(format!(
+            "invalid pull policy: {}",
+            policy
+        ))),
+    }
+}
+
+fn restart_policy(policy: &RestartPolicy) -> Result<ross_shim::RestartPolicy, ContainerError> {
+    if policy.maximum_retry_count < 0 {
+        return Err(ContainerError::InvalidArgument(format!(
+            "invalid maximum retry count: {}",
+            policy.maximum_retry_count
+        )));
+    }
+    if policy.maximum_retry_count > 0 && policy.name != "on-failure" {
+        return Err(ContainerError::InvalidArgument(format!(
+            "maximum retry count cannot be used with restart policy '{}'",
+            policy.name
+        )));
+    }
+
+    match policy.name.as_str() {
+        "" | "no" => Ok(ross_shim::RestartPolicy::No),
+        "always" => Ok(ross_shim::RestartPolicy::Always),
+        "unless-stopped" => Ok(ross_shim::RestartPolicy::UnlessStopped),
+        "on-failure" => Ok(ross_shim::RestartPolicy::OnFailure {
+            max_retries: policy.maximum_retry_count as u32,
+        }),
+        name => Err(ContainerError::InvalidArgument(format!(
+            "invalid restart policy: {}",
+            name
+        ))),
+    }
+}
+
+fn restart_policy_from_shim(policy: ross_shim::RestartPolicy) -> RestartPolicy {
+    let (name, maximum_retry_count) = match policy {
+        ross_shim::RestartPolicy::No => ("no", 0),
+        ross_shim::RestartPolicy::Always => ("always", 0),
+        ross_shim::RestartPolicy::UnlessStopped => ("unless-stopped", 0),
+        ross_shim::RestartPolicy::OnFailure { max_retries } => ("on-failure", max_retries as i32),
+    };
+    RestartPolicy {
+        name: name.to_string(),
+        maximum_retry_count,
+    }
+}
+
+/// Default CFS period used when only `cpus` is given, matching Docker.
+const DEFAULT_CPU_PERIOD: u64 = 100_000;
+
+/// Resolves the CFS quota and period, deriving the quota from `cpus` when no
+/// explicit quota is set.
+fn cpu_limits(host_config: &HostConfig) -> Result<(Option<i64>, Option<u64>), ContainerError> {
+    if let Some(quota) = host_config.cpu_quota {
+        if quota <= 0 {
+            return Err(ContainerError::InvalidArgument(format!(
+                "invalid cpu quota: {}",
+                quota
+            )));
+        }
+        return Ok((Some(quota), host_config.cpu_period));
+    }
+
+    match host_config.cpus {
+        Some(cpus) if !cpus.is_finite() || cpus <= 0.0 => Err(ContainerError::InvalidArgument(
+            format!("invalid cpus value: {}", cpus),
+        )),
+        Some(cpus) => {
+            let period = host_config.cpu_period.unwrap_or(DEFAULT_CPU_PERIOD);
+            // The kernel rejects CFS quotas below 1ms.
+            let quota = (cpus * period as f64).round() as i64;
+            Ok((Some(quota.max(1000)), Some(period)))
+        }
+        None => Ok((None, host_config.cpu_period)),
+    }
+}
+
+/// Converts a published port, accepting "80", "80/udp" or a separate protocol.
+/// The limits `params` change, refusing an update that changes none.
+fn resource_update(params: &UpdateParams) -> Result<ross_shim::ResourceLimits, ContainerError> {
+    let (cpu_quota, cpu_period) = cpu_limits(&HostConfig {
+        cpus: params.cpus,
+        cpu_quota: params.cpu_quota,
+        cpu_period: params.cpu_period,
+        ..Default::default()
+    })?;
+    let update = ross_shim::ResourceLimits {
+        memory_limit: params.memory_limit,
+        cpu_quota,
+        cpu_period,
+    };
+    if update == ross_shim::ResourceLimits::default() {
+        return Err(ContainerError::InvalidArgument(
+            "no resource limit to update".to_string(),
+        ));
+    }
+    Ok(update)
+}
+
+fn port_mapping(binding: &PortBinding) -> Result<ross_shim::PortMapping, ContainerError> {
+    let (container_port, suffix) = match binding.container_port.split_once('/') {
+        Some((port, proto)) => (port, Some(proto)),
+        None => (binding.container_port.as_str(), None),
+    };
+    let protocol = suffix
+        .or_else(|| Some(binding.protocol.as_str()).filter(|p| !p.is_empty()))
+        .unwrap_or("tcp")
+        .to_lowercase();
+    if protocol != "tcp" && protocol != "udp" {
+        return Err(ContainerError::InvalidArgument(format!(
+            "invalid port protocol: {}",
+            protocol
+        )));
+    }
+
+    let parse = |port: &str| {
+        port.trim()
+            .parse::<u16>()
+            .ok()
+            .filter(|p| *p != 0)
+            .ok_or_else(|| ContainerError::InvalidArgument(format!("invalid port: {}", port)))
     };
 
-    (repository, tag.to_string())
+    Ok(ross_shim::PortMapping {
+        host_ip: non_empty(&binding.host_ip),
+        host_port: parse(&binding.host_port)?,
+        container_port: parse(container_port)?,
+        protocol,
+    })
 }
 
 fn parse_signal(signal: &str) -> u32 {
@@ -769,3 +2700,549 @@ fn parse_signal(signal: &str) -> u32 {
         _ => signal.parse().unwrap_or(15),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ross_shim::ShimError;
+    use tokio::sync::mpsc;
+
+    fn container(id: &str, name: Option<&str>) -> ross_shim::ContainerInfo {
+        ross_shim::ContainerInfo {
+            id: id.to_string(),
+            name: name.map(|n| n.to_string()),
+            image: "alpine".to_string(),
+            state: ross_shim::ContainerState::Created,
+            pid: None,
+            exit_code: None,
+            created_at: 0,
+            started_at: None,
+            finished_at: None,
+            created_nanos: 0,
+            started_nanos: 0,
+            finished_nanos: 0,
+            bundle_path: String::new(),
+            rootfs_path: String::new(),
+            restart_policy: ross_shim::RestartPolicy::No,
+            restart_count: 0,
+            manually_stopped: false,
+            healthcheck: None,
+            image_id: String::new(),
+            command: vec![],
+            network_mode: String::new(),
+            oom_killed: false,
+            auto_remove: false,
+            resources: Default::default(),
+            labels: HashMap::new(),
+        }
+    }
+
+    /// A shim that only stops and starts containers, as shutdown and daemon
+    /// start do: it records the calls it gets, keeps the state of the
+    /// `containers` it is given and has to kill the `stubborn` ones.
+    #[derive(Default)]
+    struct StopShim {
+        stubborn: HashSet<String>,
+        containers: std::sync::Mutex<HashMap<String, ross_shim::ContainerInfo>>,
+        calls: std::sync::Mutex<Vec<(&'static str, String, u32)>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Shim for StopShim {
+        async fn create(&self, _: CreateContainerOpts) -> Result<String, ShimError> {
+            unimplemented!()
+        }
+        async fn start(&self, id: &str) -> Result<(), ShimError> {
+            let mut calls = self.calls.lock().unwrap();
+            calls.push(("start", id.to_string(), 0));
+            if let Some(info) = self.containers.lock().unwrap().get_mut(id) {
+                info.state = ross_shim::ContainerState::Running;
+                info.manually_stopped = false;
+            }
+            Ok(())
+        }
+        async fn stop(&self, id: &str, timeout: u32, manual: bool) -> Result<bool, ShimError> {
+            let mut calls = self.calls.lock().unwrap();
+            calls.push(("stop", id.to_string(), timeout));
+            if let Some(info) = self.containers.lock().unwrap().get_mut(id) {
+                info.state = ross_shim::ContainerState::Stopped;
+                info.manually_stopped |= manual;
+            }
+            Ok(self.stubborn.contains(id))
+        }
+        async fn kill(&self, _: &str, _: u32) -> Result<(), ShimError> {
+            unimplemented!()
+        }
+        async fn delete(&self, _: &str, _: bool) -> Result<(), ShimError> {
+            unimplemented!()
+        }
+        async fn pause(&self, _: &str) -> Result<(), ShimError> {
+            unimplemented!()
+        }
+        async fn resume(&self, id: &str) -> Result<(), ShimError> {
+            let mut calls = self.calls.lock().unwrap();
+            calls.push(("resume", id.to_string(), 0));
+            Ok(())
+        }
+        async fn rename(&self, _: &str, _: &str) -> Result<(), ShimError> {
+            unimplemented!()
+        }
+        async fn update(&self, _: &str, _: ross_shim::ResourceLimits) -> Result<(), ShimError> {
+            unimplemented!()
+        }
+        async fn list(&self) -> Result<Vec<ross_shim::ContainerInfo>, ShimError> {
+            Ok(self.containers.lock().unwrap().values().cloned().collect())
+        }
+        async fn get(&self, id: &str) -> Result<ross_shim::ContainerInfo, ShimError> {
+            let containers = self.containers.lock().unwrap();
+            containers
+                .get(id)
+                .cloned()
+                .ok_or_else(|| ShimError::ContainerNotFound(id.to_string()))
+        }
+        async fn wait(&self, _: &str) -> Result<ross_shim::WaitResult, ShimError> {
+            std::future::pending().await
+        }
+        async fn record_restart(&self, _: &str) -> Result<u32, ShimError> {
+            unimplemented!()
+        }
+        fn run_streaming(&self, _: String) -> ross_shim::OutputEventStream {
+            unimplemented!()
+        }
+        fn exec(&self, _: String, _: ross_shim::ExecOpts) -> ross_shim::OutputEventStream {
+            unimplemented!()
+        }
+        async fn run_interactive(
+            &self,
+            _: String,
+            _: mpsc::Receiver<ross_shim::InputEvent>,
+            _: mpsc::Sender<ross_shim::OutputEvent>,
+        ) -> Result<(), ShimError> {
+            unimplemented!()
+        }
+        async fn attach(&self, _: &str) -> Result<ross_shim::AttachStreams, ShimError> {
+            unimplemented!()
+        }
+        async fn network_stats(
+            &self,
+            _: &str,
+        ) -> Result<HashMap<String, ross_shim::InterfaceStats>, ShimError> {
+            unimplemented!()
+        }
+        async fn processes(&self, _: &str) -> Result<Vec<u32>, ShimError> {
+            unimplemented!()
+        }
+        async fn shutdown(&self) -> Result<(), ShimError> {
+            Ok(())
+        }
+    }
+
+    async fn container_service(shim: Arc<StopShim>, dir: &Path) -> ContainerService {
+        let store = Arc::new(FileSystemStore::new(dir.join("store")).await.unwrap());
+        let snapshotter = Arc::new(
+            OverlaySnapshotter::new(dir.join("snapshots"), store.clone())
+                .await
+                .unwrap(),
+        );
+        ContainerService {
+            shim,
+            execs: Arc::default(),
+            supervised: Arc::default(),
+            health: Arc::default(),
+            auto_removal: Arc::default(),
+            volumes: Arc::new(VolumeStore::new(&dir.join("volumes")).unwrap()),
+            images: Arc::new(ImageService::new(store.clone(), snapshotter.clone(), 3, 1)),
+            snapshotter,
+            store,
+            events: Arc::new(EventBus::new()),
+            shutdown: CancellationToken::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stop_for_shutdown() {
+        let with_state = |id, state| ross_shim::ContainerInfo {
+            state,
+            ..container(id, None)
+        };
+        let shim = StopShim {
+            stubborn: HashSet::from(["stubborn".to_string()]),
+            ..Default::default()
+        };
+        let containers = vec![
+            with_state("running", ross_shim::ContainerState::Running),
+            with_state("paused", ross_shim::ContainerState::Paused),
+            with_state("stubborn", ross_shim::ContainerState::Running),
+            with_state("stopped", ross_shim::ContainerState::Stopped),
+            with_state("created", ross_shim::ContainerState::Created),
+        ];
+
+        let stopped: Vec<(String, bool)> = stop_for_shutdown(&shim, containers, 7)
+            .await
+            .into_iter()
+            .map(|(c, killed)| (c.id, killed))
+            .collect();
+        assert_eq!(
+            stopped,
+            vec![
+                ("running".to_string(), false),
+                ("paused".to_string(), false),
+                ("stubborn".to_string(), true),
+            ]
+        );
+
+        // Paused containers are resumed first, so they can take the signal,
+        // and every stop gets the timeout as given.
+        let mut calls = shim.calls.into_inner().unwrap();
+        calls.sort();
+        assert_eq!(
+            calls,
+            vec![
+                ("resume", "paused".to_string(), 0),
+                ("stop", "paused".to_string(), 7),
+                ("stop", "running".to_string(), 7),
+                ("stop", "stubborn".to_string(), 7),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_leaves_unless_stopped_containers_to_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let unless_stopped = |id, state, manually_stopped| ross_shim::ContainerInfo {
+            state,
+            manually_stopped,
+            restart_policy: ross_shim::RestartPolicy::UnlessStopped,
+            ..container(id, None)
+        };
+        let shim = Arc::new(StopShim {
+            containers: std::sync::Mutex::new(HashMap::from([
+                (
+                    "running".to_string(),
+                    unless_stopped("running", ross_shim::ContainerState::Running, false),
+                ),
+                (
+                    "stopped".to_string(),
+                    unless_stopped("stopped", ross_shim::ContainerState::Stopped, true),
+                ),
+            ])),
+            ..Default::default()
+        });
+
+        container_service(shim.clone(), dir.path())
+            .await
+            .shutdown(false, 1)
+            .await;
+        // The next daemon finds the containers as the shim kept them.
+        container_service(shim.clone(), dir.path())
+            .await
+            .resume_restart_policies()
+            .await
+            .unwrap();
+
+        let calls = shim.calls.lock().unwrap();
+        let started: Vec<&str> = calls
+            .iter()
+            .filter(|(call, _, _)| *call == "start")
+            .map(|(_, id, _)| id.as_str())
+            .collect();
+        assert_eq!(started, vec!["running"]);
+    }
+
+    #[test]
+    fn test_resolve_reference() {
+        let containers = vec![
+            container("a1b2c3d4-0000", Some("web")),
+            container("a1b2ffff-1111", Some("db")),
+            container("web", None),
+        ];
+
+        // Exact id wins over a container named the same
+        assert_eq!(resolve_reference(&containers, "web").unwrap(), "web");
+        assert_eq!(
+            resolve_reference(&containers, "db").unwrap(),
+            "a1b2ffff-1111"
+        );
+        assert_eq!(
+            resolve_reference(&containers, "/db").unwrap(),
+            "a1b2ffff-1111"
+        );
+        assert_eq!(
+            resolve_reference(&containers, "a1b2c").unwrap(),
+            "a1b2c3d4-0000"
+        );
+
+        assert!(matches!(
+            resolve_reference(&containers, "a1b2"),
+            Err(ContainerError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            resolve_reference(&containers, "zzz"),
+            Err(ContainerError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_port_mapping() {
+        let binding = |host: &str, container: &str, protocol: &str| PortBinding {
+            host_ip: String::new(),
+            host_port: host.to_string(),
+            container_port: container.to_string(),
+            protocol: protocol.to_string(),
+        };
+
+        let mapping = port_mapping(&binding("8080", "80", "")).unwrap();
+        assert_eq!(mapping.host_port, 8080);
+        assert_eq!(mapping.container_port, 80);
+        assert_eq!(mapping.protocol, "tcp");
+        assert_eq!(mapping.host_ip, None);
+
+        let mapping = port_mapping(&binding("5353", "53/udp", "tcp")).unwrap();
+        assert_eq!(mapping.container_port, 53);
+        assert_eq!(mapping.protocol, "udp");
+
+        assert!(port_mapping(&binding("x", "80", "tcp")).is_err());
+        assert!(port_mapping(&binding("80", "80", "sctp")).is_err());
+    }
+
+    #[test]
+    fn test_cpu_limits() {
+        let config = |cpus, cpu_quota, cpu_period| HostConfig {
+            cpus,
+            cpu_quota,
+            cpu_period,
+            ..Default::default()
+        };
+
+        assert_eq!(cpu_limits(&config(None, None, None)).unwrap(), (None, None));
+        assert_eq!(
+            cpu_limits(&config(Some(1.5), None, None)).unwrap(),
+            (Some(150_000), Some(100_000))
+        );
+        assert_eq!(
+            cpu_limits(&config(Some(0.5), None, Some(50_000))).unwrap(),
+            (Some(25_000), Some(50_000))
+        );
+        assert_eq!(
+            cpu_limits(&config(Some(2.0), Some(10_000), None)).unwrap(),
+            (Some(10_000), None)
+        );
+        assert!(cpu_limits(&config(Some(0.0), None, None)).is_err());
+        assert!(cpu_limits(&config(None, Some(-1), None)).is_err());
+    }
+
+    #[test]
+    fn test_resource_update() {
+        let params = |memory_limit, cpus| UpdateParams {
+            memory_limit,
+            cpu_quota: None,
+            cpu_period: None,
+            cpus,
+        };
+
+        assert_eq!(
+            resource_update(&params(Some(64 << 20), None)).unwrap(),
+            ross_shim::ResourceLimits {
+                memory_limit: Some(64 << 20),
+                cpu_quota: None,
+                cpu_period: None,
+            }
+        );
+        assert_eq!(
+            resource_update(&params(None, Some(0.5))).unwrap(),
+            ross_shim::ResourceLimits {
+                memory_limit: None,
+                cpu_quota: Some(50_000),
+                cpu_period: Some(100_000),
+            }
+        );
+        assert!(matches!(
+            resource_update(&params(None, None)),
+            Err(ContainerError::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn test_restart_policy() {
+        let policy = |name: &str, maximum_retry_count| RestartPolicy {
+            name: name.to_string(),
+            maximum_retry_count,
+        };
+
+        assert_eq!(
+            restart_policy(&policy("", 0)).unwrap(),
+            ross_shim::RestartPolicy::No
+        );
+        assert_eq!(
+            restart_policy(&policy("unless-stopped", 0)).unwrap(),
+            ross_shim::RestartPolicy::UnlessStopped
+        );
+        assert_eq!(
+            restart_policy(&policy("on-failure", 3)).unwrap(),
+            ross_shim::RestartPolicy::OnFailure { max_retries: 3 }
+        );
+        assert!(restart_policy(&policy("always", 3)).is_err());
+        assert!(restart_policy(&policy("sometimes", 0)).is_err());
+
+        let on_failure = ross_shim::RestartPolicy::OnFailure { max_retries: 3 };
+        assert!(on_failure.should_restart(1, 2, false));
+        assert!(!on_failure.should_restart(1, 3, false));
+        assert!(!on_failure.should_restart(0, 0, false));
+        assert!(!ross_shim::RestartPolicy::Always.should_restart(0, 0, true));
+        assert!(!ross_shim::RestartPolicy::UnlessStopped.restart_on_daemon_start(true));
+        assert!(ross_shim::RestartPolicy::Always.restart_on_daemon_start(true));
+    }
+
+    #[test]
+    fn test_pull_policy() {
+        assert_eq!(pull_policy("").unwrap(), PullPolicy::Missing);
+        assert_eq!(pull_policy("missing").unwrap(), PullPolicy::Missing);
+        assert_eq!(pull_policy("always").unwrap(), PullPolicy::Always);
+        assert_eq!(pull_policy("never").unwrap(), PullPolicy::Never);
+        assert!(matches!(
+            pull_policy("sometimes"),
+            Err(ContainerError::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn test_health_check() {
+        let config = |test: &[&str]| HealthConfig {
+            test: test.iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
+        };
+
+        assert!(health_check(&config(&[])).unwrap().is_none());
+        assert!(health_check(&config(&["NONE"])).unwrap().is_none());
+
+        let check = health_check(&config(&["CMD-SHELL", "curl -f localhost"]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(check.cmd, vec!["/bin/sh", "-c", "curl -f localhost"]);
+        assert_eq!(check.interval, HEALTH_DEFAULT_INTERVAL);
+        assert_eq!(check.retries, HEALTH_DEFAULT_RETRIES);
+
+        let check = health_check(&HealthConfig {
+            interval: 5_000_000_000,
+            retries: 1,
+            ..config(&["CMD", "true"])
+        })
+        .unwrap()
+        .unwrap();
+        assert_eq!(check.cmd, vec!["true"]);
+        assert_eq!(check.interval, Duration::from_secs(5));
+        assert_eq!(check.retries, 1);
+
+        assert!(health_check(&config(&["CMD"])).is_err());
+        assert!(health_check(&config(&["SHELL", "true"])).is_err());
+        assert!(
+            health_check(&HealthConfig {
+                timeout: -1,
+                ..config(&["CMD", "true"])
+            })
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_record_health_probe() {
+        let probe = |exit_code| HealthLog {
+            exit_code,
+            ..Default::default()
+        };
+        let mut health = Health {
+            status: HEALTH_STARTING.to_string(),
+            ..Default::default()
+        };
+
+        // Failures during the start period are not counted.
+        record_health_probe(&mut health, probe(1), 2, true);
+        assert_eq!(health.status, HEALTH_STARTING);
+        assert_eq!(health.failing_streak, 0);
+
+        record_health_probe(&mut health, probe(0), 2, true);
+        assert_eq!(health.status, HEALTH_HEALTHY);
+
+        record_health_probe(&mut health, probe(1), 2, true);
+        assert_eq!(health.status, HEALTH_HEALTHY);
+        assert_eq!(health.failing_streak, 1);
+        record_health_probe(&mut health, probe(1), 2, false);
+        assert_eq!(health.status, HEALTH_UNHEALTHY);
+        assert_eq!(health.failing_streak, 2);
+
+        for _ in 0..HEALTH_LOG_LEN {
+            record_health_probe(&mut health, probe(0), 2, false);
+        }
+        assert_eq!(health.status, HEALTH_HEALTHY);
+        assert_eq!(health.failing_streak, 0);
+        assert_eq!(health.log.len(), HEALTH_LOG_LEN);
+    }
+
+    #[test]
+    fn test_container_usage() {
+        let with_state = |id, state| ross_shim::ContainerInfo {
+            state,
+            ..container(id, None)
+        };
+        let containers = vec![
+            with_state("running", ross_shim::ContainerState::Running),
+            with_state("paused", ross_shim::ContainerState::Paused),
+            with_state("stopped", ross_shim::ContainerState::Stopped),
+            with_state("created", ross_shim::ContainerState::Created),
+        ];
+        let sizes = HashMap::from([
+            ("running".to_string(), 100),
+            ("paused".to_string(), 20),
+            ("stopped".to_string(), 3),
+            ("gone".to_string(), 1000),
+        ]);
+
+        // A container without a snapshot takes no space, and the snapshot
+        // of one that is gone is not counted.
+        assert_eq!(
+            container_usage(&containers, &sizes),
+            DiskUsage {
+                count: 4,
+                active: 2,
+                size: 123,
+                reclaimable: 3,
+            }
+        );
+        assert_eq!(container_usage(&[], &sizes), DiskUsage::default());
+    }
+
+    #[test]
+    fn test_merge_command() {
+        let strings = |values: &[&str]| values.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+        let merge = |entrypoint: Option<&[&str]>, cmd: Option<&[&str]>| {
+            merge_command(
+                entrypoint.map(strings),
+                cmd.map(strings),
+                strings(&["/entrypoint.sh"]),
+                strings(&["serve"]),
+            )
+        };
+
+        assert_eq!(
+            merge(None, None),
+            (strings(&["/entrypoint.sh"]), strings(&["serve"]))
+        );
+        assert_eq!(
+            merge(None, Some(&["debug"])),
+            (strings(&["/entrypoint.sh"]), strings(&["debug"]))
+        );
+        // An empty command set by the user clears the image's.
+        assert_eq!(
+            merge(None, Some(&[])),
+            (strings(&["/entrypoint.sh"]), vec![])
+        );
+        // A new entrypoint does not get the image's command.
+        assert_eq!(merge(Some(&["sh"]), None), (strings(&["sh"]), vec![]));
+        assert_eq!(
+            merge(Some(&["sh"]), Some(&["-c", "env"])),
+            (strings(&["sh"]), strings(&["-c", "env"]))
+        );
+        // A cleared entrypoint runs the image's command by itself.
+        assert_eq!(merge(Some(&[]), None), (vec![], strings(&["serve"])));
+    }
+}
@@ -0,0 +1,196 @@
+use crate::error::ContainerError;
+use crate::types::{CpuStats, CpuUsage, MemoryStats, PidsStats, now_timestamp};
+use prost_types::Timestamp;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// One reading of a container's cgroup counters.
+pub(crate) struct Sample {
+    pub read: Timestamp,
+    pub cpu: CpuStats,
+    pub memory: MemoryStats,
+    pub pids: PidsStats,
+}
+
+/// Reads the cgroup v2 counters of the cgroup `pid` belongs to.
+pub(crate) async fn sample(pid: u32) -> Result<Sample, ContainerError> {
+    let cgroup = cgroup_path(pid).await?;
+
+    let cpu_stat = parse_flat_keyed(&read_cgroup_file(&cgroup, "cpu.stat").await?);
+    let system_cpu_usage = fs::read_to_string("/proc/stat")
+        .await
+        .ok()
+        .and_then(|c| parse_proc_stat(&c))
+        .map(|jiffies| jiffies.saturating_mul(1_000_000_000 / clock_ticks()))
+        .unwrap_or(0);
+
+    let cpu = CpuStats {
+        cpu_usage: Some(CpuUsage {
+            total_usage: cpu_stat.get("usage_usec").copied().unwrap_or(0) * 1000,
+            percpu_usage: vec![],
+            usage_in_kernelmode: cpu_stat.get("system_usec").copied().unwrap_or(0) * 1000,
+            usage_in_usermode: cpu_stat.get("user_usec").copied().unwrap_or(0) * 1000,
+        }),
+        system_cpu_usage,
+        online_cpus: std::thread::available_parallelism()
+            .map(|n| n.get() as u64)
+            .unwrap_or(1),
+    };
+
+    let usage = parse_u64(&read_cgroup_file(&cgroup, "memory.current").await?).unwrap_or(0);
+    let limit = match read_optional(&cgroup, "memory.max")
+        .await
+        .and_then(|c| parse_u64(&c))
+    {
+        Some(limit) => limit,
+        None => host_memory().await.unwrap_or(0),
+    };
+    let memory_events = read_optional(&cgroup, "memory.events")
+        .await
+        .map(|c| parse_flat_keyed(&c))
+        .unwrap_or_default();
+
+    let memory = MemoryStats {
+        usage,
+        max_usage: read_optional(&cgroup, "memory.peak")
+            .await
+            .and_then(|c| parse_u64(&c))
+            .unwrap_or(0),
+        stats: read_optional(&cgroup, "memory.stat")
+            .await
+            .map(|c| parse_flat_keyed(&c))
+            .unwrap_or_default(),
+        failcnt: memory_events.get("max").copied().unwrap_or(0),
+        limit,
+        commit: 0,
+        commit_peak: 0,
+        private_working_set: 0,
+    };
+
+    let pids = PidsStats {
+        current: read_optional(&cgroup, "pids.current")
+            .await
+            .and_then(|c| parse_u64(&c))
+            .unwrap_or(0),
+        limit: read_optional(&cgroup, "pids.max")
+            .await
+            .and_then(|c| parse_u64(&c))
+            .unwrap_or(0),
+    };
+
+    Ok(Sample {
+        read: now_timestamp(),
+        cpu,
+        memory,
+        pids,
+    })
+}
+
+async fn cgroup_path(pid: u32) -> Result<PathBuf, ContainerError> {
+    let content = fs::read_to_string(format!("/proc/{}/cgroup", pid))
+        .await
+        .map_err(|e| ContainerError::NotRunning(format!("process {} is gone: {}", pid, e)))?;
+
+    let relative = parse_cgroup_file(&content).ok_or_else(|| {
+        ContainerError::InvalidArgument(format!("process {} is not in a cgroup v2 hierarchy", pid))
+    })?;
+
+    Ok(Path::new(CGROUP_ROOT).join(relative.trim_start_matches('/')))
+}
+
+async fn read_cgroup_file(cgroup: &Path, name: &str) -> Result<String, ContainerError> {
+    Ok(fs::read_to_string(cgroup.join(name)).await?)
+}
+
+async fn read_optional(cgroup: &Path, name: &str) -> Option<String> {
+    fs::read_to_string(cgroup.join(name)).await.ok()
+}
+
+async fn host_memory() -> Option<u64> {
+    let meminfo = fs::read_to_string("/proc/meminfo").await.ok()?;
+    meminfo
+        .lines()
+        .find_map(|l| l.strip_prefix("MemTotal:"))
+        .and_then(|v| v.trim().trim_end_matches("kB").trim().parse::<u64>().ok())
+        .map(|kb| kb * 1024)
+}
+
+pub(crate) fn clock_ticks() -> u64 {
+    let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    if ticks > 0 { ticks as u64 } else { 100 }
+}
+
+/// Extracts the unified (v2) hierarchy path from `/proc/<pid>/cgroup`.
+fn parse_cgroup_file(content: &str) -> Option<String> {
+    content
+        .lines()
+        .find_map(|l| l.strip_prefix("0::"))
+        .map(|p| p.trim().to_string())
+}
+
+/// Parses a single-value cgroup file; "max" means unlimited.
+fn parse_u64(content: &str) -> Option<u64> {
+    content.trim().parse().ok()
+}
+
+/// Parses "key value" files such as `cpu.stat` and `memory.stat`.
+fn parse_flat_keyed(content: &str) -> HashMap<String, u64> {
+    content
+        .lines()
+        .filter_map(|l| {
+            let (key, value) = l.split_once(' ')?;
+            Some((key.to_string(), value.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+/// Sums the aggregate `cpu` line of `/proc/stat`, in clock ticks.
+fn parse_proc_stat(content: &str) -> Option<u64> {
+    let line = content.lines().find(|l| l.starts_with("cpu "))?;
+    Some(
+        line.split_whitespace()
+            .skip(1)
+            .take(8)
+            .filter_map(|v| v.parse::<u64>().ok())
+            .sum(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cgroup_file() {
+        let content = "0::/user.slice/abc\n";
+        assert_eq!(
+            parse_cgroup_file(content).as_deref(),
+            Some("/user.slice/abc")
+        );
+
+        let v1 = "12:memory:/docker/abc\n11:cpu:/docker/abc\n";
+        assert_eq!(parse_cgroup_file(v1), None);
+    }
+
+    #[test]
+    fn test_parse_limits() {
+        assert_eq!(parse_u64("1048576\n"), Some(1048576));
+        assert_eq!(parse_u64("max\n"), None);
+    }
+
+    #[test]
+    fn test_parse_flat_keyed() {
+        let stats = parse_flat_keyed("usage_usec 1500\nuser_usec 1000\nsystem_usec 500\n");
+        assert_eq!(stats.get("usage_usec"), Some(&1500));
+        assert_eq!(stats.get("system_usec"), Some(&500));
+    }
+
+    #[test]
+    fn test_parse_proc_stat() {
+        let content = "cpu  10 20 30 40 50 60 70 80 90 100\ncpu0 1 2 3 4 5 6 7 8\n";
+        assert_eq!(parse_proc_stat(content), Some(360));
+    }
+}
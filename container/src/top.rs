@@ -0,0 +1,212 @@
+//! The processes of a container as `ps` shows them, read from the host.
+
+use crate::error::ContainerError;
+use crate::stats::clock_ticks;
+use crate::types::TopResult;
+use std::collections::HashSet;
+use tokio::fs;
+
+const TITLES: &[&str] = &["UID", "PID", "PPID", "STAT", "TIME", "CMD"];
+
+/// Lists the processes `pids`. With `ps_args`, the table is the one the
+/// host's `ps` prints for them, as `docker top` does; otherwise it is read
+/// from `/proc`.
+pub(crate) async fn top(pids: &[u32], ps_args: &str) -> Result<TopResult, ContainerError> {
+    if !ps_args.trim().is_empty() {
+        return ps(pids, ps_args).await;
+    }
+
+    let mut processes = Vec::with_capacity(pids.len());
+    for &pid in pids {
+        // Processes may exit while they are listed.
+        if let Some(process) = read_process(pid).await {
+            processes.push(process);
+        }
+    }
+    Ok(TopResult {
+        titles: TITLES.iter().map(|t| t.to_string()).collect(),
+        processes,
+    })
+}
+
+async fn read_process(pid: u32) -> Option<Vec<String>> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid))
+        .await
+        .ok()?;
+    let status = fs::read_to_string(format!("/proc/{}/status", pid))
+        .await
+        .ok()?;
+    let cmdline = fs::read(format!("/proc/{}/cmdline", pid)).await.ok()?;
+
+    let stat = parse_stat(&stat)?;
+    let uid = status
+        .lines()
+        .find_map(|l| l.strip_prefix("Uid:"))
+        .and_then(|ids| ids.split_whitespace().next())
+        .unwrap_or("?");
+    // Kernel threads have no command line; ps shows their name in brackets.
+    let cmd = match parse_cmdline(&cmdline) {
+        cmd if cmd.is_empty() => format!("[{}]", stat.comm),
+        cmd => cmd,
+    };
+
+    Some(vec![
+        uid.to_string(),
+        pid.to_string(),
+        stat.ppid.to_string(),
+        stat.state.to_string(),
+        format_cpu_time(stat.cpu_ticks / clock_ticks()),
+        cmd,
+    ])
+}
+
+/// Runs the host's `ps` with `ps_args` and keeps the lines of `pids`.
+async fn ps(pids: &[u32], ps_args: &str) -> Result<TopResult, ContainerError> {
+    let output = tokio::process::Command::new("ps")
+        .args(ps_args.split_whitespace())
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(ContainerError::InvalidArgument(format!(
+            "ps {}: {}",
+            ps_args,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    parse_ps_output(&String::from_utf8_lossy(&output.stdout), pids)
+}
+
+struct Stat {
+    comm: String,
+    state: char,
+    ppid: u32,
+    /// User and system time, in clock ticks.
+    cpu_ticks: u64,
+}
+
+/// Parses `/proc/<pid>/stat`. The command name is in parentheses and may
+/// contain spaces and parentheses itself, so fields are counted from the last
+/// closing one.
+fn parse_stat(content: &str) -> Option<Stat> {
+    let open = content.find('(')?;
+    let close = content.rfind(')')?;
+    let comm = content.get(open + 1..close)?.to_string();
+    let fields: Vec<&str> = content.get(close + 1..)?.split_whitespace().collect();
+    // Fields from the third on: state, ppid, ..., utime (14th), stime (15th).
+    let state = fields.first()?.chars().next()?;
+    let ppid = fields.get(1)?.parse().ok()?;
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    Some(Stat {
+        comm,
+        state,
+        ppid,
+        cpu_ticks: utime + stime,
+    })
+}
+
+/// Joins the NUL-separated arguments of `/proc/<pid>/cmdline` with spaces.
+fn parse_cmdline(content: &[u8]) -> String {
+    content
+        .split(|b| *b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(String::from_utf8_lossy)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Formats CPU time as `ps` does, `[DD-]HH:MM:SS`.
+fn format_cpu_time(secs: u64) -> String {
+    let (days, secs) = (secs / 86400, secs % 86400);
+    let time = format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60);
+    if days > 0 {
+        format!("{}-{}", days, time)
+    } else {
+        time
+    }
+}
+
+/// Splits `ps` output into its titles and the rows of `pids`, found by the
+/// PID column. The last column, usually the command, keeps its spaces.
+fn parse_ps_output(output: &str, pids: &[u32]) -> Result<TopResult, ContainerError> {
+    let mut lines = output.lines();
+    let titles: Vec<String> = lines
+        .next()
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(str::to_string)
+        .collect();
+    let pid_column = titles.iter().position(|t| t == "PID").ok_or_else(|| {
+        ContainerError::InvalidArgument("couldn't find PID field in ps output".to_string())
+    })?;
+
+    let pids: HashSet<u32> = pids.iter().copied().collect();
+    let mut processes = Vec::new();
+    for line in lines {
+        let mut fields: Vec<String> = Vec::with_capacity(titles.len());
+        let mut rest = line.trim_start();
+        while fields.len() + 1 < titles.len() && !rest.is_empty() {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            fields.push(rest[..end].to_string());
+            rest = rest[end..].trim_start();
+        }
+        if !rest.is_empty() {
+            fields.push(rest.to_string());
+        }
+        let in_container = fields
+            .get(pid_column)
+            .and_then(|pid| pid.parse::<u32>().ok())
+            .is_some_and(|pid| pids.contains(&pid));
+        if in_container {
+            processes.push(fields);
+        }
+    }
+    Ok(TopResult { titles, processes })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_stat() {
+        let content = "4242 (my (weird) cmd) S 4200 4242 4242 0 -1 4194560 300 0 0 0 \
+                       150 50 0 0 20 0 1 0 1000 10000 200";
+        let stat = parse_stat(content).unwrap();
+        assert_eq!(stat.comm, "my (weird) cmd");
+        assert_eq!(stat.state, 'S');
+        assert_eq!(stat.ppid, 4200);
+        assert_eq!(stat.cpu_ticks, 200);
+        assert!(parse_stat("4242 (sh").is_none());
+    }
+
+    #[test]
+    fn test_parse_cmdline_and_time() {
+        assert_eq!(
+            parse_cmdline(b"nginx\0-g\0daemon off;\0"),
+            "nginx -g daemon off;"
+        );
+        assert_eq!(parse_cmdline(b""), "");
+        assert_eq!(format_cpu_time(3723), "01:02:03");
+        assert_eq!(format_cpu_time(90061), "1-01:01:01");
+    }
+
+    #[test]
+    fn test_parse_ps_output() {
+        let output = "UID          PID    PPID  C STIME TTY          TIME CMD\n\
+                      root           1       0  0 10:00 ?        00:00:01 /sbin/init\n\
+                      root        4242    4200  0 10:01 ?        00:00:00 nginx -g daemon off;\n\
+                      101         4243    4242  0 10:01 ?        00:00:00 nginx: worker process\n";
+        let top = parse_ps_output(output, &[4242, 4243]).unwrap();
+        assert_eq!(top.titles.len(), 8);
+        assert_eq!(top.processes.len(), 2);
+        assert_eq!(top.processes[0][1], "4242");
+        assert_eq!(top.processes[0][7], "nginx -g daemon off;");
+        assert_eq!(top.processes[1][7], "nginx: worker process");
+
+        assert!(matches!(
+            parse_ps_output("USER COMMAND\nroot init\n", &[1]),
+            Err(ContainerError::InvalidArgument(_))
+        ));
+    }
+}
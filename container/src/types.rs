@@ -19,8 +19,10 @@ pub struct ContainerConfig {
     pub open_stdin: bool,
     pub stdin_once: bool,
     pub env: Vec<String>,
-    pub cmd: Vec<String>,
-    pub entrypoint: Vec<String>,
+    /// Replaces the image's command when set.
+    pub cmd: Option<Vec<String>>,
+    /// Replaces the image's entrypoint when set, even to nothing.
+    pub entrypoint: Option<Vec<String>>,
     pub image: String,
     pub labels: HashMap<String, String>,
     pub working_dir: String,
@@ -29,6 +31,19 @@ pub struct ContainerConfig {
     pub stop_signal: String,
     pub stop_timeout: i32,
     pub shell: Vec<String>,
+    pub healthcheck: Option<HealthConfig>,
+}
+
+/// Healthcheck as given by the user or the image. `test` is `["NONE"]`,
+/// `["CMD", args...]` or `["CMD-SHELL", command]`; durations are in
+/// nanoseconds, with zero meaning the default.
+#[derive(Debug, Clone, Default)]
+pub struct HealthConfig {
+    pub test: Vec<String>,
+    pub interval: i64,
+    pub timeout: i64,
+    pub retries: i32,
+    pub start_period: i64,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -40,6 +55,35 @@ pub struct HostConfig {
     pub privileged: bool,
     pub publish_all_ports: bool,
     pub readonly_rootfs: bool,
+    /// Tmpfs mounts by container path, with their comma-separated options.
+    pub tmpfs: HashMap<String, String>,
+    /// Memory limit in bytes.
+    pub memory_limit: Option<i64>,
+    /// CFS quota in microseconds per `cpu_period`.
+    pub cpu_quota: Option<i64>,
+    /// CFS period in microseconds.
+    pub cpu_period: Option<u64>,
+    /// Number of CPUs; converted to a CFS quota when `cpu_quota` is unset.
+    pub cpus: Option<f64>,
+    pub restart_policy: RestartPolicy,
+    /// Name servers, search domains and options of the container's resolver.
+    pub dns: Vec<String>,
+    pub dns_search: Vec<String>,
+    pub dns_options: Vec<String>,
+    /// Extra `/etc/hosts` entries, as `NAME:IP`.
+    pub extra_hosts: Vec<String>,
+    /// Networks, as CIDRs, the container may and may not open connections
+    /// to.
+    pub egress_allow: Vec<String>,
+    pub egress_deny: Vec<String>,
+}
+
+/// Restart policy as given by the user: `name` is one of "no", "always",
+/// "unless-stopped" or "on-failure" (empty means "no").
+#[derive(Debug, Clone, Default)]
+pub struct RestartPolicy {
+    pub name: String,
+    pub maximum_retry_count: i32,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -72,6 +116,9 @@ pub struct CreateContainerParams {
     pub config: ContainerConfig,
     pub host_config: HostConfig,
     pub networking_config: NetworkingConfig,
+    /// When to pull the image first: `always`, `missing` (the default, when
+    /// empty) or `never`.
+    pub pull: String,
 }
 
 #[derive(Debug, Clone)]
@@ -85,7 +132,9 @@ pub struct ListContainersParams {
     pub all: bool,
     pub limit: i32,
     pub size: bool,
-    pub filters: HashMap<String, String>,
+    /// Accepted values by filter key (`id`, `name`, `label`, `status` or
+    /// `ancestor`). A container must match one value of every key.
+    pub filters: HashMap<String, Vec<String>>,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -117,6 +166,25 @@ pub struct ContainerState {
     pub error: String,
     pub started_at: Option<Timestamp>,
     pub finished_at: Option<Timestamp>,
+    /// Set when the container has a healthcheck.
+    pub health: Option<Health>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Health {
+    /// "starting", "healthy" or "unhealthy".
+    pub status: String,
+    pub failing_streak: i32,
+    /// Most recent probes, oldest first.
+    pub log: Vec<HealthLog>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct HealthLog {
+    pub start: Option<Timestamp>,
+    pub end: Option<Timestamp>,
+    pub exit_code: i32,
+    pub output: String,
 }
 
 #[derive(Debug, Clone)]
@@ -160,6 +228,26 @@ pub struct GetLogsParams {
     pub tail: String,
 }
 
+/// A change in a container's lifecycle.
+#[derive(Debug, Clone)]
+pub struct ContainerEvent {
+    pub container_id: String,
+    /// What happened: "create", "start", "die", "stop", ...
+    pub action: String,
+    pub time: Timestamp,
+    /// Details such as the container name, image and exit code.
+    pub attributes: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct EventsParams {
+    /// Replay the recent events from this time on before streaming new ones.
+    pub since: Option<Timestamp>,
+    /// Accepted values by filter key (`event`, `container` or `image`). An
+    /// event must match one value of every key.
+    pub filters: HashMap<String, Vec<String>>,
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct ExecConfig {
     pub attach_stdin: bool,
@@ -178,6 +266,7 @@ pub struct ExecConfig {
 pub struct ExecOutput {
     pub stream: String,
     pub data: Vec<u8>,
+    pub exit: Option<WaitResult>,
 }
 
 #[derive(Debug, Clone)]
@@ -211,6 +300,20 @@ pub enum OutputEvent {
     Exit(WaitResult),
 }
 
+/// New resource limits for a container; limits left `None` keep their
+/// value.
+#[derive(Debug, Clone, Default)]
+pub struct UpdateParams {
+    /// Memory limit in bytes.
+    pub memory_limit: Option<i64>,
+    /// CFS quota in microseconds per `cpu_period`.
+    pub cpu_quota: Option<i64>,
+    /// CFS period in microseconds.
+    pub cpu_period: Option<u64>,
+    /// Number of CPUs; converted to a CFS quota when `cpu_quota` is unset.
+    pub cpus: Option<f64>,
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct StatsParams {
     pub container_id: String,
@@ -218,6 +321,52 @@ pub struct StatsParams {
     pub one_shot: bool,
 }
 
+/// The processes of a container, as rows of `ps`-style columns.
+#[derive(Debug, Clone, Default)]
+pub struct TopResult {
+    pub titles: Vec<String>,
+    pub processes: Vec<Vec<String>>,
+}
+
+/// A volume managed by the daemon.
+#[derive(Debug, Clone, Default)]
+pub struct Volume {
+    pub name: String,
+    pub driver: String,
+    /// Directory on the host holding the volume's contents.
+    pub mountpoint: String,
+    pub created: Option<Timestamp>,
+    pub labels: HashMap<String, String>,
+}
+
+/// Disk space taken by containers or volumes, as `system df` reports it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiskUsage {
+    pub count: i64,
+    /// Running containers, or volumes an existing container mounts.
+    pub active: i64,
+    pub size: i64,
+    /// Space freed by removing the ones that are not active.
+    pub reclaimable: i64,
+}
+
+/// A container's state and, while it runs, its resource usage.
+#[derive(Debug, Clone, Default)]
+pub struct ContainerUsage {
+    pub id: String,
+    pub name: String,
+    /// The state as `ps --filter status=` names it.
+    pub state: String,
+    /// CPU and memory usage, unknown for containers that are not running or
+    /// whose cgroup cannot be read.
+    pub cpu_usage_ns: Option<u64>,
+    pub memory_usage: Option<u64>,
+    pub memory_limit: Option<u64>,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub nat_connections: u64,
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct ContainerStats {
     pub read: Option<Timestamp>,
@@ -226,9 +375,16 @@ pub struct ContainerStats {
     pub cpu_stats: Option<CpuStats>,
     pub precpu_stats: Option<CpuStats>,
     pub memory_stats: Option<MemoryStats>,
+    pub pids_stats: Option<PidsStats>,
     pub networks: HashMap<String, NetworkStats>,
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct PidsStats {
+    pub current: u64,
+    pub limit: u64,
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct CpuStats {
     pub cpu_usage: Option<CpuUsage>,
@@ -271,5 +427,10 @@ pub struct NetworkStats {
 #[derive(Debug, Clone)]
 pub enum InputEvent {
     Stdin(Vec<u8>),
-    Resize { width: u16, height: u16 },
+    Resize {
+        width: u16,
+        height: u16,
+    },
+    /// The client's stdin reached its end.
+    CloseStdin,
 }
@@ -0,0 +1,476 @@
+//! Volumes managed by the daemon: directories under the data directory that
+//! outlive the containers mounting them.
+//!
+//! A volume spec whose source is a name (`NAME:CONTAINER_PATH[:OPTIONS]`)
+//! mounts the named volume, created on first use, and a spec with only a
+//! container path (`/CONTAINER_PATH`) mounts a new anonymous volume. Specs
+//! whose source is an absolute path are bind mounts left to the shim.
+
+use crate::error::ContainerError;
+use crate::types::{DiskUsage, Volume};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Directory of a volume holding its contents.
+const DATA_DIR: &str = "_data";
+const METADATA_FILE: &str = "volume.json";
+const DRIVER: &str = "local";
+
+/// What the store records about a volume.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct VolumeRecord {
+    name: String,
+    created_at: i64,
+    #[serde(default)]
+    labels: HashMap<String, String>,
+    /// Created for a container by a spec without a source, rather than by
+    /// name.
+    #[serde(default)]
+    anonymous: bool,
+    /// Containers created with the volume mounted. May name containers
+    /// that are gone if the daemon died while removing them.
+    #[serde(default)]
+    containers: Vec<String>,
+}
+
+/// The volume specs of a container, with volumes turned into bind mounts of
+/// their directories.
+#[derive(Debug, Default)]
+pub(crate) struct ResolvedBinds {
+    pub binds: Vec<String>,
+    /// Volumes the container mounts.
+    pub volumes: Vec<String>,
+    /// Anonymous volumes created for the container, to drop if it is not
+    /// created after all.
+    pub anonymous: Vec<String>,
+}
+
+/// How a volume spec is mounted.
+#[derive(Debug, PartialEq, Eq)]
+enum VolumeSpec<'a> {
+    /// A host path, or a spec the shim rejects.
+    Bind,
+    Named {
+        name: &'a str,
+        rest: &'a str,
+    },
+    Anonymous {
+        destination: &'a str,
+    },
+}
+
+impl<'a> VolumeSpec<'a> {
+    fn parse(spec: &'a str) -> Self {
+        match spec.split_once(':') {
+            None if spec.starts_with('/') => VolumeSpec::Anonymous { destination: spec },
+            Some((source, rest)) if !source.is_empty() && !source.starts_with('/') => {
+                VolumeSpec::Named { name: source, rest }
+            }
+            _ => VolumeSpec::Bind,
+        }
+    }
+}
+
+/// The volumes of a daemon, each a directory under `root`.
+pub(crate) struct VolumeStore {
+    root: PathBuf,
+    /// Serializes changes to volume metadata.
+    lock: Mutex<()>,
+}
+
+impl VolumeStore {
+    pub fn new(root: &Path) -> Result<Self, ContainerError> {
+        std::fs::create_dir_all(root)?;
+        Ok(Self {
+            root: root.to_path_buf(),
+            lock: Mutex::new(()),
+        })
+    }
+
+    /// Creates the volume `name`, or an anonymous volume without one.
+    /// Creating a volume that exists returns it unchanged.
+    pub fn create(
+        &self,
+        name: Option<&str>,
+        labels: HashMap<String, String>,
+    ) -> Result<Volume, ContainerError> {
+        let _guard = self.lock.lock().unwrap();
+        let record = match name {
+            Some(name) => self.ensure(name, labels, false)?,
+            None => {
+                let name = uuid::Uuid::new_v4().simple().to_string();
+                self.ensure(&name, labels, true)?
+            }
+        };
+        Ok(self.volume(record))
+    }
+
+    /// Lists the volumes, sorted by name.
+    pub fn list(&self) -> Result<Vec<Volume>, ContainerError> {
+        let mut volumes = Vec::new();
+        for entry in std::fs::read_dir(&self.root)? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            match self.load(&name) {
+                Ok(record) => volumes.push(self.volume(record)),
+                Err(e) => tracing::warn!("Skipping volume {}: {}", name, e),
+            }
+        }
+        volumes.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(volumes)
+    }
+
+    /// Deletes the volume `name` and its contents, unless one of the
+    /// `containers` that exist mounts it.
+    pub fn remove(&self, name: &str, containers: &HashSet<String>) -> Result<(), ContainerError> {
+        let _guard = self.lock.lock().unwrap();
+        let record = self.load(name)?;
+        if let Some(id) = record.containers.iter().find(|id| containers.contains(*id)) {
+            return Err(ContainerError::VolumeInUse(format!(
+                "{} (container {})",
+                name, id
+            )));
+        }
+        std::fs::remove_dir_all(self.root.join(name))?;
+        tracing::info!("Removed volume {}", name);
+        Ok(())
+    }
+
+    /// Disk space taken by the volumes. That of the volumes none of the
+    /// `containers` that exist mounts is reclaimable.
+    pub fn disk_usage(&self, containers: &HashSet<String>) -> Result<DiskUsage, ContainerError> {
+        let mut usage = DiskUsage::default();
+        for record in self.list_records()? {
+            let size = dir_size(&self.mountpoint(&record.name))?;
+            usage.count += 1;
+            usage.size += size;
+            if record.containers.iter().any(|id| containers.contains(id)) {
+                usage.active += 1;
+            } else {
+                usage.reclaimable += size;
+            }
+        }
+        Ok(usage)
+    }
+
+    /// Turns the named and anonymous volumes of `binds` into bind mounts,
+    /// creating the volumes that do not exist.
+    pub fn resolve_binds(&self, binds: &[String]) -> Result<ResolvedBinds, ContainerError> {
+        let _guard = self.lock.lock().unwrap();
+        let mut resolved = ResolvedBinds::default();
+        let result = binds.iter().try_for_each(|spec| {
+            let bind = match VolumeSpec::parse(spec) {
+                VolumeSpec::Bind => spec.clone(),
+                VolumeSpec::Named { name, rest } => {
+                    let record = self.ensure(name, HashMap::new(), false)?;
+                    resolved.volumes.push(record.name);
+                    format!("{}:{}", self.mountpoint(name).display(), rest)
+                }
+                VolumeSpec::Anonymous { destination } => {
+                    let name = uuid::Uuid::new_v4().simple().to_string();
+                    self.ensure(&name, HashMap::new(), true)?;
+                    let bind = format!("{}:{}", self.mountpoint(&name).display(), destination);
+                    resolved.volumes.push(name.clone());
+                    resolved.anonymous.push(name);
+                    bind
+                }
+            };
+            resolved.binds.push(bind);
+            Ok(())
+        });
+        if let Err(e) = result {
+            self.discard(&resolved.anonymous);
+            return Err(e);
+        }
+        Ok(resolved)
+    }
+
+    /// Records that `container_id` mounts `volumes`.
+    pub fn attach(&self, volumes: &[String], container_id: &str) -> Result<(), ContainerError> {
+        let _guard = self.lock.lock().unwrap();
+        for name in volumes {
+            let mut record = self.load(name)?;
+            if !record.containers.iter().any(|id| id == container_id) {
+                record.containers.push(container_id.to_string());
+                self.save(&record)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Forgets the removed container `container_id`, deleting the anonymous
+    /// volumes no other container mounts if `remove_anonymous` is set.
+    pub fn detach(&self, container_id: &str, remove_anonymous: bool) -> Result<(), ContainerError> {
+        let _guard = self.lock.lock().unwrap();
+        for mut record in self.list_records()? {
+            let before = record.containers.len();
+            record.containers.retain(|id| id != container_id);
+            if record.containers.len() == before {
+                continue;
+            }
+            if record.anonymous && remove_anonymous && record.containers.is_empty() {
+                std::fs::remove_dir_all(self.root.join(&record.name))?;
+                tracing::info!("Removed anonymous volume {}", record.name);
+            } else {
+                self.save(&record)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Deletes anonymous volumes created for a container that was not
+    /// created after all.
+    pub fn discard(&self, anonymous: &[String]) {
+        for name in anonymous {
+            if let Err(e) = std::fs::remove_dir_all(self.root.join(name)) {
+                tracing::warn!("Failed to remove anonymous volume {}: {}", name, e);
+            }
+        }
+    }
+
+    /// Loads the volume `name`, creating it if it does not exist.
+    fn ensure(
+        &self,
+        name: &str,
+        labels: HashMap<String, String>,
+        anonymous: bool,
+    ) -> Result<VolumeRecord, ContainerError> {
+        validate_name(name)?;
+        match self.load(name) {
+            Err(ContainerError::VolumeNotFound(_)) => {}
+            result => return result,
+        }
+        std::fs::create_dir_all(self.mountpoint(name))?;
+        let record = VolumeRecord {
+            name: name.to_string(),
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or(0),
+            labels,
+            anonymous,
+            containers: Vec::new(),
+        };
+        self.save(&record)?;
+        tracing::info!("Created volume {}", name);
+        Ok(record)
+    }
+
+    fn list_records(&self) -> Result<Vec<VolumeRecord>, ContainerError> {
+        let mut records = Vec::new();
+        for entry in std::fs::read_dir(&self.root)? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if let Ok(record) = self.load(&name) {
+                records.push(record);
+            }
+        }
+        Ok(records)
+    }
+
+    fn load(&self, name: &str) -> Result<VolumeRecord, ContainerError> {
+        if validate_name(name).is_err() {
+            return Err(ContainerError::VolumeNotFound(name.to_string()));
+        }
+        let path = self.root.join(name).join(METADATA_FILE);
+        let data = match std::fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(ContainerError::VolumeNotFound(name.to_string()));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        serde_json::from_slice(&data).map_err(|e| {
+            ContainerError::Io(std::io::Error::other(format!(
+                "invalid metadata for volume {}: {}",
+                name, e
+            )))
+        })
+    }
+
+    fn save(&self, record: &VolumeRecord) -> Result<(), ContainerError> {
+        let dir = self.root.join(&record.name);
+        let tmp = dir.join(format!("{}.tmp", METADATA_FILE));
+        let data = serde_json::to_vec_pretty(record).map_err(std::io::Error::other)?;
+        std::fs::write(&tmp, data)?;
+        std::fs::rename(&tmp, dir.join(METADATA_FILE))?;
+        Ok(())
+    }
+
+    fn mountpoint(&self, name: &str) -> PathBuf {
+        self.root.join(name).join(DATA_DIR)
+    }
+
+    fn volume(&self, record: VolumeRecord) -> Volume {
+        Volume {
+            mountpoint: self.mountpoint(&record.name).display().to_string(),
+            name: record.name,
+            driver: DRIVER.to_string(),
+            created: Some(prost_types::Timestamp {
+                seconds: record.created_at,
+                nanos: 0,
+            }),
+            labels: record.labels,
+        }
+    }
+}
+
+/// On-disk size of everything under `dir`, counting hard-linked files once.
+fn dir_size(dir: &Path) -> Result<i64, ContainerError> {
+    let mut size = 0;
+    let mut seen_links = HashSet::new();
+    let mut stack = vec![dir.to_path_buf()];
+    while let Some(current) = stack.pop() {
+        let entries = match std::fs::read_dir(&current) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        for entry in entries {
+            let entry = entry?;
+            // Does not follow symlinks.
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                stack.push(entry.path());
+            } else if metadata.nlink() > 1 && !seen_links.insert((metadata.dev(), metadata.ino())) {
+                continue;
+            }
+            size += metadata.blocks() as i64 * 512;
+        }
+    }
+    Ok(size)
+}
+
+/// Accepts the names Docker does: an alphanumeric character followed by at
+/// least one alphanumeric, `_`, `.` or `-`.
+fn validate_name(name: &str) -> Result<(), ContainerError> {
+    let mut chars = name.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_alphanumeric())
+        && name.len() > 1
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'));
+    if !valid {
+        return Err(ContainerError::InvalidArgument(format!(
+            "invalid volume name '{}': names must match [a-zA-Z0-9][a-zA-Z0-9_.-]+, \
+             and bind mount sources must be absolute paths",
+            name
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse_volume_spec() {
+        assert_eq!(VolumeSpec::parse("/data:/mnt:ro"), VolumeSpec::Bind);
+        assert_eq!(
+            VolumeSpec::parse("data:/mnt:ro"),
+            VolumeSpec::Named {
+                name: "data",
+                rest: "/mnt:ro"
+            }
+        );
+        assert_eq!(
+            VolumeSpec::parse("/mnt"),
+            VolumeSpec::Anonymous {
+                destination: "/mnt"
+            }
+        );
+        assert_eq!(VolumeSpec::parse(":/mnt"), VolumeSpec::Bind);
+        assert!(validate_name("my-vol_1.0").is_ok());
+        for name in ["a", ".data", "da/ta", "./data", ""] {
+            assert!(validate_name(name).is_err(), "{} should be rejected", name);
+        }
+    }
+
+    #[test]
+    fn test_named_volume_reused() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = VolumeStore::new(temp_dir.path()).unwrap();
+
+        let first = store
+            .resolve_binds(&["data:/mnt:ro".to_string(), "/host:/host".to_string()])
+            .unwrap();
+        let mountpoint = temp_dir.path().join("data").join(DATA_DIR);
+        assert_eq!(
+            first.binds,
+            vec![
+                format!("{}:/mnt:ro", mountpoint.display()),
+                "/host:/host".to_string()
+            ]
+        );
+        assert!(mountpoint.is_dir());
+        store.attach(&first.volumes, "c1").unwrap();
+        std::fs::write(mountpoint.join("file"), "kept").unwrap();
+
+        let second = store.resolve_binds(&["data:/other".to_string()]).unwrap();
+        assert_eq!(second.volumes, vec!["data"]);
+        assert!(second.anonymous.is_empty());
+        assert!(mountpoint.join("file").exists());
+
+        let live = HashSet::from(["c1".to_string()]);
+        assert!(matches!(
+            store.remove("data", &live),
+            Err(ContainerError::VolumeInUse(_))
+        ));
+        store.detach("c1", true).unwrap();
+        assert!(mountpoint.exists());
+        store.remove("data", &live).unwrap();
+        assert!(store.list().unwrap().is_empty());
+        assert!(matches!(
+            store.remove("data", &live),
+            Err(ContainerError::VolumeNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_anonymous_volume_removed_with_container() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = VolumeStore::new(temp_dir.path()).unwrap();
+
+        let resolved = store.resolve_binds(&["/cache".to_string()]).unwrap();
+        assert_eq!(resolved.anonymous.len(), 1);
+        let name = &resolved.anonymous[0];
+        assert!(resolved.binds[0].ends_with(&format!("{}/{}:/cache", name, DATA_DIR)));
+        store.attach(&resolved.volumes, "c1").unwrap();
+
+        store.detach("c1", false).unwrap();
+        assert_eq!(store.list().unwrap().len(), 1);
+
+        store.attach(&resolved.volumes, "c2").unwrap();
+        store.detach("c2", true).unwrap();
+        assert!(store.list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_volume_disk_usage() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = VolumeStore::new(temp_dir.path()).unwrap();
+
+        let used = store.resolve_binds(&["used:/mnt".to_string()]).unwrap();
+        store.attach(&used.volumes, "c1").unwrap();
+        store.create(Some("unused"), HashMap::new()).unwrap();
+        let data = temp_dir.path().join("unused").join(DATA_DIR);
+        std::fs::write(data.join("file"), vec![1u8; 10000]).unwrap();
+        std::fs::hard_link(data.join("file"), data.join("link")).unwrap();
+
+        let usage = store
+            .disk_usage(&HashSet::from(["c1".to_string()]))
+            .unwrap();
+        assert_eq!(usage.count, 2);
+        assert_eq!(usage.active, 1);
+        // The hard-linked file is counted once.
+        assert!((10000..20000).contains(&usage.reclaimable));
+        assert_eq!(usage.size, usage.reclaimable);
+
+        // The volumes of removed containers are reclaimable too.
+        let usage = store.disk_usage(&HashSet::new()).unwrap();
+        assert_eq!(usage.active, 0);
+        assert_eq!(usage.reclaimable, usage.size);
+    }
+}
@@ -1,5 +1,36 @@
+/// `google.protobuf.Timestamp` fields, which `prost_types` does not implement
+/// `Serialize` for.
+const TIMESTAMP_FIELDS: &[&str] = &[
+    "ross.Container.created",
+    "ross.ContainerState.started_at",
+    "ross.ContainerState.finished_at",
+    "ross.HealthLog.start",
+    "ross.HealthLog.end",
+    "ross.LogEntry.timestamp",
+    "ross.StatsResponse.read",
+    "ross.StatsResponse.preread",
+    "ross.GetLogsRequest.since",
+    "ross.GetLogsRequest.until",
+    "ross.EventsRequest.since",
+    "ross.ContainerEvent.time",
+    "ross.Volume.created_at",
+    "ross.Image.created",
+    "ross.ImageHistory.created",
+    "ross.SnapshotInfo.created_at",
+    "ross.SnapshotInfo.updated_at",
+];
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_build::configure().compile_protos(
+    let mut config =
+        tonic_build::configure().type_attribute(".ross", "#[derive(serde::Serialize)]");
+    for field in TIMESTAMP_FIELDS {
+        config = config.field_attribute(
+            field,
+            "#[serde(serialize_with = \"crate::serialize_timestamp\")]",
+        );
+    }
+
+    config.compile_protos(
         &[
             "../proto/ross.proto",
             "../proto/image.proto",
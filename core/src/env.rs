@@ -0,0 +1,53 @@
+//! Environment variable lists, as `KEY=VALUE` entries.
+
+/// `base` with the entries of `extra` merged over it, in order. An entry
+/// replaces the one for the same key in place, keeping the position the key
+/// first had. A bare `KEY` keeps the value the merge already has for it,
+/// and is dropped when there is none, so that every entry has a value.
+pub fn merge_env(base: Vec<String>, extra: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut merged = base;
+    for entry in extra {
+        let Some((key, _)) = entry.split_once('=') else {
+            continue;
+        };
+        match merged
+            .iter_mut()
+            .find(|e| e.split_once('=').is_some_and(|(k, _)| k == key))
+        {
+            Some(existing) => *existing = entry,
+            None => merged.push(entry),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn test_merge_env() {
+        let merged = merge_env(
+            strings(&["PATH=/bin", "FOO=base", "BAR=base"]),
+            strings(&["FOO=extra", "BAZ=a=b", "FOO=last"]),
+        );
+        assert_eq!(
+            merged,
+            strings(&["PATH=/bin", "FOO=last", "BAR=base", "BAZ=a=b"])
+        );
+    }
+
+    #[test]
+    fn test_merge_env_bare_key() {
+        // A bare key resolves to the value merged so far, or is dropped.
+        let merged = merge_env(strings(&["FOO=base"]), strings(&["FOO", "MISSING"]));
+        assert_eq!(merged, strings(&["FOO=base"]));
+
+        let merged = merge_env(Vec::new(), strings(&["FOO=1", "FOO"]));
+        assert_eq!(merged, strings(&["FOO=1"]));
+    }
+}
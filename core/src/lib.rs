@@ -1,5 +1,18 @@
+pub mod env;
+
 pub mod ross {
     tonic::include_proto!("ross");
 }
 
 pub use ross::*;
+
+/// Serializes a protobuf timestamp as an RFC 3339 string, or `null` when unset.
+fn serialize_timestamp<S: serde::Serializer>(
+    ts: &Option<prost_types::Timestamp>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match ts {
+        Some(ts) => serializer.collect_str(ts),
+        None => serializer.serialize_none(),
+    }
+}
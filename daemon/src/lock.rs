@@ -0,0 +1,82 @@
+//! Exclusive ownership of the data directory, so that two daemons never
+//! manage the same store and containers.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, Write};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+
+const LOCK_FILE: &str = "ross-daemon.lock";
+
+/// An advisory lock on a data directory, held until dropped. The lock file
+/// holds the pid of the daemon owning the directory.
+///
+/// This is a POSIX record lock rather than `flock`, since processes forked
+/// from the daemon, like libkrun VMs, would otherwise share the lock and
+/// keep a crashed daemon from restarting.
+pub struct DataDirLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl DataDirLock {
+    /// Locks `data_dir`, creating it if needed. Fails without waiting when
+    /// another daemon holds the lock.
+    pub fn acquire(data_dir: &Path) -> std::io::Result<Self> {
+        std::fs::create_dir_all(data_dir)?;
+        let path = data_dir.join(LOCK_FILE);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+
+        if !try_lock(&file)? {
+            let mut pid = String::new();
+            let _ = file.read_to_string(&mut pid);
+            let owner = match pid.trim() {
+                "" => String::new(),
+                pid => format!(" (pid {})", pid),
+            };
+            return Err(std::io::Error::other(format!(
+                "data directory {} is in use by another ross-daemon{}",
+                data_dir.display(),
+                owner
+            )));
+        }
+
+        file.set_len(0)?;
+        file.rewind()?;
+        writeln!(file, "{}", std::process::id())?;
+        Ok(Self { file, path })
+    }
+}
+
+impl Drop for DataDirLock {
+    fn drop(&mut self) {
+        // Emptied so that the file never names a daemon that is gone.
+        // The lock itself goes with the file.
+        let _ = self.file.set_len(0);
+        tracing::debug!("Released data directory lock {:?}", self.path);
+    }
+}
+
+/// Write-locks all of `file` without waiting, returning false when another
+/// process holds it.
+fn try_lock(file: &File) -> std::io::Result<bool> {
+    // SAFETY: `flock` is plain data, for which all zeroes is valid.
+    let mut lock: libc::flock = unsafe { std::mem::zeroed() };
+    lock.l_type = libc::F_WRLCK as _;
+    lock.l_whence = libc::SEEK_SET as _;
+    // A zero start and length cover the whole file, however long.
+    // SAFETY: the fd is open for as long as `file` lives.
+    if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_SETLK, &lock) } == 0 {
+        return Ok(true);
+    }
+    let err = std::io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::EACCES | libc::EAGAIN) => Ok(false),
+        _ => Err(err),
+    }
+}
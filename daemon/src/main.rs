@@ -1,19 +1,24 @@
+mod lock;
+mod metrics;
 mod services;
 
 use clap::{Parser, Subcommand};
-use ross_container::ContainerService;
+use lock::DataDirLock;
+use ross_container::{ContainerService, Runtime};
 use ross_core::container_service_server::ContainerServiceServer;
 use ross_core::image_service_server::ImageServiceServer;
 use ross_core::ross_server::RossServer;
 use ross_core::snapshotter_service_server::SnapshotterServiceServer;
-use ross_image::ImageService;
+use ross_image::{ImageService, InsecureMode, parse_mirror};
 use ross_snapshotter::OverlaySnapshotter;
 use ross_store::FileSystemStore;
 use services::{ContainerServiceGrpc, ImageServiceGrpc, RossService, SnapshotterServiceGrpc};
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::signal;
-use tonic::transport::Server;
+use tonic::transport::{Certificate, Identity, Server, ServerTlsConfig};
 use tracing_subscriber::EnvFilter;
 
 #[derive(Parser)]
@@ -43,6 +48,65 @@ enum Commands {
         /// Maximum number of parallel blob downloads
         #[arg(long, default_value_t = 3)]
         max_concurrent_downloads: usize,
+
+        /// Attempts made for each blob download before the pull fails
+        #[arg(long, default_value_t = 5)]
+        max_download_attempts: u32,
+
+        /// Maximum number of layers unpacked in parallel
+        #[arg(long, default_value_t = 3)]
+        max_concurrent_unpacks: usize,
+
+        /// Registry to pull from without verified HTTPS: `host[:port]` skips
+        /// certificate verification, `http://host[:port]` uses plain HTTP.
+        /// May be repeated.
+        #[arg(long = "insecure-registry", value_name = "REGISTRY")]
+        insecure_registries: Vec<String>,
+
+        /// Mirror to pull from before a registry, as `[REGISTRY=]MIRROR`
+        /// with the registry defaulting to docker.io. Mirrors are tried in
+        /// the order given, then the registry itself. May be repeated.
+        #[arg(long = "registry-mirror", value_name = "MIRROR")]
+        registry_mirrors: Vec<String>,
+
+        /// Container runtime: `runc` or `libkrun`. Defaults to libkrun on
+        /// macOS builds with libkrun support and runc everywhere else.
+        #[arg(long)]
+        runtime: Option<Runtime>,
+
+        /// Link MTU of libkrun containers on the userspace network stack.
+        /// Defaults to ROSS_NET_MTU, then 1500.
+        #[arg(long, value_name = "BYTES")]
+        mtu: Option<u16>,
+
+        /// Address to serve Prometheus metrics on, at `/metrics`, e.g.
+        /// `127.0.0.1:9323`. Metrics are not served unless it is set.
+        #[arg(long, value_name = "ADDR")]
+        metrics_addr: Option<SocketAddr>,
+
+        /// Leave containers running when the daemon shuts down instead of
+        /// stopping them.
+        #[arg(long)]
+        live_restore: bool,
+
+        /// Seconds each container gets to exit on its stop signal when the
+        /// daemon shuts down, before it is killed. Containers are stopped
+        /// in parallel; ignored with --live-restore.
+        #[arg(long, value_name = "SECONDS", default_value_t = 10)]
+        shutdown_timeout: u32,
+
+        /// Certificate to serve the gRPC API over TLS with, in PEM
+        #[arg(long, value_name = "PATH", requires = "tls_key")]
+        tls_cert: Option<PathBuf>,
+
+        /// Private key of the TLS certificate, in PEM
+        #[arg(long, value_name = "PATH", requires = "tls_cert")]
+        tls_key: Option<PathBuf>,
+
+        /// CA that client certificates must be signed by. Clients without
+        /// one are refused.
+        #[arg(long, value_name = "PATH", requires = "tls_cert")]
+        tls_ca: Option<PathBuf>,
     },
 }
 
@@ -62,8 +126,40 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             port,
             data_dir,
             max_concurrent_downloads,
+            max_download_attempts,
+            max_concurrent_unpacks,
+            insecure_registries,
+            registry_mirrors,
+            runtime,
+            mtu,
+            metrics_addr,
+            live_restore,
+            shutdown_timeout,
+            tls_cert,
+            tls_key,
+            tls_ca,
         } => {
+            let insecure_registries = insecure_registries
+                .iter()
+                .map(|spec| InsecureMode::parse(spec))
+                .collect::<Result<HashMap<_, _>, _>>()?;
+            for (registry, mode) in &insecure_registries {
+                tracing::warn!("Registry {} is insecure ({:?})", registry, mode);
+            }
+            let mut mirrors: HashMap<String, Vec<String>> = HashMap::new();
+            for spec in &registry_mirrors {
+                let (registry, mirror) = parse_mirror(spec)?;
+                tracing::info!("Pulling from {} through mirror {}", registry, mirror);
+                mirrors.entry(registry).or_default().push(mirror);
+            }
+
             let addr = format!("{}:{}", host, port).parse()?;
+            let tls = match tls_cert.zip(tls_key) {
+                Some((cert, key)) => Some(server_tls_config(&cert, &key, tls_ca.as_deref())?),
+                None => None,
+            };
+
+            let data_dir_lock = DataDirLock::acquire(&data_dir)?;
 
             let store_path = data_dir.join("store");
             tracing::info!("Initisalizing store at {:?}", store_path);
@@ -72,19 +168,34 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
             let snapshotter_path = data_dir.join("snapshotter");
             tracing::info!("Initializing snapshotter at {:?}", snapshotter_path);
-            let snapshotter = OverlaySnapshotter::new(&snapshotter_path, store.clone()).await?;
+            let snapshotter = OverlaySnapshotter::new(&snapshotter_path, store.clone())
+                .await?
+                .with_max_concurrent_unpacks(max_concurrent_unpacks);
             let snapshotter = Arc::new(snapshotter);
 
-            tracing::info!("Initializing container service");
-            let container_service =
-                ContainerService::new(&data_dir, snapshotter.clone(), store.clone()).await?;
-            let container_service = Arc::new(container_service);
+            let image_service = Arc::new(
+                ImageService::new(
+                    store.clone(),
+                    snapshotter.clone(),
+                    max_concurrent_downloads,
+                    max_download_attempts,
+                )
+                .with_insecure_registries(insecure_registries)
+                .with_registry_mirrors(mirrors),
+            );
 
-            let image_service = Arc::new(ImageService::new(
-                store.clone(),
+            tracing::info!("Initializing container service");
+            let runtime = runtime.unwrap_or_else(Runtime::detect);
+            let container_service = ContainerService::new(
+                &data_dir,
+                runtime,
+                mtu,
                 snapshotter.clone(),
-                max_concurrent_downloads,
-            ));
+                store.clone(),
+                image_service.clone(),
+            )
+            .await?;
+            let container_service = Arc::new(container_service);
 
             tracing::info!(
                 "Starting Ross daemon gRPC server on {} (max concurrent downloads: {})",
@@ -92,13 +203,31 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 max_concurrent_downloads
             );
 
-            Server::builder()
-                .add_service(RossServer::new(RossService))
+            if let Some(metrics_addr) = metrics_addr {
+                let containers = container_service.clone();
+                let images = image_service.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = metrics::serve(metrics_addr, containers, images).await {
+                        tracing::error!("Metrics server failed: {}", e);
+                    }
+                });
+            }
+
+            let mut server = Server::builder();
+            if let Some(tls) = tls {
+                server = server.tls_config(tls)?;
+            }
+            server
+                .add_service(RossServer::new(RossService::new(
+                    image_service.clone(),
+                    container_service.clone(),
+                )))
                 .add_service(ImageServiceServer::new(ImageServiceGrpc::new(
                     image_service,
+                    container_service.clone(),
                 )))
                 .add_service(ContainerServiceServer::new(ContainerServiceGrpc::new(
-                    container_service,
+                    container_service.clone(),
                 )))
                 .add_service(SnapshotterServiceServer::new(SnapshotterServiceGrpc::new(
                     snapshotter,
@@ -108,8 +237,35 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     tracing::info!("Received shutdown signal, stopping server...");
                 })
                 .await?;
+
+            container_service
+                .shutdown(live_restore, shutdown_timeout)
+                .await;
+            drop(data_dir_lock);
+            tracing::info!("Ross daemon stopped");
         }
     }
 
     Ok(())
 }
+
+/// TLS for the gRPC listener, requiring client certificates signed by `ca`
+/// when it is given.
+fn server_tls_config(
+    cert: &Path,
+    key: &Path,
+    ca: Option<&Path>,
+) -> Result<ServerTlsConfig, Box<dyn std::error::Error>> {
+    let read = |path: &Path| {
+        std::fs::read(path).map_err(|e| format!("failed to read {}: {}", path.display(), e))
+    };
+    let mut config = ServerTlsConfig::new().identity(Identity::from_pem(read(cert)?, read(key)?));
+    match ca {
+        Some(ca) => {
+            tracing::info!("Requiring client certificates signed by {}", ca.display());
+            config = config.client_ca_root(Certificate::from_pem(read(ca)?));
+        }
+        None => tracing::info!("Serving over TLS without client authentication"),
+    }
+    Ok(config)
+}
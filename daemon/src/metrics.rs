@@ -0,0 +1,219 @@
+//! Prometheus metrics, served over plain HTTP on their own address.
+
+use axum::Router;
+use axum::extract::State;
+use axum::http::{StatusCode, header};
+use axum::response::IntoResponse;
+use axum::routing::get;
+use ross_container::{ContainerService, ContainerUsage};
+use ross_image::ImageService;
+use std::fmt::{Display, Write};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// Version 0.0.4 of the Prometheus text exposition format.
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// States a container can be in, all reported even when no container is.
+const STATES: &[&str] = &["created", "running", "paused", "exited"];
+
+#[derive(Clone)]
+struct Sources {
+    containers: Arc<ContainerService>,
+    images: Arc<ImageService>,
+}
+
+/// Serves `/metrics` on `addr` until the task is dropped.
+pub async fn serve(
+    addr: SocketAddr,
+    containers: Arc<ContainerService>,
+    images: Arc<ImageService>,
+) -> std::io::Result<()> {
+    let app = Router::new()
+        .route("/metrics", get(metrics))
+        .with_state(Sources { containers, images });
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    tracing::info!("Serving metrics on http://{}/metrics", addr);
+    axum::serve(listener, app).await
+}
+
+async fn metrics(State(sources): State<Sources>) -> impl IntoResponse {
+    match sources.containers.usage().await {
+        Ok(usage) => {
+            let body = render(&usage, &sources.images);
+            (StatusCode::OK, [(header::CONTENT_TYPE, CONTENT_TYPE)], body)
+        }
+        Err(e) => {
+            tracing::warn!("Failed to collect container metrics: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                [(header::CONTENT_TYPE, CONTENT_TYPE)],
+                format!("collecting container metrics: {}\n", e),
+            )
+        }
+    }
+}
+
+fn render(usage: &[ContainerUsage], images: &ImageService) -> String {
+    let mut out = String::new();
+
+    family(&mut out, "ross_containers", "gauge", "Containers by state.");
+    for state in STATES {
+        let count = usage.iter().filter(|c| c.state == *state).count();
+        sample(&mut out, "ross_containers", &[("state", *state)], count);
+    }
+
+    let pulls = images.pull_stats();
+    family(
+        &mut out,
+        "ross_images_pulled_total",
+        "counter",
+        "Image pulls completed since the daemon started.",
+    );
+    sample(&mut out, "ross_images_pulled_total", &[], pulls.images);
+    family(
+        &mut out,
+        "ross_pull_bytes_total",
+        "counter",
+        "Bytes downloaded by image pulls since the daemon started.",
+    );
+    sample(&mut out, "ross_pull_bytes_total", &[], pulls.bytes);
+    family(
+        &mut out,
+        "ross_pull_reused_bytes_total",
+        "counter",
+        "Bytes of layers image pulls found already stored, shared with other images.",
+    );
+    sample(
+        &mut out,
+        "ross_pull_reused_bytes_total",
+        &[],
+        pulls.reused_bytes,
+    );
+
+    family(
+        &mut out,
+        "ross_nat_connections",
+        "gauge",
+        "TCP connections tracked by the userspace NAT of all containers.",
+    );
+    let nat_connections: u64 = usage.iter().map(|c| c.nat_connections).sum();
+    sample(&mut out, "ross_nat_connections", &[], nat_connections);
+
+    // Per-container series only exist while the container runs.
+    let running: Vec<&ContainerUsage> = usage
+        .iter()
+        .filter(|c| c.state == "running" || c.state == "paused")
+        .collect();
+
+    family(
+        &mut out,
+        "ross_container_cpu_seconds_total",
+        "counter",
+        "CPU time consumed by the container.",
+    );
+    for c in &running {
+        if let Some(ns) = c.cpu_usage_ns {
+            let seconds = ns as f64 / 1e9;
+            sample(
+                &mut out,
+                "ross_container_cpu_seconds_total",
+                &labels(c),
+                seconds,
+            );
+        }
+    }
+    family(
+        &mut out,
+        "ross_container_memory_usage_bytes",
+        "gauge",
+        "Memory used by the container.",
+    );
+    for c in &running {
+        if let Some(bytes) = c.memory_usage {
+            sample(
+                &mut out,
+                "ross_container_memory_usage_bytes",
+                &labels(c),
+                bytes,
+            );
+        }
+    }
+    family(
+        &mut out,
+        "ross_container_memory_limit_bytes",
+        "gauge",
+        "Memory limit of the container, or the host's memory without one.",
+    );
+    for c in &running {
+        if let Some(bytes) = c.memory_limit {
+            sample(
+                &mut out,
+                "ross_container_memory_limit_bytes",
+                &labels(c),
+                bytes,
+            );
+        }
+    }
+    family(
+        &mut out,
+        "ross_container_network_receive_bytes_total",
+        "counter",
+        "Bytes received by the container's network interfaces.",
+    );
+    for c in &running {
+        sample(
+            &mut out,
+            "ross_container_network_receive_bytes_total",
+            &labels(c),
+            c.rx_bytes,
+        );
+    }
+    family(
+        &mut out,
+        "ross_container_network_transmit_bytes_total",
+        "counter",
+        "Bytes sent by the container's network interfaces.",
+    );
+    for c in &running {
+        sample(
+            &mut out,
+            "ross_container_network_transmit_bytes_total",
+            &labels(c),
+            c.tx_bytes,
+        );
+    }
+
+    out
+}
+
+/// Labels of the series of a container.
+fn labels(container: &ContainerUsage) -> [(&'static str, &str); 2] {
+    [("id", &container.id), ("name", &container.name)]
+}
+
+/// Writes the `HELP` and `TYPE` lines that introduce a metric family.
+fn family(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn sample(out: &mut String, name: &str, labels: &[(&str, &str)], value: impl Display) {
+    out.push_str(name);
+    if !labels.is_empty() {
+        let labels: Vec<String> = labels
+            .iter()
+            .map(|(key, value)| format!("{}=\"{}\"", key, escape(value)))
+            .collect();
+        let _ = write!(out, "{{{}}}", labels.join(","));
+    }
+    let _ = writeln!(out, " {}", value);
+}
+
+/// Escapes a label value as the text format requires.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
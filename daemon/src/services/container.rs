@@ -1,22 +1,28 @@
 use ross_container::{
-    AttachInput, ContainerService, CreateContainerParams, ExecConfig, GetLogsParams, InputEvent,
-    ListContainersParams, OutputEvent, StatsParams,
+    AttachInput, ContainerService, CreateContainerParams, EventsParams, ExecConfig, GetLogsParams,
+    InputEvent, ListContainersParams, OutputEvent, StatsParams, UpdateParams,
 };
 use ross_core::container_service_server::ContainerService as GrpcContainerService;
 use ross_core::{
-    AttachOutput, AttachRequest, CreateContainerRequest, CreateContainerResponse, ExecOutput,
-    ExecRequest, ExecResponse, ExecStartRequest, GetLogsRequest, InspectContainerRequest,
-    InspectContainerResponse, InteractiveInput, InteractiveOutput, KillContainerRequest,
-    KillContainerResponse, ListContainersRequest, ListContainersResponse, LogEntry,
+    AttachOutput, AttachRequest, ContainerChange, ContainerEvent, CreateContainerRequest,
+    CreateContainerResponse, CreateVolumeRequest, CreateVolumeResponse, DiffContainerRequest,
+    DiffContainerResponse, EventsRequest, ExecOutput, ExecRequest, ExecResponse, ExecStartRequest,
+    GetLogsRequest, InspectContainerRequest, InspectContainerResponse, InteractiveInput,
+    InteractiveOutput, KillContainerRequest, KillContainerResponse, ListContainersRequest,
+    ListContainersResponse, ListVolumesRequest, ListVolumesResponse, LogEntry,
     PauseContainerRequest, PauseContainerResponse, RemoveContainerRequest, RemoveContainerResponse,
-    RenameContainerRequest, RenameContainerResponse, RestartContainerRequest,
-    RestartContainerResponse, StartContainerRequest, StartContainerResponse, StatsRequest,
-    StatsResponse, StopContainerRequest, StopContainerResponse, UnpauseContainerRequest,
-    UnpauseContainerResponse, WaitContainerOutput, WaitContainerRequest,
+    RemoveVolumeRequest, RemoveVolumeResponse, RenameContainerRequest, RenameContainerResponse,
+    RestartContainerRequest, RestartContainerResponse, StartContainerRequest,
+    StartContainerResponse, StatsRequest, StatsResponse, StopContainerRequest,
+    StopContainerResponse, TopProcess, TopRequest, TopResponse, UnpauseContainerRequest,
+    UnpauseContainerResponse, UpdateContainerRequest, UpdateContainerResponse, WaitContainerOutput,
+    WaitContainerRequest,
 };
+use std::collections::HashMap;
 use std::pin::Pin;
 use std::sync::Arc;
 use tokio_stream::{Stream, StreamExt};
+use tokio_util::sync::CancellationToken;
 use tonic::{Request, Response, Status, Streaming};
 
 type StreamResult<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
@@ -57,6 +63,7 @@ impl GrpcContainerService for ContainerServiceGrpc {
                 .networking_config
                 .map(networking_config_from_grpc)
                 .unwrap_or_default(),
+            pull: req.pull,
         };
 
         let result = self.service.create(params).await.map_err(into_status)?;
@@ -131,7 +138,7 @@ impl GrpcContainerService for ContainerServiceGrpc {
             all: req.all,
             limit: req.limit,
             size: req.size,
-            filters: req.filters,
+            filters: filter_values(req.filters),
         };
 
         let containers = self.service.list(params).await.map_err(into_status)?;
@@ -397,6 +404,123 @@ impl GrpcContainerService for ContainerServiceGrpc {
         Ok(Response::new(RenameContainerResponse {}))
     }
 
+    async fn update_container(
+        &self,
+        request: Request<UpdateContainerRequest>,
+    ) -> Result<Response<UpdateContainerResponse>, Status> {
+        let req = request.into_inner();
+
+        if req.container_id.is_empty() {
+            return Err(Status::invalid_argument("container_id is required"));
+        }
+
+        let resources = req.resources.unwrap_or_default();
+        let positive = |v: i64| (v > 0).then_some(v);
+        let params = UpdateParams {
+            memory_limit: positive(resources.memory),
+            cpu_quota: positive(resources.cpu_quota),
+            cpu_period: positive(resources.cpu_period).map(|p| p as u64),
+            cpus: positive(resources.nano_cpus).map(|n| n as f64 / 1e9),
+        };
+
+        self.service
+            .update(&req.container_id, params)
+            .await
+            .map_err(into_status)?;
+
+        Ok(Response::new(UpdateContainerResponse {}))
+    }
+
+    async fn diff(
+        &self,
+        request: Request<DiffContainerRequest>,
+    ) -> Result<Response<DiffContainerResponse>, Status> {
+        let req = request.into_inner();
+
+        if req.container_id.is_empty() {
+            return Err(Status::invalid_argument("container_id is required"));
+        }
+
+        let changes = self
+            .service
+            .diff(&req.container_id)
+            .await
+            .map_err(into_status)?;
+
+        Ok(Response::new(DiffContainerResponse {
+            changes: changes.into_iter().map(change_to_grpc).collect(),
+        }))
+    }
+
+    async fn create_volume(
+        &self,
+        request: Request<CreateVolumeRequest>,
+    ) -> Result<Response<CreateVolumeResponse>, Status> {
+        let req = request.into_inner();
+
+        let volume = self
+            .service
+            .create_volume(&req.name, req.labels)
+            .await
+            .map_err(into_status)?;
+
+        Ok(Response::new(CreateVolumeResponse {
+            volume: Some(volume_to_grpc(volume)),
+        }))
+    }
+
+    async fn list_volumes(
+        &self,
+        _request: Request<ListVolumesRequest>,
+    ) -> Result<Response<ListVolumesResponse>, Status> {
+        let volumes = self.service.list_volumes().await.map_err(into_status)?;
+
+        Ok(Response::new(ListVolumesResponse {
+            volumes: volumes.into_iter().map(volume_to_grpc).collect(),
+        }))
+    }
+
+    async fn remove_volume(
+        &self,
+        request: Request<RemoveVolumeRequest>,
+    ) -> Result<Response<RemoveVolumeResponse>, Status> {
+        let req = request.into_inner();
+
+        if req.name.is_empty() {
+            return Err(Status::invalid_argument("name is required"));
+        }
+
+        self.service
+            .remove_volume(&req.name)
+            .await
+            .map_err(into_status)?;
+
+        Ok(Response::new(RemoveVolumeResponse {}))
+    }
+
+    async fn top(&self, request: Request<TopRequest>) -> Result<Response<TopResponse>, Status> {
+        let req = request.into_inner();
+
+        if req.container_id.is_empty() {
+            return Err(Status::invalid_argument("container_id is required"));
+        }
+
+        let top = self
+            .service
+            .top(&req.container_id, &req.ps_args)
+            .await
+            .map_err(into_status)?;
+
+        Ok(Response::new(TopResponse {
+            titles: top.titles,
+            processes: top
+                .processes
+                .into_iter()
+                .map(|fields| TopProcess { fields })
+                .collect(),
+        }))
+    }
+
     type StatsStream = StreamResult<StatsResponse>;
 
     async fn stats(
@@ -421,6 +545,25 @@ impl GrpcContainerService for ContainerServiceGrpc {
         Ok(Response::new(Box::pin(output)))
     }
 
+    type EventsStream = StreamResult<ContainerEvent>;
+
+    async fn events(
+        &self,
+        request: Request<EventsRequest>,
+    ) -> Result<Response<Self::EventsStream>, Status> {
+        let req = request.into_inner();
+
+        let params = EventsParams {
+            since: req.since,
+            filters: filter_values(req.filters),
+        };
+
+        let stream = self.service.events(params);
+        let output = stream.map(|result| result.map(event_to_grpc).map_err(into_status));
+
+        Ok(Response::new(Box::pin(output)))
+    }
+
     type RunInteractiveStream = StreamResult<InteractiveOutput>;
 
     async fn run_interactive(
@@ -455,10 +598,15 @@ impl GrpcContainerService for ContainerServiceGrpc {
             .await
             .map_err(into_status)?;
 
-        // Spawn task to forward input from gRPC stream to container
+        // Spawn task to forward input from gRPC stream to container, until
+        // the client disconnects and tonic drops the output stream.
+        let cancel = CancellationToken::new();
+        let cancel_on_drop = cancel.clone().drop_guard();
+        let service = self.service.clone();
+        let container_id = start.container_id;
         tokio::spawn(async move {
             tracing::debug!("Input forwarding task started");
-            while let Some(result) = input_stream.next().await {
+            while let Some(Some(result)) = cancel.run_until_cancelled(input_stream.next()).await {
                 match result {
                     Ok(msg) => {
                         let event = match msg.input {
@@ -472,6 +620,16 @@ impl GrpcContainerService for ContainerServiceGrpc {
                                     height: size.height as u16,
                                 }
                             }
+                            Some(ross_core::interactive_input::Input::Signal(signal)) => {
+                                tracing::debug!("Forwarding {} from client", signal);
+                                if let Err(e) = service.kill(&container_id, &signal).await {
+                                    tracing::warn!("Failed to forward {}: {}", signal, e);
+                                }
+                                continue;
+                            }
+                            Some(ross_core::interactive_input::Input::CloseStdin(_)) => {
+                                InputEvent::CloseStdin
+                            }
                             Some(ross_core::interactive_input::Input::Start(_)) => {
                                 tracing::warn!("Unexpected start message after session started");
                                 continue;
@@ -494,6 +652,7 @@ impl GrpcContainerService for ContainerServiceGrpc {
 
         // Map container output events to gRPC messages
         let grpc_output = async_stream::stream! {
+            let _cancel_on_drop = cancel_on_drop;
             tracing::debug!("gRPC output stream started");
             while let Some(result) = output_stream.next().await {
                 tracing::debug!("Got output from container service");
@@ -541,7 +700,17 @@ impl GrpcContainerService for ContainerServiceGrpc {
     }
 }
 
-fn into_status(e: ross_container::ContainerError) -> Status {
+/// The values given for each filter key.
+fn filter_values(
+    filters: HashMap<String, ross_core::FilterValues>,
+) -> HashMap<String, Vec<String>> {
+    filters
+        .into_iter()
+        .map(|(key, values)| (key, values.values))
+        .collect()
+}
+
+pub(super) fn into_status(e: ross_container::ContainerError) -> Status {
     match e {
         ross_container::ContainerError::NotFound(_) => Status::not_found(e.to_string()),
         ross_container::ContainerError::AlreadyExists(_) => Status::already_exists(e.to_string()),
@@ -553,14 +722,43 @@ fn into_status(e: ross_container::ContainerError) -> Status {
         ross_container::ContainerError::InvalidArgument(_) => {
             Status::invalid_argument(e.to_string())
         }
-        ross_container::ContainerError::ImageNotFound(_) => Status::not_found(e.to_string()),
+        ross_container::ContainerError::ImageNotFound(_)
+        | ross_container::ContainerError::VolumeNotFound(_) => Status::not_found(e.to_string()),
+        ross_container::ContainerError::VolumeInUse(_) => {
+            Status::failed_precondition(e.to_string())
+        }
+        ross_container::ContainerError::ImageCorrupted(_) => Status::data_loss(e.to_string()),
+        ross_container::ContainerError::PullFailed(_) => Status::unavailable(e.to_string()),
+        ross_container::ContainerError::PullDenied(_) => Status::permission_denied(e.to_string()),
+        ross_container::ContainerError::Shim(e) => shim_status(e),
         ross_container::ContainerError::Io(_)
-        | ross_container::ContainerError::Shim(_)
         | ross_container::ContainerError::Snapshotter(_)
         | ross_container::ContainerError::Store(_) => Status::internal(e.to_string()),
     }
 }
 
+/// Maps runtime errors on their own, so the status says what the runtime
+/// could not do rather than that it failed.
+fn shim_status(e: ross_container::ShimError) -> Status {
+    use ross_container::ShimError;
+    match e {
+        ShimError::ContainerNotFound(_) => Status::not_found(e.to_string()),
+        ShimError::ContainerAlreadyExists(_) => Status::already_exists(e.to_string()),
+        ShimError::ContainerNotRunning(_)
+        | ShimError::InvalidState { .. }
+        | ShimError::PermissionDenied(_)
+        | ShimError::MountFailed(_) => Status::failed_precondition(e.to_string()),
+        ShimError::InvalidMount(_)
+        | ShimError::InvalidNetworkMode(_)
+        | ShimError::InvalidUser(_)
+        | ShimError::InvalidDns(_)
+        | ShimError::InvalidEgress(_)
+        | ShimError::InvalidResources(_) => Status::invalid_argument(e.to_string()),
+        ShimError::NotSupported(_) => Status::unimplemented(e.to_string()),
+        _ => Status::internal(e.to_string()),
+    }
+}
+
 fn container_config_from_grpc(c: ross_core::ContainerConfig) -> ross_container::ContainerConfig {
     ross_container::ContainerConfig {
         hostname: c.hostname,
@@ -574,8 +772,9 @@ fn container_config_from_grpc(c: ross_core::ContainerConfig) -> ross_container::
         open_stdin: c.open_stdin,
         stdin_once: c.stdin_once,
         env: c.env,
-        cmd: c.cmd,
-        entrypoint: c.entrypoint,
+        cmd: (!c.cmd.is_empty()).then_some(c.cmd),
+        // Clients that predate the flag only send the entrypoint they set.
+        entrypoint: (c.entrypoint_set || !c.entrypoint.is_empty()).then_some(c.entrypoint),
         image: c.image,
         labels: c.labels,
         working_dir: c.working_dir,
@@ -584,10 +783,19 @@ fn container_config_from_grpc(c: ross_core::ContainerConfig) -> ross_container::
         stop_signal: c.stop_signal,
         stop_timeout: c.stop_timeout,
         shell: c.shell,
+        healthcheck: c.healthcheck.map(|h| ross_container::HealthConfig {
+            test: h.test,
+            interval: h.interval,
+            timeout: h.timeout,
+            retries: h.retries,
+            start_period: h.start_period,
+        }),
     }
 }
 
 fn host_config_from_grpc(h: ross_core::HostConfig) -> ross_container::HostConfig {
+    let resources = h.resources.unwrap_or_default();
+    let positive = |v: i64| (v > 0).then_some(v);
     ross_container::HostConfig {
         binds: h.binds,
         network_mode: h.network_mode,
@@ -600,6 +808,24 @@ fn host_config_from_grpc(h: ross_core::HostConfig) -> ross_container::HostConfig
         privileged: h.privileged,
         publish_all_ports: h.publish_all_ports,
         readonly_rootfs: h.readonly_rootfs,
+        tmpfs: h.tmpfs,
+        memory_limit: positive(resources.memory),
+        cpu_quota: positive(resources.cpu_quota),
+        cpu_period: positive(resources.cpu_period).map(|p| p as u64),
+        cpus: positive(resources.nano_cpus).map(|n| n as f64 / 1e9),
+        restart_policy: h
+            .restart_policy
+            .map(|p| ross_container::RestartPolicy {
+                name: p.name,
+                maximum_retry_count: p.maximum_retry_count,
+            })
+            .unwrap_or_default(),
+        dns: h.dns,
+        dns_search: h.dns_search,
+        dns_options: h.dns_options,
+        extra_hosts: h.extra_hosts,
+        egress_allow: h.egress_allow,
+        egress_deny: h.egress_deny,
     }
 }
 
@@ -716,7 +942,20 @@ fn container_state_to_grpc(s: ross_container::ContainerState) -> ross_core::Cont
         error: s.error,
         started_at: s.started_at,
         finished_at: s.finished_at,
-        health: None,
+        health: s.health.map(|h| ross_core::Health {
+            status: h.status,
+            failing_streak: h.failing_streak,
+            log: h
+                .log
+                .into_iter()
+                .map(|l| ross_core::HealthLog {
+                    start: l.start,
+                    end: l.end,
+                    exit_code: l.exit_code,
+                    output: l.output,
+                })
+                .collect(),
+        }),
     }
 }
 
@@ -733,8 +972,9 @@ fn container_config_to_grpc(c: ross_container::ContainerConfig) -> ross_core::Co
         open_stdin: c.open_stdin,
         stdin_once: c.stdin_once,
         env: c.env,
-        cmd: c.cmd,
-        entrypoint: c.entrypoint,
+        cmd: c.cmd.unwrap_or_default(),
+        entrypoint_set: c.entrypoint.is_some(),
+        entrypoint: c.entrypoint.unwrap_or_default(),
         image: c.image,
         labels: c.labels,
         volumes: Default::default(),
@@ -744,7 +984,13 @@ fn container_config_to_grpc(c: ross_container::ContainerConfig) -> ross_core::Co
         stop_signal: c.stop_signal,
         stop_timeout: c.stop_timeout,
         shell: c.shell,
-        healthcheck: None,
+        healthcheck: c.healthcheck.map(|h| ross_core::HealthConfig {
+            test: h.test,
+            interval: h.interval,
+            timeout: h.timeout,
+            retries: h.retries,
+            start_period: h.start_period,
+        }),
     }
 }
 
@@ -761,10 +1007,50 @@ fn host_config_to_grpc(h: ross_container::HostConfig) -> ross_core::HostConfig {
         privileged: h.privileged,
         publish_all_ports: h.publish_all_ports,
         readonly_rootfs: h.readonly_rootfs,
+        tmpfs: h.tmpfs,
+        resources: Some(ross_core::Resources {
+            memory: h.memory_limit.unwrap_or_default(),
+            cpu_quota: h.cpu_quota.unwrap_or_default(),
+            cpu_period: h.cpu_period.map(|p| p as i64).unwrap_or_default(),
+            nano_cpus: h.cpus.map(|c| (c * 1e9) as i64).unwrap_or_default(),
+            ..Default::default()
+        }),
+        restart_policy: Some(ross_core::RestartPolicy {
+            name: h.restart_policy.name,
+            maximum_retry_count: h.restart_policy.maximum_retry_count,
+        }),
+        dns: h.dns,
+        dns_search: h.dns_search,
+        dns_options: h.dns_options,
+        extra_hosts: h.extra_hosts,
+        egress_allow: h.egress_allow,
+        egress_deny: h.egress_deny,
         ..Default::default()
     }
 }
 
+fn change_to_grpc(c: ross_container::Change) -> ContainerChange {
+    let kind = match c.kind {
+        ross_container::ChangeKind::Modified => ross_core::ChangeKind::Modified,
+        ross_container::ChangeKind::Added => ross_core::ChangeKind::Added,
+        ross_container::ChangeKind::Deleted => ross_core::ChangeKind::Deleted,
+    };
+    ContainerChange {
+        kind: kind as i32,
+        path: c.path,
+    }
+}
+
+fn volume_to_grpc(v: ross_container::Volume) -> ross_core::Volume {
+    ross_core::Volume {
+        name: v.name,
+        driver: v.driver,
+        mountpoint: v.mountpoint,
+        created_at: v.created,
+        labels: v.labels,
+    }
+}
+
 fn log_entry_to_grpc(l: ross_container::LogEntry) -> LogEntry {
     LogEntry {
         timestamp: Some(l.timestamp),
@@ -773,10 +1059,23 @@ fn log_entry_to_grpc(l: ross_container::LogEntry) -> LogEntry {
     }
 }
 
+fn event_to_grpc(e: ross_container::ContainerEvent) -> ContainerEvent {
+    ContainerEvent {
+        container_id: e.container_id,
+        action: e.action,
+        time: Some(e.time),
+        attributes: e.attributes,
+    }
+}
+
 fn exec_output_to_grpc(e: ross_container::ExecOutput) -> ExecOutput {
     ExecOutput {
         stream: e.stream,
         data: e.data,
+        exit: e.exit.map(|r| ross_core::ExitResult {
+            status_code: r.status_code,
+            error: r.error.map(|msg| ross_core::WaitError { message: msg }),
+        }),
     }
 }
 
@@ -791,7 +1090,7 @@ fn stats_to_grpc(s: ross_container::ContainerStats) -> StatsResponse {
     StatsResponse {
         read: s.read,
         preread: s.preread,
-        pids_stats: None,
+        pids_stats: s.pids_stats.map(pids_stats_to_grpc),
         blkio_stats: None,
         num_procs: s.num_procs,
         storage_stats: None,
@@ -806,6 +1105,13 @@ fn stats_to_grpc(s: ross_container::ContainerStats) -> StatsResponse {
     }
 }
 
+fn pids_stats_to_grpc(p: ross_container::PidsStats) -> ross_core::PidsStats {
+    ross_core::PidsStats {
+        current: p.current,
+        limit: p.limit,
+    }
+}
+
 fn cpu_stats_to_grpc(c: ross_container::CpuStats) -> ross_core::CpuStats {
     ross_core::CpuStats {
         cpu_usage: c.cpu_usage.map(cpu_usage_to_grpc),
@@ -1,25 +1,34 @@
+use ross_container::ContainerService;
 use ross_core::image_service_server::ImageService as GrpcImageService;
 use ross_core::{
-    BuildImageProgress, BuildImageRequest, InspectImageRequest, InspectImageResponse,
-    ListImagesRequest, ListImagesResponse, PullImageProgress, PullImageRequest, PushImageProgress,
-    PushImageRequest, RemoveImageRequest, RemoveImageResponse, SearchImagesRequest,
+    BuildImageProgress, BuildImageRequest, ImportImageRequest, ImportImageResponse,
+    InspectImageRequest, InspectImageResponse, ListImagesRequest, ListImagesResponse,
+    LoadImageRequest, LoadImageResponse, PruneImagesRequest, PruneImagesResponse,
+    PullImageProgress, PullImageRequest, PushImageProgress, PushImageRequest, RemoveImageRequest,
+    RemoveImageResponse, SaveImageChunk, SaveImageRequest, SearchImagesRequest,
     SearchImagesResponse, TagImageRequest, TagImageResponse,
 };
-use ross_image::{BuildParams, ImageService, ListImagesParams, RegistryAuth, SearchParams};
+use ross_image::{
+    BuildParams, ImageService, ImportParams, ListImagesParams, RegistryAuth, SearchParams,
+};
 use std::pin::Pin;
 use std::sync::Arc;
 use tokio_stream::{Stream, StreamExt};
-use tonic::{Request, Response, Status};
+use tonic::{Request, Response, Status, Streaming};
 
 type StreamResult<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
 
 pub struct ImageServiceGrpc {
     service: Arc<ImageService>,
+    containers: Arc<ContainerService>,
 }
 
 impl ImageServiceGrpc {
-    pub fn new(service: Arc<ImageService>) -> Self {
-        Self { service }
+    pub fn new(service: Arc<ImageService>, containers: Arc<ContainerService>) -> Self {
+        Self {
+            service,
+            containers,
+        }
     }
 }
 
@@ -86,7 +95,7 @@ impl GrpcImageService for ImageServiceGrpc {
 
         let stream = self
             .service
-            .pull(&req.image_name, &req.tag, auth)
+            .pull(&req.image_name, &req.tag, auth, Some(&req.platform))
             .map_err(into_status)?;
 
         let output = stream.map(|progress| Ok(pull_progress_to_grpc(progress)));
@@ -162,6 +171,29 @@ impl GrpcImageService for ImageServiceGrpc {
         }))
     }
 
+    async fn prune_images(
+        &self,
+        _request: Request<PruneImagesRequest>,
+    ) -> Result<Response<PruneImagesResponse>, Status> {
+        let lock = self.service.lock_for_prune().await;
+        let in_use = self
+            .containers
+            .snapshots_in_use()
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?;
+
+        let result = self
+            .service
+            .prune(&lock, &in_use)
+            .await
+            .map_err(into_status)?;
+
+        Ok(Response::new(PruneImagesResponse {
+            deleted: result.deleted,
+            space_reclaimed: result.space_reclaimed,
+        }))
+    }
+
     async fn tag_image(
         &self,
         request: Request<TagImageRequest>,
@@ -206,17 +238,90 @@ impl GrpcImageService for ImageServiceGrpc {
             results: results.into_iter().map(search_result_to_grpc).collect(),
         }))
     }
+
+    type SaveImageStream = StreamResult<SaveImageChunk>;
+
+    async fn save_image(
+        &self,
+        request: Request<SaveImageRequest>,
+    ) -> Result<Response<Self::SaveImageStream>, Status> {
+        let req = request.into_inner();
+
+        if req.images.is_empty() {
+            return Err(Status::invalid_argument("images is required"));
+        }
+
+        let stream = self.service.save(&req.images).await.map_err(into_status)?;
+        let output = stream.map(|result| {
+            result
+                .map(|data| SaveImageChunk { data })
+                .map_err(into_status)
+        });
+
+        Ok(Response::new(Box::pin(output)))
+    }
+
+    async fn load_image(
+        &self,
+        request: Request<Streaming<LoadImageRequest>>,
+    ) -> Result<Response<LoadImageResponse>, Status> {
+        let input = request.into_inner().map(|result| {
+            result
+                .map(|req| req.data)
+                .map_err(|e| ross_image::ImageError::InvalidArchive(e.to_string()))
+        });
+
+        let loaded = self.service.load(input).await.map_err(into_status)?;
+
+        Ok(Response::new(LoadImageResponse { loaded }))
+    }
+
+    async fn import_image(
+        &self,
+        request: Request<Streaming<ImportImageRequest>>,
+    ) -> Result<Response<ImportImageResponse>, Status> {
+        let mut requests = request.into_inner();
+        let first = requests
+            .message()
+            .await?
+            .ok_or_else(|| Status::invalid_argument("no rootfs tarball sent"))?;
+        let params = ImportParams {
+            reference: first.reference,
+            entrypoint: first.entrypoint,
+            cmd: first.cmd,
+        };
+
+        let rest = requests.map(|result| {
+            result
+                .map(|req| req.data)
+                .map_err(|e| ross_image::ImageError::InvalidArchive(e.to_string()))
+        });
+        let input = tokio_stream::once(Ok(first.data)).chain(rest);
+
+        let id = self
+            .service
+            .import(params, input)
+            .await
+            .map_err(into_status)?;
+
+        Ok(Response::new(ImportImageResponse { id }))
+    }
 }
 
-fn into_status(e: ross_image::ImageError) -> Status {
+pub(super) fn into_status(e: ross_image::ImageError) -> Status {
     match e {
         ross_image::ImageError::NotFound(_) => Status::not_found(e.to_string()),
-        ross_image::ImageError::InvalidReference(_) => Status::invalid_argument(e.to_string()),
+        ross_image::ImageError::InvalidReference(_) | ross_image::ImageError::InvalidArchive(_) => {
+            Status::invalid_argument(e.to_string())
+        }
+        ross_image::ImageError::Conflict(_) => Status::failed_precondition(e.to_string()),
         ross_image::ImageError::PullFailed(_)
         | ross_image::ImageError::PushFailed(_)
         | ross_image::ImageError::BuildFailed(_) => Status::internal(e.to_string()),
         ross_image::ImageError::Registry(_)
         | ross_image::ImageError::Store(_)
+        | ross_image::ImageError::Snapshotter(_)
+        | ross_image::ImageError::Io(_)
         | ross_image::ImageError::Serialization(_) => Status::internal(e.to_string()),
     }
 }
@@ -277,7 +382,7 @@ fn pull_progress_to_grpc(p: ross_image::PullProgress) -> PullImageProgress {
             .current
             .zip(p.total)
             .map(|(current, total)| ross_core::ProgressDetail { current, total }),
-        error: p.error.unwrap_or_default(),
+        error: p.error.map(|e| e.message).unwrap_or_default(),
     }
 }
 
@@ -1,11 +1,27 @@
+use super::container::into_status as container_status;
+use super::image::into_status as image_status;
+use ross_container::{ContainerService, DiskUsage};
 use ross_core::ross_server::Ross;
-use ross_core::{HealthCheckRequest, HealthCheckResponse};
+use ross_core::{
+    DataUsage, HealthCheckRequest, HealthCheckResponse, SystemDataUsageRequest,
+    SystemDataUsageResponse,
+};
+use ross_image::ImageService;
+use std::sync::Arc;
 use tonic::{Request, Response, Status};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
-#[derive(Default)]
-pub struct RossService;
+pub struct RossService {
+    images: Arc<ImageService>,
+    containers: Arc<ContainerService>,
+}
+
+impl RossService {
+    pub fn new(images: Arc<ImageService>, containers: Arc<ContainerService>) -> Self {
+        Self { images, containers }
+    }
+}
 
 #[tonic::async_trait]
 impl Ross for RossService {
@@ -19,4 +35,50 @@ impl Ross for RossService {
         };
         Ok(Response::new(response))
     }
+
+    async fn system_data_usage(
+        &self,
+        _request: Request<SystemDataUsageRequest>,
+    ) -> Result<Response<SystemDataUsageResponse>, Status> {
+        let in_use = self
+            .containers
+            .images_in_use()
+            .await
+            .map_err(container_status)?;
+        let images = self
+            .images
+            .disk_usage(&in_use)
+            .await
+            .map_err(image_status)?;
+        let containers = self
+            .containers
+            .container_disk_usage()
+            .await
+            .map_err(container_status)?;
+        let volumes = self
+            .containers
+            .volume_disk_usage()
+            .await
+            .map_err(container_status)?;
+
+        Ok(Response::new(SystemDataUsageResponse {
+            images: Some(DataUsage {
+                count: images.count,
+                active: images.active,
+                size: images.size,
+                reclaimable: images.reclaimable,
+            }),
+            containers: Some(data_usage_to_grpc(containers)),
+            volumes: Some(data_usage_to_grpc(volumes)),
+        }))
+    }
+}
+
+fn data_usage_to_grpc(u: DiskUsage) -> DataUsage {
+    DataUsage {
+        count: u.count,
+        active: u.active,
+        size: u.size,
+        reclaimable: u.reclaimable,
+    }
 }
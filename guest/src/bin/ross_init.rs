@@ -5,76 +5,16 @@
 //! setup and I/O forwarding to the host via vsock.
 //!
 //! Usage:
+//!   ross-init --config /path/to/config.json
 //!   ross-init '<json-config>'
 //!   ROSS_GUEST_CONFIG='<json-config>' ross-init
 
-use ross_guest::{tty, GuestConfig};
+use ross_guest::{tty, volumes, ConfigError, GuestConfig};
 use std::env;
 use std::process::ExitCode;
 
 const CONFIG_FILE_PATH: &str = "/.ross-config.json";
 
-#[cfg(target_os = "linux")]
-fn mount_volumes(config: &GuestConfig) -> ExitCode {
-    use nix::mount::{mount, MsFlags};
-
-    for v in &config.volumes {
-        if v.tag.is_empty() || v.target.is_empty() {
-            eprintln!("ross-init: skipping invalid volume entry: {:?}", v);
-            continue;
-        }
-        if !v.target.starts_with('/') {
-            eprintln!("ross-init: volume target must be absolute: {}", v.target);
-            return ExitCode::from(1);
-        }
-
-        if let Err(e) = std::fs::create_dir_all(&v.target) {
-            eprintln!("ross-init: failed to create mountpoint {}: {}", v.target, e);
-            return ExitCode::from(1);
-        }
-
-        let mut flags = MsFlags::MS_NOSUID | MsFlags::MS_NODEV;
-        if v.read_only {
-            flags |= MsFlags::MS_RDONLY;
-        }
-
-        if let Err(e) = mount(
-            Some(v.tag.as_str()),
-            v.target.as_str(),
-            Some("virtiofs"),
-            flags,
-            None::<&str>,
-        ) {
-            eprintln!(
-                "ross-init: failed to mount virtiofs tag '{}' at '{}': {}",
-                v.tag, v.target, e
-            );
-            return ExitCode::from(1);
-        }
-
-        eprintln!(
-            "ross-init: mounted volume tag '{}' at '{}'{}",
-            v.tag,
-            v.target,
-            if v.read_only { " (ro)" } else { "" }
-        );
-    }
-    ExitCode::from(0)
-}
-
-#[cfg(not(target_os = "linux"))]
-fn mount_volumes(config: &GuestConfig) -> ExitCode {
-    // `ross-init` is meant to run inside the Linux guest. When we compile/test
-    // this crate on the host (e.g. macOS), we just no-op unless volumes were
-    // requested (which would indicate a misconfiguration).
-    if config.volumes.is_empty() {
-        ExitCode::from(0)
-    } else {
-        eprintln!("ross-init: volume mounts are only supported on Linux guests");
-        ExitCode::from(1)
-    }
-}
-
 fn setup_loopback() {
     // Bring up the loopback interface for localhost connectivity.
     // This mirrors what libkrun's init does.
@@ -176,6 +116,16 @@ fn write_sysctl(path: &str, value: &str) {
     }
 }
 
+/// Replaces the image's `path` with `contents`. The image's file may be a
+/// symlink into a directory that does not exist in the container, so it is
+/// removed rather than written through.
+fn write_etc_file(path: &str, contents: &str) {
+    let _ = std::fs::remove_file(path);
+    if let Err(e) = std::fs::write(path, contents) {
+        eprintln!("ross-init: failed to write {}: {}", path, e);
+    }
+}
+
 #[cfg(target_os = "linux")]
 fn tune_tcp_buffers() {
     // Make high-bandwidth localhost/host networking fast without requiring user flags like:
@@ -208,13 +158,27 @@ fn tune_tcp_buffers() {
 #[cfg(not(target_os = "linux"))]
 fn tune_tcp_buffers() {}
 
+/// The configuration JSON, read from the file `--config` names. Without
+/// the flag it comes from argv[1] if that looks like JSON, the environment,
+/// or the default config file, in that order.
+fn config_json() -> Result<Option<String>, ConfigError> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Some(path) = ross_guest::config_path(&args)? {
+        return ross_guest::read_config_file(path).map(Some);
+    }
+
+    Ok(args
+        .first()
+        .filter(|arg| arg.starts_with('{'))
+        .cloned()
+        .or_else(|| env::var("ROSS_GUEST_CONFIG").ok())
+        .or_else(|| std::fs::read_to_string(CONFIG_FILE_PATH).ok()))
+}
+
 fn main() -> ExitCode {
     // Set up loopback interface before anything else
     setup_loopback();
 
-    // Try to set up eth0 and get IP via DHCP (for gvproxy/passt networking)
-    setup_eth0();
-    run_dhcp_client();
     tune_tcp_buffers();
 
     eprintln!("ross-init: starting");
@@ -242,53 +206,63 @@ fn main() -> ExitCode {
         Err(_) => eprintln!("ross-init: env var not set"),
     }
 
-    // Read config from: 1) command line (if it looks like JSON), 2) env var, 3) config file
-    // Skip argv[1] if it looks like a path (starts with /)
-    let config_json = env::args()
-        .nth(1)
-        .filter(|arg| arg.starts_with('{'))
-        .or_else(|| env::var("ROSS_GUEST_CONFIG").ok())
-        .or_else(|| std::fs::read_to_string(CONFIG_FILE_PATH).ok());
-
-    let config_json = match config_json {
-        Some(json) => json,
-        None => {
+    let config_json = match config_json() {
+        Ok(Some(json)) => json,
+        Ok(None) => {
             eprintln!("ross-init: no configuration provided");
-            eprintln!("Usage: ross-init '<json-config>'");
+            eprintln!("Usage: ross-init --config <path>");
+            eprintln!("   or: ross-init '<json-config>'");
             eprintln!("   or: ROSS_GUEST_CONFIG='<json>' ross-init");
             eprintln!("   or: place config at {}", CONFIG_FILE_PATH);
             return ExitCode::from(1);
         }
+        Err(e) => {
+            eprintln!("ross-init: {}", e);
+            return ExitCode::from(1);
+        }
     };
 
-    let config: GuestConfig = match serde_json::from_str(&config_json) {
+    let config = match GuestConfig::from_json(&config_json) {
         Ok(c) => c,
         Err(e) => {
-            eprintln!("ross-init: failed to parse config: {}", e);
-            eprintln!(
-                "ross-init: config_json len = {}, first 200 chars: {:?}",
-                config_json.len(),
-                &config_json[..config_json.len().min(200)]
-            );
+            let message = format!("ross-init: {}\n", e);
+            eprint!("{}", message);
+            // The host is waiting on the vsock; tell it why nothing runs
+            // when the config names the port, however broken the rest is.
+            if let Some(port) = ross_guest::vsock_port_of(&config_json)
+                && let Err(e) = tty::report_failure(port, &message, 1)
+            {
+                eprintln!("ross-init: failed to report the error to the host: {}", e);
+            }
             return ExitCode::from(1);
         }
     };
 
-    // Validate config
-    if config.command.is_empty() {
-        eprintln!("ross-init: command is empty");
-        return ExitCode::from(1);
+    // Try to set up eth0 and get IP via DHCP (for gvproxy/passt networking)
+    if config.network_disabled {
+        eprintln!("ross-init: networking disabled, only loopback is configured");
+    } else {
+        setup_eth0();
+        run_dhcp_client();
     }
 
-    if config.vsock_port == 0 {
-        eprintln!("ross-init: vsock_port is required for interactive mode");
-        return ExitCode::from(1);
+    // Written after DHCP, whose client may have configured the resolver.
+    if let Some(resolv_conf) = &config.resolv_conf {
+        write_etc_file("/etc/resolv.conf", resolv_conf);
+    }
+    if let Some(hosts) = &config.hosts {
+        write_etc_file("/etc/hosts", hosts);
     }
 
-    // Mount requested virtio-fs volumes before starting the workload
-    let mount_status = mount_volumes(&config);
-    if mount_status != ExitCode::from(0) {
-        return mount_status;
+    // Mount requested virtio-fs volumes before starting the workload. The
+    // container must not run without one, and the host is told why.
+    if let Err(e) = volumes::mount_volumes(&config.volumes) {
+        let message = format!("ross-init: {}\n", e);
+        eprint!("{}", message);
+        if let Err(e) = tty::report_start_failure(&config, &message, 1) {
+            eprintln!("ross-init: failed to report the error to the host: {}", e);
+        }
+        return ExitCode::from(1);
     }
 
     // Run the command
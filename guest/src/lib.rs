@@ -1,12 +1,13 @@
 //! Ross guest init - runs inside the VM to handle interactive containers.
 //!
 //! This binary is placed in the container rootfs and executed by libkrun.
-//! It reads configuration from the environment or command line, then
-//! spawns the requested command with proper TTY/pipe setup and forwards
-//! I/O to the host via vsock.
+//! It reads configuration from the file named by `--config`, falling back
+//! to the environment or command line, then spawns the requested command
+//! with proper TTY/pipe setup and forwards I/O to the host via vsock.
 
 pub mod protocol;
 pub mod tty;
+pub mod volumes;
 
 use serde::{Deserialize, Serialize};
 
@@ -33,4 +34,164 @@ pub struct GuestConfig {
     pub vsock_port: u32,
     #[serde(default)]
     pub volumes: Vec<VolumeMount>,
+    /// The container has no network: only loopback is configured.
+    #[serde(default)]
+    pub network_disabled: bool,
+    /// Contents of `/etc/resolv.conf`, replacing the image's.
+    #[serde(default)]
+    pub resolv_conf: Option<String>,
+    /// Contents of `/etc/hosts`, replacing the image's.
+    #[serde(default)]
+    pub hosts: Option<String>,
+}
+
+/// Why the guest configuration could not be used.
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("failed to read config {path}: {source}")]
+    Read {
+        path: String,
+        source: std::io::Error,
+    },
+
+    #[error("invalid config: {0}")]
+    Parse(#[from] serde_json::Error),
+
+    #[error("invalid config: {0}")]
+    Invalid(&'static str),
+}
+
+impl GuestConfig {
+    /// Parses a configuration from JSON and checks it can be run.
+    pub fn from_json(json: &str) -> Result<Self, ConfigError> {
+        let config: GuestConfig = serde_json::from_str(json)?;
+        if config.command.is_empty() {
+            return Err(ConfigError::Invalid("command is empty"));
+        }
+        if config.vsock_port == 0 {
+            return Err(ConfigError::Invalid("vsock_port is required"));
+        }
+        Ok(config)
+    }
+}
+
+/// The configuration file named by `--config PATH` or `--config=PATH`
+/// anywhere in `args`, which excludes the program name. The last one wins.
+pub fn config_path(args: &[String]) -> Result<Option<&str>, ConfigError> {
+    let mut path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            let value = args
+                .next()
+                .ok_or(ConfigError::Invalid("--config requires a path"))?;
+            path = Some(value.as_str());
+        } else if let Some(value) = arg.strip_prefix("--config=") {
+            path = Some(value);
+        }
+    }
+    if path == Some("") {
+        return Err(ConfigError::Invalid("--config requires a path"));
+    }
+    Ok(path)
+}
+
+/// Reads a configuration file, as `--config` names it.
+pub fn read_config_file(path: &str) -> Result<String, ConfigError> {
+    std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+        path: path.to_string(),
+        source,
+    })
+}
+
+/// The vsock port the host listens on, picked out of a configuration that
+/// may not parse as a whole so its errors can still reach the host.
+pub fn vsock_port_of(json: &str) -> Option<u32> {
+    let config: serde_json::Value = serde_json::from_str(json).ok()?;
+    let port = config.get("vsock_port")?.as_u64()?;
+    u32::try_from(port).ok().filter(|port| *port != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_from_json() {
+        let config = GuestConfig::from_json(
+            r#"{"command":"/bin/sh","args":["-c","true"],"vsock_port":1024}"#,
+        )
+        .unwrap();
+        assert_eq!(config.command, "/bin/sh");
+        assert_eq!(config.vsock_port, 1024);
+        assert!(!config.tty);
+        assert!(config.env.is_empty());
+    }
+
+    #[test]
+    fn test_from_json_rejects_unrunnable_config() {
+        let err =
+            GuestConfig::from_json(r#"{"command":"","args":[],"vsock_port":1024}"#).unwrap_err();
+        assert!(matches!(err, ConfigError::Invalid("command is empty")));
+
+        let err = GuestConfig::from_json(r#"{"command":"/bin/sh","args":[],"vsock_port":0}"#)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            ConfigError::Invalid("vsock_port is required")
+        ));
+
+        let err = GuestConfig::from_json(r#"{"command":"/bin/sh"}"#).unwrap_err();
+        assert!(matches!(err, ConfigError::Parse(_)));
+    }
+
+    #[test]
+    fn test_vsock_port_of() {
+        // Missing fields and wrong types elsewhere don't hide the port.
+        assert_eq!(vsock_port_of(r#"{"vsock_port":1024}"#), Some(1024));
+        assert_eq!(
+            vsock_port_of(r#"{"command":5,"args":null,"vsock_port":1024}"#),
+            Some(1024)
+        );
+
+        assert_eq!(vsock_port_of(r#"{"command":"/bin/sh"}"#), None);
+        assert_eq!(vsock_port_of(r#"{"vsock_port":0}"#), None);
+        assert_eq!(vsock_port_of(r#"{"vsock_port":"1024"}"#), None);
+        assert_eq!(vsock_port_of(r#"{"vsock_port":4294967296}"#), None);
+        assert_eq!(vsock_port_of(r#"{"vsock_port":1024"#), None);
+        assert_eq!(vsock_port_of("not json"), None);
+    }
+
+    #[test]
+    fn test_config_path() {
+        assert_eq!(config_path(&args(&[])).unwrap(), None);
+        assert_eq!(config_path(&args(&[r#"{"command":"sh"}"#])).unwrap(), None);
+        assert_eq!(
+            config_path(&args(&["--config", "/a.json"])).unwrap(),
+            Some("/a.json")
+        );
+        assert_eq!(
+            config_path(&args(&["--config=/a.json"])).unwrap(),
+            Some("/a.json")
+        );
+        assert_eq!(
+            config_path(&args(&["-v", "--config", "/a.json", "x"])).unwrap(),
+            Some("/a.json")
+        );
+        assert_eq!(
+            config_path(&args(&["--config", "/a.json", "--config=/b.json"])).unwrap(),
+            Some("/b.json")
+        );
+
+        for bad in [&["--config"][..], &["-v", "--config"], &["--config="]] {
+            assert!(matches!(
+                config_path(&args(bad)),
+                Err(ConfigError::Invalid("--config requires a path"))
+            ));
+        }
+    }
 }
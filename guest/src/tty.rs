@@ -84,6 +84,36 @@ fn connect_vsock(port: u32) -> std::io::Result<RawFd> {
     }
 }
 
+/// Writes all of `data` to the non-blocking vsock, waiting for room when the
+/// host reads slower than the command writes.
+fn write_vsock(vsock: &mut File, mut data: &[u8]) -> std::io::Result<()> {
+    while !data.is_empty() {
+        match vsock.write(data) {
+            Ok(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+            Ok(n) => data = &data[n..],
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                let mut fd = libc::pollfd {
+                    fd: vsock.as_raw_fd(),
+                    events: libc::POLLOUT,
+                    revents: 0,
+                };
+                unsafe { libc::poll(&mut fd, 1, -1) };
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Sends the command's output to the host as it is read, in one write.
+fn send_output(vsock: &mut File, opcode: u16, data: &[u8]) -> std::io::Result<()> {
+    let mut frame = Vec::with_capacity(2 + data.len());
+    frame.extend_from_slice(&encode_write_cmd(opcode, data.len()).to_le_bytes());
+    frame.extend_from_slice(data);
+    write_vsock(vsock, &frame)
+}
+
 fn openpty() -> std::io::Result<(RawFd, RawFd)> {
     let mut master: libc::c_int = 0;
     let mut slave: libc::c_int = 0;
@@ -166,20 +196,16 @@ fn run_io_loop_tty(
                     // PTY closed
                     if let Some(code) = exit_code {
                         let cmd = encode_exit_cmd(code as u8);
-                        let _ = vsock.write_all(&cmd.to_le_bytes());
+                        let _ = write_vsock(vsock, &cmd.to_le_bytes());
                         return Ok(code);
                     }
                 }
-                Ok(n) => {
-                    let cmd = encode_write_cmd(CMD_WRITE_STDOUT, n);
-                    vsock.write_all(&cmd.to_le_bytes())?;
-                    vsock.write_all(&buf[..n])?;
-                }
+                Ok(n) => send_output(vsock, CMD_WRITE_STDOUT, &buf[..n])?,
                 Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
                 Err(_) => {
                     if let Some(code) = exit_code {
                         let cmd = encode_exit_cmd(code as u8);
-                        let _ = vsock.write_all(&cmd.to_le_bytes());
+                        let _ = write_vsock(vsock, &cmd.to_le_bytes());
                         return Ok(code);
                     }
                 }
@@ -190,7 +216,7 @@ fn run_io_loop_tty(
             // PTY closed
             let code = exit_code.unwrap_or(0);
             let cmd = encode_exit_cmd(code as u8);
-            let _ = vsock.write_all(&cmd.to_le_bytes());
+            let _ = write_vsock(vsock, &cmd.to_le_bytes());
             return Ok(code);
         }
 
@@ -236,7 +262,7 @@ fn run_io_loop_tty(
         if exit_code.is_some() && poll_result == 0 {
             let code = exit_code.unwrap();
             let cmd = encode_exit_cmd(code as u8);
-            let _ = vsock.write_all(&cmd.to_le_bytes());
+            let _ = write_vsock(vsock, &cmd.to_le_bytes());
             return Ok(code);
         }
     }
@@ -312,11 +338,7 @@ fn run_io_loop_pipes(
             let mut buf = [0u8; 4096];
             match stdout_pipe.read(&mut buf) {
                 Ok(0) => stdout_closed = true,
-                Ok(n) => {
-                    let cmd = encode_write_cmd(CMD_WRITE_STDOUT, n);
-                    vsock.write_all(&cmd.to_le_bytes())?;
-                    vsock.write_all(&buf[..n])?;
-                }
+                Ok(n) => send_output(vsock, CMD_WRITE_STDOUT, &buf[..n])?,
                 Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
                 Err(_) => stdout_closed = true,
             }
@@ -330,11 +352,7 @@ fn run_io_loop_pipes(
             let mut buf = [0u8; 4096];
             match stderr_pipe.read(&mut buf) {
                 Ok(0) => stderr_closed = true,
-                Ok(n) => {
-                    let cmd = encode_write_cmd(CMD_WRITE_STDERR, n);
-                    vsock.write_all(&cmd.to_le_bytes())?;
-                    vsock.write_all(&buf[..n])?;
-                }
+                Ok(n) => send_output(vsock, CMD_WRITE_STDERR, &buf[..n])?,
                 Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
                 Err(_) => stderr_closed = true,
             }
@@ -354,15 +372,28 @@ fn run_io_loop_pipes(
             let cmd = u16::from_le_bytes(cmd_buf);
             let (opcode, value) = decode_cmd(cmd);
 
-            if opcode == CMD_WRITE_STDIN {
-                if value == 0 {
-                    *stdin_pipe = None;
-                } else if let Some(stdin) = stdin_pipe {
+            match opcode {
+                // A zero-length write is EOF: dropping the pipe closes the
+                // child's stdin.
+                CMD_WRITE_STDIN if value == 0 => *stdin_pipe = None,
+                CMD_WRITE_STDIN => {
+                    // Always consume the payload to stay in sync with the host,
+                    // even when stdin was already closed.
                     let mut data = vec![0u8; value];
-                    if vsock.read_exact(&mut data).is_ok() {
-                        let _ = stdin.write_all(&data);
+                    if vsock.read_exact(&mut data).is_ok()
+                        && let Some(stdin) = stdin_pipe
+                        && stdin.write_all(&data).is_err()
+                    {
+                        // The child closed its stdin.
+                        *stdin_pipe = None;
                     }
                 }
+                CMD_UPDATE_SIZE => {
+                    // No terminal to resize without a PTY.
+                    let mut size_buf = [0u8; 4];
+                    let _ = vsock.read_exact(&mut size_buf);
+                }
+                _ => {}
             }
         }
 
@@ -375,12 +406,37 @@ fn run_io_loop_pipes(
         if exit_code.is_some() && stdout_closed && stderr_closed {
             let code = exit_code.unwrap();
             let cmd = encode_exit_cmd(code as u8);
-            let _ = vsock.write_all(&cmd.to_le_bytes());
+            let _ = write_vsock(vsock, &cmd.to_le_bytes());
             return Ok(code);
         }
     }
 }
 
+/// Reports to the host that the command could not be started. The host
+/// waits for the guest to connect before anything else, so this connects,
+/// writes `message` to stderr, then exits with `exit_code`.
+pub fn report_start_failure(
+    config: &GuestConfig,
+    message: &str,
+    exit_code: u8,
+) -> std::io::Result<()> {
+    report_failure(config.vsock_port, message, exit_code)
+}
+
+/// Like `report_start_failure`, for when only the vsock port of the
+/// configuration is known, such as when the rest of it is invalid.
+pub fn report_failure(vsock_port: u32, message: &str, exit_code: u8) -> std::io::Result<()> {
+    let vsock_fd = connect_vsock(vsock_port)?;
+    let mut vsock = unsafe { File::from_raw_fd(vsock_fd) };
+
+    for chunk in message.as_bytes().chunks(4096) {
+        let cmd = encode_write_cmd(CMD_WRITE_STDERR, chunk.len());
+        vsock.write_all(&cmd.to_le_bytes())?;
+        vsock.write_all(chunk)?;
+    }
+    vsock.write_all(&encode_exit_cmd(exit_code).to_le_bytes())
+}
+
 /// Run a command and forward I/O via vsock.
 ///
 /// This is the main entry point for the guest init process.
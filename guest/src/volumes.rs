@@ -0,0 +1,112 @@
+//! Mounting the container's volumes, virtio-fs shares the host sets up for
+//! each of them.
+//!
+//! The volumes are mounted before the command runs, and any of them failing
+//! fails the container: running without a volume would have the command
+//! write to the root filesystem what it means to keep.
+
+use crate::VolumeMount;
+use std::collections::HashSet;
+use std::path::{Component, Path};
+
+#[derive(Debug, thiserror::Error)]
+pub enum MountError {
+    #[error("invalid volume: tag {tag:?}, target {target:?}")]
+    Invalid { tag: String, target: String },
+
+    #[error("volume target must be an absolute path without '..': {0}")]
+    InvalidTarget(String),
+
+    #[error("volume target {0} is mounted more than once")]
+    DuplicateTarget(String),
+
+    #[error("failed to create mountpoint {target}: {source}")]
+    Mountpoint {
+        target: String,
+        source: std::io::Error,
+    },
+
+    #[error("failed to mount virtiofs tag '{tag}' at {target}: {source}")]
+    Mount {
+        tag: String,
+        target: String,
+        source: std::io::Error,
+    },
+
+    #[error("volume mounts are only supported on Linux guests")]
+    Unsupported,
+}
+
+/// Checks every volume before any is mounted.
+fn validate(volumes: &[VolumeMount]) -> Result<(), MountError> {
+    let mut targets = HashSet::new();
+    for v in volumes {
+        if v.tag.is_empty() || v.target.is_empty() {
+            return Err(MountError::Invalid {
+                tag: v.tag.clone(),
+                target: v.target.clone(),
+            });
+        }
+        let target = Path::new(&v.target);
+        if !target.is_absolute() || target.components().any(|c| c == Component::ParentDir) {
+            return Err(MountError::InvalidTarget(v.target.clone()));
+        }
+        if !targets.insert(target.components().collect::<Vec<_>>()) {
+            return Err(MountError::DuplicateTarget(v.target.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// Mounts each volume's virtio-fs tag at its target, creating the
+/// directory, and read-only when the volume is.
+#[cfg(target_os = "linux")]
+pub fn mount_volumes(volumes: &[VolumeMount]) -> Result<(), MountError> {
+    use nix::mount::{MsFlags, mount};
+
+    validate(volumes)?;
+
+    for v in volumes {
+        std::fs::create_dir_all(&v.target).map_err(|source| MountError::Mountpoint {
+            target: v.target.clone(),
+            source,
+        })?;
+
+        let mut flags = MsFlags::MS_NOSUID | MsFlags::MS_NODEV;
+        if v.read_only {
+            flags |= MsFlags::MS_RDONLY;
+        }
+        mount(
+            Some(v.tag.as_str()),
+            v.target.as_str(),
+            Some("virtiofs"),
+            flags,
+            None::<&str>,
+        )
+        .map_err(|e| MountError::Mount {
+            tag: v.tag.clone(),
+            target: v.target.clone(),
+            source: e.into(),
+        })?;
+
+        eprintln!(
+            "ross-init: mounted volume tag '{}' at '{}'{}",
+            v.tag,
+            v.target,
+            if v.read_only { " (ro)" } else { "" }
+        );
+    }
+    Ok(())
+}
+
+/// `ross-init` runs inside the Linux guest; built for the host, it only
+/// accepts running without volumes.
+#[cfg(not(target_os = "linux"))]
+pub fn mount_volumes(volumes: &[VolumeMount]) -> Result<(), MountError> {
+    validate(volumes)?;
+    if volumes.is_empty() {
+        Ok(())
+    } else {
+        Err(MountError::Unsupported)
+    }
+}
@@ -0,0 +1,446 @@
+//! Image archives: OCI image layouts and the docker-archive format written by
+//! `docker save`, both as tar files.
+
+use crate::error::ImageError;
+use ross_remote::Platform;
+use ross_store::{BlobWriter, Digest, FileSystemStore};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+use tokio::sync::mpsc;
+use tokio_stream::{Stream, StreamExt};
+use tokio_util::io::SyncIoBridge;
+
+/// Annotation containerd and `docker save` use for the full image name.
+pub(crate) const ANNOTATION_IMAGE_NAME: &str = "io.containerd.image.name";
+/// OCI annotation for the reference of a manifest in an image layout.
+pub(crate) const ANNOTATION_REF_NAME: &str = "org.opencontainers.image.ref.name";
+
+/// Contents of the `oci-layout` file.
+pub(crate) const OCI_LAYOUT: &[u8] = br#"{"imageLayoutVersion":"1.0.0"}"#;
+
+/// Largest piece of an archive sent in one message while streaming it.
+pub(crate) const CHUNK_SIZE: usize = 1024 * 1024;
+
+const BLOCK_SIZE: usize = 512;
+
+/// The `index.json` of an OCI image layout.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct OciIndex {
+    pub schema_version: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<String>,
+    pub manifests: Vec<IndexEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct IndexEntry {
+    pub media_type: String,
+    pub digest: String,
+    pub size: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform: Option<Platform>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub annotations: HashMap<String, String>,
+}
+
+/// An image in a docker-archive `manifest.json`. Paths are relative to the
+/// root of the archive.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub(crate) struct DockerManifestEntry {
+    pub config: String,
+    #[serde(default)]
+    pub repo_tags: Option<Vec<String>>,
+    pub layers: Vec<String>,
+}
+
+/// What reading an archive left behind: every file but the top-level
+/// metadata lands in the store as a blob.
+#[derive(Default)]
+pub(crate) struct ArchiveContents {
+    /// Digest and size of each stored file, by path in the archive.
+    pub blobs: HashMap<String, (Digest, i64)>,
+    /// JSON and layout files at the root of the archive, by name.
+    pub metadata: HashMap<String, Vec<u8>>,
+}
+
+impl ArchiveContents {
+    /// Contents of the file at `path` in the archive.
+    pub async fn read(&self, store: &FileSystemStore, path: &str) -> Result<Vec<u8>, ImageError> {
+        if let Some(data) = self.metadata.get(path) {
+            return Ok(data.clone());
+        }
+        match self.blobs.get(path) {
+            Some((digest, _)) => Ok(store.get_blob(digest, 0, -1).await?),
+            None => Err(ImageError::InvalidArchive(format!("missing file {}", path))),
+        }
+    }
+}
+
+/// Path of the blob with `digest` in an OCI image layout.
+pub(crate) fn blob_path(digest: &str) -> String {
+    format!("blobs/{}", digest.replacen(':', "/", 1))
+}
+
+/// Store digest `digest` ("sha256:<hex>") refers to.
+pub(crate) fn parse_digest(digest: &str) -> Result<Digest, ImageError> {
+    match digest.split_once(':') {
+        Some((algorithm, hash)) if !hash.is_empty() => Ok(Digest {
+            algorithm: algorithm.to_string(),
+            hash: hash.to_string(),
+        }),
+        _ => Err(ImageError::InvalidArchive(format!(
+            "invalid digest {}",
+            digest
+        ))),
+    }
+}
+
+/// Reads the tar archive at `path`, storing every file except the metadata
+/// at its root as a blob. Files under `blobs/<algorithm>/<hash>` must match
+/// the digest their path names.
+///
+/// Files are streamed into the store in pieces of at most [`CHUNK_SIZE`].
+pub(crate) async fn read_archive(
+    store: &FileSystemStore,
+    path: &Path,
+) -> Result<ArchiveContents, ImageError> {
+    let (tx, mut rx) = mpsc::channel(4);
+    let path = path.to_path_buf();
+    let reader = tokio::task::spawn_blocking(move || read_files(&path, &tx));
+
+    let mut contents = ArchiveContents::default();
+    let mut current = None;
+    while let Some(piece) = rx.recv().await {
+        match piece {
+            ArchivePiece::File(name) => {
+                if let Some(file) = current.take() {
+                    contents.add(file).await?;
+                }
+                current = Some(ArchiveFile::create(store, name).await?);
+            }
+            ArchivePiece::Data(data) => {
+                if let Some(file) = &mut current {
+                    file.write(&data).await?;
+                }
+            }
+        }
+    }
+
+    reader
+        .await
+        .map_err(|e| ImageError::InvalidArchive(format!("reader task failed: {}", e)))??;
+    // Only complete once the reader got to the end of the archive.
+    if let Some(file) = current {
+        contents.add(file).await?;
+    }
+    Ok(contents)
+}
+
+/// What [`read_files`] sends: the start of a regular file, then its data.
+enum ArchivePiece {
+    File(String),
+    Data(Vec<u8>),
+}
+
+/// A file of an archive being read.
+enum ArchiveFile<'a> {
+    Metadata(String, Vec<u8>),
+    Blob(String, Box<BlobWriter<'a>>),
+}
+
+impl<'a> ArchiveFile<'a> {
+    async fn create(store: &'a FileSystemStore, name: String) -> Result<Self, ImageError> {
+        if is_metadata(&name) {
+            return Ok(ArchiveFile::Metadata(name, Vec::new()));
+        }
+        let expected = name
+            .strip_prefix("blobs/")
+            .map(|digest| parse_digest(&digest.replacen('/', ":", 1)))
+            .transpose()?;
+        let writer = store
+            .blob_writer("application/octet-stream", expected.as_ref())
+            .await?;
+        Ok(ArchiveFile::Blob(name, Box::new(writer)))
+    }
+
+    async fn write(&mut self, data: &[u8]) -> Result<(), ImageError> {
+        match self {
+            ArchiveFile::Metadata(_, content) => content.extend_from_slice(data),
+            ArchiveFile::Blob(_, writer) => writer.write(data).await?,
+        }
+        Ok(())
+    }
+}
+
+impl ArchiveContents {
+    async fn add(&mut self, file: ArchiveFile<'_>) -> Result<(), ImageError> {
+        match file {
+            ArchiveFile::Metadata(name, content) => {
+                self.metadata.insert(name, content);
+            }
+            ArchiveFile::Blob(name, writer) => {
+                self.blobs.insert(name, writer.commit().await?);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Sends every regular file in the archive at `path` to `tx`, stopping early
+/// once the receiver is gone.
+fn read_files(path: &Path, tx: &mpsc::Sender<ArchivePiece>) -> Result<(), ImageError> {
+    let mut archive = tar::Archive::new(std::fs::File::open(path)?);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path()?.to_string_lossy().to_string();
+        let name = name.trim_start_matches("./").to_string();
+        if tx.blocking_send(ArchivePiece::File(name)).is_err() {
+            return Ok(());
+        }
+        loop {
+            let mut data = vec![0; CHUNK_SIZE.min(entry.size() as usize).max(1)];
+            let n = entry.read(&mut data)?;
+            if n == 0 {
+                break;
+            }
+            data.truncate(n);
+            if tx.blocking_send(ArchivePiece::Data(data)).is_err() {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn is_metadata(name: &str) -> bool {
+    !name.contains('/')
+        && (name.ends_with(".json") || name == "oci-layout" || name == "repositories")
+}
+
+/// Splits a regular file of an archive being written into chunks: its tar
+/// header, the data in pieces of at most [`CHUNK_SIZE`], and the padding to
+/// the next block.
+pub(crate) fn tar_file(path: &str, data: &[u8]) -> Result<Vec<Vec<u8>>, ImageError> {
+    let mut chunks = vec![tar_header(path, data.len() as u64)?];
+    chunks.extend(data.chunks(CHUNK_SIZE).map(<[u8]>::to_vec));
+    let padding = tar_padding(data.len() as u64);
+    if !padding.is_empty() {
+        chunks.push(padding);
+    }
+    Ok(chunks)
+}
+
+/// The tar header of a regular file of `size` bytes at `path`.
+pub(crate) fn tar_header(path: &str, size: u64) -> Result<Vec<u8>, ImageError> {
+    let mut header = tar::Header::new_ustar();
+    header.set_path(path)?;
+    header.set_size(size);
+    header.set_mode(0o644);
+    header.set_uid(0);
+    header.set_gid(0);
+    header.set_mtime(0);
+    header.set_entry_type(tar::EntryType::Regular);
+    header.set_cksum();
+    Ok(header.as_bytes().to_vec())
+}
+
+/// The zeros following a file of `size` bytes up to the next block.
+pub(crate) fn tar_padding(size: u64) -> Vec<u8> {
+    let block = BLOCK_SIZE as u64;
+    vec![0; ((block - size % block) % block) as usize]
+}
+
+/// The two zero blocks that end a tar archive.
+pub(crate) fn tar_trailer() -> Vec<u8> {
+    vec![0; 2 * BLOCK_SIZE]
+}
+
+/// Whether `data` starts with the gzip magic number.
+pub(crate) fn is_gzip(data: &[u8]) -> bool {
+    data.starts_with(&[0x1f, 0x8b])
+}
+
+/// Stores the tar archive `input` streams as an uncompressed blob of
+/// `media_type`, gunzipping it on the way if it is gzipped. Every entry
+/// header is read as it passes, so that anything but a tar archive is
+/// refused; the blob is only committed once the whole input was read.
+pub(crate) async fn store_tarball<S>(
+    store: &FileSystemStore,
+    input: S,
+    media_type: &str,
+) -> Result<(Digest, i64), ImageError>
+where
+    S: Stream<Item = Result<Vec<u8>, ImageError>>,
+{
+    use tokio::io::AsyncWriteExt;
+
+    let (mut pipe, tarball) = tokio::io::duplex(CHUNK_SIZE);
+    let (tx, mut rx) = mpsc::channel(4);
+    let reader = tokio::task::spawn_blocking(move || read_tarball(SyncIoBridge::new(tarball), &tx));
+
+    // Moves the pipe in, so that the reader sees the end of the input once
+    // this is done, whether or not the input was read whole.
+    let feed = async move {
+        tokio::pin!(input);
+        while let Some(chunk) = input.next().await {
+            // The reader stopped early, and says why.
+            if pipe.write_all(&chunk?).await.is_err() {
+                break;
+            }
+        }
+        Ok::<_, ImageError>(())
+    };
+    let write = async {
+        let mut writer = store.blob_writer(media_type, None).await?;
+        while let Some(data) = rx.recv().await {
+            writer.write(&data).await?;
+        }
+        Ok::<_, ImageError>(writer)
+    };
+    let (fed, writer) = tokio::join!(feed, write);
+
+    fed?;
+    let writer = writer?;
+    reader
+        .await
+        .map_err(|e| ImageError::InvalidArchive(format!("reader task failed: {}", e)))??;
+    Ok(writer.commit().await?)
+}
+
+/// Sends the tar archive `input`, gunzipped if need be, to `tx` in pieces of
+/// at most [`CHUNK_SIZE`], checking that it is one as it goes.
+fn read_tarball(mut input: impl Read, tx: &mpsc::Sender<Vec<u8>>) -> Result<(), ImageError> {
+    let mut magic = Vec::with_capacity(2);
+    (&mut input).take(2).read_to_end(&mut magic)?;
+    let gzipped = is_gzip(&magic);
+    let input = std::io::Cursor::new(magic).chain(input);
+    let input: Box<dyn Read> = if gzipped {
+        Box::new(flate2::read::GzDecoder::new(input))
+    } else {
+        Box::new(input)
+    };
+
+    let invalid =
+        |e: std::io::Error| ImageError::InvalidArchive(format!("not a tar archive: {}", e));
+    let mut tarball = Forwarder {
+        inner: input,
+        tx,
+        pending: Vec::with_capacity(CHUNK_SIZE),
+    };
+    let mut archive = tar::Archive::new(&mut tarball);
+    let mut entries = 0;
+    for entry in archive.entries().map_err(invalid)? {
+        entry.map_err(invalid)?;
+        entries += 1;
+    }
+    if entries == 0 {
+        return Err(ImageError::InvalidArchive(
+            "tar archive is empty".to_string(),
+        ));
+    }
+    // What follows the last entry, the trailer, is part of the blob too.
+    std::io::copy(&mut tarball, &mut std::io::sink())?;
+    tarball.send_pending()
+}
+
+/// A reader passing what it reads on to `tx`.
+struct Forwarder<'a, R> {
+    inner: R,
+    tx: &'a mpsc::Sender<Vec<u8>>,
+    pending: Vec<u8>,
+}
+
+impl<R> Forwarder<'_, R> {
+    fn send_pending(&mut self) -> Result<(), ImageError> {
+        let data = std::mem::replace(&mut self.pending, Vec::with_capacity(CHUNK_SIZE));
+        self.tx
+            .blocking_send(data)
+            .map_err(|_| ImageError::InvalidArchive("blob writer stopped".to_string()))
+    }
+}
+
+impl<R: Read> Read for Forwarder<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.pending.extend_from_slice(&buf[..n]);
+        if self.pending.len() >= CHUNK_SIZE {
+            self.send_pending().map_err(std::io::Error::other)?;
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_archive(path: &Path, files: &[(&str, &[u8])]) {
+        let mut archive = Vec::new();
+        for (name, data) in files {
+            archive.extend(tar_file(name, data).unwrap().concat());
+        }
+        archive.extend(tar_trailer());
+        std::fs::write(path, archive).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_read_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileSystemStore::new(dir.path().join("store"))
+            .await
+            .unwrap();
+        let blob = vec![7; CHUNK_SIZE + 100];
+        let (digest, _) = store.put_blob("", &blob, None).await.unwrap();
+        store.delete_blob(&digest).await.unwrap();
+
+        let path = dir.path().join("archive.tar");
+        let blob_name = format!("blobs/sha256/{}", digest.hash);
+        write_archive(
+            &path,
+            &[
+                (&blob_name, &blob),
+                ("layer/layer.tar", b""),
+                ("index.json", b"{}"),
+            ],
+        );
+        let contents = read_archive(&store, &path).await.unwrap();
+        assert_eq!(
+            contents.blobs[&blob_name],
+            (digest.clone(), blob.len() as i64)
+        );
+        assert!(contents.blobs.contains_key("layer/layer.tar"));
+        assert_eq!(contents.metadata["index.json"], b"{}");
+        assert_eq!(contents.read(&store, &blob_name).await.unwrap(), blob);
+    }
+
+    #[tokio::test]
+    async fn test_read_archive_verifies_blob_digests() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileSystemStore::new(dir.path().join("store"))
+            .await
+            .unwrap();
+        let path = dir.path().join("archive.tar");
+        let name = format!("blobs/sha256/{}", "0".repeat(64));
+        write_archive(&path, &[(&name, b"not what the path says")]);
+        assert!(read_archive(&store, &path).await.is_err());
+    }
+
+    #[test]
+    fn test_tar_file_padding() {
+        for size in [0, 1, BLOCK_SIZE, CHUNK_SIZE + 1] {
+            let chunks = tar_file("blob", &vec![1; size]).unwrap();
+            assert_eq!(chunks[0].len(), BLOCK_SIZE);
+            let total: usize = chunks.iter().map(Vec::len).sum();
+            assert_eq!(total % BLOCK_SIZE, 0);
+            assert_eq!(total, BLOCK_SIZE + size + tar_padding(size as u64).len());
+        }
+    }
+}
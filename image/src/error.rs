@@ -8,6 +8,9 @@ pub enum ImageError {
     #[error("invalid reference: {0}")]
     InvalidReference(String),
 
+    #[error("conflict: {0}")]
+    Conflict(String),
+
     #[error("pull failed: {0}")]
     PullFailed(String),
 
@@ -17,12 +20,21 @@ pub enum ImageError {
     #[error("build failed: {0}")]
     BuildFailed(String),
 
+    #[error("invalid archive: {0}")]
+    InvalidArchive(String),
+
     #[error("registry error: {0}")]
     Registry(#[from] ross_remote::RegistryError),
 
     #[error("store error: {0}")]
     Store(#[from] ross_store::StoreError),
 
+    #[error("snapshotter error: {0}")]
+    Snapshotter(#[from] ross_snapshotter::SnapshotterError),
+
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+
     #[error("serialization error: {0}")]
     Serialization(#[from] serde_json::Error),
 }
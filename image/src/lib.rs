@@ -1,7 +1,10 @@
+mod archive;
 mod error;
 mod service;
 mod types;
 
 pub use error::ImageError;
-pub use service::ImageService;
+pub use service::{ImageService, PruneLock};
 pub use types::*;
+
+pub use ross_remote::{InsecureMode, parse_mirror};
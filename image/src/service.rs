@@ -1,21 +1,53 @@
+use crate::archive::{
+    self, ANNOTATION_IMAGE_NAME, ANNOTATION_REF_NAME, ArchiveContents, DockerManifestEntry,
+    IndexEntry, OciIndex,
+};
 use crate::error::ImageError;
 use crate::types::*;
 use async_stream::stream;
-use ross_remote::{Descriptor, ImageReference, RegistryClient};
-use ross_snapshotter::OverlaySnapshotter;
+use ross_remote::{Descriptor, ImageReference, InsecureMode, Platform, RegistryClient};
+use ross_snapshotter::{Layer, OverlaySnapshotter};
 use ross_store::FileSystemStore;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
 use std::pin::Pin;
 use std::sync::Arc;
-use tokio::sync::{Semaphore, mpsc};
-use tokio_stream::Stream;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::{OwnedRwLockReadGuard, RwLock, RwLockWriteGuard, Semaphore, mpsc};
+use tokio_stream::{Stream, StreamExt};
+use tokio_util::sync::CancellationToken;
 
 type BoxStream<T> = Pin<Box<dyn Stream<Item = T> + Send>>;
 
+/// Minimum time between two byte-progress events for the same layer.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
 pub struct ImageService {
     store: Arc<FileSystemStore>,
     snapshotter: Arc<OverlaySnapshotter>,
     max_concurrent_downloads: usize,
+    max_download_attempts: u32,
+    insecure_registries: HashMap<String, InsecureMode>,
+    registry_mirrors: HashMap<String, Vec<String>>,
+    counters: Arc<PullCounters>,
+    /// Held for reading while content is added to the store and the
+    /// snapshotter, for writing while prune removes what nothing refers to.
+    content: Arc<RwLock<()>>,
+}
+
+/// Keeps content from being added while held; see
+/// [`ImageService::lock_for_prune`].
+pub struct PruneLock<'a> {
+    _guard: RwLockWriteGuard<'a, ()>,
+}
+
+#[derive(Debug, Default)]
+struct PullCounters {
+    images: AtomicU64,
+    bytes: AtomicU64,
+    reused_bytes: AtomicU64,
 }
 
 impl ImageService {
@@ -23,94 +55,219 @@ impl ImageService {
         store: Arc<FileSystemStore>,
         snapshotter: Arc<OverlaySnapshotter>,
         max_concurrent_downloads: usize,
+        max_download_attempts: u32,
     ) -> Self {
         Self {
             store,
             snapshotter,
             max_concurrent_downloads,
+            max_download_attempts,
+            insecure_registries: HashMap::new(),
+            registry_mirrors: HashMap::new(),
+            counters: Arc::default(),
+            content: Arc::default(),
+        }
+    }
+
+    /// Registry hosts that may be pulled from over plain HTTP or HTTPS
+    /// without certificate verification.
+    pub fn with_insecure_registries(mut self, registries: HashMap<String, InsecureMode>) -> Self {
+        self.insecure_registries = registries;
+        self
+    }
+
+    /// Mirror hosts to pull from before each registry, in order.
+    pub fn with_registry_mirrors(mut self, mirrors: HashMap<String, Vec<String>>) -> Self {
+        self.registry_mirrors = mirrors;
+        self
+    }
+
+    /// Keeps prune from running until the guard is dropped, for a container
+    /// snapshot to be prepared on an image's layers: once prepared, it is in
+    /// use.
+    pub async fn hold_content(&self) -> OwnedRwLockReadGuard<()> {
+        self.content.clone().read_owned().await
+    }
+
+    /// Waits for pulls, loads, imports and held content to finish and keeps
+    /// new ones waiting until the lock is dropped, so that what is in use can
+    /// be listed under it and handed to [`ImageService::prune`].
+    pub async fn lock_for_prune(&self) -> PruneLock<'_> {
+        PruneLock {
+            _guard: self.content.write().await,
+        }
+    }
+
+    pub fn pull_stats(&self) -> PullStats {
+        PullStats {
+            images: self.counters.images.load(Ordering::Relaxed),
+            bytes: self.counters.bytes.load(Ordering::Relaxed),
+            reused_bytes: self.counters.reused_bytes.load(Ordering::Relaxed),
         }
     }
 
+    /// Lists tagged images, one entry per image ID (config digest) with all
+    /// of its tags.
     pub async fn list(&self, _params: ListImagesParams) -> Result<Vec<Image>, ImageError> {
-        let repositories = self.store.list_repositories().await?;
-        let mut images = Vec::new();
+        let mut tags = self.tags().await?;
+        tags.sort_by(|a, b| (&a.repository, &a.tag).cmp(&(&b.repository, &b.tag)));
 
-        for repo in repositories {
-            let tags = self.store.list_tags(&repo).await?;
+        let mut images: Vec<Image> = Vec::new();
+        let mut by_id: HashMap<String, usize> = HashMap::new();
 
-            for tag_info in tags {
-                let digest = match &tag_info.digest {
-                    Some(d) => d,
-                    None => continue,
-                };
+        for tagged in tags {
+            let Some(image) = self.load_image(&tagged.manifest).await else {
+                continue;
+            };
 
-                let (manifest_bytes, _media_type) = match self.store.get_manifest(digest).await {
-                    Ok(m) => m,
-                    Err(_) => continue,
-                };
+            let name = display_name(&tagged.repository);
+            let repo_tag = format!("{}:{}", name, tagged.tag);
+            let repo_digest = format!("{}@sha256:{}", name, tagged.manifest.hash);
 
-                let manifest: ross_remote::ManifestV2 =
-                    match serde_json::from_slice(&manifest_bytes) {
-                        Ok(m) => m,
-                        Err(_) => continue,
-                    };
+            let image = match by_id.get(&image.id).copied() {
+                Some(i) => &mut images[i],
+                None => {
+                    by_id.insert(image.id.clone(), images.len());
+                    images.push(image);
+                    images.last_mut().unwrap()
+                }
+            };
+            image.repo_tags.push(repo_tag);
+            if !image.repo_digests.contains(&repo_digest) {
+                image.repo_digests.push(repo_digest);
+            }
+        }
 
-                let config_digest = ross_store::Digest {
+        Ok(images)
+    }
+
+    /// Every tag in the store.
+    async fn tags(&self) -> Result<Vec<TaggedImage>, ImageError> {
+        let mut tags = Vec::new();
+        for repository in self.store.list_repositories().await? {
+            for info in self.store.list_tags(&repository).await? {
+                if let Some(manifest) = info.digest {
+                    tags.push(TaggedImage {
+                        repository: repository.clone(),
+                        tag: info.tag,
+                        manifest,
+                    });
+                }
+            }
+        }
+        Ok(tags)
+    }
+
+    /// Reads the image a manifest describes, without tags. The ID is the
+    /// config digest and the size covers the config and all layers.
+    async fn load_image(&self, manifest_digest: &ross_store::Digest) -> Option<Image> {
+        let (manifest_bytes, _media_type) = self.store.get_manifest(manifest_digest).await.ok()?;
+        let manifest: ross_remote::ManifestV2 = serde_json::from_slice(&manifest_bytes).ok()?;
+
+        let config_digest = ross_store::Digest {
+            algorithm: "sha256".to_string(),
+            hash: manifest
+                .config
+                .digest
+                .trim_start_matches("sha256:")
+                .to_string(),
+        };
+        let config_bytes = self.store.get_blob(&config_digest, 0, -1).await.ok()?;
+        let config: ross_remote::ImageConfig = serde_json::from_slice(&config_bytes).ok()?;
+
+        let total_size: i64 =
+            manifest.config.size + manifest.layers.iter().map(|l| l.size).sum::<i64>();
+
+        let labels = config
+            .config
+            .as_ref()
+            .map(|c| c.labels.clone())
+            .unwrap_or_default();
+
+        Some(Image {
+            id: format!("sha256:{}", config_digest.hash),
+            repo_tags: vec![],
+            repo_digests: vec![],
+            parent: String::new(),
+            comment: String::new(),
+            container: String::new(),
+            docker_version: String::new(),
+            author: String::new(),
+            architecture: config.architecture.clone(),
+            os: config.os.clone(),
+            size: total_size,
+            virtual_size: total_size,
+            labels,
+            root_fs: config.rootfs.map(|rootfs| RootFs {
+                fs_type: rootfs.fs_type,
+                layers: rootfs.diff_ids,
+            }),
+        })
+    }
+
+    /// Finds the manifest `name` refers to, with the tags to act on.
+    ///
+    /// `name` is a reference (`repo[:tag]`, `repo@digest`), giving the tags it
+    /// names, or an image ID (a prefix of a config or manifest digest), giving
+    /// every tag of the image.
+    async fn find_image(
+        &self,
+        name: &str,
+    ) -> Result<(ross_store::Digest, Vec<TaggedImage>), ImageError> {
+        let mut tags = self.tags().await?;
+
+        if let Ok(reference) = ImageReference::parse(name) {
+            if let Some(digest) = &reference.digest {
+                let manifest = ross_store::Digest {
                     algorithm: "sha256".to_string(),
-                    hash: manifest
-                        .config
-                        .digest
-                        .trim_start_matches("sha256:")
-                        .to_string(),
+                    hash: digest.trim_start_matches("sha256:").to_string(),
                 };
+                if self.store.get_manifest(&manifest).await.is_err() {
+                    return Err(ImageError::NotFound(name.to_string()));
+                }
+                tags.retain(|t| t.repository == reference.repository && t.manifest == manifest);
+                return Ok((manifest, tags));
+            }
 
-                let config_bytes = match self.store.get_blob(&config_digest, 0, -1).await {
-                    Ok(b) => b,
-                    Err(_) => continue,
-                };
+            let tag = reference.tag_or_default();
+            if let Some(pos) = tags
+                .iter()
+                .position(|t| t.repository == reference.repository && t.tag == tag)
+            {
+                let tagged = tags.swap_remove(pos);
+                return Ok((tagged.manifest.clone(), vec![tagged]));
+            }
+        }
 
-                let config: ross_remote::ImageConfig = match serde_json::from_slice(&config_bytes) {
-                    Ok(c) => c,
-                    Err(_) => continue,
-                };
+        let id = name.strip_prefix("sha256:").unwrap_or(name);
+        if id.is_empty() || !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(ImageError::NotFound(name.to_string()));
+        }
 
-                let total_size: i64 = manifest.layers.iter().map(|l| l.size).sum();
-
-                let repo_tag = format!("{}:{}", repo, tag_info.tag);
-                let repo_digest = format!("{}@sha256:{}", repo, digest.hash);
-
-                let labels = config
-                    .config
-                    .as_ref()
-                    .map(|c| c.labels.clone())
-                    .unwrap_or_default();
-
-                let layer_digests: Vec<String> =
-                    manifest.layers.iter().map(|l| l.digest.clone()).collect();
-
-                images.push(Image {
-                    id: format!("sha256:{}", digest.hash),
-                    repo_tags: vec![repo_tag],
-                    repo_digests: vec![repo_digest],
-                    parent: String::new(),
-                    comment: String::new(),
-                    container: String::new(),
-                    docker_version: String::new(),
-                    author: String::new(),
-                    architecture: config.architecture.clone(),
-                    os: config.os.clone(),
-                    size: total_size,
-                    virtual_size: total_size,
-                    labels,
-                    root_fs: Some(RootFs {
-                        fs_type: "layers".to_string(),
-                        layers: layer_digests,
-                    }),
-                });
+        let mut found: Option<ross_store::Digest> = None;
+        for tagged in &tags {
+            if found.as_ref() == Some(&tagged.manifest) {
+                continue;
+            }
+            let config_matches = match self.load_image(&tagged.manifest).await {
+                Some(image) => image.id.trim_start_matches("sha256:").starts_with(id),
+                None => false,
+            };
+            if !config_matches && !tagged.manifest.hash.starts_with(id) {
+                continue;
+            }
+            if found.is_some() {
+                return Err(ImageError::InvalidReference(format!(
+                    "image ID {} is ambiguous",
+                    name
+                )));
             }
+            found = Some(tagged.manifest.clone());
         }
 
-        Ok(images)
+        let manifest = found.ok_or_else(|| ImageError::NotFound(name.to_string()))?;
+        tags.retain(|t| t.manifest == manifest);
+        Ok((manifest, tags))
     }
 
     pub async fn inspect(&self, image_id: &str) -> Result<ImageInspection, ImageError> {
@@ -125,11 +282,19 @@ impl ImageService {
         &self,
         image_name: &str,
         tag: &str,
-        _auth: Option<RegistryAuth>,
+        auth: Option<RegistryAuth>,
+        platform: Option<&str>,
     ) -> Result<BoxStream<PullProgress>, ImageError> {
         let parsed = ImageReference::parse(image_name)
             .map_err(|e| ImageError::InvalidReference(e.to_string()))?;
 
+        let platform = match platform.filter(|p| !p.is_empty()) {
+            Some(p) => {
+                Platform::parse(p).map_err(|e| ImageError::InvalidReference(e.to_string()))?
+            }
+            None => Platform::host(),
+        };
+
         let reference = if parsed.tag.is_some() || parsed.digest.is_some() {
             parsed
         } else {
@@ -144,8 +309,16 @@ impl ImageService {
         let store = self.store.clone();
         let snapshotter = self.snapshotter.clone();
         let max_concurrent = self.max_concurrent_downloads;
+        let max_attempts = self.max_download_attempts;
+        let insecure_registries = self.insecure_registries.clone();
+        let registry_mirrors = self.registry_mirrors.clone();
+        let counters = self.counters.clone();
+        let content = self.content.clone();
 
         let output = stream! {
+            // Shared with the extraction, which outlives the stream.
+            let content = Arc::new(content.read_owned().await);
+
             yield PullProgress {
                 id: reference.full_name(),
                 status: "Resolving".to_string(),
@@ -155,8 +328,16 @@ impl ImageService {
                 error: None,
             };
 
-            let registry = match RegistryClient::new() {
-                Ok(r) => Arc::new(r),
+            let client = match auth.filter(|a| !a.username.is_empty()) {
+                Some(auth) => RegistryClient::with_credentials(auth.username, auth.password),
+                None => RegistryClient::new(),
+            };
+            let client = client.and_then(|c| c.with_insecure_registries(insecure_registries));
+            let registry = match client {
+                Ok(r) => Arc::new(
+                    r.with_mirrors(registry_mirrors)
+                        .with_max_attempts(max_attempts),
+                ),
                 Err(e) => {
                     yield PullProgress {
                         id: reference.full_name(),
@@ -164,24 +345,17 @@ impl ImageService {
                         progress: String::new(),
                         current: None,
                         total: None,
-                        error: Some(format!("Failed to create registry client: {}", e)),
+                        error: Some(registry_pull_error("Failed to create registry client", &e)),
                     };
                     return;
                 }
             };
 
-            let os = "linux";
-            let arch = match std::env::consts::ARCH {
-                "x86_64" => "amd64",
-                "aarch64" => "arm64",
-                a => a,
-            };
-
-            let (manifest, media_type, manifest_digest) = match registry
-                .get_manifest_for_platform(&reference, os, arch)
+            let resolved = match registry
+                .get_manifest_for_platform(&reference, &platform)
                 .await
             {
-                Ok(result) => result,
+                Ok(resolved) => resolved,
                 Err(e) => {
                     yield PullProgress {
                         id: reference.full_name(),
@@ -189,15 +363,17 @@ impl ImageService {
                         progress: String::new(),
                         current: None,
                         total: None,
-                        error: Some(format!("Failed to get manifest: {}", e)),
+                        error: Some(registry_pull_error("Failed to get manifest", &e)),
                     };
                     return;
                 }
             };
 
+            let manifest = &resolved.manifest;
+
             yield PullProgress {
                 id: reference.full_name(),
-                status: format!("Resolved digest: {}", &manifest_digest),
+                status: format!("Resolved digest: {}", &resolved.digest),
                 progress: String::new(),
                 current: None,
                 total: None,
@@ -221,7 +397,10 @@ impl ImageService {
             };
 
             let config_bytes = match registry.get_blob_bytes(&reference, config_digest).await {
-                Ok(bytes) => bytes,
+                Ok(bytes) => {
+                    counters.bytes.fetch_add(bytes.len() as u64, Ordering::Relaxed);
+                    bytes
+                }
                 Err(e) => {
                     yield PullProgress {
                         id: short_config_id.to_string(),
@@ -229,24 +408,49 @@ impl ImageService {
                         progress: String::new(),
                         current: None,
                         total: None,
-                        error: Some(format!("Failed to pull config: {}", e)),
+                        error: Some(registry_pull_error("Failed to pull config", &e)),
                     };
                     return;
                 }
             };
 
-            if let Err(e) = store.put_blob(&manifest.config.media_type, &config_bytes, None).await {
+            let expected_config_digest = ross_store::Digest {
+                algorithm: "sha256".to_string(),
+                hash: config_digest.trim_start_matches("sha256:").to_string(),
+            };
+            if let Err(e) = store
+                .put_blob(
+                    &manifest.config.media_type,
+                    &config_bytes,
+                    Some(&expected_config_digest),
+                )
+                .await
+            {
                 yield PullProgress {
                     id: short_config_id.to_string(),
                     status: String::new(),
                     progress: String::new(),
                     current: None,
                     total: None,
-                    error: Some(format!("Failed to store config: {}", e)),
+                    error: Some(pull_error(format!("Failed to store config: {}", e))),
                 };
                 return;
             }
 
+            let Some(snapshot_layers) = layer_snapshots(&manifest.layers, &config_bytes) else {
+                yield PullProgress {
+                    id: short_config_id.to_string(),
+                    status: String::new(),
+                    progress: String::new(),
+                    current: None,
+                    total: None,
+                    error: Some(pull_error(
+                        "Image config does not list a diff ID per layer".to_string(),
+                    )),
+                };
+                return;
+            };
+
             yield PullProgress {
                 id: short_config_id.to_string(),
                 status: "Pull complete".to_string(),
@@ -260,9 +464,15 @@ impl ImageService {
             let (tx, mut rx) = mpsc::channel::<LayerEvent>(manifest.layers.len() * 4);
             let total_layers = manifest.layers.len();
 
+            // The downloads stop when the client goes away and the stream is
+            // dropped; unfinished layers are discarded from the store's
+            // ingest area. Extraction, once started, runs to completion.
+            let cancel = CancellationToken::new();
+            let _cancel_on_drop = cancel.clone().drop_guard();
+
             let mut handles = Vec::new();
             for (i, layer) in manifest.layers.iter().enumerate() {
-                let handle = tokio::spawn(download_layer(
+                let download = download_layer(
                     registry.clone(),
                     store.clone(),
                     reference.clone(),
@@ -271,17 +481,26 @@ impl ImageService {
                     total_layers,
                     semaphore.clone(),
                     tx.clone(),
-                ));
-                handles.push(handle);
+                    counters.clone(),
+                );
+                let cancel = cancel.clone();
+                handles.push(tokio::spawn(async move {
+                    cancel.run_until_cancelled(download).await;
+                }));
             }
 
             drop(tx);
 
             let mut error_occurred = false;
             let mut any_downloaded = false;
+            // Layers already in the store, shared with images pulled before.
+            let mut reused_layers = 0;
+            let mut reused_bytes = 0i64;
             while let Some(event) = rx.recv().await {
                 match event {
-                    LayerEvent::Exists { id } => {
+                    LayerEvent::Exists { id, size } => {
+                        reused_layers += 1;
+                        reused_bytes += size;
                         yield PullProgress {
                             id,
                             status: "Already exists".to_string(),
@@ -301,10 +520,20 @@ impl ImageService {
                             error: None,
                         };
                     }
+                    LayerEvent::Progress { id, current, total } => {
+                        yield PullProgress {
+                            id,
+                            status: "Downloading".to_string(),
+                            progress: String::new(),
+                            current: Some(current),
+                            total: Some(total),
+                            error: None,
+                        };
+                    }
                     LayerEvent::Downloaded { id } => {
                         yield PullProgress {
                             id,
-                            status: "Download complete".to_string(),
+                            status: "Verifying checksum".to_string(),
                             progress: String::new(),
                             current: None,
                             total: None,
@@ -315,7 +544,7 @@ impl ImageService {
                         any_downloaded = true;
                         yield PullProgress {
                             id,
-                            status: "Pull complete".to_string(),
+                            status: "Download complete".to_string(),
                             progress: String::new(),
                             current: None,
                             total: None,
@@ -344,8 +573,8 @@ impl ImageService {
                 return;
             }
 
-            let manifest_bytes = serde_json::to_vec(&manifest).unwrap_or_default();
-            let (stored_digest, _) = match store.put_manifest(&manifest_bytes, &media_type).await {
+            // Store the bytes as served so the stored digest matches the registry's.
+            let (stored_digest, _) = match store.put_manifest(&resolved.bytes, &resolved.media_type).await {
                 Ok(result) => result,
                 Err(e) => {
                     yield PullProgress {
@@ -354,20 +583,37 @@ impl ImageService {
                         progress: String::new(),
                         current: None,
                         total: None,
-                        error: Some(format!("Failed to store manifest: {}", e)),
+                        error: Some(pull_error(format!("Failed to store manifest: {}", e))),
                     };
                     return;
                 }
             };
 
-            if let Err(e) = store.set_tag(&reference.repository, reference.tag_or_default(), &stored_digest).await {
+            // Keep the manifest list too, so `name@<index digest>` resolves locally.
+            if let Some(index) = &resolved.index
+                && let Err(e) = store.put_index(index).await
+            {
                 yield PullProgress {
                     id: reference.full_name(),
                     status: String::new(),
                     progress: String::new(),
                     current: None,
                     total: None,
-                    error: Some(format!("Failed to set tag: {}", e)),
+                    error: Some(pull_error(format!("Failed to store index: {}", e))),
+                };
+                return;
+            }
+
+            // A digest-only pull must not move the repository's default tag.
+            let tag_image = reference.tag.is_some() || reference.digest.is_none();
+            if tag_image && let Err(e) = store.set_tag(&reference.repository, reference.tag_or_default(), &stored_digest).await {
+                yield PullProgress {
+                    id: reference.full_name(),
+                    status: String::new(),
+                    progress: String::new(),
+                    current: None,
+                    total: None,
+                    error: Some(pull_error(format!("Failed to set tag: {}", e))),
                 };
                 return;
             }
@@ -381,74 +627,116 @@ impl ImageService {
                 error: None,
             };
 
-            let mut parent_key: Option<String> = None;
-            for (i, layer) in manifest.layers.iter().enumerate() {
-                let layer_digest = &layer.digest;
-                let short_id = if layer_digest.len() > 19 {
-                    &layer_digest[7..19]
+            let short_id = |digest: &str| {
+                if digest.len() > 19 {
+                    digest[7..19].to_string()
                 } else {
-                    layer_digest
-                };
-
-                let snapshot_key = layer_digest.clone();
+                    digest.to_string()
+                }
+            };
 
-                if snapshotter.stat(&snapshot_key).await.is_ok() {
+            // Layers already unpacked, shared with images pulled before.
+            let mut unpacked = HashSet::new();
+            let mut layers = Vec::with_capacity(total_layers);
+            for (i, layer) in snapshot_layers.into_iter().enumerate() {
+                if snapshotter.stat(&layer.key).await.is_ok() {
+                    unpacked.insert(i);
                     yield PullProgress {
-                        id: short_id.to_string(),
-                        status: "Layer already extracted".to_string(),
+                        id: short_id(&layer.digest),
+                        status: "Already exists".to_string(),
                         progress: String::new(),
                         current: None,
                         total: None,
                         error: None,
                     };
-                    parent_key = Some(snapshot_key);
-                    continue;
+                } else {
+                    yield PullProgress {
+                        id: short_id(&layer.digest),
+                        status: "Extracting".to_string(),
+                        progress: format!("[{}/{}]", i + 1, total_layers),
+                        current: None,
+                        total: None,
+                        error: None,
+                    };
                 }
 
+                layers.push(layer);
+            }
+
+            // Layers are unpacked concurrently and reported as they are
+            // committed, in order.
+            let (committed_tx, mut committed_rx) = mpsc::unbounded_channel();
+            let extraction = {
+                let snapshotter = snapshotter.clone();
+                let content = content.clone();
+                tokio::spawn(async move {
+                    let _content = content;
+                    snapshotter
+                        .extract_layers(None, layers, |index, size| {
+                            let _ = committed_tx.send((index, size));
+                        })
+                        .await
+                })
+            };
+
+            let mut committed = 0;
+            let mut reused_snapshots = 0;
+            let mut reused_unpacked = 0i64;
+            while let Some((i, size)) = committed_rx.recv().await {
+                committed += 1;
+                if unpacked.contains(&i) {
+                    reused_snapshots += 1;
+                    reused_unpacked += size;
+                    continue;
+                }
                 yield PullProgress {
-                    id: short_id.to_string(),
-                    status: format!("Extracting layer {}/{}", i + 1, manifest.layers.len()),
-                    progress: String::new(),
+                    id: short_id(&manifest.layers[i].digest),
+                    status: "Pull complete".to_string(),
+                    progress: format!("({} bytes)", size),
                     current: None,
                     total: None,
                     error: None,
                 };
+            }
 
-                let mut labels = HashMap::new();
-                labels.insert("containerd.io/snapshot/layer.digest".to_string(), layer_digest.clone());
-
-                match snapshotter.extract_layer(
-                    layer_digest,
-                    parent_key.as_deref(),
-                    &snapshot_key,
-                    labels,
-                ).await {
-                    Ok((key, size)) => {
-                        yield PullProgress {
-                            id: short_id.to_string(),
-                            status: format!("Extracted ({} bytes)", size),
-                            progress: String::new(),
-                            current: None,
-                            total: None,
-                            error: None,
-                        };
-                        parent_key = Some(key);
-                    }
-                    Err(e) => {
-                        yield PullProgress {
-                            id: short_id.to_string(),
-                            status: String::new(),
-                            progress: String::new(),
-                            current: None,
-                            total: None,
-                            error: Some(format!("Failed to extract layer: {}", e)),
-                        };
-                        return;
-                    }
-                }
+            let failed = match extraction.await {
+                Ok(Ok(_)) => None,
+                Ok(Err(e)) => Some(e.to_string()),
+                Err(e) => Some(e.to_string()),
+            };
+            if let Some(e) = failed {
+                let id = manifest
+                    .layers
+                    .get(committed)
+                    .map_or_else(|| reference.full_name(), |layer| short_id(&layer.digest));
+                yield PullProgress {
+                    id,
+                    status: String::new(),
+                    progress: String::new(),
+                    current: None,
+                    total: None,
+                    error: Some(pull_error(format!("Failed to extract layer: {}", e))),
+                };
+                return;
             }
 
-            let _ = parent_key; // Top layer is tracked in manifest, no need for separate snapshot
+            counters.reused_bytes.fetch_add(reused_bytes as u64, Ordering::Relaxed);
+            if reused_layers > 0 || reused_snapshots > 0 {
+                yield PullProgress {
+                    id: reference.full_name(),
+                    status: format!(
+                        "Deduplicated: {}/{} layers shared with other images, {} bytes not downloaded, {} bytes not unpacked",
+                        reused_layers.max(reused_snapshots),
+                        total_layers,
+                        reused_bytes,
+                        reused_unpacked,
+                    ),
+                    progress: String::new(),
+                    current: None,
+                    total: None,
+                    error: None,
+                };
+            }
 
             let digest_str = format!("sha256:{}", stored_digest.hash);
             yield PullProgress {
@@ -460,6 +748,7 @@ impl ImageService {
                 error: None,
             };
 
+            counters.images.fetch_add(1, Ordering::Relaxed);
             let status_message = if any_downloaded {
                 format!("Status: Downloaded newer image for {}", reference.full_name())
             } else {
@@ -525,19 +814,136 @@ impl ImageService {
         Box::pin(output)
     }
 
+    /// Untags an image and, once no tag references its manifest, deletes
+    /// the manifest so that its config and layer blobs are reclaimed by the
+    /// next garbage collection.
+    ///
+    /// By image ID every tag of the image is removed, which needs `force`
+    /// when they span several repositories.
     pub async fn remove(
         &self,
         image_id: &str,
-        _force: bool,
+        force: bool,
         _prune_children: bool,
     ) -> Result<RemoveImageResult, ImageError> {
         tracing::info!("Removing image: {}", image_id);
-        Ok(RemoveImageResult {
-            deleted: vec![],
-            untagged: vec![],
+
+        let (manifest, tags) = self.find_image(image_id).await?;
+
+        let repositories: HashSet<&str> = tags.iter().map(|t| t.repository.as_str()).collect();
+        if repositories.len() > 1 && !force {
+            return Err(ImageError::Conflict(format!(
+                "image {} is referenced in multiple repositories, use --force to remove them all",
+                image_id
+            )));
+        }
+
+        let mut untagged = Vec::new();
+        for tagged in &tags {
+            self.store
+                .delete_tag(&tagged.repository, &tagged.tag)
+                .await?;
+            untagged.push(format!(
+                "{}:{}",
+                display_name(&tagged.repository),
+                tagged.tag
+            ));
+        }
+
+        let mut deleted = Vec::new();
+        let still_tagged = self.tags().await?.iter().any(|t| t.manifest == manifest);
+        if !still_tagged {
+            let image = self.load_image(&manifest).await;
+            if self.store.delete_manifest(&manifest).await? {
+                if let Some(image) = image {
+                    deleted.push(image.id);
+                }
+                deleted.push(format!("{}:{}", manifest.algorithm, manifest.hash));
+            }
+        }
+
+        Ok(RemoveImageResult { deleted, untagged })
+    }
+
+    /// Deletes store content no tagged image references, then every snapshot
+    /// that neither a tagged image nor a snapshot in `in_use` builds on.
+    /// `in_use` is listed under `_lock`, so that no content is added between
+    /// then and now.
+    pub async fn prune(
+        &self,
+        _lock: &PruneLock<'_>,
+        in_use: &HashSet<String>,
+    ) -> Result<PruneImagesResult, ImageError> {
+        tracing::info!("Pruning unreferenced images");
+
+        let (_, _, store_reclaimed, removed) = self.store.garbage_collect(false, true).await?;
+
+        let mut keep = in_use.clone();
+        for image in self.list(ListImagesParams::default()).await? {
+            if let Some(root_fs) = image.root_fs {
+                keep.extend(ross_snapshotter::chain_ids(&root_fs.layers));
+            }
+        }
+
+        let (removed_snapshots, snapshots_reclaimed) = self.snapshotter.prune(&keep).await?;
+
+        let mut deleted: Vec<String> = removed
+            .into_iter()
+            .map(|d| format!("{}:{}", d.algorithm, d.hash))
+            .collect();
+        deleted.extend(removed_snapshots);
+
+        Ok(PruneImagesResult {
+            deleted,
+            space_reclaimed: store_reclaimed + snapshots_reclaimed,
         })
     }
 
+    /// Disk space taken by images. `in_use` holds the IDs of the images
+    /// containers were created from; blobs shared between images are counted
+    /// once, and are reclaimable only if none of those images refers to them.
+    pub async fn disk_usage(&self, in_use: &HashSet<String>) -> Result<ImageDiskUsage, ImageError> {
+        let mut manifests = HashSet::new();
+        let mut images = HashSet::new();
+        let mut blobs: HashMap<String, i64> = HashMap::new();
+        let mut active_blobs = HashSet::new();
+
+        for tagged in self.tags().await? {
+            if !manifests.insert(tagged.manifest.hash.clone()) {
+                continue;
+            }
+            let Ok((manifest_bytes, _)) = self.store.get_manifest(&tagged.manifest).await else {
+                continue;
+            };
+            let Ok(manifest) = serde_json::from_slice::<ross_remote::ManifestV2>(&manifest_bytes)
+            else {
+                continue;
+            };
+
+            let active = in_use.contains(&manifest.config.digest);
+            for blob in std::iter::once(&manifest.config).chain(&manifest.layers) {
+                blobs.insert(blob.digest.clone(), blob.size);
+                if active {
+                    active_blobs.insert(blob.digest.clone());
+                }
+            }
+            images.insert(manifest.config.digest);
+        }
+
+        Ok(ImageDiskUsage {
+            count: images.len() as i64,
+            active: images.intersection(in_use).count() as i64,
+            size: blobs.values().sum(),
+            reclaimable: blobs
+                .iter()
+                .filter(|(digest, _)| !active_blobs.contains(*digest))
+                .map(|(_, size)| size)
+                .sum(),
+        })
+    }
+
+    /// Points `repository:tag` at the manifest of `source_image`. An empty
+    /// `tag` takes the one in `repository`, if any, or "latest".
     pub async fn tag(
         &self,
         source_image: &str,
@@ -545,6 +951,32 @@ impl ImageService {
         tag: &str,
     ) -> Result<(), ImageError> {
         tracing::info!("Tagging image {} as {}:{}", source_image, repository, tag);
+
+        let (manifest, _) = self.find_image(source_image).await?;
+
+        let target = ImageReference::parse(repository)
+            .map_err(|e| ImageError::InvalidReference(e.to_string()))?;
+        if target.digest.is_some() {
+            return Err(ImageError::InvalidReference(format!(
+                "cannot tag with a digest: {}",
+                repository
+            )));
+        }
+        let tag = if tag.is_empty() {
+            target.tag_or_default()
+        } else {
+            tag
+        };
+        if !is_valid_tag(tag) {
+            return Err(ImageError::InvalidReference(format!(
+                "invalid tag: {}",
+                tag
+            )));
+        }
+
+        self.store
+            .set_tag(&target.repository, tag, &manifest)
+            .await?;
         Ok(())
     }
 
@@ -552,6 +984,635 @@ impl ImageService {
         tracing::info!("Searching images with term: {}", params.term);
         Ok(vec![])
     }
+
+    /// Exports images as a tar archive that is both an OCI image layout and a
+    /// docker-archive, so either `ross image load` or `docker load` reads it.
+    ///
+    /// The images are resolved before the archive starts streaming, so an
+    /// unknown name fails the call rather than truncating the archive.
+    pub async fn save(
+        &self,
+        names: &[String],
+    ) -> Result<BoxStream<Result<Vec<u8>, ImageError>>, ImageError> {
+        if names.is_empty() {
+            return Err(ImageError::InvalidReference(
+                "no images to save".to_string(),
+            ));
+        }
+
+        let mut index = OciIndex {
+            schema_version: 2,
+            media_type: Some(ross_remote::MEDIA_TYPE_OCI_INDEX.to_string()),
+            manifests: Vec::new(),
+        };
+        let mut docker_manifest: Vec<DockerManifestEntry> = Vec::new();
+        let mut files: Vec<(String, Vec<u8>)> = Vec::new();
+        let mut blobs: Vec<ross_store::Digest> = Vec::new();
+        let mut seen: HashSet<String> = HashSet::new();
+
+        for name in names {
+            let (digest, tags) = self.find_image(name).await?;
+            let (bytes, media_type) = self.store.get_manifest(&digest).await?;
+            let manifest: ross_remote::ManifestV2 = serde_json::from_slice(&bytes)?;
+            let manifest_digest = format!("{}:{}", digest.algorithm, digest.hash);
+
+            let repo_tags: Vec<String> = tags
+                .iter()
+                .map(|t| format!("{}:{}", display_name(&t.repository), t.tag))
+                .collect();
+            let mut annotations: Vec<HashMap<String, String>> = repo_tags
+                .iter()
+                .zip(&tags)
+                .map(|(repo_tag, t)| {
+                    HashMap::from([
+                        (ANNOTATION_IMAGE_NAME.to_string(), repo_tag.clone()),
+                        (ANNOTATION_REF_NAME.to_string(), t.tag.clone()),
+                    ])
+                })
+                .collect();
+            if annotations.is_empty() {
+                annotations.push(HashMap::new());
+            }
+            for annotations in annotations {
+                let duplicate = index
+                    .manifests
+                    .iter()
+                    .any(|e| e.digest == manifest_digest && e.annotations == annotations);
+                if !duplicate {
+                    index.manifests.push(IndexEntry {
+                        media_type: media_type.clone(),
+                        digest: manifest_digest.clone(),
+                        size: bytes.len() as i64,
+                        platform: None,
+                        annotations,
+                    });
+                }
+            }
+
+            let config_path = archive::blob_path(&manifest.config.digest);
+            match docker_manifest.iter_mut().find(|e| e.config == config_path) {
+                Some(entry) => {
+                    let existing = entry.repo_tags.get_or_insert_with(Vec::new);
+                    for repo_tag in repo_tags {
+                        if !existing.contains(&repo_tag) {
+                            existing.push(repo_tag);
+                        }
+                    }
+                }
+                None => docker_manifest.push(DockerManifestEntry {
+                    config: config_path,
+                    repo_tags: Some(repo_tags),
+                    layers: manifest
+                        .layers
+                        .iter()
+                        .map(|l| archive::blob_path(&l.digest))
+                        .collect(),
+                }),
+            }
+
+            // Manifests live outside the blob store, so they are written inline.
+            if seen.insert(manifest_digest.clone()) {
+                files.push((archive::blob_path(&manifest_digest), bytes));
+            }
+            for descriptor in std::iter::once(&manifest.config).chain(&manifest.layers) {
+                if seen.insert(descriptor.digest.clone()) {
+                    blobs.push(archive::parse_digest(&descriptor.digest)?);
+                }
+            }
+        }
+
+        files.push(("oci-layout".to_string(), archive::OCI_LAYOUT.to_vec()));
+        files.push(("index.json".to_string(), serde_json::to_vec(&index)?));
+        files.push((
+            "manifest.json".to_string(),
+            serde_json::to_vec(&docker_manifest)?,
+        ));
+
+        let store = self.store.clone();
+        let output = stream! {
+            // Blobs are streamed from the store rather than read whole.
+            for digest in blobs {
+                let path = format!("blobs/{}/{}", digest.algorithm, digest.hash);
+                let (mut file, size) = match store.open_blob(&digest).await {
+                    Ok(blob) => blob,
+                    Err(e) => {
+                        yield Err(ImageError::from(e));
+                        return;
+                    }
+                };
+                match archive::tar_header(&path, size) {
+                    Ok(header) => yield Ok(header),
+                    Err(e) => {
+                        yield Err(e);
+                        return;
+                    }
+                }
+                // Exactly the size in the header, should the blob change.
+                let mut remaining = size;
+                while remaining > 0 {
+                    let mut chunk = vec![0; remaining.min(archive::CHUNK_SIZE as u64) as usize];
+                    if let Err(e) = file.read_exact(&mut chunk).await {
+                        yield Err(ImageError::from(e));
+                        return;
+                    }
+                    remaining -= chunk.len() as u64;
+                    yield Ok(chunk);
+                }
+                let padding = archive::tar_padding(size);
+                if !padding.is_empty() {
+                    yield Ok(padding);
+                }
+            }
+
+            for (path, data) in files {
+                match archive::tar_file(&path, &data) {
+                    Ok(chunks) => {
+                        for chunk in chunks {
+                            yield Ok(chunk);
+                        }
+                    }
+                    Err(e) => {
+                        yield Err(e);
+                        return;
+                    }
+                }
+            }
+
+            yield Ok(archive::tar_trailer());
+        };
+
+        Ok(Box::pin(output))
+    }
+
+    /// Imports the images in a tar archive streamed as `input`, either an OCI
+    /// image layout or a docker-archive. Every blob is verified against its
+    /// digest and images are tagged with the names the archive gives them.
+    ///
+    /// Returns the loaded `name:tag`s, or the image ID of untagged images.
+    pub async fn load<S>(&self, input: S) -> Result<Vec<String>, ImageError>
+    where
+        S: Stream<Item = Result<Vec<u8>, ImageError>>,
+    {
+        static NEXT_LOAD: AtomicU64 = AtomicU64::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "ross-load-{}-{}",
+            std::process::id(),
+            NEXT_LOAD.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let result = self.load_archive(input, &path).await;
+        let _ = tokio::fs::remove_file(&path).await;
+        result
+    }
+
+    async fn load_archive<S>(&self, input: S, path: &Path) -> Result<Vec<String>, ImageError>
+    where
+        S: Stream<Item = Result<Vec<u8>, ImageError>>,
+    {
+        // The archive is spooled to disk first: tar gives no index, and the
+        // manifests describing the blobs may come after them.
+        let mut file = tokio::fs::File::create(path).await?;
+        tokio::pin!(input);
+        while let Some(chunk) = input.next().await {
+            file.write_all(&chunk?).await?;
+        }
+        file.flush().await?;
+        drop(file);
+
+        let _content = self.hold_content().await;
+        let contents = archive::read_archive(&self.store, path).await?;
+        let images = self.archived_images(&contents).await?;
+        if images.is_empty() {
+            return Err(ImageError::InvalidArchive(
+                "archive contains no images".to_string(),
+            ));
+        }
+
+        let mut loaded = Vec::new();
+        for mut image in images {
+            self.check_blobs(&image).await?;
+
+            let (bytes, media_type) = match image.manifest.take() {
+                Some(manifest) => manifest,
+                None => {
+                    let manifest = ross_remote::ManifestV2 {
+                        schema_version: 2,
+                        media_type: Some(ross_remote::MEDIA_TYPE_MANIFEST_V2.to_string()),
+                        config: Descriptor {
+                            media_type: ross_remote::MEDIA_TYPE_CONFIG.to_string(),
+                            ..image.config.clone()
+                        },
+                        layers: image.layers.clone(),
+                    };
+                    (
+                        serde_json::to_vec(&manifest)?,
+                        ross_remote::MEDIA_TYPE_MANIFEST_V2.to_string(),
+                    )
+                }
+            };
+            let (manifest, _) = self.store.put_manifest(&bytes, &media_type).await?;
+
+            let config = self
+                .store
+                .get_blob(&archive::parse_digest(&image.config.digest)?, 0, -1)
+                .await?;
+            self.unpack_layers(&image.layers, &config).await?;
+
+            if image.tags.is_empty() {
+                loaded.push(image.config.digest.clone());
+            }
+            for name in &image.tags {
+                let reference = ImageReference::parse(name)
+                    .ok()
+                    .filter(|r| r.digest.is_none())
+                    .ok_or_else(|| {
+                        ImageError::InvalidArchive(format!("invalid image name {}", name))
+                    })?;
+                let tag = reference.tag_or_default();
+                self.store
+                    .set_tag(&reference.repository, tag, &manifest)
+                    .await?;
+                loaded.push(format!("{}:{}", display_name(&reference.repository), tag));
+            }
+        }
+
+        Ok(loaded)
+    }
+
+    /// The images an archive holds. An OCI `index.json` takes precedence, with
+    /// names from its annotations and from a `manifest.json` next to it;
+    /// otherwise the archive is a plain docker-archive.
+    async fn archived_images(
+        &self,
+        contents: &ArchiveContents,
+    ) -> Result<Vec<ArchivedImage>, ImageError> {
+        let docker_manifest: Vec<DockerManifestEntry> = match contents.metadata.get("manifest.json")
+        {
+            Some(data) => serde_json::from_slice(data)?,
+            None => Vec::new(),
+        };
+
+        let mut images = Vec::new();
+
+        if let Some(index) = contents.metadata.get("index.json") {
+            let index: OciIndex = serde_json::from_slice(index)?;
+            for entry in index.manifests {
+                let (bytes, media_type) = self
+                    .archived_manifest(contents, &entry.media_type, &entry.digest)
+                    .await?;
+                let manifest: ross_remote::ManifestV2 = serde_json::from_slice(&bytes)?;
+
+                // A bare ref name is only a tag; it names nothing without a repository.
+                let mut tags: Vec<String> = entry
+                    .annotations
+                    .get(ANNOTATION_IMAGE_NAME)
+                    .or_else(|| {
+                        entry
+                            .annotations
+                            .get(ANNOTATION_REF_NAME)
+                            .filter(|r| r.contains(['/', ':']))
+                    })
+                    .cloned()
+                    .into_iter()
+                    .collect();
+                for docker_entry in &docker_manifest {
+                    let config = contents.blobs.get(&docker_entry.config);
+                    if config.is_some_and(|(d, _)| {
+                        format!("{}:{}", d.algorithm, d.hash) == manifest.config.digest
+                    }) {
+                        for repo_tag in docker_entry.repo_tags.iter().flatten() {
+                            if !tags.contains(repo_tag) {
+                                tags.push(repo_tag.clone());
+                            }
+                        }
+                    }
+                }
+
+                images.push(ArchivedImage {
+                    config: manifest.config,
+                    layers: manifest.layers,
+                    manifest: Some((bytes, media_type)),
+                    tags,
+                });
+            }
+            return Ok(images);
+        }
+
+        for entry in docker_manifest {
+            // Legacy archives keep the config at the root, outside the blobs.
+            let config = contents.read(&self.store, &entry.config).await?;
+            let (digest, size) = self
+                .store
+                .put_blob(ross_remote::MEDIA_TYPE_CONFIG, &config, None)
+                .await?;
+
+            let mut layers = Vec::new();
+            for path in &entry.layers {
+                let (digest, size) = contents
+                    .blobs
+                    .get(path)
+                    .ok_or_else(|| ImageError::InvalidArchive(format!("missing layer {}", path)))?;
+                layers.push(Descriptor {
+                    media_type: self.archived_layer_media_type(digest).await?.to_string(),
+                    digest: format!("{}:{}", digest.algorithm, digest.hash),
+                    size: *size,
+                    urls: vec![],
+                    annotations: HashMap::new(),
+                });
+            }
+
+            images.push(ArchivedImage {
+                manifest: None,
+                config: Descriptor {
+                    media_type: ross_remote::MEDIA_TYPE_CONFIG.to_string(),
+                    digest: format!("{}:{}", digest.algorithm, digest.hash),
+                    size,
+                    urls: vec![],
+                    annotations: HashMap::new(),
+                },
+                layers,
+                tags: entry.repo_tags.unwrap_or_default(),
+            });
+        }
+
+        Ok(images)
+    }
+
+    /// The manifest at `digest` in an archive, picking the host platform's
+    /// image when it is an index.
+    async fn archived_manifest(
+        &self,
+        contents: &ArchiveContents,
+        media_type: &str,
+        digest: &str,
+    ) -> Result<(Vec<u8>, String), ImageError> {
+        let bytes = contents
+            .read(&self.store, &archive::blob_path(digest))
+            .await?;
+        if !ross_remote::is_index_media_type(media_type) {
+            return Ok((bytes, media_type.to_string()));
+        }
+
+        let list: ross_remote::ManifestList = serde_json::from_slice(&bytes)?;
+        let platform = Platform::host();
+        let selected = list.select(&platform).ok_or_else(|| {
+            ImageError::InvalidArchive(format!("no image for {} in {}", platform, digest))
+        })?;
+        let bytes = contents
+            .read(&self.store, &archive::blob_path(&selected.digest))
+            .await?;
+        Ok((bytes, selected.media_type.clone()))
+    }
+
+    /// Checks every blob of an archived image landed in the store.
+    async fn check_blobs(&self, image: &ArchivedImage) -> Result<(), ImageError> {
+        let blobs = std::iter::once(("config", &image.config))
+            .chain(image.layers.iter().map(|layer| ("layer", layer)));
+        for (what, descriptor) in blobs {
+            let digest = archive::parse_digest(&descriptor.digest)?;
+            if !self.store.has_blob(&digest).await {
+                return Err(ImageError::InvalidArchive(format!(
+                    "missing {} {}",
+                    what, descriptor.digest
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Media type of a docker-archive layer, which the archive leaves out,
+    /// told apart by the magic number of the stored blob.
+    async fn archived_layer_media_type(
+        &self,
+        digest: &ross_store::Digest,
+    ) -> Result<&'static str, ImageError> {
+        let (blob, _) = self.store.open_blob(digest).await?;
+        let mut magic = Vec::with_capacity(4);
+        blob.take(4).read_to_end(&mut magic).await?;
+        Ok(match magic.as_slice() {
+            [0x1f, 0x8b, ..] => ross_remote::MEDIA_TYPE_LAYER_GZIP,
+            [0x28, 0xb5, 0x2f, 0xfd] => MEDIA_TYPE_LAYER_ZSTD,
+            _ => MEDIA_TYPE_LAYER,
+        })
+    }
+
+    /// Creates an image from a rootfs tarball streamed as `input`, gzipped or
+    /// not, as `docker import` does: the tarball is the image's only layer,
+    /// under a config running `params.entrypoint` and `params.cmd`.
+    ///
+    /// Returns the image ID.
+    pub async fn import<S>(&self, params: ImportParams, input: S) -> Result<String, ImageError>
+    where
+        S: Stream<Item = Result<Vec<u8>, ImageError>>,
+    {
+        let reference = match params.reference.as_str() {
+            "" => None,
+            name => {
+                let reference = ImageReference::parse(name)
+                    .map_err(|e| ImageError::InvalidReference(e.to_string()))?;
+                if reference.digest.is_some() {
+                    return Err(ImageError::InvalidReference(format!(
+                        "cannot tag with a digest: {}",
+                        name
+                    )));
+                }
+                if !is_valid_tag(reference.tag_or_default()) {
+                    return Err(ImageError::InvalidReference(format!(
+                        "invalid tag: {}",
+                        reference.tag_or_default()
+                    )));
+                }
+                Some(reference)
+            }
+        };
+        tracing::info!("Importing image {}", params.reference);
+
+        let _content = self.hold_content().await;
+
+        // The layer is stored uncompressed, so that its digest, checked by
+        // the store as it is written, is also its diff ID.
+        let (digest, size) =
+            archive::store_tarball(&self.store, input, ross_remote::MEDIA_TYPE_OCI_LAYER).await?;
+        let layer = Descriptor {
+            media_type: ross_remote::MEDIA_TYPE_OCI_LAYER.to_string(),
+            digest: format!("{}:{}", digest.algorithm, digest.hash),
+            size,
+            urls: vec![],
+            annotations: HashMap::new(),
+        };
+
+        let platform = Platform::host();
+        let config = ross_remote::ImageConfig {
+            architecture: platform.architecture,
+            os: platform.os,
+            config: Some(ross_remote::ContainerConfig {
+                hostname: String::new(),
+                domainname: String::new(),
+                user: String::new(),
+                env: vec![],
+                cmd: params.cmd,
+                entrypoint: params.entrypoint,
+                working_dir: String::new(),
+                labels: HashMap::new(),
+                exposed_ports: HashMap::new(),
+                volumes: HashMap::new(),
+            }),
+            rootfs: Some(ross_remote::RootFs {
+                fs_type: "layers".to_string(),
+                diff_ids: vec![layer.digest.clone()],
+            }),
+            history: vec![ross_remote::HistoryEntry {
+                created: None,
+                created_by: Some("ross image import".to_string()),
+                empty_layer: None,
+                comment: None,
+            }],
+        };
+        let config = serde_json::to_vec(&config)?;
+        let (digest, size) = self
+            .store
+            .put_blob(ross_remote::MEDIA_TYPE_OCI_CONFIG, &config, None)
+            .await?;
+        let image_id = format!("{}:{}", digest.algorithm, digest.hash);
+
+        let manifest = ross_remote::ManifestV2 {
+            schema_version: 2,
+            media_type: Some(ross_remote::MEDIA_TYPE_OCI_MANIFEST.to_string()),
+            config: Descriptor {
+                media_type: ross_remote::MEDIA_TYPE_OCI_CONFIG.to_string(),
+                digest: image_id.clone(),
+                size,
+                urls: vec![],
+                annotations: HashMap::new(),
+            },
+            layers: vec![layer],
+        };
+        let (manifest_digest, _) = self
+            .store
+            .put_manifest(
+                &serde_json::to_vec(&manifest)?,
+                ross_remote::MEDIA_TYPE_OCI_MANIFEST,
+            )
+            .await?;
+
+        self.unpack_layers(&manifest.layers, &config).await?;
+
+        if let Some(reference) = reference {
+            self.store
+                .set_tag(
+                    &reference.repository,
+                    reference.tag_or_default(),
+                    &manifest_digest,
+                )
+                .await?;
+        }
+
+        Ok(image_id)
+    }
+
+    /// Extracts `layers` into snapshots keyed by their chain IDs, as pull
+    /// does.
+    async fn unpack_layers(&self, layers: &[Descriptor], config: &[u8]) -> Result<(), ImageError> {
+        let layers = layer_snapshots(layers, config).ok_or_else(|| {
+            ImageError::InvalidArchive("image config does not list a diff ID per layer".to_string())
+        })?;
+        self.snapshotter
+            .extract_layers(None, layers, |_, _| {})
+            .await?;
+        Ok(())
+    }
+}
+
+/// Snapshots to unpack an image's `layers` into, keyed by the chain IDs of
+/// the diff IDs its `config` lists. `None` if it does not list one per layer.
+fn layer_snapshots(layers: &[Descriptor], config: &[u8]) -> Option<Vec<Layer>> {
+    let config: ross_remote::ImageConfig = serde_json::from_slice(config).ok()?;
+    let diff_ids = config.rootfs?.diff_ids;
+    if diff_ids.len() != layers.len() {
+        return None;
+    }
+
+    let layers = layers
+        .iter()
+        .zip(ross_snapshotter::chain_ids(&diff_ids))
+        .map(|(layer, key)| Layer {
+            digest: layer.digest.clone(),
+            media_type: layer.media_type.clone(),
+            key,
+            labels: HashMap::from([(
+                "containerd.io/snapshot/layer.digest".to_string(),
+                layer.digest.clone(),
+            )]),
+        })
+        .collect();
+    Some(layers)
+}
+
+/// Media type of an uncompressed docker layer tarball.
+const MEDIA_TYPE_LAYER: &str = "application/vnd.docker.image.rootfs.diff.tar";
+
+/// Media type of a zstd-compressed layer, which docker only has in OCI form.
+const MEDIA_TYPE_LAYER_ZSTD: &str = "application/vnd.oci.image.layer.v1.tar+zstd";
+
+/// An image read from an archive, its blobs already in the store.
+struct ArchivedImage {
+    /// The archived manifest and its media type, while it still describes
+    /// the stored blobs.
+    manifest: Option<(Vec<u8>, String)>,
+    config: Descriptor,
+    layers: Vec<Descriptor>,
+    /// Names to tag the image with, as `name:tag`.
+    tags: Vec<String>,
+}
+
+/// A tag in the store and the manifest it points to.
+struct TaggedImage {
+    repository: String,
+    tag: String,
+    manifest: ross_store::Digest,
+}
+
+/// Repository name as users type it: Docker Hub official images without
+/// their `library/` prefix.
+fn display_name(repository: &str) -> &str {
+    repository.strip_prefix("library/").unwrap_or(repository)
+}
+
+/// Tags are up to 128 word characters, dots and dashes, not starting with a
+/// dot or dash.
+fn is_valid_tag(tag: &str) -> bool {
+    let valid_char = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-';
+    tag.len() <= 128
+        && tag
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+        && tag.chars().all(valid_char)
+}
+
+/// A pull failure of no particular kind.
+fn pull_error(message: String) -> PullError {
+    PullError {
+        kind: PullErrorKind::Other,
+        message,
+    }
+}
+
+/// A pull failure on the registry's `e`, kept apart when the image is
+/// missing or may not be pulled.
+fn registry_pull_error(context: &str, e: &ross_remote::RegistryError) -> PullError {
+    use ross_remote::RegistryError;
+    let kind = match e {
+        RegistryError::ManifestNotFound(_) | RegistryError::BlobNotFound(_) => {
+            PullErrorKind::NotFound
+        }
+        RegistryError::AuthRequired | RegistryError::AuthFailed(_) => PullErrorKind::Unauthorized,
+        _ => PullErrorKind::Other,
+    };
+    PullError {
+        kind,
+        message: format!("{}: {}", context, e),
+    }
 }
 
 #[derive(Debug)]
@@ -561,6 +1622,11 @@ enum LayerEvent {
         index: usize,
         total: usize,
     },
+    Progress {
+        id: String,
+        current: i64,
+        total: i64,
+    },
     Downloaded {
         id: String,
     },
@@ -569,10 +1635,11 @@ enum LayerEvent {
     },
     Exists {
         id: String,
+        size: i64,
     },
     Error {
         id: String,
-        error: String,
+        error: PullError,
     },
 }
 
@@ -586,6 +1653,7 @@ async fn download_layer(
     total: usize,
     semaphore: Arc<Semaphore>,
     tx: mpsc::Sender<LayerEvent>,
+    counters: Arc<PullCounters>,
 ) {
     let layer_digest = layer.digest.clone();
     let short_layer_id = if layer_digest.len() > 19 {
@@ -600,7 +1668,12 @@ async fn download_layer(
     };
 
     if let Ok(Some(_)) = store.stat_blob(&store_digest).await {
-        let _ = tx.send(LayerEvent::Exists { id: short_layer_id }).await;
+        let _ = tx
+            .send(LayerEvent::Exists {
+                id: short_layer_id,
+                size: layer.size,
+            })
+            .await;
         return;
     }
 
@@ -614,30 +1687,69 @@ async fn download_layer(
         })
         .await;
 
-    let layer_bytes = match registry.get_blob_bytes(&reference, &layer_digest).await {
-        Ok(bytes) => bytes,
+    // The layer is streamed into the store's ingest area and only lands in
+    // the store once its digest has been verified.
+    let mut writer = match store
+        .blob_writer(&layer.media_type, Some(&store_digest))
+        .await
+    {
+        Ok(writer) => writer,
         Err(e) => {
             let _ = tx
                 .send(LayerEvent::Error {
                     id: short_layer_id,
-                    error: format!("Failed to download layer: {}", e),
+                    error: pull_error(format!("Failed to store layer: {}", e)),
                 })
                 .await;
             return;
         }
     };
 
+    let mut last_report = Instant::now();
+    let progress_tx = tx.clone();
+    let progress_id = short_layer_id.clone();
+    let on_progress = |current: u64| {
+        if last_report.elapsed() < PROGRESS_INTERVAL {
+            return;
+        }
+        last_report = Instant::now();
+        // Progress is best effort: drop updates rather than stall the download.
+        let _ = progress_tx.try_send(LayerEvent::Progress {
+            id: progress_id.clone(),
+            current: current as i64,
+            total: layer.size,
+        });
+    };
+
+    match registry
+        .download_blob(&reference, &layer_digest, &mut writer, on_progress)
+        .await
+    {
+        Ok(bytes) => {
+            counters.bytes.fetch_add(bytes, Ordering::Relaxed);
+        }
+        Err(e) => {
+            let _ = tx
+                .send(LayerEvent::Error {
+                    id: short_layer_id,
+                    error: registry_pull_error("Failed to download layer", &e),
+                })
+                .await;
+            return;
+        }
+    }
+
     let _ = tx
         .send(LayerEvent::Downloaded {
             id: short_layer_id.clone(),
         })
         .await;
 
-    if let Err(e) = store.put_blob(&layer.media_type, &layer_bytes, None).await {
+    if let Err(e) = writer.commit().await {
         let _ = tx
             .send(LayerEvent::Error {
                 id: short_layer_id,
-                error: format!("Failed to store layer: {}", e),
+                error: pull_error(format!("Failed to store layer: {}", e)),
             })
             .await;
         return;
@@ -645,3 +1757,439 @@ async fn download_layer(
 
     let _ = tx.send(LayerEvent::Stored { id: short_layer_id }).await;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn image_service(dir: &Path) -> ImageService {
+        let store = Arc::new(FileSystemStore::new(dir.join("store")).await.unwrap());
+        let snapshotter = Arc::new(
+            OverlaySnapshotter::new(dir.join("snapshots"), store.clone())
+                .await
+                .unwrap(),
+        );
+        ImageService::new(store, snapshotter, 3, 1)
+    }
+
+    fn rootfs() -> Vec<u8> {
+        layer_tar("hello.txt", b"hello")
+    }
+
+    /// A layer tarball holding the single file `path`.
+    fn layer_tar(path: &str, data: &[u8]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_ustar();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_mtime(0);
+        header.set_cksum();
+        builder.append_data(&mut header, path, data).unwrap();
+        builder.into_inner().unwrap()
+    }
+
+    async fn import(service: &ImageService, reference: &str) -> String {
+        let params = ImportParams {
+            reference: reference.to_string(),
+            entrypoint: vec![],
+            cmd: vec!["/bin/sh".to_string()],
+        };
+        service
+            .import(params, tokio_stream::once(Ok(rootfs())))
+            .await
+            .unwrap()
+    }
+
+    async fn save(service: &ImageService, names: &[&str]) -> Vec<Vec<u8>> {
+        let names: Vec<String> = names.iter().map(|n| n.to_string()).collect();
+        let stream = service.save(&names).await.unwrap();
+        stream.map(Result::unwrap).collect().await
+    }
+
+    #[tokio::test]
+    async fn test_save_load_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = image_service(&dir.path().join("source")).await;
+        let id = import(&source, "app:1.0").await;
+        let chunks = save(&source, &["app:1.0"]).await;
+        assert!(chunks.iter().all(|c| c.len() <= archive::CHUNK_SIZE));
+
+        let target = image_service(&dir.path().join("target")).await;
+        let loaded = target
+            .load(tokio_stream::iter(chunks.into_iter().map(Ok)))
+            .await
+            .unwrap();
+        assert_eq!(loaded, vec!["app:1.0"]);
+
+        // The archived manifest and its uncompressed layer are kept as is.
+        let images = target.list(ListImagesParams::default()).await.unwrap();
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].id, id);
+        assert_eq!(images[0].repo_tags, vec!["app:1.0"]);
+        let (saved, _) = source.find_image("app:1.0").await.unwrap();
+        let (manifest, _) = target.find_image("app:1.0").await.unwrap();
+        assert_eq!(manifest, saved);
+    }
+
+    #[tokio::test]
+    async fn test_load_rejects_incomplete_input() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = image_service(&dir.path().join("source")).await;
+        import(&source, "app:1.0").await;
+        let archive: Vec<u8> = save(&source, &["app:1.0"]).await.concat();
+
+        let target = image_service(&dir.path().join("target")).await;
+        let truncated = archive[..archive.len() / 2].to_vec();
+        assert!(
+            target
+                .load(tokio_stream::once(Ok(truncated)))
+                .await
+                .is_err()
+        );
+
+        // A failed read of the input fails the load, whatever came before.
+        let input = tokio_stream::iter(vec![
+            Ok(archive),
+            Err(ImageError::InvalidArchive("read failed".to_string())),
+        ]);
+        assert!(target.load(input).await.is_err());
+        assert!(target.tags().await.unwrap().is_empty());
+    }
+
+    /// Digest of `data`, as the store gives it.
+    async fn digest_of(dir: &Path, data: &[u8]) -> String {
+        let store = FileSystemStore::new(dir.join("scratch")).await.unwrap();
+        let (digest, _) = store.put_blob("", data, None).await.unwrap();
+        format!("{}:{}", digest.algorithm, digest.hash)
+    }
+
+    /// An image name and its layers, as the media type to store each with and
+    /// its tarball.
+    type ArchiveImage<'a> = (&'a str, Vec<(&'a str, Vec<u8>)>);
+
+    /// An OCI layout archive of `images`. Returns the archive and each
+    /// image's manifest.
+    async fn oci_archive(dir: &Path, images: &[ArchiveImage<'_>]) -> (Vec<u8>, Vec<Vec<u8>>) {
+        let mut blobs = Vec::new();
+        let mut blob = async |media_type: &str, data: Vec<u8>| {
+            let descriptor = Descriptor {
+                media_type: media_type.to_string(),
+                digest: digest_of(dir, &data).await,
+                size: data.len() as i64,
+                urls: vec![],
+                annotations: HashMap::new(),
+            };
+            blobs.push((archive::blob_path(&descriptor.digest), data));
+            descriptor
+        };
+
+        let mut manifests = Vec::new();
+        let mut entries = Vec::new();
+        for (name, layers) in images {
+            let mut descriptors = Vec::new();
+            let mut diff_ids = Vec::new();
+            for (media_type, tar) in layers {
+                diff_ids.push(digest_of(dir, tar).await);
+                let data = match *media_type {
+                    MEDIA_TYPE_LAYER_ZSTD => zstd::encode_all(tar.as_slice(), 0).unwrap(),
+                    _ => tar.clone(),
+                };
+                descriptors.push(blob(media_type, data).await);
+            }
+            let config = serde_json::json!({
+                "architecture": "amd64",
+                "os": "linux",
+                "rootfs": {"type": "layers", "diff_ids": diff_ids},
+            });
+            let config = blob(
+                ross_remote::MEDIA_TYPE_OCI_CONFIG,
+                serde_json::to_vec(&config).unwrap(),
+            )
+            .await;
+            let manifest = serde_json::to_vec(&ross_remote::ManifestV2 {
+                schema_version: 2,
+                media_type: Some(ross_remote::MEDIA_TYPE_OCI_MANIFEST.to_string()),
+                config,
+                layers: descriptors,
+            })
+            .unwrap();
+            let descriptor = blob(ross_remote::MEDIA_TYPE_OCI_MANIFEST, manifest.clone()).await;
+            manifests.push(manifest);
+            entries.push(IndexEntry {
+                media_type: descriptor.media_type,
+                digest: descriptor.digest,
+                size: descriptor.size,
+                platform: None,
+                annotations: HashMap::from([(ANNOTATION_IMAGE_NAME.to_string(), name.to_string())]),
+            });
+        }
+        let index = serde_json::to_vec(&OciIndex {
+            schema_version: 2,
+            media_type: None,
+            manifests: entries,
+        })
+        .unwrap();
+
+        let mut tar = Vec::new();
+        blobs.insert(0, ("oci-layout".to_string(), archive::OCI_LAYOUT.to_vec()));
+        blobs.insert(1, ("index.json".to_string(), index));
+        for (path, data) in blobs {
+            tar.extend(archive::tar_file(&path, &data).unwrap().concat());
+        }
+        tar.extend(archive::tar_trailer());
+        (tar, manifests)
+    }
+
+    #[tokio::test]
+    async fn test_load_keeps_zstd_layers() {
+        let dir = tempfile::tempdir().unwrap();
+        let (tar, manifests) = oci_archive(
+            dir.path(),
+            &[("app:zstd", vec![(MEDIA_TYPE_LAYER_ZSTD, rootfs())])],
+        )
+        .await;
+
+        let target = image_service(&dir.path().join("target")).await;
+        let loaded = target.load(tokio_stream::once(Ok(tar))).await.unwrap();
+        assert_eq!(loaded, vec!["app:zstd"]);
+
+        let (stored, _) = target.find_image("app:zstd").await.unwrap();
+        let (bytes, _) = target.store.get_manifest(&stored).await.unwrap();
+        assert_eq!(bytes, manifests[0]);
+        // A single layer's chain ID is its diff ID.
+        let diff_id = digest_of(dir.path(), &rootfs()).await;
+        let mounts = target.snapshotter.mounts(&diff_id).await.unwrap();
+        let unpacked = Path::new(&mounts[0].source).join("hello.txt");
+        assert_eq!(std::fs::read_to_string(unpacked).unwrap(), "hello");
+    }
+
+    #[tokio::test]
+    async fn test_shared_layer_gets_a_snapshot_per_base() {
+        let dir = tempfile::tempdir().unwrap();
+        let one = layer_tar("one", b"1");
+        let two = layer_tar("two", b"2");
+        let shared = layer_tar("shared", b"shared");
+        let (tar, _) = oci_archive(
+            dir.path(),
+            &[
+                (
+                    "one:1",
+                    vec![
+                        (MEDIA_TYPE_LAYER, one.clone()),
+                        (MEDIA_TYPE_LAYER, shared.clone()),
+                    ],
+                ),
+                (
+                    "two:1",
+                    vec![
+                        (MEDIA_TYPE_LAYER, two.clone()),
+                        (MEDIA_TYPE_LAYER, shared.clone()),
+                    ],
+                ),
+            ],
+        )
+        .await;
+
+        let target = image_service(&dir.path().join("target")).await;
+        target.load(tokio_stream::once(Ok(tar))).await.unwrap();
+
+        let shared = digest_of(dir.path(), &shared).await;
+        for base in [one, two] {
+            let chain =
+                ross_snapshotter::chain_ids(&[digest_of(dir.path(), &base).await, shared.clone()]);
+            let info = target.snapshotter.stat(&chain[1]).await.unwrap();
+            assert_eq!(info.parent.as_ref(), Some(&chain[0]));
+        }
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        use std::io::Write;
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_import_gzipped_rootfs() {
+        let dir = tempfile::tempdir().unwrap();
+        let service = image_service(dir.path()).await;
+        let id = import(&service, "app:1.0").await;
+
+        // In pieces that split the gzip header, the layer is stored as
+        // before: uncompressed, under the same digest.
+        let gzipped = gzip(&rootfs());
+        let chunks: Vec<_> = gzipped.chunks(1).map(|c| Ok(c.to_vec())).collect();
+        let params = ImportParams {
+            reference: "app:gz".to_string(),
+            entrypoint: vec![],
+            cmd: vec!["/bin/sh".to_string()],
+        };
+        let gzipped_id = service
+            .import(params, tokio_stream::iter(chunks))
+            .await
+            .unwrap();
+        assert_eq!(gzipped_id, id);
+
+        let (manifest, _) = service.find_image("app:gz").await.unwrap();
+        let (bytes, _) = service.store.get_manifest(&manifest).await.unwrap();
+        let manifest: ross_remote::ManifestV2 = serde_json::from_slice(&bytes).unwrap();
+        let layer = archive::parse_digest(&manifest.layers[0].digest).unwrap();
+        let data = service.store.get_blob(&layer, 0, -1).await.unwrap();
+        assert_eq!(data, rootfs());
+    }
+
+    #[tokio::test]
+    async fn test_import_rejects_bad_input() {
+        let dir = tempfile::tempdir().unwrap();
+        let service = image_service(dir.path()).await;
+        let params = || ImportParams {
+            reference: "app:1.0".to_string(),
+            entrypoint: vec![],
+            cmd: vec![],
+        };
+
+        let inputs = [
+            vec![],
+            b"not a tarball, not at all".repeat(100),
+            gzip(b"not a tarball either"),
+        ];
+        for input in inputs {
+            let result = service
+                .import(params(), tokio_stream::once(Ok(input)))
+                .await;
+            assert!(
+                matches!(result, Err(ImageError::InvalidArchive(_))),
+                "{:?}",
+                result
+            );
+        }
+
+        // A failed read of the input fails the import, even after a whole
+        // tarball came through.
+        let input = tokio_stream::iter(vec![
+            Ok(rootfs()),
+            Err(ImageError::InvalidArchive("read failed".to_string())),
+        ]);
+        assert!(service.import(params(), input).await.is_err());
+        assert!(service.tags().await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_registry_pull_error() {
+        use ross_remote::RegistryError;
+        let kind = |e: RegistryError| registry_pull_error("Failed to get manifest", &e).kind;
+        assert_eq!(
+            kind(RegistryError::ManifestNotFound("x".to_string())),
+            PullErrorKind::NotFound
+        );
+        assert_eq!(
+            kind(RegistryError::BlobNotFound("x".to_string())),
+            PullErrorKind::NotFound
+        );
+        assert_eq!(
+            kind(RegistryError::AuthRequired),
+            PullErrorKind::Unauthorized
+        );
+        assert_eq!(
+            kind(RegistryError::AuthFailed("x".to_string())),
+            PullErrorKind::Unauthorized
+        );
+        assert_eq!(
+            kind(RegistryError::Registry("x".to_string())),
+            PullErrorKind::Other
+        );
+
+        let error = registry_pull_error("Failed to get manifest", &RegistryError::AuthRequired);
+        assert_eq!(
+            error.to_string(),
+            "Failed to get manifest: authentication required"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_prune_waits_for_content_being_added() {
+        let dir = tempfile::tempdir().unwrap();
+        let service = image_service(dir.path()).await;
+
+        let content = service.hold_content().await;
+        let waiting = tokio::time::timeout(Duration::from_millis(50), service.lock_for_prune());
+        assert!(waiting.await.is_err());
+        drop(content);
+
+        let lock = service.lock_for_prune().await;
+        let import = import(&service, "app:1.0");
+        tokio::pin!(import);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), &mut import)
+                .await
+                .is_err()
+        );
+        service.prune(&lock, &HashSet::new()).await.unwrap();
+        drop(lock);
+        import.await;
+        let images = service.list(ListImagesParams::default()).await.unwrap();
+        assert_eq!(images.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_disk_usage_counts_shared_blobs_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let service = image_service(dir.path()).await;
+        let app = import(&service, "app:1.0").await;
+        service.tag("app:1.0", "app", "latest").await.unwrap();
+        // Same rootfs, other config: the two images share their layer.
+        let params = ImportParams {
+            reference: "tool:1.0".to_string(),
+            entrypoint: vec![],
+            cmd: vec!["/bin/true".to_string()],
+        };
+        let tool = service
+            .import(params, tokio_stream::once(Ok(rootfs())))
+            .await
+            .unwrap();
+
+        let manifest = |reference: &'static str| async {
+            let (manifest, _) = service.find_image(reference).await.unwrap();
+            let (bytes, _) = service.store.get_manifest(&manifest).await.unwrap();
+            serde_json::from_slice::<ross_remote::ManifestV2>(&bytes).unwrap()
+        };
+        let app_manifest = manifest("app:1.0").await;
+        let tool_manifest = manifest("tool:1.0").await;
+        assert_eq!(
+            app_manifest.layers[0].digest,
+            tool_manifest.layers[0].digest
+        );
+        let layer = app_manifest.layers[0].size;
+        let (app_config, tool_config) = (app_manifest.config.size, tool_manifest.config.size);
+
+        let usage = service.disk_usage(&HashSet::new()).await.unwrap();
+        assert_eq!(
+            usage,
+            ImageDiskUsage {
+                count: 2,
+                active: 0,
+                size: layer + app_config + tool_config,
+                reclaimable: layer + app_config + tool_config,
+            }
+        );
+
+        // The layer is kept for the image in use, so only the other config
+        // is reclaimable.
+        let usage = service
+            .disk_usage(&HashSet::from([app.clone()]))
+            .await
+            .unwrap();
+        assert_eq!(usage.active, 1);
+        assert_eq!(usage.size, layer + app_config + tool_config);
+        assert_eq!(usage.reclaimable, tool_config);
+
+        let usage = service
+            .disk_usage(&HashSet::from([app, tool]))
+            .await
+            .unwrap();
+        assert_eq!(usage.active, 2);
+        assert_eq!(usage.reclaimable, 0);
+    }
+}
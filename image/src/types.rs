@@ -24,6 +24,18 @@ pub struct RootFs {
     pub layers: Vec<String>,
 }
 
+/// Totals of the pulls made since the daemon started.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PullStats {
+    /// Pulls that completed.
+    pub images: u64,
+    /// Bytes of configs and layers downloaded, not counting those already
+    /// stored.
+    pub bytes: u64,
+    /// Bytes of layers pulls found already stored, shared with other images.
+    pub reused_bytes: u64,
+}
+
 #[derive(Debug, Clone)]
 pub struct ImageHistory {
     pub id: String,
@@ -53,7 +65,28 @@ pub struct PullProgress {
     pub progress: String,
     pub current: Option<i64>,
     pub total: Option<i64>,
-    pub error: Option<String>,
+    pub error: Option<PullError>,
+}
+
+/// Why a pull failed. The kind lets callers tell an image the registry does
+/// not have from one they may not pull.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PullError {
+    pub kind: PullErrorKind,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PullErrorKind {
+    NotFound,
+    Unauthorized,
+    Other,
+}
+
+impl std::fmt::Display for PullError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -87,12 +120,40 @@ pub struct BuildParams {
     pub platform: String,
 }
 
+/// How to tag and configure an image imported from a rootfs tarball.
+#[derive(Debug, Clone, Default)]
+pub struct ImportParams {
+    /// `name[:tag]` to tag the image with; the image is left untagged when
+    /// empty.
+    pub reference: String,
+    pub entrypoint: Vec<String>,
+    pub cmd: Vec<String>,
+}
+
 #[derive(Debug, Clone)]
 pub struct RemoveImageResult {
     pub deleted: Vec<String>,
     pub untagged: Vec<String>,
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct PruneImagesResult {
+    pub deleted: Vec<String>,
+    pub space_reclaimed: i64,
+}
+
+/// Disk space taken by images, as `system df` reports it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImageDiskUsage {
+    pub count: i64,
+    /// Images some container was created from.
+    pub active: i64,
+    /// Size of the blobs of every image, each counted once.
+    pub size: i64,
+    /// Size of the blobs that only images without containers refer to.
+    pub reclaimable: i64,
+}
+
 #[derive(Debug, Clone)]
 pub struct SearchResult {
     pub name: String,
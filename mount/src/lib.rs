@@ -2,7 +2,10 @@ mod error;
 mod overlay;
 
 pub use error::MountError;
-pub use overlay::{mount_overlay, unmount};
+pub use overlay::{
+    escape_overlay_path, lowerdir_option, mount_overlay, split_lowerdir, unescape_overlay_path,
+    unmount,
+};
 
 #[derive(Debug, Clone)]
 pub struct MountSpec {
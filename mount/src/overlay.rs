@@ -5,6 +5,65 @@ use std::path::Path;
 #[cfg(target_os = "linux")]
 use nix::mount::{MntFlags, MsFlags, mount, umount2};
 
+/// Escapes a layer path for an overlay mount option. The kernel splits the
+/// options on `,` and `lowerdir` on `:`, unless they are escaped with a
+/// backslash, which it then removes. `=` needs no escaping: only the first
+/// one separates an option's name from its value.
+pub fn escape_overlay_path(path: &str) -> String {
+    let mut escaped = String::with_capacity(path.len());
+    for c in path.chars() {
+        if matches!(c, '\\' | ':' | ',') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Reverses [`escape_overlay_path`].
+pub fn unescape_overlay_path(path: &str) -> String {
+    let mut unescaped = String::with_capacity(path.len());
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            c => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+/// Builds the `lowerdir` option for `dirs`, the uppermost layer first as
+/// overlayfs stacks them.
+pub fn lowerdir_option<S: AsRef<str>>(dirs: &[S]) -> String {
+    let dirs: Vec<String> = dirs
+        .iter()
+        .map(|dir| escape_overlay_path(dir.as_ref()))
+        .collect();
+    format!("lowerdir={}", dirs.join(":"))
+}
+
+/// Splits the value of a `lowerdir` option into its layer paths, uppermost
+/// first, unescaping them.
+pub fn split_lowerdir(value: &str) -> Vec<String> {
+    let mut dirs = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            ':' => {
+                dirs.push(unescape_overlay_path(&value[start..i]));
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    dirs.push(unescape_overlay_path(&value[start..]));
+    dirs
+}
+
 /// Mount a filesystem based on the mount specification.
 ///
 /// Supports:
@@ -129,4 +188,71 @@ mod tests {
         assert_eq!(spec.mount_type, "overlay");
         assert_eq!(spec.options.len(), 3);
     }
+
+    #[test]
+    fn test_overlay_path_escaping() {
+        let dirs = [
+            "/layers/a:b",
+            "/layers/c,d",
+            "/layers/e=f\\g",
+            "/layers/plain",
+        ];
+        let option = lowerdir_option(&dirs);
+        assert_eq!(
+            option,
+            "lowerdir=/layers/a\\:b:/layers/c\\,d:/layers/e=f\\\\g:/layers/plain"
+        );
+        let value = option.strip_prefix("lowerdir=").unwrap();
+        assert_eq!(split_lowerdir(value), dirs);
+
+        for dir in dirs {
+            assert_eq!(unescape_overlay_path(&escape_overlay_path(dir)), dir);
+        }
+    }
+
+    /// Mounts layers whose paths the option string would otherwise split
+    /// apart. Needs root; skipped otherwise.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_mount_overlay_special_characters() {
+        if !nix::unistd::geteuid().is_root() {
+            eprintln!("skipping: overlay mounts need root");
+            return;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().to_string();
+        let (upper, lower, base) = (path("up,per=1"), path("low:er,2"), path("ba\\se=3"));
+        let (work, target) = (path("wo:rk"), dir.path().join("merged"));
+        for d in [&upper, &lower, &base, &work] {
+            std::fs::create_dir_all(d).unwrap();
+        }
+        std::fs::write(Path::new(&base).join("file"), "base").unwrap();
+        std::fs::write(Path::new(&base).join("shadowed"), "base").unwrap();
+        std::fs::write(Path::new(&lower).join("shadowed"), "lower").unwrap();
+
+        let spec = MountSpec::new(
+            "overlay",
+            "overlay",
+            vec![
+                lowerdir_option(&[&lower, &base]),
+                format!("upperdir={}", escape_overlay_path(&upper)),
+                format!("workdir={}", escape_overlay_path(&work)),
+            ],
+        );
+        mount_overlay(&spec, &target).unwrap();
+
+        let read = |name: &str| std::fs::read_to_string(target.join(name)).unwrap();
+        let written = std::fs::write(target.join("new"), "upper");
+        let (file, shadowed) = (read("file"), read("shadowed"));
+        unmount(&target).unwrap();
+
+        written.unwrap();
+        assert_eq!(file, "base");
+        assert_eq!(shadowed, "lower");
+        assert_eq!(
+            std::fs::read_to_string(Path::new(&upper).join("new")).unwrap(),
+            "upper"
+        );
+    }
 }
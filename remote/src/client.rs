@@ -1,14 +1,123 @@
 use crate::error::RegistryError;
-use crate::reference::ImageReference;
+use crate::reference::{ImageReference, canonical_registry};
 use crate::types::*;
-use reqwest::Client;
-use reqwest::header::{ACCEPT, AUTHORIZATION, HeaderMap, HeaderValue};
+use reqwest::header::{ACCEPT, CONTENT_RANGE, HeaderMap, HeaderValue, RANGE, WWW_AUTHENTICATE};
+use reqwest::{Client, RequestBuilder, StatusCode};
+use sha2::{Digest as _, Sha256};
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 use tokio::sync::RwLock;
 
+/// Lifetime assumed for tokens that don't specify `expires_in`, per the
+/// distribution token spec.
+const DEFAULT_TOKEN_LIFETIME: Duration = Duration::from_secs(60);
+/// Tokens are refreshed this long before they actually expire.
+const TOKEN_EXPIRY_MARGIN: Duration = Duration::from_secs(10);
+
+/// Attempts made for a blob download unless configured otherwise.
+const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+/// Delay before the first blob download retry, doubled on each further one.
+const RETRY_BACKOFF_MIN: Duration = Duration::from_millis(500);
+const RETRY_BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+#[derive(Clone)]
+struct Credentials {
+    username: String,
+    password: String,
+}
+
+/// How requests for a repository are authenticated.
+#[derive(Clone)]
+enum CachedAuth {
+    Bearer { token: String, expires_at: Instant },
+    Basic,
+}
+
+/// How a registry host that opted out of verified HTTPS is reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InsecureMode {
+    /// HTTPS without verifying the server certificate.
+    SkipVerify,
+    /// Plain HTTP.
+    Http,
+}
+
+impl InsecureMode {
+    /// Parses an insecure registry setting: `http://host[:port]` for plain
+    /// HTTP, or a bare `host[:port]` for HTTPS without verification.
+    ///
+    /// Only exact hosts are accepted, no wildcards or paths, so a setting
+    /// never applies to more registries than the one it names. The scheme's
+    /// default port is dropped, as URLs leave it out.
+    pub fn parse(spec: &str) -> Result<(String, InsecureMode), RegistryError> {
+        let (host, mode) = match spec.strip_prefix("http://") {
+            Some(host) => (host, InsecureMode::Http),
+            None => (
+                spec.strip_prefix("https://").unwrap_or(spec),
+                InsecureMode::SkipVerify,
+            ),
+        };
+        let host = host.trim_end_matches('/');
+
+        if !is_valid_host(host) {
+            return Err(RegistryError::InvalidReference(format!(
+                "invalid insecure registry: {}",
+                spec
+            )));
+        }
+
+        let host = host.to_ascii_lowercase();
+        Ok((mode.without_default_port(&host).to_string(), mode))
+    }
+
+    /// `host` without the default port of the scheme this mode uses.
+    fn without_default_port(self, host: &str) -> &str {
+        let port = match self {
+            InsecureMode::SkipVerify => ":443",
+            InsecureMode::Http => ":80",
+        };
+        host.strip_suffix(port).unwrap_or(host)
+    }
+}
+
+/// Parses a registry mirror setting, `[REGISTRY=]MIRROR`, into the registry
+/// and the mirror host. Both are `host[:port]`; the registry defaults to
+/// Docker Hub. A mirror to be reached over plain HTTP or without certificate
+/// verification must also be listed as an insecure registry.
+pub fn parse_mirror(spec: &str) -> Result<(String, String), RegistryError> {
+    let (registry, mirror) = spec.split_once('=').unwrap_or(("docker.io", spec));
+    let mirror = mirror.strip_prefix("https://").unwrap_or(mirror);
+    let mirror = mirror.trim_end_matches('/');
+    if !is_valid_host(registry) || !is_valid_host(mirror) {
+        return Err(RegistryError::InvalidReference(format!(
+            "invalid registry mirror: {}",
+            spec
+        )));
+    }
+    Ok((canonical_registry(registry), mirror.to_ascii_lowercase()))
+}
+
+/// Whether `host` is a bare `host[:port]`, without scheme, path or wildcard.
+fn is_valid_host(host: &str) -> bool {
+    !host.is_empty()
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | ':' | '[' | ']'))
+}
+
 pub struct RegistryClient {
     client: Client,
-    tokens: Arc<RwLock<std::collections::HashMap<String, String>>>,
+    /// Client that skips certificate verification, only used for hosts
+    /// listed as [`InsecureMode::SkipVerify`].
+    insecure_client: Option<Client>,
+    insecure_registries: HashMap<String, InsecureMode>,
+    /// Mirror hosts tried, in order, before each registry.
+    mirrors: HashMap<String, Vec<String>>,
+    credentials: Option<Credentials>,
+    auth: Arc<RwLock<HashMap<String, CachedAuth>>>,
+    max_attempts: u32,
 }
 
 impl RegistryClient {
@@ -17,47 +126,234 @@ impl RegistryClient {
 
         Ok(Self {
             client,
-            tokens: Arc::new(RwLock::new(std::collections::HashMap::new())),
+            insecure_client: None,
+            insecure_registries: HashMap::new(),
+            mirrors: HashMap::new(),
+            credentials: None,
+            auth: Arc::new(RwLock::new(HashMap::new())),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
         })
     }
 
+    /// Sets how many times a blob download is attempted before giving up.
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Allows the listed registry hosts (`host[:port]`) to be reached over
+    /// plain HTTP or HTTPS without certificate verification. Every other
+    /// registry still requires verified HTTPS.
+    pub fn with_insecure_registries(
+        mut self,
+        registries: HashMap<String, InsecureMode>,
+    ) -> Result<Self, RegistryError> {
+        if registries.values().any(|m| *m == InsecureMode::SkipVerify) {
+            self.insecure_client = Some(
+                Client::builder()
+                    .user_agent("ross/0.1.0")
+                    .danger_accept_invalid_certs(true)
+                    .build()?,
+            );
+        }
+        self.insecure_registries = registries
+            .into_iter()
+            .map(|(host, mode)| {
+                let host = host.to_ascii_lowercase();
+                (mode.without_default_port(&host).to_string(), mode)
+            })
+            .collect();
+        Ok(self)
+    }
+
+    /// Fetches images of each listed registry from its mirrors first, in
+    /// order, and from the registry itself only when none of them has what
+    /// is asked for. Registries are `host[:port]`, with `docker.io` standing
+    /// for Docker Hub.
+    pub fn with_mirrors(mut self, mirrors: HashMap<String, Vec<String>>) -> Self {
+        self.mirrors = mirrors
+            .into_iter()
+            .map(|(registry, mirrors)| (canonical_registry(&registry), mirrors))
+            .collect();
+        self
+    }
+
+    /// Creates a client that authenticates with static credentials, either
+    /// directly (basic auth) or to obtain bearer tokens.
+    pub fn with_credentials(
+        username: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Result<Self, RegistryError> {
+        let mut client = Self::new()?;
+        client.credentials = Some(Credentials {
+            username: username.into(),
+            password: password.into(),
+        });
+        Ok(client)
+    }
+
     fn registry_url(&self, registry: &str) -> String {
-        if registry.starts_with("localhost") || registry.contains("127.0.0.1") {
+        let registry_key = registry.to_ascii_lowercase();
+        let plain_http = self
+            .insecure_registries
+            .get(InsecureMode::Http.without_default_port(&registry_key))
+            == Some(&InsecureMode::Http);
+        if plain_http || registry.starts_with("localhost") || registry.contains("127.0.0.1") {
             format!("http://{}", registry)
         } else {
             format!("https://{}", registry)
         }
     }
 
-    async fn get_token(&self, reference: &ImageReference) -> Result<Option<String>, RegistryError> {
-        let key = format!("{}/{}", reference.registry, reference.repository);
-        let tokens = self.tokens.read().await;
-        Ok(tokens.get(&key).cloned())
+    /// The HTTP client for `url`, which only skips certificate verification
+    /// when its host is listed as such.
+    fn client_for(&self, url: &str) -> &Client {
+        let Some(insecure) = &self.insecure_client else {
+            return &self.client;
+        };
+        let Ok(url) = reqwest::Url::parse(url) else {
+            return &self.client;
+        };
+        let Some(host) = url.host_str() else {
+            return &self.client;
+        };
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        };
+        match self.insecure_registries.get(&host) {
+            Some(InsecureMode::SkipVerify) => insecure,
+            _ => &self.client,
+        }
+    }
+
+    /// `reference` on each of the mirrors of its registry, in the order they
+    /// are tried.
+    fn mirrors_of(&self, reference: &ImageReference) -> Vec<ImageReference> {
+        self.mirrors
+            .get(&reference.registry.to_ascii_lowercase())
+            .into_iter()
+            .flatten()
+            .map(|mirror| reference.mirrored(mirror))
+            .collect()
+    }
+
+    /// The credentials to present to the registry of `reference`. They are
+    /// meant for the registries pulled from, so mirrors never see them.
+    fn credentials_for(&self, reference: &ImageReference) -> Option<&Credentials> {
+        let registry = reference.registry.to_ascii_lowercase();
+        if self
+            .mirrors
+            .values()
+            .flatten()
+            .any(|mirror| *mirror == registry)
+        {
+            return None;
+        }
+        self.credentials.as_ref()
+    }
+
+    fn auth_key(reference: &ImageReference) -> String {
+        format!("{}/{}", reference.registry, reference.repository)
+    }
+
+    /// Returns the cached authentication for the repository, dropping expired tokens.
+    async fn cached_auth(&self, reference: &ImageReference) -> Option<CachedAuth> {
+        let key = Self::auth_key(reference);
+        let auth = self.auth.read().await.get(&key).cloned();
+        match auth {
+            Some(CachedAuth::Bearer { expires_at, .. }) if Instant::now() >= expires_at => {
+                self.auth.write().await.remove(&key);
+                None
+            }
+            auth => auth,
+        }
+    }
+
+    fn apply_auth(
+        &self,
+        request: RequestBuilder,
+        reference: &ImageReference,
+        auth: Option<&CachedAuth>,
+    ) -> RequestBuilder {
+        match (auth, self.credentials_for(reference)) {
+            (Some(CachedAuth::Bearer { token, .. }), _) => request.bearer_auth(token),
+            (Some(CachedAuth::Basic), Some(creds)) => {
+                request.basic_auth(&creds.username, Some(&creds.password))
+            }
+            _ => request,
+        }
     }
 
+    /// Answers a `Www-Authenticate` challenge and caches the result for the repository.
     async fn authenticate(
         &self,
         reference: &ImageReference,
         www_auth: &str,
-    ) -> Result<String, RegistryError> {
-        let realm = extract_auth_param(www_auth, "realm")
+    ) -> Result<CachedAuth, RegistryError> {
+        let challenge = Challenge::parse(www_auth).ok_or_else(|| {
+            RegistryError::AuthFailed(format!("unsupported www-authenticate: {}", www_auth))
+        })?;
+
+        let auth = match challenge.scheme.as_str() {
+            "basic" => {
+                if self.credentials_for(reference).is_none() {
+                    return Err(RegistryError::AuthRequired);
+                }
+                CachedAuth::Basic
+            }
+            "bearer" => self.fetch_token(reference, &challenge).await?,
+            other => {
+                return Err(RegistryError::AuthFailed(format!(
+                    "unsupported auth scheme: {}",
+                    other
+                )));
+            }
+        };
+
+        self.auth
+            .write()
+            .await
+            .insert(Self::auth_key(reference), auth.clone());
+
+        Ok(auth)
+    }
+
+    async fn fetch_token(
+        &self,
+        reference: &ImageReference,
+        challenge: &Challenge,
+    ) -> Result<CachedAuth, RegistryError> {
+        let realm = challenge
+            .param("realm")
             .ok_or_else(|| RegistryError::AuthFailed("no realm in www-authenticate".to_string()))?;
-        let service = extract_auth_param(www_auth, "service");
-        let scope = extract_auth_param(www_auth, "scope")
+        // On a mirror the repository is still the one of the origin registry.
+        let scope = challenge
+            .param("scope")
+            .map(|s| s.to_string())
             .unwrap_or_else(|| format!("repository:{}:pull", reference.repository));
 
-        let mut url = realm.to_string();
-        url.push_str("?scope=");
-        url.push_str(&scope);
-        if let Some(svc) = service {
-            url.push_str("&service=");
-            url.push_str(&svc);
+        let mut query = vec![("scope", scope.as_str())];
+        if let Some(service) = challenge.param("service") {
+            query.push(("service", service));
         }
 
-        tracing::debug!("Authenticating at: {}", url);
+        tracing::debug!("Authenticating at: {} (scope {})", realm, scope);
 
-        let response = self.client.get(&url).send().await?;
+        let credentials = self.credentials_for(reference);
+        let mut request = self.client_for(realm).get(realm).query(&query);
+        if let Some(creds) = credentials {
+            request = request.basic_auth(&creds.username, Some(&creds.password));
+        }
+        let response = request.send().await?;
 
+        if response.status() == StatusCode::UNAUTHORIZED {
+            return Err(if credentials.is_some() {
+                RegistryError::AuthFailed("invalid credentials".to_string())
+            } else {
+                RegistryError::AuthRequired
+            });
+        }
         if !response.status().is_success() {
             return Err(RegistryError::AuthFailed(format!(
                 "token endpoint returned {}",
@@ -65,16 +361,23 @@ impl RegistryClient {
             )));
         }
 
+        let issued = Instant::now();
         let token_response: TokenResponse = response.json().await?;
         let token = token_response
             .get_token()
             .ok_or_else(|| RegistryError::AuthFailed("no token in response".to_string()))?
             .to_string();
 
-        let key = format!("{}/{}", reference.registry, reference.repository);
-        self.tokens.write().await.insert(key, token.clone());
+        let lifetime = token_response
+            .expires_in
+            .filter(|secs| *secs > 0)
+            .map(|secs| Duration::from_secs(secs as u64))
+            .unwrap_or(DEFAULT_TOKEN_LIFETIME);
 
-        Ok(token)
+        Ok(CachedAuth::Bearer {
+            token,
+            expires_at: issued + lifetime.saturating_sub(TOKEN_EXPIRY_MARGIN),
+        })
     }
 
     async fn request_with_auth(
@@ -85,41 +388,92 @@ impl RegistryClient {
     ) -> Result<reqwest::Response, RegistryError> {
         let mut headers = HeaderMap::new();
         headers.insert(ACCEPT, HeaderValue::from_str(&accept.join(", ")).unwrap());
+        self.request_with_headers(url, reference, headers).await
+    }
 
-        if let Some(token) = self.get_token(reference).await? {
-            headers.insert(
-                AUTHORIZATION,
-                HeaderValue::from_str(&format!("Bearer {}", token)).unwrap(),
-            );
+    async fn request_with_headers(
+        &self,
+        url: &str,
+        reference: &ImageReference,
+        headers: HeaderMap,
+    ) -> Result<reqwest::Response, RegistryError> {
+        let auth = self.cached_auth(reference).await;
+        let request = self.client_for(url).get(url).headers(headers.clone());
+        let response = self
+            .apply_auth(request, reference, auth.as_ref())
+            .send()
+            .await?;
+
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return Ok(response);
         }
 
-        let response = self.client.get(url).headers(headers.clone()).send().await?;
+        let www_auth = response
+            .headers()
+            .get(WWW_AUTHENTICATE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("")
+            .to_string();
 
-        if response.status() == reqwest::StatusCode::UNAUTHORIZED {
-            let www_auth = response
-                .headers()
-                .get("www-authenticate")
-                .and_then(|v| v.to_str().ok())
-                .unwrap_or("");
+        let auth = self.authenticate(reference, &www_auth).await?;
 
-            let token = self.authenticate(reference, www_auth).await?;
+        let request = self.client_for(url).get(url).headers(headers);
+        let response = self
+            .apply_auth(request, reference, Some(&auth))
+            .send()
+            .await?;
+        if response.status() == StatusCode::UNAUTHORIZED {
+            self.auth.write().await.remove(&Self::auth_key(reference));
+            return Err(RegistryError::AuthFailed(format!(
+                "access to {} denied",
+                reference.full_name()
+            )));
+        }
+        Ok(response)
+    }
 
-            headers.insert(
-                AUTHORIZATION,
-                HeaderValue::from_str(&format!("Bearer {}", token)).unwrap(),
-            );
+    pub async fn get_manifest(
+        &self,
+        reference: &ImageReference,
+    ) -> Result<(Manifest, String, String), RegistryError> {
+        let (body, media_type, digest) = self.get_manifest_bytes(reference).await?;
 
-            let response = self.client.get(url).headers(headers).send().await?;
-            Ok(response)
+        let manifest = if is_index_media_type(&media_type) {
+            let list: ManifestList = serde_json::from_slice(&body)?;
+            Manifest::List(list)
         } else {
-            Ok(response)
+            Manifest::V2(ManifestV2::parse(&body, &media_type)?)
+        };
+
+        Ok((manifest, media_type, digest))
+    }
+
+    /// Fetches the raw manifest bytes along with their media type and digest.
+    /// Digest references are verified against the content.
+    pub async fn get_manifest_bytes(
+        &self,
+        reference: &ImageReference,
+    ) -> Result<(Vec<u8>, String, String), RegistryError> {
+        for mirror in self.mirrors_of(reference) {
+            match self.fetch_manifest_bytes(&mirror).await {
+                Ok(manifest) => return Ok(manifest),
+                Err(e) => tracing::warn!(
+                    "Fetching manifest of {} from mirror {} failed: {}",
+                    reference.full_name(),
+                    mirror.registry,
+                    e
+                ),
+            }
         }
+        self.fetch_manifest_bytes(reference).await
     }
 
-    pub async fn get_manifest(
+    /// Fetches a manifest from the registry `reference` names, ignoring
+    /// mirrors.
+    async fn fetch_manifest_bytes(
         &self,
         reference: &ImageReference,
-    ) -> Result<(Manifest, String, String), RegistryError> {
+    ) -> Result<(Vec<u8>, String, String), RegistryError> {
         let tag_or_digest = reference.reference();
         let url = format!(
             "{}/v2/{}/manifests/{}",
@@ -130,14 +484,9 @@ impl RegistryClient {
 
         tracing::debug!("Fetching manifest from: {}", url);
 
-        let accept = [
-            MEDIA_TYPE_MANIFEST_V2,
-            MEDIA_TYPE_MANIFEST_LIST,
-            MEDIA_TYPE_OCI_MANIFEST,
-            MEDIA_TYPE_OCI_INDEX,
-        ];
-
-        let response = self.request_with_auth(&url, reference, &accept).await?;
+        let response = self
+            .request_with_auth(&url, reference, MANIFEST_MEDIA_TYPES)
+            .await?;
 
         if !response.status().is_success() {
             return Err(RegistryError::ManifestNotFound(format!(
@@ -150,75 +499,104 @@ impl RegistryClient {
             .headers()
             .get("content-type")
             .and_then(|v| v.to_str().ok())
-            .unwrap_or(MEDIA_TYPE_MANIFEST_V2)
-            .to_string();
+            .map(str::to_string);
 
-        let digest = response
+        let header_digest = response
             .headers()
             .get("docker-content-digest")
             .and_then(|v| v.to_str().ok())
-            .unwrap_or("")
-            .to_string();
+            .map(str::to_string);
 
-        let body = response.text().await?;
+        let body = response.bytes().await?.to_vec();
+        let media_type = manifest_media_type(content_type.as_deref(), &body)?;
+        let digest = format!("sha256:{}", hex::encode(Sha256::digest(&body)));
 
-        let manifest =
-            if content_type.contains("manifest.list") || content_type.contains("image.index") {
-                let list: ManifestList = serde_json::from_str(&body)?;
-                Manifest::List(list)
-            } else {
-                let v2: ManifestV2 = serde_json::from_str(&body)?;
-                Manifest::V2(v2)
-            };
+        if let Some(expected) = &reference.digest
+            && *expected != digest
+        {
+            return Err(RegistryError::Registry(format!(
+                "manifest digest mismatch: expected {}, got {}",
+                expected, digest
+            )));
+        }
+
+        // Only trust the header for algorithms we can't recompute ourselves.
+        let digest = match header_digest {
+            Some(d) if !d.starts_with("sha256:") => d,
+            _ => digest,
+        };
 
-        Ok((manifest, content_type, digest))
+        Ok((body, media_type, digest))
     }
 
+    /// Resolves `reference` to the manifest for `platform`, going through the
+    /// manifest list when the reference points at one.
     pub async fn get_manifest_for_platform(
         &self,
         reference: &ImageReference,
-        os: &str,
-        arch: &str,
-    ) -> Result<(ManifestV2, String, String), RegistryError> {
-        let (manifest, content_type, digest) = self.get_manifest(reference).await?;
-
-        match manifest {
-            Manifest::V2(m) => Ok((m, content_type, digest)),
-            Manifest::List(list) => {
-                let platform_manifest = list
-                    .manifests
-                    .iter()
-                    .find(|m| {
-                        if let Some(p) = &m.platform {
-                            p.os == os && p.architecture == arch
-                        } else {
-                            false
-                        }
-                    })
-                    .ok_or_else(|| {
-                        RegistryError::ManifestNotFound(format!("no manifest for {}/{}", os, arch))
-                    })?;
-
-                let mut ref_with_digest = reference.clone();
-                ref_with_digest.digest = Some(platform_manifest.digest.clone());
-                ref_with_digest.tag = None;
-
-                let (manifest, content_type, digest) = self.get_manifest(&ref_with_digest).await?;
-
-                match manifest {
-                    Manifest::V2(m) => Ok((m, content_type, digest)),
-                    Manifest::List(_) => Err(RegistryError::UnsupportedMediaType(
-                        "nested manifest lists not supported".to_string(),
-                    )),
-                }
-            }
+        platform: &Platform,
+    ) -> Result<ResolvedManifest, RegistryError> {
+        let (body, media_type, digest) = self.get_manifest_bytes(reference).await?;
+
+        if !is_index_media_type(&media_type) {
+            return Ok(ResolvedManifest {
+                manifest: ManifestV2::parse(&body, &media_type)?,
+                bytes: body,
+                media_type,
+                digest,
+                index: None,
+            });
         }
+
+        let list: ManifestList = serde_json::from_slice(&body)?;
+        let platform_manifest = list.select(platform).ok_or_else(|| {
+            RegistryError::ManifestNotFound(format!("no manifest for {}", platform))
+        })?;
+
+        let mut ref_with_digest = reference.clone();
+        ref_with_digest.digest = Some(platform_manifest.digest.clone());
+        ref_with_digest.tag = None;
+
+        let (bytes, media_type, digest) = self.get_manifest_bytes(&ref_with_digest).await?;
+        if is_index_media_type(&media_type) {
+            return Err(RegistryError::UnsupportedMediaType(
+                "nested manifest lists not supported".to_string(),
+            ));
+        }
+
+        Ok(ResolvedManifest {
+            manifest: ManifestV2::parse(&bytes, &media_type)?,
+            bytes,
+            media_type,
+            digest,
+            index: Some(body),
+        })
     }
 
     pub async fn get_blob(
         &self,
         reference: &ImageReference,
         digest: &str,
+    ) -> Result<reqwest::Response, RegistryError> {
+        for mirror in self.mirrors_of(reference) {
+            match self.fetch_blob(&mirror, digest).await {
+                Ok(response) => return Ok(response),
+                Err(e) => tracing::warn!(
+                    "Fetching blob {} from mirror {} failed: {}",
+                    digest,
+                    mirror.registry,
+                    e
+                ),
+            }
+        }
+        self.fetch_blob(reference, digest).await
+    }
+
+    /// Requests a blob from the registry `reference` names, ignoring mirrors.
+    async fn fetch_blob(
+        &self,
+        reference: &ImageReference,
+        digest: &str,
     ) -> Result<reqwest::Response, RegistryError> {
         let url = format!(
             "{}/v2/{}/blobs/{}",
@@ -240,6 +618,162 @@ impl RegistryClient {
         Ok(response)
     }
 
+    /// Streams a blob into `sink`, returning the number of bytes written.
+    /// `on_progress` is called with the running total after each chunk.
+    ///
+    /// Transfers that fail part way are retried with exponential backoff,
+    /// resuming after the bytes already written with a `Range` request. When
+    /// the registry ignores the range and resends the whole blob, the prefix
+    /// already written is skipped. The caller verifies the digest.
+    ///
+    /// Each mirror of the registry gets one attempt, which the next source
+    /// resumes from; the retries are spent on the registry itself.
+    pub async fn download_blob<W>(
+        &self,
+        reference: &ImageReference,
+        digest: &str,
+        sink: &mut W,
+        mut on_progress: impl FnMut(u64),
+    ) -> Result<u64, RegistryError>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let mut written = 0u64;
+        for mirror in self.mirrors_of(reference) {
+            match self
+                .fetch_blob_from(&mirror, digest, &mut written, sink, &mut on_progress)
+                .await
+            {
+                Ok(()) => {
+                    sink.flush().await?;
+                    return Ok(written);
+                }
+                Err(e @ RegistryError::Io(_)) => return Err(e),
+                Err(e) => tracing::warn!(
+                    digest,
+                    offset = written,
+                    "Downloading blob from mirror {} failed: {}",
+                    mirror.registry,
+                    e
+                ),
+            }
+        }
+
+        let mut backoff = RETRY_BACKOFF_MIN;
+        let mut attempt = 1;
+
+        loop {
+            match self
+                .fetch_blob_from(reference, digest, &mut written, sink, &mut on_progress)
+                .await
+            {
+                Ok(()) => {
+                    sink.flush().await?;
+                    return Ok(written);
+                }
+                Err(e) if attempt < self.max_attempts && is_transient(&e) => {
+                    tracing::warn!(
+                        digest,
+                        attempt,
+                        offset = written,
+                        "Blob download failed, retrying in {:?}: {}",
+                        backoff,
+                        e
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(RETRY_BACKOFF_MAX);
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// One attempt at fetching a blob from byte `*written` on, advancing
+    /// `*written` as data reaches the sink.
+    async fn fetch_blob_from<W>(
+        &self,
+        reference: &ImageReference,
+        digest: &str,
+        written: &mut u64,
+        sink: &mut W,
+        on_progress: &mut impl FnMut(u64),
+    ) -> Result<(), RegistryError>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let url = format!(
+            "{}/v2/{}/blobs/{}",
+            self.registry_url(&reference.registry),
+            reference.repository,
+            digest
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, HeaderValue::from_static("application/octet-stream"));
+        if *written > 0 {
+            tracing::debug!("Resuming blob {} at byte {}", digest, written);
+            headers.insert(
+                RANGE,
+                HeaderValue::from_str(&format!("bytes={}-", written)).unwrap(),
+            );
+        }
+
+        let mut response = self.request_with_headers(&url, reference, headers).await?;
+
+        let status = response.status();
+        let mut skip = match status {
+            StatusCode::PARTIAL_CONTENT => {
+                let start = response
+                    .headers()
+                    .get(CONTENT_RANGE)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(content_range_start)
+                    .unwrap_or(*written);
+                if start > *written {
+                    return Err(RegistryError::Registry(format!(
+                        "registry resumed blob {} at byte {}, expected {}",
+                        digest, start, written
+                    )));
+                }
+                *written - start
+            }
+            // The range was ignored and the whole blob is coming again.
+            s if s.is_success() => *written,
+            // Everything was already written before the last attempt failed.
+            StatusCode::RANGE_NOT_SATISFIABLE if *written > 0 => return Ok(()),
+            s if s.is_server_error() || s == StatusCode::TOO_MANY_REQUESTS => {
+                return Err(RegistryError::Registry(format!(
+                    "fetching blob {}: {}",
+                    digest, s
+                )));
+            }
+            _ => return Err(RegistryError::BlobNotFound(digest.to_string())),
+        };
+
+        while let Some(mut chunk) = response.chunk().await? {
+            if skip > 0 {
+                let n = skip.min(chunk.len() as u64);
+                skip -= n;
+                chunk = chunk.slice(n as usize..);
+            }
+            if chunk.is_empty() {
+                continue;
+            }
+            sink.write_all(&chunk).await?;
+            *written += chunk.len() as u64;
+            on_progress(*written);
+        }
+
+        if skip > 0 {
+            return Err(RegistryError::Registry(format!(
+                "blob {} ended before byte {}",
+                digest, written
+            )));
+        }
+        Ok(())
+    }
+
     pub async fn get_blob_bytes(
         &self,
         reference: &ImageReference,
@@ -267,13 +801,224 @@ impl Default for RegistryClient {
     }
 }
 
-fn extract_auth_param(header: &str, param: &str) -> Option<String> {
-    let search = format!("{}=\"", param);
-    if let Some(start) = header.find(&search) {
-        let start = start + search.len();
-        if let Some(end) = header[start..].find('"') {
-            return Some(header[start..start + end].to_string());
+/// Whether a failed blob transfer is worth retrying: network failures and
+/// server-side errors, but not missing blobs or local write errors.
+fn is_transient(error: &RegistryError) -> bool {
+    matches!(error, RegistryError::Http(_) | RegistryError::Registry(_))
+}
+
+/// First byte offset of a `Content-Range: bytes START-END/TOTAL` header.
+fn content_range_start(header: &str) -> Option<u64> {
+    let range = header.trim().strip_prefix("bytes ")?;
+    let (start, _) = range.split_once('-')?;
+    start.trim().parse().ok()
+}
+
+/// A parsed `Www-Authenticate` challenge, e.g.
+/// `Bearer realm="https://auth.docker.io/token",service="registry.docker.io"`.
+struct Challenge {
+    /// Lowercased auth scheme.
+    scheme: String,
+    params: HashMap<String, String>,
+}
+
+impl Challenge {
+    fn parse(header: &str) -> Option<Self> {
+        let header = header.trim();
+        let (scheme, rest) = header.split_once(' ').unwrap_or((header, ""));
+        if scheme.is_empty() {
+            return None;
+        }
+
+        let mut params = HashMap::new();
+        let mut rest = rest.trim_start();
+        while !rest.is_empty() {
+            let (key, after) = rest.split_once('=')?;
+            let key = key.trim().to_ascii_lowercase();
+
+            let (value, after) = if let Some(quoted) = after.strip_prefix('"') {
+                let end = quoted.find('"')?;
+                (&quoted[..end], &quoted[end + 1..])
+            } else {
+                after.split_once(',').map_or((after, ""), |(v, a)| (v, a))
+            };
+            params.insert(key, value.trim().to_string());
+
+            rest = after.trim_start().trim_start_matches(',').trim_start();
         }
+
+        Some(Self {
+            scheme: scheme.to_ascii_lowercase(),
+            params,
+        })
+    }
+
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(|v| v.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bearer_challenge() {
+        let challenge = Challenge::parse(
+            r#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:library/alpine:pull,push""#,
+        )
+        .unwrap();
+        assert_eq!(challenge.scheme, "bearer");
+        assert_eq!(
+            challenge.param("realm"),
+            Some("https://auth.docker.io/token")
+        );
+        assert_eq!(challenge.param("service"), Some("registry.docker.io"));
+        assert_eq!(
+            challenge.param("scope"),
+            Some("repository:library/alpine:pull,push")
+        );
+    }
+
+    #[test]
+    fn test_parse_basic_challenge() {
+        let challenge = Challenge::parse(r#"Basic realm="Registry Realm""#).unwrap();
+        assert_eq!(challenge.scheme, "basic");
+        assert_eq!(challenge.param("realm"), Some("Registry Realm"));
+
+        let challenge = Challenge::parse("Basic").unwrap();
+        assert_eq!(challenge.scheme, "basic");
+        assert!(challenge.params.is_empty());
+
+        assert!(Challenge::parse("").is_none());
+    }
+
+    #[test]
+    fn test_content_range_start() {
+        assert_eq!(content_range_start("bytes 1024-2047/4096"), Some(1024));
+        assert_eq!(content_range_start("bytes 0-0/*"), Some(0));
+        assert_eq!(content_range_start("items 1-2/3"), None);
+        assert_eq!(content_range_start("bytes */4096"), None);
+    }
+
+    #[test]
+    fn test_parse_insecure_registry() {
+        assert_eq!(
+            InsecureMode::parse("http://Registry.local:5000/").unwrap(),
+            ("registry.local:5000".to_string(), InsecureMode::Http)
+        );
+        assert_eq!(
+            InsecureMode::parse("registry.local").unwrap(),
+            ("registry.local".to_string(), InsecureMode::SkipVerify)
+        );
+        assert_eq!(
+            InsecureMode::parse("registry.local:443").unwrap(),
+            ("registry.local".to_string(), InsecureMode::SkipVerify)
+        );
+        assert_eq!(
+            InsecureMode::parse("http://registry.local:80").unwrap(),
+            ("registry.local".to_string(), InsecureMode::Http)
+        );
+        assert_eq!(
+            InsecureMode::parse("http://registry.local:443").unwrap(),
+            ("registry.local:443".to_string(), InsecureMode::Http)
+        );
+        assert!(InsecureMode::parse("*.local").is_err());
+        assert!(InsecureMode::parse("registry.local/library").is_err());
+        assert!(InsecureMode::parse("http://").is_err());
+    }
+
+    #[test]
+    fn test_parse_mirror() {
+        assert_eq!(
+            parse_mirror("https://Mirror.local:5000/").unwrap(),
+            (
+                "registry-1.docker.io".to_string(),
+                "mirror.local:5000".to_string()
+            )
+        );
+        assert_eq!(
+            parse_mirror("ghcr.io=mirror.local").unwrap(),
+            ("ghcr.io".to_string(), "mirror.local".to_string())
+        );
+        assert!(parse_mirror("").is_err());
+        assert!(parse_mirror("docker.io=").is_err());
+        assert!(parse_mirror("mirror.local/dockerhub").is_err());
+    }
+
+    #[test]
+    fn test_mirrors_keep_repository_and_credentials() {
+        let client = RegistryClient::with_credentials("user", "secret")
+            .unwrap()
+            .with_mirrors(HashMap::from([(
+                "docker.io".to_string(),
+                vec!["a.local".to_string(), "b.local".to_string()],
+            )]));
+
+        let reference = ImageReference::parse("nginx:alpine").unwrap();
+        let mirrors = client.mirrors_of(&reference);
+        let registries: Vec<&str> = mirrors.iter().map(|m| m.registry.as_str()).collect();
+        assert_eq!(registries, ["a.local", "b.local"]);
+        assert!(mirrors.iter().all(|m| m.repository == "library/nginx"));
+        assert!(client.credentials_for(&mirrors[0]).is_none());
+        assert!(client.credentials_for(&reference).is_some());
+
+        let reference = ImageReference::parse("ghcr.io/owner/app").unwrap();
+        assert!(client.mirrors_of(&reference).is_empty());
+    }
+
+    #[test]
+    fn test_insecure_registries_are_per_host() {
+        let client = RegistryClient::new()
+            .unwrap()
+            .with_insecure_registries(HashMap::from([
+                ("plain.local:5000".to_string(), InsecureMode::Http),
+                ("selfsigned.local".to_string(), InsecureMode::SkipVerify),
+            ]))
+            .unwrap();
+
+        assert_eq!(
+            client.registry_url("plain.local:5000"),
+            "http://plain.local:5000"
+        );
+        assert_eq!(client.registry_url("plain.local"), "https://plain.local");
+
+        let default_ports = RegistryClient::new()
+            .unwrap()
+            .with_insecure_registries(HashMap::from([
+                ("plain.local:80".to_string(), InsecureMode::Http),
+                ("selfsigned.local:443".to_string(), InsecureMode::SkipVerify),
+            ]))
+            .unwrap();
+        assert_eq!(
+            default_ports.registry_url("plain.local:80"),
+            "http://plain.local:80"
+        );
+        assert_eq!(
+            default_ports.registry_url("plain.local"),
+            "http://plain.local"
+        );
+        assert!(std::ptr::eq(
+            default_ports.client_for("https://selfsigned.local:443/v2/"),
+            default_ports.insecure_client.as_ref().unwrap()
+        ));
+        assert_eq!(
+            client.registry_url("registry-1.docker.io"),
+            "https://registry-1.docker.io"
+        );
+
+        let insecure = client.insecure_client.as_ref().unwrap();
+        assert!(std::ptr::eq(
+            client.client_for("https://selfsigned.local/v2/"),
+            insecure
+        ));
+        assert!(std::ptr::eq(
+            client.client_for("https://selfsigned.local.evil.com/v2/"),
+            &client.client
+        ));
+        assert!(std::ptr::eq(
+            client.client_for("https://registry-1.docker.io/v2/"),
+            &client.client
+        ));
     }
-    None
 }
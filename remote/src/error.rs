@@ -5,6 +5,9 @@ pub enum RegistryError {
     #[error("invalid image reference: {0}")]
     InvalidReference(String),
 
+    #[error("invalid platform: {0}")]
+    InvalidPlatform(String),
+
     #[error("authentication required")]
     AuthRequired,
 
@@ -3,7 +3,7 @@ mod error;
 mod reference;
 mod types;
 
-pub use client::RegistryClient;
+pub use client::{InsecureMode, RegistryClient, parse_mirror};
 pub use error::RegistryError;
-pub use reference::ImageReference;
+pub use reference::{ImageReference, ReferenceParts, split_reference};
 pub use types::*;
@@ -1,5 +1,12 @@
 use crate::error::RegistryError;
 
+/// Registry host that Docker Hub images are fetched from.
+const DOCKER_HUB_REGISTRY: &str = "registry-1.docker.io";
+
+/// Longest repository name, registry included, that registries accept.
+const MAX_NAME_LEN: usize = 255;
+const MAX_TAG_LEN: usize = 128;
+
 #[derive(Debug, Clone)]
 pub struct ImageReference {
     pub registry: String,
@@ -8,60 +15,114 @@ pub struct ImageReference {
     pub digest: Option<String>,
 }
 
-impl ImageReference {
-    pub fn parse(reference: &str) -> Result<Self, RegistryError> {
-        let reference = reference.trim();
+/// A reference split as written, `NAME[:TAG][@DIGEST]`, each part validated
+/// but nothing defaulted or canonicalized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReferenceParts<'a> {
+    /// Repository name, with the registry when one is given.
+    pub name: &'a str,
+    pub tag: Option<&'a str>,
+    pub digest: Option<&'a str>,
+}
 
-        let (reference, digest) = if let Some(idx) = reference.rfind('@') {
-            let digest = reference[idx + 1..].to_string();
-            let reference = &reference[..idx];
-            (reference, Some(digest))
-        } else {
-            (reference, None)
-        };
+/// Splits `reference` into its name, tag and digest.
+///
+/// A `:` only starts a tag after the last `/`, so `localhost:5000/app` has a
+/// registry port and no tag. A tag may accompany a digest
+/// (`name:tag@sha256:...`); the digest wins when fetching but the tag is kept
+/// for naming.
+pub fn split_reference(reference: &str) -> Result<ReferenceParts<'_>, RegistryError> {
+    let invalid = RegistryError::InvalidReference;
 
-        let (reference, tag) = if digest.is_none() {
-            if let Some(idx) = reference.rfind(':') {
-                let potential_tag = &reference[idx + 1..];
-                if !potential_tag.contains('/') {
-                    (&reference[..idx], Some(potential_tag.to_string()))
-                } else {
-                    (reference, None)
-                }
-            } else {
-                (reference, None)
-            }
-        } else {
-            (reference, None)
-        };
+    let (rest, digest) = match reference.split_once('@') {
+        Some((rest, digest)) if is_valid_digest(digest) => (rest, Some(digest)),
+        Some((_, digest)) => return Err(invalid(format!("invalid digest: {}", digest))),
+        None => (reference, None),
+    };
 
-        let (registry, repository) = if reference.contains('/') {
-            let first_slash = reference.find('/').unwrap();
-            let first_part = &reference[..first_slash];
-
-            if first_part.contains('.') || first_part.contains(':') || first_part == "localhost" {
-                (
-                    first_part.to_string(),
-                    reference[first_slash + 1..].to_string(),
-                )
-            } else {
-                ("registry-1.docker.io".to_string(), reference.to_string())
-            }
+    let last_component = rest.rfind('/').map_or(0, |i| i + 1);
+    let (name, tag) = match rest[last_component..].rfind(':') {
+        Some(i) => {
+            let i = last_component + i;
+            (&rest[..i], Some(&rest[i + 1..]))
+        }
+        None => (rest, None),
+    };
+    if let Some(tag) = tag
+        && !is_valid_tag(tag)
+    {
+        return Err(invalid(format!("invalid tag: '{}'", tag)));
+    }
+
+    if name.is_empty() {
+        return Err(invalid("missing repository name".to_string()));
+    }
+    if name.len() > MAX_NAME_LEN {
+        return Err(invalid(format!(
+            "repository name longer than {} characters",
+            MAX_NAME_LEN
+        )));
+    }
+    let (domain, path) = split_domain(name);
+    if let Some(domain) = domain
+        && !is_valid_domain(domain)
+    {
+        return Err(invalid(format!("invalid registry: '{}'", domain)));
+    }
+    if let Some(component) = path.split('/').find(|c| !is_valid_path_component(c)) {
+        return Err(invalid(format!(
+            "invalid repository name component '{}' in {} (must be lowercase)",
+            component, name
+        )));
+    }
+
+    Ok(ReferenceParts { name, tag, digest })
+}
+
+/// Splits the registry off a name. The first component is a registry when it
+/// looks like a host (`.`, `:` or `localhost`) and more components follow.
+fn split_domain(name: &str) -> (Option<&str>, &str) {
+    match name.split_once('/') {
+        Some((first, path))
+            if first.contains('.') || first.contains(':') || first == "localhost" =>
+        {
+            (Some(first), path)
+        }
+        _ => (None, name),
+    }
+}
+
+impl ImageReference {
+    pub fn parse(reference: &str) -> Result<Self, RegistryError> {
+        let parts = split_reference(reference.trim())?;
+        let (domain, path) = split_domain(parts.name);
+
+        let registry = domain.map_or_else(|| DOCKER_HUB_REGISTRY.to_string(), canonical_registry);
+        // Official Docker Hub images live in `library/`.
+        let repository = if registry == DOCKER_HUB_REGISTRY && !path.contains('/') {
+            format!("library/{}", path)
         } else {
-            (
-                "registry-1.docker.io".to_string(),
-                format!("library/{}", reference),
-            )
+            path.to_string()
         };
 
         Ok(Self {
             registry,
             repository,
-            tag,
-            digest,
+            tag: parts.tag.map(str::to_string),
+            digest: parts.digest.map(str::to_string),
         })
     }
 
+    /// The same image on `mirror` (`host[:port]`) instead of its registry.
+    /// Mirrors serve a registry's repositories under the same names, so the
+    /// repository, and with it the auth scope, stays the original one.
+    pub fn mirrored(&self, mirror: &str) -> Self {
+        Self {
+            registry: mirror.to_string(),
+            ..self.clone()
+        }
+    }
+
     pub fn tag_or_default(&self) -> &str {
         self.tag.as_deref().unwrap_or("latest")
     }
@@ -74,20 +135,93 @@ impl ImageReference {
         }
     }
 
+    /// Short, re-parseable name: `name:tag`, or `name@digest` for digest
+    /// references (`name:tag@digest` when both are given).
     pub fn full_name(&self) -> String {
-        let tag = self.tag_or_default();
-        if self.registry == "registry-1.docker.io" {
-            if self.repository.starts_with("library/") {
-                format!("{}:{}", &self.repository[8..], tag)
-            } else {
-                format!("{}:{}", self.repository, tag)
-            }
+        let name = if self.registry == DOCKER_HUB_REGISTRY {
+            self.repository
+                .strip_prefix("library/")
+                .unwrap_or(&self.repository)
+                .to_string()
         } else {
-            format!("{}/{}:{}", self.registry, self.repository, tag)
+            format!("{}/{}", self.registry, self.repository)
+        };
+
+        match (&self.tag, &self.digest) {
+            (Some(tag), Some(digest)) => format!("{}:{}@{}", name, tag, digest),
+            (None, Some(digest)) => format!("{}@{}", name, digest),
+            _ => format!("{}:{}", name, self.tag_or_default()),
         }
     }
 }
 
+/// The host references to `registry` resolve to, which for Docker Hub is not
+/// the one it is known by.
+pub(crate) fn canonical_registry(registry: &str) -> String {
+    match registry.to_ascii_lowercase().as_str() {
+        "docker.io" | "index.docker.io" => DOCKER_HUB_REGISTRY.to_string(),
+        registry => registry.to_string(),
+    }
+}
+
+/// Tags are up to 128 word characters, dots and dashes, not starting with a
+/// dot or dash.
+fn is_valid_tag(tag: &str) -> bool {
+    tag.len() <= MAX_TAG_LEN
+        && tag.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_')
+        && tag
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+}
+
+/// A registry host, optionally with a numeric port.
+fn is_valid_domain(domain: &str) -> bool {
+    let (host, port) = match domain.rfind(':') {
+        // The colons of an IPv6 address do not start a port.
+        Some(i) if !domain[i..].contains(']') => (&domain[..i], Some(&domain[i + 1..])),
+        _ => (domain, None),
+    };
+    let valid_host = match host.strip_prefix('[') {
+        Some(ip) => ip.strip_suffix(']').is_some_and(|ip| {
+            !ip.is_empty() && ip.chars().all(|c| c.is_ascii_hexdigit() || c == ':')
+        }),
+        None => host.split('.').all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        }),
+    };
+    let valid_port =
+        port.is_none_or(|port| !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()));
+    valid_host && valid_port
+}
+
+/// Repository path components are lowercase alphanumerics, separated by a
+/// single `.` or `_`, a `__`, or a run of `-`.
+fn is_valid_path_component(component: &str) -> bool {
+    let is_alnum = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit();
+    if !component.starts_with(is_alnum) || !component.ends_with(is_alnum) {
+        return false;
+    }
+    component
+        .split(is_alnum)
+        .filter(|separator| !separator.is_empty())
+        .all(|separator| {
+            matches!(separator, "." | "_" | "__") || separator.chars().all(|c| c == '-')
+        })
+}
+
+/// Checks that `digest` is `sha256:` followed by 64 lowercase hex characters.
+fn is_valid_digest(digest: &str) -> bool {
+    digest.strip_prefix("sha256:").is_some_and(|hex| {
+        hex.len() == 64
+            && hex
+                .bytes()
+                .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -117,6 +251,163 @@ mod tests {
         assert_eq!(r.tag, Some("v1".to_string()));
     }
 
+    #[test]
+    fn test_parse_with_digest() {
+        let digest = format!("sha256:{}", "a".repeat(64));
+
+        let r = ImageReference::parse(&format!("nginx@{}", digest)).unwrap();
+        assert_eq!(r.repository, "library/nginx");
+        assert_eq!(r.tag, None);
+        assert_eq!(r.digest.as_deref(), Some(digest.as_str()));
+        assert_eq!(r.reference(), digest);
+        assert_eq!(r.full_name(), format!("nginx@{}", digest));
+
+        let r = ImageReference::parse(&format!("localhost:5000/app:v1@{}", digest)).unwrap();
+        assert_eq!(r.registry, "localhost:5000");
+        assert_eq!(r.repository, "app");
+        assert_eq!(r.tag, Some("v1".to_string()));
+        assert_eq!(r.reference(), digest);
+
+        assert!(ImageReference::parse("nginx@sha256:abc").is_err());
+        assert!(ImageReference::parse("nginx@md5:abc").is_err());
+        assert!(ImageReference::parse(&format!("@{}", digest)).is_err());
+    }
+
+    /// Reference, registry, repository, tag and digest.
+    type Case<'a> = (&'a str, &'a str, &'a str, Option<&'a str>, Option<&'a str>);
+
+    #[test]
+    fn test_parse_table() {
+        let digest = format!("sha256:{}", "c".repeat(64));
+        let cases: &[Case] = &[
+            (
+                "localhost:5000/foo:bar",
+                "localhost:5000",
+                "foo",
+                Some("bar"),
+                None,
+            ),
+            ("localhost:5000/foo", "localhost:5000", "foo", None, None),
+            ("localhost/foo", "localhost", "foo", None, None),
+            ("[::1]:5000/foo:v1", "[::1]:5000", "foo", Some("v1"), None),
+            (
+                "registry.example.com/a/b/c:tag",
+                "registry.example.com",
+                "a/b/c",
+                Some("tag"),
+                None,
+            ),
+            (
+                "registry.example.com:443/a/b/c",
+                "registry.example.com:443",
+                "a/b/c",
+                None,
+                None,
+            ),
+            (
+                "nginx:1.25",
+                "registry-1.docker.io",
+                "library/nginx",
+                Some("1.25"),
+                None,
+            ),
+            (
+                "docker.io/nginx",
+                "registry-1.docker.io",
+                "library/nginx",
+                None,
+                None,
+            ),
+            (
+                "docker.io/library/nginx:latest",
+                "registry-1.docker.io",
+                "library/nginx",
+                Some("latest"),
+                None,
+            ),
+            (
+                "index.docker.io/user/app",
+                "registry-1.docker.io",
+                "user/app",
+                None,
+                None,
+            ),
+            (
+                "my-org/my_app.v2",
+                "registry-1.docker.io",
+                "my-org/my_app.v2",
+                None,
+                None,
+            ),
+            (
+                "a__b/c---d",
+                "registry-1.docker.io",
+                "a__b/c---d",
+                None,
+                None,
+            ),
+        ];
+        for &(input, registry, repository, tag, expected_digest) in cases {
+            let r = ImageReference::parse(input).unwrap();
+            assert_eq!(r.registry, registry, "{}", input);
+            assert_eq!(r.repository, repository, "{}", input);
+            assert_eq!(r.tag.as_deref(), tag, "{}", input);
+            assert_eq!(r.digest.as_deref(), expected_digest, "{}", input);
+        }
+
+        let r = ImageReference::parse(&format!("nginx:1.25@{}", digest)).unwrap();
+        assert_eq!(r.repository, "library/nginx");
+        assert_eq!(r.tag.as_deref(), Some("1.25"));
+        assert_eq!(r.digest.as_deref(), Some(digest.as_str()));
+        assert_eq!(r.full_name(), format!("nginx:1.25@{}", digest));
+
+        for invalid in [
+            "",
+            ":tag",
+            "Nginx",
+            "nginx:",
+            "nginx:-tag",
+            "nginx:a+b",
+            "a//b",
+            "-foo",
+            "foo-",
+            "foo..bar",
+            "foo___bar",
+            "localhost:5000/",
+            "localhost:port/foo",
+            "bad_host.com/foo",
+            "nginx:bad/tag",
+        ] {
+            assert!(
+                ImageReference::parse(invalid).is_err(),
+                "{} should be rejected",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_split_reference() {
+        let digest = format!("sha256:{}", "d".repeat(64));
+        let reference = format!("localhost:5000/foo:bar@{}", digest);
+        assert_eq!(
+            split_reference(&reference).unwrap(),
+            ReferenceParts {
+                name: "localhost:5000/foo",
+                tag: Some("bar"),
+                digest: Some(&digest),
+            }
+        );
+        assert_eq!(
+            split_reference("localhost:5000/foo").unwrap(),
+            ReferenceParts {
+                name: "localhost:5000/foo",
+                tag: None,
+                digest: None,
+            }
+        );
+    }
+
     #[test]
     fn test_parse_custom_registry() {
         let r = ImageReference::parse("ghcr.io/owner/repo:latest").unwrap();
@@ -1,3 +1,4 @@
+use crate::error::RegistryError;
 use serde::{Deserialize, Serialize};
 
 pub const MEDIA_TYPE_MANIFEST_V2: &str = "application/vnd.docker.distribution.manifest.v2+json";
@@ -6,9 +7,88 @@ pub const MEDIA_TYPE_MANIFEST_LIST: &str =
 pub const MEDIA_TYPE_OCI_MANIFEST: &str = "application/vnd.oci.image.manifest.v1+json";
 pub const MEDIA_TYPE_OCI_INDEX: &str = "application/vnd.oci.image.index.v1+json";
 pub const MEDIA_TYPE_LAYER_GZIP: &str = "application/vnd.docker.image.rootfs.diff.tar.gzip";
+pub const MEDIA_TYPE_OCI_LAYER: &str = "application/vnd.oci.image.layer.v1.tar";
 pub const MEDIA_TYPE_OCI_LAYER_GZIP: &str = "application/vnd.oci.image.layer.v1.tar+gzip";
 pub const MEDIA_TYPE_CONFIG: &str = "application/vnd.docker.container.image.v1+json";
 pub const MEDIA_TYPE_OCI_CONFIG: &str = "application/vnd.oci.image.config.v1+json";
+pub const MEDIA_TYPE_FOREIGN_LAYER: &str =
+    "application/vnd.docker.image.rootfs.foreign.diff.tar.gzip";
+pub const MEDIA_TYPE_OCI_NONDISTRIBUTABLE_LAYER: &str =
+    "application/vnd.oci.image.layer.nondistributable.v1.tar+gzip";
+
+/// The manifest and index media types a registry may answer with, in order
+/// of preference.
+pub const MANIFEST_MEDIA_TYPES: &[&str] = &[
+    MEDIA_TYPE_OCI_INDEX,
+    MEDIA_TYPE_MANIFEST_LIST,
+    MEDIA_TYPE_OCI_MANIFEST,
+    MEDIA_TYPE_MANIFEST_V2,
+];
+
+/// Whether `media_type` is a Docker manifest list or an OCI image index.
+pub fn is_index_media_type(media_type: &str) -> bool {
+    media_type.contains("manifest.list") || media_type.contains("image.index")
+}
+
+/// The OCI equivalent of a Docker schema2 media type; other media types are
+/// returned as they are.
+pub fn oci_media_type(media_type: &str) -> &str {
+    match media_type {
+        MEDIA_TYPE_MANIFEST_V2 => MEDIA_TYPE_OCI_MANIFEST,
+        MEDIA_TYPE_MANIFEST_LIST => MEDIA_TYPE_OCI_INDEX,
+        MEDIA_TYPE_CONFIG => MEDIA_TYPE_OCI_CONFIG,
+        MEDIA_TYPE_LAYER_GZIP => MEDIA_TYPE_OCI_LAYER_GZIP,
+        MEDIA_TYPE_FOREIGN_LAYER => MEDIA_TYPE_OCI_NONDISTRIBUTABLE_LAYER,
+        other => other,
+    }
+}
+
+/// Works out the media type of a manifest served with `content_type`.
+///
+/// Registries don't always send a usable `Content-Type`, so when it is
+/// missing or generic the manifest's own `mediaType` field decides, and
+/// failing that its shape. Schema1 manifests are rejected.
+pub fn manifest_media_type(
+    content_type: Option<&str>,
+    body: &[u8],
+) -> Result<String, RegistryError> {
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Probe {
+        #[serde(default)]
+        schema_version: Option<i32>,
+        #[serde(default)]
+        media_type: Option<String>,
+        #[serde(default)]
+        manifests: Option<serde_json::Value>,
+    }
+
+    let content_type = content_type
+        .and_then(|c| c.split(';').next())
+        .map(str::trim)
+        .unwrap_or_default();
+    if content_type.contains("distribution.manifest.v1") {
+        return Err(RegistryError::UnsupportedMediaType(
+            content_type.to_string(),
+        ));
+    }
+    if MANIFEST_MEDIA_TYPES.contains(&content_type) {
+        return Ok(content_type.to_string());
+    }
+
+    let probe: Probe = serde_json::from_slice(body)?;
+    if probe.schema_version == Some(1) {
+        return Err(RegistryError::UnsupportedMediaType(
+            "schema1 manifests are not supported".to_string(),
+        ));
+    }
+    match probe.media_type {
+        Some(media_type) if MANIFEST_MEDIA_TYPES.contains(&media_type.as_str()) => Ok(media_type),
+        Some(media_type) => Err(RegistryError::UnsupportedMediaType(media_type)),
+        None if probe.manifests.is_some() => Ok(MEDIA_TYPE_OCI_INDEX.to_string()),
+        None => Ok(MEDIA_TYPE_OCI_MANIFEST.to_string()),
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -19,6 +99,33 @@ pub struct ManifestV2 {
     pub layers: Vec<Descriptor>,
 }
 
+impl ManifestV2 {
+    /// Parses a Docker schema2 or OCI image manifest served as `media_type`,
+    /// converting the media types it names to their OCI equivalents so both
+    /// formats read the same afterwards. The bytes themselves, which the
+    /// manifest digest covers, are left alone.
+    pub fn parse(bytes: &[u8], media_type: &str) -> Result<Self, RegistryError> {
+        if is_index_media_type(media_type) {
+            return Err(RegistryError::UnsupportedMediaType(format!(
+                "expected an image manifest, got {}",
+                media_type
+            )));
+        }
+        let mut manifest: ManifestV2 = serde_json::from_slice(bytes)?;
+        if manifest.schema_version != 2 {
+            return Err(RegistryError::UnsupportedMediaType(format!(
+                "manifest schema version {}",
+                manifest.schema_version
+            )));
+        }
+        manifest.media_type = Some(oci_media_type(media_type).to_string());
+        for descriptor in std::iter::once(&mut manifest.config).chain(&mut manifest.layers) {
+            descriptor.media_type = oci_media_type(&descriptor.media_type).to_string();
+        }
+        Ok(manifest)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ManifestList {
@@ -47,6 +154,69 @@ pub struct Platform {
     pub os_version: Option<String>,
 }
 
+impl ManifestList {
+    /// Returns the manifest built for `platform`, if the list has one.
+    pub fn select(&self, platform: &Platform) -> Option<&ManifestDescriptor> {
+        self.manifests
+            .iter()
+            .find(|m| m.platform.as_ref().is_some_and(|p| platform.matches(p)))
+    }
+}
+
+impl Platform {
+    /// Linux on the architecture the daemon runs on.
+    pub fn host() -> Self {
+        let architecture = match std::env::consts::ARCH {
+            "x86_64" => "amd64",
+            "aarch64" => "arm64",
+            a => a,
+        };
+        Self {
+            architecture: architecture.to_string(),
+            os: "linux".to_string(),
+            variant: None,
+            os_version: None,
+        }
+    }
+
+    /// Parses an `os/arch[/variant]` platform such as `linux/arm64/v8`.
+    pub fn parse(s: &str) -> Result<Self, RegistryError> {
+        let mut parts = s.split('/');
+        let (Some(os), Some(architecture)) = (parts.next(), parts.next()) else {
+            return Err(RegistryError::InvalidPlatform(s.to_string()));
+        };
+        let variant = parts.next();
+        if os.is_empty() || architecture.is_empty() || parts.next().is_some() {
+            return Err(RegistryError::InvalidPlatform(s.to_string()));
+        }
+
+        Ok(Self {
+            architecture: architecture.to_string(),
+            os: os.to_string(),
+            variant: variant.filter(|v| !v.is_empty()).map(String::from),
+            os_version: None,
+        })
+    }
+
+    /// Returns true if an image built for `other` runs on this platform. The
+    /// variant is only compared when this platform names one.
+    pub fn matches(&self, other: &Platform) -> bool {
+        self.os == other.os
+            && self.architecture == other.architecture
+            && (self.variant.is_none() || self.variant == other.variant)
+    }
+}
+
+impl std::fmt::Display for Platform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.os, self.architecture)?;
+        if let Some(variant) = &self.variant {
+            write!(f, "/{}", variant)?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Descriptor {
@@ -151,6 +321,19 @@ impl Manifest {
     }
 }
 
+/// A platform-specific manifest as served by the registry.
+#[derive(Debug, Clone)]
+pub struct ResolvedManifest {
+    pub manifest: ManifestV2,
+    /// The exact bytes served, which `digest` covers.
+    pub bytes: Vec<u8>,
+    pub media_type: String,
+    pub digest: String,
+    /// The raw manifest list the manifest was selected from, if the
+    /// reference pointed at one.
+    pub index: Option<Vec<u8>>,
+}
+
 #[derive(Debug, Clone)]
 pub struct PullProgress {
     pub id: String,
@@ -187,3 +370,141 @@ impl std::fmt::Display for PullStatus {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_platform_parse() {
+        let platform = Platform::parse("linux/arm64/v8").unwrap();
+        assert_eq!(platform.os, "linux");
+        assert_eq!(platform.architecture, "arm64");
+        assert_eq!(platform.variant.as_deref(), Some("v8"));
+        assert_eq!(platform.to_string(), "linux/arm64/v8");
+
+        assert!(Platform::parse("linux").is_err());
+        assert!(Platform::parse("linux/").is_err());
+        assert!(Platform::parse("linux/arm/v7/extra").is_err());
+    }
+
+    #[test]
+    fn test_manifest_list_select() {
+        let list: ManifestList = serde_json::from_str(
+            r#"{
+                "schemaVersion": 2,
+                "manifests": [
+                    {"mediaType": "m", "digest": "sha256:amd", "size": 1,
+                     "platform": {"architecture": "amd64", "os": "linux"}},
+                    {"mediaType": "m", "digest": "sha256:armv7", "size": 1,
+                     "platform": {"architecture": "arm", "os": "linux", "variant": "v7"}},
+                    {"mediaType": "m", "digest": "sha256:arm64", "size": 1,
+                     "platform": {"architecture": "arm64", "os": "linux", "variant": "v8"}}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let select = |p: &str| {
+            list.select(&Platform::parse(p).unwrap())
+                .map(|m| m.digest.as_str())
+        };
+        assert_eq!(select("linux/amd64"), Some("sha256:amd"));
+        assert_eq!(select("linux/arm64"), Some("sha256:arm64"));
+        assert_eq!(select("linux/arm/v6"), None);
+        assert_eq!(select("windows/amd64"), None);
+    }
+
+    #[test]
+    fn test_manifest_media_type() {
+        let manifest = br#"{"schemaVersion": 2, "config": {}, "layers": []}"#;
+        let index = br#"{"schemaVersion": 2, "manifests": []}"#;
+
+        let negotiate = |content_type, body: &[u8]| manifest_media_type(content_type, body);
+        assert_eq!(
+            negotiate(Some(MEDIA_TYPE_MANIFEST_V2), manifest).unwrap(),
+            MEDIA_TYPE_MANIFEST_V2
+        );
+        assert_eq!(
+            negotiate(
+                Some("application/vnd.oci.image.index.v1+json; charset=utf-8"),
+                index
+            )
+            .unwrap(),
+            MEDIA_TYPE_OCI_INDEX
+        );
+
+        // Missing or generic content types fall back to the body.
+        assert_eq!(
+            negotiate(Some("application/json"), manifest).unwrap(),
+            MEDIA_TYPE_OCI_MANIFEST
+        );
+        assert_eq!(negotiate(None, index).unwrap(), MEDIA_TYPE_OCI_INDEX);
+        let listed = format!(
+            r#"{{"schemaVersion": 2, "mediaType": "{}", "manifests": []}}"#,
+            MEDIA_TYPE_MANIFEST_LIST
+        );
+        assert_eq!(
+            negotiate(Some("text/plain"), listed.as_bytes()).unwrap(),
+            MEDIA_TYPE_MANIFEST_LIST
+        );
+
+        assert!(
+            negotiate(
+                Some("application/vnd.docker.distribution.manifest.v1+prettyjws"),
+                manifest
+            )
+            .is_err()
+        );
+        assert!(negotiate(None, br#"{"schemaVersion": 1, "fsLayers": []}"#).is_err());
+        assert!(negotiate(None, br#"{"schemaVersion": 2, "mediaType": "x/y"}"#).is_err());
+    }
+
+    #[test]
+    fn test_manifest_parse_converts_schema2() {
+        let bytes = format!(
+            r#"{{
+                "schemaVersion": 2,
+                "mediaType": "{}",
+                "config": {{"mediaType": "{}", "digest": "sha256:c", "size": 1}},
+                "layers": [
+                    {{"mediaType": "{}", "digest": "sha256:l", "size": 2}},
+                    {{"mediaType": "{}", "digest": "sha256:f", "size": 3}}
+                ]
+            }}"#,
+            MEDIA_TYPE_MANIFEST_V2,
+            MEDIA_TYPE_CONFIG,
+            MEDIA_TYPE_LAYER_GZIP,
+            MEDIA_TYPE_FOREIGN_LAYER
+        );
+        let manifest = ManifestV2::parse(bytes.as_bytes(), MEDIA_TYPE_MANIFEST_V2).unwrap();
+        assert_eq!(
+            manifest.media_type.as_deref(),
+            Some(MEDIA_TYPE_OCI_MANIFEST)
+        );
+        assert_eq!(manifest.config.media_type, MEDIA_TYPE_OCI_CONFIG);
+        assert_eq!(manifest.layers[0].media_type, MEDIA_TYPE_OCI_LAYER_GZIP);
+        assert_eq!(
+            manifest.layers[1].media_type,
+            MEDIA_TYPE_OCI_NONDISTRIBUTABLE_LAYER
+        );
+
+        // OCI manifests, including the ones that omit mediaType, read the same.
+        let oci = format!(
+            r#"{{
+                "schemaVersion": 2,
+                "config": {{"mediaType": "{}", "digest": "sha256:c", "size": 1}},
+                "layers": [{{"mediaType": "{}", "digest": "sha256:l", "size": 2}}]
+            }}"#,
+            MEDIA_TYPE_OCI_CONFIG, MEDIA_TYPE_OCI_LAYER_GZIP
+        );
+        let manifest = ManifestV2::parse(oci.as_bytes(), MEDIA_TYPE_OCI_MANIFEST).unwrap();
+        assert_eq!(
+            manifest.media_type.as_deref(),
+            Some(MEDIA_TYPE_OCI_MANIFEST)
+        );
+        assert_eq!(manifest.layers[0].media_type, MEDIA_TYPE_OCI_LAYER_GZIP);
+
+        assert!(ManifestV2::parse(oci.as_bytes(), MEDIA_TYPE_OCI_INDEX).is_err());
+    }
+}
@@ -0,0 +1,227 @@
+//! Bind mounts given as `HOST_PATH:CONTAINER_PATH[:OPTIONS]` volume specs.
+
+use crate::error::ShimError;
+use std::path::{Component, Path, PathBuf};
+
+/// Mount propagation modes accepted in a bind spec.
+const PROPAGATION_MODES: &[&str] = &[
+    "private", "rprivate", "shared", "rshared", "slave", "rslave",
+];
+
+/// Options Docker accepts that have no effect here: SELinux relabeling and
+/// the macOS consistency hints.
+const IGNORED_OPTIONS: &[&str] = &["z", "Z", "consistent", "cached", "delegated"];
+
+/// A validated bind mount of a host path into a container.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BindMount {
+    /// Absolute path on the host. Canonical once the mount is resolved.
+    pub source: PathBuf,
+    /// Absolute, normalized path in the container.
+    pub destination: String,
+    pub read_only: bool,
+    /// Whether mounts below the source are bound too (`rbind`, the default).
+    pub recursive: bool,
+    /// Propagation mode, `rprivate` unless the spec names one.
+    pub propagation: String,
+    /// Whether a missing source is created rather than rejected.
+    pub create: bool,
+}
+
+impl BindMount {
+    /// Parses and validates `spec` without looking at the host filesystem.
+    pub fn parse(spec: &str) -> Result<Self, ShimError> {
+        let parts: Vec<&str> = spec.split(':').collect();
+        let (source, destination, options) = match parts.as_slice() {
+            [source, destination] => (*source, *destination, ""),
+            [source, destination, options] => (*source, *destination, *options),
+            _ => {
+                return Err(invalid(spec, "expected HOST_PATH:CONTAINER_PATH[:OPTIONS]"));
+            }
+        };
+
+        if source.is_empty() || !source.starts_with('/') {
+            return Err(invalid(spec, "host path must be absolute"));
+        }
+        let destination = normalize_destination(destination)
+            .ok_or_else(|| invalid(spec, "container path must be absolute"))?;
+        if destination == "/" {
+            return Err(invalid(spec, "container path cannot be /"));
+        }
+
+        let mut mount = BindMount {
+            source: PathBuf::from(source),
+            destination,
+            read_only: false,
+            recursive: true,
+            propagation: "rprivate".to_string(),
+            create: false,
+        };
+
+        let (mut access, mut kind, mut propagation) = (None, None, None);
+        for option in options.split(',').filter(|o| !o.is_empty()) {
+            let slot = match option {
+                "ro" | "rw" => &mut access,
+                "bind" | "rbind" => &mut kind,
+                o if PROPAGATION_MODES.contains(&o) => &mut propagation,
+                "create" => {
+                    mount.create = true;
+                    continue;
+                }
+                o if IGNORED_OPTIONS.contains(&o) => continue,
+                o => {
+                    return Err(invalid(spec, &format!("unknown option '{}'", o)));
+                }
+            };
+            if let Some(previous) = slot.replace(option)
+                && previous != option
+            {
+                return Err(invalid(
+                    spec,
+                    &format!("conflicting options '{}' and '{}'", previous, option),
+                ));
+            }
+        }
+
+        mount.read_only = access == Some("ro");
+        mount.recursive = kind != Some("bind");
+        if let Some(propagation) = propagation {
+            mount.propagation = propagation.to_string();
+        }
+        Ok(mount)
+    }
+
+    /// Parses `spec` and resolves its source on the host, creating it if the
+    /// spec has the `create` option.
+    pub fn resolve(spec: &str) -> Result<Self, ShimError> {
+        let mut mount = Self::parse(spec)?;
+        if !mount.source.exists() {
+            if !mount.create {
+                return Err(ShimError::InvalidMount(format!(
+                    "bind source path {} does not exist; create it or add the 'create' option",
+                    mount.source.display()
+                )));
+            }
+            std::fs::create_dir_all(&mount.source).map_err(|e| {
+                ShimError::InvalidMount(format!(
+                    "failed to create bind source path {}: {}",
+                    mount.source.display(),
+                    e
+                ))
+            })?;
+        }
+        mount.source = mount.source.canonicalize().map_err(|e| {
+            ShimError::InvalidMount(format!(
+                "failed to resolve bind source path {}: {}",
+                mount.source.display(),
+                e
+            ))
+        })?;
+        Ok(mount)
+    }
+
+    /// Options of the OCI mount for this bind.
+    pub fn oci_options(&self) -> Vec<String> {
+        let kind = if self.recursive { "rbind" } else { "bind" };
+        let access = if self.read_only { "ro" } else { "rw" };
+        vec![
+            kind.to_string(),
+            self.propagation.clone(),
+            access.to_string(),
+        ]
+    }
+}
+
+/// Resolves every bind spec of a container, failing on the first bad one.
+pub fn resolve_binds(binds: &[String]) -> Result<Vec<BindMount>, ShimError> {
+    binds.iter().map(|spec| BindMount::resolve(spec)).collect()
+}
+
+/// Lexically cleans an absolute container path: drops `.` components and
+/// repeated or trailing slashes. Returns `None` for relative paths and paths
+/// that climb with `..`.
+pub(crate) fn normalize_destination(path: &str) -> Option<String> {
+    if !path.starts_with('/') {
+        return None;
+    }
+    let mut normalized = String::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::RootDir | Component::CurDir => {}
+            Component::Normal(name) => {
+                normalized.push('/');
+                normalized.push_str(name.to_str()?);
+            }
+            Component::ParentDir | Component::Prefix(_) => return None,
+        }
+    }
+    if normalized.is_empty() {
+        normalized.push('/');
+    }
+    Some(normalized)
+}
+
+fn invalid(spec: &str, reason: &str) -> ShimError {
+    ShimError::InvalidMount(format!("volume spec '{}': {}", spec, reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse_defaults() {
+        let mount = BindMount::parse("/data:/mnt/data").unwrap();
+        assert_eq!(mount.source, PathBuf::from("/data"));
+        assert_eq!(mount.destination, "/mnt/data");
+        assert!(!mount.read_only);
+        assert!(!mount.create);
+        assert_eq!(mount.oci_options(), vec!["rbind", "rprivate", "rw"]);
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let mount = BindMount::parse("/data:/mnt/./data/:ro,bind,rshared,z").unwrap();
+        assert_eq!(mount.destination, "/mnt/data");
+        assert_eq!(mount.oci_options(), vec!["bind", "rshared", "ro"]);
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid_specs() {
+        for spec in [
+            "/data",
+            "/data:/mnt:ro:extra",
+            "data:/mnt",
+            ":/mnt",
+            "/data:mnt",
+            "/data:/",
+            "/data:/mnt/../etc",
+            "/data:/mnt:noexec",
+            "/data:/mnt:ro,rw",
+        ] {
+            assert!(
+                matches!(BindMount::parse(spec), Err(ShimError::InvalidMount(_))),
+                "{} should be rejected",
+                spec
+            );
+        }
+    }
+
+    #[test]
+    fn test_resolve_missing_source() {
+        let temp_dir = TempDir::new().unwrap();
+        let source = temp_dir.path().join("missing");
+        let spec = format!("{}:/mnt", source.display());
+
+        assert!(matches!(
+            BindMount::resolve(&spec),
+            Err(ShimError::InvalidMount(_))
+        ));
+        assert!(!source.exists());
+
+        let mount = BindMount::resolve(&format!("{}:create", spec)).unwrap();
+        assert!(source.is_dir());
+        assert_eq!(mount.source, source.canonicalize().unwrap());
+    }
+}
@@ -0,0 +1,171 @@
+//! Which destinations a container may reach through the NAT, as
+//! `--egress-allow` and `--egress-deny` configure it.
+//!
+//! A denied network is never reachable, allowed or not; once any network is
+//! allowed, every other destination is denied too. DNS and DHCP, which the
+//! gateway answers itself, are not subject to the policy.
+
+use crate::error::ShimError;
+use crate::types::HostConfig;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// An IPv4 or IPv6 network, `ADDRESS/PREFIX` or a single address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    address: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.address, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = ShimError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ShimError::InvalidEgress(format!("invalid network {}", s));
+        let (address, prefix) = match s.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (s, None),
+        };
+        let address: IpAddr = address.parse().map_err(|_| invalid())?;
+        let max = if address.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse::<u8>().map_err(|_| invalid())?,
+            None => max,
+        };
+        if prefix > max {
+            return Err(invalid());
+        }
+        Ok(Self { address, prefix })
+    }
+}
+
+impl std::fmt::Display for Cidr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix)
+    }
+}
+
+/// A container's egress policy, checked.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EgressPolicy {
+    pub allow: Vec<Cidr>,
+    pub deny: Vec<Cidr>,
+}
+
+impl EgressPolicy {
+    pub fn from_host_config(host_config: &HostConfig) -> Result<Self, ShimError> {
+        let parse = |networks: &[String]| {
+            networks
+                .iter()
+                .map(|network| network.parse())
+                .collect::<Result<Vec<Cidr>, _>>()
+        };
+        Ok(Self {
+            allow: parse(&host_config.egress_allow)?,
+            deny: parse(&host_config.egress_deny)?,
+        })
+    }
+
+    /// Whether the policy restricts anything.
+    pub fn is_restricted(&self) -> bool {
+        !self.allow.is_empty() || !self.deny.is_empty()
+    }
+
+    /// Whether the container may open flows to `ip`.
+    #[cfg_attr(not(all(feature = "libkrun", target_os = "macos")), allow(dead_code))]
+    pub fn permits(&self, ip: IpAddr) -> bool {
+        if self.deny.iter().any(|network| network.contains(ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|network| network.contains(ip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn policy(allow: &[&str], deny: &[&str]) -> EgressPolicy {
+        let strings = |values: &[&str]| values.iter().map(|v| v.to_string()).collect();
+        EgressPolicy::from_host_config(&HostConfig {
+            egress_allow: strings(allow),
+            egress_deny: strings(deny),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_cidr_contains() {
+        let network: Cidr = "10.1.0.0/16".parse().unwrap();
+        assert!(network.contains(ip("10.1.200.3")));
+        assert!(!network.contains(ip("10.2.0.1")));
+        assert!(!network.contains(ip("::ffff:10.1.0.1")));
+
+        let host: Cidr = "93.184.216.34".parse().unwrap();
+        assert_eq!(host.to_string(), "93.184.216.34/32");
+        assert!(host.contains(ip("93.184.216.34")));
+        assert!(!host.contains(ip("93.184.216.35")));
+
+        let any: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(any.contains(ip("1.2.3.4")));
+
+        let v6: Cidr = "2001:db8::/32".parse().unwrap();
+        assert!(v6.contains(ip("2001:db8:1::1")));
+        assert!(!v6.contains(ip("2001:db9::1")));
+
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("2001:db8::/129".parse::<Cidr>().is_err());
+        assert!("example.com/24".parse::<Cidr>().is_err());
+        assert!("10.0.0.0/".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn test_egress_policy() {
+        let open = policy(&[], &[]);
+        assert!(!open.is_restricted());
+        assert!(open.permits(ip("1.1.1.1")));
+
+        let allowlist = policy(&["10.0.0.0/8"], &[]);
+        assert!(allowlist.permits(ip("10.3.2.1")));
+        assert!(!allowlist.permits(ip("1.1.1.1")));
+        assert!(!allowlist.permits(ip("2001:db8::1")));
+
+        let denylist = policy(&[], &["169.254.169.254"]);
+        assert!(!denylist.permits(ip("169.254.169.254")));
+        assert!(denylist.permits(ip("1.1.1.1")));
+
+        // Denied networks win over allowed ones.
+        let both = policy(&["10.0.0.0/8"], &["10.0.5.0/24"]);
+        assert!(both.permits(ip("10.0.4.1")));
+        assert!(!both.permits(ip("10.0.5.1")));
+
+        assert!(
+            EgressPolicy::from_host_config(&HostConfig {
+                egress_allow: vec!["nowhere".to_string()],
+                ..Default::default()
+            })
+            .is_err()
+        );
+    }
+}
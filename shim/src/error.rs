@@ -1,3 +1,5 @@
+use std::process::ExitStatus;
+use std::time::Duration;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -20,15 +22,55 @@ pub enum ShimError {
     #[error("runc error: {0}")]
     Runc(String),
 
+    /// runc is missing or too old to run containers with.
+    #[error("runc is unavailable: {0}")]
+    RuncUnavailable(String),
+
+    /// A runc command that ran and exited nonzero, with what it printed.
+    #[error("runc {command} failed ({status}): {stderr}")]
+    RuncExited {
+        command: String,
+        status: ExitStatus,
+        stderr: String,
+    },
+
+    #[error("permission denied: {0}")]
+    PermissionDenied(String),
+
+    #[error("failed to mount rootfs: {0}")]
+    MountFailed(String),
+
     #[error("oci spec error: {0}")]
     OciSpec(String),
 
     #[error("runtime error: {0}")]
     RuntimeError(String),
 
+    #[error("invalid mount: {0}")]
+    InvalidMount(String),
+
+    #[error("invalid network mode: {0}")]
+    InvalidNetworkMode(String),
+
+    #[error("invalid user: {0}")]
+    InvalidUser(String),
+
+    #[error("invalid dns configuration: {0}")]
+    InvalidDns(String),
+
+    #[error("invalid egress policy: {0}")]
+    InvalidEgress(String),
+
+    #[error("invalid resources: {0}")]
+    InvalidResources(String),
+
     #[error("not supported: {0}")]
     NotSupported(String),
 
+    /// An exec process killed for running past its timeout.
+    #[error("exec timed out after {0:?}")]
+    ExecTimedOut(Duration),
+
     #[error("io error: {0}")]
     Io(#[from] std::io::Error),
 
@@ -36,9 +78,35 @@ pub enum ShimError {
     Json(#[from] serde_json::Error),
 }
 
-impl From<runc::error::Error> for ShimError {
-    fn from(e: runc::error::Error) -> Self {
-        ShimError::Runc(e.to_string())
+impl ShimError {
+    /// The error for runc `command` on container `id` exiting with `status`.
+    /// What it printed tells a missing container or a denied permission
+    /// apart from other failures.
+    pub(crate) fn runc_exited(id: &str, command: &str, status: ExitStatus, stderr: &str) -> Self {
+        let stderr = stderr.trim();
+        classify_runc(id, stderr).unwrap_or_else(|| ShimError::RuncExited {
+            command: command.to_string(),
+            status,
+            stderr: stderr.to_string(),
+        })
+    }
+
+    /// The error for a runc crate call on container `id`, classified as
+    /// [`ShimError::runc_exited`] does.
+    pub(crate) fn runc(id: &str, e: runc::error::Error) -> Self {
+        let message = e.to_string();
+        classify_runc(id, &message).unwrap_or(ShimError::Runc(message))
+    }
+}
+
+fn classify_runc(id: &str, message: &str) -> Option<ShimError> {
+    let lower = message.to_ascii_lowercase();
+    if lower.contains("container does not exist") {
+        Some(ShimError::ContainerNotFound(id.to_string()))
+    } else if lower.contains("permission denied") || lower.contains("operation not permitted") {
+        Some(ShimError::PermissionDenied(message.to_string()))
+    } else {
+        None
     }
 }
 
@@ -47,3 +115,31 @@ impl From<oci_spec::OciSpecError> for ShimError {
         ShimError::OciSpec(e.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+
+    #[test]
+    fn test_runc_exited() {
+        let status = ExitStatus::from_raw(1 << 8);
+
+        let e = ShimError::runc_exited("abc", "kill", status, "container does not exist\n");
+        assert!(matches!(e, ShimError::ContainerNotFound(id) if id == "abc"));
+
+        let e = ShimError::runc_exited(
+            "abc",
+            "run",
+            status,
+            "runc run failed: unable to start container process: exec: \"/app\": permission denied",
+        );
+        assert!(matches!(e, ShimError::PermissionDenied(m) if m.ends_with("permission denied")));
+
+        let e = ShimError::runc_exited("abc", "run", status, "  cgroup setup failed\n");
+        assert_eq!(
+            e.to_string(),
+            "runc run failed (exit status: 1): cgroup setup failed"
+        );
+    }
+}
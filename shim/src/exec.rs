@@ -0,0 +1,116 @@
+//! The process spec of a `runc exec`, derived from the container's own
+//! process so that what the exec does not override is inherited.
+
+use crate::error::ShimError;
+use crate::types::ExecOpts;
+use crate::user::resolve_user;
+use oci_spec::runtime::{Process, UserBuilder};
+use ross_core::env::merge_env;
+use std::path::{Path, PathBuf};
+
+/// The process to run for `opts` in a container whose main process is
+/// `base` and whose root filesystem is at `rootfs`.
+///
+/// The exec's environment is merged over the container's, its working
+/// directory and user replace the container's when given, and it cannot
+/// gain privileges unless it is privileged.
+pub(crate) fn exec_process(
+    base: Option<&Process>,
+    opts: &ExecOpts,
+    rootfs: &Path,
+) -> Result<Process, ShimError> {
+    let mut process = base.cloned().unwrap_or_default();
+    process.set_args(Some(opts.cmd.clone()));
+    process.set_env(Some(merge_env(
+        process.env().clone().unwrap_or_default(),
+        opts.env.iter().cloned(),
+    )));
+    if let Some(cwd) = &opts.working_dir {
+        process.set_cwd(PathBuf::from(cwd));
+    }
+    if let Some(user) = &opts.user {
+        let user = resolve_user(rootfs, user)?;
+        process.set_user(
+            UserBuilder::default()
+                .uid(user.uid)
+                .gid(user.gid)
+                .additional_gids(user.additional_gids)
+                .build()
+                .map_err(|e| ShimError::OciSpec(e.to_string()))?,
+        );
+    }
+    process.set_terminal(Some(opts.tty));
+    process.set_console_size(None);
+    process.set_no_new_privileges(Some(!opts.privileged));
+    Ok(process)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oci_spec::runtime::ProcessBuilder;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn test_exec_process_overrides_container_process() {
+        let base = ProcessBuilder::default()
+            .args(strings(&["nginx"]))
+            .env(strings(&["PATH=/usr/bin:/bin", "FOO=container"]))
+            .cwd("/srv")
+            .no_new_privileges(true)
+            .build()
+            .unwrap();
+        let opts = ExecOpts {
+            cmd: strings(&["env"]),
+            env: strings(&["FOO=bar", "EMPTY=", "PATH", "UNSET"]),
+            user: Some("1000:1000".to_string()),
+            working_dir: Some("/tmp".to_string()),
+            tty: false,
+            privileged: false,
+            timeout: None,
+        };
+
+        let process = exec_process(Some(&base), &opts, Path::new("/nonexistent")).unwrap();
+        assert_eq!(process.args().as_deref(), Some(&strings(&["env"])[..]));
+        assert_eq!(
+            process.env().as_deref(),
+            Some(&strings(&["PATH=/usr/bin:/bin", "FOO=bar", "EMPTY="])[..])
+        );
+        assert_eq!(process.cwd(), Path::new("/tmp"));
+        assert_eq!(process.user().uid(), 1000);
+        assert_eq!(process.user().gid(), 1000);
+        let json = serde_json::to_value(&process).unwrap();
+        assert_eq!(json["terminal"], false);
+        assert_eq!(json["noNewPrivileges"], true);
+    }
+
+    #[test]
+    fn test_exec_process_inherits_container_process() {
+        let base = ProcessBuilder::default()
+            .args(strings(&["nginx"]))
+            .env(strings(&["PATH=/usr/bin:/bin"]))
+            .cwd("/srv")
+            .build()
+            .unwrap();
+        let opts = ExecOpts {
+            cmd: strings(&["sh"]),
+            tty: true,
+            privileged: true,
+            ..Default::default()
+        };
+
+        let process = exec_process(Some(&base), &opts, Path::new("/nonexistent")).unwrap();
+        assert_eq!(
+            process.env().as_deref(),
+            Some(&strings(&["PATH=/usr/bin:/bin"])[..])
+        );
+        assert_eq!(process.cwd(), Path::new("/srv"));
+        assert_eq!(process.user().uid(), 0);
+        let json = serde_json::to_value(&process).unwrap();
+        assert_eq!(json["terminal"], true);
+        assert_eq!(json["noNewPrivileges"], false);
+    }
+}
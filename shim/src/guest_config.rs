@@ -3,7 +3,9 @@
 //! This module defines types that are serialized/deserialized between
 //! the host (macOS shim) and guest (Linux init process).
 
+use crate::resolv::DnsConfig;
 use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VolumeMount {
@@ -15,7 +17,7 @@ pub struct VolumeMount {
     pub read_only: bool,
 }
 
-/// Configuration passed from host to guest via command-line JSON.
+/// Configuration passed from host to guest as a JSON file in the rootfs.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GuestConfig {
     pub command: String,
@@ -28,4 +30,33 @@ pub struct GuestConfig {
     pub vsock_port: u32,
     #[serde(default)]
     pub volumes: Vec<VolumeMount>,
+    /// The container has no network: only loopback is configured.
+    #[serde(default)]
+    pub network_disabled: bool,
+    /// Contents of `/etc/resolv.conf`, replacing the image's.
+    #[serde(default)]
+    pub resolv_conf: Option<String>,
+    /// Contents of `/etc/hosts`, replacing the image's.
+    #[serde(default)]
+    pub hosts: Option<String>,
+}
+
+impl GuestConfig {
+    /// Has the guest replace the image's resolver files, when the container
+    /// configures them, with ones pointing at `nameservers` and naming
+    /// `address` after `hostname`.
+    pub fn configure_dns(
+        &mut self,
+        dns: &DnsConfig,
+        nameservers: &[IpAddr],
+        hostname: &str,
+        address: Option<IpAddr>,
+    ) {
+        if dns.configures_resolver() {
+            self.resolv_conf = Some(dns.resolv_conf(nameservers));
+        }
+        if dns.configures_hosts() {
+            self.hosts = Some(dns.hosts(hostname, address));
+        }
+    }
 }
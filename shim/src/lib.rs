@@ -1,16 +1,31 @@
+mod bind;
+mod egress;
 mod error;
+mod exec;
 mod guest_config;
 mod libkrun;
+mod logfile;
+mod names;
+mod resolv;
 pub mod rootfs;
 mod runc_shim;
+mod runc_version;
+mod runtime;
 mod shim;
+mod stdin;
+mod tmpfs;
 pub mod tty_host;
 pub mod tty_protocol;
 mod types;
+mod user;
 
+pub use bind::{BindMount, resolve_binds};
 pub use error::ShimError;
 pub use guest_config::GuestConfig;
 pub use libkrun::KrunShim;
+pub use logfile::LogRecord;
 pub use runc_shim::RuncShim;
+pub use runtime::Runtime;
 pub use shim::{OutputEventStream, Shim};
+pub use stdin::StdinState;
 pub use types::*;
@@ -0,0 +1,116 @@
+//! Reaping the processes forked to run VMs.
+//!
+//! Every fork is wrapped in a [`VmProcess`] so that it is waited for on
+//! every path, including the early returns between the fork and the session
+//! that would otherwise wait for it, and no VM is left behind as a zombie.
+
+/// A forked VM process, reaped by [`VmProcess::wait`] or, when dropped
+/// without being waited for, on a thread of its own once it exits. The VM
+/// is not killed: with live restore it outlives the session that started it.
+pub struct VmProcess {
+    pid: libc::pid_t,
+    waited: bool,
+}
+
+impl VmProcess {
+    pub fn new(pid: libc::pid_t) -> Self {
+        Self { pid, waited: false }
+    }
+
+    pub fn pid(&self) -> libc::pid_t {
+        self.pid
+    }
+
+    /// Waits for the VM to exit and returns its exit code.
+    pub fn wait(mut self) -> i32 {
+        self.waited = true;
+        wait_for_child(self.pid)
+    }
+}
+
+impl Drop for VmProcess {
+    fn drop(&mut self) {
+        if self.waited {
+            return;
+        }
+        let pid = self.pid;
+        tracing::debug!(pid, "Reaping VM process in the background");
+        std::thread::spawn(move || wait_for_child(pid));
+    }
+}
+
+/// Waits for child `pid` and returns its exit code, or 128 plus the signal
+/// that killed it.
+fn wait_for_child(pid: libc::pid_t) -> i32 {
+    let mut status: libc::c_int = 0;
+    while unsafe { libc::waitpid(pid, &mut status, 0) } < 0 {
+        if std::io::Error::last_os_error().kind() != std::io::ErrorKind::Interrupted {
+            tracing::warn!(pid, "Failed to wait for VM process");
+            return 1;
+        }
+    }
+    if libc::WIFEXITED(status) {
+        libc::WEXITSTATUS(status)
+    } else if libc::WIFSIGNALED(status) {
+        128 + libc::WTERMSIG(status)
+    } else {
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn fork_exiting(code: i32) -> VmProcess {
+        let pid = unsafe { libc::fork() };
+        assert!(pid >= 0, "fork failed");
+        if pid == 0 {
+            unsafe { libc::_exit(code) };
+        }
+        VmProcess::new(pid)
+    }
+
+    /// Whether `pid` is gone, zombies included.
+    fn reaped(pid: libc::pid_t) -> bool {
+        let ret = unsafe { libc::kill(pid, 0) };
+        ret < 0 && std::io::Error::last_os_error().raw_os_error() == Some(libc::ESRCH)
+    }
+
+    #[test]
+    fn test_no_zombies_after_sequential_runs() {
+        let mut pids = Vec::new();
+        for i in 0..100 {
+            let child = fork_exiting(i % 3);
+            pids.push(child.pid());
+            // Half the runs end early, without waiting for their VM.
+            if i % 2 == 0 {
+                assert_eq!(child.wait(), i % 3);
+            } else {
+                drop(child);
+            }
+        }
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        for pid in pids {
+            while !reaped(pid) {
+                assert!(Instant::now() < deadline, "process {} is <defunct>", pid);
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        }
+    }
+
+    #[test]
+    fn test_wait_reports_signals() {
+        let pid = unsafe { libc::fork() };
+        assert!(pid >= 0, "fork failed");
+        if pid == 0 {
+            unsafe {
+                libc::raise(libc::SIGKILL);
+                libc::_exit(0);
+            }
+        }
+        assert_eq!(VmProcess::new(pid).wait(), 128 + libc::SIGKILL);
+    }
+}
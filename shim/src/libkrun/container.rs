@@ -10,6 +10,9 @@ pub struct ContainerMetadata {
     pub info: ContainerInfo,
     pub config: ContainerConfig,
     pub host_config: HostConfig,
+    /// Pid of the forked VM process while it runs.
+    #[serde(default)]
+    pub vm_pid: Option<i32>,
 }
 
 impl ContainerMetadata {
@@ -9,7 +9,14 @@ use std::ffi::CString;
 use std::os::unix::io::RawFd;
 use std::path::Path;
 
+use super::child::VmProcess;
 use super::net::{COMPAT_NET_FEATURES, NET_FLAG_VFKIT};
+use super::resources::VmResources;
+
+/// Where the guest config is written, relative to the rootfs. ross-init is
+/// pointed at it with `--config`, which keeps large configs off the kernel
+/// command line.
+const GUEST_CONFIG_FILE: &str = ".ross-config.json";
 
 /// Network configuration for the VM.
 #[derive(Clone, Debug)]
@@ -43,28 +50,15 @@ pub fn fix_root_mode(rootfs: &Path) {
         .output();
 }
 
-/// Wait for child process and return exit code.
-pub fn wait_for_child(pid: libc::pid_t) -> i32 {
-    let mut status: libc::c_int = 0;
-    unsafe {
-        libc::waitpid(pid, &mut status, 0);
-    }
-    if libc::WIFEXITED(status) {
-        libc::WEXITSTATUS(status)
-    } else {
-        1
-    }
-}
-
 /// Fork and run VM in child process (legacy non-interactive mode).
-/// Returns (stdout_read_fd, child_pid) on success.
+/// Returns (stdout_read_fd, child) on success.
 pub fn fork_and_run_vm(
     rootfs_path: &Path,
     exec_path: &str,
     argv: &[String],
     env: &[String],
     workdir: Option<&str>,
-) -> Result<(RawFd, libc::pid_t), ShimError> {
+) -> Result<(RawFd, VmProcess), ShimError> {
     let mut stdout_pipe: [libc::c_int; 2] = [0, 0];
     if unsafe { libc::pipe(stdout_pipe.as_mut_ptr()) } != 0 {
         return Err(ShimError::RuntimeError("Failed to create pipe".to_string()));
@@ -73,6 +67,10 @@ pub fn fork_and_run_vm(
     let pid = unsafe { libc::fork() };
 
     if pid < 0 {
+        unsafe {
+            libc::close(stdout_pipe[0]);
+            libc::close(stdout_pipe[1]);
+        }
         return Err(ShimError::RuntimeError("Fork failed".to_string()));
     }
 
@@ -84,38 +82,49 @@ pub fn fork_and_run_vm(
             libc::close(stdout_pipe[1]);
         }
 
-        run_vm_inner(rootfs_path, exec_path, argv, env, workdir, None, None, &[]);
+        run_vm_inner(
+            rootfs_path,
+            exec_path,
+            argv,
+            env,
+            workdir,
+            VmResources::default(),
+            None,
+            None,
+            &[],
+        );
     }
 
     unsafe {
         libc::close(stdout_pipe[1]);
     }
 
-    Ok((stdout_pipe[0], pid))
+    Ok((stdout_pipe[0], VmProcess::new(pid)))
 }
 
 /// Fork and run VM with vsock for interactive I/O.
-/// Returns child_pid on success.
+/// Returns the child on success.
 #[allow(dead_code)]
 pub fn fork_and_run_vm_interactive(
     rootfs_path: &Path,
     guest_config: &GuestConfig,
     vsock_port: u32,
-) -> Result<libc::pid_t, ShimError> {
+) -> Result<VmProcess, ShimError> {
     fork_and_run_vm_interactive_with_network(rootfs_path, guest_config, vsock_port, None)
 }
 
 /// Fork and run VM with vsock for interactive I/O and optional network configuration.
-/// Returns child_pid on success.
+/// Returns the child on success.
 pub fn fork_and_run_vm_interactive_with_network(
     rootfs_path: &Path,
     guest_config: &GuestConfig,
     vsock_port: u32,
     network_config: Option<NetworkConfig>,
-) -> Result<libc::pid_t, ShimError> {
+) -> Result<VmProcess, ShimError> {
     fork_and_run_vm_interactive_with_network_and_shares(
         rootfs_path,
         guest_config,
+        VmResources::default(),
         vsock_port,
         network_config,
         &[],
@@ -127,17 +136,18 @@ pub fn fork_and_run_vm_interactive_with_network(
 pub fn fork_and_run_vm_interactive_with_network_and_shares(
     rootfs_path: &Path,
     guest_config: &GuestConfig,
+    resources: VmResources,
     vsock_port: u32,
     network_config: Option<NetworkConfig>,
     virtiofs_shares: &[(String, String)],
-) -> Result<libc::pid_t, ShimError> {
+) -> Result<VmProcess, ShimError> {
     // Compute socket path before fork so both parent and child use the same path
     let socket_path = get_vsock_socket_path(vsock_port);
 
     // Write config to a file in the rootfs that ross-init can read
     let config_json = serde_json::to_string(guest_config)
         .map_err(|e| ShimError::RuntimeError(format!("Failed to serialize config: {}", e)))?;
-    let config_path = rootfs_path.join(".ross-config.json");
+    let config_path = rootfs_path.join(GUEST_CONFIG_FILE);
 
     tracing::debug!(
         config_path = %config_path.display(),
@@ -157,7 +167,11 @@ pub fn fork_and_run_vm_interactive_with_network_and_shares(
 
     if pid == 0 {
         let exec_path = "/ross-init";
-        let argv = vec![exec_path.to_string()];
+        let argv = vec![
+            exec_path.to_string(),
+            "--config".to_string(),
+            format!("/{}", GUEST_CONFIG_FILE),
+        ];
         let env: Vec<String> = guest_config.env.clone();
 
         run_vm_inner(
@@ -166,21 +180,24 @@ pub fn fork_and_run_vm_interactive_with_network_and_shares(
             &argv,
             &env,
             guest_config.workdir.as_deref(),
+            resources,
             Some((vsock_port, socket_path)),
             network_config,
             virtiofs_shares,
         );
     }
 
-    Ok(pid)
+    Ok(VmProcess::new(pid))
 }
 
+#[allow(clippy::too_many_arguments)]
 fn run_vm_inner(
     rootfs_path: &Path,
     exec_path: &str,
     argv: &[String],
     env: &[String],
     workdir: Option<&str>,
+    resources: VmResources,
     vsock_config: Option<(u32, String)>,
     network_config: Option<NetworkConfig>,
     virtiofs_shares: &[(String, String)],
@@ -194,7 +211,7 @@ fn run_vm_inner(
     }
     let ctx_id = ctx_id as u32;
 
-    if unsafe { krun_sys::krun_set_vm_config(ctx_id, 2, 1100) } < 0 {
+    if unsafe { krun_sys::krun_set_vm_config(ctx_id, resources.vcpus, resources.ram_mib) } < 0 {
         eprintln!("Failed to set VM config");
         std::process::exit(1);
     }
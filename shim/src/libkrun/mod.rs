@@ -3,9 +3,13 @@
 //! This module implements the container shim using libkrun to run Linux
 //! containers in a lightweight VM.
 
+#[cfg(any(test, all(feature = "libkrun", target_os = "macos")))]
+mod child;
 mod container;
+mod resources;
 mod rootfs;
 mod shim;
+mod xattr;
 
 #[cfg(all(feature = "libkrun", target_os = "macos"))]
 mod krun;
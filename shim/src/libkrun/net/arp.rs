@@ -1,25 +1,98 @@
 //! ARP handling.
 
 use super::eth::{build_eth_header, ETHERTYPE_ARP};
-use super::{GATEWAY_IP, GATEWAY_MAC, HOST_IP};
+use super::{DEFAULT_MAC, GATEWAY_IP, GATEWAY_MAC, GUEST_IP, HOST_IP, SUBNET_MASK};
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::sync::RwLock;
 
-/// Handle ARP request and return response if applicable.
-pub fn handle_arp(payload: &[u8], src_mac: &[u8]) -> Option<Vec<u8>> {
+/// Most bindings kept; the link only ever holds the guest, so more than a
+/// handful means something is making addresses up.
+const MAX_ENTRIES: usize = 16;
+
+/// IPv4 to MAC bindings of the guest side of the link, learned from the ARP
+/// and IP traffic it sends. Shared by the workers of a stack, since ARP is
+/// handled by one of them while frames to the guest are built by all.
+#[derive(Debug, Default)]
+pub struct ArpTable {
+    entries: RwLock<HashMap<[u8; 4], [u8; 6]>>,
+}
+
+impl ArpTable {
+    /// Records that `ip` is at `mac`. Addresses outside the guest subnet,
+    /// the unspecified address, and the ones the stack answers for itself
+    /// are ignored.
+    pub fn learn(&self, ip: &[u8], mac: &[u8]) {
+        let (Ok(ip), Ok(mac)) = (<[u8; 4]>::try_from(ip), <[u8; 6]>::try_from(mac)) else {
+            return;
+        };
+        let in_subnet = (0..4).all(|i| ip[i] & SUBNET_MASK[i] == GATEWAY_IP[i] & SUBNET_MASK[i]);
+        if !in_subnet || ip == GATEWAY_IP || ip == HOST_IP || mac[0] & 1 != 0 {
+            return;
+        }
+        // Checked under the read lock first: every guest packet gets here.
+        if self.entries.read().unwrap().get(&ip) == Some(&mac) {
+            return;
+        }
+
+        let mut entries = self.entries.write().unwrap();
+        if entries.len() >= MAX_ENTRIES && !entries.contains_key(&ip) {
+            return;
+        }
+        if let Some(old) = entries.insert(ip, mac)
+            && old != mac
+        {
+            tracing::debug!(
+                "MAC of {} changed from {:02x?} to {:02x?}",
+                Ipv4Addr::from(ip),
+                old,
+                mac
+            );
+        }
+    }
+
+    pub fn lookup(&self, ip: &[u8; 4]) -> Option<[u8; 6]> {
+        self.entries.read().unwrap().get(ip).copied()
+    }
+
+    /// MAC of the guest, or `DEFAULT_MAC` until it has sent anything.
+    pub fn guest_mac(&self) -> [u8; 6] {
+        self.lookup(&GUEST_IP).unwrap_or(DEFAULT_MAC)
+    }
+}
+
+/// Handle an ARP packet, learning the sender's binding, and return the reply
+/// if one is due.
+///
+/// Requests for the gateway and host IPs are answered. Gratuitous ARP (the
+/// sender announcing its own address) only updates the table, unless it
+/// claims one of those addresses, which is then defended with a reply.
+pub fn handle_arp(payload: &[u8], src_mac: &[u8], table: &ArpTable) -> Option<Vec<u8>> {
     if payload.len() < 28 {
         return None;
     }
-
-    let operation = u16::from_be_bytes([payload[6], payload[7]]);
-    if operation != 1 {
-        return None; // Only handle requests
+    // Ethernet hardware addresses for IPv4 only.
+    if payload[0..6] != [0, 1, 0x08, 0, 6, 4] {
+        return None;
     }
 
+    let operation = u16::from_be_bytes([payload[6], payload[7]]);
+    let sender_mac = &payload[8..14];
+    let sender_ip = &payload[14..18];
     let target_ip = &payload[24..28];
-    
-    // Respond for gateway IP and host IP (ross.host.internal)
+    let gratuitous = sender_ip == target_ip;
+
     let is_gateway = target_ip == GATEWAY_IP;
     let is_host = target_ip == HOST_IP;
-    
+    if gratuitous && (is_gateway || is_host) {
+        tracing::warn!("Guest announced the gateway's own address, defending it");
+    } else {
+        table.learn(sender_ip, sender_mac);
+        if gratuitous || operation != 1 {
+            return None;
+        }
+    }
+
     if !is_gateway && !is_host {
         return None;
     }
@@ -43,8 +116,107 @@ pub fn handle_arp(payload: &[u8], src_mac: &[u8]) -> Option<Vec<u8>> {
     arp[8..14].copy_from_slice(&GATEWAY_MAC); // sender MAC
     arp[14..18].copy_from_slice(target_ip);   // sender IP (the IP being requested)
     arp[18..24].copy_from_slice(src_mac);     // target MAC
-    arp[24..28].copy_from_slice(&payload[14..18]); // target IP
+    arp[24..28].copy_from_slice(sender_ip); // target IP
 
     response.extend_from_slice(&arp);
     Some(response)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUEST_MAC: [u8; 6] = [0x5a, 0x94, 0xef, 0xe4, 0x0c, 0xee];
+    const OTHER_MAC: [u8; 6] = [0x5a, 0x94, 0xef, 0xe4, 0x0c, 0xef];
+
+    fn arp(operation: u16, sender_mac: &[u8], sender_ip: &[u8], target_ip: &[u8]) -> Vec<u8> {
+        let mut arp = vec![0, 1, 0x08, 0, 6, 4];
+        arp.extend_from_slice(&operation.to_be_bytes());
+        arp.extend_from_slice(sender_mac);
+        arp.extend_from_slice(sender_ip);
+        arp.extend_from_slice(&[0; 6]);
+        arp.extend_from_slice(target_ip);
+        arp
+    }
+
+    #[test]
+    fn test_learn() {
+        let table = ArpTable::default();
+        assert_eq!(table.guest_mac(), DEFAULT_MAC);
+
+        table.learn(&GUEST_IP, &GUEST_MAC);
+        assert_eq!(table.lookup(&GUEST_IP), Some(GUEST_MAC));
+        assert_eq!(table.guest_mac(), GUEST_MAC);
+
+        // A later binding for the same address replaces the old one.
+        table.learn(&GUEST_IP, &OTHER_MAC);
+        assert_eq!(table.guest_mac(), OTHER_MAC);
+
+        // Outside the subnet, the stack's own addresses and multicast MACs
+        // are never learned.
+        table.learn(&[10, 0, 0, 2], &GUEST_MAC);
+        table.learn(&GATEWAY_IP, &GUEST_MAC);
+        table.learn(&HOST_IP, &GUEST_MAC);
+        table.learn(&[192, 168, 127, 3], &[0x01, 0, 0x5e, 0, 0, 1]);
+        assert_eq!(table.entries.read().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_learn_is_bounded() {
+        let table = ArpTable::default();
+        for host in 2..100u8 {
+            table.learn(&[192, 168, 127, host], &GUEST_MAC);
+        }
+        assert_eq!(table.entries.read().unwrap().len(), MAX_ENTRIES);
+        // Known addresses are still refreshed once the table is full.
+        table.learn(&GUEST_IP, &OTHER_MAC);
+        assert_eq!(table.guest_mac(), OTHER_MAC);
+        assert_eq!(table.lookup(&[192, 168, 127, 99]), None);
+    }
+
+    #[test]
+    fn test_request_for_gateway() {
+        let table = ArpTable::default();
+        let request = arp(1, &GUEST_MAC, &GUEST_IP, &GATEWAY_IP);
+        let reply = handle_arp(&request, &GUEST_MAC, &table).unwrap();
+
+        assert_eq!(&reply[0..6], &GUEST_MAC);
+        let reply = &reply[14..];
+        assert_eq!(u16::from_be_bytes([reply[6], reply[7]]), 2);
+        assert_eq!(&reply[8..14], &GATEWAY_MAC);
+        assert_eq!(&reply[14..18], &GATEWAY_IP);
+        assert_eq!(&reply[24..28], &GUEST_IP);
+        assert_eq!(table.guest_mac(), GUEST_MAC);
+
+        // Other addresses on the link are not the stack's to answer for.
+        let request = arp(1, &GUEST_MAC, &GUEST_IP, &[192, 168, 127, 9]);
+        assert!(handle_arp(&request, &GUEST_MAC, &table).is_none());
+    }
+
+    #[test]
+    fn test_gratuitous_arp_updates_table() {
+        let table = ArpTable::default();
+        table.learn(&GUEST_IP, &GUEST_MAC);
+        let announcement = arp(1, &OTHER_MAC, &GUEST_IP, &GUEST_IP);
+        assert!(handle_arp(&announcement, &OTHER_MAC, &table).is_none());
+        assert_eq!(table.guest_mac(), OTHER_MAC);
+
+        // Replies teach the table too, and are not answered.
+        let reply = arp(2, &GUEST_MAC, &GUEST_IP, &GATEWAY_IP);
+        assert!(handle_arp(&reply, &GUEST_MAC, &table).is_none());
+        assert_eq!(table.guest_mac(), GUEST_MAC);
+    }
+
+    #[test]
+    fn test_gratuitous_arp_for_gateway_is_defended() {
+        let table = ArpTable::default();
+        for claimed in [GATEWAY_IP, HOST_IP] {
+            let announcement = arp(1, &GUEST_MAC, &claimed, &claimed);
+            let reply = handle_arp(&announcement, &GUEST_MAC, &table).unwrap();
+            let reply = &reply[14..];
+            assert_eq!(&reply[8..14], &GATEWAY_MAC);
+            assert_eq!(&reply[14..18], &claimed);
+            assert_eq!(table.lookup(&claimed), None);
+        }
+    }
+}
@@ -1,11 +1,50 @@
 //! DHCP server.
+//!
+//! The guest always leases `GUEST_IP`. Clients asking for another address,
+//! remembered from an earlier network, are refused so that they start over;
+//! renewals and rebinds of the guest's lease are acknowledged, so that
+//! long-running guests keep their address.
 
 use super::eth::{ETHERTYPE_IPV4, IP_PROTO_UDP, build_eth_header, build_ip_header};
-use super::{GATEWAY_IP, GATEWAY_MAC, GUEST_IP, SUBNET_MASK};
+use super::{GATEWAY_IP, GATEWAY_MAC, GUEST_IP, NetConfig, SUBNET_MASK};
 
-/// Handle DHCP request and return response.
-pub fn handle_dhcp(payload: &[u8]) -> Option<Vec<u8>> {
-    if payload.len() < 240 {
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+/// Smallest BOOTP message, which some clients insist on.
+const MIN_MESSAGE_LEN: usize = 300;
+
+const DHCPDISCOVER: u8 = 1;
+const DHCPOFFER: u8 = 2;
+const DHCPREQUEST: u8 = 3;
+const DHCPDECLINE: u8 = 4;
+const DHCPACK: u8 = 5;
+const DHCPNAK: u8 = 6;
+const DHCPRELEASE: u8 = 7;
+const DHCPINFORM: u8 = 8;
+
+const OPT_PAD: u8 = 0;
+const OPT_SUBNET_MASK: u8 = 1;
+const OPT_ROUTER: u8 = 3;
+const OPT_DNS: u8 = 6;
+const OPT_HOSTNAME: u8 = 12;
+const OPT_DOMAIN_NAME: u8 = 15;
+const OPT_MTU: u8 = 26;
+const OPT_REQUESTED_IP: u8 = 50;
+const OPT_LEASE_TIME: u8 = 51;
+const OPT_MESSAGE_TYPE: u8 = 53;
+const OPT_SERVER_ID: u8 = 54;
+const OPT_RENEWAL_TIME: u8 = 58;
+const OPT_REBINDING_TIME: u8 = 59;
+const OPT_END: u8 = 255;
+
+/// Lease granted unless the client asks for another one.
+const DEFAULT_LEASE_SECS: u32 = 86400;
+/// Bounds on the lease a client may ask for.
+const MIN_LEASE_SECS: u32 = 60;
+const MAX_LEASE_SECS: u32 = 7 * 86400;
+
+/// Handle DHCP request and return response, advertising the link `mtu`.
+pub fn handle_dhcp(payload: &[u8], mtu: usize, config: &NetConfig) -> Option<Vec<u8>> {
+    if payload.len() < 240 || payload[236..240] != MAGIC_COOKIE {
         return None;
     }
 
@@ -13,22 +52,52 @@ pub fn handle_dhcp(payload: &[u8]) -> Option<Vec<u8>> {
         return None; // Only BOOTREQUEST
     }
 
-    // Find message type in options
-    let msg_type = find_dhcp_option(&payload[240..], 53)?;
-    let response_type = match msg_type {
-        1 => 2, // DISCOVER -> OFFER
-        3 => 5, // REQUEST -> ACK
+    let options = &payload[240..];
+    let msg_type = *find_dhcp_option(options, OPT_MESSAGE_TYPE)?.first()?;
+    let ciaddr: [u8; 4] = payload[12..16].try_into().ok()?;
+    let requested_ip =
+        find_dhcp_option(options, OPT_REQUESTED_IP).and_then(|ip| <[u8; 4]>::try_from(ip).ok());
+
+    tracing::debug!(msg_type = msg_type, "DHCP request");
+
+    // Offers and acknowledgements of a lease.
+    let (response_type, lease) = match msg_type {
+        DHCPDISCOVER => (DHCPOFFER, Some(lease_time(options))),
+        DHCPREQUEST => {
+            // The client took another server's offer.
+            if find_dhcp_option(options, OPT_SERVER_ID).is_some_and(|id| id != GATEWAY_IP) {
+                return None;
+            }
+            // Selecting and rebooting clients name the address they want;
+            // renewing and rebinding ones already have it configured.
+            if requested_ip.unwrap_or(ciaddr) == GUEST_IP {
+                (DHCPACK, Some(lease_time(options)))
+            } else {
+                (DHCPNAK, None)
+            }
+        }
+        // The client configured its address itself and only wants the rest.
+        DHCPINFORM => (DHCPACK, None),
+        DHCPRELEASE | DHCPDECLINE => {
+            tracing::debug!(msg_type = msg_type, "DHCP lease given up");
+            return None;
+        }
         _ => return None,
     };
 
-    tracing::debug!(msg_type = msg_type, "DHCP request");
+    let dhcp = build_dhcp_response(payload, response_type, lease, mtu as u16, config);
 
-    let mut dhcp = [0u8; 300];
-    let dhcp_len = build_dhcp_response(payload, response_type, &mut dhcp);
+    // Clients holding an address are answered there; the others, and
+    // refused ones, by broadcast.
+    let (dst_mac, dst_ip) = if ciaddr != [0; 4] && response_type != DHCPNAK {
+        (&payload[28..34], ciaddr)
+    } else {
+        (&[0xff; 6][..], [255, 255, 255, 255])
+    };
 
-    let udp_len = 8 + dhcp_len;
-    let ip = build_ip_header(&GATEWAY_IP, &[255, 255, 255, 255], IP_PROTO_UDP, udp_len, 0);
-    let eth = build_eth_header(&[0xff; 6], &GATEWAY_MAC, ETHERTYPE_IPV4);
+    let udp_len = 8 + dhcp.len();
+    let ip = build_ip_header(&GATEWAY_IP, &dst_ip, IP_PROTO_UDP, udp_len, 0);
+    let eth = build_eth_header(dst_mac, &GATEWAY_MAC, ETHERTYPE_IPV4);
 
     let mut response = Vec::with_capacity(14 + 20 + udp_len);
     response.extend_from_slice(&eth);
@@ -38,28 +107,34 @@ pub fn handle_dhcp(payload: &[u8]) -> Option<Vec<u8>> {
     response.extend_from_slice(&68u16.to_be_bytes());
     response.extend_from_slice(&(udp_len as u16).to_be_bytes());
     response.extend_from_slice(&[0, 0]);
-    response.extend_from_slice(&dhcp[..dhcp_len]);
+    response.extend_from_slice(&dhcp);
 
     tracing::info!(
-        response = if response_type == 2 { "OFFER" } else { "ACK" },
+        response = match response_type {
+            DHCPOFFER => "OFFER",
+            DHCPACK => "ACK",
+            _ => "NAK",
+        },
         ip = format!(
             "{}.{}.{}.{}",
             GUEST_IP[0], GUEST_IP[1], GUEST_IP[2], GUEST_IP[3]
         ),
+        lease,
         "DHCP response"
     );
 
     Some(response)
 }
 
-fn find_dhcp_option(options: &[u8], opt_code: u8) -> Option<u8> {
+/// The value of option `opt_code`, if present.
+fn find_dhcp_option(options: &[u8], opt_code: u8) -> Option<&[u8]> {
     let mut i = 0;
     while i < options.len() {
         let code = options[i];
-        if code == 255 {
+        if code == OPT_END {
             break;
         }
-        if code == 0 {
+        if code == OPT_PAD {
             i += 1;
             continue;
         }
@@ -67,70 +142,211 @@ fn find_dhcp_option(options: &[u8], opt_code: u8) -> Option<u8> {
             break;
         }
         let len = options[i + 1] as usize;
-        if code == opt_code && len >= 1 && i + 2 < options.len() {
-            return Some(options[i + 2]);
+        let value = options.get(i + 2..i + 2 + len)?;
+        if code == opt_code {
+            return Some(value);
         }
         i += 2 + len;
     }
     None
 }
 
-fn build_dhcp_response(request: &[u8], msg_type: u8, out: &mut [u8; 300]) -> usize {
-    out.fill(0);
+/// The lease the client asks for, within bounds, or the default one.
+fn lease_time(options: &[u8]) -> u32 {
+    find_dhcp_option(options, OPT_LEASE_TIME)
+        .and_then(|secs| <[u8; 4]>::try_from(secs).ok())
+        .map(|secs| u32::from_be_bytes(secs).clamp(MIN_LEASE_SECS, MAX_LEASE_SECS))
+        .unwrap_or(DEFAULT_LEASE_SECS)
+}
+
+/// Builds the reply to `request`. NAKs carry no configuration, and replies
+/// without a `lease` no address.
+fn build_dhcp_response(
+    request: &[u8],
+    msg_type: u8,
+    lease: Option<u32>,
+    mtu: u16,
+    config: &NetConfig,
+) -> Vec<u8> {
+    let mut out = vec![0u8; 240];
 
     out[0] = 2; // BOOTREPLY
     out[1] = 1; // Ethernet
     out[2] = 6; // MAC length
     out[4..8].copy_from_slice(&request[4..8]); // Transaction ID
     out[10..12].copy_from_slice(&[0x80, 0]); // Broadcast flag
-    out[16..20].copy_from_slice(&GUEST_IP); // Your IP
+    if msg_type != DHCPNAK {
+        out[12..16].copy_from_slice(&request[12..16]); // Client IP
+    }
+    if lease.is_some() {
+        out[16..20].copy_from_slice(&GUEST_IP); // Your IP
+    }
     out[20..24].copy_from_slice(&GATEWAY_IP); // Server IP
-    out[28..34].copy_from_slice(&request[28..34]); // Client MAC
-
-    // Magic cookie
-    out[236..240].copy_from_slice(&[99, 130, 83, 99]);
-
-    // Options
-    let mut i = 240;
-
-    // Message type
-    out[i] = 53;
-    out[i + 1] = 1;
-    out[i + 2] = msg_type;
-    i += 3;
-
-    // Server identifier
-    out[i] = 54;
-    out[i + 1] = 4;
-    out[i + 2..i + 6].copy_from_slice(&GATEWAY_IP);
-    i += 6;
-
-    // Lease time (24h)
-    out[i] = 51;
-    out[i + 1] = 4;
-    out[i + 2..i + 6].copy_from_slice(&86400u32.to_be_bytes());
-    i += 6;
-
-    // Subnet mask
-    out[i] = 1;
-    out[i + 1] = 4;
-    out[i + 2..i + 6].copy_from_slice(&SUBNET_MASK);
-    i += 6;
-
-    // Router
-    out[i] = 3;
-    out[i + 1] = 4;
-    out[i + 2..i + 6].copy_from_slice(&GATEWAY_IP);
-    i += 6;
-
-    // DNS
-    out[i] = 6;
-    out[i + 1] = 4;
-    out[i + 2..i + 6].copy_from_slice(&GATEWAY_IP);
-    i += 6;
-
-    // End
-    out[i] = 255;
-    i += 1;
-    i
+    out[24..28].copy_from_slice(&request[24..28]); // Relay agent IP
+    out[28..44].copy_from_slice(&request[28..44]); // Client hardware address
+
+    out[236..240].copy_from_slice(&MAGIC_COOKIE);
+
+    push_option(&mut out, OPT_MESSAGE_TYPE, &[msg_type]);
+    push_option(&mut out, OPT_SERVER_ID, &GATEWAY_IP);
+
+    if msg_type != DHCPNAK {
+        if let Some(lease) = lease {
+            push_option(&mut out, OPT_LEASE_TIME, &lease.to_be_bytes());
+            // Renew halfway through the lease and rebind at 7/8, as RFC 2131
+            // suggests.
+            push_option(&mut out, OPT_RENEWAL_TIME, &(lease / 2).to_be_bytes());
+            push_option(&mut out, OPT_REBINDING_TIME, &(lease / 8 * 7).to_be_bytes());
+        }
+        push_option(&mut out, OPT_SUBNET_MASK, &SUBNET_MASK);
+        push_option(&mut out, OPT_ROUTER, &GATEWAY_IP);
+        // DNS queries to the gateway are answered by the forwarder.
+        push_option(&mut out, OPT_DNS, &GATEWAY_IP);
+        push_option(&mut out, OPT_HOSTNAME, config.hostname.as_bytes());
+        if let Some(domain) = &config.domain {
+            push_option(&mut out, OPT_DOMAIN_NAME, domain.as_bytes());
+        }
+        push_option(&mut out, OPT_MTU, &mtu.to_be_bytes());
+    }
+
+    out.push(OPT_END);
+    if out.len() < MIN_MESSAGE_LEN {
+        out.resize(MIN_MESSAGE_LEN, 0);
+    }
+    out
+}
+
+/// Appends option `code`, skipping empty values and ones too long for it.
+fn push_option(out: &mut Vec<u8>, code: u8, value: &[u8]) {
+    if value.is_empty() || value.len() > u8::MAX as usize {
+        return;
+    }
+    out.push(code);
+    out.push(value.len() as u8);
+    out.extend_from_slice(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIENT_MAC: [u8; 6] = [0x52, 0x54, 0, 0x12, 0x34, 0x56];
+
+    fn request(msg_type: u8, ciaddr: [u8; 4], options: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut msg = vec![0u8; 240];
+        msg[0] = 1;
+        msg[1] = 1;
+        msg[2] = 6;
+        msg[4..8].copy_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+        msg[12..16].copy_from_slice(&ciaddr);
+        msg[28..34].copy_from_slice(&CLIENT_MAC);
+        msg[236..240].copy_from_slice(&MAGIC_COOKIE);
+        push_option(&mut msg, OPT_MESSAGE_TYPE, &[msg_type]);
+        for (code, value) in options {
+            push_option(&mut msg, *code, value);
+        }
+        msg.push(OPT_END);
+        msg
+    }
+
+    /// The DHCP message of a response frame.
+    fn message(frame: &[u8]) -> &[u8] {
+        &frame[14 + 20 + 8..]
+    }
+
+    fn option(frame: &[u8], code: u8) -> Option<&[u8]> {
+        find_dhcp_option(&message(frame)[240..], code)
+    }
+
+    fn config() -> NetConfig {
+        NetConfig::new("web.example.com")
+    }
+
+    #[test]
+    fn test_net_config_splits_domain() {
+        assert_eq!(config().hostname, "web");
+        assert_eq!(config().domain.as_deref(), Some("example.com"));
+        assert_eq!(NetConfig::new("web").domain, None);
+        assert_eq!(NetConfig::new(".web").hostname, ".web");
+    }
+
+    #[test]
+    fn test_offer_configures_guest() {
+        let offer = handle_dhcp(&request(DHCPDISCOVER, [0; 4], &[]), 1500, &config()).unwrap();
+        assert_eq!(&offer[0..6], &[0xff; 6]);
+        assert_eq!(&message(&offer)[16..20], &GUEST_IP);
+        assert_eq!(option(&offer, OPT_MESSAGE_TYPE), Some(&[DHCPOFFER][..]));
+        assert_eq!(option(&offer, OPT_SUBNET_MASK), Some(&SUBNET_MASK[..]));
+        assert_eq!(option(&offer, OPT_ROUTER), Some(&GATEWAY_IP[..]));
+        assert_eq!(option(&offer, OPT_DNS), Some(&GATEWAY_IP[..]));
+        assert_eq!(option(&offer, OPT_HOSTNAME), Some(&b"web"[..]));
+        assert_eq!(option(&offer, OPT_DOMAIN_NAME), Some(&b"example.com"[..]));
+        assert_eq!(
+            option(&offer, OPT_LEASE_TIME),
+            Some(&DEFAULT_LEASE_SECS.to_be_bytes()[..])
+        );
+        assert_eq!(option(&offer, OPT_MTU), Some(&1500u16.to_be_bytes()[..]));
+        assert!(message(&offer).len() >= MIN_MESSAGE_LEN);
+    }
+
+    #[test]
+    fn test_requested_lease_is_bounded() {
+        let lease = |secs: u32| {
+            let req = request(
+                DHCPREQUEST,
+                [0; 4],
+                &[
+                    (OPT_REQUESTED_IP, &GUEST_IP),
+                    (OPT_LEASE_TIME, &secs.to_be_bytes()),
+                ],
+            );
+            let ack = handle_dhcp(&req, 1500, &config()).unwrap();
+            u32::from_be_bytes(option(&ack, OPT_LEASE_TIME).unwrap().try_into().unwrap())
+        };
+        assert_eq!(lease(3600), 3600);
+        assert_eq!(lease(1), MIN_LEASE_SECS);
+        assert_eq!(lease(u32::MAX), MAX_LEASE_SECS);
+    }
+
+    #[test]
+    fn test_renew_is_acknowledged_by_unicast() {
+        let ack = handle_dhcp(&request(DHCPREQUEST, GUEST_IP, &[]), 1500, &config()).unwrap();
+        assert_eq!(option(&ack, OPT_MESSAGE_TYPE), Some(&[DHCPACK][..]));
+        assert_eq!(&ack[0..6], &CLIENT_MAC);
+        assert_eq!(&ack[14 + 16..14 + 20], &GUEST_IP);
+        assert_eq!(&message(&ack)[16..20], &GUEST_IP);
+        assert_eq!(
+            option(&ack, OPT_RENEWAL_TIME),
+            Some(&(DEFAULT_LEASE_SECS / 2).to_be_bytes()[..])
+        );
+    }
+
+    #[test]
+    fn test_other_addresses_and_servers() {
+        let stale = request(DHCPREQUEST, [0; 4], &[(OPT_REQUESTED_IP, &[10, 0, 0, 5])]);
+        let nak = handle_dhcp(&stale, 1500, &config()).unwrap();
+        assert_eq!(option(&nak, OPT_MESSAGE_TYPE), Some(&[DHCPNAK][..]));
+        assert_eq!(&message(&nak)[16..20], &[0; 4]);
+        assert_eq!(option(&nak, OPT_ROUTER), None);
+
+        let other_server = request(
+            DHCPREQUEST,
+            [0; 4],
+            &[
+                (OPT_REQUESTED_IP, &GUEST_IP),
+                (OPT_SERVER_ID, &[10, 0, 0, 1]),
+            ],
+        );
+        assert!(handle_dhcp(&other_server, 1500, &config()).is_none());
+        assert!(handle_dhcp(&request(DHCPRELEASE, GUEST_IP, &[]), 1500, &config()).is_none());
+    }
+
+    #[test]
+    fn test_inform_gets_configuration_without_lease() {
+        let ack = handle_dhcp(&request(DHCPINFORM, GUEST_IP, &[]), 1500, &config()).unwrap();
+        assert_eq!(option(&ack, OPT_MESSAGE_TYPE), Some(&[DHCPACK][..]));
+        assert_eq!(&message(&ack)[16..20], &[0; 4]);
+        assert_eq!(option(&ack, OPT_LEASE_TIME), None);
+        assert_eq!(option(&ack, OPT_DNS), Some(&GATEWAY_IP[..]));
+    }
 }
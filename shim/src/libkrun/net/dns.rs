@@ -1,29 +1,64 @@
-//! DNS forwarding with special handling for ross.host.internal.
+//! DNS forwarding with special handling for ross.host.internal and the
+//! container's `--add-host` names, which are answered without asking
+//! upstream.
+//!
+//! Upstream answers are cached per (qname, qtype, qclass) until their TTL
+//! expires so repeated lookups from the guest don't hit the resolver.
 
 use super::eth::{build_eth_header, build_ip_header, tcp_udp_checksum, ETHERTYPE_IPV4, IP_PROTO_UDP};
-use super::{GATEWAY_IP, GATEWAY_MAC, HOST_IP};
-use std::net::{SocketAddr, UdpSocket};
-use std::time::Duration;
+use super::{GATEWAY_IP, GATEWAY_MAC, HOST_IP, NetConfig};
+use crate::resolv::ExtraHost;
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
 
 const ROSS_HOST_INTERNAL: &str = "ross.host.internal";
 const DEFAULT_DNS_SERVER: &str = "8.8.8.8:53";
 
+/// Maximum number of cached answers before the least recently used is evicted.
+const DNS_CACHE_CAPACITY: usize = 1024;
+/// TTL (seconds) for negative answers: NXDOMAIN and empty NOERROR responses.
+const NEGATIVE_TTL: u32 = 30;
+
+const DNS_FLAG_TC: u16 = 0x0200;
+const DNS_RCODE_NOERROR: u16 = 0;
+const DNS_RCODE_NXDOMAIN: u16 = 3;
+const DNS_TYPE_A: u16 = 1;
+const DNS_TYPE_AAAA: u16 = 28;
+const DNS_CLASS_IN: u16 = 1;
+/// EDNS0 OPT pseudo-record; its TTL field carries flags, not a TTL.
+const DNS_TYPE_OPT: u16 = 41;
+
 /// Persistent UDP socket for forwarding DNS queries.
 ///
 /// Creating/binding sockets per DNS packet is extremely expensive; keeping a single
 /// connected socket avoids repeated syscalls and kernel allocations.
 pub struct DnsForwarder {
     socket: UdpSocket,
+    cache: DnsCache,
 }
 
 impl DnsForwarder {
-    pub fn new() -> Option<Self> {
-        let dns_server: SocketAddr = DEFAULT_DNS_SERVER.parse().ok()?;
-        let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    /// Forwards to the first of `servers`, or to the default server when
+    /// there are none.
+    pub fn new(servers: &[IpAddr]) -> Option<Self> {
+        let dns_server: SocketAddr = match servers.first() {
+            Some(&server) => SocketAddr::new(server, 53),
+            None => DEFAULT_DNS_SERVER.parse().ok()?,
+        };
+        let local = if dns_server.is_ipv6() {
+            "[::]:0"
+        } else {
+            "0.0.0.0:0"
+        };
+        let socket = UdpSocket::bind(local).ok()?;
         // A connected UDP socket avoids specifying the destination on every send.
         socket.connect(dns_server).ok()?;
         socket.set_read_timeout(Some(Duration::from_secs(2))).ok()?;
-        Some(Self { socket })
+        Some(Self {
+            socket,
+            cache: DnsCache::new(DNS_CACHE_CAPACITY),
+        })
     }
 
     #[inline]
@@ -44,6 +79,7 @@ pub fn handle_dns(
     client_mac: &[u8],
     client_ip: &[u8],
     client_port: u16,
+    config: &NetConfig,
     forwarder: &mut Option<DnsForwarder>,
 ) -> Option<Vec<u8>> {
     if query.len() < 12 {
@@ -53,17 +89,27 @@ pub fn handle_dns(
     // Check if this is a query for ross.host.internal
     if is_query_for_ross_host_internal(query) {
         tracing::debug!(name = ROSS_HOST_INTERNAL, "Resolving special hostname to host IP");
-        if let Some(response) = build_dns_response(query, &HOST_IP) {
+        if let Some(response) = build_dns_response(query, &[IpAddr::from(HOST_IP)]) {
             return build_udp_response(client_mac, client_ip, client_port, 53, &response);
         }
     }
 
+    if let Some(response) = answer_extra_host(query, &config.extra_hosts) {
+        tracing::debug!("Resolving extra host locally");
+        return build_udp_response(client_mac, client_ip, client_port, 53, &response);
+    }
+
     // Forward to upstream DNS
     if forwarder.is_none() {
-        *forwarder = DnsForwarder::new();
+        *forwarder = DnsForwarder::new(&config.dns_servers);
+    }
+
+    let fwd = forwarder.as_mut()?;
+    if let Some(cached) = fwd.cache.get(query, Instant::now()) {
+        tracing::debug!(len = cached.len(), "DNS response (cached)");
+        return build_udp_response(client_mac, client_ip, client_port, 53, &cached);
     }
 
-    let fwd = forwarder.as_ref()?;
     if !fwd.send_query(query) {
         return None;
     }
@@ -73,9 +119,217 @@ pub fn handle_dns(
 
     tracing::debug!(len = response.len(), "DNS response");
 
+    fwd.cache.insert(query, response, Instant::now());
+
     build_udp_response(client_mac, client_ip, client_port, 53, response)
 }
 
+/// Cache key: lowercased wire-format QNAME plus QTYPE and QCLASS.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    qname: Vec<u8>,
+    qtype: u16,
+    qclass: u16,
+}
+
+struct CacheEntry {
+    response: Vec<u8>,
+    /// Offset of every TTL field in `response` and the TTL it held when stored.
+    ttls: Vec<(usize, u32)>,
+    stored_at: Instant,
+    expires_at: Instant,
+    /// Position in the LRU order; higher is more recently used.
+    tick: u64,
+}
+
+/// In-memory cache of upstream DNS responses with TTL expiry and LRU eviction.
+struct DnsCache {
+    entries: HashMap<CacheKey, CacheEntry>,
+    lru: BTreeMap<u64, CacheKey>,
+    next_tick: u64,
+    capacity: usize,
+}
+
+impl DnsCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            lru: BTreeMap::new(),
+            next_tick: 0,
+            capacity,
+        }
+    }
+
+    /// Return a cached response for `query`, rewritten with the query's
+    /// transaction ID and question and with TTLs decremented by the time
+    /// spent in the cache.
+    fn get(&mut self, query: &[u8], now: Instant) -> Option<Vec<u8>> {
+        let (key, question_end) = parse_question(query)?;
+        let entry = self.entries.get_mut(&key)?;
+
+        if now >= entry.expires_at {
+            let tick = entry.tick;
+            self.lru.remove(&tick);
+            self.entries.remove(&key);
+            return None;
+        }
+
+        self.lru.remove(&entry.tick);
+        entry.tick = self.next_tick;
+        self.next_tick += 1;
+
+        let elapsed = now.duration_since(entry.stored_at).as_secs();
+        let elapsed = u32::try_from(elapsed).unwrap_or(u32::MAX);
+
+        let mut response = entry.response.clone();
+        // Echo the query's ID and question so 0x20 case randomization still matches.
+        response[0..2].copy_from_slice(&query[0..2]);
+        response[12..question_end].copy_from_slice(&query[12..question_end]);
+        for &(offset, ttl) in &entry.ttls {
+            response[offset..offset + 4]
+                .copy_from_slice(&ttl.saturating_sub(elapsed).to_be_bytes());
+        }
+
+        self.lru.insert(entry.tick, key);
+        Some(response)
+    }
+
+    /// Store an upstream `response` to `query` if it is cacheable.
+    fn insert(&mut self, query: &[u8], response: &[u8], now: Instant) {
+        let Some((key, _)) = parse_question(query) else {
+            return;
+        };
+        // Guard against caching a late reply to an earlier query under this key.
+        let Some((response_key, question_end)) = parse_question(response) else {
+            return;
+        };
+        if response_key != key {
+            return;
+        }
+        let Some((ttls, ttl)) = cacheable_ttls(response, question_end) else {
+            return;
+        };
+        if ttl == 0 {
+            return;
+        }
+
+        if let Some(old) = self.entries.remove(&key) {
+            self.lru.remove(&old.tick);
+        }
+        while self.entries.len() >= self.capacity {
+            let Some((_, evicted)) = self.lru.pop_first() else {
+                break;
+            };
+            self.entries.remove(&evicted);
+        }
+
+        let tick = self.next_tick;
+        self.next_tick += 1;
+        self.lru.insert(tick, key.clone());
+        self.entries.insert(
+            key,
+            CacheEntry {
+                response: response.to_vec(),
+                ttls,
+                stored_at: now,
+                expires_at: now + Duration::from_secs(u64::from(ttl)),
+                tick,
+            },
+        );
+    }
+}
+
+/// Parse the single question of a DNS message into a cache key, returning
+/// it along with the offset just past the question section.
+fn parse_question(msg: &[u8]) -> Option<(CacheKey, usize)> {
+    if msg.len() < 12 || u16::from_be_bytes([msg[4], msg[5]]) != 1 {
+        return None;
+    }
+
+    let mut qname = Vec::new();
+    let mut pos = 12usize;
+    loop {
+        let len = *msg.get(pos)? as usize;
+        qname.push(len as u8);
+        pos += 1;
+
+        if len == 0 {
+            break;
+        }
+        if len & 0b1100_0000 != 0 {
+            return None;
+        }
+
+        let label = msg.get(pos..pos + len)?;
+        qname.extend(label.iter().map(u8::to_ascii_lowercase));
+        pos += len;
+    }
+
+    let fields = msg.get(pos..pos + 4)?;
+    let key = CacheKey {
+        qname,
+        qtype: u16::from_be_bytes([fields[0], fields[1]]),
+        qclass: u16::from_be_bytes([fields[2], fields[3]]),
+    };
+    Some((key, pos + 4))
+}
+
+/// Skip a possibly compressed domain name, returning the offset after it.
+fn skip_name(msg: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *msg.get(pos)? as usize;
+        if len == 0 {
+            return Some(pos + 1);
+        }
+        if len & 0b1100_0000 == 0b1100_0000 {
+            return (pos + 2 <= msg.len()).then_some(pos + 2);
+        }
+        pos += 1 + len;
+    }
+}
+
+/// Collect the TTL fields of every resource record in `response` and decide
+/// how long it may be cached. Returns `None` for uncacheable responses
+/// (truncated, server failures, malformed records).
+fn cacheable_ttls(response: &[u8], question_end: usize) -> Option<(Vec<(usize, u32)>, u32)> {
+    let flags = u16::from_be_bytes([response[2], response[3]]);
+    if flags & DNS_FLAG_TC != 0 {
+        return None;
+    }
+    let rcode = flags & 0x000F;
+    if rcode != DNS_RCODE_NOERROR && rcode != DNS_RCODE_NXDOMAIN {
+        return None;
+    }
+
+    let ancount = u16::from_be_bytes([response[6], response[7]]);
+    let nscount = u16::from_be_bytes([response[8], response[9]]);
+    let arcount = u16::from_be_bytes([response[10], response[11]]);
+
+    let mut ttls = Vec::new();
+    let mut pos = question_end;
+    for _ in 0..u32::from(ancount) + u32::from(nscount) + u32::from(arcount) {
+        pos = skip_name(response, pos)?;
+        let rr = response.get(pos..pos + 10)?;
+        let rtype = u16::from_be_bytes([rr[0], rr[1]]);
+        let ttl = u32::from_be_bytes([rr[4], rr[5], rr[6], rr[7]]);
+        let rdlength = u16::from_be_bytes([rr[8], rr[9]]) as usize;
+        if rtype != DNS_TYPE_OPT {
+            ttls.push((pos + 4, ttl));
+        }
+        pos += 10 + rdlength;
+        if pos > response.len() {
+            return None;
+        }
+    }
+
+    let ttl = if rcode == DNS_RCODE_NXDOMAIN || ancount == 0 {
+        NEGATIVE_TTL
+    } else {
+        ttls.iter().map(|&(_, ttl)| ttl).min()?
+    };
+    Some((ttls, ttl))
+}
+
 #[inline]
 fn eq_ascii_case_insensitive(a: &[u8], b: &[u8]) -> bool {
     if a.len() != b.len() {
@@ -126,8 +380,56 @@ fn is_query_for_ross_host_internal(query: &[u8]) -> bool {
     false
 }
 
-/// Build a DNS response for an A record query.
-fn build_dns_response(query: &[u8], ip: &[u8; 4]) -> Option<Vec<u8>> {
+/// Answers a query for one of `hosts` with its addresses of the queried
+/// family, or with no records for other record types. Queries for other
+/// names are left to upstream.
+fn answer_extra_host(query: &[u8], hosts: &[ExtraHost]) -> Option<Vec<u8>> {
+    if hosts.is_empty() {
+        return None;
+    }
+    let (key, _) = parse_question(query)?;
+    if key.qclass != DNS_CLASS_IN {
+        return None;
+    }
+    let name = qname_to_string(&key.qname)?;
+    let matching: Vec<IpAddr> = hosts
+        .iter()
+        .filter(|host| host.name.trim_end_matches('.').eq_ignore_ascii_case(&name))
+        .map(|host| host.ip)
+        .collect();
+    if matching.is_empty() {
+        return None;
+    }
+    let addresses: Vec<IpAddr> = matching
+        .into_iter()
+        .filter(|ip| match key.qtype {
+            DNS_TYPE_A => ip.is_ipv4(),
+            DNS_TYPE_AAAA => ip.is_ipv6(),
+            _ => false,
+        })
+        .collect();
+    build_dns_response(query, &addresses)
+}
+
+/// The dotted form of a wire-format QNAME, as `parse_question` keys it.
+fn qname_to_string(qname: &[u8]) -> Option<String> {
+    let mut labels = Vec::new();
+    let mut pos = 0usize;
+    loop {
+        let len = *qname.get(pos)? as usize;
+        if len == 0 {
+            break;
+        }
+        let label = qname.get(pos + 1..pos + 1 + len)?;
+        labels.push(std::str::from_utf8(label).ok()?);
+        pos += 1 + len;
+    }
+    Some(labels.join("."))
+}
+
+/// Build an authoritative DNS response answering `query` with `addresses`,
+/// as A or AAAA records by their family.
+fn build_dns_response(query: &[u8], addresses: &[IpAddr]) -> Option<Vec<u8>> {
     if query.len() < 12 {
         return None;
     }
@@ -144,7 +446,7 @@ fn build_dns_response(query: &[u8], ip: &[u8; 4]) -> Option<Vec<u8>> {
         return None;
     }
 
-    let mut response = Vec::with_capacity(query.len() + 16);
+    let mut response = Vec::with_capacity(query.len() + 28 * addresses.len());
 
     // Copy transaction ID
     response.extend_from_slice(&query[0..2]);
@@ -155,8 +457,8 @@ fn build_dns_response(query: &[u8], ip: &[u8; 4]) -> Option<Vec<u8>> {
 
     // QDCOUNT = 1
     response.extend_from_slice(&[0x00, 0x01]);
-    // ANCOUNT = 1
-    response.extend_from_slice(&[0x00, 0x01]);
+    // ANCOUNT
+    response.extend_from_slice(&(addresses.len() as u16).to_be_bytes());
     // NSCOUNT = 0
     response.extend_from_slice(&[0x00, 0x00]);
     // ARCOUNT = 0
@@ -165,18 +467,31 @@ fn build_dns_response(query: &[u8], ip: &[u8; 4]) -> Option<Vec<u8>> {
     // Copy question section
     response.extend_from_slice(&query[12..question_end]);
 
-    // Answer section - use pointer to name in question (0xC00C = offset 12)
-    response.extend_from_slice(&[0xC0, 0x0C]);
-    // TYPE = A (1)
-    response.extend_from_slice(&[0x00, 0x01]);
-    // CLASS = IN (1)
-    response.extend_from_slice(&[0x00, 0x01]);
-    // TTL = 60 seconds
-    response.extend_from_slice(&[0x00, 0x00, 0x00, 0x3C]);
-    // RDLENGTH = 4
-    response.extend_from_slice(&[0x00, 0x04]);
-    // RDATA = IP address
-    response.extend_from_slice(ip);
+    for address in addresses {
+        // Answer section - use pointer to name in question (0xC00C = offset 12)
+        response.extend_from_slice(&[0xC0, 0x0C]);
+        // TYPE, CLASS = IN
+        let rtype = if address.is_ipv4() {
+            DNS_TYPE_A
+        } else {
+            DNS_TYPE_AAAA
+        };
+        response.extend_from_slice(&rtype.to_be_bytes());
+        response.extend_from_slice(&DNS_CLASS_IN.to_be_bytes());
+        // TTL = 60 seconds
+        response.extend_from_slice(&[0x00, 0x00, 0x00, 0x3C]);
+        // RDLENGTH, RDATA = IP address
+        match address {
+            IpAddr::V4(ip) => {
+                response.extend_from_slice(&4u16.to_be_bytes());
+                response.extend_from_slice(&ip.octets());
+            }
+            IpAddr::V6(ip) => {
+                response.extend_from_slice(&16u16.to_be_bytes());
+                response.extend_from_slice(&ip.octets());
+            }
+        }
+    }
 
     Some(response)
 }
@@ -214,3 +529,132 @@ fn build_udp_response(
 
     Some(response)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(id: u16, name: &str, qtype: u16) -> Vec<u8> {
+        let mut msg = Vec::new();
+        msg.extend_from_slice(&id.to_be_bytes());
+        msg.extend_from_slice(&[0x01, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+        for label in name.split('.') {
+            msg.push(label.len() as u8);
+            msg.extend_from_slice(label.as_bytes());
+        }
+        msg.push(0);
+        msg.extend_from_slice(&qtype.to_be_bytes());
+        msg.extend_from_slice(&[0x00, 0x01]);
+        msg
+    }
+
+    fn answer(query: &[u8], ttl: u32) -> Vec<u8> {
+        let mut msg = query.to_vec();
+        msg[2..4].copy_from_slice(&[0x81, 0x80]);
+        msg[6..8].copy_from_slice(&[0x00, 0x01]);
+        msg.extend_from_slice(&[0xC0, 0x0C, 0x00, 0x01, 0x00, 0x01]);
+        msg.extend_from_slice(&ttl.to_be_bytes());
+        msg.extend_from_slice(&[0x00, 0x04, 10, 0, 0, 1]);
+        msg
+    }
+
+    fn response_ttl(response: &[u8]) -> u32 {
+        let offset = response.len() - 10;
+        u32::from_be_bytes(response[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_cache_serves_with_decremented_ttl() {
+        let mut cache = DnsCache::new(16);
+        let now = Instant::now();
+        let q = query(1, "example.com", 1);
+        cache.insert(&q, &answer(&q, 300), now);
+
+        let q2 = query(2, "EXAMPLE.com", 1);
+        let cached = cache.get(&q2, now + Duration::from_secs(100)).unwrap();
+        assert_eq!(&cached[0..2], &[0, 2]);
+        assert_eq!(&cached[12..q2.len()], &q2[12..]);
+        assert_eq!(response_ttl(&cached), 200);
+
+        assert!(cache.get(&query(3, "example.com", 28), now).is_none());
+        assert!(cache.get(&q, now + Duration::from_secs(300)).is_none());
+    }
+
+    #[test]
+    fn test_cache_negative_nxdomain() {
+        let mut cache = DnsCache::new(16);
+        let now = Instant::now();
+        let q = query(1, "missing.example", 1);
+        let mut nx = q.clone();
+        nx[2..4].copy_from_slice(&[0x81, 0x83]);
+        cache.insert(&q, &nx, now);
+
+        let secs = u64::from(NEGATIVE_TTL);
+        assert!(cache.get(&q, now + Duration::from_secs(secs - 1)).is_some());
+        assert!(cache.get(&q, now + Duration::from_secs(secs)).is_none());
+    }
+
+    #[test]
+    fn test_cache_skips_uncacheable() {
+        let mut cache = DnsCache::new(16);
+        let now = Instant::now();
+        let q = query(1, "example.com", 1);
+
+        let mut servfail = q.clone();
+        servfail[2..4].copy_from_slice(&[0x81, 0x82]);
+        cache.insert(&q, &servfail, now);
+        cache.insert(&q, &answer(&q, 0), now);
+        cache.insert(&q, &answer(&query(1, "other.com", 1), 60), now);
+        assert!(cache.get(&q, now).is_none());
+    }
+
+    fn extra_hosts() -> Vec<ExtraHost> {
+        ["db:10.0.0.5", "db:fd00::5", "cache:10.0.0.6"]
+            .iter()
+            .map(|host| host.parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_extra_host_answers() {
+        let q = query(7, "DB", DNS_TYPE_A);
+        let response = answer_extra_host(&q, &extra_hosts()).unwrap();
+        assert_eq!(&response[0..2], &[0, 7]);
+        assert_eq!(&response[6..8], &[0, 1]);
+        assert_eq!(&response[response.len() - 4..], &[10, 0, 0, 5]);
+
+        let q = query(8, "db", DNS_TYPE_AAAA);
+        let response = answer_extra_host(&q, &extra_hosts()).unwrap();
+        assert_eq!(&response[6..8], &[0, 1]);
+        assert_eq!(
+            &response[response.len() - 16..],
+            &"fd00::5".parse::<std::net::Ipv6Addr>().unwrap().octets()
+        );
+
+        // Known names have no records of other types, which is not an error.
+        let q = query(9, "cache", DNS_TYPE_AAAA);
+        let response = answer_extra_host(&q, &extra_hosts()).unwrap();
+        assert_eq!(&response[2..4], &[0x85, 0x80]);
+        assert_eq!(&response[6..8], &[0, 0]);
+
+        assert!(answer_extra_host(&query(10, "example.com", DNS_TYPE_A), &extra_hosts()).is_none());
+        assert!(answer_extra_host(&query(11, "db", DNS_TYPE_A), &[]).is_none());
+    }
+
+    #[test]
+    fn test_cache_evicts_least_recently_used() {
+        let mut cache = DnsCache::new(2);
+        let now = Instant::now();
+        let a = query(1, "a.com", 1);
+        let b = query(1, "b.com", 1);
+        let c = query(1, "c.com", 1);
+        cache.insert(&a, &answer(&a, 60), now);
+        cache.insert(&b, &answer(&b, 60), now);
+        assert!(cache.get(&a, now).is_some());
+        cache.insert(&c, &answer(&c, 60), now);
+
+        assert!(cache.get(&a, now).is_some());
+        assert!(cache.get(&b, now).is_none());
+        assert!(cache.get(&c, now).is_some());
+    }
+}
@@ -1,11 +1,15 @@
 //! Ethernet frame utilities.
 
+use super::GATEWAY_MAC;
+
 pub const ETHERTYPE_ARP: u16 = 0x0806;
 pub const ETHERTYPE_IPV4: u16 = 0x0800;
+pub const ETHERTYPE_IPV6: u16 = 0x86dd;
 
 pub const IP_PROTO_ICMP: u8 = 1;
 pub const IP_PROTO_TCP: u8 = 6;
 pub const IP_PROTO_UDP: u8 = 17;
+pub const IP_PROTO_ICMPV6: u8 = 58;
 
 /// Build an ethernet header.
 pub fn build_eth_header(dst: &[u8], src: &[u8], ethertype: u16) -> [u8; 14] {
@@ -21,14 +25,14 @@ pub fn checksum(data: &[u8]) -> u16 {
     finalize_checksum(sum_be_words(data))
 }
 
-/// Calculate TCP/UDP checksum with pseudo-header.
+/// Calculate TCP/UDP/ICMPv6 checksum with pseudo-header.
+///
+/// The addresses are either both IPv4 or both IPv6.
 pub fn tcp_udp_checksum(src_ip: &[u8], dst_ip: &[u8], proto: u8, data: &[u8]) -> u16 {
     // Pseudo-header
     let mut sum = 0u64;
-    sum += u16::from_be_bytes([src_ip[0], src_ip[1]]) as u64;
-    sum += u16::from_be_bytes([src_ip[2], src_ip[3]]) as u64;
-    sum += u16::from_be_bytes([dst_ip[0], dst_ip[1]]) as u64;
-    sum += u16::from_be_bytes([dst_ip[2], dst_ip[3]]) as u64;
+    sum += sum_be_words(src_ip);
+    sum += sum_be_words(dst_ip);
     sum += proto as u64;
     sum += data.len() as u64;
     sum += sum_be_words(data);
@@ -156,3 +160,127 @@ pub fn build_ip_header(
     hdr[10..12].copy_from_slice(&cksum.to_be_bytes());
     hdr
 }
+
+/// Split an IPv4 frame built by `build_ip_header` into fragments whose IP
+/// packets fit in `mtu` bytes, all carrying identification `id`.
+pub fn fragment_ipv4(frame: &[u8], mtu: usize, id: u16) -> Vec<Vec<u8>> {
+    let (headers, payload) = frame.split_at(14 + 20);
+    // Offsets are counted in 8-byte units, so every fragment but the last
+    // carries a multiple of 8 bytes.
+    let chunk = (mtu - 20) & !7;
+
+    let mut fragments = Vec::with_capacity(payload.len().div_ceil(chunk));
+    for (i, data) in payload.chunks(chunk).enumerate() {
+        let offset = i * chunk;
+        let more_fragments = offset + data.len() < payload.len();
+
+        let mut ip = build_ip_header(
+            &headers[26..30],
+            &headers[30..34],
+            headers[23],
+            data.len(),
+            id,
+        );
+        let flags = (offset / 8) as u16 | if more_fragments { 0x2000 } else { 0 };
+        ip[6..8].copy_from_slice(&flags.to_be_bytes());
+        ip[10..12].copy_from_slice(&[0, 0]);
+        let cksum = checksum(&ip);
+        ip[10..12].copy_from_slice(&cksum.to_be_bytes());
+
+        let mut fragment = Vec::with_capacity(14 + 20 + data.len());
+        fragment.extend_from_slice(&headers[..14]);
+        fragment.extend_from_slice(&ip);
+        fragment.extend_from_slice(data);
+        fragments.push(fragment);
+    }
+    fragments
+}
+
+/// Build an IPv6 header.
+pub fn build_ipv6_header(
+    src: &[u8],
+    dst: &[u8],
+    next_header: u8,
+    payload_len: usize,
+    hop_limit: u8,
+) -> [u8; 40] {
+    let mut hdr = [0u8; 40];
+    hdr[0] = 0x60; // version, no traffic class or flow label
+    hdr[4..6].copy_from_slice(&(payload_len as u16).to_be_bytes());
+    hdr[6] = next_header;
+    hdr[7] = hop_limit;
+    hdr[8..24].copy_from_slice(src);
+    hdr[24..40].copy_from_slice(dst);
+    hdr
+}
+
+/// Append ethernet and IP headers for a packet from the gateway.
+///
+/// The IP version follows the address length: 4 bytes for IPv4, 16 for IPv6.
+pub fn push_ip_headers(
+    out: &mut Vec<u8>,
+    dst_mac: &[u8],
+    src_ip: &[u8],
+    dst_ip: &[u8],
+    proto: u8,
+    payload_len: usize,
+) {
+    if src_ip.len() == 16 {
+        out.extend_from_slice(&build_eth_header(dst_mac, &GATEWAY_MAC, ETHERTYPE_IPV6));
+        out.extend_from_slice(&build_ipv6_header(src_ip, dst_ip, proto, payload_len, 64));
+    } else {
+        out.extend_from_slice(&build_eth_header(dst_mac, &GATEWAY_MAC, ETHERTYPE_IPV4));
+        out.extend_from_slice(&build_ip_header(src_ip, dst_ip, proto, payload_len, 0));
+    }
+}
+
+/// Length of the ethernet and IP headers `push_ip_headers` writes.
+#[inline]
+pub fn ip_headers_len(ip: &[u8]) -> usize {
+    if ip.len() == 16 { 14 + 40 } else { 14 + 20 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SRC: [u8; 4] = [93, 184, 216, 34];
+    const DST: [u8; 4] = [192, 168, 64, 2];
+
+    #[test]
+    fn test_fragment_ipv4() {
+        let payload: Vec<u8> = (0..3000u32).map(|i| i as u8).collect();
+        let mut frame = build_eth_header(&[2; 6], &GATEWAY_MAC, ETHERTYPE_IPV4).to_vec();
+        frame.extend_from_slice(&build_ip_header(&SRC, &DST, IP_PROTO_UDP, payload.len(), 0));
+        frame.extend_from_slice(&payload);
+
+        let mtu = 1400;
+        let fragments = fragment_ipv4(&frame, mtu, 0x1234);
+        assert_eq!(fragments.len(), 3);
+
+        let mut reassembled = Vec::new();
+        for (i, fragment) in fragments.iter().enumerate() {
+            let ip = &fragment[14..];
+            assert!(ip.len() <= mtu);
+            assert_eq!(&fragment[..14], &frame[..14]);
+            assert_eq!(u16::from_be_bytes([ip[2], ip[3]]) as usize, ip.len());
+            assert_eq!(u16::from_be_bytes([ip[4], ip[5]]), 0x1234);
+            assert_eq!(ip[9], IP_PROTO_UDP);
+            assert_eq!(&ip[12..16], &SRC);
+            assert_eq!(&ip[16..20], &DST);
+            assert_eq!(checksum(&ip[..20]), 0);
+
+            let flags = u16::from_be_bytes([ip[6], ip[7]]);
+            // DF is cleared, MF is set on all but the last fragment.
+            assert_eq!(flags & 0x4000, 0);
+            assert_eq!(flags & 0x2000 != 0, i < fragments.len() - 1);
+            // Offsets count 8-byte units.
+            assert_eq!((flags & 0x1fff) as usize * 8, reassembled.len());
+            if i < fragments.len() - 1 {
+                assert_eq!((ip.len() - 20) % 8, 0);
+            }
+            reassembled.extend_from_slice(&ip[20..]);
+        }
+        assert_eq!(reassembled, payload);
+    }
+}
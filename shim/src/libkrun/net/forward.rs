@@ -0,0 +1,161 @@
+//! Host port publishing.
+//!
+//! Binds a host listener for each published port and feeds accepted
+//! connections and received datagrams into the NAT as flows from the gateway
+//! to the guest.
+
+use super::eth::{IP_PROTO_TCP, IP_PROTO_UDP};
+use super::nat::NatState;
+use super::stack::shard_for_flow;
+use super::{GATEWAY_IP, GUEST_IP};
+use crate::{PortMapping, ShimError};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, UdpSocket};
+use std::sync::Arc;
+
+enum Listener {
+    Tcp {
+        listener: TcpListener,
+        guest_port: u16,
+    },
+    Udp {
+        socket: Arc<UdpSocket>,
+        guest_port: u16,
+    },
+}
+
+/// Host listeners for the ports published by one VM.
+///
+/// Shared by all network workers. Each worker accepts into its own `NatState`
+/// and picks a gateway port whose guest replies are dispatched back to it.
+/// The listeners are closed when the forwarder is dropped.
+pub struct PortForwarder {
+    listeners: Vec<Listener>,
+}
+
+impl PortForwarder {
+    pub fn bind(ports: &[PortMapping]) -> Result<Self, ShimError> {
+        let mut listeners = Vec::with_capacity(ports.len());
+
+        for port in ports {
+            let host_ip = match port.host_ip.as_deref() {
+                None | Some("") => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                Some(ip) => ip
+                    .parse()
+                    .map_err(|_| ShimError::RuntimeError(format!("invalid host ip: {}", ip)))?,
+            };
+            let addr = SocketAddr::new(host_ip, port.host_port);
+
+            let listener = match port.protocol.as_str() {
+                "tcp" => {
+                    let listener = TcpListener::bind(addr).map_err(|e| {
+                        ShimError::RuntimeError(format!("bind {}/tcp: {}", addr, e))
+                    })?;
+                    listener.set_nonblocking(true)?;
+                    Listener::Tcp {
+                        listener,
+                        guest_port: port.container_port,
+                    }
+                }
+                "udp" => {
+                    let socket = UdpSocket::bind(addr).map_err(|e| {
+                        ShimError::RuntimeError(format!("bind {}/udp: {}", addr, e))
+                    })?;
+                    socket.set_nonblocking(true)?;
+                    Listener::Udp {
+                        socket: Arc::new(socket),
+                        guest_port: port.container_port,
+                    }
+                }
+                other => {
+                    return Err(ShimError::NotSupported(format!("port protocol: {}", other)));
+                }
+            };
+
+            tracing::info!(
+                host = %addr,
+                container_port = port.container_port,
+                protocol = %port.protocol,
+                "Publishing port"
+            );
+            listeners.push(listener);
+        }
+
+        Ok(Self { listeners })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.listeners.is_empty()
+    }
+
+    /// Accepts pending host connections and datagrams for the worker `shard`
+    /// out of `workers`, appending the frames to send to the guest.
+    pub fn poll(
+        &self,
+        nat: &mut NatState,
+        shard: usize,
+        workers: usize,
+        buf: &mut [u8],
+        responses: &mut Vec<Vec<u8>>,
+    ) {
+        for listener in &self.listeners {
+            match listener {
+                Listener::Tcp {
+                    listener,
+                    guest_port,
+                } => {
+                    while let Ok((stream, peer)) = listener.accept() {
+                        let Some(gateway_port) = nat.alloc_inbound_port(*guest_port, |p| {
+                            owns_flow(IP_PROTO_TCP, *guest_port, p, shard, workers)
+                        }) else {
+                            tracing::warn!(%peer, "no free port to forward connection");
+                            continue;
+                        };
+
+                        tracing::debug!(%peer, guest_port, gateway_port, "Forwarding connection");
+                        if let Some(syn) = nat.open_inbound_tcp(stream, gateway_port, *guest_port) {
+                            responses.push(syn);
+                        }
+                    }
+                }
+                Listener::Udp { socket, guest_port } => {
+                    while let Ok((len, peer)) = socket.recv_from(buf) {
+                        let gateway_port = match nat.inbound_udp_port(&peer, *guest_port) {
+                            Some(port) => port,
+                            None => {
+                                let Some(port) = nat.alloc_inbound_port(*guest_port, |p| {
+                                    owns_flow(IP_PROTO_UDP, *guest_port, p, shard, workers)
+                                }) else {
+                                    tracing::warn!(%peer, "no free port to forward datagram");
+                                    continue;
+                                };
+                                port
+                            }
+                        };
+
+                        if let Some(frame) = nat.forward_inbound_udp(
+                            socket,
+                            peer,
+                            gateway_port,
+                            *guest_port,
+                            &buf[..len],
+                        ) {
+                            responses.push(frame);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Returns true if the guest's replies on this flow are dispatched to `shard`.
+fn owns_flow(proto: u8, guest_port: u16, gateway_port: u16, shard: usize, workers: usize) -> bool {
+    shard_for_flow(
+        proto,
+        &GUEST_IP,
+        &GATEWAY_IP,
+        guest_port,
+        gateway_port,
+        workers,
+    ) == shard
+}
@@ -0,0 +1,97 @@
+//! Unprivileged ICMP echo sockets.
+//!
+//! Uses `SOCK_DGRAM`/`IPPROTO_ICMP` ("ping") sockets, which macOS allows
+//! without root and Linux allows for groups in `net.ipv4.ping_group_range`.
+
+use std::io;
+use std::net::Ipv4Addr;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+/// A nonblocking ping socket connected to a single destination.
+pub struct PingSocket {
+    fd: OwnedFd,
+}
+
+impl PingSocket {
+    pub fn connect(dst: Ipv4Addr) -> io::Result<Self> {
+        let raw = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM, libc::IPPROTO_ICMP) };
+        if raw < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(raw) };
+
+        let flags = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETFL) };
+        if flags < 0
+            || unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut addr: libc::sockaddr_in = unsafe { std::mem::zeroed() };
+        addr.sin_family = libc::AF_INET as libc::sa_family_t;
+        addr.sin_addr.s_addr = u32::from_ne_bytes(dst.octets());
+        #[cfg(target_os = "macos")]
+        {
+            addr.sin_len = std::mem::size_of::<libc::sockaddr_in>() as u8;
+        }
+
+        let ret = unsafe {
+            libc::connect(
+                fd.as_raw_fd(),
+                &addr as *const libc::sockaddr_in as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self { fd })
+    }
+
+    /// Sends an ICMP message (header and payload, without the IP header).
+    pub fn send(&self, icmp: &[u8]) -> io::Result<()> {
+        let ret = unsafe {
+            libc::send(
+                self.fd.as_raw_fd(),
+                icmp.as_ptr() as *const libc::c_void,
+                icmp.len(),
+                0,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Receives an ICMP message into `buf`, returning its range in `buf` with
+    /// any IP header stripped (macOS delivers it, Linux does not).
+    pub fn recv(&self, buf: &mut [u8]) -> io::Result<std::ops::Range<usize>> {
+        let ret = unsafe {
+            libc::recv(
+                self.fd.as_raw_fd(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+                0,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let len = ret as usize;
+        let start = if len >= 20 && buf[0] >> 4 == 4 {
+            (buf[0] & 0x0f) as usize * 4
+        } else {
+            0
+        };
+        if start + 8 > len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "short ICMP message",
+            ));
+        }
+        Ok(start..len)
+    }
+}
@@ -1,16 +1,74 @@
 //! Userspace network stack for libkrun VMs.
 //!
-//! Provides NAT, DHCP, and DNS without external dependencies.
+//! Provides NAT, DHCP, and DNS without external dependencies. IPv6 guests
+//! configure themselves from router advertisements (SLAAC).
 
 mod arp;
 mod dhcp;
 mod dns;
 mod eth;
+mod forward;
+mod icmp;
 mod nat;
+mod ndp;
 mod ring_spsc;
 mod stack;
+mod stats;
 
-pub use stack::{VmNetwork, network_available};
+pub use stack::{DetachedLink, NetworkHandle, VmLink, VmNetwork, network_available};
+pub use stats::NetCounters;
+
+use crate::egress::EgressPolicy;
+use crate::resolv::{DnsConfig, ExtraHost};
+use std::net::IpAddr;
+
+/// What the guest is told about itself and its network besides its address.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NetConfig {
+    /// Host name of the guest, without its domain.
+    pub hostname: String,
+    /// Domain the guest's resolver searches.
+    pub domain: Option<String>,
+    /// Servers the DNS forwarder asks; empty for its default.
+    pub dns_servers: Vec<IpAddr>,
+    /// Names the DNS forwarder answers itself.
+    pub extra_hosts: Vec<ExtraHost>,
+    /// Destinations the NAT opens flows to.
+    pub egress: EgressPolicy,
+}
+
+impl NetConfig {
+    /// Splits `hostname`, when fully qualified, into the guest's host name
+    /// and domain.
+    pub fn new(hostname: &str) -> Self {
+        match hostname.split_once('.') {
+            Some((host, domain)) if !host.is_empty() && !domain.is_empty() => Self {
+                hostname: host.to_string(),
+                domain: Some(domain.to_string()),
+                ..Default::default()
+            },
+            _ => Self {
+                hostname: hostname.to_string(),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Resolves names with the container's `--dns` servers and `--add-host`
+    /// entries.
+    pub fn with_dns(mut self, dns: &DnsConfig) -> Self {
+        self.dns_servers = dns.nameservers.clone();
+        self.extra_hosts = dns.extra_hosts.clone();
+        self
+    }
+
+    /// Restricts the flows the NAT opens to the container's `--egress-allow`
+    /// and `--egress-deny` networks.
+    pub fn with_egress(mut self, egress: EgressPolicy) -> Self {
+        self.egress = egress;
+        self
+    }
+}
 
 /// Network constants.
 pub const GATEWAY_IP: [u8; 4] = [192, 168, 127, 1];
@@ -23,12 +81,35 @@ pub const DEFAULT_MAC: [u8; 6] = [0x02, 0x52, 0x4f, 0x53, 0x53, 0x00];
 /// When the guest connects to this IP, NAT translates it to 127.0.0.1 on the host.
 pub const HOST_IP: [u8; 4] = [192, 168, 127, 254];
 
+/// IPv6 /64 prefix advertised to the guest (a unique local address range).
+pub const IPV6_PREFIX: [u8; 16] = [
+    0xfd, 0x52, 0x4f, 0x53, 0x53, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+pub const IPV6_PREFIX_LEN: u8 = 64;
+pub const GATEWAY_IP6: [u8; 16] = [
+    0xfd, 0x52, 0x4f, 0x53, 0x53, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1,
+];
+/// Link-local address of the gateway, derived from `GATEWAY_MAC` (EUI-64).
+/// Router advertisements come from it, so it is the guest's default route.
+pub const GATEWAY_LINK_LOCAL: [u8; 16] = [
+    0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0x00, 0x52, 0x4f, 0xff, 0xfe, 0x53, 0x53, 0x01,
+];
+
+/// Link MTU used unless `ROSS_NET_MTU` or the VM's network options set one.
+pub const DEFAULT_MTU: u16 = 1500;
+/// Smallest link MTU accepted: the IPv6 minimum, below which IPv6 stops working.
+pub const MIN_MTU: u16 = 1280;
+/// Largest link MTU accepted (jumbo frames).
+pub const MAX_MTU: u16 = 9000;
+
 /// Network features for virtio-net device.
 pub const COMPAT_NET_FEATURES: u32 = (1 << 0)   // CSUM
     | (1 << 1)   // GUEST_CSUM
     | (1 << 7)   // GUEST_TSO4
+    | (1 << 8)   // GUEST_TSO6
     | (1 << 10)  // GUEST_UFO
     | (1 << 11)  // HOST_TSO4
+    | (1 << 12)  // HOST_TSO6
     | (1 << 14); // HOST_UFO
 
 /// Flag to send vfkit magic bytes.
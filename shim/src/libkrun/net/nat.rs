@@ -1,14 +1,22 @@
 //! NAT for TCP and UDP connections.
 
+use super::arp::ArpTable;
 use super::eth::{
     ETHERTYPE_IPV4, IP_PROTO_ICMP, IP_PROTO_TCP, IP_PROTO_UDP, build_eth_header, build_ip_header,
-    checksum, tcp_udp_checksum,
+    checksum, fragment_ipv4, ip_headers_len, push_ip_headers, tcp_udp_checksum,
 };
-use super::{GATEWAY_MAC, HOST_IP};
-use std::collections::HashMap;
+use super::icmp::PingSocket;
+use super::{GATEWAY_IP, GATEWAY_MAC, GUEST_IP, HOST_IP, MAX_MTU, NetConfig};
+use nix::errno::Errno;
+use nix::sys::socket::{AddressFamily, SockFlag, SockType, SockaddrStorage, connect, socket};
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap};
 use std::hash::{BuildHasherDefault, Hasher};
 use std::io::{Read, Write};
-use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream, UdpSocket};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpStream, UdpSocket};
+use std::ops::Deref;
+use std::os::unix::io::AsRawFd;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 /// Fast non-cryptographic hasher for internal NAT tables.
@@ -51,9 +59,9 @@ impl Hasher for FastHasher {
 
 type FastHashMap<K, V> = HashMap<K, V, BuildHasherDefault<FastHasher>>;
 
-// Max TCP payload for non-TSO frames (standard MTU).
-// Keep IP total length <= 1500 (typical MTU): 1500 - 20 (IP) - 20 (TCP) = 1460.
-const MAX_SEGMENT_SIZE: usize = 1460;
+// Max TCP payload for non-TSO frames at the largest supported MTU; the
+// actual MSS follows the configured MTU (see `max_segment_size`).
+const MAX_SEGMENT_SIZE: usize = MAX_MTU as usize - 20 - 20;
 
 // TSO (TCP Segmentation Offload) segment size.
 // With virtio-net TSO enabled (GUEST_TSO4), we can send much larger segments
@@ -70,20 +78,125 @@ const TCP_READ_BUFFER_SIZE: usize = 64 * 1024;
 // At 45 Gbits/sec, 16MB allows ~2.8ms of data in flight.
 const TCP_INFLIGHT_CAP: u32 = 16 * 1024 * 1024; // 16MiB
 const UDP_MAX_DATAGRAM: usize = 65535;
+// Out-of-order guest data held per connection, measured from the next expected
+// byte. Segments beyond it are dropped and left to the guest to retransmit.
+const TCP_REASSEMBLY_MAX: usize = 256 * 1024;
+/// Guest bytes waiting for a slow remote to accept them, above which the
+/// receive window advertised to the guest closes so that TCP flow control
+/// throttles it. At our window scale, a full window.
+const TCP_WRITE_HIGH_WATER: usize = (u16::MAX as usize) << OUR_WSCALE;
+/// Guest bytes still waiting below which a closed window reopens.
+const TCP_WRITE_LOW_WATER: usize = TCP_WRITE_HIGH_WATER / 4;
 const OUR_WSCALE: u8 = 7; // advertise 128x window scale to guest (~8MiB effective at 65535)
 
 // Socket buffer sizes for TCP connections - use large buffers for high throughput
 const TCP_SOCKET_SNDBUF: i32 = 16 * 1024 * 1024; // 16MB send buffer
 const TCP_SOCKET_RCVBUF: i32 = 16 * 1024 * 1024; // 16MB receive buffer
 
+// Gateway-side ports used as the source of connections forwarded from published host ports.
+const INBOUND_PORT_MIN: u16 = 49152;
+const INBOUND_PORT_MAX: u16 = 65535;
+// SYNs to the guest are retransmitted every second, up to this many times.
+const INBOUND_SYN_RETRIES: u8 = 5;
+// Guest SYNs whose host connection is not up by then are answered with a RST.
+const TCP_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+// Our initial sequence number on every connection.
+const INITIAL_SEQ: u32 = 1000;
+// A connection the guest closed last lingers this long, so that a FIN the
+// guest sends again because our ACK was lost is still acknowledged.
+const TCP_TIME_WAIT: Duration = Duration::from_secs(5);
+
+// Default cap on concurrent TCP connections, each holding a host socket.
+pub const DEFAULT_MAX_TCP_CONNECTIONS: usize = 4096;
+// Past this share of the cap (in percent), idle connections are evicted to make room.
+const TCP_EVICT_THRESHOLD_PERCENT: usize = 90;
+// Connections active more recently than this are never evicted.
+const TCP_EVICT_MIN_IDLE: Duration = Duration::from_secs(10);
+
+// Keepalive on host sockets: probes start after this much idle time and a
+// remote that misses enough of them fails the socket, which resets the guest.
+const HOST_KEEPALIVE_IDLE: Duration = Duration::from_secs(60);
+const HOST_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(10);
+const HOST_KEEPALIVE_PROBES: libc::c_int = 3;
+// A connection idle this long has the guest probed with keepalives, and is
+// dropped only once the guest leaves them all unanswered.
+const GUEST_KEEPALIVE_IDLE: Duration = Duration::from_secs(300);
+const GUEST_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(10);
+const GUEST_KEEPALIVE_PROBES: u8 = 3;
+
+// Default idle time after which a guest UDP flow's host socket is closed.
+pub const DEFAULT_UDP_TIMEOUT: Duration = Duration::from_secs(120);
+// DNS lookups are a single request and response, so their sockets are freed
+// much sooner.
+const UDP_DNS_TIMEOUT: Duration = Duration::from_secs(5);
+const DNS_PORT: u16 = 53;
+
+/// An IPv4 or IPv6 address as raw bytes, used in NAT keys and entries.
+///
+/// Derefs to the 4 or 16 address bytes so it can be passed straight to the
+/// packet builders.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct IpBytes {
+    len: u8,
+    bytes: [u8; 16],
+}
+
+impl IpBytes {
+    fn from_slice(ip: &[u8]) -> Self {
+        let mut bytes = [0u8; 16];
+        bytes[..ip.len()].copy_from_slice(ip);
+        Self {
+            len: ip.len() as u8,
+            bytes,
+        }
+    }
+
+    fn to_ip_addr(self) -> IpAddr {
+        if self.len == 16 {
+            IpAddr::V6(Ipv6Addr::from(self.bytes))
+        } else {
+            IpAddr::V4(Ipv4Addr::new(
+                self.bytes[0],
+                self.bytes[1],
+                self.bytes[2],
+                self.bytes[3],
+            ))
+        }
+    }
+
+    fn is_ipv6(&self) -> bool {
+        self.len == 16
+    }
+}
+
+impl From<[u8; 4]> for IpBytes {
+    fn from(ip: [u8; 4]) -> Self {
+        Self::from_slice(&ip)
+    }
+}
+
+impl Deref for IpBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+}
+
+impl std::fmt::Debug for IpBytes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_ip_addr().fmt(f)
+    }
+}
+
 /// Translate destination IP if it's the special host IP.
 /// Returns (actual_ip, original_ip) where actual_ip is what we connect to
 /// and original_ip is what we report back to the guest.
-fn translate_host_ip(dst_ip: &[u8]) -> ([u8; 4], [u8; 4]) {
-    let dst = [dst_ip[0], dst_ip[1], dst_ip[2], dst_ip[3]];
-    if dst == HOST_IP {
+fn translate_host_ip(dst_ip: &[u8]) -> (IpBytes, IpBytes) {
+    let dst = IpBytes::from_slice(dst_ip);
+    if dst_ip == HOST_IP {
         // Translate to localhost
-        ([127, 0, 0, 1], dst)
+        ([127, 0, 0, 1].into(), dst)
     } else {
         (dst, dst)
     }
@@ -93,9 +206,9 @@ fn translate_host_ip(dst_ip: &[u8]) -> ([u8; 4], [u8; 4]) {
 struct TcpNatEntry {
     stream: TcpStream,
     client_mac: [u8; 6],
-    client_ip: [u8; 4],
+    client_ip: IpBytes,
     client_port: u16,
-    remote_ip: [u8; 4],
+    remote_ip: IpBytes,
     remote_port: u16,
     /// Our sequence number (next byte we'll send)
     our_seq: u32,
@@ -111,6 +224,38 @@ struct TcpNatEntry {
     /// Pending data to write to the remote server
     write_buffer: Vec<u8>,
     write_offset: usize,
+    /// Set when the write buffer filled up, until it drains below
+    /// `TCP_WRITE_LOW_WATER`: the guest is told there is no room.
+    window_closed: bool,
+    /// Right edge of the receive window last advertised to the guest, the
+    /// sequence number up to which it may send. Advertising a window updates
+    /// it, which is why it is a `Cell`.
+    window_edge: Cell<u32>,
+    /// Inbound connection waiting for the guest's SYN-ACK, with the number of
+    /// SYNs sent so far.
+    syn_sent: Option<u8>,
+    /// Outbound connection whose host socket is still connecting, since when.
+    /// The guest's SYN is answered once it connects.
+    connecting: Option<Instant>,
+    /// Guest segments received ahead of `expected_guest_seq`.
+    reassembly: ReassemblyBuffer,
+    /// Payload bytes the guest sent on this connection.
+    bytes_from_guest: u64,
+    /// Payload bytes relayed to the guest on this connection.
+    bytes_to_guest: u64,
+    /// Keepalive probes sent to the guest since the connection went idle.
+    keepalive_probes: u8,
+    /// Set once the guest sent its FIN. The host socket stops sending once
+    /// the guest's data before it reached the remote.
+    guest_fin: bool,
+    /// Whether the write half of the host socket was shut down.
+    write_shut: bool,
+    /// Sequence number of our FIN, sent once the remote closed its end and
+    /// everything it sent before was relayed to the guest.
+    our_fin: Option<u32>,
+    /// Since when the connection is closed both ways, if the guest's FIN
+    /// came last and the entry lingers to acknowledge it again.
+    time_wait: Option<Instant>,
 }
 
 impl TcpNatEntry {
@@ -125,42 +270,544 @@ impl TcpNatEntry {
         let limit = guest_adv.min(TCP_INFLIGHT_CAP);
         unacked < limit
     }
+
+    /// A RST for the guest, in its window so that it tears the connection
+    /// down right away.
+    fn reset_packet(&self) -> Option<Vec<u8>> {
+        self.empty_segment(self.our_seq, 0x14)
+    }
+
+    /// Our SYN-ACK to the guest's SYN on an outbound connection.
+    fn syn_ack(&self, mtu: usize) -> Option<Vec<u8>> {
+        build_tcp_syn(
+            &self.client_mac,
+            &self.client_ip,
+            self.client_port,
+            self.remote_port,
+            &self.remote_ip,
+            INITIAL_SEQ,
+            0x12,
+            self.expected_guest_seq,
+            max_segment_size(mtu, &self.client_ip),
+        )
+    }
+
+    fn empty_segment(&self, seq: u32, flags: u8) -> Option<Vec<u8>> {
+        self.segment(seq, flags, &[])
+    }
+
+    /// A segment for the guest acknowledging everything it sent so far and
+    /// advertising our receive window.
+    fn segment(&self, seq: u32, flags: u8, data: &[u8]) -> Option<Vec<u8>> {
+        build_tcp_packet_with_options(
+            &self.client_mac,
+            &self.client_ip,
+            self.client_port,
+            self.remote_port,
+            &self.remote_ip,
+            seq,
+            self.expected_guest_seq,
+            flags,
+            self.receive_window(),
+            &[],
+            data,
+        )
+    }
+
+    /// Our FIN to the guest, sent once the remote closed its end. Nothing is
+    /// read from the remote after it, and the entry stays until both ends
+    /// closed.
+    fn fin(&mut self) -> Option<Vec<u8>> {
+        let fin = self.segment(self.our_seq, 0x11, &[]);
+        self.our_fin = Some(self.our_seq);
+        self.our_seq = self.our_seq.wrapping_add(1);
+        fin
+    }
+
+    /// Whether the guest acknowledged our FIN.
+    fn fin_acked(&self) -> bool {
+        self.our_fin
+            .is_some_and(|fin| seq_after(self.acked_seq, fin))
+    }
+
+    /// Passes the guest's FIN on to the remote by shutting down the write
+    /// half of the host socket, once the guest's data before it was written.
+    fn shutdown_write_if_flushed(&mut self) {
+        if self.guest_fin && !self.write_shut && self.buffered() == 0 {
+            if let Err(e) = self.stream.shutdown(Shutdown::Write) {
+                tracing::debug!(error = %e, "TCP shutdown failed");
+            }
+            self.write_shut = true;
+        }
+    }
+
+    /// Guest bytes not yet written to the remote.
+    fn buffered(&self) -> usize {
+        self.write_buffer.len().saturating_sub(self.write_offset)
+    }
+
+    /// The window to advertise to the guest, scaled by `OUR_WSCALE`: the
+    /// room left below the high-water mark, none while the window is closed.
+    ///
+    /// The scaled window is rounded down, so the room alone could move the
+    /// right edge back as the guest's data is acknowledged, which TCP does
+    /// not allow (RFC 7323, 2.4). What is left of the window advertised
+    /// before is kept, rounded up, and may overshoot the high-water mark by
+    /// less than one unit of scale.
+    fn receive_window(&self) -> u16 {
+        let room = if self.window_closed {
+            0
+        } else {
+            TCP_WRITE_HIGH_WATER.saturating_sub(self.buffered())
+        };
+        let edge = self.window_edge.get();
+        let promised = if seq_after(edge, self.expected_guest_seq) {
+            edge.wrapping_sub(self.expected_guest_seq) as usize
+        } else {
+            0
+        };
+        let window = (room >> OUR_WSCALE)
+            .max(promised.div_ceil(1 << OUR_WSCALE))
+            .min(u16::MAX as usize);
+
+        let new_edge = self
+            .expected_guest_seq
+            .wrapping_add((window << OUR_WSCALE) as u32);
+        if seq_after(new_edge, edge) {
+            self.window_edge.set(new_edge);
+        }
+        window as u16
+    }
+
+    /// Whether guest data ending at `end` fits in the window advertised.
+    fn in_window(&self, end: u32) -> bool {
+        !seq_after(end, self.window_edge.get())
+    }
+
+    /// Closes the window once the write buffer has no room left for the
+    /// guest, and reopens it once the buffer drained below the low-water
+    /// mark. Returns whether it reopened, which the guest must be told of
+    /// as it is not sending.
+    fn update_window(&mut self) -> bool {
+        if !self.window_closed {
+            self.window_closed = self.buffered() + (1 << OUR_WSCALE) > TCP_WRITE_HIGH_WATER;
+            false
+        } else if self.buffered() <= TCP_WRITE_LOW_WATER {
+            self.window_closed = false;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl Drop for TcpNatEntry {
+    fn drop(&mut self) {
+        tracing::debug!(
+            remote = ?self.remote_ip,
+            remote_port = self.remote_port,
+            guest_port = self.client_port,
+            bytes_from_guest = self.bytes_from_guest,
+            bytes_to_guest = self.bytes_to_guest,
+            "TCP flow closed"
+        );
+    }
+}
+
+/// Whether sequence number `a` comes after `b`, allowing for wraparound.
+#[inline]
+fn seq_after(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
+/// Out-of-order guest segments, keyed by sequence number.
+#[derive(Default)]
+struct ReassemblyBuffer {
+    segments: BTreeMap<u32, Vec<u8>>,
+    bytes: usize,
+}
+
+impl ReassemblyBuffer {
+    fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// Holds `data` received at `seq`, ahead of the next expected byte.
+    fn insert(&mut self, expected: u32, seq: u32, data: &[u8]) {
+        let offset = seq.wrapping_sub(expected) as usize;
+        if offset + data.len() > TCP_REASSEMBLY_MAX {
+            return;
+        }
+        if let Some(existing) = self.segments.get(&seq) {
+            if existing.len() >= data.len() {
+                return;
+            }
+            self.bytes -= existing.len();
+        }
+        if self.bytes + data.len() > TCP_REASSEMBLY_MAX {
+            self.segments.remove(&seq);
+            return;
+        }
+        self.bytes += data.len();
+        self.segments.insert(seq, data.to_vec());
+    }
+
+    /// Appends the buffered data that continues the stream at `expected` to
+    /// `out`, and returns the new expected sequence number.
+    fn drain_into(&mut self, mut expected: u32, out: &mut Vec<u8>) -> u32 {
+        // Keys are raw sequence numbers, so after wraparound the next segment
+        // is not necessarily the first one; the buffer is small enough to scan.
+        while let Some(seq) = self
+            .segments
+            .keys()
+            .copied()
+            .find(|&seq| !seq_after(seq, expected))
+        {
+            let data = self.segments.remove(&seq).unwrap();
+            self.bytes -= data.len();
+            let skip = expected.wrapping_sub(seq) as usize;
+            if skip < data.len() {
+                out.extend_from_slice(&data[skip..]);
+                expected = expected.wrapping_add((data.len() - skip) as u32);
+            }
+        }
+        expected
+    }
 }
 
 /// UDP NAT entry.
 struct UdpNatEntry {
     socket: UdpSocket,
     client_mac: [u8; 6],
-    client_ip: [u8; 4],
+    client_ip: IpBytes,
     client_port: u16,
     last_active: Instant,
+    /// Payload bytes the guest sent on this flow.
+    bytes_from_guest: u64,
+    /// Payload bytes relayed to the guest on this flow.
+    bytes_to_guest: u64,
+}
+
+impl Drop for UdpNatEntry {
+    fn drop(&mut self) {
+        tracing::debug!(
+            remote = ?self.socket.peer_addr().ok(),
+            guest_port = self.client_port,
+            bytes_from_guest = self.bytes_from_guest,
+            bytes_to_guest = self.bytes_to_guest,
+            "UDP flow closed"
+        );
+    }
+}
+
+/// Echo requests forwarded to a real destination.
+struct IcmpNatEntry {
+    socket: PingSocket,
+    client_mac: [u8; 6],
+    client_ip: IpBytes,
+    /// Echo identifier used by the guest; Linux replaces it with its own.
+    id: u16,
+    last_active: Instant,
+}
+
+/// UDP flow from a published host port into the guest.
+struct UdpInboundEntry {
+    /// The published socket; replies are sent from it so the peer sees the port it used.
+    socket: Arc<UdpSocket>,
+    peer: SocketAddr,
+    last_active: Instant,
 }
 
 /// NAT state.
 pub struct NatState {
-    tcp: FastHashMap<([u8; 4], u16, u16), TcpNatEntry>,
-    udp: FastHashMap<([u8; 4], u16, u16), UdpNatEntry>,
+    tcp: FastHashMap<(IpBytes, u16, u16), TcpNatEntry>,
+    udp: FastHashMap<(IpBytes, u16, u16), UdpNatEntry>,
+    /// Inbound UDP flows keyed by (gateway port, guest port).
+    udp_inbound: FastHashMap<(u16, u16), UdpInboundEntry>,
+    udp_inbound_peers: FastHashMap<(SocketAddr, u16), u16>,
+    next_inbound_port: u16,
+    /// Echo flows keyed by (destination IP as seen by the guest, echo id).
+    icmp: FastHashMap<(IpBytes, u16), IcmpNatEntry>,
+    /// Set once ping sockets turn out to be unavailable; echo requests are
+    /// then refused with an ICMP error instead of being forwarded.
+    icmp_unavailable: bool,
     // Reusable scratch buffers to avoid per-poll/per-packet stack allocations.
     udp_rx_buf: Vec<u8>,
     // Large read buffer to batch reads from host sockets
     tcp_rx_buf: Vec<u8>,
-    tcp_keys_scratch: Vec<([u8; 4], u16, u16)>,
+    tcp_keys_scratch: Vec<(IpBytes, u16, u16)>,
+    /// Upper bound on `tcp` entries.
+    max_tcp: usize,
+    /// Frames sent on the next poll: RSTs for evicted connections and the
+    /// trailing fragments of oversized datagrams.
+    pending: Vec<Vec<u8>>,
+    /// Link MTU of the guest interface.
+    mtu: usize,
+    /// Identification for the next fragmented IPv4 packet.
+    next_ip_id: u16,
+    /// Idle time after which UDP flows other than DNS are dropped.
+    udp_timeout: Duration,
+    /// Where the guest's MAC is looked up for flows it did not start.
+    arp: Arc<ArpTable>,
+    /// Host name and domain handed to the guest with its lease, and how its
+    /// DNS queries are answered.
+    config: Arc<NetConfig>,
 }
 
 impl NatState {
-    pub fn new() -> Self {
+    pub fn new(max_tcp: usize, mtu: u16) -> Self {
         Self {
             tcp: FastHashMap::default(),
             udp: FastHashMap::default(),
+            udp_inbound: FastHashMap::default(),
+            udp_inbound_peers: FastHashMap::default(),
+            next_inbound_port: INBOUND_PORT_MIN,
+            icmp: FastHashMap::default(),
+            icmp_unavailable: false,
             udp_rx_buf: vec![0u8; UDP_MAX_DATAGRAM],
             tcp_rx_buf: vec![0u8; TCP_READ_BUFFER_SIZE],
             tcp_keys_scratch: Vec::with_capacity(64),
+            max_tcp: max_tcp.max(1),
+            pending: Vec::new(),
+            mtu: mtu as usize,
+            next_ip_id: 0,
+            udp_timeout: DEFAULT_UDP_TIMEOUT,
+            arp: Arc::default(),
+            config: Arc::default(),
         }
     }
+
+    /// Sets the idle timeout for UDP flows. Flows to port 53 always use a
+    /// short timeout instead.
+    pub fn with_udp_timeout(mut self, timeout: Duration) -> Self {
+        self.udp_timeout = timeout;
+        self
+    }
+
+    /// Shares `arp` with the other workers of the stack.
+    pub fn with_arp_table(mut self, arp: Arc<ArpTable>) -> Self {
+        self.arp = arp;
+        self
+    }
+
+    pub fn arp(&self) -> &ArpTable {
+        &self.arp
+    }
+
+    /// Shares the stack's guest configuration.
+    pub fn with_net_config(mut self, config: Arc<NetConfig>) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn net_config(&self) -> &NetConfig {
+        &self.config
+    }
+
+    /// TCP connections currently tracked.
+    pub fn tcp_connections(&self) -> usize {
+        self.tcp.len()
+    }
+
+    /// Link MTU of the guest interface.
+    pub fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    /// Returns `frame` for immediate delivery, or its first fragment if it
+    /// exceeds the MTU, queueing the rest for the next poll.
+    fn fit_to_mtu(&mut self, frame: Vec<u8>) -> Option<Vec<u8>> {
+        let mut frames = Vec::new();
+        push_fragmented(&mut frames, frame, self.mtu, &mut self.next_ip_id);
+        let mut frames = frames.into_iter();
+        let first = frames.next();
+        self.pending.extend(frames);
+        first
+    }
+
+    /// Makes room for a new TCP connection, returning false if the table is
+    /// full of active connections.
+    ///
+    /// Near the limit the least recently active idle connections are evicted
+    /// and reset, so stale flows don't hold host sockets until the timeout
+    /// sweep.
+    fn reserve_tcp(&mut self) -> bool {
+        let threshold = (self.max_tcp * TCP_EVICT_THRESHOLD_PERCENT / 100).max(1);
+        let now = Instant::now();
+        while self.tcp.len() >= threshold {
+            let Some((&key, entry)) = self.tcp.iter().min_by_key(|(_, e)| e.last_active) else {
+                break;
+            };
+            if now.duration_since(entry.last_active) < TCP_EVICT_MIN_IDLE {
+                break;
+            }
+            tracing::debug!(
+                remote_port = entry.remote_port,
+                client_port = entry.client_port,
+                "Evicting idle TCP connection"
+            );
+            if let Some(rst) = entry.reset_packet() {
+                self.pending.push(rst);
+            }
+            self.tcp.remove(&key);
+        }
+        self.tcp.len() < self.max_tcp
+    }
+
+    /// Picks a free gateway port for a new inbound flow to `guest_port`.
+    ///
+    /// `accept` lets the caller restrict the choice, e.g. to ports whose guest
+    /// replies are dispatched to the calling worker.
+    pub fn alloc_inbound_port(
+        &mut self,
+        guest_port: u16,
+        accept: impl Fn(u16) -> bool,
+    ) -> Option<u16> {
+        let range = (INBOUND_PORT_MAX - INBOUND_PORT_MIN) as u32 + 1;
+        for _ in 0..range {
+            let port = self.next_inbound_port;
+            self.next_inbound_port = if port == INBOUND_PORT_MAX {
+                INBOUND_PORT_MIN
+            } else {
+                port + 1
+            };
+
+            if self
+                .tcp
+                .contains_key(&(IpBytes::from(GATEWAY_IP), port, guest_port))
+                || self.udp_inbound.contains_key(&(port, guest_port))
+            {
+                continue;
+            }
+            if accept(port) {
+                return Some(port);
+            }
+        }
+        None
+    }
+
+    /// Starts forwarding a host connection accepted on a published port to
+    /// `guest_port`, returning the SYN to send to the guest.
+    ///
+    /// The connection appears to the guest as coming from the gateway on
+    /// `gateway_port`; once the guest completes the handshake it is handled
+    /// like any other NAT entry.
+    pub fn open_inbound_tcp(
+        &mut self,
+        stream: TcpStream,
+        gateway_port: u16,
+        guest_port: u16,
+    ) -> Option<Vec<u8>> {
+        if !self.reserve_tcp() {
+            tracing::warn!(
+                guest_port,
+                "TCP connection limit reached, dropping connection"
+            );
+            return None;
+        }
+
+        stream.set_nonblocking(true).ok();
+        stream.set_nodelay(true).ok();
+        enable_keepalive(&stream);
+
+        let guest_mac = self.arp.guest_mac();
+        let our_seq = INITIAL_SEQ;
+        let syn = build_tcp_syn(
+            &guest_mac,
+            &GUEST_IP,
+            guest_port,
+            gateway_port,
+            &GATEWAY_IP,
+            our_seq,
+            0x02,
+            0,
+            max_segment_size(self.mtu, &GUEST_IP),
+        );
+
+        self.tcp.insert(
+            (GATEWAY_IP.into(), gateway_port, guest_port),
+            TcpNatEntry {
+                stream,
+                client_mac: guest_mac,
+                client_ip: GUEST_IP.into(),
+                client_port: guest_port,
+                remote_ip: GATEWAY_IP.into(),
+                remote_port: gateway_port,
+                our_seq: our_seq.wrapping_add(1),
+                acked_seq: our_seq,
+                expected_guest_seq: 0,
+                last_active: Instant::now(),
+                guest_window: 65535,
+                guest_wscale: 0,
+                write_buffer: Vec::with_capacity(64 * 1024),
+                write_offset: 0,
+                window_closed: false,
+                // Set once the guest's SYN-ACK gives its sequence number.
+                window_edge: Cell::new(0),
+                syn_sent: Some(1),
+                connecting: None,
+                reassembly: ReassemblyBuffer::default(),
+                bytes_from_guest: 0,
+                bytes_to_guest: 0,
+                keepalive_probes: 0,
+                guest_fin: false,
+                write_shut: false,
+                our_fin: None,
+                time_wait: None,
+            },
+        );
+
+        syn
+    }
+
+    /// Returns the gateway port of the inbound UDP flow from `peer` to
+    /// `guest_port`, if one exists.
+    pub fn inbound_udp_port(&self, peer: &SocketAddr, guest_port: u16) -> Option<u16> {
+        self.udp_inbound_peers.get(&(*peer, guest_port)).copied()
+    }
+
+    /// Forwards a datagram received on a published UDP port to `guest_port`.
+    pub fn forward_inbound_udp(
+        &mut self,
+        socket: &Arc<UdpSocket>,
+        peer: SocketAddr,
+        gateway_port: u16,
+        guest_port: u16,
+        data: &[u8],
+    ) -> Option<Vec<u8>> {
+        let entry = self
+            .udp_inbound
+            .entry((gateway_port, guest_port))
+            .or_insert_with(|| UdpInboundEntry {
+                socket: socket.clone(),
+                peer,
+                last_active: Instant::now(),
+            });
+        entry.last_active = Instant::now();
+        self.udp_inbound_peers
+            .insert((peer, guest_port), gateway_port);
+
+        let frame = build_udp_response(
+            &self.arp.guest_mac(),
+            &GUEST_IP,
+            guest_port,
+            gateway_port,
+            &GATEWAY_IP,
+            data,
+        )?;
+        self.fit_to_mtu(frame)
+    }
 }
 
 /// Handle ICMP packets.
+///
+/// Echo requests to the gateway are answered locally. Anything else is sent
+/// to the real destination and the genuine reply relayed by
+/// `poll_nat_sockets`, so ping from the guest reflects actual reachability.
+/// Without ping sockets on the host, the guest is told the destination is
+/// unreachable rather than given a reply no host sent.
 pub fn handle_icmp(
+    state: &mut NatState,
     payload: &[u8],
     src_mac: &[u8],
     src_ip: &[u8],
@@ -169,7 +816,50 @@ pub fn handle_icmp(
     if payload.len() < 8 || payload[0] != 8 {
         return None;
     }
-    build_icmp_reply(src_mac, src_ip, dst_ip, payload)
+    if dst_ip == GATEWAY_IP {
+        return build_icmp_reply(src_mac, src_ip, dst_ip, payload);
+    }
+    if state.icmp_unavailable {
+        return build_echo_prohibited(src_mac, src_ip, dst_ip, payload);
+    }
+
+    let (actual_ip, original_ip) = translate_host_ip(dst_ip);
+    if !state.config.egress.permits(original_ip.to_ip_addr()) {
+        return None;
+    }
+    let IpAddr::V4(actual_ip) = actual_ip.to_ip_addr() else {
+        return None;
+    };
+    let id = u16::from_be_bytes([payload[4], payload[5]]);
+
+    let entry = match state.icmp.entry((original_ip, id)) {
+        std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+        std::collections::hash_map::Entry::Vacant(vacant) => {
+            let socket = match PingSocket::connect(actual_ip) {
+                Ok(socket) => socket,
+                Err(e) => {
+                    tracing::warn!("ICMP sockets unavailable, refusing pings: {}", e);
+                    state.icmp_unavailable = true;
+                    return build_echo_prohibited(src_mac, src_ip, dst_ip, payload);
+                }
+            };
+            let mut client_mac = [0u8; 6];
+            client_mac.copy_from_slice(&src_mac[..6]);
+            vacant.insert(IcmpNatEntry {
+                socket,
+                client_mac,
+                client_ip: IpBytes::from_slice(src_ip),
+                id,
+                last_active: Instant::now(),
+            })
+        }
+    };
+
+    entry.last_active = Instant::now();
+    if let Err(e) = entry.socket.send(payload) {
+        tracing::debug!(dst = ?original_ip, "ICMP echo failed: {}", e);
+    }
+    None
 }
 
 fn build_icmp_reply(
@@ -178,27 +868,88 @@ fn build_icmp_reply(
     src_ip: &[u8],
     request: &[u8],
 ) -> Option<Vec<u8>> {
-    let icmp_len = request.len();
+    let mut reply = request.to_vec();
+    reply[0] = 0;
+    build_icmp_packet(dst_mac, dst_ip, src_ip, &mut reply)
+}
+
+/// Builds a frame carrying `icmp` to the guest, recomputing its checksum.
+fn build_icmp_packet(
+    dst_mac: &[u8],
+    dst_ip: &[u8],
+    src_ip: &[u8],
+    icmp: &mut [u8],
+) -> Option<Vec<u8>> {
+    let icmp_len = icmp.len();
     let total_len = 14 + 20 + icmp_len;
 
     let eth = build_eth_header(dst_mac, &GATEWAY_MAC, ETHERTYPE_IPV4);
     let ip = build_ip_header(src_ip, dst_ip, IP_PROTO_ICMP, icmp_len, 0);
 
+    icmp[2..4].copy_from_slice(&[0, 0]);
+    let cksum = checksum(icmp);
+    icmp[2..4].copy_from_slice(&cksum.to_be_bytes());
+
     let mut response = Vec::with_capacity(total_len);
     response.extend_from_slice(&eth);
     response.extend_from_slice(&ip);
-    response.extend_from_slice(request);
-
-    // Flip echo request -> reply, recompute checksum in-place.
-    let icmp_start = 14 + 20;
-    response[icmp_start] = 0;
-    response[icmp_start + 2..icmp_start + 4].copy_from_slice(&[0, 0]);
-    let cksum = checksum(&response[icmp_start..icmp_start + icmp_len]);
-    response[icmp_start + 2..icmp_start + 4].copy_from_slice(&cksum.to_be_bytes());
+    response.extend_from_slice(icmp);
 
     Some(response)
 }
 
+/// Builds the ICMP "communication administratively prohibited" error
+/// refusing the echo request `payload` the guest sent from `src_ip` to
+/// `dst_ip`.
+fn build_echo_prohibited(
+    src_mac: &[u8],
+    src_ip: &[u8],
+    dst_ip: &[u8],
+    payload: &[u8],
+) -> Option<Vec<u8>> {
+    let mut packet = build_ip_header(src_ip, dst_ip, IP_PROTO_ICMP, payload.len(), 0).to_vec();
+    packet.extend_from_slice(payload);
+    build_unreachable(src_mac, &packet, 13, 0)
+}
+
+/// Builds the ICMP "fragmentation needed" error telling the guest that
+/// `packet`, an IPv4 packet with DF set, does not fit in `mtu`.
+pub fn build_frag_needed(dst_mac: &[u8], packet: &[u8], mtu: usize) -> Option<Vec<u8>> {
+    build_unreachable(dst_mac, packet, 4, mtu as u16)
+}
+
+/// Builds an ICMP "destination unreachable" error with `code` about
+/// `packet`, an IPv4 packet from the guest.
+fn build_unreachable(
+    dst_mac: &[u8],
+    packet: &[u8],
+    code: u8,
+    next_hop_mtu: u16,
+) -> Option<Vec<u8>> {
+    let ihl = (packet[0] & 0x0f) as usize * 4;
+    // The error quotes the offending IP header and the first 8 payload bytes.
+    let quoted = &packet[..packet.len().min(ihl + 8)];
+
+    let mut icmp = Vec::with_capacity(8 + quoted.len());
+    icmp.extend_from_slice(&[3, code, 0, 0, 0, 0]);
+    icmp.extend_from_slice(&next_hop_mtu.to_be_bytes());
+    icmp.extend_from_slice(quoted);
+    build_icmp_packet(dst_mac, &packet[12..16], &GATEWAY_IP, &mut icmp)
+}
+
+/// Appends `frame` to `out`, split into fragments if it is an IPv4 packet
+/// larger than `mtu`.
+///
+/// IPv6 packets are left whole: only the source may fragment them.
+fn push_fragmented(out: &mut Vec<Vec<u8>>, frame: Vec<u8>, mtu: usize, next_id: &mut u16) {
+    if frame.len() - 14 <= mtu || frame[12..14] != ETHERTYPE_IPV4.to_be_bytes() {
+        out.push(frame);
+        return;
+    }
+    *next_id = next_id.wrapping_add(1);
+    out.extend(fragment_ipv4(&frame, mtu, *next_id));
+}
+
 /// Handle UDP packets.
 pub fn handle_udp(
     state: &mut NatState,
@@ -215,50 +966,76 @@ pub fn handle_udp(
     let dst_port = u16::from_be_bytes([payload[2], payload[3]]);
     let data = &payload[8..];
 
+    // Replies to a flow forwarded from a published host port.
+    if dst_ip == GATEWAY_IP
+        && let Some(entry) = state.udp_inbound.get_mut(&(dst_port, src_port))
+    {
+        entry.last_active = Instant::now();
+        let _ = entry.socket.send_to(data, entry.peer);
+        return None;
+    }
+
     // Translate HOST_IP to localhost
     let (actual_ip, original_ip) = translate_host_ip(dst_ip);
 
     // Key uses original IP so responses go back correctly
     let key = (original_ip, dst_port, src_port);
 
-    let entry = state.udp.entry(key).or_insert_with(|| {
-        let socket = UdpSocket::bind("0.0.0.0:0").expect("bind UDP");
-        socket.set_nonblocking(true).ok();
-        // Connect to actual IP (localhost for HOST_IP)
-        let dst = SocketAddr::new(
-            IpAddr::V4(Ipv4Addr::new(
-                actual_ip[0],
-                actual_ip[1],
-                actual_ip[2],
-                actual_ip[3],
-            )),
-            dst_port,
-        );
-        socket.connect(dst).ok();
-        UdpNatEntry {
-            socket,
-            client_mac: [
-                src_mac[0], src_mac[1], src_mac[2], src_mac[3], src_mac[4], src_mac[5],
-            ],
-            client_ip: [src_ip[0], src_ip[1], src_ip[2], src_ip[3]],
-            client_port: src_port,
-            last_active: Instant::now(),
+    let entry = match state.udp.entry(key) {
+        std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+        std::collections::hash_map::Entry::Vacant(vacant) => {
+            if !state.config.egress.permits(original_ip.to_ip_addr()) {
+                tracing::debug!(dst = ?original_ip, dst_port, "UDP flow denied by egress policy");
+                return None;
+            }
+            let bind_addr = if actual_ip.is_ipv6() {
+                "[::]:0"
+            } else {
+                "0.0.0.0:0"
+            };
+            let socket = match UdpSocket::bind(bind_addr) {
+                Ok(socket) => socket,
+                Err(e) => {
+                    tracing::debug!(dst = ?original_ip, "UDP bind failed: {}", e);
+                    return None;
+                }
+            };
+            socket.set_nonblocking(true).ok();
+            // Connect to actual IP (localhost for HOST_IP)
+            socket
+                .connect(SocketAddr::new(actual_ip.to_ip_addr(), dst_port))
+                .ok();
+            vacant.insert(UdpNatEntry {
+                socket,
+                client_mac: [
+                    src_mac[0], src_mac[1], src_mac[2], src_mac[3], src_mac[4], src_mac[5],
+                ],
+                client_ip: IpBytes::from_slice(src_ip),
+                client_port: src_port,
+                last_active: Instant::now(),
+                bytes_from_guest: 0,
+                bytes_to_guest: 0,
+            })
         }
-    });
+    };
 
     entry.last_active = Instant::now();
-    let _ = entry.socket.send(data);
+    if entry.socket.send(data).is_ok() {
+        entry.bytes_from_guest += data.len() as u64;
+    }
 
     if let Ok(len) = entry.socket.recv(&mut state.udp_rx_buf) {
+        entry.bytes_to_guest += len as u64;
         // Use original_ip in response so guest sees the IP it connected to
-        return build_udp_response(
+        let frame = build_udp_response(
             &entry.client_mac,
             &entry.client_ip,
             entry.client_port,
             dst_port,
             &original_ip,
             &state.udp_rx_buf[..len],
-        );
+        )?;
+        return state.fit_to_mtu(frame);
     }
     None
 }
@@ -272,12 +1049,17 @@ fn build_udp_response(
     data: &[u8],
 ) -> Option<Vec<u8>> {
     let udp_len = 8 + data.len();
-    let ip = build_ip_header(src_ip, dst_ip, IP_PROTO_UDP, udp_len, 0);
-    let eth = build_eth_header(dst_mac, &GATEWAY_MAC, ETHERTYPE_IPV4);
+    let udp_start = ip_headers_len(src_ip);
 
-    let mut response = Vec::with_capacity(14 + 20 + udp_len);
-    response.extend_from_slice(&eth);
-    response.extend_from_slice(&ip);
+    let mut response = Vec::with_capacity(udp_start + udp_len);
+    push_ip_headers(
+        &mut response,
+        dst_mac,
+        src_ip,
+        dst_ip,
+        IP_PROTO_UDP,
+        udp_len,
+    );
 
     // UDP header (checksum filled after payload copy).
     response.extend_from_slice(&src_port.to_be_bytes());
@@ -286,7 +1068,6 @@ fn build_udp_response(
     response.extend_from_slice(&[0, 0]);
     response.extend_from_slice(data);
 
-    let udp_start = 14 + 20;
     let udp_end = udp_start + udp_len;
     let cksum = tcp_udp_checksum(src_ip, dst_ip, IP_PROTO_UDP, &response[udp_start..udp_end]);
     response[udp_start + 6..udp_start + 8].copy_from_slice(&cksum.to_be_bytes());
@@ -324,11 +1105,12 @@ pub fn handle_tcp(
     } else {
         &[]
     };
-    let key = (
-        [dst_ip[0], dst_ip[1], dst_ip[2], dst_ip[3]],
-        dst_port,
-        src_port,
-    );
+    let opts = if data_offset > 20 && data_offset <= payload.len() {
+        &payload[20..data_offset]
+    } else {
+        &[]
+    };
+    let key = (IpBytes::from_slice(dst_ip), dst_port, src_port);
 
     if rst {
         state.tcp.remove(&key);
@@ -337,60 +1119,71 @@ pub fn handle_tcp(
 
     // SYN - new connection
     if syn && !ack_flag {
-        let opts = if data_offset > 20 && data_offset <= payload.len() {
-            &payload[20..data_offset]
-        } else {
-            &[]
-        };
         return handle_tcp_syn(
             state, key, src_mac, src_ip, dst_ip, src_port, dst_port, seq, opts,
         );
     }
 
     let entry = state.tcp.get_mut(&key)?;
+    // Nothing but the SYN is expected before our SYN-ACK.
+    if entry.connecting.is_some() {
+        return None;
+    }
     entry.last_active = Instant::now();
     // Track the guest advertised receive window (unscaled TCP header field).
     entry.guest_window = window.max(1024); // clamp away pathological 0/1 windows
 
+    // Inbound connection: wait for the guest's SYN-ACK, then ACK it.
+    if entry.syn_sent.is_some() {
+        if !(syn && ack_flag) || ack != entry.our_seq {
+            return None;
+        }
+        entry.syn_sent = None;
+        entry.acked_seq = ack;
+        entry.expected_guest_seq = seq.wrapping_add(1);
+        // Our SYN's window, which is never scaled.
+        entry
+            .window_edge
+            .set(entry.expected_guest_seq.wrapping_add(u16::MAX as u32));
+        entry.guest_wscale = parse_tcp_wscale(opts).unwrap_or(0).min(14);
+        return entry.segment(entry.our_seq, 0x10, &[]);
+    }
+
     // Update acked_seq from guest's ACK
-    if ack_flag && ack > entry.acked_seq {
+    if ack_flag && seq_after(ack, entry.acked_seq) {
         entry.acked_seq = ack;
     }
 
+    // The guest acknowledged our FIN after sending its own: both ends are
+    // closed, and the guest is the one to linger.
+    if entry.guest_fin && entry.fin_acked() && entry.time_wait.is_none() {
+        state.tcp.remove(&key);
+        return None;
+    }
+
     // Handle retransmit
-    if seq < entry.expected_guest_seq {
-        return build_tcp_packet(
-            &entry.client_mac,
-            &entry.client_ip,
-            entry.client_port,
-            entry.remote_port,
-            &entry.remote_ip,
-            entry.our_seq,
-            entry.expected_guest_seq,
-            0x10,
-            &[],
-        );
+    if seq_after(entry.expected_guest_seq, seq) {
+        return entry.segment(entry.our_seq, 0x10, &[]);
     }
 
-    // Out of order
-    if seq > entry.expected_guest_seq && !data.is_empty() {
-        return build_tcp_packet(
-            &entry.client_mac,
-            &entry.client_ip,
-            entry.client_port,
-            entry.remote_port,
-            &entry.remote_ip,
-            entry.our_seq,
-            entry.expected_guest_seq,
-            0x10,
-            &[],
-        );
+    // Out of order: hold on to the data until the gap is filled, and ACK the
+    // last in-order byte so the guest retransmits the missing segment.
+    if seq_after(seq, entry.expected_guest_seq) && !data.is_empty() {
+        entry.reassembly.insert(entry.expected_guest_seq, seq, data);
+        return entry.segment(entry.our_seq, 0x10, &[]);
+    }
+
+    // Data past the window we advertised: the remote is not keeping up, so
+    // drop it and repeat how much room there is until the buffer drains.
+    if !data.is_empty() && !entry.in_window(seq.wrapping_add(data.len() as u32)) {
+        return entry.segment(entry.our_seq, 0x10, &[]);
     }
 
     // Process data from guest.
     // Fast path: if we have no pending buffered data, try to write directly to the remote stream
     // to avoid an extra userspace copy into write_buffer.
     if !data.is_empty() {
+        let accepted_from = entry.expected_guest_seq;
         if entry.write_offset == 0 && entry.write_buffer.is_empty() {
             match entry.stream.write(data) {
                 Ok(0) => {
@@ -445,6 +1238,18 @@ pub fn handle_tcp(
             entry.write_buffer.extend_from_slice(data);
         }
         entry.expected_guest_seq = entry.expected_guest_seq.wrapping_add(data.len() as u32);
+
+        // The gap may now be filled; queue whatever follows for the flush below.
+        if !entry.reassembly.is_empty() {
+            if entry.write_offset >= entry.write_buffer.len() {
+                entry.write_buffer.clear();
+                entry.write_offset = 0;
+            }
+            entry.expected_guest_seq = entry
+                .reassembly
+                .drain_into(entry.expected_guest_seq, &mut entry.write_buffer);
+        }
+        entry.bytes_from_guest += entry.expected_guest_seq.wrapping_sub(accepted_from) as u64;
     }
 
     // Try to flush write buffer
@@ -480,6 +1285,7 @@ pub fn handle_tcp(
                     entry.write_buffer.clear();
                     entry.write_offset = 0;
                 }
+                entry.shutdown_write_if_flushed();
             }
             Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
                 // Can't write now, will retry later
@@ -503,74 +1309,39 @@ pub fn handle_tcp(
         }
     }
 
-    // FIN
-    if fin {
+    // Every segment below carries the window, reopened or not.
+    entry.update_window();
+
+    // FIN: the guest is done sending, but the remote may not be, so only
+    // this direction closes. What the remote still sends is relayed until
+    // it closes too. A FIN ahead of missing data waits for it.
+    if fin && seq.wrapping_add(data.len() as u32) == entry.expected_guest_seq {
         entry.expected_guest_seq = entry.expected_guest_seq.wrapping_add(1);
-        let resp = build_tcp_packet(
-            &entry.client_mac,
-            &entry.client_ip,
-            entry.client_port,
-            entry.remote_port,
-            &entry.remote_ip,
-            entry.our_seq,
-            entry.expected_guest_seq,
-            0x11,
-            &[],
-        );
-        state.tcp.remove(&key);
-        return resp;
+        entry.guest_fin = true;
+        entry.shutdown_write_if_flushed();
+        if entry.fin_acked() {
+            entry.time_wait = Some(Instant::now());
+        }
+        return entry.segment(entry.our_seq, 0x10, &[]);
     }
 
     // Try to send data to guest if we have window space
-    // Read up to MAX_SEGMENT_SIZE here since we can only return one packet.
+    // Read up to one MSS here since we can only return one packet.
     // The bulk of data transfer happens in poll_nat_sockets with batch reads.
-    if entry.can_send() {
+    if entry.our_fin.is_none() && entry.can_send() {
         // Use a stack buffer for quick inline reads (avoid indexing the large heap buffer)
         let mut quick_buf = [0u8; MAX_SEGMENT_SIZE];
-        match entry.stream.read(&mut quick_buf) {
-            Ok(0) => {
-                let resp = build_tcp_packet(
-                    &entry.client_mac,
-                    &entry.client_ip,
-                    entry.client_port,
-                    entry.remote_port,
-                    &entry.remote_ip,
-                    entry.our_seq,
-                    entry.expected_guest_seq,
-                    0x11,
-                    &[],
-                );
-                state.tcp.remove(&key);
-                return resp;
-            }
+        let mss = max_segment_size(state.mtu, &entry.client_ip);
+        match entry.stream.read(&mut quick_buf[..mss]) {
+            Ok(0) => return entry.fin(),
             Ok(len) => {
-                let resp = build_tcp_packet(
-                    &entry.client_mac,
-                    &entry.client_ip,
-                    entry.client_port,
-                    entry.remote_port,
-                    &entry.remote_ip,
-                    entry.our_seq,
-                    entry.expected_guest_seq,
-                    0x18,
-                    &quick_buf[..len],
-                );
+                let resp = entry.segment(entry.our_seq, 0x18, &quick_buf[..len]);
                 entry.our_seq = entry.our_seq.wrapping_add(len as u32);
                 return resp;
             }
             Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
                 if !data.is_empty() || ack_flag {
-                    return build_tcp_packet(
-                        &entry.client_mac,
-                        &entry.client_ip,
-                        entry.client_port,
-                        entry.remote_port,
-                        &entry.remote_ip,
-                        entry.our_seq,
-                        entry.expected_guest_seq,
-                        0x10,
-                        &[],
-                    );
+                    return entry.segment(entry.our_seq, 0x10, &[]);
                 }
             }
             Err(e) => {
@@ -592,17 +1363,7 @@ pub fn handle_tcp(
         }
     } else if !data.is_empty() {
         // ACK guest data
-        return build_tcp_packet(
-            &entry.client_mac,
-            &entry.client_ip,
-            entry.client_port,
-            entry.remote_port,
-            &entry.remote_ip,
-            entry.our_seq,
-            entry.expected_guest_seq,
-            0x10,
-            &[],
-        );
+        return entry.segment(entry.our_seq, 0x10, &[]);
     }
 
     None
@@ -610,7 +1371,7 @@ pub fn handle_tcp(
 
 fn handle_tcp_syn(
     state: &mut NatState,
-    key: ([u8; 4], u16, u16),
+    key: (IpBytes, u16, u16),
     src_mac: &[u8],
     src_ip: &[u8],
     dst_ip: &[u8],
@@ -619,49 +1380,63 @@ fn handle_tcp_syn(
     seq: u32,
     syn_options: &[u8],
 ) -> Option<Vec<u8>> {
+    // A retransmitted SYN: wait for the connect still in progress, or send
+    // the SYN-ACK again if ours was lost.
+    if let Some(entry) = state.tcp.get(&key)
+        && entry.syn_sent.is_none()
+        && entry.expected_guest_seq == seq.wrapping_add(1)
+        && entry.acked_seq == INITIAL_SEQ
+    {
+        if entry.connecting.is_some() {
+            return None;
+        }
+        return entry.syn_ack(state.mtu);
+    }
+
     let guest_wscale = parse_tcp_wscale(syn_options).unwrap_or(0).min(14);
     // Translate HOST_IP to localhost
     let (actual_ip, original_ip) = translate_host_ip(dst_ip);
 
-    let dst = SocketAddr::new(
-        IpAddr::V4(Ipv4Addr::new(
-            actual_ip[0],
-            actual_ip[1],
-            actual_ip[2],
-            actual_ip[3],
-        )),
-        dst_port,
-    );
+    // Refuse connections the container's egress policy does not allow, as a
+    // firewall rejecting them would.
+    if !state.config.egress.permits(original_ip.to_ip_addr()) {
+        tracing::debug!(dst = ?original_ip, dst_port, "TCP connection denied by egress policy");
+        return build_tcp_packet(
+            src_mac,
+            src_ip,
+            src_port,
+            dst_port,
+            &original_ip,
+            0,
+            seq.wrapping_add(1),
+            0x14,
+            &[],
+        );
+    }
 
-    match TcpStream::connect_timeout(&dst, Duration::from_secs(10)) {
-        Ok(stream) => {
-            stream.set_nonblocking(true).ok();
-            stream.set_nodelay(true).ok();
-            // Increase socket buffers for better throughput
-            unsafe {
-                use std::os::unix::io::AsRawFd;
-                let fd = stream.as_raw_fd();
-                // Large send buffer to avoid backpressure stalls
-                libc::setsockopt(
-                    fd,
-                    libc::SOL_SOCKET,
-                    libc::SO_SNDBUF,
-                    &TCP_SOCKET_SNDBUF as *const _ as *const libc::c_void,
-                    std::mem::size_of::<libc::c_int>() as libc::socklen_t,
-                );
-                // Large receive buffer to absorb bursts from remote server
-                libc::setsockopt(
-                    fd,
-                    libc::SOL_SOCKET,
-                    libc::SO_RCVBUF,
-                    &TCP_SOCKET_RCVBUF as *const _ as *const libc::c_void,
-                    std::mem::size_of::<libc::c_int>() as libc::socklen_t,
-                );
-            }
+    // Refuse rather than connect when the guest already holds too many
+    // connections, so it cannot exhaust the host's file descriptors.
+    if !state.tcp.contains_key(&key) && !state.reserve_tcp() {
+        tracing::warn!(dst_port, "TCP connection limit reached, resetting SYN");
+        return build_tcp_packet(
+            src_mac,
+            src_ip,
+            src_port,
+            dst_port,
+            &original_ip,
+            0,
+            seq.wrapping_add(1),
+            0x14,
+            &[],
+        );
+    }
 
-            let our_seq = 1000u32;
-            let expected_guest_seq = seq.wrapping_add(1);
+    let dst = SocketAddr::new(actual_ip.to_ip_addr(), dst_port);
 
+    // The connection is started here and completed by `poll_nat_sockets`,
+    // which sends the SYN-ACK once the host socket is connected.
+    match connect_nonblocking(dst) {
+        Ok(stream) => {
             state.tcp.insert(
                 key,
                 TcpNatEntry {
@@ -669,32 +1444,35 @@ fn handle_tcp_syn(
                     client_mac: [
                         src_mac[0], src_mac[1], src_mac[2], src_mac[3], src_mac[4], src_mac[5],
                     ],
-                    client_ip: [src_ip[0], src_ip[1], src_ip[2], src_ip[3]],
+                    client_ip: IpBytes::from_slice(src_ip),
                     client_port: src_port,
                     // Store original_ip so responses go back with the IP guest expects
                     remote_ip: original_ip,
                     remote_port: dst_port,
-                    our_seq: our_seq.wrapping_add(1),
-                    acked_seq: our_seq, // Guest hasn't ACKed anything yet
-                    expected_guest_seq,
+                    our_seq: INITIAL_SEQ.wrapping_add(1),
+                    acked_seq: INITIAL_SEQ, // Guest hasn't ACKed anything yet
+                    expected_guest_seq: seq.wrapping_add(1),
                     last_active: Instant::now(),
                     guest_window: 65535,
                     guest_wscale,
                     write_buffer: Vec::with_capacity(64 * 1024), // Pre-allocate for perf
                     write_offset: 0,
+                    window_closed: false,
+                    // Our SYN-ACK's window, which is never scaled.
+                    window_edge: Cell::new(seq.wrapping_add(1).wrapping_add(u16::MAX as u32)),
+                    syn_sent: None,
+                    connecting: Some(Instant::now()),
+                    reassembly: ReassemblyBuffer::default(),
+                    bytes_from_guest: 0,
+                    bytes_to_guest: 0,
+                    keepalive_probes: 0,
+                    guest_fin: false,
+                    write_shut: false,
+                    our_fin: None,
+                    time_wait: None,
                 },
             );
-
-            build_tcp_synack(
-                src_mac,
-                src_ip,
-                src_port,
-                dst_port,
-                &original_ip,
-                our_seq,
-                expected_guest_seq,
-                OUR_WSCALE,
-            )
+            None
         }
         Err(e) => {
             tracing::debug!(error = %e, "TCP connect failed");
@@ -713,27 +1491,86 @@ fn handle_tcp_syn(
     }
 }
 
-fn build_tcp_synack(
+/// Starts connecting a host socket to `dst` without waiting for the
+/// handshake; `connect_result` tells when it is done.
+fn connect_nonblocking(dst: SocketAddr) -> std::io::Result<TcpStream> {
+    let family = match dst {
+        SocketAddr::V4(_) => AddressFamily::Inet,
+        SocketAddr::V6(_) => AddressFamily::Inet6,
+    };
+    let stream = TcpStream::from(socket(family, SockType::Stream, SockFlag::empty(), None)?);
+    stream.set_nonblocking(true)?;
+    stream.set_nodelay(true).ok();
+    enable_keepalive(&stream);
+    // Increase socket buffers for better throughput
+    unsafe {
+        let fd = stream.as_raw_fd();
+        // Large send buffer to avoid backpressure stalls
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_SNDBUF,
+            &TCP_SOCKET_SNDBUF as *const _ as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        );
+        // Large receive buffer to absorb bursts from remote server
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_RCVBUF,
+            &TCP_SOCKET_RCVBUF as *const _ as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        );
+    }
+    match connect(stream.as_raw_fd(), &SockaddrStorage::from(dst)) {
+        Ok(()) | Err(Errno::EINPROGRESS) => Ok(stream),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Whether a socket from `connect_nonblocking` is connected yet, or the error
+/// its connect failed with.
+fn connect_result(stream: &TcpStream) -> std::io::Result<bool> {
+    if let Some(e) = stream.take_error()? {
+        return Err(e);
+    }
+    match stream.peer_addr() {
+        Ok(_) => Ok(true),
+        Err(e) if e.raw_os_error() == Some(libc::ENOTCONN) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// TCP payload that fits in one `mtu`-sized packet to `ip`; advertised to the
+/// guest as our MSS.
+fn max_segment_size(mtu: usize, ip: &[u8]) -> usize {
+    let ip_header = if ip.len() == 16 { 40 } else { 20 };
+    mtu - ip_header - 20
+}
+
+/// Builds a SYN (`flags` 0x02) or SYN-ACK (0x12) carrying our MSS and window scale.
+fn build_tcp_syn(
     dst_mac: &[u8],
     dst_ip: &[u8],
     dst_port: u16,
     src_port: u16,
     src_ip: &[u8],
     seq: u32,
+    flags: u8,
     ack: u32,
-    our_wscale: u8,
+    mss: usize,
 ) -> Option<Vec<u8>> {
     // TCP options: MSS (4) + WS (4 incl NOP padding) = 8 bytes.
     let mut opts = [0u8; 8];
     // MSS
     opts[0] = 2;
     opts[1] = 4;
-    opts[2..4].copy_from_slice(&(MAX_SEGMENT_SIZE as u16).to_be_bytes());
+    opts[2..4].copy_from_slice(&(mss as u16).to_be_bytes());
     // NOP + WS
     opts[4] = 1;
     opts[5] = 3;
     opts[6] = 3;
-    opts[7] = our_wscale;
+    opts[7] = OUR_WSCALE;
 
     build_tcp_packet_with_options(
         dst_mac,
@@ -743,7 +1580,8 @@ fn build_tcp_synack(
         src_ip,
         seq,
         ack,
-        0x12,
+        flags,
+        u16::MAX,
         &opts,
         &[],
     )
@@ -769,6 +1607,7 @@ fn build_tcp_packet(
         seq,
         ack,
         flags,
+        u16::MAX,
         &[],
         data,
     )
@@ -786,36 +1625,24 @@ fn build_tcp_packet_tso(
     seq: u32,
     ack: u32,
     flags: u8,
+    window: u16,
     data: &[u8],
 ) -> Option<Vec<u8>> {
     let tcp_len = 20 + data.len();
-    let total_len = 14 + 20 + tcp_len;
+    let total_len = ip_headers_len(src_ip) + tcp_len;
 
     // Pre-allocate exact size to avoid reallocation
     let mut response = Vec::with_capacity(total_len);
 
-    // Ethernet header (14 bytes)
-    response.extend_from_slice(dst_mac);
-    response.extend_from_slice(&GATEWAY_MAC);
-    response.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
-
-    // IP header (20 bytes)
-    let ip_total_len = (20 + tcp_len) as u16;
-    response.push(0x45); // version + IHL
-    response.push(0); // DSCP + ECN
-    response.extend_from_slice(&ip_total_len.to_be_bytes());
-    response.extend_from_slice(&[0, 0]); // ID
-    response.extend_from_slice(&[0x40, 0]); // Don't fragment
-    response.push(64); // TTL
-    response.push(IP_PROTO_TCP);
-    let ip_cksum_pos = response.len();
-    response.extend_from_slice(&[0, 0]); // checksum placeholder
-    response.extend_from_slice(src_ip);
-    response.extend_from_slice(dst_ip);
-
-    // IP checksum
-    let ip_cksum = checksum(&response[14..34]);
-    response[ip_cksum_pos..ip_cksum_pos + 2].copy_from_slice(&ip_cksum.to_be_bytes());
+    // Ethernet and IP headers
+    push_ip_headers(
+        &mut response,
+        dst_mac,
+        src_ip,
+        dst_ip,
+        IP_PROTO_TCP,
+        tcp_len,
+    );
 
     // TCP header (20 bytes)
     let tcp_start = response.len();
@@ -825,7 +1652,7 @@ fn build_tcp_packet_tso(
     response.extend_from_slice(&ack.to_be_bytes());
     response.push(5 << 4); // data offset (5 words = 20 bytes)
     response.push(flags);
-    response.extend_from_slice(&(u16::MAX).to_be_bytes()); // window
+    response.extend_from_slice(&window.to_be_bytes());
     let tcp_cksum_pos = response.len();
     response.extend_from_slice(&[0, 0]); // checksum placeholder
     response.extend_from_slice(&[0, 0]); // urgent pointer
@@ -849,17 +1676,23 @@ fn build_tcp_packet_with_options(
     seq: u32,
     ack: u32,
     flags: u8,
+    window: u16,
     options: &[u8],
     data: &[u8],
 ) -> Option<Vec<u8>> {
     debug_assert!(options.len() % 4 == 0);
     let tcp_len = 20 + options.len() + data.len();
-    let ip = build_ip_header(src_ip, dst_ip, IP_PROTO_TCP, tcp_len, 0);
-    let eth = build_eth_header(dst_mac, &GATEWAY_MAC, ETHERTYPE_IPV4);
+    let tcp_start = ip_headers_len(src_ip);
 
-    let mut response = Vec::with_capacity(14 + 20 + tcp_len);
-    response.extend_from_slice(&eth);
-    response.extend_from_slice(&ip);
+    let mut response = Vec::with_capacity(tcp_start + tcp_len);
+    push_ip_headers(
+        &mut response,
+        dst_mac,
+        src_ip,
+        dst_ip,
+        IP_PROTO_TCP,
+        tcp_len,
+    );
 
     response.extend_from_slice(&src_port.to_be_bytes());
     response.extend_from_slice(&dst_port.to_be_bytes());
@@ -869,13 +1702,12 @@ fn build_tcp_packet_with_options(
     let doff_words = ((20 + options.len()) / 4) as u8;
     response.push(doff_words << 4);
     response.push(flags);
-    response.extend_from_slice(&(u16::MAX).to_be_bytes());
+    response.extend_from_slice(&window.to_be_bytes());
     response.extend_from_slice(&[0, 0]); // checksum placeholder
     response.extend_from_slice(&[0, 0]); // urgent pointer
     response.extend_from_slice(options);
     response.extend_from_slice(data);
 
-    let tcp_start = 14 + 20;
     let tcp_end = tcp_start + tcp_len;
     let cksum = tcp_udp_checksum(src_ip, dst_ip, IP_PROTO_TCP, &response[tcp_start..tcp_end]);
     response[tcp_start + 16..tcp_start + 18].copy_from_slice(&cksum.to_be_bytes());
@@ -913,10 +1745,12 @@ fn parse_tcp_wscale(options: &[u8]) -> Option<u8> {
 /// Poll NAT sockets for incoming data.
 pub fn poll_nat_sockets(state: &mut NatState, responses: &mut Vec<Vec<u8>>) {
     responses.clear();
+    responses.append(&mut state.pending);
 
     // Poll UDP
     for (key, entry) in state.udp.iter_mut() {
         while let Ok(len) = entry.socket.recv(&mut state.udp_rx_buf) {
+            entry.bytes_to_guest += len as u64;
             if let Some(resp) = build_udp_response(
                 &entry.client_mac,
                 &entry.client_ip,
@@ -925,7 +1759,26 @@ pub fn poll_nat_sockets(state: &mut NatState, responses: &mut Vec<Vec<u8>>) {
                 &key.0,
                 &state.udp_rx_buf[..len],
             ) {
-                responses.push(resp);
+                push_fragmented(responses, resp, state.mtu, &mut state.next_ip_id);
+            }
+        }
+    }
+
+    // Poll ICMP echo replies
+    for (key, entry) in state.icmp.iter_mut() {
+        while let Ok(range) = entry.socket.recv(&mut state.udp_rx_buf) {
+            let icmp = &mut state.udp_rx_buf[range];
+            if icmp[0] != 0 {
+                continue;
+            }
+            // macOS hands every ping socket all replies from the destination.
+            if cfg!(target_os = "macos") && icmp[4..6] != entry.id.to_be_bytes() {
+                continue;
+            }
+            icmp[4..6].copy_from_slice(&entry.id.to_be_bytes());
+            if let Some(resp) = build_icmp_packet(&entry.client_mac, &entry.client_ip, &key.0, icmp)
+            {
+                push_fragmented(responses, resp, state.mtu, &mut state.next_ip_id);
             }
         }
     }
@@ -935,6 +1788,69 @@ pub fn poll_nat_sockets(state: &mut NatState, responses: &mut Vec<Vec<u8>>) {
     state.tcp_keys_scratch.extend(state.tcp.keys().cloned());
 
     for key in state.tcp_keys_scratch.iter().cloned() {
+        // Outbound connections still connecting on the host: answer the
+        // guest's SYN once connected, and reset it on failure or timeout.
+        if let Some(entry) = state.tcp.get_mut(&key)
+            && let Some(started) = entry.connecting
+        {
+            match connect_result(&entry.stream) {
+                Ok(true) => {
+                    entry.connecting = None;
+                    entry.last_active = Instant::now();
+                    if let Some(resp) = entry.syn_ack(state.mtu) {
+                        responses.push(resp);
+                    }
+                }
+                Ok(false) if started.elapsed() < TCP_CONNECT_TIMEOUT => {}
+                result => {
+                    match result {
+                        Err(e) => tracing::debug!(error = %e, "TCP connect failed"),
+                        _ => tracing::debug!(port = entry.remote_port, "TCP connect timed out"),
+                    }
+                    // The guest's SYN was never acknowledged, so the RST
+                    // acknowledges it instead of using our sequence number.
+                    if let Some(resp) = entry.empty_segment(0, 0x14) {
+                        responses.push(resp);
+                    }
+                    state.tcp.remove(&key);
+                }
+            }
+            continue;
+        }
+
+        // Inbound connections still waiting for the guest: retransmit the SYN,
+        // and give up (closing the host connection) if the guest never answers.
+        if let Some(entry) = state.tcp.get_mut(&key)
+            && let Some(sent) = entry.syn_sent
+        {
+            if entry.last_active.elapsed() >= Duration::from_secs(1) {
+                if sent > INBOUND_SYN_RETRIES {
+                    tracing::debug!(
+                        port = entry.client_port,
+                        "guest did not accept forwarded connection"
+                    );
+                    state.tcp.remove(&key);
+                    continue;
+                }
+                entry.syn_sent = Some(sent + 1);
+                entry.last_active = Instant::now();
+                if let Some(resp) = build_tcp_syn(
+                    &entry.client_mac,
+                    &entry.client_ip,
+                    entry.client_port,
+                    entry.remote_port,
+                    &entry.remote_ip,
+                    entry.acked_seq,
+                    0x02,
+                    0,
+                    max_segment_size(state.mtu, &entry.client_ip),
+                ) {
+                    responses.push(resp);
+                }
+            }
+            continue;
+        }
+
         // First, try to flush any pending write buffer
         if let Some(entry) = state.tcp.get_mut(&key) {
             if entry.write_offset < entry.write_buffer.len() {
@@ -970,20 +1886,18 @@ pub fn poll_nat_sockets(state: &mut NatState, responses: &mut Vec<Vec<u8>>) {
                             entry.write_buffer.clear();
                             entry.write_offset = 0;
                         }
+                        entry.shutdown_write_if_flushed();
+                        // A guest facing a closed window waits to hear it
+                        // reopened.
+                        if entry.update_window()
+                            && let Some(resp) = entry.segment(entry.our_seq, 0x10, &[])
+                        {
+                            responses.push(resp);
+                        }
                     }
                     Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
                     Err(_) => {
-                        if let Some(resp) = build_tcp_packet(
-                            &entry.client_mac,
-                            &entry.client_ip,
-                            entry.client_port,
-                            entry.remote_port,
-                            &entry.remote_ip,
-                            0,
-                            0,
-                            0x04,
-                            &[],
-                        ) {
+                        if let Some(resp) = entry.reset_packet() {
                             responses.push(resp);
                         }
                         state.tcp.remove(&key);
@@ -1001,27 +1915,15 @@ pub fn poll_nat_sockets(state: &mut NatState, responses: &mut Vec<Vec<u8>>) {
                 break;
             };
 
-            if !entry.can_send() {
+            if entry.our_fin.is_some() || !entry.can_send() {
                 break;
             }
 
             match entry.stream.read(&mut state.tcp_rx_buf) {
                 Ok(0) => {
-                    // Connection closed
-                    if let Some(resp) = build_tcp_packet(
-                        &entry.client_mac,
-                        &entry.client_ip,
-                        entry.client_port,
-                        entry.remote_port,
-                        &entry.remote_ip,
-                        entry.our_seq,
-                        entry.expected_guest_seq,
-                        0x11,
-                        &[],
-                    ) {
-                        responses.push(resp);
-                    }
-                    state.tcp.remove(&key);
+                    // The remote closed its end, after all it sent was
+                    // relayed.
+                    responses.extend(entry.fin());
                     break 'read_loop;
                 }
                 Ok(total_len) => {
@@ -1043,6 +1945,7 @@ pub fn poll_nat_sockets(state: &mut NatState, responses: &mut Vec<Vec<u8>>) {
                                 seq,
                                 e.expected_guest_seq,
                                 0x18,
+                                e.receive_window(),
                                 chunk,
                             ) {
                                 responses.push(resp);
@@ -1052,6 +1955,7 @@ pub fn poll_nat_sockets(state: &mut NatState, responses: &mut Vec<Vec<u8>>) {
                     }
                     if let Some(e) = state.tcp.get_mut(&key) {
                         e.our_seq = e.our_seq.wrapping_add(total_len as u32);
+                        e.bytes_to_guest += total_len as u64;
                     }
                     // If we read less than buffer size, socket is likely drained
                     if total_len < TCP_READ_BUFFER_SIZE / 2 {
@@ -1063,19 +1967,9 @@ pub fn poll_nat_sockets(state: &mut NatState, responses: &mut Vec<Vec<u8>>) {
                     break 'read_loop;
                 }
                 Err(_) => {
-                    // Error - send RST
+                    // Error (including a keepalive timeout) - send RST
                     if let Some(entry) = state.tcp.get(&key)
-                        && let Some(resp) = build_tcp_packet(
-                            &entry.client_mac,
-                            &entry.client_ip,
-                            entry.client_port,
-                            entry.remote_port,
-                            &entry.remote_ip,
-                            0,
-                            0,
-                            0x04,
-                            &[],
-                        )
+                        && let Some(resp) = entry.reset_packet()
                     {
                         responses.push(resp);
                     }
@@ -1088,12 +1982,111 @@ pub fn poll_nat_sockets(state: &mut NatState, responses: &mut Vec<Vec<u8>>) {
 
     // Cleanup stale connections
     let now = Instant::now();
+    let udp_timeout = state.udp_timeout;
+    state.udp.retain(|key, e| {
+        let timeout = if key.1 == DNS_PORT {
+            UDP_DNS_TIMEOUT.min(udp_timeout)
+        } else {
+            udp_timeout
+        };
+        now.duration_since(e.last_active) < timeout
+    });
     state
-        .udp
+        .udp_inbound
         .retain(|_, e| now.duration_since(e.last_active) < Duration::from_secs(60));
     state
-        .tcp
-        .retain(|_, e| now.duration_since(e.last_active) < Duration::from_secs(300));
+        .icmp
+        .retain(|_, e| now.duration_since(e.last_active) < Duration::from_secs(60));
+    state.tcp.retain(|_, e| {
+        e.time_wait
+            .is_none_or(|since| now.duration_since(since) < TCP_TIME_WAIT)
+    });
+    let udp_inbound = &state.udp_inbound;
+    state
+        .udp_inbound_peers
+        .retain(|(_, guest_port), gateway_port| {
+            udp_inbound.contains_key(&(*gateway_port, *guest_port))
+        });
+    probe_idle_tcp(state, responses, now);
+}
+
+/// Probes the guest end of idle TCP connections and drops the connections
+/// whose guest stopped answering, resetting them. A dead remote end is caught
+/// by the host socket's keepalive instead, failing its reads.
+fn probe_idle_tcp(state: &mut NatState, responses: &mut Vec<Vec<u8>>, now: Instant) {
+    state.tcp.retain(|_, e| {
+        let idle = now.duration_since(e.last_active);
+        if e.syn_sent.is_some() || e.connecting.is_some() {
+            // Not established, so there is nothing to probe yet.
+            return idle < GUEST_KEEPALIVE_IDLE;
+        }
+        if idle < GUEST_KEEPALIVE_IDLE {
+            // Any segment from the guest counts as an answer.
+            e.keepalive_probes = 0;
+            return true;
+        }
+
+        let due = ((idle - GUEST_KEEPALIVE_IDLE).as_secs() / GUEST_KEEPALIVE_INTERVAL.as_secs())
+            .min(GUEST_KEEPALIVE_PROBES as u64) as u8;
+        if e.keepalive_probes > due {
+            return true;
+        }
+        if e.keepalive_probes == GUEST_KEEPALIVE_PROBES {
+            tracing::debug!(
+                guest_port = e.client_port,
+                "Guest did not answer keepalive probes, resetting connection"
+            );
+            responses.extend(e.reset_packet());
+            return false;
+        }
+        // A segment one byte before the next one we would send, which the
+        // guest must acknowledge.
+        e.keepalive_probes += 1;
+        responses.extend(e.empty_segment(e.our_seq.wrapping_sub(1), 0x10));
+        true
+    });
+}
+
+/// Turns on keepalive for a host connection, so that a remote end that went
+/// away fails the socket instead of leaving the connection open forever.
+fn enable_keepalive(stream: &TcpStream) {
+    #[cfg(target_os = "macos")]
+    const TCP_KEEPIDLE: libc::c_int = libc::TCP_KEEPALIVE;
+    #[cfg(not(target_os = "macos"))]
+    const TCP_KEEPIDLE: libc::c_int = libc::TCP_KEEPIDLE;
+
+    let fd = stream.as_raw_fd();
+    let options = [
+        (libc::SOL_SOCKET, libc::SO_KEEPALIVE, 1),
+        (
+            libc::IPPROTO_TCP,
+            TCP_KEEPIDLE,
+            HOST_KEEPALIVE_IDLE.as_secs() as libc::c_int,
+        ),
+        (
+            libc::IPPROTO_TCP,
+            libc::TCP_KEEPINTVL,
+            HOST_KEEPALIVE_INTERVAL.as_secs() as libc::c_int,
+        ),
+        (libc::IPPROTO_TCP, libc::TCP_KEEPCNT, HOST_KEEPALIVE_PROBES),
+    ];
+    for (level, name, value) in options {
+        let ret = unsafe {
+            libc::setsockopt(
+                fd,
+                level,
+                name,
+                &value as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if ret != 0 {
+            tracing::debug!(
+                error = %std::io::Error::last_os_error(),
+                "failed to enable TCP keepalive"
+            );
+        }
+    }
 }
 
 #[inline]
@@ -1111,3 +2104,184 @@ fn compact_write_buffer(entry: &mut TcpNatEntry) {
     entry.write_buffer.truncate(remaining);
     entry.write_offset = 0;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::eth::ETHERTYPE_IPV6;
+    use super::*;
+
+    const GUEST_MAC: [u8; 6] = [0x5a, 0x94, 0xef, 0xe4, 0x0c, 0xee];
+
+    fn echo_request() -> Vec<u8> {
+        let mut icmp = vec![8, 0, 0, 0, 0x12, 0x34, 0, 1];
+        icmp.extend_from_slice(b"ping");
+        icmp
+    }
+
+    #[test]
+    fn test_gateway_echo_is_answered() {
+        let mut state = NatState::new(16, 1500);
+        let frame = handle_icmp(
+            &mut state,
+            &echo_request(),
+            &GUEST_MAC,
+            &GUEST_IP,
+            &GATEWAY_IP,
+        )
+        .unwrap();
+        let icmp = &frame[34..];
+        assert_eq!(icmp[0], 0);
+        assert_eq!(&frame[26..30], &GATEWAY_IP);
+        assert_eq!(&icmp[4..], &echo_request()[4..]);
+    }
+
+    #[test]
+    fn test_echo_without_ping_sockets_is_refused() {
+        let mut state = NatState::new(16, 1500);
+        state.icmp_unavailable = true;
+        let dst = [1, 1, 1, 1];
+        let frame = handle_icmp(&mut state, &echo_request(), &GUEST_MAC, &GUEST_IP, &dst).unwrap();
+
+        // No reply from the destination, an error from the gateway.
+        assert_eq!(&frame[0..6], &GUEST_MAC);
+        assert_eq!(&frame[26..30], &GATEWAY_IP);
+        let icmp = &frame[34..];
+        assert_eq!((icmp[0], icmp[1]), (3, 13));
+        // The error quotes the request's IP header and start of its payload.
+        let quoted = &icmp[8..];
+        assert_eq!(quoted.len(), 28);
+        assert_eq!(&quoted[12..16], &GUEST_IP);
+        assert_eq!(&quoted[16..20], &dst);
+        assert_eq!(&quoted[20..], &echo_request()[..8]);
+    }
+
+    fn udp_frame(ethertype: u16, payload_len: usize) -> Vec<u8> {
+        let mut frame = build_eth_header(&GUEST_MAC, &GATEWAY_MAC, ethertype).to_vec();
+        frame.extend_from_slice(&build_ip_header(
+            &[1, 1, 1, 1],
+            &GUEST_IP,
+            IP_PROTO_UDP,
+            payload_len,
+            0,
+        ));
+        frame.extend(std::iter::repeat_n(0xab, payload_len));
+        frame
+    }
+
+    #[test]
+    fn test_frag_needed() {
+        let mut packet = build_ip_header(&GUEST_IP, &[1, 1, 1, 1], IP_PROTO_UDP, 1600, 0).to_vec();
+        packet.extend_from_slice(&[0x30, 0x39, 0, 53, 0x06, 0x48, 0, 0]);
+        packet.extend(std::iter::repeat_n(0, 1592));
+
+        let frame = build_frag_needed(&GUEST_MAC, &packet, 1400).unwrap();
+        assert_eq!(&frame[0..6], &GUEST_MAC);
+        assert_eq!(&frame[26..30], &GATEWAY_IP);
+        assert_eq!(&frame[30..34], &GUEST_IP);
+        let icmp = &frame[34..];
+        assert_eq!((icmp[0], icmp[1]), (3, 4));
+        assert_eq!(u16::from_be_bytes([icmp[6], icmp[7]]), 1400);
+        assert_eq!(&icmp[8..], &packet[..28]);
+    }
+
+    #[test]
+    fn test_push_fragmented() {
+        let mut out = Vec::new();
+        let mut next_id = 7;
+
+        // Packets that fit, and IPv6 packets, go out whole.
+        let small = udp_frame(ETHERTYPE_IPV4, 1400 - 20);
+        push_fragmented(&mut out, small.clone(), 1400, &mut next_id);
+        let ipv6 = udp_frame(ETHERTYPE_IPV6, 3000);
+        push_fragmented(&mut out, ipv6.clone(), 1400, &mut next_id);
+        assert_eq!(out, vec![small, ipv6]);
+        assert_eq!(next_id, 7);
+
+        // Larger IPv4 packets are fragmented, each under a new id.
+        out.clear();
+        push_fragmented(
+            &mut out,
+            udp_frame(ETHERTYPE_IPV4, 2000),
+            1400,
+            &mut next_id,
+        );
+        push_fragmented(
+            &mut out,
+            udp_frame(ETHERTYPE_IPV4, 2000),
+            1400,
+            &mut next_id,
+        );
+        assert_eq!(out.len(), 4);
+        assert!(out.iter().all(|frame| frame.len() - 14 <= 1400));
+        let ids: Vec<u16> = out
+            .iter()
+            .map(|frame| u16::from_be_bytes([frame[18], frame[19]]))
+            .collect();
+        assert_eq!(ids, vec![8, 8, 9, 9]);
+    }
+
+    #[test]
+    fn test_seq_after_wraps() {
+        assert!(seq_after(2, 1));
+        assert!(!seq_after(1, 2));
+        assert!(!seq_after(5, 5));
+        assert!(seq_after(3, u32::MAX - 3));
+        assert!(!seq_after(u32::MAX - 3, 3));
+    }
+
+    #[test]
+    fn test_reassembly_out_of_order() {
+        let mut buffer = ReassemblyBuffer::default();
+        buffer.insert(100, 110, b"klm");
+        buffer.insert(100, 105, b"fghij");
+
+        let mut out = Vec::new();
+        // Nothing continues the stream until the gap is filled.
+        assert_eq!(buffer.drain_into(100, &mut out), 100);
+        assert!(out.is_empty());
+
+        out.extend_from_slice(b"abcde");
+        assert_eq!(buffer.drain_into(105, &mut out), 113);
+        assert_eq!(out, b"abcdefghijklm");
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.bytes, 0);
+    }
+
+    #[test]
+    fn test_reassembly_overlap() {
+        let mut buffer = ReassemblyBuffer::default();
+        buffer.insert(100, 103, b"def");
+        // A longer retransmission at the same sequence number replaces it.
+        buffer.insert(100, 103, b"defgh");
+        buffer.insert(100, 105, b"fghijk");
+        // Already received in full by the time the gap is filled.
+        buffer.insert(100, 101, b"b");
+        assert_eq!(buffer.bytes, 12);
+
+        let mut out = Vec::new();
+        assert_eq!(buffer.drain_into(103, &mut out), 111);
+        assert_eq!(out, b"defghijk");
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.bytes, 0);
+    }
+
+    #[test]
+    fn test_reassembly_wraparound() {
+        let expected = u32::MAX - 1;
+        let mut buffer = ReassemblyBuffer::default();
+        buffer.insert(expected, 2, b"ef");
+        buffer.insert(expected, 0, b"cd");
+
+        let mut out = b"ab".to_vec();
+        assert_eq!(buffer.drain_into(expected.wrapping_add(2), &mut out), 4);
+        assert_eq!(out, b"abcdef");
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_reassembly_is_bounded() {
+        let mut buffer = ReassemblyBuffer::default();
+        buffer.insert(0, TCP_REASSEMBLY_MAX as u32, b"x");
+        assert!(buffer.is_empty());
+    }
+}
@@ -0,0 +1,154 @@
+//! ICMPv6 handling for the gateway: neighbor discovery, router
+//! advertisements, and echo.
+
+use super::eth::{
+    ETHERTYPE_IPV6, IP_PROTO_ICMPV6, build_eth_header, build_ipv6_header, tcp_udp_checksum,
+};
+use super::{GATEWAY_IP6, GATEWAY_LINK_LOCAL, GATEWAY_MAC, IPV6_PREFIX, IPV6_PREFIX_LEN};
+
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const ICMPV6_ECHO_REPLY: u8 = 129;
+const ICMPV6_ROUTER_SOLICIT: u8 = 133;
+const ICMPV6_ROUTER_ADVERT: u8 = 134;
+const ICMPV6_NEIGHBOR_SOLICIT: u8 = 135;
+const ICMPV6_NEIGHBOR_ADVERT: u8 = 136;
+
+const OPT_SOURCE_LINK_ADDR: u8 = 1;
+const OPT_TARGET_LINK_ADDR: u8 = 2;
+const OPT_PREFIX_INFO: u8 = 3;
+const OPT_MTU: u8 = 5;
+
+/// Neighbor discovery messages must carry the maximum hop limit.
+const NDP_HOP_LIMIT: u8 = 255;
+const ALL_NODES: [u8; 16] = [0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+const ALL_NODES_MAC: [u8; 6] = [0x33, 0x33, 0, 0, 0, 1];
+
+const ROUTER_LIFETIME_SECS: u16 = 1800;
+const PREFIX_VALID_SECS: u32 = 86400;
+const PREFIX_PREFERRED_SECS: u32 = 14400;
+
+fn is_gateway(ip: &[u8]) -> bool {
+    ip == GATEWAY_IP6 || ip == GATEWAY_LINK_LOCAL
+}
+
+/// Handle an ICMPv6 message from the guest and return a response if applicable.
+///
+/// Only messages for the gateway itself are answered; echo requests to other
+/// destinations are dropped. Router advertisements carry the link `mtu`.
+pub fn handle_icmpv6(
+    payload: &[u8],
+    src_mac: &[u8],
+    src_ip: &[u8],
+    dst_ip: &[u8],
+    mtu: usize,
+) -> Option<Vec<u8>> {
+    if payload.len() < 8 {
+        return None;
+    }
+
+    match payload[0] {
+        ICMPV6_ECHO_REQUEST if is_gateway(dst_ip) => {
+            let mut reply = payload.to_vec();
+            reply[0] = ICMPV6_ECHO_REPLY;
+            Some(build_icmpv6_frame(src_mac, dst_ip, src_ip, 64, &mut reply))
+        }
+        ICMPV6_ROUTER_SOLICIT => {
+            tracing::debug!("Router solicitation from guest");
+            Some(build_router_advert(src_mac, src_ip, mtu))
+        }
+        ICMPV6_NEIGHBOR_SOLICIT if payload.len() >= 24 => {
+            let target = &payload[8..24];
+            if !is_gateway(target) {
+                return None;
+            }
+            Some(build_neighbor_advert(src_mac, src_ip, target))
+        }
+        _ => None,
+    }
+}
+
+/// Where to send a reply to `src_ip`: solicitations from the unspecified
+/// address (during address configuration) are answered to all nodes.
+fn reply_destination<'a>(src_mac: &'a [u8], src_ip: &'a [u8]) -> (&'a [u8], &'a [u8], bool) {
+    if src_ip.iter().all(|&b| b == 0) {
+        (&ALL_NODES_MAC, &ALL_NODES, false)
+    } else {
+        (src_mac, src_ip, true)
+    }
+}
+
+fn build_neighbor_advert(src_mac: &[u8], src_ip: &[u8], target: &[u8]) -> Vec<u8> {
+    let (dst_mac, dst_ip, solicited) = reply_destination(src_mac, src_ip);
+
+    let mut icmp = Vec::with_capacity(32);
+    icmp.extend_from_slice(&[ICMPV6_NEIGHBOR_ADVERT, 0, 0, 0]);
+    // Router and override, plus solicited for unicast replies.
+    let mut flags = 0x80 | 0x20;
+    if solicited {
+        flags |= 0x40;
+    }
+    icmp.extend_from_slice(&[flags, 0, 0, 0]);
+    icmp.extend_from_slice(target);
+    icmp.extend_from_slice(&[OPT_TARGET_LINK_ADDR, 1]);
+    icmp.extend_from_slice(&GATEWAY_MAC);
+
+    build_icmpv6_frame(dst_mac, target, dst_ip, NDP_HOP_LIMIT, &mut icmp)
+}
+
+fn build_router_advert(src_mac: &[u8], src_ip: &[u8], mtu: usize) -> Vec<u8> {
+    let (dst_mac, dst_ip, _) = reply_destination(src_mac, src_ip);
+
+    let mut icmp = Vec::with_capacity(64);
+    icmp.extend_from_slice(&[ICMPV6_ROUTER_ADVERT, 0, 0, 0]);
+    icmp.push(64); // current hop limit
+    icmp.push(0); // no DHCPv6: addresses come from SLAAC
+    icmp.extend_from_slice(&ROUTER_LIFETIME_SECS.to_be_bytes());
+    icmp.extend_from_slice(&0u32.to_be_bytes()); // reachable time
+    icmp.extend_from_slice(&0u32.to_be_bytes()); // retransmit timer
+
+    icmp.extend_from_slice(&[OPT_SOURCE_LINK_ADDR, 1]);
+    icmp.extend_from_slice(&GATEWAY_MAC);
+
+    icmp.extend_from_slice(&[OPT_MTU, 1, 0, 0]);
+    icmp.extend_from_slice(&(mtu as u32).to_be_bytes());
+
+    icmp.extend_from_slice(&[OPT_PREFIX_INFO, 4, IPV6_PREFIX_LEN]);
+    icmp.push(0xc0); // on-link, autonomous address configuration
+    icmp.extend_from_slice(&PREFIX_VALID_SECS.to_be_bytes());
+    icmp.extend_from_slice(&PREFIX_PREFERRED_SECS.to_be_bytes());
+    icmp.extend_from_slice(&[0, 0, 0, 0]);
+    icmp.extend_from_slice(&IPV6_PREFIX);
+
+    build_icmpv6_frame(
+        dst_mac,
+        &GATEWAY_LINK_LOCAL,
+        dst_ip,
+        NDP_HOP_LIMIT,
+        &mut icmp,
+    )
+}
+
+/// Builds a frame carrying `icmp` to the guest, computing its checksum.
+fn build_icmpv6_frame(
+    dst_mac: &[u8],
+    src_ip: &[u8],
+    dst_ip: &[u8],
+    hop_limit: u8,
+    icmp: &mut [u8],
+) -> Vec<u8> {
+    icmp[2..4].copy_from_slice(&[0, 0]);
+    let cksum = tcp_udp_checksum(src_ip, dst_ip, IP_PROTO_ICMPV6, icmp);
+    icmp[2..4].copy_from_slice(&cksum.to_be_bytes());
+
+    let mut frame = Vec::with_capacity(14 + 40 + icmp.len());
+    frame.extend_from_slice(&build_eth_header(dst_mac, &GATEWAY_MAC, ETHERTYPE_IPV6));
+    frame.extend_from_slice(&build_ipv6_header(
+        src_ip,
+        dst_ip,
+        IP_PROTO_ICMPV6,
+        icmp.len(),
+        hop_limit,
+    ));
+    frame.extend_from_slice(icmp);
+    frame
+}
@@ -1,13 +1,23 @@
 //! Main network stack implementation.
 
-use super::GATEWAY_IP;
-use super::arp::handle_arp;
+use super::NetConfig;
+use super::arp::{ArpTable, handle_arp};
 use super::dhcp::handle_dhcp;
 use super::dns::{DnsForwarder, handle_dns};
-use super::eth::{ETHERTYPE_ARP, ETHERTYPE_IPV4, IP_PROTO_ICMP, IP_PROTO_TCP, IP_PROTO_UDP};
-use super::nat::{NatState, handle_icmp, handle_tcp, handle_udp, poll_nat_sockets};
+use super::eth::{
+    ETHERTYPE_ARP, ETHERTYPE_IPV4, ETHERTYPE_IPV6, IP_PROTO_ICMP, IP_PROTO_ICMPV6, IP_PROTO_TCP,
+    IP_PROTO_UDP,
+};
+use super::forward::PortForwarder;
+use super::nat::{
+    DEFAULT_MAX_TCP_CONNECTIONS, DEFAULT_UDP_TIMEOUT, NatState, build_frag_needed, handle_icmp,
+    handle_tcp, handle_udp, poll_nat_sockets,
+};
+use super::ndp::handle_icmpv6;
 use super::ring_spsc::{PacketRef, SpscPacketRing};
-use crate::ShimError;
+use super::stats::NetCounters;
+use super::{DEFAULT_MTU, GATEWAY_IP, MAX_MTU, MIN_MTU};
+use crate::{PortMapping, ShimError};
 use nix::sys::socket::{AddressFamily, SockFlag, SockType, UnixAddr, bind, socket};
 use std::collections::VecDeque;
 use std::os::fd::{AsRawFd, OwnedFd};
@@ -25,10 +35,25 @@ pub struct VmNetwork {
     _server_fd: OwnedFd,
     shutdown: Arc<AtomicBool>,
     thread_handle: Option<thread::JoinHandle<()>>,
+    counters: Arc<NetCounters>,
 }
 
 impl VmNetwork {
-    pub fn start(container_id: &str) -> Result<Self, ShimError> {
+    /// Starts the stack for a VM, publishing `ports` on the host and
+    /// leasing the guest its address with `config`.
+    ///
+    /// `mtu` sets the guest's link MTU, falling back to `ROSS_NET_MTU` and
+    /// then the standard 1500 bytes.
+    pub fn start(
+        container_id: &str,
+        ports: &[PortMapping],
+        mtu: Option<u16>,
+        config: NetConfig,
+    ) -> Result<Self, ShimError> {
+        let mtu = net_mtu(mtu)?;
+        let config = Arc::new(config);
+        let forwarder = Arc::new(PortForwarder::bind(ports)?);
+
         let socket_path = PathBuf::from(format!("/tmp/ross-net-{}.sock", container_id));
         let _ = std::fs::remove_file(&socket_path);
 
@@ -71,22 +96,60 @@ impl VmNetwork {
         let shutdown = Arc::new(AtomicBool::new(false));
         let shutdown_clone = shutdown.clone();
         let fd = server_fd.as_raw_fd();
+        let counters = Arc::new(NetCounters::default());
+        let counters_clone = counters.clone();
 
-        let thread_handle = thread::spawn(move || run_stack(fd, shutdown_clone));
+        let thread_handle = thread::spawn(move || {
+            run_stack(fd, shutdown_clone, forwarder, mtu, config, counters_clone)
+        });
 
-        tracing::info!(path = %socket_path.display(), "Network stack started");
+        tracing::info!(path = %socket_path.display(), mtu, "Network stack started");
 
         Ok(Self {
             socket_path,
             _server_fd: server_fd,
             shutdown,
             thread_handle: Some(thread_handle),
+            counters,
         })
     }
 
     pub fn socket_path(&self) -> &str {
         self.socket_path.to_str().unwrap_or("")
     }
+
+    /// A handle on the running stack for the code that does not own it.
+    pub fn handle(&self) -> NetworkHandle {
+        NetworkHandle {
+            counters: self.counters.clone(),
+            shutdown: self.shutdown.clone(),
+            socket_path: self.socket_path.clone(),
+        }
+    }
+}
+
+/// Shared view of a running [`VmNetwork`]: its traffic counters, and a way to
+/// stop it from outside the task that owns it.
+#[derive(Clone)]
+pub struct NetworkHandle {
+    counters: Arc<NetCounters>,
+    shutdown: Arc<AtomicBool>,
+    socket_path: PathBuf,
+}
+
+impl NetworkHandle {
+    /// Traffic counters of the guest link, updated live by the stack.
+    pub fn counters(&self) -> &NetCounters {
+        &self.counters
+    }
+
+    /// Stops forwarding packets and removes the stack's socket. The published
+    /// ports close as the stack's threads exit; they are joined when the
+    /// owner drops the [`VmNetwork`].
+    pub fn stop(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        let _ = std::fs::remove_file(&self.socket_path);
+    }
 }
 
 impl Drop for VmNetwork {
@@ -99,11 +162,86 @@ impl Drop for VmNetwork {
     }
 }
 
+/// Network device for a VM that must have no connectivity.
+///
+/// Giving libkrun a network device turns off TSI, which would otherwise hand
+/// the guest the host's network. Nothing reads the socket behind the device,
+/// so the guest's link leads nowhere.
+pub struct DetachedLink {
+    socket_path: PathBuf,
+    _fd: OwnedFd,
+}
+
+impl DetachedLink {
+    pub fn bind(container_id: &str) -> Result<Self, ShimError> {
+        let socket_path = PathBuf::from(format!("/tmp/ross-net-{}.sock", container_id));
+        let _ = std::fs::remove_file(&socket_path);
+
+        let fd = socket(
+            AddressFamily::Unix,
+            SockType::Datagram,
+            SockFlag::empty(),
+            None,
+        )
+        .map_err(|e| ShimError::RuntimeError(format!("socket: {}", e)))?;
+        let addr = UnixAddr::new(&socket_path)
+            .map_err(|e| ShimError::RuntimeError(format!("addr: {}", e)))?;
+        bind(fd.as_raw_fd(), &addr).map_err(|e| ShimError::RuntimeError(format!("bind: {}", e)))?;
+
+        Ok(Self {
+            socket_path,
+            _fd: fd,
+        })
+    }
+
+    pub fn socket_path(&self) -> &str {
+        self.socket_path.to_str().unwrap_or("")
+    }
+}
+
+impl Drop for DetachedLink {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.socket_path);
+    }
+}
+
+/// The network device of a VM, held for as long as the VM runs.
+pub enum VmLink {
+    /// The userspace stack, publishing the container's ports.
+    Stack(VmNetwork),
+    /// A link leading nowhere, for a container without network.
+    Detached(DetachedLink),
+}
+
+impl VmLink {
+    pub fn socket_path(&self) -> &str {
+        match self {
+            Self::Stack(network) => network.socket_path(),
+            Self::Detached(link) => link.socket_path(),
+        }
+    }
+
+    /// A handle on the userspace stack, if the VM has one.
+    pub fn handle(&self) -> Option<NetworkHandle> {
+        match self {
+            Self::Stack(network) => Some(network.handle()),
+            Self::Detached(_) => None,
+        }
+    }
+}
+
 pub fn network_available() -> bool {
     true
 }
 
-fn run_stack(fd: i32, shutdown: Arc<AtomicBool>) {
+fn run_stack(
+    fd: i32,
+    shutdown: Arc<AtomicBool>,
+    forwarder: Arc<PortForwarder>,
+    mtu: u16,
+    config: Arc<NetConfig>,
+    counters: Arc<NetCounters>,
+) {
     // Boost thread priority for lower latency networking
     boost_thread_priority();
 
@@ -155,9 +293,9 @@ fn run_stack(fd: i32, shutdown: Arc<AtomicBool>) {
     // Default is single-threaded unless explicitly enabled.
     let workers = net_workers();
     if workers > 1 {
-        run_stack_multi(fd, shutdown, workers);
+        run_stack_multi(fd, shutdown, forwarder, workers, mtu, config, counters);
     } else {
-        run_stack_single(fd, shutdown);
+        run_stack_single(fd, shutdown, &forwarder, mtu, config, &counters);
     }
 }
 
@@ -175,6 +313,58 @@ fn net_workers() -> usize {
     1
 }
 
+fn net_max_tcp_connections() -> usize {
+    // Cap on concurrent guest TCP connections, shared between workers.
+    //
+    // Example:
+    //   ROSS_NET_MAX_TCP=16384 ross ...
+    if let Ok(v) = std::env::var("ROSS_NET_MAX_TCP")
+        && let Ok(n) = v.parse::<usize>()
+    {
+        return n.max(1);
+    }
+    DEFAULT_MAX_TCP_CONNECTIONS
+}
+
+fn net_udp_timeout() -> Duration {
+    // Idle time in seconds before a guest UDP flow is dropped. Raise it for
+    // long-lived flows that go quiet, such as QUIC connections.
+    //
+    // Example:
+    //   ROSS_NET_UDP_TIMEOUT=300 ross ...
+    if let Ok(v) = std::env::var("ROSS_NET_UDP_TIMEOUT")
+        && let Ok(secs) = v.parse::<u64>()
+    {
+        return Duration::from_secs(secs.max(1));
+    }
+    DEFAULT_UDP_TIMEOUT
+}
+
+fn net_mtu(requested: Option<u16>) -> Result<u16, ShimError> {
+    // Link MTU of the guest interface. Lower it when the host's path out is
+    // smaller than 1500 bytes (VPNs, tunnels) so the guest sizes its packets
+    // to fit.
+    //
+    // Example:
+    //   ROSS_NET_MTU=1400 ross ...
+    let mtu = match requested {
+        Some(mtu) => mtu,
+        None => match std::env::var("ROSS_NET_MTU") {
+            Ok(v) => v
+                .parse::<u16>()
+                .map_err(|_| ShimError::RuntimeError(format!("invalid ROSS_NET_MTU: {}", v)))?,
+            Err(_) => DEFAULT_MTU,
+        },
+    };
+    if !(MIN_MTU..=MAX_MTU).contains(&mtu) {
+        return Err(ShimError::RuntimeError(format!(
+            "MTU {} out of range {}-{}",
+            mtu, MIN_MTU, MAX_MTU
+        )));
+    }
+    Ok(mtu)
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum SendResult {
     Sent,
@@ -182,9 +372,18 @@ enum SendResult {
     Failed,
 }
 
-fn run_stack_single(fd: i32, shutdown: Arc<AtomicBool>) {
+fn run_stack_single(
+    fd: i32,
+    shutdown: Arc<AtomicBool>,
+    forwarder: &PortForwarder,
+    mtu: u16,
+    config: Arc<NetConfig>,
+    counters: &NetCounters,
+) {
     // Main loop - prioritize draining VM packets to prevent TX queue stalls
-    let mut nat_state = NatState::new();
+    let mut nat_state = NatState::new(net_max_tcp_connections(), mtu)
+        .with_udp_timeout(net_udp_timeout())
+        .with_net_config(config);
     let mut dns_forwarder: Option<DnsForwarder> = None;
     let mut pending_responses: Vec<Vec<u8>> = Vec::with_capacity(512);
     let mut nat_responses: Vec<Vec<u8>> = Vec::with_capacity(512);
@@ -192,6 +391,7 @@ fn run_stack_single(fd: i32, shutdown: Arc<AtomicBool>) {
     // Outbox of packets waiting for VM socket to become writable.
     let mut outbox: VecDeque<Vec<u8>> = VecDeque::with_capacity(2048);
     let mut idle_count = 0u32;
+    let mut tcp_connections = 0;
 
     loop {
         if shutdown.load(Ordering::Relaxed) {
@@ -199,7 +399,7 @@ fn run_stack_single(fd: i32, shutdown: Arc<AtomicBool>) {
         }
 
         // Phase 0: Flush any queued packets to the VM (never block RX).
-        flush_outbox_nowait(fd, &mut outbox);
+        flush_outbox_nowait(fd, &mut outbox, counters);
 
         let mut received_any = false;
 
@@ -215,15 +415,16 @@ fn run_stack_single(fd: i32, shutdown: Arc<AtomicBool>) {
                 received_any = true;
                 rx_batch += 1;
                 let n = n as usize;
+                counters.record_from_guest(n);
                 if let Some(resp) = process_frame(&buf[..n], &mut nat_state, &mut dns_forwarder) {
                     pending_responses.push(resp);
                 }
                 // Periodically flush to keep TX moving
                 if rx_batch >= 64 && !pending_responses.is_empty() {
                     for resp in pending_responses.drain(..) {
-                        queue_or_send_nowait(fd, &mut outbox, resp);
+                        queue_or_send_nowait(fd, &mut outbox, resp, counters);
                     }
-                    flush_outbox_nowait(fd, &mut outbox);
+                    flush_outbox_nowait(fd, &mut outbox, counters);
                     rx_batch = 0;
                 }
             } else if n < 0 {
@@ -245,14 +446,20 @@ fn run_stack_single(fd: i32, shutdown: Arc<AtomicBool>) {
 
         // Phase 2: Send pending responses to VM
         for resp in pending_responses.drain(..) {
-            queue_or_send_nowait(fd, &mut outbox, resp);
+            queue_or_send_nowait(fd, &mut outbox, resp, counters);
         }
 
-        // Phase 3: Poll NAT sockets for data from remote servers
+        // Phase 3: Poll NAT sockets for data from remote servers, and
+        // published host ports for new inbound connections
         poll_nat_sockets(&mut nat_state, &mut nat_responses);
+        if !forwarder.is_empty() {
+            forwarder.poll(&mut nat_state, 0, 1, &mut buf, &mut nat_responses);
+        }
+        counters.nat_connections_changed(tcp_connections, nat_state.tcp_connections());
+        tcp_connections = nat_state.tcp_connections();
         let sent_any = !nat_responses.is_empty();
         for resp in nat_responses.drain(..) {
-            queue_or_send_nowait(fd, &mut outbox, resp);
+            queue_or_send_nowait(fd, &mut outbox, resp, counters);
         }
 
         // Adaptive idle: spin briefly, then yield, then sleep
@@ -275,12 +482,28 @@ fn run_stack_single(fd: i32, shutdown: Arc<AtomicBool>) {
     tracing::debug!("Network stack stopped");
 }
 
-fn run_stack_multi(fd: i32, shutdown: Arc<AtomicBool>, workers: usize) {
+fn run_stack_multi(
+    fd: i32,
+    shutdown: Arc<AtomicBool>,
+    forwarder: Arc<PortForwarder>,
+    workers: usize,
+    mtu: u16,
+    config: Arc<NetConfig>,
+    counters: Arc<NetCounters>,
+) {
     tracing::info!(workers, "Network stack running in multi-threaded mode");
-    run_stack_multi_lockfree(fd, shutdown, workers);
+    run_stack_multi_lockfree(fd, shutdown, forwarder, workers, mtu, config, counters);
 }
 
-fn run_stack_multi_lockfree(fd: i32, shutdown: Arc<AtomicBool>, workers: usize) {
+fn run_stack_multi_lockfree(
+    fd: i32,
+    shutdown: Arc<AtomicBool>,
+    forwarder: Arc<PortForwarder>,
+    workers: usize,
+    mtu: u16,
+    config: Arc<NetConfig>,
+    counters: Arc<NetCounters>,
+) {
     tracing::info!(workers, "Multi-threaded lock-free mode");
 
     let rx_rings: Vec<Arc<SpscPacketRing>> = (0..workers)
@@ -291,15 +514,25 @@ fn run_stack_multi_lockfree(fd: i32, shutdown: Arc<AtomicBool>, workers: usize)
         .collect();
 
     // Spawn workers.
+    let arp = Arc::new(ArpTable::default());
     let mut handles = Vec::with_capacity(workers);
     for i in 0..workers {
+        let arp = arp.clone();
+        let config = config.clone();
         let rx = rx_rings[i].clone();
         let tx = tx_rings[i].clone();
         let shutdown = shutdown.clone();
+        let forwarder = forwarder.clone();
+        let counters = counters.clone();
+        let shard = (i, workers);
         let h = thread::Builder::new()
             .name(format!("ross-net-worker-{}", i))
             .stack_size(4 * 1024 * 1024)
-            .spawn(move || net_worker_loop_lockfree(fd, rx, tx, shutdown, false))
+            .spawn(move || {
+                net_worker_loop_lockfree(
+                    fd, rx, tx, shutdown, &forwarder, shard, mtu, arp, config, &counters, false,
+                )
+            })
             .expect("spawn net worker");
         handles.push(h);
     }
@@ -308,11 +541,12 @@ fn run_stack_multi_lockfree(fd: i32, shutdown: Arc<AtomicBool>, workers: usize)
     let tx_handle = {
         let shutdown = shutdown.clone();
         let tx_rings = tx_rings.clone();
+        let counters = counters.clone();
         Some(
             thread::Builder::new()
                 .name("ross-net-tx".to_string())
                 .stack_size(2 * 1024 * 1024)
-                .spawn(move || tx_sender_loop_lockfree(fd, tx_rings, shutdown))
+                .spawn(move || tx_sender_loop_lockfree(fd, tx_rings, shutdown, &counters))
                 .expect("spawn net tx"),
         )
     };
@@ -332,10 +566,13 @@ fn run_stack_multi_lockfree(fd: i32, shutdown: Arc<AtomicBool>, workers: usize)
             if n > 0 {
                 received_any = true;
                 let n = n as usize;
+                counters.record_from_guest(n);
                 let shard = shard_for_frame(&buf[..n], workers);
                 // CRITICAL: never spin-wait on ring capacity here; it stalls VM draining
                 // and triggers virtio-net TX watchdog timeouts. Drop instead.
-                let _ = rx_rings[shard].push(&buf[..n]);
+                if !rx_rings[shard].push(&buf[..n]) {
+                    counters.record_from_guest_dropped();
+                }
             } else if n < 0 {
                 let err = std::io::Error::last_os_error();
                 if err.kind() == std::io::ErrorKind::WouldBlock {
@@ -374,19 +611,32 @@ fn run_stack_multi_lockfree(fd: i32, shutdown: Arc<AtomicBool>, workers: usize)
     tracing::debug!("Network stack stopped");
 }
 
+#[allow(clippy::too_many_arguments)]
 fn net_worker_loop_lockfree(
     fd: i32,
     rx: Arc<SpscPacketRing>,
     tx: Arc<SpscPacketRing>,
     shutdown: Arc<AtomicBool>,
+    forwarder: &PortForwarder,
+    (shard, workers): (usize, usize),
+    mtu: u16,
+    arp: Arc<ArpTable>,
+    config: Arc<NetConfig>,
+    counters: &NetCounters,
     direct_send: bool,
 ) {
-    let mut nat_state = NatState::new();
+    // Each worker tracks its own shard of the connections.
+    let mut nat_state = NatState::new(net_max_tcp_connections().div_ceil(workers), mtu)
+        .with_udp_timeout(net_udp_timeout())
+        .with_arp_table(arp)
+        .with_net_config(config);
     let mut dns_forwarder: Option<DnsForwarder> = None;
     let mut nat_responses: Vec<Vec<u8>> = Vec::with_capacity(256);
     let mut outbox: VecDeque<Vec<u8>> = VecDeque::with_capacity(1024);
     let mut pending_tx: VecDeque<Vec<u8>> = VecDeque::with_capacity(1024);
+    let mut forward_buf = vec![0u8; 65535];
     let mut idle_count = 0u32;
+    let mut tcp_connections = 0;
 
     loop {
         if shutdown.load(Ordering::Relaxed) {
@@ -405,31 +655,50 @@ fn net_worker_loop_lockfree(
         }
 
         if direct_send {
-            flush_outbox_nowait(fd, &mut outbox);
+            flush_outbox_nowait(fd, &mut outbox, counters);
         }
 
         while let Some(pkt) = rx.pop_ref() {
             did_work = true;
             if let Some(resp) = process_frame(&pkt, &mut nat_state, &mut dns_forwarder) {
                 if direct_send {
-                    queue_or_send_nowait(fd, &mut outbox, resp);
+                    queue_or_send_nowait(fd, &mut outbox, resp, counters);
                 } else {
-                    if !tx.push(&resp) && pending_tx.len() < 4096 {
-                        pending_tx.push_back(resp);
+                    if !tx.push(&resp) {
+                        if pending_tx.len() < 4096 {
+                            pending_tx.push_back(resp);
+                        } else {
+                            counters.record_to_guest_dropped();
+                        }
                     }
                 }
             }
         }
 
         poll_nat_sockets(&mut nat_state, &mut nat_responses);
+        if !forwarder.is_empty() {
+            forwarder.poll(
+                &mut nat_state,
+                shard,
+                workers,
+                &mut forward_buf,
+                &mut nat_responses,
+            );
+        }
+        counters.nat_connections_changed(tcp_connections, nat_state.tcp_connections());
+        tcp_connections = nat_state.tcp_connections();
         if !nat_responses.is_empty() {
             did_work = true;
             for resp in nat_responses.drain(..) {
                 if direct_send {
-                    queue_or_send_nowait(fd, &mut outbox, resp);
+                    queue_or_send_nowait(fd, &mut outbox, resp, counters);
                 } else {
-                    if !tx.push(&resp) && pending_tx.len() < 4096 {
-                        pending_tx.push_back(resp);
+                    if !tx.push(&resp) {
+                        if pending_tx.len() < 4096 {
+                            pending_tx.push_back(resp);
+                        } else {
+                            counters.record_to_guest_dropped();
+                        }
                     }
                 }
             }
@@ -448,7 +717,12 @@ fn net_worker_loop_lockfree(
     }
 }
 
-fn tx_sender_loop_lockfree(fd: i32, tx_rings: Vec<Arc<SpscPacketRing>>, shutdown: Arc<AtomicBool>) {
+fn tx_sender_loop_lockfree(
+    fd: i32,
+    tx_rings: Vec<Arc<SpscPacketRing>>,
+    shutdown: Arc<AtomicBool>,
+    counters: &NetCounters,
+) {
     // Send directly from ring storage (no memcpy) and only poll when we hit EAGAIN.
     let mut idle_count = 0u32;
 
@@ -465,10 +739,13 @@ fn tx_sender_loop_lockfree(fd: i32, tx_rings: Vec<Arc<SpscPacketRing>>, shutdown
             for _ in 0..64 {
                 let Some(pkt) = ring.pop_ref() else { break };
                 did_work = true;
+                let len = pkt.len();
                 if !send_packet_ref(fd, pkt) {
                     // Permanent send failure; keep looping to avoid deadlock.
+                    counters.record_to_guest_dropped();
                     break;
                 }
+                counters.record_to_guest(len);
             }
         }
 
@@ -552,31 +829,40 @@ fn send_packet_nowait(fd: i32, data: &[u8]) -> SendResult {
     }
 }
 
-fn queue_or_send_nowait(fd: i32, outbox: &mut VecDeque<Vec<u8>>, pkt: Vec<u8>) {
+fn queue_or_send_nowait(
+    fd: i32,
+    outbox: &mut VecDeque<Vec<u8>>,
+    pkt: Vec<u8>,
+    counters: &NetCounters,
+) {
     // Large outbox to handle burst traffic - 16K packets at 1500 bytes = 24MB max
     const OUTBOX_MAX: usize = 16384;
     if outbox.is_empty() {
         match send_packet_nowait(fd, &pkt) {
-            SendResult::Sent => return,
-            SendResult::WouldBlock => {
-                if outbox.len() < OUTBOX_MAX {
-                    outbox.push_back(pkt);
-                }
+            SendResult::Sent => {
+                counters.record_to_guest(pkt.len());
+                return;
+            }
+            SendResult::WouldBlock => {}
+            SendResult::Failed => {
+                counters.record_to_guest_dropped();
                 return;
             }
-            SendResult::Failed => return,
         }
-    } else if outbox.len() < OUTBOX_MAX {
+    }
+    if outbox.len() < OUTBOX_MAX {
         outbox.push_back(pkt);
+    } else {
+        // Drop packet if outbox is full (better than stalling)
+        counters.record_to_guest_dropped();
     }
-    // Drop packet if outbox is full (better than stalling)
 }
 
-fn flush_outbox_nowait(fd: i32, outbox: &mut VecDeque<Vec<u8>>) {
+fn flush_outbox_nowait(fd: i32, outbox: &mut VecDeque<Vec<u8>>, counters: &NetCounters) {
     // Use sendmmsg to batch multiple packets in a single syscall when available
     #[cfg(target_os = "linux")]
     {
-        flush_outbox_sendmmsg(fd, outbox);
+        flush_outbox_sendmmsg(fd, outbox, counters);
     }
     #[cfg(not(target_os = "linux"))]
     {
@@ -584,10 +870,12 @@ fn flush_outbox_nowait(fd: i32, outbox: &mut VecDeque<Vec<u8>>) {
         while let Some(pkt) = outbox.front() {
             match send_packet_nowait(fd, pkt) {
                 SendResult::Sent => {
+                    counters.record_to_guest(pkt.len());
                     let _ = outbox.pop_front();
                 }
                 SendResult::WouldBlock => break,
                 SendResult::Failed => {
+                    counters.record_to_guest_dropped();
                     let _ = outbox.pop_front();
                 }
             }
@@ -596,7 +884,7 @@ fn flush_outbox_nowait(fd: i32, outbox: &mut VecDeque<Vec<u8>>) {
 }
 
 #[cfg(target_os = "linux")]
-fn flush_outbox_sendmmsg(fd: i32, outbox: &mut VecDeque<Vec<u8>>) {
+fn flush_outbox_sendmmsg(fd: i32, outbox: &mut VecDeque<Vec<u8>>, counters: &NetCounters) {
     const MAX_BATCH: usize = 64;
 
     while !outbox.is_empty() {
@@ -627,12 +915,15 @@ fn flush_outbox_sendmmsg(fd: i32, outbox: &mut VecDeque<Vec<u8>>) {
             }
             // On error, try to at least drain one packet to avoid infinite loop
             let _ = outbox.pop_front();
+            counters.record_to_guest_dropped();
             break;
         }
 
         // Remove sent packets
         for _ in 0..sent {
-            let _ = outbox.pop_front();
+            if let Some(pkt) = outbox.pop_front() {
+                counters.record_to_guest(pkt.len());
+            }
         }
     }
 }
@@ -643,20 +934,18 @@ fn shard_for_frame(frame: &[u8], workers: usize) -> usize {
         return 0;
     }
     let ethertype = u16::from_be_bytes([frame[12], frame[13]]);
-    if ethertype != ETHERTYPE_IPV4 || frame.len() < 14 + 20 {
-        return 0;
-    }
-
     let ip = &frame[14..];
-    let ihl = (ip[0] & 0x0f) as usize * 4;
-    if ihl < 20 || ip.len() < ihl {
-        return 0;
-    }
-
-    let proto = ip[9];
-    let src_ip = &ip[12..16];
-    let dst_ip = &ip[16..20];
-    let l4 = &ip[ihl..];
+    let (proto, src_ip, dst_ip, l4) = match ethertype {
+        ETHERTYPE_IPV4 if ip.len() >= 20 => {
+            let ihl = (ip[0] & 0x0f) as usize * 4;
+            if ihl < 20 || ip.len() < ihl {
+                return 0;
+            }
+            (ip[9], &ip[12..16], &ip[16..20], &ip[ihl..])
+        }
+        ETHERTYPE_IPV6 if ip.len() >= 40 => (ip[6], &ip[8..24], &ip[24..40], &ip[40..]),
+        _ => return 0,
+    };
 
     let mut src_port = 0u16;
     let mut dst_port = 0u16;
@@ -665,14 +954,47 @@ fn shard_for_frame(frame: &[u8], workers: usize) -> usize {
         dst_port = u16::from_be_bytes([l4[2], l4[3]]);
     }
 
-    // Cheap hash; good enough to spread flows across workers.
+    shard_for_flow(proto, src_ip, dst_ip, src_port, dst_port, workers)
+}
+
+/// Picks the worker for a flow. Both directions of a connection hash
+/// alike, so every packet of it reaches the worker holding its NAT state.
+#[inline]
+pub(super) fn shard_for_flow(
+    proto: u8,
+    src_ip: &[u8],
+    dst_ip: &[u8],
+    src_port: u16,
+    dst_port: u16,
+    workers: usize,
+) -> usize {
+    if workers <= 1 {
+        return 0;
+    }
+
+    // Only the last four address bytes are used, which for IPv6 is the
+    // varying host part.
+    let tail = |ip: &[u8]| {
+        let n = ip.len();
+        u32::from_be_bytes([ip[n - 4], ip[n - 3], ip[n - 2], ip[n - 1]])
+    };
+    // Order the endpoints so that the key does not depend on direction.
+    let src = (tail(src_ip), src_port);
+    let dst = (tail(dst_ip), dst_port);
+    let (lo, hi) = if src <= dst { (src, dst) } else { (dst, src) };
+
     let mut h: u32 = (proto as u32).wrapping_mul(0x9e37_79b9);
-    h ^= u32::from_be_bytes([src_ip[0], src_ip[1], src_ip[2], src_ip[3]]);
-    h = h.rotate_left(13) ^ u32::from_be_bytes([dst_ip[0], dst_ip[1], dst_ip[2], dst_ip[3]]);
-    // IMPORTANT: include `src_port` in the low bits so modulo small worker counts
-    // (e.g. 8) actually distributes flows. Put dst_port in the high bits.
-    let ports = (src_port as u32) | ((dst_port as u32) << 16);
-    h = h.wrapping_mul(0x85eb_ca6b) ^ ports;
+    h ^= lo.0;
+    h = h.rotate_left(13) ^ hi.0;
+    h = h.wrapping_mul(0x85eb_ca6b) ^ (lo.1 as u32 | (hi.1 as u32) << 16);
+    // Finish mixing (murmur3's finalizer) so that every bit of the key, the
+    // ephemeral port wherever it ended up included, affects the low bits
+    // the modulo keeps.
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2_ae35);
+    h ^= h >> 16;
     (h as usize) % workers
 }
 
@@ -690,8 +1012,9 @@ fn process_frame(
     let payload = &frame[14..];
 
     match ethertype {
-        ETHERTYPE_ARP => handle_arp(payload, src_mac),
+        ETHERTYPE_ARP => handle_arp(payload, src_mac, nat_state.arp()),
         ETHERTYPE_IPV4 => process_ipv4(payload, src_mac, nat_state, dns_forwarder),
+        ETHERTYPE_IPV6 => process_ipv6(payload, src_mac, nat_state),
         _ => None,
     }
 }
@@ -715,16 +1038,37 @@ fn process_ipv4(
     let src_ip = &payload[12..16];
     let dst_ip = &payload[16..20];
     let ip_payload = &payload[ihl..];
+    nat_state.arp().learn(src_ip, src_mac);
+
+    // A datagram with DF set that exceeds the link MTU would be dropped by a
+    // real router; tell the guest so it lowers its path MTU. TCP is exempt
+    // since the guest hands us TSO segments larger than the MTU.
+    let total_len = u16::from_be_bytes([payload[2], payload[3]]) as usize;
+    let dont_fragment = payload[6] & 0x40 != 0;
+    if dont_fragment && total_len > nat_state.mtu() && proto != IP_PROTO_TCP {
+        return build_frag_needed(
+            src_mac,
+            &payload[..total_len.min(payload.len())],
+            nat_state.mtu(),
+        );
+    }
 
     match proto {
-        IP_PROTO_ICMP => handle_icmp(ip_payload, src_mac, src_ip, dst_ip),
+        IP_PROTO_ICMP => handle_icmp(nat_state, ip_payload, src_mac, src_ip, dst_ip),
         IP_PROTO_UDP => {
             let dst_port = u16::from_be_bytes([ip_payload[2], ip_payload[3]]);
             if dst_port == 67 {
-                handle_dhcp(&ip_payload[8..])
+                handle_dhcp(&ip_payload[8..], nat_state.mtu(), nat_state.net_config())
             } else if dst_port == 53 && dst_ip == GATEWAY_IP {
                 let src_port = u16::from_be_bytes([ip_payload[0], ip_payload[1]]);
-                handle_dns(&ip_payload[8..], src_mac, src_ip, src_port, dns_forwarder)
+                handle_dns(
+                    &ip_payload[8..],
+                    src_mac,
+                    src_ip,
+                    src_port,
+                    nat_state.net_config(),
+                    dns_forwarder,
+                )
             } else {
                 handle_udp(nat_state, ip_payload, src_mac, src_ip, dst_ip)
             }
@@ -734,6 +1078,36 @@ fn process_ipv4(
     }
 }
 
+/// Handle an IPv6 packet. Extension headers are not supported.
+fn process_ipv6(payload: &[u8], src_mac: &[u8], nat_state: &mut NatState) -> Option<Vec<u8>> {
+    if payload.len() < 40 {
+        return None;
+    }
+
+    let payload_len = u16::from_be_bytes([payload[4], payload[5]]) as usize;
+    let next_header = payload[6];
+    let src_ip = &payload[8..24];
+    let dst_ip = &payload[24..40];
+    // Ethernet padding may follow the packet.
+    let ip_payload = &payload[40..payload.len().min(40 + payload_len)];
+
+    if next_header == IP_PROTO_ICMPV6 {
+        return handle_icmpv6(ip_payload, src_mac, src_ip, dst_ip, nat_state.mtu());
+    }
+
+    // Link-local and multicast traffic stays on the virtual link.
+    let link_scoped = dst_ip[0] == 0xff || (dst_ip[0] == 0xfe && dst_ip[1] & 0xc0 == 0x80);
+    if link_scoped {
+        return None;
+    }
+
+    match next_header {
+        IP_PROTO_UDP => handle_udp(nat_state, ip_payload, src_mac, src_ip, dst_ip),
+        IP_PROTO_TCP => handle_tcp(nat_state, ip_payload, src_mac, src_ip, dst_ip),
+        _ => None,
+    }
+}
+
 /// Boost thread priority for lower latency networking.
 /// On macOS, uses pthread_setschedparam with SCHED_RR (round-robin real-time).
 fn boost_thread_priority() {
@@ -766,3 +1140,389 @@ fn boost_thread_priority() {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::eth::{
+        build_eth_header, build_ip_header, checksum, push_ip_headers, tcp_udp_checksum,
+    };
+    use super::super::{DEFAULT_MAC, GATEWAY_IP6, GATEWAY_MAC, GUEST_IP, HOST_IP, IPV6_PREFIX};
+    use super::*;
+    use std::collections::{HashMap, HashSet};
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::time::Instant;
+
+    /// Workers of a stack started with `ROSS_NET_WORKERS=4`.
+    const WORKERS: usize = 4;
+
+    const TCP_FIN: u8 = 0x01;
+    const TCP_SYN: u8 = 0x02;
+    const TCP_RST: u8 = 0x04;
+    const TCP_PSH: u8 = 0x08;
+    const TCP_ACK: u8 = 0x10;
+
+    fn tcp_segment(
+        src: (&[u8], u16),
+        dst: (&[u8], u16),
+        seq: u32,
+        ack: u32,
+        flags: u8,
+        data: &[u8],
+    ) -> Vec<u8> {
+        let mut tcp = Vec::with_capacity(20 + data.len());
+        tcp.extend_from_slice(&src.1.to_be_bytes());
+        tcp.extend_from_slice(&dst.1.to_be_bytes());
+        tcp.extend_from_slice(&seq.to_be_bytes());
+        tcp.extend_from_slice(&ack.to_be_bytes());
+        tcp.extend_from_slice(&[0x50, flags, 0xff, 0xff, 0, 0, 0, 0]);
+        tcp.extend_from_slice(data);
+        let cksum = tcp_udp_checksum(src.0, dst.0, IP_PROTO_TCP, &tcp);
+        tcp[16..18].copy_from_slice(&cksum.to_be_bytes());
+        tcp
+    }
+
+    /// A TCP segment from the guest to the host's localhost.
+    fn guest_frame(
+        port: u16,
+        host_port: u16,
+        seq: u32,
+        ack: u32,
+        flags: u8,
+        data: &[u8],
+    ) -> Vec<u8> {
+        let tcp = tcp_segment(
+            (&GUEST_IP, port),
+            (&HOST_IP, host_port),
+            seq,
+            ack,
+            flags,
+            data,
+        );
+        let mut frame = build_eth_header(&GATEWAY_MAC, &DEFAULT_MAC, ETHERTYPE_IPV4).to_vec();
+        frame.extend_from_slice(&build_ip_header(
+            &GUEST_IP,
+            &HOST_IP,
+            IP_PROTO_TCP,
+            tcp.len(),
+            0,
+        ));
+        frame.extend_from_slice(&tcp);
+        frame
+    }
+
+    /// Guest port, sequence number, flags and data of a TCP segment sent to
+    /// the guest.
+    fn to_guest(frame: &[u8]) -> Option<(u16, u32, u8, Vec<u8>)> {
+        let ip = frame.get(14..)?;
+        if u16::from_be_bytes([frame[12], frame[13]]) != ETHERTYPE_IPV4 || ip[9] != IP_PROTO_TCP {
+            return None;
+        }
+        let total_len = u16::from_be_bytes([ip[2], ip[3]]) as usize;
+        let tcp = &ip[(ip[0] & 0x0f) as usize * 4..total_len];
+        let port = u16::from_be_bytes([tcp[2], tcp[3]]);
+        let seq = u32::from_be_bytes([tcp[4], tcp[5], tcp[6], tcp[7]]);
+        let data = tcp[(tcp[12] >> 4) as usize * 4..].to_vec();
+        Some((port, seq, tcp[13], data))
+    }
+
+    /// Dispatches a guest frame the way the lock-free stack does, recording
+    /// which worker answered.
+    fn deliver(workers: &mut [NatState], frame: &[u8], out: &mut Vec<(usize, Vec<u8>)>) {
+        let shard = shard_for_frame(frame, workers.len());
+        if let Some(response) = process_frame(frame, &mut workers[shard], &mut None) {
+            out.push((shard, response));
+        }
+    }
+
+    fn poll(workers: &mut [NatState], out: &mut Vec<(usize, Vec<u8>)>) {
+        let mut responses = Vec::new();
+        for (shard, state) in workers.iter_mut().enumerate() {
+            poll_nat_sockets(state, &mut responses);
+            out.extend(responses.drain(..).map(|frame| (shard, frame)));
+        }
+    }
+
+    #[test]
+    fn test_net_mtu() {
+        assert_eq!(net_mtu(Some(1400)).unwrap(), 1400);
+        assert_eq!(net_mtu(Some(MIN_MTU)).unwrap(), MIN_MTU);
+        assert_eq!(net_mtu(Some(MAX_MTU)).unwrap(), MAX_MTU);
+        assert!(net_mtu(Some(1000)).is_err());
+        assert!(net_mtu(Some(9001)).is_err());
+    }
+
+    #[test]
+    fn test_dont_fragment_over_mtu() {
+        let mut state = NatState::new(16, 1400);
+        let datagram = |len: usize| {
+            let mut udp = vec![0x30, 0x39, 0x1f, 0x90, 0, 0, 0, 0];
+            udp.extend(std::iter::repeat_n(0, len));
+            let mut frame = build_eth_header(&GATEWAY_MAC, &DEFAULT_MAC, ETHERTYPE_IPV4).to_vec();
+            // build_ip_header sets DF.
+            frame.extend_from_slice(&build_ip_header(
+                &GUEST_IP,
+                &[192, 0, 2, 1],
+                IP_PROTO_UDP,
+                udp.len(),
+                0,
+            ));
+            frame.extend_from_slice(&udp);
+            frame
+        };
+
+        let frame = process_frame(&datagram(1500), &mut state, &mut None).unwrap();
+        assert_eq!(&frame[0..6], &DEFAULT_MAC);
+        let icmp = &frame[34..];
+        assert_eq!((icmp[0], icmp[1]), (3, 4));
+        assert_eq!(u16::from_be_bytes([icmp[6], icmp[7]]), 1400);
+
+        // Without DF, the datagram is forwarded instead.
+        let mut frame = datagram(1500);
+        frame[20] = 0;
+        frame[24..26].copy_from_slice(&[0, 0]);
+        let cksum = checksum(&frame[14..34]);
+        frame[24..26].copy_from_slice(&cksum.to_be_bytes());
+        assert!(process_frame(&frame, &mut state, &mut None).is_none());
+    }
+
+    #[test]
+    fn test_shard_is_direction_independent() {
+        let remote6 = {
+            let mut ip = IPV6_PREFIX;
+            ip[15] = 0x42;
+            ip
+        };
+        let flows: [(&[u8], &[u8]); 3] = [
+            (&GUEST_IP, &[93, 184, 216, 34]),
+            (&GUEST_IP, &HOST_IP),
+            (&GATEWAY_IP6, &remote6),
+        ];
+        for (guest, remote) in flows {
+            for port in 40000..40256u16 {
+                let frame = |src: (&[u8], u16), dst: (&[u8], u16)| {
+                    let tcp = tcp_segment(src, dst, 1, 0, TCP_SYN, &[]);
+                    let mut frame = Vec::new();
+                    push_ip_headers(
+                        &mut frame,
+                        &DEFAULT_MAC,
+                        src.0,
+                        dst.0,
+                        IP_PROTO_TCP,
+                        tcp.len(),
+                    );
+                    frame.extend_from_slice(&tcp);
+                    frame
+                };
+                assert_eq!(
+                    shard_for_frame(&frame((guest, port), (remote, 443)), WORKERS),
+                    shard_for_frame(&frame((remote, 443), (guest, port)), WORKERS),
+                );
+            }
+        }
+
+        // Connections to a single server still spread over the workers.
+        let used: HashSet<usize> = (40000..40064u16)
+            .map(|port| shard_for_frame(&guest_frame(port, 443, 1, 0, TCP_SYN, &[]), WORKERS))
+            .collect();
+        assert_eq!(used.len(), WORKERS);
+    }
+
+    #[test]
+    fn test_multi_worker_transfer() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let host_port = listener.local_addr().unwrap().port();
+        let mut workers: Vec<NatState> = (0..WORKERS)
+            .map(|_| NatState::new(64, DEFAULT_MTU))
+            .collect();
+        let ports: Vec<u16> = (40000..40016).collect();
+        let owner: HashMap<u16, usize> = ports
+            .iter()
+            .map(|&port| {
+                let syn = guest_frame(port, host_port, 1000, 0, TCP_SYN, &[]);
+                (port, shard_for_frame(&syn, WORKERS))
+            })
+            .collect();
+        assert!(owner.values().collect::<HashSet<_>>().len() > 1);
+
+        // Everything sent to the guest comes from the worker owning the
+        // flow, and nothing is reset.
+        let check = |frames: &mut Vec<(usize, Vec<u8>)>| -> Vec<(u16, u32, u8, Vec<u8>)> {
+            frames
+                .drain(..)
+                .filter_map(|(shard, frame)| {
+                    let segment = to_guest(&frame)?;
+                    assert_eq!(shard, owner[&segment.0], "answered by another worker");
+                    assert_eq!(segment.2 & TCP_RST, 0, "connection {} reset", segment.0);
+                    Some(segment)
+                })
+                .collect()
+        };
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let mut frames = Vec::new();
+
+        for &port in &ports {
+            deliver(
+                &mut workers,
+                &guest_frame(port, host_port, 1000, 0, TCP_SYN, &[]),
+                &mut frames,
+            );
+        }
+        let mut server_seq = HashMap::new();
+        while server_seq.len() < ports.len() {
+            assert!(Instant::now() < deadline, "handshakes did not complete");
+            poll(&mut workers, &mut frames);
+            for (port, seq, flags, _) in check(&mut frames) {
+                if flags & (TCP_SYN | TCP_ACK) == TCP_SYN | TCP_ACK {
+                    server_seq.insert(port, seq.wrapping_add(1));
+                }
+            }
+        }
+        let streams: Vec<TcpStream> = ports.iter().map(|_| listener.accept().unwrap().0).collect();
+
+        // Guest to host.
+        for &port in &ports {
+            let data = format!("flow {}", port);
+            let frame = guest_frame(
+                port,
+                host_port,
+                1001,
+                server_seq[&port],
+                TCP_PSH | TCP_ACK,
+                data.as_bytes(),
+            );
+            deliver(&mut workers, &frame, &mut frames);
+        }
+        check(&mut frames);
+        let mut received = HashSet::new();
+        for mut stream in &streams {
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            let mut buf = [0u8; 10];
+            stream.read_exact(&mut buf).unwrap();
+            received.insert(String::from_utf8(buf.to_vec()).unwrap());
+        }
+        let expected: HashSet<String> = ports.iter().map(|port| format!("flow {}", port)).collect();
+        assert_eq!(received, expected);
+
+        // Host to guest.
+        for mut stream in &streams {
+            stream.write_all(b"ok").unwrap();
+        }
+        let mut replied = HashSet::new();
+        while replied.len() < ports.len() {
+            assert!(Instant::now() < deadline, "replies did not reach the guest");
+            poll(&mut workers, &mut frames);
+            for (port, seq, _, data) in check(&mut frames) {
+                if data == b"ok" {
+                    assert_eq!(seq, server_seq[&port]);
+                    replied.insert(port);
+                }
+            }
+        }
+
+        // Each worker holds exactly the connections it owns.
+        for (shard, state) in workers.iter().enumerate() {
+            let owned = owner.values().filter(|&&owner| owner == shard).count();
+            assert_eq!(state.tcp_connections(), owned);
+        }
+
+        for &port in &ports {
+            let frame = guest_frame(
+                port,
+                host_port,
+                1011,
+                server_seq[&port].wrapping_add(2),
+                TCP_FIN | TCP_ACK,
+                &[],
+            );
+            deliver(&mut workers, &frame, &mut frames);
+        }
+        poll(&mut workers, &mut frames);
+        check(&mut frames);
+    }
+
+    #[test]
+    fn test_tcp_half_close() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let host_port = listener.local_addr().unwrap().port();
+        let mut state = NatState::new(64, DEFAULT_MTU);
+        let port = 40000;
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let mut responses = Vec::new();
+
+        let syn = guest_frame(port, host_port, 1000, 0, TCP_SYN, &[]);
+        assert!(process_frame(&syn, &mut state, &mut None).is_none());
+        let server_seq = loop {
+            assert!(Instant::now() < deadline, "handshake did not complete");
+            poll_nat_sockets(&mut state, &mut responses);
+            if let Some((_, seq, _, _)) = responses
+                .iter()
+                .filter_map(|frame| to_guest(frame))
+                .find(|segment| segment.2 & (TCP_SYN | TCP_ACK) == TCP_SYN | TCP_ACK)
+            {
+                break seq.wrapping_add(1);
+            }
+        };
+        responses.clear();
+        let (mut stream, _) = listener.accept().unwrap();
+
+        // The guest sends its request and closes its end: the FIN is
+        // acknowledged, not answered with one.
+        let request = guest_frame(
+            port,
+            host_port,
+            1001,
+            server_seq,
+            TCP_FIN | TCP_PSH | TCP_ACK,
+            b"request",
+        );
+        let ack = process_frame(&request, &mut state, &mut None).unwrap();
+        let (_, _, flags, _) = to_guest(&ack).unwrap();
+        assert_eq!(flags & (TCP_FIN | TCP_RST), 0);
+
+        // The remote sees the end of the request, then answers.
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut received = Vec::new();
+        stream.read_to_end(&mut received).unwrap();
+        assert_eq!(received, b"request");
+        stream.write_all(b"response").unwrap();
+        drop(stream);
+
+        // The response reaches the guest in full, followed by our FIN.
+        let mut relayed = Vec::new();
+        let fin_seq = loop {
+            assert!(
+                Instant::now() < deadline,
+                "response did not reach the guest"
+            );
+            poll_nat_sockets(&mut state, &mut responses);
+            let mut fin = None;
+            // Each frame is looked at once, so that no data counts twice.
+            for (_, seq, flags, data) in responses.drain(..).filter_map(|frame| to_guest(&frame)) {
+                assert_eq!(flags & TCP_RST, 0, "connection reset");
+                if !data.is_empty() {
+                    assert_eq!(seq, server_seq.wrapping_add(relayed.len() as u32));
+                    relayed.extend_from_slice(&data);
+                }
+                if flags & TCP_FIN != 0 {
+                    fin = Some(seq);
+                }
+            }
+            if let Some(seq) = fin {
+                break seq;
+            }
+        };
+        assert_eq!(relayed, b"response");
+        assert_eq!(fin_seq, server_seq.wrapping_add(relayed.len() as u32));
+        assert_eq!(state.tcp_connections(), 1);
+
+        // The guest acknowledges our FIN, which closes the connection.
+        let last_ack = guest_frame(port, host_port, 1009, fin_seq.wrapping_add(1), TCP_ACK, &[]);
+        assert!(process_frame(&last_ack, &mut state, &mut None).is_none());
+        assert_eq!(state.tcp_connections(), 0);
+    }
+}
@@ -0,0 +1,74 @@
+//! Traffic counters for a VM's network stack.
+
+use crate::InterfaceStats;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Frames and bytes crossing the guest link, counted from the guest's point
+/// of view: `rx` is traffic delivered to the guest, `tx` traffic it sent.
+///
+/// Shared between the stack's threads and readers of the stats, so every
+/// counter is a relaxed atomic; no ordering between them is needed.
+#[derive(Debug, Default)]
+pub struct NetCounters {
+    rx_bytes: AtomicU64,
+    rx_packets: AtomicU64,
+    rx_dropped: AtomicU64,
+    tx_bytes: AtomicU64,
+    tx_packets: AtomicU64,
+    tx_dropped: AtomicU64,
+    nat_connections: AtomicU64,
+}
+
+impl NetCounters {
+    /// A frame of `len` bytes was delivered to the guest.
+    #[inline]
+    pub fn record_to_guest(&self, len: usize) {
+        self.rx_bytes.fetch_add(len as u64, Ordering::Relaxed);
+        self.rx_packets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A frame for the guest was dropped before it could be delivered.
+    #[inline]
+    pub fn record_to_guest_dropped(&self) {
+        self.rx_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A frame of `len` bytes was received from the guest.
+    #[inline]
+    pub fn record_from_guest(&self, len: usize) {
+        self.tx_bytes.fetch_add(len as u64, Ordering::Relaxed);
+        self.tx_packets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A frame from the guest was dropped before it was processed.
+    #[inline]
+    pub fn record_from_guest_dropped(&self) {
+        self.tx_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The NAT of one of the stack's workers went from `before` to `after`
+    /// tracked TCP connections.
+    #[inline]
+    pub fn nat_connections_changed(&self, before: usize, after: usize) {
+        if after > before {
+            self.nat_connections
+                .fetch_add((after - before) as u64, Ordering::Relaxed);
+        } else {
+            self.nat_connections
+                .fetch_sub((before - after) as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Current values of the counters.
+    pub fn snapshot(&self) -> InterfaceStats {
+        InterfaceStats {
+            rx_bytes: self.rx_bytes.load(Ordering::Relaxed),
+            rx_packets: self.rx_packets.load(Ordering::Relaxed),
+            rx_dropped: self.rx_dropped.load(Ordering::Relaxed),
+            tx_bytes: self.tx_bytes.load(Ordering::Relaxed),
+            tx_packets: self.tx_packets.load(Ordering::Relaxed),
+            tx_dropped: self.tx_dropped.load(Ordering::Relaxed),
+            nat_connections: self.nat_connections.load(Ordering::Relaxed),
+        }
+    }
+}
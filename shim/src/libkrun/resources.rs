@@ -0,0 +1,171 @@
+//! vCPU and memory sizing for libkrun VMs.
+
+use crate::error::ShimError;
+use crate::types::HostConfig;
+
+/// vCPUs given to a VM when no CPU limit is set.
+pub const DEFAULT_VCPUS: u8 = 2;
+/// Memory given to a VM when no memory limit is set.
+pub const DEFAULT_RAM_MIB: u32 = 1100;
+/// Smallest VM the guest kernel and ross-init reliably boot in.
+const MIN_RAM_MIB: u32 = 128;
+/// CFS period assumed when only a quota is given.
+const DEFAULT_CPU_PERIOD: u64 = 100_000;
+
+const MIB: u64 = 1024 * 1024;
+
+/// Size of the VM backing a container, as passed to `krun_set_vm_config`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VmResources {
+    pub vcpus: u8,
+    pub ram_mib: u32,
+}
+
+impl Default for VmResources {
+    fn default() -> Self {
+        Self {
+            vcpus: DEFAULT_VCPUS,
+            ram_mib: DEFAULT_RAM_MIB,
+        }
+    }
+}
+
+impl VmResources {
+    /// Sizes the VM from the container's `--cpus` and `--memory` limits,
+    /// rejecting requests the host cannot satisfy.
+    pub fn from_host_config(host_config: &HostConfig) -> Result<Self, ShimError> {
+        Self::resolve(host_config, host_cpus(), host_memory())
+    }
+
+    fn resolve(
+        host_config: &HostConfig,
+        host_cpus: u32,
+        host_memory: Option<u64>,
+    ) -> Result<Self, ShimError> {
+        let vcpus = match host_config.cpu_quota {
+            Some(quota) if quota > 0 => {
+                let period = host_config
+                    .cpu_period
+                    .filter(|p| *p > 0)
+                    .unwrap_or(DEFAULT_CPU_PERIOD);
+                // A VM can only have whole vCPUs, so round fractional limits up.
+                let vcpus = (quota as u64).div_ceil(period);
+                if vcpus > host_cpus as u64 {
+                    return Err(ShimError::RuntimeError(format!(
+                        "requested {} CPUs but the host only has {}",
+                        vcpus, host_cpus
+                    )));
+                }
+                u8::try_from(vcpus).map_err(|_| {
+                    ShimError::RuntimeError(format!(
+                        "requested {} CPUs, at most 255 are supported",
+                        vcpus
+                    ))
+                })?
+            }
+            _ => DEFAULT_VCPUS.min(host_cpus.clamp(1, u8::MAX as u32) as u8),
+        };
+
+        let ram_mib = match host_config.memory_limit {
+            Some(limit) if limit > 0 => {
+                let ram_mib = (limit as u64).div_ceil(MIB);
+                if ram_mib < MIN_RAM_MIB as u64 {
+                    return Err(ShimError::RuntimeError(format!(
+                        "memory limit must be at least {}MiB for a VM",
+                        MIN_RAM_MIB
+                    )));
+                }
+                if let Some(host_memory) = host_memory
+                    && ram_mib > host_memory / MIB
+                {
+                    return Err(ShimError::RuntimeError(format!(
+                        "requested {}MiB of memory but the host only has {}MiB",
+                        ram_mib,
+                        host_memory / MIB
+                    )));
+                }
+                u32::try_from(ram_mib).map_err(|_| {
+                    ShimError::RuntimeError(format!("memory limit of {}MiB is too large", ram_mib))
+                })?
+            }
+            _ => DEFAULT_RAM_MIB,
+        };
+
+        Ok(Self { vcpus, ram_mib })
+    }
+}
+
+fn host_cpus() -> u32 {
+    std::thread::available_parallelism()
+        .map(|n| n.get() as u32)
+        .unwrap_or(1)
+}
+
+/// Physical memory of the host in bytes, if it can be determined.
+#[cfg(target_os = "macos")]
+fn host_memory() -> Option<u64> {
+    let mut memsize: u64 = 0;
+    let mut len = std::mem::size_of::<u64>();
+    let ret = unsafe {
+        libc::sysctlbyname(
+            c"hw.memsize".as_ptr(),
+            (&mut memsize as *mut u64).cast(),
+            &mut len,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    (ret == 0).then_some(memsize)
+}
+
+/// Physical memory of the host in bytes, if it can be determined.
+#[cfg(not(target_os = "macos"))]
+fn host_memory() -> Option<u64> {
+    let pages = unsafe { libc::sysconf(libc::_SC_PHYS_PAGES) };
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    (pages > 0 && page_size > 0).then(|| pages as u64 * page_size as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host_config(memory_limit: Option<i64>, cpu_quota: Option<i64>) -> HostConfig {
+        HostConfig {
+            memory_limit,
+            cpu_quota,
+            cpu_period: cpu_quota.map(|_| 100_000),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_defaults_when_unset() {
+        let resources = VmResources::resolve(&host_config(None, None), 8, Some(16 << 30)).unwrap();
+        assert_eq!(resources, VmResources::default());
+
+        let resources = VmResources::resolve(&host_config(None, None), 1, Some(16 << 30)).unwrap();
+        assert_eq!(resources.vcpus, 1);
+    }
+
+    #[test]
+    fn test_limits_round_up() {
+        let config = host_config(Some(512 * 1024 * 1024 + 1), Some(150_000));
+        let resources = VmResources::resolve(&config, 8, Some(16 << 30)).unwrap();
+        assert_eq!(resources.vcpus, 2);
+        assert_eq!(resources.ram_mib, 513);
+    }
+
+    #[test]
+    fn test_rejects_requests_exceeding_host() {
+        let config = host_config(Some(32 << 30), None);
+        assert!(VmResources::resolve(&config, 8, Some(16 << 30)).is_err());
+        assert!(VmResources::resolve(&config, 8, None).is_ok());
+
+        let config = host_config(None, Some(900_000));
+        assert!(VmResources::resolve(&config, 8, Some(16 << 30)).is_err());
+
+        let config = host_config(Some(64 * 1024 * 1024), None);
+        assert!(VmResources::resolve(&config, 8, Some(16 << 30)).is_err());
+    }
+}
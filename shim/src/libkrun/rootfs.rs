@@ -6,9 +6,27 @@
 use crate::ShimError;
 use crate::rootfs as common_rootfs;
 use crate::types::SnapshotMount;
-use std::path::Path;
+use std::collections::HashMap;
+use std::ffi::{CStr, OsStr};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
 use tokio::fs;
 
+/// xattr through which libkrun's virtio-fs reports ownership and mode to the
+/// guest in place of the host values ("uid:gid:0mode").
+const OVERRIDE_STAT_XATTR: &CStr = c"user.containers.override_stat";
+
+/// OCI whiteout markers: `.wh.<name>` deletes `<name>` from lower layers and
+/// `.wh..wh..opq` hides a directory's lower-layer contents.
+const WHITEOUT_PREFIX: &[u8] = b".wh.";
+const OPAQUE_WHITEOUT: &[u8] = b".wh..wh..opq";
+
+/// How overlayfs marks an opaque directory, depending on whether it was
+/// mounted with `userxattr`. Layers unpacked on Linux use these, and
+/// character devices numbered 0/0 for deleted entries, instead of markers.
+const OVERLAY_OPAQUE_XATTRS: [&CStr; 2] = [c"trusted.overlay.opaque", c"user.overlay.opaque"];
+
 /// The ross-init binary, compiled for Linux aarch64.
 /// This is embedded at compile time from the guest crate build output.
 #[cfg(all(feature = "libkrun", target_os = "macos"))]
@@ -84,21 +102,34 @@ fn parse_overlay_options(options: &[String]) -> Result<(Vec<String>, Option<Stri
 
     for opt in options {
         if let Some(dirs) = opt.strip_prefix("lowerdir=") {
-            lowerdirs = dirs.split(':').map(String::from).collect();
+            lowerdirs = ross_mount::split_lowerdir(dirs);
         } else if let Some(dir) = opt.strip_prefix("upperdir=") {
-            upperdir = Some(dir.to_string());
+            upperdir = Some(ross_mount::unescape_overlay_path(dir));
         }
     }
 
     Ok((lowerdirs, upperdir))
 }
 
+/// Copies a layer onto the layers already flattened into `dst`, keeping
+/// ownership, mode, extended attributes and hardlinks.
+///
+/// The layer's whiteouts are applied first, so they only ever remove content
+/// from lower layers and never the layer's own files.
 async fn copy_dir_contents(src: &Path, dst: &Path) -> Result<(), ShimError> {
     if !src.exists() {
         return Ok(());
     }
 
-    let mut stack = vec![(src.to_path_buf(), std::path::PathBuf::new())];
+    apply_whiteouts(src, dst).await?;
+
+    let privileged = nix::unistd::geteuid().is_root();
+    // First copy of each multiply-linked file, by (device, inode).
+    let mut links: HashMap<(u64, u64), PathBuf> = HashMap::new();
+    // Directory metadata is applied once the walk is done, so read-only
+    // directories can still be filled.
+    let mut dirs = Vec::new();
+    let mut stack = vec![(src.to_path_buf(), PathBuf::new())];
 
     while let Some((current_src, relative)) = stack.pop() {
         let current_dst = dst.join(&relative);
@@ -111,46 +142,103 @@ async fn copy_dir_contents(src: &Path, dst: &Path) -> Result<(), ShimError> {
 
         while let Some(entry) = entries.next_entry().await? {
             let name = entry.file_name();
-            let name_str = name.to_string_lossy();
-
-            if name_str.starts_with(".wh.") {
-                if name_str == ".wh..wh..opq" {
-                    if current_dst.exists() {
-                        clear_directory(&current_dst).await?;
-                    }
-                } else {
-                    let target_name = name_str.strip_prefix(".wh.").unwrap();
-                    let target_path = current_dst.join(target_name);
-                    if target_path.exists() {
-                        if target_path.is_dir() {
-                            fs::remove_dir_all(&target_path).await?;
-                        } else {
-                            fs::remove_file(&target_path).await?;
-                        }
-                    }
-                }
+            if name.as_bytes().starts_with(WHITEOUT_PREFIX) {
                 continue;
             }
 
             let src_path = entry.path();
             let dst_path = current_dst.join(&name);
-            let file_type = entry.file_type().await?;
+            let meta = fs::symlink_metadata(&src_path).await?;
+            let file_type = meta.file_type();
 
             if file_type.is_dir() {
+                // A directory replacing a lower layer's file.
+                if let Ok(existing) = fs::symlink_metadata(&dst_path).await
+                    && !existing.is_dir()
+                {
+                    fs::remove_file(&dst_path).await?;
+                }
                 fs::create_dir_all(&dst_path).await?;
+                dirs.push((src_path.clone(), dst_path, meta));
                 stack.push((src_path, relative.join(&name)));
             } else if file_type.is_file() {
                 if let Some(parent) = dst_path.parent() {
                     fs::create_dir_all(parent).await?;
                 }
+                remove_path(&dst_path).await?;
+                if meta.nlink() > 1 {
+                    let key = (meta.dev(), meta.ino());
+                    if let Some(first) = links.get(&key) {
+                        fs::hard_link(first, &dst_path).await?;
+                        continue;
+                    }
+                    links.insert(key, dst_path.clone());
+                }
                 fs::copy(&src_path, &dst_path).await?;
+                copy_metadata(src_path, dst_path, meta, privileged).await?;
             } else if file_type.is_symlink() {
                 let link_target = fs::read_link(&src_path).await?;
-                if dst_path.exists() {
-                    fs::remove_file(&dst_path).await?;
+                remove_path(&dst_path).await?;
+                fs::symlink(&link_target, &dst_path).await?;
+                copy_metadata(src_path, dst_path, meta, privileged).await?;
+            }
+        }
+    }
+
+    // Innermost first, so restricting a parent doesn't block its children.
+    for (src_path, dst_path, meta) in dirs.into_iter().rev() {
+        copy_metadata(src_path, dst_path, meta, privileged).await?;
+    }
+
+    Ok(())
+}
+
+/// Applies the whiteouts of layer `src` to the lower layers in `dst`.
+///
+/// `.wh.<name>` or a 0/0 character device `<name>` deletes `<name>`, and
+/// `.wh..wh..opq` or an overlayfs opaque xattr empties its directory.
+/// Whiteouts only apply below real directories of the lower layers: one
+/// under a lower layer's symlink would otherwise reach wherever it points.
+async fn apply_whiteouts(src: &Path, dst: &Path) -> Result<(), ShimError> {
+    let mut stack = vec![PathBuf::new()];
+
+    while let Some(relative) = stack.pop() {
+        let current_dst = lower_dir(dst, &relative).await?;
+        if let Some(dir) = &current_dst
+            && is_opaque_dir(&src.join(&relative))
+        {
+            clear_directory(dir).await?;
+        }
+
+        let mut entries = match fs::read_dir(src.join(&relative)).await {
+            Ok(e) => e,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name();
+            let name = name.as_bytes();
+
+            if name == OPAQUE_WHITEOUT {
+                if let Some(dir) = &current_dst {
+                    clear_directory(dir).await?;
+                }
+            } else if let Some(target) = name.strip_prefix(WHITEOUT_PREFIX) {
+                if let Some(dir) = &current_dst
+                    && !matches!(target, b"" | b"." | b"..")
+                {
+                    remove_path(&dir.join(OsStr::from_bytes(target))).await?;
+                }
+            } else {
+                let meta = fs::symlink_metadata(entry.path()).await?;
+                if is_whiteout(&meta) {
+                    if let Some(dir) = &current_dst {
+                        remove_path(&dir.join(OsStr::from_bytes(name))).await?;
+                    }
+                } else if meta.is_dir() {
+                    stack.push(relative.join(OsStr::from_bytes(name)));
                 }
-                #[cfg(unix)]
-                tokio::fs::symlink(&link_target, &dst_path).await?;
             }
         }
     }
@@ -158,15 +246,349 @@ async fn copy_dir_contents(src: &Path, dst: &Path) -> Result<(), ShimError> {
     Ok(())
 }
 
+/// Whether `meta` is an overlayfs whiteout: a character device numbered 0/0.
+fn is_whiteout(meta: &std::fs::Metadata) -> bool {
+    meta.file_type().is_char_device() && meta.rdev() == 0
+}
+
+/// Whether overlayfs would treat the directory `path` as opaque.
+fn is_opaque_dir(path: &Path) -> bool {
+    OVERLAY_OPAQUE_XATTRS
+        .iter()
+        .any(|name| super::xattr::get(path, name).is_ok_and(|value| value == b"y"))
+}
+
+/// `dst` joined with `relative`, if each of its components is a directory in
+/// `dst` and not a symlink.
+async fn lower_dir(dst: &Path, relative: &Path) -> Result<Option<PathBuf>, ShimError> {
+    let mut path = dst.to_path_buf();
+    for component in relative.components() {
+        path.push(component);
+        match fs::symlink_metadata(&path).await {
+            Ok(meta) if meta.is_dir() => {}
+            Ok(_) => return Ok(None),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(Some(path))
+}
+
+/// Removes whatever is at `path`, without following symlinks.
+async fn remove_path(path: &Path) -> Result<(), ShimError> {
+    match fs::symlink_metadata(path).await {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(path).await?,
+        Ok(_) => fs::remove_file(path).await?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    Ok(())
+}
+
+/// Copies extended attributes, ownership and permission bits (including
+/// setuid/setgid) from the layer entry `src`, described by `meta`, to `dst`.
+///
+/// Without the privilege to chown, ownership and mode are recorded in
+/// `OVERRIDE_STAT_XATTR` instead, and directories stay writable on the host
+/// so later layers can still be applied.
+async fn copy_metadata(
+    src: PathBuf,
+    dst: PathBuf,
+    meta: std::fs::Metadata,
+    privileged: bool,
+) -> Result<(), ShimError> {
+    tokio::task::spawn_blocking(move || -> std::io::Result<()> {
+        copy_xattrs(&src, &dst);
+
+        let mode = meta.mode() & 0o7777;
+        let is_symlink = meta.file_type().is_symlink();
+        if privileged {
+            // Before chmod, as chown clears the setuid and setgid bits.
+            std::os::unix::fs::lchown(&dst, Some(meta.uid()), Some(meta.gid()))?;
+        } else {
+            let stat = format!("{}:{}:0{:o}", meta.uid(), meta.gid(), mode);
+            if let Err(e) = super::xattr::set(&dst, OVERRIDE_STAT_XATTR, stat.as_bytes()) {
+                tracing::debug!("Failed to record ownership of {}: {}", dst.display(), e);
+            }
+        }
+
+        if !is_symlink {
+            let host_mode = if !privileged && meta.is_dir() {
+                mode | 0o700
+            } else {
+                mode
+            };
+            std::fs::set_permissions(&dst, std::fs::Permissions::from_mode(host_mode))?;
+        }
+        Ok(())
+    })
+    .await
+    .map_err(|e| ShimError::RuntimeError(format!("metadata copy task failed: {}", e)))??;
+    Ok(())
+}
+
+/// Copies the extended attributes of `src` to `dst`. Attributes the
+/// filesystem or our privileges don't allow are skipped.
+fn copy_xattrs(src: &Path, dst: &Path) {
+    let names = match super::xattr::list(src) {
+        Ok(names) => names,
+        Err(e) => {
+            tracing::debug!("Failed to list xattrs of {}: {}", src.display(), e);
+            return;
+        }
+    };
+    for name in names {
+        // Overlayfs markers describe the layer, not the flattened rootfs.
+        if OVERLAY_OPAQUE_XATTRS.contains(&name.as_c_str()) {
+            continue;
+        }
+        let result =
+            super::xattr::get(src, &name).and_then(|value| super::xattr::set(dst, &name, &value));
+        if let Err(e) = result {
+            tracing::debug!(
+                "Failed to copy xattr {:?} to {}: {}",
+                name,
+                dst.display(),
+                e
+            );
+        }
+    }
+}
+
+/// Removes the contents of `dir`, which must not be reached through a
+/// symlink. Links in it are removed, never followed.
 async fn clear_directory(dir: &Path) -> Result<(), ShimError> {
     let mut entries = fs::read_dir(dir).await?;
     while let Some(entry) = entries.next_entry().await? {
-        let path = entry.path();
-        if path.is_dir() {
-            fs::remove_dir_all(&path).await?;
-        } else {
-            fs::remove_file(&path).await?;
-        }
+        remove_path(&entry.path()).await?;
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ross_snapshotter::{Layer, OverlaySnapshotter};
+    use ross_store::FileSystemStore;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_copy_dir_contents_preserves_mode_and_hardlinks() {
+        let src = TempDir::new().unwrap();
+        let dst = TempDir::new().unwrap();
+
+        let bin = src.path().join("usr/bin");
+        std::fs::create_dir_all(&bin).unwrap();
+        std::fs::write(bin.join("sudo"), b"binary").unwrap();
+        std::fs::set_permissions(bin.join("sudo"), std::fs::Permissions::from_mode(0o4755))
+            .unwrap();
+        std::fs::hard_link(bin.join("sudo"), bin.join("sudoedit")).unwrap();
+        std::os::unix::fs::symlink("sudo", bin.join("link")).unwrap();
+
+        copy_dir_contents(src.path(), dst.path()).await.unwrap();
+
+        let bin = dst.path().join("usr/bin");
+        let sudo = std::fs::metadata(bin.join("sudo")).unwrap();
+        assert_eq!(sudo.mode() & 0o7777, 0o4755);
+        let sudoedit = std::fs::metadata(bin.join("sudoedit")).unwrap();
+        assert_eq!(sudo.ino(), sudoedit.ino());
+        assert_eq!(
+            std::fs::read_link(bin.join("link")).unwrap(),
+            Path::new("sudo")
+        );
+    }
+
+    /// Writes `files` (relative path, contents) under `root`; directories end in '/'.
+    fn write_layer(root: &Path, files: &[(&str, &str)]) {
+        for (path, contents) in files {
+            let path = root.join(path);
+            if path.to_string_lossy().ends_with('/') {
+                std::fs::create_dir_all(&path).unwrap();
+            } else {
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(&path, contents).unwrap();
+            }
+        }
+    }
+
+    fn list(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// An uncompressed layer of `files` (path, contents); directories end
+    /// in '/'.
+    fn layer_tar(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            if path.ends_with('/') {
+                header.set_entry_type(tar::EntryType::Directory);
+                header.set_mode(0o755);
+            } else {
+                header.set_entry_type(tar::EntryType::Regular);
+                header.set_mode(0o644);
+            }
+            header.set_size(contents.len() as u64);
+            header.set_uid(0);
+            header.set_gid(0);
+            header.set_mtime(0);
+            builder
+                .append_data(&mut header, path, contents.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_flatten_pulled_layers_applies_whiteouts() {
+        let store_dir = TempDir::new().unwrap();
+        let snap_dir = TempDir::new().unwrap();
+        let target = TempDir::new().unwrap();
+        let store = Arc::new(FileSystemStore::new(store_dir.path()).await.unwrap());
+        let snapshotter = OverlaySnapshotter::new(snap_dir.path(), store.clone())
+            .await
+            .unwrap();
+
+        let lower = layer_tar(&[
+            ("etc/a", "lower"),
+            ("etc/b", "lower"),
+            ("opt/app/old", "lower"),
+            ("var/cache/", ""),
+            ("var/log", "lower"),
+        ]);
+        // Replaces etc/a, deletes etc/b and var/cache, makes opt/app opaque
+        // while adding its own file there.
+        let middle = layer_tar(&[
+            ("etc/a", "middle"),
+            ("etc/.wh.b", ""),
+            ("opt/app/.wh..wh..opq", ""),
+            ("opt/app/new", "middle"),
+            ("var/.wh.cache", ""),
+        ]);
+        // Brings etc/b back and deletes a file the middle layer added.
+        let upper = layer_tar(&[("etc/b", "upper"), ("opt/app/.wh.new", "")]);
+
+        let media_type = "application/vnd.oci.image.layer.v1.tar";
+        let mut layers = Vec::new();
+        for blob in [lower, middle, upper] {
+            let (digest, _) = store.put_blob(media_type, &blob, None).await.unwrap();
+            let digest = format!("{}:{}", digest.algorithm, digest.hash);
+            layers.push(Layer {
+                digest: digest.clone(),
+                media_type: media_type.to_string(),
+                key: digest,
+                labels: HashMap::new(),
+            });
+        }
+        let extracted = snapshotter
+            .extract_layers(None, layers, |_, _| {})
+            .await
+            .unwrap();
+        let mounts = snapshotter
+            .prepare("container", Some(&extracted[2].0), HashMap::new())
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|mount| SnapshotMount {
+                mount_type: mount.mount_type,
+                source: mount.source,
+                options: mount.options,
+            })
+            .collect::<Vec<_>>();
+        prepare_from_mounts(&mounts, target.path()).await.unwrap();
+
+        let root = target.path();
+        assert_eq!(list(&root.join("etc")), ["a", "b", "resolv.conf"]);
+        assert_eq!(
+            std::fs::read_to_string(root.join("etc/a")).unwrap(),
+            "middle"
+        );
+        assert_eq!(
+            std::fs::read_to_string(root.join("etc/b")).unwrap(),
+            "upper"
+        );
+        assert!(list(&root.join("opt/app")).is_empty());
+        assert_eq!(list(&root.join("var")), ["log", "tmp"]);
+        assert_eq!(
+            std::fs::read_to_string(root.join("var/log")).unwrap(),
+            "lower"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_copy_dir_contents_opaque_keeps_own_files() {
+        let lower = TempDir::new().unwrap();
+        let upper = TempDir::new().unwrap();
+        let target = TempDir::new().unwrap();
+
+        write_layer(
+            lower.path(),
+            &[("data/old", "lower"), ("data/sub/x", "lower")],
+        );
+        // Many entries, so the opaque marker isn't read first.
+        let mut files: Vec<(String, &str)> = (0..32)
+            .map(|i| (format!("data/file{i}"), "upper"))
+            .collect();
+        files.push(("data/.wh..wh..opq".to_string(), ""));
+        let files: Vec<(&str, &str)> = files.iter().map(|(p, c)| (p.as_str(), *c)).collect();
+        write_layer(upper.path(), &files);
+
+        copy_dir_contents(lower.path(), target.path())
+            .await
+            .unwrap();
+        copy_dir_contents(upper.path(), target.path())
+            .await
+            .unwrap();
+
+        let names = list(&target.path().join("data"));
+        assert_eq!(names.len(), 32);
+        assert!(names.iter().all(|n| n.starts_with("file")));
+    }
+
+    #[tokio::test]
+    async fn test_whiteouts_do_not_follow_lower_symlinks() {
+        let outside = TempDir::new().unwrap();
+        let lower = TempDir::new().unwrap();
+        let upper = TempDir::new().unwrap();
+        let target = TempDir::new().unwrap();
+
+        write_layer(
+            outside.path(),
+            &[("passwd", "host"), ("keep/x", "host"), ("secret", "host")],
+        );
+        // A malicious lower layer points etc and its opaque sibling out of
+        // the rootfs.
+        std::fs::create_dir_all(lower.path().join("var")).unwrap();
+        std::os::unix::fs::symlink(outside.path(), lower.path().join("etc")).unwrap();
+        std::os::unix::fs::symlink(outside.path(), lower.path().join("var/lib")).unwrap();
+        write_layer(
+            upper.path(),
+            &[
+                ("etc/.wh.passwd", ""),
+                ("etc/.wh..wh..opq", ""),
+                ("var/lib/.wh.secret", ""),
+                ("var/.wh...", ""),
+            ],
+        );
+
+        copy_dir_contents(lower.path(), target.path())
+            .await
+            .unwrap();
+        copy_dir_contents(upper.path(), target.path())
+            .await
+            .unwrap();
+
+        assert_eq!(list(outside.path()), ["keep", "passwd", "secret"]);
+        assert!(outside.path().join("keep/x").exists());
+        // The upper layer's directory replaces the lower layer's symlink.
+        let etc = std::fs::symlink_metadata(target.path().join("etc")).unwrap();
+        assert!(etc.is_dir());
+        assert!(target.path().join("var").is_dir());
+    }
+}
@@ -1,8 +1,13 @@
 //! KrunShim implementation - main shim logic.
 
 use super::container::ContainerMetadata;
+use super::resources::VmResources;
 use super::rootfs as krun_rootfs;
+use crate::bind::resolve_binds;
+use crate::egress::EgressPolicy;
 use crate::error::ShimError;
+use crate::names::{NameReservation, NameReservations};
+use crate::resolv::DnsConfig;
 use crate::rootfs;
 use crate::shim::{OutputEventStream, Shim};
 use crate::types::*;
@@ -10,38 +15,34 @@ use async_trait::async_trait;
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::Duration;
 use tokio::fs;
 use tokio::sync::RwLock;
 use uuid::Uuid;
 
-#[cfg(all(feature = "libkrun", target_os = "macos"))]
-fn parse_bind_spec(spec: &str) -> Result<(String, String, bool), ShimError> {
-    // Format: host_path:guest_path[:options]
-    // Options is a comma-separated list. We only interpret "ro" for now.
-    let parts: Vec<&str> = spec.splitn(3, ':').collect();
-    if parts.len() < 2 {
-        return Err(ShimError::RuntimeError(format!(
-            "Invalid volume spec '{}', expected HOST_PATH:GUEST_PATH[:OPTIONS]",
-            spec
-        )));
-    }
-
-    let host_path = parts[0].to_string();
-    let guest_path = parts[1].to_string();
-    if !guest_path.starts_with('/') {
-        return Err(ShimError::RuntimeError(format!(
-            "Invalid volume spec '{}': guest path must be absolute",
-            spec
-        )));
-    }
-
-    let read_only = parts
-        .get(2)
-        .map(|opts| opts.split(',').any(|o| o.trim() == "ro"))
-        .unwrap_or(false);
-
-    Ok((host_path, guest_path, read_only))
+/// How often `stop` checks whether the VM has exited.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How long `stop` waits for the VM to exit after SIGKILL.
+const STOP_KILL_GRACE: Duration = Duration::from_secs(5);
+
+/// Sends `signal` to a VM process. A process that is already gone is not an
+/// error.
+fn signal_vm(pid: i32, signal: i32) -> Result<(), ShimError> {
+    if unsafe { libc::kill(pid, signal) } != 0 {
+        let err = std::io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::ESRCH) {
+            return Err(ShimError::RuntimeError(format!(
+                "failed to signal VM process {}: {}",
+                pid, err
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn process_alive(pid: i32) -> bool {
+    let alive = unsafe { libc::kill(pid, 0) == 0 };
+    alive || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
 }
 
 #[cfg(all(feature = "libkrun", target_os = "macos"))]
@@ -57,9 +58,119 @@ fn vsock_port_for_container(container_id: &str) -> u32 {
     50_000 + v as u32
 }
 
+/// Network stacks of running VMs, by container.
+#[cfg(all(feature = "libkrun", target_os = "macos"))]
+type Networks = Arc<std::sync::Mutex<HashMap<String, super::net::NetworkHandle>>>;
+
+/// Gives the VM of container `id` its network device. Bridged containers get
+/// the userspace network stack, falling back to TSI; host mode uses TSI,
+/// which shares the host's network, and a container without network gets a
+/// link leading nowhere. `None` leaves the VM on TSI.
+#[cfg(all(feature = "libkrun", target_os = "macos"))]
+fn connect_vm(
+    id: &str,
+    host_config: &HostConfig,
+    hostname: &str,
+    dns: &DnsConfig,
+    mtu: Option<u16>,
+) -> Result<Option<super::net::VmLink>, ShimError> {
+    use super::net::{DetachedLink, NetConfig, VmLink, VmNetwork, network_available};
+
+    let network_mode = host_config.network()?;
+    let egress = EgressPolicy::from_host_config(host_config)?;
+    if network_mode == NetworkMode::None {
+        tracing::info!(container_id = %id, "Networking disabled");
+        return Ok(Some(VmLink::Detached(DetachedLink::bind(id)?)));
+    }
+    if network_mode == NetworkMode::Host {
+        if !host_config.port_bindings.is_empty() {
+            tracing::warn!(container_id = %id, "Published ports are ignored in host network mode");
+        }
+        tracing::debug!(container_id = %id, "Host network mode, using TSI networking");
+        return Ok(None);
+    }
+    if !network_available() {
+        if egress.is_restricted() {
+            return Err(ShimError::NotSupported(
+                "egress policies need the userspace network stack, which is not available"
+                    .to_string(),
+            ));
+        }
+        tracing::debug!(container_id = %id, "Network stack not available, using TSI networking");
+        return Ok(None);
+    }
+
+    let restricted = egress.is_restricted();
+    let net_config = NetConfig::new(hostname).with_dns(dns).with_egress(egress);
+    match VmNetwork::start(id, &host_config.port_bindings, mtu, net_config) {
+        Ok(network) => {
+            tracing::info!(container_id = %id, "Userspace network stack enabled");
+            Ok(Some(VmLink::Stack(network)))
+        }
+        // TSI can neither publish ports nor filter the guest's flows, so
+        // don't silently drop either.
+        Err(e) if !host_config.port_bindings.is_empty() || restricted => Err(e),
+        Err(e) => {
+            tracing::warn!(container_id = %id, error = %e, "Failed to start network stack, falling back to TSI");
+            Ok(None)
+        }
+    }
+}
+
+/// Waits in the background for the VM of container `id` to exit and records
+/// its exit, resolving to the exit code.
+///
+/// The waiter owns the VM's network device, so the stack and the ports it
+/// publishes stay up for as long as the VM runs, whether or not a client
+/// is still attached to the session that started it.
+#[cfg(all(feature = "libkrun", target_os = "macos"))]
+fn spawn_vm_waiter(
+    containers: Arc<RwLock<HashMap<String, ContainerMetadata>>>,
+    networks: Networks,
+    data_dir: PathBuf,
+    id: String,
+    child: super::child::VmProcess,
+    link: Option<super::net::VmLink>,
+) -> tokio::task::JoinHandle<i32> {
+    if let Some(network) = link.as_ref().and_then(|link| link.handle()) {
+        networks.lock().unwrap().insert(id.clone(), network);
+    }
+
+    tokio::spawn(async move {
+        let network_id = id.clone();
+        let exit_code = tokio::task::spawn_blocking(move || {
+            let exit_code = child.wait();
+            networks.lock().unwrap().remove(&network_id);
+            // Joins the stack's threads, closing the published ports.
+            drop(link);
+            exit_code
+        })
+        .await
+        .unwrap_or(1);
+
+        let mut containers = containers.write().await;
+        if let Some(metadata) = containers.get_mut(&id) {
+            metadata.info.state = ContainerState::Stopped;
+            metadata.info.exit_code = Some(exit_code);
+            metadata.info.mark_finished();
+            metadata.info.pid = None;
+            metadata.vm_pid = None;
+            let _ = metadata.save(&data_dir.join("containers").join(&id)).await;
+        }
+        exit_code
+    })
+}
+
 pub struct KrunShim {
     data_dir: PathBuf,
     containers: Arc<RwLock<HashMap<String, ContainerMetadata>>>,
+    /// Network stacks of running VMs.
+    #[cfg(all(feature = "libkrun", target_os = "macos"))]
+    networks: Networks,
+    /// Link MTU of VMs on the userspace network stack, if configured.
+    #[cfg_attr(not(all(feature = "libkrun", target_os = "macos")), allow(dead_code))]
+    mtu: Option<u16>,
+    names: NameReservations,
 }
 
 impl KrunShim {
@@ -70,6 +181,10 @@ impl KrunShim {
         let shim = Self {
             data_dir: data_dir.to_path_buf(),
             containers: Arc::new(RwLock::new(HashMap::new())),
+            #[cfg(all(feature = "libkrun", target_os = "macos"))]
+            networks: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            mtu: None,
+            names: NameReservations::default(),
         };
 
         shim.load_containers().await?;
@@ -77,6 +192,23 @@ impl KrunShim {
         Ok(shim)
     }
 
+    /// Sets the link MTU of VMs on the userspace network stack, which
+    /// otherwise comes from `ROSS_NET_MTU` or defaults to 1500 bytes.
+    pub fn with_mtu(mut self, mtu: Option<u16>) -> Self {
+        self.mtu = mtu;
+        self
+    }
+
+    /// Claims `name` for a container being created, failing if a container
+    /// has it or another creation claimed it first.
+    async fn reserve_name(&self, name: &str) -> Result<NameReservation, ShimError> {
+        let containers = self.containers.read().await;
+        let taken = containers
+            .values()
+            .any(|m| m.info.name.as_deref() == Some(name));
+        self.names.reserve(name, taken)
+    }
+
     async fn load_containers(&self) -> Result<(), ShimError> {
         let containers_dir = self.data_dir.join("containers");
         let mut entries = fs::read_dir(&containers_dir).await?;
@@ -85,8 +217,19 @@ impl KrunShim {
         while let Some(entry) = entries.next_entry().await? {
             let metadata_path = entry.path().join("metadata.json");
             if metadata_path.exists()
-                && let Ok(metadata) = ContainerMetadata::load(&metadata_path).await
+                && let Ok(mut metadata) = ContainerMetadata::load(&metadata_path).await
             {
+                // The VM exited while nobody was waiting for it; don't keep a
+                // pid around that may since have been reused.
+                if let Some(pid) = metadata.vm_pid
+                    && !process_alive(pid)
+                {
+                    metadata.vm_pid = None;
+                    metadata.info.pid = None;
+                    metadata.info.state = ContainerState::Stopped;
+                    metadata.info.mark_finished();
+                    let _ = metadata.save(&entry.path()).await;
+                }
                 containers.insert(metadata.info.id.clone(), metadata);
             }
         }
@@ -99,29 +242,86 @@ impl KrunShim {
         metadata.save(&container_dir).await
     }
 
-    fn current_timestamp() -> i64 {
-        SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64
-    }
-
     fn container_dir(&self, id: &str) -> PathBuf {
         self.data_dir.join("containers").join(id)
     }
+
+    /// Waits up to `timeout` for the VM process `pid` of container `id` to
+    /// exit. Returns whether it did.
+    async fn wait_stopped(&self, id: &str, pid: i32, timeout: Duration) -> bool {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let reaped = self
+                .containers
+                .read()
+                .await
+                .get(id)
+                .is_none_or(|m| m.vm_pid != Some(pid));
+            if reaped || !process_alive(pid) {
+                return true;
+            }
+            if tokio::time::Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(STOP_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Stops the network stack of container `id`, closing its published
+    /// ports. The VM waiter does so when the VM exits; this covers a VM that
+    /// outlives its container.
+    #[allow(unused_variables)]
+    fn release_network(&self, id: &str) {
+        #[cfg(all(feature = "libkrun", target_os = "macos"))]
+        if let Some(network) = self.networks.lock().unwrap().remove(id) {
+            tracing::debug!(container_id = %id, "Stopping network stack");
+            network.stop();
+        }
+    }
 }
 
 #[async_trait]
 impl Shim for KrunShim {
-    async fn create(&self, opts: CreateContainerOpts) -> Result<String, ShimError> {
+    async fn create(&self, mut opts: CreateContainerOpts) -> Result<String, ShimError> {
         let id = Uuid::new_v4().to_string();
 
+        // Reject limits the host cannot back, bad volumes, DNS options, egress
+        // policies, network modes and options the VM lacks before preparing
+        // anything.
+        VmResources::from_host_config(&opts.host_config)?;
+        resolve_binds(&opts.host_config.binds)?;
+        DnsConfig::from_host_config(&opts.host_config)?;
+        let egress = EgressPolicy::from_host_config(&opts.host_config)?;
+        if !opts.host_config.tmpfs.is_empty() {
+            return Err(ShimError::InvalidMount(
+                "tmpfs mounts are not supported by the libkrun runtime".to_string(),
+            ));
+        }
+        if opts.host_config.readonly_rootfs {
+            return Err(ShimError::NotSupported(
+                "read-only root filesystems are not supported by the libkrun runtime".to_string(),
+            ));
+        }
+        let network_mode = opts.host_config.network()?;
+        if egress.is_restricted() && network_mode == NetworkMode::Host {
+            return Err(ShimError::NotSupported(
+                "egress policies cannot be enforced in host network mode".to_string(),
+            ));
+        }
+        opts.host_config.network_mode = Some(network_mode.to_string());
+
         {
             let containers = self.containers.read().await;
             if containers.contains_key(&id) {
                 return Err(ShimError::ContainerAlreadyExists(id));
             }
         }
+        // Held until the container is in the map, so a concurrent create
+        // cannot take the same name.
+        let _reservation = match &opts.name {
+            Some(name) => Some(self.reserve_name(name).await?),
+            None => None,
+        };
 
         let bundle_path = self.container_dir(&id).join("bundle");
         let rootfs_path = bundle_path.join("rootfs");
@@ -135,7 +335,7 @@ impl Shim for KrunShim {
             rootfs::create_minimal_rootfs(&rootfs_path).await?;
         }
 
-        let now = Self::current_timestamp();
+        let now = since_epoch();
 
         let info = ContainerInfo {
             id: id.clone(),
@@ -144,17 +344,32 @@ impl Shim for KrunShim {
             state: ContainerState::Created,
             pid: None,
             exit_code: None,
-            created_at: now,
+            created_at: now.as_secs() as i64,
             started_at: None,
             finished_at: None,
+            created_nanos: now.subsec_nanos(),
+            started_nanos: 0,
+            finished_nanos: 0,
             bundle_path: bundle_path.to_string_lossy().to_string(),
             rootfs_path: rootfs_path.to_string_lossy().to_string(),
+            restart_policy: opts.host_config.restart_policy,
+            restart_count: 0,
+            manually_stopped: false,
+            healthcheck: opts.config.healthcheck.clone(),
+            image_id: opts.config.image_id.clone(),
+            command: opts.config.command(),
+            network_mode: opts.host_config.network_mode.clone().unwrap_or_default(),
+            oom_killed: false,
+            auto_remove: opts.host_config.auto_remove,
+            resources: ResourceLimits::of(&opts.host_config),
+            labels: opts.config.labels.clone(),
         };
 
         let metadata = ContainerMetadata {
             info,
             config: opts.config,
             host_config: opts.host_config,
+            vm_pid: None,
         };
 
         self.save_container(&metadata).await?;
@@ -174,38 +389,76 @@ impl Shim for KrunShim {
             .get_mut(id)
             .ok_or_else(|| ShimError::ContainerNotFound(id.to_string()))?;
 
-        if metadata.info.state != ContainerState::Created {
+        if metadata.info.state != ContainerState::Created
+            && metadata.info.state != ContainerState::Stopped
+        {
             return Err(ShimError::InvalidState {
-                expected: "created".to_string(),
+                expected: "created or stopped".to_string(),
                 actual: metadata.info.state.to_string(),
             });
         }
 
         metadata.info.state = ContainerState::Running;
-        metadata.info.started_at = Some(Self::current_timestamp());
+        metadata.info.mark_started();
+        metadata.info.exit_code = None;
+        metadata.info.manually_stopped = false;
         self.save_container(metadata).await?;
 
         tracing::info!(container_id = %id, "Container started (libkrun)");
         Ok(())
     }
 
-    async fn stop(&self, id: &str, _timeout: u32) -> Result<(), ShimError> {
+    async fn stop(&self, id: &str, timeout: u32, manual: bool) -> Result<bool, ShimError> {
+        let (vm_pid, stop_signal) = {
+            let mut containers = self.containers.write().await;
+            let metadata = containers
+                .get_mut(id)
+                .ok_or_else(|| ShimError::ContainerNotFound(id.to_string()))?;
+
+            if metadata.info.state != ContainerState::Running {
+                return Err(ShimError::ContainerNotRunning(id.to_string()));
+            }
+
+            // Mark before signalling so a restart policy watching the exit
+            // never sees the container as having died on its own.
+            if manual {
+                metadata.info.manually_stopped = true;
+                self.save_container(metadata).await?;
+            }
+            (
+                metadata.vm_pid,
+                metadata.config.stop_signal.unwrap_or(libc::SIGTERM as u32),
+            )
+        };
+
+        // Don't hold the lock while waiting, the VM waiter needs it to record
+        // the exit status.
+        let mut killed = false;
+        if let Some(pid) = vm_pid {
+            signal_vm(pid, stop_signal as i32)?;
+            let timeout = Duration::from_secs(timeout as u64);
+            killed = !self.wait_stopped(id, pid, timeout).await;
+            if killed {
+                tracing::info!(container_id = %id, "VM did not stop in time, killing it");
+                signal_vm(pid, libc::SIGKILL)?;
+                self.wait_stopped(id, pid, STOP_KILL_GRACE).await;
+            }
+        }
+
         let mut containers = self.containers.write().await;
         let metadata = containers
             .get_mut(id)
             .ok_or_else(|| ShimError::ContainerNotFound(id.to_string()))?;
 
-        if metadata.info.state != ContainerState::Running {
-            return Err(ShimError::ContainerNotRunning(id.to_string()));
-        }
-
         metadata.info.state = ContainerState::Stopped;
-        metadata.info.finished_at = Some(Self::current_timestamp());
+        metadata.info.mark_finished();
         metadata.info.pid = None;
+        metadata.vm_pid = None;
         self.save_container(metadata).await?;
+        self.release_network(id);
 
         tracing::info!(container_id = %id, "Container stopped (libkrun)");
-        Ok(())
+        Ok(killed)
     }
 
     async fn kill(&self, id: &str, signal: u32) -> Result<(), ShimError> {
@@ -217,6 +470,11 @@ impl Shim for KrunShim {
         if metadata.info.state != ContainerState::Running {
             return Err(ShimError::ContainerNotRunning(id.to_string()));
         }
+        let pid = metadata
+            .vm_pid
+            .ok_or_else(|| ShimError::ContainerNotRunning(id.to_string()))?;
+
+        signal_vm(pid, signal as i32)?;
 
         tracing::info!(container_id = %id, signal = signal, "Signal sent to container (libkrun)");
         Ok(())
@@ -246,6 +504,7 @@ impl Shim for KrunShim {
             let mut containers = self.containers.write().await;
             containers.remove(id);
         }
+        self.release_network(id);
 
         tracing::info!(container_id = %id, "Container deleted (libkrun)");
         Ok(())
@@ -288,6 +547,58 @@ impl Shim for KrunShim {
         Ok(())
     }
 
+    async fn rename(&self, id: &str, new_name: &str) -> Result<(), ShimError> {
+        let mut containers = self.containers.write().await;
+
+        if self.names.contains(new_name)
+            || containers
+                .values()
+                .any(|m| m.info.id != id && m.info.name.as_deref() == Some(new_name))
+        {
+            return Err(ShimError::ContainerAlreadyExists(new_name.to_string()));
+        }
+
+        let metadata = containers
+            .get_mut(id)
+            .ok_or_else(|| ShimError::ContainerNotFound(id.to_string()))?;
+
+        metadata.info.name = Some(new_name.to_string());
+        self.save_container(metadata).await?;
+
+        tracing::info!(container_id = %id, name = %new_name, "Container renamed (libkrun)");
+        Ok(())
+    }
+
+    async fn update(&self, id: &str, update: ResourceLimits) -> Result<(), ShimError> {
+        let mut containers = self.containers.write().await;
+        let metadata = containers
+            .get_mut(id)
+            .ok_or_else(|| ShimError::ContainerNotFound(id.to_string()))?;
+
+        // The limits size the VM, which cannot be resized once booted; they
+        // apply from the container's next start.
+        if matches!(
+            metadata.info.state,
+            ContainerState::Running | ContainerState::Paused
+        ) {
+            return Err(ShimError::NotSupported(format!(
+                "the libkrun runtime cannot change the resources of running container {}",
+                id
+            )));
+        }
+
+        let mut host_config = metadata.host_config.clone();
+        update.apply_to(&mut host_config);
+        VmResources::from_host_config(&host_config)?;
+
+        metadata.info.resources = ResourceLimits::of(&host_config);
+        metadata.host_config = host_config;
+        self.save_container(metadata).await?;
+
+        tracing::info!(container_id = %id, resources = ?metadata.info.resources, "Container resources updated (libkrun)");
+        Ok(())
+    }
+
     async fn list(&self) -> Result<Vec<ContainerInfo>, ShimError> {
         let containers = self.containers.read().await;
         Ok(containers.values().map(|m| m.info.clone()).collect())
@@ -320,17 +631,32 @@ impl Shim for KrunShim {
         }
     }
 
+    async fn record_restart(&self, id: &str) -> Result<u32, ShimError> {
+        let mut containers = self.containers.write().await;
+        let metadata = containers
+            .get_mut(id)
+            .ok_or_else(|| ShimError::ContainerNotFound(id.to_string()))?;
+
+        metadata.info.restart_count += 1;
+        self.save_container(metadata).await?;
+        Ok(metadata.info.restart_count)
+    }
+
     #[allow(unused_variables)]
     fn run_streaming(&self, id: String) -> OutputEventStream {
         #[cfg(all(feature = "libkrun", target_os = "macos"))]
         {
             use super::krun;
             use crate::guest_config::{GuestConfig, VolumeMount};
+            use crate::stdin::StdinState;
             use crate::tty_host;
+            use std::net::IpAddr;
             use std::os::unix::net::UnixListener;
 
             let containers = self.containers.clone();
+            let networks = self.networks.clone();
             let data_dir = self.data_dir.clone();
+            let mtu = self.mtu;
 
             Box::pin(async_stream::try_stream! {
                 let (config, rootfs_path, host_config): (ContainerConfig, PathBuf, HostConfig);
@@ -352,12 +678,14 @@ impl Shim for KrunShim {
                     host_config = metadata.host_config.clone();
 
                     metadata.info.state = ContainerState::Running;
-                    metadata.info.started_at = Some(KrunShim::current_timestamp());
+                    metadata.info.mark_started();
                     metadata.save(&data_dir.join("containers").join(&id)).await?;
                 }
 
                 tracing::info!(container_id = %id, rootfs = ?rootfs_path, "Starting container with libkrun (streaming via ross-init)");
 
+                let network_mode = host_config.network()?;
+
                 krun::fix_root_mode(&rootfs_path);
 
                 // Allocate a vsock port for communication (non-tty still uses vsock for stdout/stderr/exit)
@@ -381,15 +709,18 @@ impl Shim for KrunShim {
 
                 let mut volumes: Vec<VolumeMount> = Vec::new();
                 let mut virtiofs_shares: Vec<(String, String)> = Vec::new();
-                for (idx, bind) in host_config.binds.iter().enumerate() {
-                    let (host_path, guest_path, read_only) = parse_bind_spec(bind)?;
+                for (idx, bind) in resolve_binds(&host_config.binds)?.into_iter().enumerate() {
                     // virtio-fs tag must be unique
                     let tag = format!("rossvol{}", idx);
-                    volumes.push(VolumeMount { tag: tag.clone(), target: guest_path, read_only });
-                    virtiofs_shares.push((tag, host_path));
+                    volumes.push(VolumeMount {
+                        tag: tag.clone(),
+                        target: bind.destination,
+                        read_only: bind.read_only,
+                    });
+                    virtiofs_shares.push((tag, bind.source.to_string_lossy().to_string()));
                 }
 
-                let guest_config = GuestConfig {
+                let mut guest_config = GuestConfig {
                     command,
                     args,
                     env: config.env.clone(),
@@ -397,44 +728,65 @@ impl Shim for KrunShim {
                     tty: false,
                     vsock_port,
                     volumes,
+                    network_disabled: network_mode == NetworkMode::None,
+                    resolv_conf: None,
+                    hosts: None,
                 };
+                let dns = DnsConfig::from_host_config(&host_config)?;
+                let hostname = config.hostname.as_deref().unwrap_or("container");
+                let link = connect_vm(&id, &host_config, hostname, &dns, mtu)?;
+
+                // Behind the userspace stack the guest asks the gateway, which
+                // forwards to the container's name servers; under TSI it asks
+                // them itself.
+                if link.as_ref().is_some_and(|link| link.handle().is_some()) {
+                    let gateway = IpAddr::from(super::net::GATEWAY_IP);
+                    let address = IpAddr::from(super::net::GUEST_IP);
+                    guest_config.configure_dns(&dns, &[gateway], hostname, Some(address));
+                } else {
+                    guest_config.configure_dns(&dns, &dns.nameservers_or_host(), hostname, None);
+                }
+
+                let network_config = link.as_ref().map(|link| krun::NetworkConfig {
+                    socket_path: link.socket_path().to_string(),
+                    mac: super::net::DEFAULT_MAC,
+                });
 
-                let child_pid = krun::fork_and_run_vm_interactive_with_network_and_shares(
+                let resources = VmResources::from_host_config(&host_config)?;
+                // Reaped however the stream ends, even before it is waited for.
+                let child = krun::fork_and_run_vm_interactive_with_network_and_shares(
                     &rootfs_path,
                     &guest_config,
+                    resources,
                     vsock_port,
-                    None,
+                    network_config,
                     &virtiofs_shares,
                 )?;
+                let child_pid = child.pid();
+
+                {
+                    let mut containers_guard = containers.write().await;
+                    if let Some(metadata) = containers_guard.get_mut(&id) {
+                        metadata.vm_pid = Some(child_pid);
+                        metadata.info.pid = Some(child_pid as u32);
+                        metadata.save(&data_dir.join("containers").join(&id)).await?;
+                    }
+                }
 
-                // Create std::sync channels for the blocking I/O loop
-                // Keep the sender alive so the receiver doesn't disconnect immediately.
-                let (_sync_input_tx_keepalive, sync_input_rx) =
-                    std::sync::mpsc::channel::<InputEvent>();
+                // Create std::sync channels for the blocking I/O loop.
+                // There is no client input here: the guest's stdin is closed
+                // from the start, as if it were redirected from /dev/null.
+                let (_, sync_input_rx) = std::sync::mpsc::channel::<InputEvent>();
                 let (sync_output_tx, sync_output_rx) = std::sync::mpsc::channel::<OutputEvent>();
 
-                let containers_for_wait = containers.clone();
-                let id_for_wait = id.clone();
-                let data_dir_for_wait = data_dir.clone();
-
-                tokio::task::spawn_blocking(move || {
-                    let exit_code = krun::wait_for_child(child_pid);
-
-                    let rt = tokio::runtime::Builder::new_current_thread()
-                        .enable_all()
-                        .build()
-                        .unwrap();
-
-                    rt.block_on(async {
-                        let mut containers_guard = containers_for_wait.write().await;
-                        if let Some(metadata) = containers_guard.get_mut(&id_for_wait) {
-                            metadata.info.state = ContainerState::Stopped;
-                            metadata.info.exit_code = Some(exit_code);
-                            metadata.info.finished_at = Some(KrunShim::current_timestamp());
-                            let _ = metadata.save(&data_dir_for_wait.join("containers").join(&id_for_wait)).await;
-                        }
-                    });
-                });
+                spawn_vm_waiter(
+                    containers.clone(),
+                    networks.clone(),
+                    data_dir.clone(),
+                    id.clone(),
+                    child,
+                    link,
+                );
 
                 // Spawn a forwarder from std output channel to stream yields
                 let (tokio_out_tx, mut tokio_out_rx) = tokio::sync::mpsc::channel::<OutputEvent>(64);
@@ -449,7 +801,7 @@ impl Shim for KrunShim {
 
                 // Run host I/O loop (blocking) that reads vsock and emits OutputEvents
                 let _ = tokio::task::spawn_blocking(move || {
-                    let _ = tty_host::run_io_host_with_channels(listener, false, sync_input_rx, sync_output_tx);
+                    let _ = tty_host::run_io_host_with_channels(listener, false, StdinState::default(), sync_input_rx, sync_output_tx);
                 });
 
                 while let Some(ev) = tokio_out_rx.recv().await {
@@ -469,6 +821,15 @@ impl Shim for KrunShim {
         }
     }
 
+    fn exec(&self, id: String, _opts: ExecOpts) -> OutputEventStream {
+        Box::pin(futures::stream::once(async move {
+            Err(ShimError::NotSupported(format!(
+                "exec is not supported by the libkrun runtime (container {})",
+                id
+            )))
+        }))
+    }
+
     #[allow(unused_variables)]
     async fn run_interactive(
         &self,
@@ -479,10 +840,12 @@ impl Shim for KrunShim {
         #[cfg(all(feature = "libkrun", target_os = "macos"))]
         {
             use super::krun::{self, NetworkConfig};
-            use super::net::{DEFAULT_MAC, VmNetwork, network_available};
+            use super::net::{DEFAULT_MAC, GATEWAY_IP, GUEST_IP};
             use crate::guest_config::GuestConfig;
             use crate::guest_config::VolumeMount;
+            use crate::stdin::StdinState;
             use crate::tty_host;
+            use std::net::IpAddr;
             use std::os::unix::net::UnixListener;
 
             let input_rx = input_rx;
@@ -506,7 +869,7 @@ impl Shim for KrunShim {
                 host_config = metadata.host_config.clone();
 
                 metadata.info.state = ContainerState::Running;
-                metadata.info.started_at = Some(Self::current_timestamp());
+                metadata.info.mark_started();
                 self.save_container(metadata).await?;
             }
 
@@ -538,18 +901,20 @@ impl Shim for KrunShim {
 
             let mut volumes: Vec<VolumeMount> = Vec::new();
             let mut virtiofs_shares: Vec<(String, String)> = Vec::new();
-            for (idx, bind) in host_config.binds.iter().enumerate() {
-                let (host_path, guest_path, read_only) = parse_bind_spec(bind)?;
+            for (idx, bind) in resolve_binds(&host_config.binds)?.into_iter().enumerate() {
                 let tag = format!("rossvol{}", idx);
                 volumes.push(VolumeMount {
                     tag: tag.clone(),
-                    target: guest_path,
-                    read_only,
+                    target: bind.destination,
+                    read_only: bind.read_only,
                 });
-                virtiofs_shares.push((tag, host_path));
+                virtiofs_shares.push((tag, bind.source.to_string_lossy().to_string()));
             }
 
-            let guest_config = GuestConfig {
+            let network_mode = host_config.network()?;
+            let dns = DnsConfig::from_host_config(&host_config)?;
+            let hostname = config.hostname.as_deref().unwrap_or("container");
+            let mut guest_config = GuestConfig {
                 command,
                 args,
                 env: config.env.clone(),
@@ -557,44 +922,71 @@ impl Shim for KrunShim {
                 tty: config.tty,
                 vsock_port,
                 volumes,
+                network_disabled: network_mode == NetworkMode::None,
+                resolv_conf: None,
+                hosts: None,
             };
 
-            // Start userspace network stack if available
-            let network = if network_available() {
-                match VmNetwork::start(&id) {
-                    Ok(n) => {
-                        tracing::info!(container_id = %id, "Userspace network stack enabled");
-                        Some(n)
-                    }
-                    Err(e) => {
-                        tracing::warn!(container_id = %id, error = %e, "Failed to start network stack, falling back to TSI");
-                        None
-                    }
+            let link = match connect_vm(&id, &host_config, hostname, &dns, self.mtu) {
+                Ok(link) => link,
+                Err(e) => {
+                    let _ = std::fs::remove_file(&socket_path);
+                    return Err(e);
                 }
-            } else {
-                tracing::debug!(container_id = %id, "Network stack not available, using TSI networking");
-                None
             };
 
-            // Prepare network config if network stack is running
-            let network_config = network.as_ref().map(|n| NetworkConfig {
-                socket_path: n.socket_path().to_string(),
+            // Behind the userspace stack the guest asks the gateway, which
+            // forwards to the container's name servers; under TSI it asks
+            // them itself.
+            if link.as_ref().is_some_and(|link| link.handle().is_some()) {
+                let gateway = IpAddr::from(GATEWAY_IP);
+                let address = IpAddr::from(GUEST_IP);
+                guest_config.configure_dns(&dns, &[gateway], hostname, Some(address));
+            } else {
+                guest_config.configure_dns(&dns, &dns.nameservers_or_host(), hostname, None);
+            }
+
+            // Prepare network config if the VM gets a network device
+            let network_config = link.as_ref().map(|link| NetworkConfig {
+                socket_path: link.socket_path().to_string(),
                 mac: DEFAULT_MAC,
             });
 
             // Fork and start VM
-            let child_pid = krun::fork_and_run_vm_interactive_with_network_and_shares(
+            let resources = VmResources::from_host_config(&host_config)?;
+            tracing::debug!(container_id = %id, vcpus = resources.vcpus, ram_mib = resources.ram_mib, "Sizing VM");
+            // Reaped even when the session ends early, before waiting for it.
+            let child = krun::fork_and_run_vm_interactive_with_network_and_shares(
                 &rootfs_path,
                 &guest_config,
+                resources,
                 vsock_port,
                 network_config,
                 &virtiofs_shares,
             )?;
+            let child_pid = child.pid();
+
+            {
+                let mut containers = self.containers.write().await;
+                if let Some(metadata) = containers.get_mut(&id) {
+                    metadata.vm_pid = Some(child_pid);
+                    metadata.info.pid = Some(child_pid as u32);
+                    self.save_container(metadata).await?;
+                }
+            }
+
+            // Records the exit even if this session is dropped first.
+            let waiter = spawn_vm_waiter(
+                self.containers.clone(),
+                self.networks.clone(),
+                self.data_dir.clone(),
+                id.clone(),
+                child,
+                link,
+            );
 
             let is_tty = config.tty;
-            let containers = self.containers.clone();
-            let data_dir = self.data_dir.clone();
-            let id_clone = id.clone();
+            let stdin = StdinState::new(&config);
 
             // Create std::sync channels for the blocking I/O loop
             let (sync_input_tx, sync_input_rx) = std::sync::mpsc::channel::<InputEvent>();
@@ -623,15 +1015,20 @@ impl Shim for KrunShim {
 
             // Run I/O loop in blocking task
             let io_result = tokio::task::spawn_blocking(move || {
-                tty_host::run_io_host_with_channels(listener, is_tty, sync_input_rx, sync_output_tx)
+                tty_host::run_io_host_with_channels(
+                    listener,
+                    is_tty,
+                    stdin,
+                    sync_input_rx,
+                    sync_output_tx,
+                )
             })
             .await
             .map_err(|e| ShimError::RuntimeError(format!("I/O task panicked: {}", e)))?;
 
-            // Wait for child process
-            let exit_code = tokio::task::spawn_blocking(move || krun::wait_for_child(child_pid))
-                .await
-                .unwrap_or(1);
+            // Wait for the child, which outlives the I/O loop when the
+            // guest closed its connection early.
+            let exit_code = waiter.await.unwrap_or(1);
 
             // Clean up socket
             let _ = std::fs::remove_file(&socket_path);
@@ -640,19 +1037,6 @@ impl Shim for KrunShim {
             input_forwarder.abort();
             output_forwarder.abort();
 
-            // Update container state
-            {
-                let mut containers_guard = containers.write().await;
-                if let Some(metadata) = containers_guard.get_mut(&id_clone) {
-                    metadata.info.state = ContainerState::Stopped;
-                    metadata.info.exit_code = Some(exit_code);
-                    metadata.info.finished_at = Some(Self::current_timestamp());
-                    let _ = metadata
-                        .save(&data_dir.join("containers").join(&id_clone))
-                        .await;
-                }
-            }
-
             let final_exit_code = io_result.unwrap_or(exit_code as u8);
             let _ = output_tx
                 .send(OutputEvent::Exit(WaitResult {
@@ -676,4 +1060,52 @@ impl Shim for KrunShim {
             Ok(())
         }
     }
+
+    async fn attach(&self, id: &str) -> Result<AttachStreams, ShimError> {
+        Err(ShimError::NotSupported(format!(
+            "attach is not supported by the libkrun runtime (container {})",
+            id
+        )))
+    }
+
+    async fn network_stats(&self, id: &str) -> Result<HashMap<String, InterfaceStats>, ShimError> {
+        self.get(id).await?;
+
+        // Only VMs on the userspace stack are counted; TSI traffic goes
+        // through the VMM directly.
+        #[cfg(all(feature = "libkrun", target_os = "macos"))]
+        if let Some(network) = self.networks.lock().unwrap().get(id) {
+            return Ok(HashMap::from([(
+                "eth0".to_string(),
+                network.counters().snapshot(),
+            )]));
+        }
+        Ok(HashMap::new())
+    }
+
+    async fn processes(&self, id: &str) -> Result<Vec<u32>, ShimError> {
+        // The host only sees the VM's process.
+        Err(ShimError::NotSupported(format!(
+            "listing processes is not supported by the libkrun runtime (container {})",
+            id
+        )))
+    }
+
+    async fn shutdown(&self) -> Result<(), ShimError> {
+        #[cfg(all(feature = "libkrun", target_os = "macos"))]
+        {
+            for (id, network) in self.networks.lock().unwrap().drain() {
+                tracing::debug!(container_id = %id, "Stopping network stack");
+                network.stop();
+            }
+            // The vsock and network sockets of every container, including
+            // those of VMs left running, which the next daemon cannot reach.
+            for id in self.containers.read().await.keys() {
+                let vsock_path = super::krun::get_vsock_socket_path(vsock_port_for_container(id));
+                let _ = std::fs::remove_file(vsock_path);
+                let _ = std::fs::remove_file(format!("/tmp/ross-net-{}.sock", id));
+            }
+        }
+        Ok(())
+    }
 }
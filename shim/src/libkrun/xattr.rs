@@ -0,0 +1,144 @@
+//! Extended attribute access that does not follow symlinks.
+
+use std::ffi::{CStr, CString};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+fn c_path(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use libc::{c_char, c_int, c_void, size_t, ssize_t};
+
+    pub unsafe fn list(path: *const c_char, buf: *mut c_char, size: size_t) -> ssize_t {
+        unsafe { libc::llistxattr(path, buf, size) }
+    }
+
+    pub unsafe fn get(
+        path: *const c_char,
+        name: *const c_char,
+        buf: *mut c_void,
+        size: size_t,
+    ) -> ssize_t {
+        unsafe { libc::lgetxattr(path, name, buf, size) }
+    }
+
+    pub unsafe fn set(
+        path: *const c_char,
+        name: *const c_char,
+        value: *const c_void,
+        size: size_t,
+    ) -> c_int {
+        unsafe { libc::lsetxattr(path, name, value, size, 0) }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod sys {
+    use libc::{XATTR_NOFOLLOW, c_char, c_int, c_void, size_t, ssize_t};
+
+    pub unsafe fn list(path: *const c_char, buf: *mut c_char, size: size_t) -> ssize_t {
+        unsafe { libc::listxattr(path, buf, size, XATTR_NOFOLLOW) }
+    }
+
+    pub unsafe fn get(
+        path: *const c_char,
+        name: *const c_char,
+        buf: *mut c_void,
+        size: size_t,
+    ) -> ssize_t {
+        unsafe { libc::getxattr(path, name, buf, size, 0, XATTR_NOFOLLOW) }
+    }
+
+    pub unsafe fn set(
+        path: *const c_char,
+        name: *const c_char,
+        value: *const c_void,
+        size: size_t,
+    ) -> c_int {
+        unsafe { libc::setxattr(path, name, value, size, 0, XATTR_NOFOLLOW) }
+    }
+}
+
+/// Names of the extended attributes set on `path`.
+pub fn list(path: &Path) -> io::Result<Vec<CString>> {
+    let path = c_path(path)?;
+    loop {
+        let size = unsafe { sys::list(path.as_ptr(), std::ptr::null_mut(), 0) };
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if size == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut buf = vec![0u8; size as usize];
+        let len = unsafe { sys::list(path.as_ptr(), buf.as_mut_ptr().cast(), buf.len()) };
+        if len < 0 {
+            let err = io::Error::last_os_error();
+            // The list grew between the two calls.
+            if err.raw_os_error() == Some(libc::ERANGE) {
+                continue;
+            }
+            return Err(err);
+        }
+        buf.truncate(len as usize);
+
+        return Ok(buf
+            .split(|&b| b == 0)
+            .filter(|name| !name.is_empty())
+            .filter_map(|name| CString::new(name).ok())
+            .collect());
+    }
+}
+
+/// Value of the extended attribute `name` on `path`.
+pub fn get(path: &Path, name: &CStr) -> io::Result<Vec<u8>> {
+    let path = c_path(path)?;
+    loop {
+        let size = unsafe { sys::get(path.as_ptr(), name.as_ptr(), std::ptr::null_mut(), 0) };
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut buf = vec![0u8; size as usize];
+        let len = unsafe {
+            sys::get(
+                path.as_ptr(),
+                name.as_ptr(),
+                buf.as_mut_ptr().cast(),
+                buf.len(),
+            )
+        };
+        if len < 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() == Some(libc::ERANGE) {
+                continue;
+            }
+            return Err(err);
+        }
+        buf.truncate(len as usize);
+        return Ok(buf);
+    }
+}
+
+/// Sets the extended attribute `name` on `path`, replacing any existing value.
+pub fn set(path: &Path, name: &CStr, value: &[u8]) -> io::Result<()> {
+    let path = c_path(path)?;
+    let ret = unsafe {
+        sys::set(
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_ptr().cast(),
+            value.len(),
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -0,0 +1,277 @@
+//! The log files of detached containers, `stdout.log` and `stderr.log` in the
+//! bundle.
+//!
+//! As with Docker's `json-file` driver, each line the container writes is
+//! stored as a JSON object of its own line, stamped with the time it was
+//! read from the container. The lines are read by a [`LogCopier`] process
+//! per stream, which outlives the daemon.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read, Write};
+use std::os::fd::{FromRawFd, IntoRawFd, OwnedFd, RawFd};
+use std::path::Path;
+use tokio::fs;
+
+/// Longest piece of a line stored in one record. Longer lines are split over
+/// several records, all but the last without a trailing newline.
+const MAX_RECORD_SIZE: usize = 16 * 1024;
+
+/// One line of a log file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogRecord {
+    /// What the container wrote, including the newline ending it.
+    pub log: String,
+    /// `stdout` or `stderr`.
+    pub stream: String,
+    /// When the line was read from the container.
+    pub time: DateTime<Utc>,
+}
+
+impl LogRecord {
+    /// Parses a line of a log file. Logs written before lines were recorded
+    /// this way hold the raw output, which gives `None`.
+    pub fn parse(line: &str) -> Option<Self> {
+        serde_json::from_str(line).ok()
+    }
+}
+
+/// A process copying what a container writes to a pipe into its log.
+///
+/// The copy runs in a process forked from the daemon rather than in the
+/// daemon: a container left running by live restore, or by a crash, would
+/// otherwise write to a pipe nobody reads, and block or die of `SIGPIPE`.
+/// Reaped by [`LogCopier::wait`] or, when dropped, on a thread of its own.
+pub(crate) struct LogCopier {
+    pid: libc::pid_t,
+    waited: bool,
+}
+
+impl LogCopier {
+    /// Starts copying `pipe` into the log at `path` until every copy of the
+    /// pipe's write end is closed, i.e. the container exited.
+    ///
+    /// The pipe is read even when the log cannot be written, so that a full
+    /// disk does not block the container on its output.
+    pub(crate) fn spawn(pipe: OwnedFd, path: &Path, stream: &'static str) -> io::Result<Self> {
+        // Append so logs from before a restart are kept.
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+
+        let pid = unsafe { libc::fork() };
+        if pid < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if pid == 0 {
+            run_copier(pipe.into_raw_fd(), file.into_raw_fd(), stream);
+        }
+        Ok(Self { pid, waited: false })
+    }
+
+    /// Waits for the copy to end, with all the container wrote in the log.
+    pub(crate) async fn wait(mut self) {
+        self.waited = true;
+        let pid = self.pid;
+        let _ = tokio::task::spawn_blocking(move || wait_for_child(pid)).await;
+    }
+}
+
+impl Drop for LogCopier {
+    fn drop(&mut self) {
+        if !self.waited {
+            let pid = self.pid;
+            std::thread::spawn(move || wait_for_child(pid));
+        }
+    }
+}
+
+/// The copier process: reads the pipe as its stdin and appends the records
+/// to the log as its stdout, then exits. It runs in a session of its own, so
+/// that signals meant for the daemon's process group do not stop it, and
+/// holds no other file, so that it keeps no socket or other container's pipe
+/// open.
+fn run_copier(pipe: RawFd, log: RawFd, stream: &str) -> ! {
+    unsafe {
+        libc::setsid();
+        for signal in [libc::SIGHUP, libc::SIGINT, libc::SIGTERM] {
+            libc::signal(signal, libc::SIG_DFL);
+        }
+        libc::dup2(pipe, 0);
+        libc::dup2(log, 1);
+        let null = libc::open(c"/dev/null".as_ptr(), libc::O_WRONLY);
+        if null >= 0 {
+            libc::dup2(null, 2);
+        }
+        close_from(3);
+
+        copy(
+            std::fs::File::from_raw_fd(0),
+            std::fs::File::from_raw_fd(1),
+            stream,
+        );
+        libc::_exit(0)
+    }
+}
+
+/// Closes every file descriptor from `first` up.
+unsafe fn close_from(first: libc::c_int) {
+    #[cfg(target_os = "linux")]
+    if unsafe { libc::syscall(libc::SYS_close_range, first, libc::c_uint::MAX, 0) } == 0 {
+        return;
+    }
+    let max = unsafe { libc::sysconf(libc::_SC_OPEN_MAX) }.clamp(1024, 1 << 20);
+    for fd in first..max as libc::c_int {
+        unsafe { libc::close(fd) };
+    }
+}
+
+/// Waits for child `pid` to exit.
+fn wait_for_child(pid: libc::pid_t) {
+    let mut status: libc::c_int = 0;
+    while unsafe { libc::waitpid(pid, &mut status, 0) } < 0
+        && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted
+    {}
+}
+
+/// Copies `pipe` into `log` as records until the pipe ends. Write errors are
+/// ignored, with nowhere to report them.
+fn copy(mut pipe: impl Read, mut log: impl Write, stream: &str) {
+    let mut buf = vec![0u8; 4096];
+    let mut pending = Vec::new();
+    loop {
+        let n = match pipe.read(&mut buf) {
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => 0,
+        };
+        let eof = n == 0;
+        pending.extend_from_slice(&buf[..n]);
+
+        let records = encode_records(&mut pending, stream, Utc::now(), eof);
+        if !records.is_empty() {
+            let _ = log.write_all(&records);
+        }
+        if eof {
+            return;
+        }
+    }
+}
+
+/// Drains the lines complete in `pending`, and lines too long for one record,
+/// as records stamped with `time`. With `eof`, what is left is the last line.
+fn encode_records(pending: &mut Vec<u8>, stream: &str, time: DateTime<Utc>, eof: bool) -> Vec<u8> {
+    let mut out = Vec::new();
+    loop {
+        let end = match pending.iter().position(|b| *b == b'\n') {
+            Some(pos) if pos < MAX_RECORD_SIZE => pos + 1,
+            _ if pending.len() >= MAX_RECORD_SIZE => MAX_RECORD_SIZE,
+            _ if eof && !pending.is_empty() => pending.len(),
+            _ => return out,
+        };
+        let line: Vec<u8> = pending.drain(..end).collect();
+        let record = LogRecord {
+            log: String::from_utf8_lossy(&line).into_owned(),
+            stream: stream.to_string(),
+            time,
+        };
+        // A record only holds strings, it always serializes.
+        serde_json::to_writer(&mut out, &record).expect("log record serializes");
+        out.push(b'\n');
+    }
+}
+
+/// The last non-empty line of the log at `path`, e.g. why runc failed.
+pub(crate) async fn last_line(path: &Path) -> Option<String> {
+    let content = fs::read_to_string(path).await.ok()?;
+    content
+        .lines()
+        .map(|line| match LogRecord::parse(line) {
+            Some(record) => record.log.trim_end().to_string(),
+            None => line.to_string(),
+        })
+        .rfind(|line| !line.trim().is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(out: &[u8]) -> Vec<LogRecord> {
+        std::str::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| LogRecord::parse(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_encode_records_keeps_partial_line() {
+        let time = Utc::now();
+        let mut pending = b"one\ntwo\nthr".to_vec();
+        let records = decode(&encode_records(&mut pending, "stdout", time, false));
+        let lines: Vec<_> = records.iter().map(|r| r.log.as_str()).collect();
+        assert_eq!(lines, vec!["one\n", "two\n"]);
+        assert!(
+            records
+                .iter()
+                .all(|r| r.stream == "stdout" && r.time == time)
+        );
+        assert_eq!(pending, b"thr");
+
+        let records = decode(&encode_records(&mut pending, "stdout", time, true));
+        assert_eq!(records[0].log, "thr");
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn test_encode_records_splits_long_lines() {
+        let mut pending = vec![b'a'; MAX_RECORD_SIZE + 10];
+        pending.push(b'\n');
+        let records = decode(&encode_records(&mut pending, "stderr", Utc::now(), false));
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].log.len(), MAX_RECORD_SIZE);
+        assert_eq!(records[1].log, format!("{}\n", "a".repeat(10)));
+    }
+
+    #[test]
+    fn test_record_format() {
+        let record = LogRecord {
+            log: "hello \"world\"\n".to_string(),
+            stream: "stdout".to_string(),
+            time: DateTime::from_timestamp(1_700_000_000, 123_456_789).unwrap(),
+        };
+        let line = serde_json::to_string(&record).unwrap();
+        assert_eq!(
+            line,
+            r#"{"log":"hello \"world\"\n","stream":"stdout","time":"2023-11-14T22:13:20.123456789Z"}"#
+        );
+        assert_eq!(LogRecord::parse(&line), Some(record));
+        assert_eq!(LogRecord::parse("plain output"), None);
+    }
+
+    #[tokio::test]
+    async fn test_log_copier_appends_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stdout.log");
+        std::fs::write(&path, "from before\n").unwrap();
+
+        let (reader, mut writer) = std::io::pipe().unwrap();
+        let copier = LogCopier::spawn(reader.into(), &path, "stdout").unwrap();
+        assert_ne!(copier.pid, std::process::id() as libc::pid_t);
+        // Only the copier process reads the pipe.
+        writer.write_all(b"first\nsecond\nlast").unwrap();
+        drop(writer);
+        copier.wait().await;
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let mut lines = content.lines();
+        assert_eq!(lines.next(), Some("from before"));
+        let logged: Vec<_> = lines
+            .map(|line| LogRecord::parse(line).unwrap().log)
+            .collect();
+        assert_eq!(logged, vec!["first\n", "second\n", "last"]);
+        assert_eq!(last_line(&path).await.as_deref(), Some("last"));
+    }
+}
@@ -0,0 +1,65 @@
+//! Container names claimed by creations still in progress.
+
+use crate::error::ShimError;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+/// Names reserved by containers being created. A shim checks them, together
+/// with the names of its existing containers, so that two concurrent
+/// creations cannot both take the same name.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct NameReservations(Arc<Mutex<HashSet<String>>>);
+
+impl NameReservations {
+    /// Claims `name` for a container being created. `taken` tells whether an
+    /// existing container has the name; the caller must hold its containers
+    /// lock across that check and this call.
+    pub fn reserve(&self, name: &str, taken: bool) -> Result<NameReservation, ShimError> {
+        if taken || !self.0.lock().unwrap().insert(name.to_string()) {
+            return Err(ShimError::ContainerAlreadyExists(name.to_string()));
+        }
+        Ok(NameReservation {
+            names: self.clone(),
+            name: name.to_string(),
+        })
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.0.lock().unwrap().contains(name)
+    }
+}
+
+/// Holds a name until dropped, by which time the created container carries
+/// it or the creation failed.
+#[derive(Debug)]
+pub(crate) struct NameReservation {
+    names: NameReservations,
+    name: String,
+}
+
+impl Drop for NameReservation {
+    fn drop(&mut self) {
+        self.names.0.lock().unwrap().remove(&self.name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reserve() {
+        let names = NameReservations::default();
+        let web = names.reserve("web", false).unwrap();
+        assert!(matches!(
+            names.reserve("web", false),
+            Err(ShimError::ContainerAlreadyExists(name)) if name == "web"
+        ));
+        assert!(names.reserve("db", true).is_err());
+        assert!(names.contains("web"));
+
+        drop(web);
+        assert!(!names.contains("web"));
+        assert!(names.reserve("web", false).is_ok());
+    }
+}
@@ -0,0 +1,233 @@
+//! The files containers resolve names with, `/etc/resolv.conf` and
+//! `/etc/hosts`, as `--dns`, `--dns-search`, `--dns-option` and `--add-host`
+//! configure them.
+//!
+//! Containers given none of these keep the files of their image.
+
+use crate::error::ShimError;
+use crate::types::HostConfig;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// Where the host's resolver is configured, for the name servers of
+/// containers that only set search domains or options.
+const HOST_RESOLV_CONF: &str = "/etc/resolv.conf";
+
+/// An `/etc/hosts` entry given as `NAME:IP`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtraHost {
+    pub name: String,
+    pub ip: IpAddr,
+}
+
+impl FromStr for ExtraHost {
+    type Err = ShimError;
+
+    /// Parses `NAME:IP`, or `NAME=IP`. Names have no colons, so an IPv6
+    /// address follows the first one, in brackets or not.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            || ShimError::InvalidDns(format!("invalid extra host {} (expected NAME:IP)", s));
+        let (name, ip) = s.split_once(['=', ':']).ok_or_else(invalid)?;
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(invalid());
+        }
+        let ip = ip
+            .strip_prefix('[')
+            .and_then(|ip| ip.strip_suffix(']'))
+            .unwrap_or(ip);
+        Ok(Self {
+            name: name.to_string(),
+            ip: ip.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// A container's resolver configuration, checked.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DnsConfig {
+    pub nameservers: Vec<IpAddr>,
+    pub search: Vec<String>,
+    pub options: Vec<String>,
+    pub extra_hosts: Vec<ExtraHost>,
+}
+
+impl DnsConfig {
+    pub fn from_host_config(host_config: &HostConfig) -> Result<Self, ShimError> {
+        let nameservers = host_config
+            .dns
+            .iter()
+            .map(|server| {
+                server
+                    .parse()
+                    .map_err(|_| ShimError::InvalidDns(format!("invalid name server {}", server)))
+            })
+            .collect::<Result<_, _>>()?;
+        let word = |kind: &str, value: &String| {
+            if value.is_empty() || value.contains(char::is_whitespace) {
+                Err(ShimError::InvalidDns(format!(
+                    "invalid {} {:?}",
+                    kind, value
+                )))
+            } else {
+                Ok(value.clone())
+            }
+        };
+        let search = host_config
+            .dns_search
+            .iter()
+            .map(|domain| word("search domain", domain))
+            .collect::<Result<_, _>>()?;
+        let options = host_config
+            .dns_options
+            .iter()
+            .map(|option| word("resolver option", option))
+            .collect::<Result<_, _>>()?;
+        let extra_hosts = host_config
+            .extra_hosts
+            .iter()
+            .map(|host| host.parse())
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            nameservers,
+            search,
+            options,
+            extra_hosts,
+        })
+    }
+
+    /// Whether the container has its own `/etc/resolv.conf`.
+    pub fn configures_resolver(&self) -> bool {
+        !self.nameservers.is_empty() || !self.search.is_empty() || !self.options.is_empty()
+    }
+
+    /// Whether the container has its own `/etc/hosts`.
+    pub fn configures_hosts(&self) -> bool {
+        !self.extra_hosts.is_empty()
+    }
+
+    /// Contents of `/etc/resolv.conf`, pointing at `nameservers`.
+    pub fn resolv_conf(&self, nameservers: &[IpAddr]) -> String {
+        let mut conf = String::new();
+        for server in nameservers {
+            conf.push_str(&format!("nameserver {}\n", server));
+        }
+        if !self.search.is_empty() {
+            conf.push_str(&format!("search {}\n", self.search.join(" ")));
+        }
+        if !self.options.is_empty() {
+            conf.push_str(&format!("options {}\n", self.options.join(" ")));
+        }
+        conf
+    }
+
+    /// Contents of `/etc/hosts`: loopback, the extra hosts, and `hostname`
+    /// when the container's `address` is known.
+    pub fn hosts(&self, hostname: &str, address: Option<IpAddr>) -> String {
+        let mut hosts =
+            String::from("127.0.0.1\tlocalhost\n::1\tlocalhost ip6-localhost ip6-loopback\n");
+        for host in &self.extra_hosts {
+            hosts.push_str(&format!("{}\t{}\n", host.ip, host.name));
+        }
+        if let Some(address) = address {
+            hosts.push_str(&format!("{}\t{}\n", address, hostname));
+        }
+        hosts
+    }
+
+    /// The name servers the container asked for, or the host's.
+    pub fn nameservers_or_host(&self) -> Vec<IpAddr> {
+        if !self.nameservers.is_empty() {
+            return self.nameservers.clone();
+        }
+        std::fs::read_to_string(HOST_RESOLV_CONF)
+            .map(|conf| parse_nameservers(&conf))
+            .unwrap_or_default()
+    }
+}
+
+/// The `nameserver` addresses of a `resolv.conf`.
+fn parse_nameservers(conf: &str) -> Vec<IpAddr> {
+    conf.lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .filter_map(|server| server.trim().parse().ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host_config(dns: &[&str], search: &[&str], extra_hosts: &[&str]) -> HostConfig {
+        let strings = |values: &[&str]| values.iter().map(|v| v.to_string()).collect();
+        HostConfig {
+            dns: strings(dns),
+            dns_search: strings(search),
+            dns_options: vec!["ndots:2".to_string()],
+            extra_hosts: strings(extra_hosts),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_extra_host_parse() {
+        let host: ExtraHost = "db:10.0.0.5".parse().unwrap();
+        assert_eq!(host.name, "db");
+        assert_eq!(host.ip, "10.0.0.5".parse::<IpAddr>().unwrap());
+        assert_eq!(
+            "v6:2001:db8::33".parse::<ExtraHost>().unwrap().ip,
+            "2001:db8::33".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            "v6=[::1]".parse::<ExtraHost>().unwrap().ip,
+            "::1".parse::<IpAddr>().unwrap()
+        );
+        assert!("db".parse::<ExtraHost>().is_err());
+        assert!(":10.0.0.5".parse::<ExtraHost>().is_err());
+        assert!("db:nowhere".parse::<ExtraHost>().is_err());
+    }
+
+    #[test]
+    fn test_dns_config_files() {
+        let config = DnsConfig::from_host_config(&host_config(
+            &["1.1.1.1"],
+            &["example.com"],
+            &["db:10.0.0.5"],
+        ))
+        .unwrap();
+        assert!(config.configures_resolver());
+        assert_eq!(
+            config.resolv_conf(&config.nameservers_or_host()),
+            "nameserver 1.1.1.1\nsearch example.com\noptions ndots:2\n"
+        );
+        assert_eq!(
+            config.hosts("web", Some("192.168.127.2".parse().unwrap())),
+            "127.0.0.1\tlocalhost\n::1\tlocalhost ip6-localhost ip6-loopback\n\
+             10.0.0.5\tdb\n192.168.127.2\tweb\n"
+        );
+
+        let config = DnsConfig::from_host_config(&HostConfig::default()).unwrap();
+        assert!(!config.configures_resolver());
+        assert!(!config.configures_hosts());
+    }
+
+    #[test]
+    fn test_dns_config_rejects_invalid() {
+        assert!(DnsConfig::from_host_config(&host_config(&["dns.google"], &[], &[])).is_err());
+        assert!(DnsConfig::from_host_config(&host_config(&[], &["a b"], &[])).is_err());
+        assert!(DnsConfig::from_host_config(&host_config(&[], &[], &["db"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_nameservers() {
+        let conf =
+            "# generated\nnameserver 10.0.0.1\nnameserver  fd00::1\nsearch lan\nnameserver bogus\n";
+        assert_eq!(
+            parse_nameservers(conf),
+            vec![
+                "10.0.0.1".parse::<IpAddr>().unwrap(),
+                "fd00::1".parse().unwrap()
+            ]
+        );
+    }
+}
@@ -1,40 +1,126 @@
+use crate::bind::{BindMount, resolve_binds};
+use crate::egress::EgressPolicy;
 use crate::error::ShimError;
+use crate::exec::exec_process;
+use crate::logfile::{LogCopier, last_line};
+use crate::names::{NameReservation, NameReservations};
+use crate::resolv::DnsConfig;
+use crate::runc_version;
 use crate::shim::{OutputEventStream, Shim};
+use crate::stdin::StdinState;
+use crate::tmpfs::{TmpfsMount, parse_tmpfs, readonly_tmpfs};
 use crate::types::*;
+use crate::user::resolve_user;
 use async_trait::async_trait;
 use oci_spec::runtime::{
-    LinuxBuilder, LinuxNamespace, LinuxNamespaceBuilder, LinuxNamespaceType, Mount, MountBuilder,
-    ProcessBuilder, RootBuilder, Spec, SpecBuilder,
+    Linux, LinuxBuilder, LinuxCpuBuilder, LinuxMemoryBuilder, LinuxNamespace,
+    LinuxNamespaceBuilder, LinuxNamespaceType, LinuxResources, Mount, MountBuilder, ProcessBuilder,
+    RootBuilder, Spec, SpecBuilder,
 };
 use ross_mount::MountSpec;
 use runc::Runc;
 use runc::options::{DeleteOpts, GlobalOpts, KillOpts};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::Duration;
 use tokio::fs;
 use tokio::net::UnixListener;
-use tokio::sync::RwLock;
+use tokio::sync::{RwLock, broadcast, mpsc};
 use uuid::Uuid;
 
+/// How often `stop` checks whether the container has exited.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How long `stop` waits for the container to die after SIGKILL.
+const STOP_KILL_GRACE: Duration = Duration::from_secs(5);
+/// Output events buffered for each attached client before it starts lagging.
+const ATTACH_BUFFER: usize = 256;
+/// Mount point of the cgroup v2 hierarchy.
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct ContainerMetadata {
     info: ContainerInfo,
     config: ContainerConfig,
     host_config: HostConfig,
+    /// OOM kills counted in the container's cgroup when it last exited.
+    #[serde(default)]
+    oom_kills: u64,
+}
+
+/// Fan-out of a running container's I/O to attached clients.
+struct AttachHub {
+    output: broadcast::Sender<OutputEvent>,
+    input: Option<mpsc::Sender<InputEvent>>,
+}
+
+type AttachHubs = Arc<std::sync::Mutex<HashMap<String, AttachHub>>>;
+
+/// Keeps a container attachable until dropped.
+struct AttachRegistration {
+    hubs: AttachHubs,
+    id: String,
+    output: broadcast::Sender<OutputEvent>,
+}
+
+impl AttachRegistration {
+    fn new(hubs: &AttachHubs, id: &str, input: Option<mpsc::Sender<InputEvent>>) -> Self {
+        let (output, _) = broadcast::channel(ATTACH_BUFFER);
+        hubs.lock().unwrap().insert(
+            id.to_string(),
+            AttachHub {
+                output: output.clone(),
+                input,
+            },
+        );
+        Self {
+            hubs: hubs.clone(),
+            id: id.to_string(),
+            output,
+        }
+    }
+
+    /// Send `event` to every attached client. Having none is not an error.
+    fn publish(&self, event: &OutputEvent) {
+        let _ = self.output.send(event.clone());
+    }
+
+    /// Give clients that attach from now on no stdin, once it is closed.
+    fn close_input(&self) {
+        if let Some(hub) = self.hubs.lock().unwrap().get_mut(&self.id) {
+            hub.input = None;
+        }
+    }
+}
+
+impl Drop for AttachRegistration {
+    fn drop(&mut self) {
+        self.hubs.lock().unwrap().remove(&self.id);
+    }
 }
 
 pub struct RuncShim {
     runc: Runc,
     data_dir: PathBuf,
     containers: Arc<RwLock<HashMap<String, ContainerMetadata>>>,
+    /// Containers whose init process is being reaped by this daemon.
+    watched: Arc<std::sync::Mutex<HashSet<String>>>,
+    /// I/O of containers started in the foreground, for `attach`.
+    attached: AttachHubs,
+    names: NameReservations,
+    /// Serializes resource updates, which run runc without `containers` held.
+    updates: tokio::sync::Mutex<()>,
 }
 
 impl RuncShim {
     pub async fn new(data_dir: &Path) -> Result<Self, ShimError> {
+        // Without a usable runc no container could start; say so now rather
+        // than on the first start.
+        let version = runc_version::probe().await?;
+        tracing::info!(%version, "Using runc");
+
         let containers_dir = data_dir.join("containers");
         fs::create_dir_all(&containers_dir).await?;
 
@@ -45,10 +131,24 @@ impl RuncShim {
             .build()
             .map_err(|e| ShimError::Runc(e.to_string()))?;
 
+        // Become a child subreaper so that detached container processes are
+        // re-parented to the daemon and their exit status can be collected.
+        #[cfg(target_os = "linux")]
+        if unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) } != 0 {
+            tracing::warn!(
+                "Failed to become child subreaper, exit codes of detached containers will be unavailable: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+
         let shim = Self {
             runc,
             data_dir: data_dir.to_path_buf(),
             containers: Arc::new(RwLock::new(HashMap::new())),
+            watched: Arc::new(std::sync::Mutex::new(HashSet::new())),
+            attached: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            names: NameReservations::default(),
+            updates: tokio::sync::Mutex::new(()),
         };
 
         shim.load_containers().await?;
@@ -75,15 +175,59 @@ impl RuncShim {
     }
 
     async fn save_container(&self, metadata: &ContainerMetadata) -> Result<(), ShimError> {
-        let container_dir = self.data_dir.join("containers").join(&metadata.info.id);
-        fs::create_dir_all(&container_dir).await?;
-        let metadata_path = container_dir.join("metadata.json");
-        let content = serde_json::to_string_pretty(metadata)?;
-        fs::write(&metadata_path, content).await?;
-        Ok(())
+        persist_metadata(&self.data_dir, metadata).await
+    }
+
+    fn is_watched(&self, id: &str) -> bool {
+        self.watched.lock().unwrap().contains(id)
     }
 
-    pub async fn create(&self, opts: CreateContainerOpts) -> Result<String, ShimError> {
+    /// Reap the container's init process in the background and persist its
+    /// exit status once it terminates.
+    fn watch_exit(&self, id: &str, pid: u32) {
+        self.watched.lock().unwrap().insert(id.to_string());
+
+        let containers = self.containers.clone();
+        let watched = self.watched.clone();
+        let data_dir = self.data_dir.clone();
+        let id = id.to_string();
+
+        tokio::spawn(async move {
+            let exit_code = tokio::task::spawn_blocking(move || reap_pid(pid))
+                .await
+                .ok()
+                .flatten();
+
+            if let Some(exit_code) = exit_code {
+                let mut containers = containers.write().await;
+                if let Some(metadata) = containers.get_mut(&id) {
+                    metadata.info.state = ContainerState::Stopped;
+                    metadata.info.exit_code = Some(exit_code);
+                    metadata.info.mark_finished();
+                    metadata.info.pid = None;
+                    record_oom_kills(metadata).await;
+                    if let Err(e) = persist_metadata(&data_dir, metadata).await {
+                        tracing::warn!(container_id = %id, "Failed to persist exit status: {}", e);
+                    }
+                }
+                tracing::info!(container_id = %id, exit_code = exit_code, "Container process exited");
+            }
+
+            watched.lock().unwrap().remove(&id);
+        });
+    }
+
+    /// Claims `name` for a container being created, failing if a container
+    /// has it or another creation claimed it first.
+    async fn reserve_name(&self, name: &str) -> Result<NameReservation, ShimError> {
+        let containers = self.containers.read().await;
+        let taken = containers
+            .values()
+            .any(|m| m.info.name.as_deref() == Some(name));
+        self.names.reserve(name, taken)
+    }
+
+    pub async fn create(&self, mut opts: CreateContainerOpts) -> Result<String, ShimError> {
         let id = Uuid::new_v4().to_string();
 
         {
@@ -92,16 +236,83 @@ impl RuncShim {
                 return Err(ShimError::ContainerAlreadyExists(id));
             }
         }
+        // Held until the container is in the map, so a concurrent create
+        // cannot take the same name.
+        let _reservation = match &opts.name {
+            Some(name) => Some(self.reserve_name(name).await?),
+            None => None,
+        };
+
+        // Reject bad volumes, DNS options, egress policies and network modes
+        // before preparing anything.
+        let mut binds = resolve_binds(&opts.host_config.binds)?;
+        let tmpfs = parse_tmpfs(&opts.host_config.tmpfs, &binds)?;
+        let dns = DnsConfig::from_host_config(&opts.host_config)?;
+        let network_mode = opts.host_config.network()?;
+        // Outside host mode the container only has loopback, which no policy
+        // forbids; in host mode nothing would enforce one.
+        if EgressPolicy::from_host_config(&opts.host_config)?.is_restricted()
+            && network_mode == NetworkMode::Host
+        {
+            return Err(ShimError::NotSupported(
+                "egress policies cannot be enforced in host network mode".to_string(),
+            ));
+        }
+        opts.host_config.network_mode = Some(network_mode.to_string());
 
         let bundle_path = self.data_dir.join("containers").join(&id).join("bundle");
         let rootfs_path = bundle_path.join("rootfs");
         fs::create_dir_all(&bundle_path).await?;
         fs::create_dir_all(&rootfs_path).await?;
 
+        // Resolver files the container configures are generated in the
+        // bundle and bound over the image's, unless a volume replaces them.
+        let hostname = opts.config.hostname.as_deref().unwrap_or("container");
+        let dns_files = [
+            (
+                "/etc/resolv.conf",
+                dns.configures_resolver()
+                    .then(|| dns.resolv_conf(&dns.nameservers_or_host())),
+            ),
+            (
+                "/etc/hosts",
+                dns.configures_hosts().then(|| dns.hosts(hostname, None)),
+            ),
+        ];
+        for (destination, contents) in dns_files {
+            let Some(contents) = contents else {
+                continue;
+            };
+            if binds.iter().any(|bind| bind.destination == destination) {
+                continue;
+            }
+            let source = bundle_path.join(destination.trim_start_matches("/etc/"));
+            fs::write(&source, contents).await?;
+            binds.push(BindMount {
+                source,
+                destination: destination.to_string(),
+                read_only: false,
+                recursive: false,
+                propagation: "rprivate".to_string(),
+                create: false,
+            });
+        }
+
         // Mount the rootfs using the snapshotter mount specification
         self.mount_rootfs(&opts.mounts, &rootfs_path).await?;
 
-        let spec = self.generate_spec(&opts, &rootfs_path)?;
+        // Resolving the user reads the image's account files, so the spec is
+        // built on the mounted rootfs; undo the mount if that fails.
+        let spec = match Self::generate_spec(&id, &opts, &rootfs_path, &binds, &tmpfs) {
+            Ok(spec) => spec,
+            Err(e) => {
+                if let Err(e) = ross_mount::unmount(&rootfs_path) {
+                    tracing::warn!("Failed to unmount rootfs: {}", e);
+                }
+                let _ = fs::remove_dir_all(self.data_dir.join("containers").join(&id)).await;
+                return Err(e);
+            }
+        };
         tracing::info!(
             "Generated OCI spec with args: {:?}",
             spec.process().as_ref().and_then(|p| p.args().as_ref())
@@ -117,10 +328,7 @@ impl RuncShim {
         fs::write(&stdout_path, "").await?;
         fs::write(&stderr_path, "").await?;
 
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64;
+        let now = since_epoch();
 
         let info = ContainerInfo {
             id: id.clone(),
@@ -129,17 +337,32 @@ impl RuncShim {
             state: ContainerState::Created,
             pid: None,
             exit_code: None,
-            created_at: now,
+            created_at: now.as_secs() as i64,
             started_at: None,
             finished_at: None,
+            created_nanos: now.subsec_nanos(),
+            started_nanos: 0,
+            finished_nanos: 0,
             bundle_path: bundle_path.to_string_lossy().to_string(),
             rootfs_path: rootfs_path.to_string_lossy().to_string(),
+            restart_policy: opts.host_config.restart_policy,
+            restart_count: 0,
+            manually_stopped: false,
+            healthcheck: opts.config.healthcheck.clone(),
+            image_id: opts.config.image_id.clone(),
+            command: opts.config.command(),
+            network_mode: opts.host_config.network_mode.clone().unwrap_or_default(),
+            oom_killed: false,
+            auto_remove: opts.host_config.auto_remove,
+            resources: ResourceLimits::of(&opts.host_config),
+            labels: opts.config.labels.clone(),
         };
 
         let metadata = ContainerMetadata {
             info,
             config: opts.config,
             host_config: opts.host_config,
+            oom_kills: 0,
         };
 
         self.save_container(&metadata).await?;
@@ -155,7 +378,7 @@ impl RuncShim {
 
     async fn mount_rootfs(&self, mounts: &[SnapshotMount], target: &Path) -> Result<(), ShimError> {
         if mounts.is_empty() {
-            return Err(ShimError::Runc("No mounts provided".to_string()));
+            return Err(ShimError::MountFailed("no mounts provided".to_string()));
         }
 
         let mount = &mounts[0];
@@ -169,52 +392,70 @@ impl RuncShim {
         let spec = MountSpec::new(&mount.mount_type, &mount.source, mount.options.clone());
 
         ross_mount::mount_overlay(&spec, target)
-            .map_err(|e| ShimError::Runc(format!("Failed to mount rootfs: {}", e)))?;
+            .map_err(|e| ShimError::MountFailed(e.to_string()))?;
 
         Ok(())
     }
 
     pub async fn start(&self, id: &str) -> Result<(), ShimError> {
         let bundle_path: PathBuf;
+        let restarting: bool;
         {
             let mut containers = self.containers.write().await;
             let metadata = containers
                 .get_mut(id)
                 .ok_or_else(|| ShimError::ContainerNotFound(id.to_string()))?;
 
-            if metadata.info.state != ContainerState::Created {
+            if metadata.info.state != ContainerState::Created
+                && metadata.info.state != ContainerState::Stopped
+            {
                 return Err(ShimError::InvalidState {
-                    expected: "created".to_string(),
+                    expected: "created or stopped".to_string(),
                     actual: metadata.info.state.to_string(),
                 });
             }
 
             bundle_path = PathBuf::from(&metadata.info.bundle_path);
-
-            let now = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs() as i64;
+            restarting = metadata.info.state == ContainerState::Stopped;
 
             metadata.info.state = ContainerState::Running;
-            metadata.info.started_at = Some(now);
+            metadata.info.mark_started();
+            metadata.info.exit_code = None;
+            metadata.info.oom_killed = false;
+            metadata.info.manually_stopped = false;
             self.save_container(metadata).await?;
         }
 
-        // Use runc run with --detach to start the container in background
-        // Redirect stdout/stderr to log files
+        // runc keeps the state of an exited container around; it has to go
+        // before the same id can be run again.
+        if restarting
+            && let Err(e) = self
+                .runc
+                .delete(id, Some(&DeleteOpts::new().force(true)))
+                .await
+            && !e.to_string().contains("does not exist")
+        {
+            tracing::warn!(container_id = %id, "Failed to delete previous runc state: {}", e);
+        }
+
+        // Use runc run with --detach to start the container in background.
+        // The container inherits runc's stdout/stderr, pipes copied into the
+        // log files with a timestamp per line by processes of their own, so
+        // that the container keeps being logged when the daemon is gone.
         let runc_root = self.data_dir.join("runc");
         let pid_file = bundle_path.join("container.pid");
         let stdout_path = bundle_path.join("stdout.log");
         let stderr_path = bundle_path.join("stderr.log");
 
-        let stdout_file = std::fs::File::create(&stdout_path)
-            .map_err(|e| ShimError::Runc(format!("Failed to create stdout log: {}", e)))?;
-        let stderr_file = std::fs::File::create(&stderr_path)
-            .map_err(|e| ShimError::Runc(format!("Failed to create stderr log: {}", e)))?;
+        let (stdout, stdout_writer) = std::io::pipe()?;
+        let (stderr, stderr_writer) = std::io::pipe()?;
+        let _stdout_copier = LogCopier::spawn(stdout.into(), &stdout_path, "stdout")?;
+        let stderr_copier = LogCopier::spawn(stderr.into(), &stderr_path, "stderr")?;
 
         tracing::info!(container_id = %id, bundle = ?bundle_path, "Starting container with runc run");
 
+        // The command, and with it the daemon's copy of the write ends, is
+        // dropped once runc is spawned.
         let mut child = tokio::process::Command::new("runc")
             .arg("--root")
             .arg(&runc_root)
@@ -227,8 +468,8 @@ impl RuncShim {
             .arg("--detach")
             .arg(id)
             .stdin(std::process::Stdio::null())
-            .stdout(stdout_file)
-            .stderr(stderr_file)
+            .stdout(stdout_writer)
+            .stderr(stderr_writer)
             .spawn()
             .map_err(|e| ShimError::Runc(format!("Failed to spawn runc: {}", e)))?;
 
@@ -239,57 +480,84 @@ impl RuncShim {
 
         if !status.success() {
             tracing::error!(container_id = %id, status = ?status, "runc run failed");
-            return Err(ShimError::Runc(format!(
-                "runc run failed with status: {}",
-                status
-            )));
+            // runc says why on the stderr it shares with the container; with
+            // no container left, the copy ends once runc is gone.
+            stderr_copier.wait().await;
+            let reason = last_line(&stderr_path).await;
+            return Err(ShimError::runc_exited(
+                id,
+                "run",
+                status,
+                reason.as_deref().unwrap_or_default(),
+            ));
         }
 
         // Read PID from pid file
         if let Ok(pid_str) = fs::read_to_string(&pid_file).await
             && let Ok(pid) = pid_str.trim().parse::<u32>()
         {
-            let mut containers = self.containers.write().await;
-            if let Some(metadata) = containers.get_mut(id) {
-                metadata.info.pid = Some(pid);
-                let _ = self.save_container(metadata).await;
+            {
+                let mut containers = self.containers.write().await;
+                if let Some(metadata) = containers.get_mut(id) {
+                    metadata.info.pid = Some(pid);
+                    let _ = self.save_container(metadata).await;
+                }
             }
+            self.watch_exit(id, pid);
         }
 
         tracing::info!(container_id = %id, "Container started");
         Ok(())
     }
 
-    pub async fn stop(&self, id: &str, timeout: u32) -> Result<(), ShimError> {
-        let mut containers = self.containers.write().await;
-        let metadata = containers
-            .get_mut(id)
-            .ok_or_else(|| ShimError::ContainerNotFound(id.to_string()))?;
-
-        if metadata.info.state != ContainerState::Running {
-            return Err(ShimError::ContainerNotRunning(id.to_string()));
-        }
+    pub async fn stop(&self, id: &str, timeout: u32, manual: bool) -> Result<bool, ShimError> {
+        let stop_signal = {
+            let mut containers = self.containers.write().await;
+            let metadata = containers
+                .get_mut(id)
+                .ok_or_else(|| ShimError::ContainerNotFound(id.to_string()))?;
 
-        self.runc.kill(id, 15, None).await?;
+            if metadata.info.state != ContainerState::Running {
+                return Err(ShimError::ContainerNotRunning(id.to_string()));
+            }
 
-        tokio::time::sleep(tokio::time::Duration::from_secs(timeout as u64)).await;
+            // Mark before signalling so a restart policy watching the exit
+            // never sees the container as having died on its own.
+            if manual {
+                metadata.info.manually_stopped = true;
+                self.save_container(metadata).await?;
+            }
+            metadata.config.stop_signal.unwrap_or(15)
+        };
 
-        let kill_opts = KillOpts::new().all(true);
-        let _ = self.runc.kill(id, 9, Some(&kill_opts)).await;
+        self.runc
+            .kill(id, stop_signal, None)
+            .await
+            .map_err(|e| ShimError::runc(id, e))?;
+
+        // Don't hold the lock while waiting, the reaper needs it to record the exit status.
+        let timeout = Duration::from_secs(timeout as u64);
+        let killed = !self.wait_stopped(id, timeout).await?;
+        if killed {
+            tracing::info!(container_id = %id, "Container did not stop in time, killing it");
+            let kill_opts = KillOpts::new().all(true);
+            let _ = self.runc.kill(id, 9, Some(&kill_opts)).await;
+            let _ = self.wait_stopped(id, STOP_KILL_GRACE).await;
+        }
 
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64;
+        let mut containers = self.containers.write().await;
+        let metadata = containers
+            .get_mut(id)
+            .ok_or_else(|| ShimError::ContainerNotFound(id.to_string()))?;
 
         metadata.info.state = ContainerState::Stopped;
-        metadata.info.finished_at = Some(now);
+        metadata.info.mark_finished();
         metadata.info.pid = None;
 
         self.save_container(metadata).await?;
 
         tracing::info!(container_id = %id, "Container stopped");
-        Ok(())
+        Ok(killed)
     }
 
     pub async fn kill(&self, id: &str, signal: u32) -> Result<(), ShimError> {
@@ -302,7 +570,10 @@ impl RuncShim {
             return Err(ShimError::ContainerNotRunning(id.to_string()));
         }
 
-        self.runc.kill(id, signal, None).await?;
+        self.runc
+            .kill(id, signal, None)
+            .await
+            .map_err(|e| ShimError::runc(id, e))?;
 
         tracing::info!(container_id = %id, signal = signal, "Signal sent to container");
         Ok(())
@@ -332,10 +603,11 @@ impl RuncShim {
         if let Err(e) = self.runc.delete(id, Some(&delete_opts)).await {
             let err_str = e.to_string();
             if !err_str.contains("does not exist") {
-                return Err(e.into());
+                return Err(ShimError::runc(id, e));
             }
             tracing::debug!(container_id = %id, "Container already removed from runc");
         }
+        let _ = fs::remove_dir(cgroup_parent(id)).await;
 
         // Unmount the rootfs
         if rootfs_path.exists()
@@ -353,6 +625,7 @@ impl RuncShim {
             let mut containers = self.containers.write().await;
             containers.remove(id);
         }
+        self.watched.lock().unwrap().remove(id);
 
         tracing::info!(container_id = %id, "Container deleted");
         Ok(())
@@ -368,7 +641,10 @@ impl RuncShim {
             return Err(ShimError::ContainerNotRunning(id.to_string()));
         }
 
-        self.runc.pause(id).await?;
+        self.runc
+            .pause(id)
+            .await
+            .map_err(|e| ShimError::runc(id, e))?;
         metadata.info.state = ContainerState::Paused;
         self.save_container(metadata).await?;
 
@@ -389,7 +665,10 @@ impl RuncShim {
             });
         }
 
-        self.runc.resume(id).await?;
+        self.runc
+            .resume(id)
+            .await
+            .map_err(|e| ShimError::runc(id, e))?;
         metadata.info.state = ContainerState::Running;
         self.save_container(metadata).await?;
 
@@ -397,6 +676,107 @@ impl RuncShim {
         Ok(())
     }
 
+    pub async fn rename(&self, id: &str, new_name: &str) -> Result<(), ShimError> {
+        let mut containers = self.containers.write().await;
+
+        if self.names.contains(new_name)
+            || containers
+                .values()
+                .any(|m| m.info.id != id && m.info.name.as_deref() == Some(new_name))
+        {
+            return Err(ShimError::ContainerAlreadyExists(new_name.to_string()));
+        }
+
+        let metadata = containers
+            .get_mut(id)
+            .ok_or_else(|| ShimError::ContainerNotFound(id.to_string()))?;
+
+        metadata.info.name = Some(new_name.to_string());
+        self.save_container(metadata).await?;
+
+        tracing::info!(container_id = %id, name = %new_name, "Container renamed");
+        Ok(())
+    }
+
+    /// Changes the container's cgroup limits with `runc update` while it
+    /// runs, and in its spec so that its next start keeps them.
+    pub async fn update(&self, id: &str, update: ResourceLimits) -> Result<(), ShimError> {
+        // runc runs without `containers` held, so as not to stall every other
+        // container on it; updates wait for one another instead.
+        let _update = self.updates.lock().await;
+        let running = {
+            let containers = self.containers.read().await;
+            let metadata = containers
+                .get(id)
+                .ok_or_else(|| ShimError::ContainerNotFound(id.to_string()))?;
+            matches!(
+                metadata.info.state,
+                ContainerState::Running | ContainerState::Paused
+            )
+        };
+
+        if running {
+            if let Some(limit) = update.memory_limit {
+                check_memory_limit(limit, memory_usage(id).await)?;
+            }
+
+            let mut command = tokio::process::Command::new("runc");
+            command
+                .arg("--root")
+                .arg(self.data_dir.join("runc"))
+                .arg("update");
+            if let Some(limit) = update.memory_limit {
+                // As at create, swap equal to the limit disables swap.
+                command
+                    .arg("--memory")
+                    .arg(limit.to_string())
+                    .arg("--memory-swap")
+                    .arg(limit.to_string());
+            }
+            if let Some(quota) = update.cpu_quota {
+                command.arg("--cpu-quota").arg(quota.to_string());
+            }
+            if let Some(period) = update.cpu_period {
+                command.arg("--cpu-period").arg(period.to_string());
+            }
+            let output = command
+                .arg(id)
+                .output()
+                .await
+                .map_err(|e| ShimError::Runc(format!("Failed to run runc update: {}", e)))?;
+            if !output.status.success() {
+                return Err(ShimError::runc_exited(
+                    id,
+                    "update",
+                    output.status,
+                    &String::from_utf8_lossy(&output.stderr),
+                ));
+            }
+        }
+
+        let mut containers = self.containers.write().await;
+        let metadata = containers
+            .get_mut(id)
+            .ok_or_else(|| ShimError::ContainerNotFound(id.to_string()))?;
+        let mut host_config = metadata.host_config.clone();
+        update.apply_to(&mut host_config);
+
+        let spec_path = Path::new(&metadata.info.bundle_path).join("config.json");
+        let mut spec: Spec = serde_json::from_slice(&fs::read(&spec_path).await?)?;
+        if let Some(mut linux) = spec.linux().clone() {
+            linux.set_resources(Some(Self::generate_resources(&host_config)?));
+            spec.set_linux(Some(linux));
+        }
+        fs::write(&spec_path, serde_json::to_string_pretty(&spec)?).await?;
+
+        metadata.info.resources = ResourceLimits::of(&host_config);
+        metadata.host_config = host_config;
+        self.save_container(metadata).await?;
+
+        tracing::info!(container_id = %id, resources = ?metadata.info.resources, "Container resources updated");
+        Ok(())
+    }
+
     pub async fn list(&self) -> Result<Vec<ContainerInfo>, ShimError> {
         let containers = self.containers.read().await;
         Ok(containers.values().map(|m| m.info.clone()).collect())
@@ -410,82 +790,94 @@ impl RuncShim {
             .ok_or_else(|| ShimError::ContainerNotFound(id.to_string()))
     }
 
-    async fn get_container_exit_code(&self, id: &str) -> Result<i32, ShimError> {
-        let runc_root = self.data_dir.join("runc");
-
-        // Poll until container exits
-        loop {
-            let output = tokio::process::Command::new("runc")
-                .arg("--root")
-                .arg(&runc_root)
-                .arg("state")
-                .arg(id)
-                .output()
-                .await
-                .map_err(|e| ShimError::Runc(format!("Failed to get runc state: {}", e)))?;
+    /// Returns the runc status of the container, or `None` if runc no longer knows it.
+    async fn runc_status(&self, id: &str) -> Result<Option<String>, ShimError> {
+        let output = tokio::process::Command::new("runc")
+            .arg("--root")
+            .arg(self.data_dir.join("runc"))
+            .arg("state")
+            .arg(id)
+            .output()
+            .await
+            .map_err(|e| ShimError::Runc(format!("Failed to get runc state: {}", e)))?;
 
-            if !output.status.success() {
-                // Container is gone, default to 0
-                return Ok(0);
-            }
+        if !output.status.success() {
+            return Ok(None);
+        }
 
-            let state_json: serde_json::Value = serde_json::from_slice(&output.stdout)
-                .map_err(|e| ShimError::Runc(format!("Failed to parse runc state: {}", e)))?;
+        let state_json: serde_json::Value = serde_json::from_slice(&output.stdout)
+            .map_err(|e| ShimError::Runc(format!("Failed to parse runc state: {}", e)))?;
+        Ok(Some(
+            state_json["status"].as_str().unwrap_or("").to_string(),
+        ))
+    }
 
-            let status = state_json["status"].as_str().unwrap_or("");
-            if status == "stopped" {
-                // Try to get exit code from state, default to 0
-                // Note: runc state doesn't include exit code directly
-                return Ok(0);
+    /// Polls runc until the container stops or `timeout` elapses; returns whether it stopped.
+    async fn wait_stopped(&self, id: &str, timeout: Duration) -> Result<bool, ShimError> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            if self.runc_status(id).await?.is_none_or(|s| s == "stopped") {
+                return Ok(true);
             }
-
-            tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+            if tokio::time::Instant::now() >= deadline {
+                return Ok(false);
+            }
+            tokio::time::sleep(STOP_POLL_INTERVAL).await;
         }
     }
 
     pub async fn wait(&self, id: &str) -> Result<WaitResult, ShimError> {
-        let runc_root = self.data_dir.join("runc");
-
         loop {
-            // Check runc state to see if container is still running
-            let output = tokio::process::Command::new("runc")
-                .arg("--root")
-                .arg(&runc_root)
-                .arg("state")
-                .arg(id)
-                .output()
-                .await
-                .map_err(|e| ShimError::Runc(format!("Failed to get runc state: {}", e)))?;
-
-            let container_gone = !output.status.success();
-            let is_stopped = if !container_gone {
-                let state_json: serde_json::Value = serde_json::from_slice(&output.stdout)
-                    .map_err(|e| ShimError::Runc(format!("Failed to parse runc state: {}", e)))?;
-                let status = state_json["status"].as_str().unwrap_or("");
-                tracing::debug!(container_id = %id, status = %status, "Container status");
-                status == "stopped"
-            } else {
-                true
-            };
+            let watched = self.is_watched(id);
+            {
+                let containers = self.containers.read().await;
+                let metadata = containers
+                    .get(id)
+                    .ok_or_else(|| ShimError::ContainerNotFound(id.to_string()))?;
+
+                // While the reaper is running, wait for it to record the real status
+                if metadata.info.state == ContainerState::Stopped
+                    && (metadata.info.exit_code.is_some() || !watched)
+                {
+                    return Ok(WaitResult {
+                        exit_code: metadata.info.exit_code.unwrap_or(0),
+                        error: None,
+                    });
+                }
+            }
+
+            if watched {
+                tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+                continue;
+            }
+
+            // The process is not our child (e.g. it was started before a daemon
+            // restart), so fall back to polling runc for the container state.
+            let status = self.runc_status(id).await?;
+            tracing::debug!(container_id = %id, status = ?status, "Container status");
 
-            if container_gone || is_stopped {
+            if status.is_none_or(|s| s == "stopped") {
                 tracing::info!(container_id = %id, "Container has stopped");
 
-                // Update internal state
                 let mut containers = self.containers.write().await;
-                if let Some(metadata) = containers.get_mut(id) {
-                    let now = SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs() as i64;
-                    metadata.info.state = ContainerState::Stopped;
-                    metadata.info.finished_at = Some(now);
-                    metadata.info.exit_code = Some(0); // TODO: get actual exit code
-                    let _ = self.save_container(metadata).await;
+                let exit_code = match containers.get_mut(id) {
+                    Some(metadata) => {
+                        metadata.info.state = ContainerState::Stopped;
+                        metadata.info.mark_finished();
+                        metadata.info.pid = None;
+                        record_oom_kills(metadata).await;
+                        let _ = self.save_container(metadata).await;
+                        metadata.info.exit_code
+                    }
+                    None => None,
+                };
+
+                if exit_code.is_none() {
+                    tracing::warn!(container_id = %id, "Exit status of container is unavailable");
                 }
 
                 return Ok(WaitResult {
-                    exit_code: 0,
+                    exit_code: exit_code.unwrap_or(0),
                     error: None,
                 });
             }
@@ -494,6 +886,17 @@ impl RuncShim {
         }
     }
 
+    pub async fn record_restart(&self, id: &str) -> Result<u32, ShimError> {
+        let mut containers = self.containers.write().await;
+        let metadata = containers
+            .get_mut(id)
+            .ok_or_else(|| ShimError::ContainerNotFound(id.to_string()))?;
+
+        metadata.info.restart_count += 1;
+        self.save_container(metadata).await?;
+        Ok(metadata.info.restart_count)
+    }
+
     /// Run a container and stream its output. This is a combined start+wait operation
     /// that captures stdout/stderr in real-time.
     pub fn run_streaming(
@@ -502,6 +905,7 @@ impl RuncShim {
     ) -> impl futures::Stream<Item = Result<OutputEvent, ShimError>> + Send + 'static {
         let data_dir = self.data_dir.clone();
         let containers = self.containers.clone();
+        let attached = self.attached.clone();
 
         async_stream::try_stream! {
             let bundle_path: PathBuf;
@@ -520,13 +924,8 @@ impl RuncShim {
 
                 bundle_path = PathBuf::from(&metadata.info.bundle_path);
 
-                let now = SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs() as i64;
-
                 metadata.info.state = ContainerState::Running;
-                metadata.info.started_at = Some(now);
+                metadata.info.mark_started();
 
                 let container_dir = data_dir.join("containers").join(&metadata.info.id);
                 fs::create_dir_all(&container_dir).await?;
@@ -564,6 +963,8 @@ impl RuncShim {
             let mut stdout_reader = tokio::io::BufReader::new(stdout);
             let mut stderr_reader = tokio::io::BufReader::new(stderr);
 
+            let registration = AttachRegistration::new(&attached, &id, None);
+
             let mut stdout_buf = vec![0u8; 4096];
             let mut stderr_buf = vec![0u8; 4096];
 
@@ -573,7 +974,9 @@ impl RuncShim {
                         match result {
                             Ok(0) => {}, // EOF on stdout
                             Ok(n) => {
-                                yield OutputEvent::Stdout(stdout_buf[..n].to_vec());
+                                let event = OutputEvent::Stdout(stdout_buf[..n].to_vec());
+                                registration.publish(&event);
+                                yield event;
                             }
                             Err(e) => {
                                 tracing::warn!("Error reading stdout: {}", e);
@@ -584,7 +987,9 @@ impl RuncShim {
                         match result {
                             Ok(0) => {}, // EOF on stderr
                             Ok(n) => {
-                                yield OutputEvent::Stderr(stderr_buf[..n].to_vec());
+                                let event = OutputEvent::Stderr(stderr_buf[..n].to_vec());
+                                registration.publish(&event);
+                                yield event;
                             }
                             Err(e) => {
                                 tracing::warn!("Error reading stderr: {}", e);
@@ -593,7 +998,7 @@ impl RuncShim {
                     }
                     status = child.wait() => {
                         let exit_code = match status {
-                            Ok(s) => s.code().unwrap_or(-1),
+                            Ok(s) => exit_code_from_status(&s),
                             Err(e) => {
                                 tracing::error!("Error waiting for child: {}", e);
                                 -1
@@ -603,13 +1008,10 @@ impl RuncShim {
                         // Update internal state
                         let mut containers_guard = containers.write().await;
                         if let Some(metadata) = containers_guard.get_mut(&id) {
-                            let now = SystemTime::now()
-                                .duration_since(UNIX_EPOCH)
-                                .unwrap()
-                                .as_secs() as i64;
                             metadata.info.state = ContainerState::Stopped;
-                            metadata.info.finished_at = Some(now);
+                            metadata.info.mark_finished();
                             metadata.info.exit_code = Some(exit_code);
+                            record_oom_kills(metadata).await;
 
                             let container_dir = data_dir.join("containers").join(&metadata.info.id);
                             let metadata_path = container_dir.join("metadata.json");
@@ -620,10 +1022,12 @@ impl RuncShim {
 
                         tracing::info!(container_id = %id, exit_code = exit_code, "Container exited");
 
-                        yield OutputEvent::Exit(WaitResult {
+                        let event = OutputEvent::Exit(WaitResult {
                             exit_code,
                             error: None,
                         });
+                        registration.publish(&event);
+                        yield event;
 
                         break;
                     }
@@ -632,8 +1036,100 @@ impl RuncShim {
         }
     }
 
+    /// Run an additional process inside a running container using `runc exec`.
+    pub fn exec(
+        &self,
+        id: String,
+        opts: ExecOpts,
+    ) -> impl futures::Stream<Item = Result<OutputEvent, ShimError>> + Send + 'static {
+        let data_dir = self.data_dir.clone();
+        let containers = self.containers.clone();
+
+        async_stream::try_stream! {
+            let (rootfs_path, bundle_path) = {
+                let containers_guard = containers.read().await;
+                let metadata = containers_guard
+                    .get(&id)
+                    .ok_or_else(|| ShimError::ContainerNotFound(id.clone()))?;
+
+                if metadata.info.state != ContainerState::Running {
+                    Err(ShimError::ContainerNotRunning(id.clone()))?;
+                }
+                (
+                    PathBuf::from(&metadata.info.rootfs_path),
+                    PathBuf::from(&metadata.info.bundle_path),
+                )
+            };
+
+            if opts.cmd.is_empty() {
+                Err(ShimError::RuntimeError("exec command must not be empty".to_string()))?;
+            }
+
+            // The exec process starts from the container's own, so that it
+            // keeps the container's environment, user and capabilities.
+            let spec = fs::read(bundle_path.join("config.json")).await?;
+            let spec: Spec = serde_json::from_slice(&spec)?;
+            let process = exec_process(spec.process().as_ref(), &opts, &rootfs_path)?;
+            let exec_id = Uuid::new_v4();
+            let process_path = bundle_path.join(format!("exec-{}.json", exec_id));
+            fs::write(&process_path, serde_json::to_vec(&process)?).await?;
+
+            let pid_file = bundle_path.join(format!("exec-{}.pid", exec_id));
+            let runc_root = data_dir.join("runc");
+
+            let mut command = tokio::process::Command::new("runc");
+            command
+                .arg("--root")
+                .arg(&runc_root)
+                .arg("exec")
+                .arg("--process")
+                .arg(&process_path)
+                .arg("--pid-file")
+                .arg(&pid_file)
+                .arg(&id);
+
+            tracing::info!(container_id = %id, cmd = ?opts.cmd, "Executing process with runc exec");
+
+            for await event in run_exec(command, process_path, pid_file, opts.timeout) {
+                let event = event?;
+                if let OutputEvent::Exit(result) = &event {
+                    tracing::info!(container_id = %id, exit_code = result.exit_code, "Exec process exited");
+                }
+                yield event;
+            }
+        }
+    }
+
+    /// Connect to a container started by `run_streaming` or `run_interactive`.
+    /// Containers started detached write their output to log files instead.
+    pub async fn attach(&self, id: &str) -> Result<AttachStreams, ShimError> {
+        {
+            let containers = self.containers.read().await;
+            let metadata = containers
+                .get(id)
+                .ok_or_else(|| ShimError::ContainerNotFound(id.to_string()))?;
+            if metadata.info.state != ContainerState::Running {
+                return Err(ShimError::ContainerNotRunning(id.to_string()));
+            }
+        }
+
+        let attached = self.attached.lock().unwrap();
+        let hub = attached.get(id).ok_or_else(|| {
+            ShimError::NotSupported(format!(
+                "container {} was started detached, use logs to follow its output",
+                id
+            ))
+        })?;
+
+        Ok(AttachStreams {
+            output: hub.output.subscribe(),
+            input: hub.input.clone(),
+        })
+    }
+
     /// Run a container interactively with a PTY for stdin/stdout.
     /// This uses runc's console-socket feature to get a PTY master fd.
+    /// Containers without a TTY get pipes instead.
     pub async fn run_interactive(
         &self,
         id: String,
@@ -641,6 +1137,8 @@ impl RuncShim {
         output_tx: tokio::sync::mpsc::Sender<OutputEvent>,
     ) -> Result<(), ShimError> {
         let bundle_path: PathBuf;
+        let stdin: StdinState;
+        let tty: bool;
         {
             let mut containers = self.containers.write().await;
             let metadata = containers
@@ -655,17 +1153,20 @@ impl RuncShim {
             }
 
             bundle_path = PathBuf::from(&metadata.info.bundle_path);
-
-            let now = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs() as i64;
+            stdin = StdinState::new(&metadata.config);
+            tty = metadata.config.tty;
 
             metadata.info.state = ContainerState::Running;
-            metadata.info.started_at = Some(now);
+            metadata.info.mark_started();
             self.save_container(metadata).await?;
         }
 
+        if !tty {
+            return self
+                .run_interactive_piped(id, bundle_path, stdin, input_rx, output_tx)
+                .await;
+        }
+
         let runc_root = self.data_dir.join("runc");
         let pid_file = bundle_path.join("container.pid");
         let console_socket_path = bundle_path.join("console.sock");
@@ -762,21 +1263,42 @@ impl RuncShim {
                 use std::io::Read;
                 let _ = stderr.read_to_string(&mut stderr_output);
             }
-            return Err(ShimError::Runc(format!(
-                "runc run failed with status {}: {}",
-                runc_status, stderr_output
-            )));
+            return Err(ShimError::runc_exited(
+                &id,
+                "run",
+                runc_status,
+                &stderr_output,
+            ));
         }
 
         tracing::info!(container_id = %id, "runc started container in detached mode");
 
-        let containers = self.containers.clone();
-        let data_dir = self.data_dir.clone();
-        let id_for_cleanup = id.clone();
+        if let Ok(pid_str) = fs::read_to_string(&pid_file).await
+            && let Ok(pid) = pid_str.trim().parse::<u32>()
+        {
+            {
+                let mut containers = self.containers.write().await;
+                if let Some(metadata) = containers.get_mut(&id) {
+                    metadata.info.pid = Some(pid);
+                    let _ = self.save_container(metadata).await;
+                }
+            }
+            self.watch_exit(&id, pid);
+        }
+
+        // Share the PTY with clients that attach later
+        let (attach_input_tx, mut attach_input_rx) = mpsc::channel::<InputEvent>(ATTACH_BUFFER);
+        let registration = Arc::new(AttachRegistration::new(
+            &self.attached,
+            &id,
+            Some(attach_input_tx),
+        ));
 
         // Read PTY output and send to output channel
         let output_tx_clone = output_tx.clone();
+        let read_registration = registration.clone();
         let read_task = tokio::spawn(async move {
+            let mut output_open = true;
             tracing::debug!("PTY read task started");
             let mut buf = vec![0u8; 4096];
             loop {
@@ -793,13 +1315,13 @@ impl RuncShim {
                             }
                             Ok(Ok(n)) => {
                                 tracing::debug!("Read {} bytes from PTY", n);
-                                if output_tx_clone
-                                    .send(OutputEvent::Stdout(buf[..n].to_vec()))
-                                    .await
-                                    .is_err()
-                                {
+                                let event = OutputEvent::Stdout(buf[..n].to_vec());
+                                read_registration.publish(&event);
+                                // Keep draining the PTY for attached clients
+                                // once the original client has gone.
+                                if output_open && output_tx_clone.send(event).await.is_err() {
                                     tracing::debug!("Output channel closed");
-                                    break;
+                                    output_open = false;
                                 }
                             }
                             Ok(Err(e)) => {
@@ -824,8 +1346,14 @@ impl RuncShim {
         // Handle input from client
         let write_task = tokio::spawn(async move {
             tracing::debug!("PTY write task started");
-            while let Some(event) = input_rx.recv().await {
+            loop {
+                let event = tokio::select! {
+                    Some(event) = input_rx.recv() => event,
+                    Some(event) = attach_input_rx.recv() => event,
+                    else => break,
+                };
                 match event {
+                    InputEvent::Stdin(_) if !stdin.is_open() => {}
                     InputEvent::Stdin(data) => {
                         tracing::debug!("Writing {} bytes to PTY", data.len());
                         loop {
@@ -858,8 +1386,15 @@ impl RuncShim {
                         }
                     }
                     InputEvent::Resize { width, height } => {
-                        tracing::debug!("Resize requested: {}x{} (not implemented)", width, height);
+                        tracing::debug!("Resizing PTY to {}x{}", width, height);
+                        if let Err(e) =
+                            set_pty_size(pty_write_fd.get_ref().as_raw_fd(), width, height)
+                        {
+                            tracing::warn!("Error resizing PTY: {}", e);
+                        }
                     }
+                    // The input of a terminal is never closed.
+                    InputEvent::CloseStdin => {}
                 }
             }
             tracing::debug!("PTY write task exiting");
@@ -869,46 +1404,215 @@ impl RuncShim {
         let _ = read_task.await;
         write_task.abort();
 
-        // Get the exit code from the container
-        let exit_code = self.get_container_exit_code(&id).await.unwrap_or(-1);
+        // Wait for the reaper to record the exit status of the container
+        let exit_code = self.wait(&id).await.map(|r| r.exit_code).unwrap_or(-1);
 
-        // Update container state
-        {
-            let mut containers = containers.write().await;
-            if let Some(metadata) = containers.get_mut(&id_for_cleanup) {
-                let now = SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs() as i64;
-                metadata.info.state = ContainerState::Stopped;
-                metadata.info.finished_at = Some(now);
-                metadata.info.exit_code = Some(exit_code);
+        tracing::info!(container_id = %id, exit_code = exit_code, "Container exited (interactive)");
 
-                let container_dir = data_dir.join("containers").join(&metadata.info.id);
-                let metadata_path = container_dir.join("metadata.json");
-                if let Ok(content) = serde_json::to_string_pretty(&metadata) {
-                    let _ = std::fs::write(&metadata_path, content);
+        // Send exit event
+        let event = OutputEvent::Exit(WaitResult {
+            exit_code,
+            error: None,
+        });
+        registration.publish(&event);
+        drop(registration);
+        let _ = output_tx.send(event).await;
+
+        // Cleanup socket
+        let _ = std::fs::remove_file(&console_socket_path);
+
+        Ok(())
+    }
+
+    /// `run_interactive` for a container without a TTY. Started detached
+    /// without a console socket, runc passes its own stdio to the container,
+    /// so the container writes to our pipes and reads stdin from one, which
+    /// is closed (its EOF) as `stdin` says.
+    async fn run_interactive_piped(
+        &self,
+        id: String,
+        bundle_path: PathBuf,
+        mut stdin: StdinState,
+        mut input_rx: mpsc::Receiver<InputEvent>,
+        output_tx: mpsc::Sender<OutputEvent>,
+    ) -> Result<(), ShimError> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let runc_root = self.data_dir.join("runc");
+        let pid_file = bundle_path.join("container.pid");
+
+        tracing::info!(container_id = %id, bundle = ?bundle_path, "Starting container with runc run (interactive, no TTY)");
+
+        let mut child = tokio::process::Command::new("runc")
+            .arg("--root")
+            .arg(&runc_root)
+            .arg("run")
+            .arg("--bundle")
+            .arg(&bundle_path)
+            .arg("--pid-file")
+            .arg(&pid_file)
+            .arg("--no-pivot")
+            .arg("--detach")
+            .arg(&id)
+            .stdin(if stdin.is_open() {
+                std::process::Stdio::piped()
+            } else {
+                std::process::Stdio::null()
+            })
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .map_err(|e| ShimError::Runc(format!("Failed to spawn runc: {}", e)))?;
+
+        let mut stdin_pipe = child.stdin.take();
+        let mut stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| ShimError::Runc("Failed to capture stdout".to_string()))?;
+        let mut stderr = child
+            .stderr
+            .take()
+            .ok_or_else(|| ShimError::Runc("Failed to capture stderr".to_string()))?;
+
+        let runc_status = child
+            .wait()
+            .await
+            .map_err(|e| ShimError::Runc(format!("Failed to wait for runc: {}", e)))?;
+        if !runc_status.success() {
+            // Nothing else holds the pipe when the container did not start.
+            let mut stderr_output = String::new();
+            let _ = stderr.read_to_string(&mut stderr_output).await;
+            return Err(ShimError::runc_exited(
+                &id,
+                "run",
+                runc_status,
+                &stderr_output,
+            ));
+        }
+
+        tracing::info!(container_id = %id, "runc started container in detached mode");
+
+        if let Ok(pid_str) = fs::read_to_string(&pid_file).await
+            && let Ok(pid) = pid_str.trim().parse::<u32>()
+        {
+            {
+                let mut containers = self.containers.write().await;
+                if let Some(metadata) = containers.get_mut(&id) {
+                    metadata.info.pid = Some(pid);
+                    let _ = self.save_container(metadata).await;
                 }
             }
+            self.watch_exit(&id, pid);
         }
 
-        tracing::info!(container_id = %id, exit_code = exit_code, "Container exited (interactive)");
+        // Share the pipes with clients that attach later
+        let (attach_input_tx, mut attach_input_rx) = mpsc::channel::<InputEvent>(ATTACH_BUFFER);
+        let registration = Arc::new(AttachRegistration::new(
+            &self.attached,
+            &id,
+            stdin_pipe.is_some().then_some(attach_input_tx),
+        ));
 
-        // Send exit event
-        let _ = output_tx
-            .send(OutputEvent::Exit(WaitResult {
-                exit_code,
-                error: None,
-            }))
-            .await;
+        // Read both output pipes until the container closes them
+        let output_tx_clone = output_tx.clone();
+        let read_registration = registration.clone();
+        let read_task = tokio::spawn(async move {
+            let mut output_open = true;
+            let mut stdout_buf = vec![0u8; 4096];
+            let mut stderr_buf = vec![0u8; 4096];
+            let mut stdout_done = false;
+            let mut stderr_done = false;
+            while !stdout_done || !stderr_done {
+                let event = tokio::select! {
+                    result = stdout.read(&mut stdout_buf), if !stdout_done => match result {
+                        Ok(n) if n > 0 => OutputEvent::Stdout(stdout_buf[..n].to_vec()),
+                        _ => {
+                            stdout_done = true;
+                            continue;
+                        }
+                    },
+                    result = stderr.read(&mut stderr_buf), if !stderr_done => match result {
+                        Ok(n) if n > 0 => OutputEvent::Stderr(stderr_buf[..n].to_vec()),
+                        _ => {
+                            stderr_done = true;
+                            continue;
+                        }
+                    },
+                };
+                read_registration.publish(&event);
+                // Keep draining the pipes for attached clients once the
+                // original client has gone.
+                if output_open && output_tx_clone.send(event).await.is_err() {
+                    output_open = false;
+                }
+            }
+        });
 
-        // Cleanup socket
-        let _ = std::fs::remove_file(&console_socket_path);
+        // Forward input from the client that started the container and from
+        // attached ones
+        let write_registration = registration.clone();
+        let write_task = tokio::spawn(async move {
+            let mut session_open = true;
+            loop {
+                let event = tokio::select! {
+                    event = input_rx.recv(), if session_open => match event {
+                        Some(event) => event,
+                        None => {
+                            session_open = false;
+                            InputEvent::CloseStdin
+                        }
+                    },
+                    Some(event) = attach_input_rx.recv() => event,
+                    else => break,
+                };
+                match event {
+                    InputEvent::Stdin(data) => {
+                        if let Some(pipe) = stdin_pipe.as_mut()
+                            && let Err(e) = pipe.write_all(&data).await
+                        {
+                            tracing::debug!("Container stdin closed: {}", e);
+                            stdin_pipe = None;
+                        }
+                    }
+                    InputEvent::CloseStdin => {
+                        if stdin.client_done() {
+                            tracing::debug!("Closing container stdin");
+                            stdin_pipe = None;
+                            write_registration.close_input();
+                        }
+                    }
+                    InputEvent::Resize { .. } => {}
+                }
+            }
+        });
+
+        // The pipes close when the container exits
+        let _ = read_task.await;
+        write_task.abort();
+
+        // Wait for the reaper to record the exit status of the container
+        let exit_code = self.wait(&id).await.map(|r| r.exit_code).unwrap_or(-1);
+
+        tracing::info!(container_id = %id, exit_code = exit_code, "Container exited (interactive)");
+
+        let event = OutputEvent::Exit(WaitResult {
+            exit_code,
+            error: None,
+        });
+        registration.publish(&event);
+        drop(registration);
+        let _ = output_tx.send(event).await;
 
         Ok(())
     }
 
-    fn generate_spec(&self, opts: &CreateContainerOpts, rootfs: &Path) -> Result<Spec, ShimError> {
+    fn generate_spec(
+        id: &str,
+        opts: &CreateContainerOpts,
+        rootfs: &Path,
+        binds: &[BindMount],
+        tmpfs: &[TmpfsMount],
+    ) -> Result<Spec, ShimError> {
         let args = if !opts.config.entrypoint.is_empty() {
             let mut args = opts.config.entrypoint.clone();
             args.extend(opts.config.cmd.clone());
@@ -931,15 +1635,15 @@ impl RuncShim {
             opts.config.env.clone()
         };
 
-        let user = opts.config.user.clone().unwrap_or_default();
-        let (uid, gid) = parse_user(&user);
+        let user = resolve_user(rootfs, opts.config.user.as_deref().unwrap_or_default())?;
 
         let process = ProcessBuilder::default()
             .terminal(opts.config.tty)
             .user(
                 oci_spec::runtime::UserBuilder::default()
-                    .uid(uid)
-                    .gid(gid)
+                    .uid(user.uid)
+                    .gid(user.gid)
+                    .additional_gids(user.additional_gids)
                     .build()
                     .map_err(|e| ShimError::OciSpec(e.to_string()))?,
             )
@@ -956,12 +1660,20 @@ impl RuncShim {
             .build()
             .map_err(|e| ShimError::OciSpec(e.to_string()))?;
 
-        let mounts = self.generate_mounts(&opts.host_config)?;
+        let mut tmpfs = tmpfs.to_vec();
+        if opts.host_config.readonly_rootfs {
+            tmpfs.extend(readonly_tmpfs(&tmpfs, binds, rootfs));
+        }
+        let mounts = Self::generate_mounts(binds, &tmpfs)?;
+
+        let namespaces = Self::generate_namespaces(&opts.host_config)?;
 
-        let namespaces = self.generate_namespaces(&opts.host_config)?;
+        let resources = Self::generate_resources(&opts.host_config)?;
 
         let linux = LinuxBuilder::default()
+            .cgroups_path(PathBuf::from("/ross").join(id).join("container"))
             .namespaces(namespaces)
+            .resources(resources)
             .build()
             .map_err(|e| ShimError::OciSpec(e.to_string()))?;
 
@@ -984,7 +1696,37 @@ impl RuncShim {
         Ok(spec)
     }
 
-    fn generate_mounts(&self, host_config: &HostConfig) -> Result<Vec<Mount>, ShimError> {
+    fn generate_resources(host_config: &HostConfig) -> Result<LinuxResources, ShimError> {
+        // Start from the default resources so the deny-all device cgroup rule is kept.
+        let mut resources = Linux::default().resources().clone().unwrap_or_default();
+
+        if let Some(limit) = host_config.memory_limit {
+            // Setting swap equal to the limit disables swap, so exceeding the
+            // limit triggers the OOM killer instead of paging out.
+            let memory = LinuxMemoryBuilder::default()
+                .limit(limit)
+                .swap(limit)
+                .build()
+                .map_err(|e| ShimError::OciSpec(e.to_string()))?;
+            resources.set_memory(Some(memory));
+        }
+
+        if host_config.cpu_quota.is_some() || host_config.cpu_period.is_some() {
+            let mut cpu = LinuxCpuBuilder::default();
+            if let Some(quota) = host_config.cpu_quota {
+                cpu = cpu.quota(quota);
+            }
+            if let Some(period) = host_config.cpu_period {
+                cpu = cpu.period(period);
+            }
+            let cpu = cpu.build().map_err(|e| ShimError::OciSpec(e.to_string()))?;
+            resources.set_cpu(Some(cpu));
+        }
+
+        Ok(resources)
+    }
+
+    fn generate_mounts(binds: &[BindMount], tmpfs: &[TmpfsMount]) -> Result<Vec<Mount>, ShimError> {
         let mut mounts = vec![
             MountBuilder::default()
                 .destination("/proc")
@@ -1044,34 +1786,34 @@ impl RuncShim {
                 .map_err(|e| ShimError::OciSpec(e.to_string()))?,
         ];
 
-        for bind in &host_config.binds {
-            let parts: Vec<&str> = bind.split(':').collect();
-            if parts.len() >= 2 {
-                let options = if parts.len() > 2 {
-                    parts[2].split(',').map(|s| s.to_string()).collect()
-                } else {
-                    vec!["rbind".to_string(), "rprivate".to_string()]
-                };
+        for bind in binds {
+            mounts.push(
+                MountBuilder::default()
+                    .destination(&bind.destination)
+                    .typ("bind")
+                    .source(&bind.source)
+                    .options(bind.oci_options())
+                    .build()
+                    .map_err(|e| ShimError::OciSpec(e.to_string()))?,
+            );
+        }
 
-                mounts.push(
-                    MountBuilder::default()
-                        .destination(parts[1])
-                        .typ("bind")
-                        .source(parts[0])
-                        .options(options)
-                        .build()
-                        .map_err(|e| ShimError::OciSpec(e.to_string()))?,
-                );
-            }
+        for mount in tmpfs {
+            mounts.push(
+                MountBuilder::default()
+                    .destination(&mount.destination)
+                    .typ("tmpfs")
+                    .source("tmpfs")
+                    .options(mount.options.clone())
+                    .build()
+                    .map_err(|e| ShimError::OciSpec(e.to_string()))?,
+            );
         }
 
         Ok(mounts)
     }
 
-    fn generate_namespaces(
-        &self,
-        host_config: &HostConfig,
-    ) -> Result<Vec<LinuxNamespace>, ShimError> {
+    fn generate_namespaces(host_config: &HostConfig) -> Result<Vec<LinuxNamespace>, ShimError> {
         let mut namespaces = vec![
             LinuxNamespaceBuilder::default()
                 .typ(LinuxNamespaceType::Pid)
@@ -1091,13 +1833,9 @@ impl RuncShim {
                 .map_err(|e| ShimError::OciSpec(e.to_string()))?,
         ];
 
-        let use_host_network = host_config
-            .network_mode
-            .as_ref()
-            .map(|m| m == "host")
-            .unwrap_or(false);
-
-        if !use_host_network {
+        // runc has no network stack of its own: outside host mode the
+        // container gets a namespace with only loopback, bridged or not.
+        if host_config.network()? != NetworkMode::Host {
             namespaces.push(
                 LinuxNamespaceBuilder::default()
                     .typ(LinuxNamespaceType::Network)
@@ -1110,16 +1848,201 @@ impl RuncShim {
     }
 }
 
-fn parse_user(user: &str) -> (u32, u32) {
-    if user.is_empty() {
-        return (0, 0);
+async fn persist_metadata(data_dir: &Path, metadata: &ContainerMetadata) -> Result<(), ShimError> {
+    let container_dir = data_dir.join("containers").join(&metadata.info.id);
+    fs::create_dir_all(&container_dir).await?;
+    let metadata_path = container_dir.join("metadata.json");
+    let content = serde_json::to_string_pretty(metadata)?;
+    fs::write(&metadata_path, content).await?;
+    Ok(())
+}
+
+/// Cgroup the container's own cgroup is created in. A foreground `runc run`
+/// removes the container's cgroup when it ends, but this one outlives it, so
+/// the container's memory events can still be read after it exits.
+fn cgroup_parent(id: &str) -> PathBuf {
+    Path::new(CGROUP_ROOT).join("ross").join(id)
+}
+
+/// Processes of the container killed by the OOM killer so far. `memory.events`
+/// counts those of the whole subtree.
+async fn oom_kill_count(id: &str) -> u64 {
+    fs::read_to_string(cgroup_parent(id).join("memory.events"))
+        .await
+        .ok()
+        .and_then(|events| {
+            events
+                .lines()
+                .find_map(|line| line.strip_prefix("oom_kill "))
+                .and_then(|count| count.trim().parse().ok())
+        })
+        .unwrap_or(0)
+}
+
+/// Refuses a memory `limit` below the `usage` of a running container: the
+/// kernel would reclaim, then OOM-kill, until the container fits.
+fn check_memory_limit(limit: i64, usage: Option<u64>) -> Result<(), ShimError> {
+    match usage {
+        Some(usage) if usage > limit as u64 => Err(ShimError::InvalidResources(format!(
+            "memory limit of {} bytes is below the {} bytes the container uses",
+            limit, usage
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Memory the container uses, `memory.current` of its cgroup subtree.
+async fn memory_usage(id: &str) -> Option<u64> {
+    fs::read_to_string(cgroup_parent(id).join("memory.current"))
+        .await
+        .ok()
+        .and_then(|usage| usage.trim().parse().ok())
+}
+
+/// Records whether the OOM killer struck since the container last exited.
+async fn record_oom_kills(metadata: &mut ContainerMetadata) {
+    let kills = oom_kill_count(&metadata.info.id).await;
+    metadata.info.oom_killed = kills > metadata.oom_kills;
+    metadata.oom_kills = kills;
+    if metadata.info.oom_killed {
+        tracing::warn!(container_id = %metadata.info.id, "Container was killed by the OOM killer");
+    }
+}
+
+/// Block until `pid` terminates and return its exit code. Returns `None` when
+/// the process is not a child of the daemon.
+fn reap_pid(pid: u32) -> Option<i32> {
+    use nix::sys::wait::{WaitStatus, waitpid};
+    use nix::unistd::Pid;
+
+    loop {
+        match waitpid(Pid::from_raw(pid as i32), None) {
+            Ok(WaitStatus::Exited(_, code)) => return Some(code),
+            Ok(WaitStatus::Signaled(_, signal, _)) => return Some(128 + signal as i32),
+            Ok(_) | Err(nix::errno::Errno::EINTR) => continue,
+            Err(e) => {
+                tracing::debug!(pid = pid, "Unable to reap container process: {}", e);
+                return None;
+            }
+        }
+    }
+}
+
+/// Runs `command`, a `runc exec` told to write the pid of the process it
+/// starts to `pid_file`, streaming the process's output. A process still
+/// running after `timeout` is killed through its pid, as killing runc would
+/// leave it running in the container. The process spec at `process_path` and
+/// the pid file are removed once runc exits, or right away if it could not
+/// run.
+fn run_exec(
+    mut command: tokio::process::Command,
+    process_path: PathBuf,
+    pid_file: PathBuf,
+    timeout: Option<Duration>,
+) -> impl futures::Stream<Item = Result<OutputEvent, ShimError>> + Send + 'static {
+    async_stream::try_stream! {
+        let spawned = command
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn();
+        let mut child = match spawned {
+            Ok(child) => child,
+            Err(e) => {
+                let _ = fs::remove_file(&process_path).await;
+                Err(ShimError::Runc(format!("Failed to spawn runc exec: {}", e)))?
+            }
+        };
+
+        let mut stdout = child.stdout.take()
+            .ok_or_else(|| ShimError::Runc("Failed to capture stdout".to_string()))?;
+        let mut stderr = child.stderr.take()
+            .ok_or_else(|| ShimError::Runc("Failed to capture stderr".to_string()))?;
+
+        let mut stdout_buf = vec![0u8; 4096];
+        let mut stderr_buf = vec![0u8; 4096];
+        let mut stdout_done = false;
+        let mut stderr_done = false;
+        let expired = tokio::time::sleep(timeout.unwrap_or(Duration::MAX));
+        tokio::pin!(expired);
+        let mut timed_out = None;
+
+        // Drain both pipes until EOF before reaping so no trailing output is lost
+        while !stdout_done || !stderr_done {
+            tokio::select! {
+                result = tokio::io::AsyncReadExt::read(&mut stdout, &mut stdout_buf), if !stdout_done => {
+                    match result {
+                        Ok(0) => stdout_done = true,
+                        Ok(n) => yield OutputEvent::Stdout(stdout_buf[..n].to_vec()),
+                        Err(e) => {
+                            tracing::warn!("Error reading exec stdout: {}", e);
+                            stdout_done = true;
+                        }
+                    }
+                }
+                result = tokio::io::AsyncReadExt::read(&mut stderr, &mut stderr_buf), if !stderr_done => {
+                    match result {
+                        Ok(0) => stderr_done = true,
+                        Ok(n) => yield OutputEvent::Stderr(stderr_buf[..n].to_vec()),
+                        Err(e) => {
+                            tracing::warn!("Error reading exec stderr: {}", e);
+                            stderr_done = true;
+                        }
+                    }
+                }
+                () = &mut expired, if timeout.is_some() => {
+                    timed_out = timeout;
+                    break;
+                }
+            }
+        }
+
+        if timed_out.is_some() {
+            // runc writes the pid file before the process starts; until then,
+            // killing runc is enough.
+            let pid = fs::read_to_string(&pid_file)
+                .await
+                .ok()
+                .and_then(|pid| pid.trim().parse::<i32>().ok());
+            match pid {
+                Some(pid) => {
+                    let _ = nix::sys::signal::kill(
+                        nix::unistd::Pid::from_raw(pid),
+                        nix::sys::signal::Signal::SIGKILL,
+                    );
+                }
+                None => {
+                    let _ = child.start_kill();
+                }
+            }
+        }
+
+        let status = child.wait().await;
+        let _ = fs::remove_file(&process_path).await;
+        let _ = fs::remove_file(&pid_file).await;
+        let status =
+            status.map_err(|e| ShimError::Runc(format!("Failed to wait for runc exec: {}", e)))?;
+
+        if let Some(timeout) = timed_out {
+            Err(ShimError::ExecTimedOut(timeout))?;
+        }
+
+        yield OutputEvent::Exit(WaitResult {
+            exit_code: exit_code_from_status(&status),
+            error: None,
+        });
     }
+}
 
-    let parts: Vec<&str> = user.split(':').collect();
-    let uid = parts.first().and_then(|s| s.parse().ok()).unwrap_or(0);
-    let gid = parts.get(1).and_then(|s| s.parse().ok()).unwrap_or(uid);
+/// Maps a process exit status to a shell-style exit code (128 + signal when killed).
+fn exit_code_from_status(status: &std::process::ExitStatus) -> i32 {
+    use std::os::unix::process::ExitStatusExt;
 
-    (uid, gid)
+    match (status.code(), status.signal()) {
+        (Some(code), _) => code,
+        (None, Some(signal)) => 128 + signal,
+        (None, None) => -1,
+    }
 }
 
 #[async_trait]
@@ -1132,8 +2055,8 @@ impl Shim for RuncShim {
         self.start(id).await
     }
 
-    async fn stop(&self, id: &str, timeout: u32) -> Result<(), ShimError> {
-        self.stop(id, timeout).await
+    async fn stop(&self, id: &str, timeout: u32, manual: bool) -> Result<bool, ShimError> {
+        self.stop(id, timeout, manual).await
     }
 
     async fn kill(&self, id: &str, signal: u32) -> Result<(), ShimError> {
@@ -1152,6 +2075,14 @@ impl Shim for RuncShim {
         self.resume(id).await
     }
 
+    async fn rename(&self, id: &str, new_name: &str) -> Result<(), ShimError> {
+        self.rename(id, new_name).await
+    }
+
+    async fn update(&self, id: &str, update: ResourceLimits) -> Result<(), ShimError> {
+        self.update(id, update).await
+    }
+
     async fn list(&self) -> Result<Vec<ContainerInfo>, ShimError> {
         self.list().await
     }
@@ -1164,10 +2095,18 @@ impl Shim for RuncShim {
         self.wait(id).await
     }
 
+    async fn record_restart(&self, id: &str) -> Result<u32, ShimError> {
+        self.record_restart(id).await
+    }
+
     fn run_streaming(&self, id: String) -> OutputEventStream {
         Box::pin(self.run_streaming(id))
     }
 
+    fn exec(&self, id: String, opts: ExecOpts) -> OutputEventStream {
+        Box::pin(self.exec(id, opts))
+    }
+
     async fn run_interactive(
         &self,
         id: String,
@@ -1176,6 +2115,82 @@ impl Shim for RuncShim {
     ) -> Result<(), ShimError> {
         self.run_interactive(id, input_rx, output_tx).await
     }
+
+    async fn attach(&self, id: &str) -> Result<AttachStreams, ShimError> {
+        self.attach(id).await
+    }
+
+    async fn network_stats(&self, id: &str) -> Result<HashMap<String, InterfaceStats>, ShimError> {
+        // Containers get an empty network namespace (or the host's), with no
+        // interface of ours to count.
+        self.get(id).await?;
+        Ok(HashMap::new())
+    }
+
+    async fn processes(&self, id: &str) -> Result<Vec<u32>, ShimError> {
+        let info = self.get(id).await?;
+        if !matches!(info.state, ContainerState::Running | ContainerState::Paused) {
+            return Err(ShimError::ContainerNotRunning(id.to_string()));
+        }
+
+        // The container's cgroup and those below it, which runc may create
+        // for exec'd processes.
+        let mut pids = Vec::new();
+        let mut cgroups = vec![cgroup_parent(id)];
+        while let Some(cgroup) = cgroups.pop() {
+            let procs = fs::read_to_string(cgroup.join("cgroup.procs")).await?;
+            pids.extend(
+                procs
+                    .lines()
+                    .filter_map(|pid| pid.trim().parse::<u32>().ok()),
+            );
+            let mut entries = fs::read_dir(&cgroup).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                if entry.file_type().await?.is_dir() {
+                    cgroups.push(entry.path());
+                }
+            }
+        }
+        pids.sort_unstable();
+        Ok(pids)
+    }
+
+    async fn shutdown(&self) -> Result<(), ShimError> {
+        // Console sockets only live while `runc run` hands over the PTY; one
+        // left behind belongs to a run the daemon no longer waits on.
+        let socket_paths: Vec<PathBuf> = self
+            .containers
+            .read()
+            .await
+            .values()
+            .map(|m| Path::new(&m.info.bundle_path).join("console.sock"))
+            .collect();
+        for socket_path in socket_paths {
+            match fs::remove_file(&socket_path).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    tracing::warn!("Failed to remove {}: {}", socket_path.display(), e);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Sets the window size of the PTY behind `fd`; the kernel signals the
+/// foreground process group with SIGWINCH.
+fn set_pty_size(fd: std::os::fd::RawFd, width: u16, height: u16) -> std::io::Result<()> {
+    let size = libc::winsize {
+        ws_row: height,
+        ws_col: width,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    if unsafe { libc::ioctl(fd, libc::TIOCSWINSZ, &size) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
 }
 
 fn receive_pty_fd(stream: &std::os::unix::net::UnixStream) -> Result<OwnedFd, ShimError> {
@@ -1215,3 +2230,132 @@ fn receive_pty_fd(stream: &std::os::unix::net::UnixStream) -> Result<OwnedFd, Sh
         "No file descriptor received from console socket".to_string(),
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(host_config: HostConfig, rootfs: &Path) -> Spec {
+        let opts = CreateContainerOpts {
+            name: None,
+            config: ContainerConfig::default(),
+            host_config,
+            mounts: vec![],
+        };
+        RuncShim::generate_spec("test", &opts, rootfs, &[], &[]).unwrap()
+    }
+
+    #[test]
+    fn test_readonly_rootfs() {
+        let rootfs = tempfile::tempdir().unwrap();
+        let tmpfs_destinations = |spec: &Spec| -> Vec<String> {
+            spec.mounts()
+                .iter()
+                .flatten()
+                .filter(|m| m.typ().as_deref() == Some("tmpfs"))
+                .map(|m| m.destination().to_string_lossy().to_string())
+                .collect()
+        };
+
+        let writable = spec(HostConfig::default(), rootfs.path());
+        assert_eq!(writable.root().as_ref().unwrap().readonly(), Some(false));
+        assert!(!tmpfs_destinations(&writable).contains(&"/tmp".to_string()));
+
+        let host_config = HostConfig {
+            readonly_rootfs: true,
+            ..Default::default()
+        };
+        let readonly = spec(host_config, rootfs.path());
+        assert_eq!(readonly.root().as_ref().unwrap().readonly(), Some(true));
+        assert!(tmpfs_destinations(&readonly).contains(&"/tmp".to_string()));
+    }
+
+    #[test]
+    fn test_update_resources() {
+        let mut host_config = HostConfig {
+            memory_limit: Some(256 << 20),
+            cpu_quota: Some(50_000),
+            cpu_period: Some(100_000),
+            ..Default::default()
+        };
+
+        // Limits left out of the update are kept.
+        let update = ResourceLimits {
+            memory_limit: Some(512 << 20),
+            cpu_quota: None,
+            cpu_period: None,
+        };
+        update.apply_to(&mut host_config);
+        assert_eq!(
+            ResourceLimits::of(&host_config),
+            ResourceLimits {
+                memory_limit: Some(512 << 20),
+                cpu_quota: Some(50_000),
+                cpu_period: Some(100_000),
+            }
+        );
+        ResourceLimits::default().apply_to(&mut host_config);
+        assert_eq!(host_config.memory_limit, Some(512 << 20));
+
+        let resources = RuncShim::generate_resources(&host_config).unwrap();
+        let memory = resources.memory().as_ref().unwrap();
+        assert_eq!(memory.limit(), Some(512 << 20));
+        assert_eq!(memory.swap(), Some(512 << 20));
+        let cpu = resources.cpu().as_ref().unwrap();
+        assert_eq!(cpu.quota(), Some(50_000));
+        assert_eq!(cpu.period(), Some(100_000));
+    }
+
+    #[test]
+    fn test_check_memory_limit() {
+        assert!(check_memory_limit(64 << 20, Some(32 << 20)).is_ok());
+        assert!(check_memory_limit(64 << 20, Some(64 << 20)).is_ok());
+        assert!(matches!(
+            check_memory_limit(64 << 20, Some((64 << 20) + 1)),
+            Err(ShimError::InvalidResources(_))
+        ));
+        // Without the cgroup's usage, the update is left to runc.
+        assert!(check_memory_limit(64 << 20, None).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_exec_timeout_kills_process() {
+        use futures::StreamExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let process_path = dir.path().join("exec.json");
+        let pid_file = dir.path().join("exec.pid");
+        std::fs::write(&process_path, "{}").unwrap();
+
+        // Stands in for runc exec: starts a probe that sleeps past its
+        // timeout, writes its pid, prints it and waits for it.
+        let mut command = tokio::process::Command::new("sh");
+        command
+            .arg("-c")
+            .arg("sleep 60 & echo $! > \"$0\"; echo $!; wait")
+            .arg(&pid_file);
+        let events: Vec<_> = run_exec(
+            command,
+            process_path.clone(),
+            pid_file.clone(),
+            Some(Duration::from_millis(500)),
+        )
+        .collect()
+        .await;
+
+        let Some(Ok(OutputEvent::Stdout(pid))) = events.first() else {
+            panic!("unexpected events: {:?}", events);
+        };
+        let pid: i32 = String::from_utf8_lossy(pid).trim().parse().unwrap();
+        assert!(matches!(
+            events.last(),
+            Some(Err(ShimError::ExecTimedOut(_)))
+        ));
+        assert_eq!(
+            nix::sys::signal::kill(nix::unistd::Pid::from_raw(pid), None),
+            Err(nix::errno::Errno::ESRCH)
+        );
+        assert!(!process_path.exists());
+        assert!(!pid_file.exists());
+    }
+}
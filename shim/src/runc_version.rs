@@ -0,0 +1,129 @@
+//! Checking, before any container is created, that the `runc` the shim runs
+//! containers with is installed and recent enough.
+
+use crate::error::ShimError;
+use std::fmt;
+
+/// Oldest runc supported: the first release implementing the 1.0.2 runtime
+/// spec the shim writes bundles for.
+pub const MIN_RUNC_VERSION: RuncVersion = RuncVersion {
+    major: 1,
+    minor: 0,
+    patch: 0,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RuncVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl fmt::Display for RuncVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl RuncVersion {
+    /// Parses the output of `runc --version`, whose first line reads
+    /// `runc version 1.1.12`, possibly with a pre-release suffix.
+    fn parse(output: &str) -> Option<Self> {
+        let version = output
+            .lines()
+            .next()?
+            .trim()
+            .strip_prefix("runc version")?
+            .trim();
+        let release = version.split(['-', '+', '~']).next()?;
+        let mut parts = release.split('.').map(|part| part.parse::<u32>().ok());
+        Some(Self {
+            major: parts.next()??,
+            minor: parts.next().unwrap_or(Some(0))?,
+            patch: parts.next().unwrap_or(Some(0))?,
+        })
+    }
+}
+
+fn unavailable(problem: &str) -> ShimError {
+    ShimError::RuncUnavailable(format!(
+        "{}; install runc {} or later and make sure it is on the daemon's PATH",
+        problem, MIN_RUNC_VERSION
+    ))
+}
+
+/// Runs `runc --version` and checks the version it reports against
+/// [`MIN_RUNC_VERSION`].
+pub async fn probe() -> Result<RuncVersion, ShimError> {
+    let output = match tokio::process::Command::new("runc")
+        .arg("--version")
+        .output()
+        .await
+    {
+        Ok(output) => output,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(unavailable("runc was not found"));
+        }
+        Err(e) => return Err(unavailable(&format!("runc could not be run: {}", e))),
+    };
+    if !output.status.success() {
+        return Err(unavailable(&format!(
+            "runc --version failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let version = RuncVersion::parse(&stdout).ok_or_else(|| {
+        unavailable(&format!(
+            "unrecognized runc --version output {:?}",
+            stdout.lines().next().unwrap_or_default()
+        ))
+    })?;
+    if version < MIN_RUNC_VERSION {
+        return Err(unavailable(&format!("runc {} is too old", version)));
+    }
+    Ok(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(major: u32, minor: u32, patch: u32) -> RuncVersion {
+        RuncVersion {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    #[test]
+    fn test_parse_runc_version() {
+        let output = "runc version 1.1.12\ncommit: v1.1.12-0-g51d5e94\nspec: 1.0.2-dev\n";
+        assert_eq!(RuncVersion::parse(output), Some(version(1, 1, 12)));
+        assert_eq!(
+            RuncVersion::parse("runc version 1.2.0-rc.1\n"),
+            Some(version(1, 2, 0))
+        );
+        assert_eq!(
+            RuncVersion::parse("runc version 1.0.0~rc93+ds1\n"),
+            Some(version(1, 0, 0))
+        );
+        assert_eq!(
+            RuncVersion::parse("runc version 0.1.1"),
+            Some(version(0, 1, 1))
+        );
+        assert_eq!(RuncVersion::parse("crun version 1.8.7"), None);
+        assert_eq!(RuncVersion::parse("runc version unknown"), None);
+        assert_eq!(RuncVersion::parse(""), None);
+    }
+
+    #[test]
+    fn test_minimum_runc_version() {
+        assert!(version(0, 1, 1) < MIN_RUNC_VERSION);
+        assert!(version(1, 0, 0) >= MIN_RUNC_VERSION);
+        assert!(version(1, 1, 12) >= MIN_RUNC_VERSION);
+    }
+}
@@ -0,0 +1,75 @@
+//! Selection of the shim backing the container service.
+
+use crate::error::ShimError;
+use crate::libkrun::KrunShim;
+use crate::runc_shim::RuncShim;
+use crate::shim::Shim;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Container runtime a daemon runs its containers with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Runtime {
+    /// OCI containers run by `runc` on the host kernel.
+    Runc,
+    /// Each container runs in its own lightweight VM.
+    Libkrun,
+}
+
+impl Runtime {
+    /// Runtime used when none is configured: libkrun where it is built in,
+    /// runc otherwise.
+    pub fn detect() -> Self {
+        if cfg!(all(feature = "libkrun", target_os = "macos")) {
+            Runtime::Libkrun
+        } else {
+            Runtime::Runc
+        }
+    }
+
+    /// Creates the shim for this runtime, keeping its state under `data_dir`.
+    /// `mtu` is the link MTU of libkrun VMs on the userspace network stack.
+    pub async fn create_shim(
+        self,
+        data_dir: &Path,
+        mtu: Option<u16>,
+    ) -> Result<Arc<dyn Shim + Send + Sync>, ShimError> {
+        match self {
+            Runtime::Runc => Ok(Arc::new(RuncShim::new(data_dir).await?)),
+            Runtime::Libkrun => {
+                if !cfg!(all(feature = "libkrun", target_os = "macos")) {
+                    return Err(ShimError::NotSupported(
+                        "the libkrun runtime requires macOS and a build with the libkrun feature"
+                            .to_string(),
+                    ));
+                }
+                Ok(Arc::new(KrunShim::new(data_dir).await?.with_mtu(mtu)))
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Runtime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Runtime::Runc => write!(f, "runc"),
+            Runtime::Libkrun => write!(f, "libkrun"),
+        }
+    }
+}
+
+impl FromStr for Runtime {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "runc" => Ok(Runtime::Runc),
+            "libkrun" | "krun" => Ok(Runtime::Libkrun),
+            _ => Err(format!(
+                "unknown runtime '{}', expected 'runc' or 'libkrun'",
+                s
+            )),
+        }
+    }
+}
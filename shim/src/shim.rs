@@ -1,6 +1,7 @@
 use crate::error::ShimError;
 use crate::types::*;
 use async_trait::async_trait;
+use std::collections::HashMap;
 use std::pin::Pin;
 
 pub type OutputEventStream =
@@ -12,7 +13,11 @@ pub trait Shim: Send + Sync {
 
     async fn start(&self, id: &str) -> Result<(), ShimError>;
 
-    async fn stop(&self, id: &str, timeout: u32) -> Result<(), ShimError>;
+    /// Stop the container with its stop signal, killing it if it has not
+    /// exited after `timeout` seconds. Returns whether it had to be killed.
+    /// A `manual` stop, the user's, keeps restart policies from starting the
+    /// container again, also when the daemon starts.
+    async fn stop(&self, id: &str, timeout: u32, manual: bool) -> Result<bool, ShimError>;
 
     async fn kill(&self, id: &str, signal: u32) -> Result<(), ShimError>;
 
@@ -22,18 +27,49 @@ pub trait Shim: Send + Sync {
 
     async fn resume(&self, id: &str) -> Result<(), ShimError>;
 
+    async fn rename(&self, id: &str, new_name: &str) -> Result<(), ShimError>;
+
+    /// Change the resource limits of a container, applying them at once
+    /// when it runs; the limits `update` leaves unset keep their value.
+    async fn update(&self, id: &str, update: ResourceLimits) -> Result<(), ShimError>;
+
     async fn list(&self) -> Result<Vec<ContainerInfo>, ShimError>;
 
     async fn get(&self, id: &str) -> Result<ContainerInfo, ShimError>;
 
     async fn wait(&self, id: &str) -> Result<WaitResult, ShimError>;
 
+    /// Record that the restart policy is starting the container again,
+    /// returning the new restart count.
+    async fn record_restart(&self, id: &str) -> Result<u32, ShimError>;
+
     fn run_streaming(&self, id: String) -> OutputEventStream;
 
+    /// Run an additional process inside a running container, streaming its
+    /// output and finishing with its exit status.
+    fn exec(&self, id: String, opts: ExecOpts) -> OutputEventStream;
+
     async fn run_interactive(
         &self,
         id: String,
         input_rx: tokio::sync::mpsc::Receiver<InputEvent>,
         output_tx: tokio::sync::mpsc::Sender<OutputEvent>,
     ) -> Result<(), ShimError>;
+
+    /// Connect to the I/O of a container started by `run_streaming` or
+    /// `run_interactive`. Detaching never affects the container.
+    async fn attach(&self, id: &str) -> Result<AttachStreams, ShimError>;
+
+    /// Traffic counters of the container's network interfaces, by interface
+    /// name. Empty when the shim does not run the container's network.
+    async fn network_stats(&self, id: &str) -> Result<HashMap<String, InterfaceStats>, ShimError>;
+
+    /// PIDs, as seen from the host, of the processes running in the
+    /// container, including those it was exec'd.
+    async fn processes(&self, id: &str) -> Result<Vec<u32>, ShimError>;
+
+    /// Release the sockets and network stacks held for containers before the
+    /// daemon exits, so the next daemon does not trip over stale ones.
+    /// Containers still running are left as they are.
+    async fn shutdown(&self) -> Result<(), ShimError>;
 }
@@ -0,0 +1,81 @@
+//! Whether a container's stdin is open, following Docker's `OpenStdin` and
+//! `StdinOnce`.
+
+use crate::types::ContainerConfig;
+
+/// The stdin of a running container. Without `open_stdin` the container
+/// gets no input at all. With it, stdin stays open for the life of the
+/// process as clients come and go, unless `stdin_once` is set: then it is
+/// closed once the first client is done with it, and clients attaching
+/// later get no stdin.
+///
+/// As with Docker, the input of a TTY is never closed: a terminal has no end
+/// of input of its own, the process reads one from a `^D` typed into it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StdinState {
+    open: bool,
+    once: bool,
+}
+
+impl StdinState {
+    pub fn new(config: &ContainerConfig) -> Self {
+        Self {
+            open: config.open_stdin,
+            once: config.stdin_once && !config.tty,
+        }
+    }
+
+    /// Whether input from clients reaches the container.
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /// Records that a client reached the end of its input or went away.
+    /// Returns whether this closes the container's stdin.
+    pub fn client_done(&mut self) -> bool {
+        if self.open && self.once {
+            self.open = false;
+            return true;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(open_stdin: bool, stdin_once: bool, tty: bool) -> StdinState {
+        StdinState::new(&ContainerConfig {
+            open_stdin,
+            stdin_once,
+            tty,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_stdin_once_closes_after_first_client() {
+        let mut stdin = state(true, true, false);
+        assert!(stdin.is_open());
+        assert!(stdin.client_done());
+        assert!(!stdin.is_open());
+        assert!(!stdin.client_done());
+    }
+
+    #[test]
+    fn test_open_stdin_outlives_clients() {
+        let mut stdin = state(true, false, false);
+        assert!(!stdin.client_done());
+        assert!(stdin.is_open());
+
+        // A terminal's input is never closed.
+        let mut stdin = state(true, true, true);
+        assert!(!stdin.client_done());
+        assert!(stdin.is_open());
+
+        let mut stdin = state(false, true, false);
+        assert!(!stdin.is_open());
+        assert!(!stdin.client_done());
+    }
+}
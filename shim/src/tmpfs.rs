@@ -0,0 +1,196 @@
+//! Tmpfs mounts: the ones asked for with `--tmpfs`, and the writable paths a
+//! container with a read-only root filesystem gets by default.
+
+use crate::bind::{BindMount, normalize_destination};
+use crate::error::ShimError;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Paths most images expect to write to, mounted as tmpfs when the root
+/// filesystem is read-only, with the mode each is created with.
+const READONLY_WRITABLE_PATHS: &[(&str, &str)] = &[
+    ("/tmp", "mode=1777"),
+    ("/run", "mode=755"),
+    ("/var/run", "mode=755"),
+];
+
+/// Options every `--tmpfs` mount starts from, as with Docker. The user's
+/// options come after them, so `exec` undoes `noexec`.
+const DEFAULT_OPTIONS: &[&str] = &["noexec", "nosuid", "nodev"];
+
+const FLAG_OPTIONS: &[&str] = &[
+    "ro",
+    "rw",
+    "exec",
+    "noexec",
+    "suid",
+    "nosuid",
+    "dev",
+    "nodev",
+    "atime",
+    "noatime",
+    "relatime",
+    "norelatime",
+    "strictatime",
+    "nostrictatime",
+];
+const VALUE_OPTIONS: &[&str] = &["size", "mode", "uid", "gid", "nr_inodes", "nr_blocks"];
+
+/// A tmpfs mounted into a container.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TmpfsMount {
+    /// Absolute, normalized path in the container.
+    pub destination: String,
+    /// Mount options, in the order they apply.
+    pub options: Vec<String>,
+}
+
+/// Validates the requested tmpfs mounts, keyed by container path with their
+/// comma-separated options. A path can be mounted only once, by a tmpfs or a
+/// bind.
+pub fn parse_tmpfs(
+    tmpfs: &HashMap<String, String>,
+    binds: &[BindMount],
+) -> Result<Vec<TmpfsMount>, ShimError> {
+    let mut mounts: Vec<TmpfsMount> = Vec::with_capacity(tmpfs.len());
+    for (path, options) in tmpfs {
+        let destination = normalize_destination(path)
+            .filter(|destination| destination != "/")
+            .ok_or_else(|| invalid(path, "path must be absolute and not /"))?;
+        if mounts.iter().any(|m| m.destination == destination)
+            || binds.iter().any(|b| b.destination == destination)
+        {
+            return Err(invalid(path, "path is already mounted"));
+        }
+
+        let mut mount = TmpfsMount {
+            destination,
+            options: DEFAULT_OPTIONS.iter().map(|o| o.to_string()).collect(),
+        };
+        for option in options.split(',').filter(|o| !o.is_empty()) {
+            let known = match option.split_once('=') {
+                Some((key, value)) => VALUE_OPTIONS.contains(&key) && !value.is_empty(),
+                None => FLAG_OPTIONS.contains(&option),
+            };
+            if !known {
+                return Err(invalid(path, &format!("unknown option '{}'", option)));
+            }
+            mount.options.push(option.to_string());
+        }
+        mounts.push(mount);
+    }
+    mounts.sort_by(|a, b| a.destination.cmp(&b.destination));
+    Ok(mounts)
+}
+
+/// The tmpfs mounts that keep the usual writable paths writable on top of a
+/// read-only root filesystem at `rootfs`, leaving out the paths already
+/// mounted. A path that is a symlink in the image (`/var/run` usually points
+/// to `/run`) is left alone, since mounting would follow it.
+pub fn readonly_tmpfs(
+    mounts: &[TmpfsMount],
+    binds: &[BindMount],
+    rootfs: &Path,
+) -> Vec<TmpfsMount> {
+    READONLY_WRITABLE_PATHS
+        .iter()
+        .filter(|(path, _)| {
+            !mounts.iter().any(|m| m.destination == *path)
+                && !binds.iter().any(|b| b.destination == *path)
+        })
+        .filter(|(path, _)| {
+            !std::fs::symlink_metadata(rootfs.join(path.trim_start_matches('/')))
+                .is_ok_and(|metadata| metadata.file_type().is_symlink())
+        })
+        .map(|(path, mode)| TmpfsMount {
+            destination: path.to_string(),
+            options: vec!["nosuid".to_string(), "nodev".to_string(), mode.to_string()],
+        })
+        .collect()
+}
+
+fn invalid(path: &str, reason: &str) -> ShimError {
+    ShimError::InvalidMount(format!("tmpfs '{}': {}", path, reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn tmpfs(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(path, options)| (path.to_string(), options.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_tmpfs() {
+        let mounts =
+            parse_tmpfs(&tmpfs(&[("/cache/", "size=64m,exec"), ("/tmp", "")]), &[]).unwrap();
+        assert_eq!(
+            mounts,
+            vec![
+                TmpfsMount {
+                    destination: "/cache".to_string(),
+                    options: ["noexec", "nosuid", "nodev", "size=64m", "exec"]
+                        .into_iter()
+                        .map(String::from)
+                        .collect(),
+                },
+                TmpfsMount {
+                    destination: "/tmp".to_string(),
+                    options: ["noexec", "nosuid", "nodev"]
+                        .into_iter()
+                        .map(String::from)
+                        .collect(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_tmpfs_rejects_invalid() {
+        let binds = [BindMount::parse("/data:/data").unwrap()];
+        for (path, options) in [
+            ("tmp", ""),
+            ("/", ""),
+            ("/tmp/../etc", ""),
+            ("/tmp", "bind"),
+            ("/tmp", "size="),
+            ("/data", ""),
+        ] {
+            assert!(
+                matches!(
+                    parse_tmpfs(&tmpfs(&[(path, options)]), &binds),
+                    Err(ShimError::InvalidMount(_))
+                ),
+                "{}:{} should be rejected",
+                path,
+                options
+            );
+        }
+        assert!(parse_tmpfs(&tmpfs(&[("/a", ""), ("/a/", "")]), &[]).is_err());
+    }
+
+    #[test]
+    fn test_readonly_rootfs_keeps_writable_paths() {
+        let rootfs = TempDir::new().unwrap();
+        std::fs::create_dir_all(rootfs.path().join("run")).unwrap();
+        std::fs::create_dir_all(rootfs.path().join("var")).unwrap();
+        std::os::unix::fs::symlink("/run", rootfs.path().join("var/run")).unwrap();
+
+        let destinations = |mounts: Vec<TmpfsMount>| -> Vec<String> {
+            mounts.into_iter().map(|m| m.destination).collect()
+        };
+        assert_eq!(
+            destinations(readonly_tmpfs(&[], &[], rootfs.path())),
+            vec!["/tmp", "/run"]
+        );
+
+        let mounts = parse_tmpfs(&tmpfs(&[("/tmp", "size=1m")]), &[]).unwrap();
+        let binds = [BindMount::parse("/data:/run").unwrap()];
+        assert!(readonly_tmpfs(&mounts, &binds, rootfs.path()).is_empty());
+    }
+}
@@ -130,6 +130,8 @@ pub fn run_io_host(listener: UnixListener, is_tty: bool) -> Result<u8, ShimError
         let _ = send_terminal_size(&mut remote, cols, rows);
     }
 
+    let mut decoder = GuestDecoder::default();
+
     #[cfg(target_os = "macos")]
     let _sigwinch_handler = if is_tty {
         Some(setup_sigwinch_handler()?)
@@ -181,7 +183,8 @@ pub fn run_io_host(listener: UnixListener, is_tty: bool) -> Result<u8, ShimError
 
         // Process remote socket events
         if remote_ready {
-            match process_guest_message(&mut remote, is_tty, &mut stdout, &mut stderr) {
+            match process_guest_message(&mut remote, &mut decoder, is_tty, &mut stdout, &mut stderr)
+            {
                 Ok(Some(exit_code)) => return Ok(exit_code),
                 Ok(None) => {}
                 Err(e) => {
@@ -245,6 +248,17 @@ fn set_nonblocking(fd: i32) -> Result<(), ShimError> {
     Ok(())
 }
 
+/// Forward stdin to the guest, split into frames the command word can describe.
+#[cfg(unix)]
+fn write_stdin(remote: &mut std::os::unix::net::UnixStream, data: &[u8]) -> std::io::Result<()> {
+    for chunk in data.chunks(MAX_DATA_LEN) {
+        let cmd = encode_write_cmd(CMD_WRITE_STDIN, chunk.len());
+        remote.write_all(&cmd.to_le_bytes())?;
+        remote.write_all(chunk)?;
+    }
+    Ok(())
+}
+
 #[cfg(unix)]
 fn send_terminal_size(
     remote: &mut std::os::unix::net::UnixStream,
@@ -258,17 +272,26 @@ fn send_terminal_size(
     remote.write_all(&buf)
 }
 
+/// Reads what the guest has sent since the last call, without blocking,
+/// and splits it into messages. Output comes out as soon as it is read,
+/// even when the rest of the write it belongs to is still on its way.
 #[cfg(unix)]
-fn process_guest_message(
+fn read_guest_messages(
     remote: &mut std::os::unix::net::UnixStream,
-    is_tty: bool,
-    stdout: &mut File,
-    stderr: &mut File,
-) -> Result<Option<u8>, ShimError> {
-    let mut cmd_buf = [0u8; 2];
-    match remote.read_exact(&mut cmd_buf) {
-        Ok(()) => {}
-        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(None),
+    decoder: &mut GuestDecoder,
+) -> Result<Vec<GuestMessage>, ShimError> {
+    let mut buf = [0u8; MAX_DATA_LEN + 2];
+    let mut messages = Vec::new();
+    match remote.read(&mut buf) {
+        Ok(0) => {
+            return Err(ShimError::RuntimeError(
+                "guest closed the connection".to_string(),
+            ))
+        }
+        Ok(n) => decoder.feed(&buf[..n], &mut messages),
+        Err(e)
+            if e.kind() == std::io::ErrorKind::WouldBlock
+                || e.kind() == std::io::ErrorKind::Interrupted => {}
         Err(e) => {
             return Err(ShimError::RuntimeError(format!(
                 "Failed to read from guest: {}",
@@ -276,44 +299,30 @@ fn process_guest_message(
             )))
         }
     }
+    Ok(messages)
+}
 
-    let cmd = u16::from_le_bytes(cmd_buf);
-    let (opcode, value) = decode_cmd(cmd);
-
-    match opcode {
-        CMD_WRITE_STDOUT | CMD_WRITE_STDERR => {
-            if value > 0 {
-                let mut data = vec![0u8; value];
-                match remote.read_exact(&mut data) {
-                    Ok(()) => {}
-                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(None),
-                    Err(e) => {
-                        return Err(ShimError::RuntimeError(format!(
-                            "Failed to read data from guest: {}",
-                            e
-                        )))
-                    }
-                }
-
-                let target = if opcode == CMD_WRITE_STDOUT || is_tty {
-                    &mut *stdout
-                } else {
-                    &mut *stderr
-                };
-
-                target.write_all(&data).map_err(|e| {
-                    ShimError::RuntimeError(format!("Failed to write to terminal: {}", e))
-                })?;
-                target.flush().ok();
-            }
-            Ok(None)
-        }
-        CMD_EXIT => Ok(Some(value as u8)),
-        _ => {
-            tracing::warn!("Unknown opcode from guest: {}", opcode);
-            Ok(None)
-        }
+#[cfg(unix)]
+fn process_guest_message(
+    remote: &mut std::os::unix::net::UnixStream,
+    decoder: &mut GuestDecoder,
+    is_tty: bool,
+    stdout: &mut File,
+    stderr: &mut File,
+) -> Result<Option<u8>, ShimError> {
+    for message in read_guest_messages(remote, decoder)? {
+        let (target, data) = match message {
+            GuestMessage::Stdout(data) => (&mut *stdout, data),
+            GuestMessage::Stderr(data) if is_tty => (&mut *stdout, data),
+            GuestMessage::Stderr(data) => (&mut *stderr, data),
+            GuestMessage::Exit(code) => return Ok(Some(code)),
+        };
+        target.write_all(&data).map_err(|e| {
+            ShimError::RuntimeError(format!("Failed to write to terminal: {}", e))
+        })?;
+        target.flush().ok();
     }
+    Ok(None)
 }
 
 #[cfg(target_os = "macos")]
@@ -365,10 +374,15 @@ pub enum HostIoEvent {
 
 /// Run the host-side I/O loop using channels for gRPC integration.
 /// This version uses input_rx/output_tx channels instead of the daemon's terminal.
+/// The guest's stdin is closed, by sending it EOF over the vsock, as `stdin`
+/// says: right away when it is not open, or when the client is done with it
+/// (a `CloseStdin` event, or every input sender dropped) if it is only open
+/// once. The loop keeps forwarding output until the guest process exits.
 #[cfg(unix)]
 pub fn run_io_host_with_channels(
     listener: UnixListener,
     is_tty: bool,
+    mut stdin: crate::stdin::StdinState,
     input_rx: std::sync::mpsc::Receiver<crate::types::InputEvent>,
     output_tx: std::sync::mpsc::Sender<crate::types::OutputEvent>,
 ) -> Result<u8, ShimError> {
@@ -388,19 +402,29 @@ pub fn run_io_host_with_channels(
         }
     }
 
+    let mut input_open = true;
+    let mut decoder = GuestDecoder::default();
+    // An empty write is EOF to the guest.
+    let send_eof = |remote: &mut std::os::unix::net::UnixStream| {
+        let cmd = encode_write_cmd(CMD_WRITE_STDIN, 0);
+        let _ = remote.write_all(&cmd.to_le_bytes());
+    };
+    if !stdin.is_open() && !is_tty {
+        send_eof(&mut remote);
+    }
+
     loop {
         // Check for input from gRPC client (non-blocking)
-        match input_rx.try_recv() {
+        let input = if input_open {
+            input_rx.try_recv()
+        } else {
+            Err(std::sync::mpsc::TryRecvError::Empty)
+        };
+        match input {
+            // An empty write means EOF to the guest, so don't forward one early.
+            Ok(InputEvent::Stdin(data)) if data.is_empty() || !stdin.is_open() => {}
             Ok(InputEvent::Stdin(data)) => {
-                let cmd = encode_write_cmd(CMD_WRITE_STDIN, data.len());
-                if remote.write_all(&cmd.to_le_bytes()).is_err() {
-                    let _ = output_tx.send(OutputEvent::Exit(WaitResult {
-                        exit_code: 1,
-                        error: Some("Failed to write to guest".to_string()),
-                    }));
-                    return Ok(1);
-                }
-                if !data.is_empty() && remote.write_all(&data).is_err() {
+                if write_stdin(&mut remote, &data).is_err() {
                     let _ = output_tx.send(OutputEvent::Exit(WaitResult {
                         exit_code: 1,
                         error: Some("Failed to write to guest".to_string()),
@@ -411,12 +435,18 @@ pub fn run_io_host_with_channels(
             Ok(InputEvent::Resize { width, height }) => {
                 let _ = send_terminal_size(&mut remote, width, height);
             }
+            Ok(InputEvent::CloseStdin) => {
+                if stdin.client_done() {
+                    send_eof(&mut remote);
+                }
+            }
             Err(std::sync::mpsc::TryRecvError::Empty) => {}
             Err(std::sync::mpsc::TryRecvError::Disconnected) => {
-                // Client disconnected, send EOF to guest
-                let cmd = encode_write_cmd(CMD_WRITE_STDIN, 0);
-                let _ = remote.write_all(&cmd.to_le_bytes());
-                return Ok(1);
+                // The client is gone; stdin stays open unless only open once
+                if stdin.client_done() {
+                    send_eof(&mut remote);
+                }
+                input_open = false;
             }
         }
 
@@ -447,7 +477,7 @@ pub fn run_io_host_with_channels(
 
         // Process messages from guest
         if remote_ready {
-            match process_guest_message_to_channel(&mut remote, is_tty, &output_tx) {
+            match process_guest_message_to_channel(&mut remote, &mut decoder, is_tty, &output_tx) {
                 Ok(Some(exit_code)) => {
                     let _ = output_tx.send(OutputEvent::Exit(WaitResult {
                         exit_code: exit_code as i32,
@@ -481,57 +511,22 @@ pub fn run_io_host_with_channels(
 #[cfg(unix)]
 fn process_guest_message_to_channel(
     remote: &mut std::os::unix::net::UnixStream,
+    decoder: &mut GuestDecoder,
     is_tty: bool,
     output_tx: &std::sync::mpsc::Sender<crate::types::OutputEvent>,
 ) -> Result<Option<u8>, ShimError> {
     use crate::types::OutputEvent;
 
-    let mut cmd_buf = [0u8; 2];
-    match remote.read_exact(&mut cmd_buf) {
-        Ok(()) => {}
-        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(None),
-        Err(e) => {
-            return Err(ShimError::RuntimeError(format!(
-                "Failed to read from guest: {}",
-                e
-            )))
-        }
-    }
-
-    let cmd = u16::from_le_bytes(cmd_buf);
-    let (opcode, value) = decode_cmd(cmd);
-
-    match opcode {
-        CMD_WRITE_STDOUT | CMD_WRITE_STDERR => {
-            if value > 0 {
-                let mut data = vec![0u8; value];
-                match remote.read_exact(&mut data) {
-                    Ok(()) => {}
-                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(None),
-                    Err(e) => {
-                        return Err(ShimError::RuntimeError(format!(
-                            "Failed to read data from guest: {}",
-                            e
-                        )))
-                    }
-                }
-
-                let event = if opcode == CMD_WRITE_STDOUT || is_tty {
-                    OutputEvent::Stdout(data)
-                } else {
-                    OutputEvent::Stderr(data)
-                };
-
-                output_tx.send(event).map_err(|e| {
-                    ShimError::RuntimeError(format!("Failed to send output event: {}", e))
-                })?;
-            }
-            Ok(None)
-        }
-        CMD_EXIT => Ok(Some(value as u8)),
-        _ => {
-            tracing::warn!("Unknown opcode from guest: {}", opcode);
-            Ok(None)
-        }
+    for message in read_guest_messages(remote, decoder)? {
+        let event = match message {
+            GuestMessage::Stdout(data) => OutputEvent::Stdout(data),
+            GuestMessage::Stderr(data) if is_tty => OutputEvent::Stdout(data),
+            GuestMessage::Stderr(data) => OutputEvent::Stderr(data),
+            GuestMessage::Exit(code) => return Ok(Some(code)),
+        };
+        output_tx.send(event).map_err(|e| {
+            ShimError::RuntimeError(format!("Failed to send output event: {}", e))
+        })?;
     }
+    Ok(None)
 }
@@ -48,6 +48,72 @@ pub fn decode_cmd(cmd: u16) -> (u16, usize) {
     (opcode, value)
 }
 
+/// A message from the guest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GuestMessage {
+    Stdout(Vec<u8>),
+    Stderr(Vec<u8>),
+    Exit(u8),
+}
+
+/// Splits the bytes the guest sends into messages as they arrive.
+///
+/// The data of a write is handed out as soon as any of it is in, in as many
+/// pieces as it arrives in, so output is seen while the guest writes it
+/// rather than once a whole write has come through. A command word split
+/// across reads is kept until its second byte arrives.
+#[derive(Debug, Default)]
+pub struct GuestDecoder {
+    /// First byte of a command word whose second has yet to arrive.
+    partial_cmd: Option<u8>,
+    /// Opcode and number of bytes still to come of the write being received.
+    write: Option<(u16, usize)>,
+}
+
+impl GuestDecoder {
+    /// Decodes `data`, the next bytes from the guest, into `messages`.
+    pub fn feed(&mut self, mut data: &[u8], messages: &mut Vec<GuestMessage>) {
+        while !data.is_empty() {
+            if let Some((opcode, remaining)) = self.write {
+                let (chunk, rest) = data.split_at(remaining.min(data.len()));
+                messages.push(if opcode == CMD_WRITE_STDOUT {
+                    GuestMessage::Stdout(chunk.to_vec())
+                } else {
+                    GuestMessage::Stderr(chunk.to_vec())
+                });
+                self.write = (remaining > chunk.len()).then(|| (opcode, remaining - chunk.len()));
+                data = rest;
+                continue;
+            }
+
+            let cmd = match self.partial_cmd.take() {
+                Some(low) => {
+                    let cmd = u16::from_le_bytes([low, data[0]]);
+                    data = &data[1..];
+                    cmd
+                }
+                None if data.len() < 2 => {
+                    self.partial_cmd = Some(data[0]);
+                    return;
+                }
+                None => {
+                    let cmd = u16::from_le_bytes([data[0], data[1]]);
+                    data = &data[2..];
+                    cmd
+                }
+            };
+            match decode_cmd(cmd) {
+                (CMD_WRITE_STDOUT | CMD_WRITE_STDERR, 0) => {}
+                (opcode @ (CMD_WRITE_STDOUT | CMD_WRITE_STDERR), len) => {
+                    self.write = Some((opcode, len));
+                }
+                (CMD_EXIT, code) => messages.push(GuestMessage::Exit(code as u8)),
+                (opcode, _) => tracing::warn!("Unknown opcode from guest: {}", opcode),
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -99,4 +165,60 @@ mod tests {
         assert_eq!(opcode, CMD_WRITE_STDIN);
         assert_eq!(len, 0);
     }
+
+    fn write(opcode: u16, data: &[u8]) -> Vec<u8> {
+        let mut bytes = encode_write_cmd(opcode, data.len()).to_le_bytes().to_vec();
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    #[test]
+    fn test_guest_decoder() {
+        let mut stream = write(CMD_WRITE_STDOUT, b"hello");
+        stream.extend(write(CMD_WRITE_STDERR, b"oops"));
+        stream.extend(write(CMD_WRITE_STDOUT, b""));
+        stream.extend(encode_exit_cmd(3).to_le_bytes());
+
+        let mut decoder = GuestDecoder::default();
+        let mut messages = Vec::new();
+        decoder.feed(&stream, &mut messages);
+        assert_eq!(
+            messages,
+            vec![
+                GuestMessage::Stdout(b"hello".to_vec()),
+                GuestMessage::Stderr(b"oops".to_vec()),
+                GuestMessage::Exit(3),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_guest_decoder_split_reads() {
+        let mut stream = write(CMD_WRITE_STDOUT, b"streamed");
+        stream.extend(encode_exit_cmd(0).to_le_bytes());
+
+        // One byte at a time, as slowly as the guest could send it: output
+        // comes out as it arrives, and split command words are kept.
+        let mut decoder = GuestDecoder::default();
+        let mut messages = Vec::new();
+        for (i, byte) in stream.iter().enumerate() {
+            decoder.feed(std::slice::from_ref(byte), &mut messages);
+            if (2..10).contains(&i) {
+                assert_eq!(
+                    messages.last(),
+                    Some(&GuestMessage::Stdout(vec![stream[i]]))
+                );
+            }
+        }
+        let output: Vec<u8> = messages
+            .iter()
+            .filter_map(|m| match m {
+                GuestMessage::Stdout(data) => Some(data.clone()),
+                _ => None,
+            })
+            .flatten()
+            .collect();
+        assert_eq!(output, b"streamed");
+        assert_eq!(messages.last(), Some(&GuestMessage::Exit(0)));
+    }
 }
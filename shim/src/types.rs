@@ -1,5 +1,8 @@
+use crate::error::ShimError;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ContainerConfig {
@@ -13,6 +16,37 @@ pub struct ContainerConfig {
     pub labels: HashMap<String, String>,
     pub tty: bool,
     pub open_stdin: bool,
+    /// Close stdin once the first client is done with it.
+    #[serde(default)]
+    pub stdin_once: bool,
+    /// Signal sent by `stop` before escalating to SIGKILL; SIGTERM when unset.
+    #[serde(default)]
+    pub stop_signal: Option<u32>,
+    #[serde(default)]
+    pub healthcheck: Option<HealthCheck>,
+    /// Digest of the image config the container was created from.
+    #[serde(default)]
+    pub image_id: String,
+}
+
+impl ContainerConfig {
+    /// The process the container runs: its entrypoint followed by its cmd.
+    pub fn command(&self) -> Vec<String> {
+        self.entrypoint.iter().chain(&self.cmd).cloned().collect()
+    }
+}
+
+/// A command run periodically inside the container to judge its health.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HealthCheck {
+    /// Command and arguments, exec'd directly.
+    pub cmd: Vec<String>,
+    pub interval: Duration,
+    pub timeout: Duration,
+    /// Failures during this period after start do not count towards `retries`.
+    pub start_period: Duration,
+    /// Consecutive failures before the container is unhealthy.
+    pub retries: u32,
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -21,7 +55,190 @@ pub struct HostConfig {
     pub network_mode: Option<String>,
     pub privileged: bool,
     pub readonly_rootfs: bool,
+    /// Tmpfs mounts by container path, with their comma-separated options.
+    #[serde(default)]
+    pub tmpfs: HashMap<String, String>,
     pub auto_remove: bool,
+    #[serde(default)]
+    pub port_bindings: Vec<PortMapping>,
+    /// Memory limit in bytes enforced through the memory cgroup.
+    #[serde(default)]
+    pub memory_limit: Option<i64>,
+    /// CFS quota in microseconds per `cpu_period`.
+    #[serde(default)]
+    pub cpu_quota: Option<i64>,
+    /// CFS period in microseconds.
+    #[serde(default)]
+    pub cpu_period: Option<u64>,
+    #[serde(default)]
+    pub restart_policy: RestartPolicy,
+    /// Name servers of the container's resolver.
+    #[serde(default)]
+    pub dns: Vec<String>,
+    /// Search domains of the container's resolver.
+    #[serde(default)]
+    pub dns_search: Vec<String>,
+    /// Options of the container's resolver, as `resolv.conf` takes them.
+    #[serde(default)]
+    pub dns_options: Vec<String>,
+    /// Extra `/etc/hosts` entries, as `NAME:IP`.
+    #[serde(default)]
+    pub extra_hosts: Vec<String>,
+    /// Networks, as CIDRs, the container may open connections to; empty
+    /// allows all of them.
+    #[serde(default)]
+    pub egress_allow: Vec<String>,
+    /// Networks, as CIDRs, the container may not open connections to.
+    #[serde(default)]
+    pub egress_deny: Vec<String>,
+}
+
+impl HostConfig {
+    /// Network mode of the container, checked against the rest of the config.
+    pub fn network(&self) -> Result<NetworkMode, ShimError> {
+        let mode = self
+            .network_mode
+            .as_deref()
+            .map_or(Ok(NetworkMode::Bridge), str::parse)?;
+        if mode == NetworkMode::None && !self.port_bindings.is_empty() {
+            return Err(ShimError::InvalidNetworkMode(
+                "none cannot be combined with published ports".to_string(),
+            ));
+        }
+        Ok(mode)
+    }
+}
+
+/// How a container is connected to the network.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkMode {
+    /// Its own network namespace, reaching the outside through the runtime's
+    /// userspace network stack where it has one. The default.
+    Bridge,
+    /// The host's network stack.
+    Host,
+    /// Its own network namespace with only a loopback interface.
+    None,
+}
+
+impl FromStr for NetworkMode {
+    type Err = ShimError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "" | "default" | "bridge" => Ok(NetworkMode::Bridge),
+            "host" => Ok(NetworkMode::Host),
+            "none" => Ok(NetworkMode::None),
+            _ => Err(ShimError::InvalidNetworkMode(format!(
+                "'{}', expected bridge, host or none",
+                s
+            ))),
+        }
+    }
+}
+
+impl std::fmt::Display for NetworkMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NetworkMode::Bridge => write!(f, "bridge"),
+            NetworkMode::Host => write!(f, "host"),
+            NetworkMode::None => write!(f, "none"),
+        }
+    }
+}
+
+/// When a container is started again after its process exits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    #[default]
+    No,
+    Always,
+    /// Like `Always`, but not for containers the user explicitly stopped,
+    /// including across daemon restarts.
+    UnlessStopped,
+    /// Restart on a non-zero exit code, at most `max_retries` times
+    /// (unbounded when 0).
+    OnFailure {
+        max_retries: u32,
+    },
+}
+
+impl RestartPolicy {
+    /// Whether a container that exited with `exit_code` after `restart_count`
+    /// policy restarts should be started again.
+    pub fn should_restart(
+        &self,
+        exit_code: i32,
+        restart_count: u32,
+        manually_stopped: bool,
+    ) -> bool {
+        if manually_stopped {
+            return false;
+        }
+        match self {
+            RestartPolicy::No => false,
+            RestartPolicy::Always | RestartPolicy::UnlessStopped => true,
+            RestartPolicy::OnFailure { max_retries } => {
+                exit_code != 0 && (*max_retries == 0 || restart_count < *max_retries)
+            }
+        }
+    }
+
+    /// Whether a stopped container should be started when the daemon starts.
+    pub fn restart_on_daemon_start(&self, manually_stopped: bool) -> bool {
+        match self {
+            RestartPolicy::Always => true,
+            RestartPolicy::UnlessStopped => !manually_stopped,
+            RestartPolicy::No | RestartPolicy::OnFailure { .. } => false,
+        }
+    }
+}
+
+/// The cgroup limits of a container. In an update, limits left `None` keep
+/// their value.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResourceLimits {
+    /// Memory limit in bytes.
+    pub memory_limit: Option<i64>,
+    /// CFS quota in microseconds per `cpu_period`.
+    pub cpu_quota: Option<i64>,
+    /// CFS period in microseconds.
+    pub cpu_period: Option<u64>,
+}
+
+impl ResourceLimits {
+    pub fn of(host_config: &HostConfig) -> Self {
+        Self {
+            memory_limit: host_config.memory_limit,
+            cpu_quota: host_config.cpu_quota,
+            cpu_period: host_config.cpu_period,
+        }
+    }
+
+    /// Sets the limits given here on `host_config`, keeping the others.
+    pub fn apply_to(&self, host_config: &mut HostConfig) {
+        if let Some(limit) = self.memory_limit {
+            host_config.memory_limit = Some(limit);
+        }
+        if let Some(quota) = self.cpu_quota {
+            host_config.cpu_quota = Some(quota);
+        }
+        if let Some(period) = self.cpu_period {
+            host_config.cpu_period = Some(period);
+        }
+    }
+}
+
+/// A host port published to a port inside the container.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PortMapping {
+    /// Host address to listen on; all interfaces when unset.
+    pub host_ip: Option<String>,
+    pub host_port: u16,
+    pub container_port: u16,
+    /// "tcp" or "udp".
+    pub protocol: String,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -54,8 +271,75 @@ pub struct ContainerInfo {
     pub created_at: i64,
     pub started_at: Option<i64>,
     pub finished_at: Option<i64>,
+    /// Nanoseconds past the second of `created_at`, `started_at` and
+    /// `finished_at`; zero in metadata written before they were kept.
+    #[serde(default)]
+    pub created_nanos: u32,
+    #[serde(default)]
+    pub started_nanos: u32,
+    #[serde(default)]
+    pub finished_nanos: u32,
     pub bundle_path: String,
     pub rootfs_path: String,
+    #[serde(default)]
+    pub restart_policy: RestartPolicy,
+    /// Number of times the restart policy has started the container again.
+    #[serde(default)]
+    pub restart_count: u32,
+    /// Set by `stop` so restart policies leave the container alone.
+    #[serde(default)]
+    pub manually_stopped: bool,
+    #[serde(default)]
+    pub healthcheck: Option<HealthCheck>,
+    /// Digest of the image config the container was created from.
+    #[serde(default)]
+    pub image_id: String,
+    /// Entrypoint and cmd the container runs.
+    #[serde(default)]
+    pub command: Vec<String>,
+    /// Network mode validated at create time.
+    #[serde(default)]
+    pub network_mode: String,
+    /// Whether the OOM killer struck during the container's last run.
+    #[serde(default)]
+    pub oom_killed: bool,
+    /// Whether the daemon removes the container once it exits.
+    #[serde(default)]
+    pub auto_remove: bool,
+    /// Limits the container runs with, as created or last updated.
+    #[serde(default)]
+    pub resources: ResourceLimits,
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+}
+
+impl ContainerInfo {
+    /// Records that the container started now, forgetting when its previous
+    /// run finished.
+    pub(crate) fn mark_started(&mut self) {
+        let now = since_epoch();
+        self.started_at = Some(now.as_secs() as i64);
+        self.started_nanos = now.subsec_nanos();
+        self.finished_at = None;
+        self.finished_nanos = 0;
+    }
+
+    /// Records that the container finished now, unless its run was already
+    /// seen to finish.
+    pub(crate) fn mark_finished(&mut self) {
+        if self.finished_at.is_none() {
+            let now = since_epoch();
+            self.finished_at = Some(now.as_secs() as i64);
+            self.finished_nanos = now.subsec_nanos();
+        }
+    }
+}
+
+/// The current time as a duration since the Unix epoch.
+pub(crate) fn since_epoch() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
 }
 
 #[derive(Debug, Clone)]
@@ -73,6 +357,34 @@ pub struct SnapshotMount {
     pub options: Vec<String>,
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct ExecOpts {
+    pub cmd: Vec<String>,
+    pub env: Vec<String>,
+    pub user: Option<String>,
+    pub working_dir: Option<String>,
+    pub tty: bool,
+    /// Lets the process gain privileges, through setuid binaries for one.
+    pub privileged: bool,
+    /// Kills the process once it has run this long.
+    pub timeout: Option<Duration>,
+}
+
+/// Traffic counters of one of a container's network interfaces, from the
+/// container's point of view.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InterfaceStats {
+    pub rx_bytes: u64,
+    pub rx_packets: u64,
+    pub rx_dropped: u64,
+    pub tx_bytes: u64,
+    pub tx_packets: u64,
+    pub tx_dropped: u64,
+    /// TCP connections the userspace NAT behind the interface is tracking,
+    /// 0 for interfaces without one.
+    pub nat_connections: u64,
+}
+
 #[derive(Debug, Clone)]
 pub struct WaitResult {
     pub exit_code: i32,
@@ -89,5 +401,20 @@ pub enum OutputEvent {
 #[derive(Debug, Clone)]
 pub enum InputEvent {
     Stdin(Vec<u8>),
-    Resize { width: u16, height: u16 },
+    Resize {
+        width: u16,
+        height: u16,
+    },
+    /// The client reached the end of its input. A client going away, by
+    /// closing its channel, counts as well.
+    CloseStdin,
+}
+
+/// Handles onto the I/O of a running container for an attached client.
+#[derive(Debug)]
+pub struct AttachStreams {
+    /// Output of the container's main process, ending with its exit.
+    pub output: tokio::sync::broadcast::Receiver<OutputEvent>,
+    /// Writes to the container's stdin, if it has one to write to.
+    pub input: Option<tokio::sync::mpsc::Sender<InputEvent>>,
 }
@@ -0,0 +1,246 @@
+//! Resolution of `--user` specs against a container's own `/etc/passwd` and
+//! `/etc/group`.
+
+use crate::error::ShimError;
+use std::path::Path;
+
+/// The identity a container process runs as.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct User {
+    pub uid: u32,
+    pub gid: u32,
+    /// Groups that list the user as a member, other than its primary one.
+    pub additional_gids: Vec<u32>,
+}
+
+struct PasswdEntry {
+    name: String,
+    uid: u32,
+    gid: u32,
+}
+
+struct GroupEntry {
+    name: String,
+    gid: u32,
+    members: Vec<String>,
+}
+
+/// Resolves `spec` (`USER[:GROUP]`, each a name or a numeric id) against the
+/// account files of the root filesystem at `rootfs`. An empty spec is root.
+///
+/// A numeric user needs no passwd entry; without one its group defaults to
+/// its uid. A name that is not found is an error rather than root.
+pub fn resolve_user(rootfs: &Path, spec: &str) -> Result<User, ShimError> {
+    if spec.is_empty() {
+        return Ok(User::default());
+    }
+
+    let (user, group) = match spec.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (spec, None),
+    };
+    if user.is_empty() || group.is_some_and(str::is_empty) {
+        return Err(ShimError::InvalidUser(format!(
+            "invalid user spec '{}' (expected USER[:GROUP])",
+            spec
+        )));
+    }
+
+    let passwd = read_account_file(rootfs, "passwd")?;
+    let entry = passwd.as_deref().and_then(|passwd| {
+        parse_passwd(passwd).find(|e| e.name == user || e.uid.to_string() == user)
+    });
+    let (uid, primary_gid, name) = match (entry, user.parse::<u32>()) {
+        (Some(entry), _) => (entry.uid, entry.gid, Some(entry.name)),
+        (None, Ok(uid)) => (uid, uid, None),
+        (None, Err(_)) => {
+            return Err(ShimError::InvalidUser(format!(
+                "unable to find user {}: no matching entries in passwd file",
+                user
+            )));
+        }
+    };
+
+    let groups = read_account_file(rootfs, "group")?;
+    let gid = match group {
+        None => primary_gid,
+        Some(group) => {
+            let entry = groups.as_deref().and_then(|groups| {
+                parse_group(groups).find(|e| e.name == group || e.gid.to_string() == group)
+            });
+            match (entry, group.parse::<u32>()) {
+                (Some(entry), _) => entry.gid,
+                (None, Ok(gid)) => gid,
+                (None, Err(_)) => {
+                    return Err(ShimError::InvalidUser(format!(
+                        "unable to find group {}: no matching entries in group file",
+                        group
+                    )));
+                }
+            }
+        }
+    };
+
+    let mut additional_gids = Vec::new();
+    if let (Some(name), Some(groups)) = (name, groups.as_deref()) {
+        for entry in parse_group(groups) {
+            if entry.gid != gid
+                && entry.members.contains(&name)
+                && !additional_gids.contains(&entry.gid)
+            {
+                additional_gids.push(entry.gid);
+            }
+        }
+    }
+
+    Ok(User {
+        uid,
+        gid,
+        additional_gids,
+    })
+}
+
+/// Reads `/etc/<name>` of the root filesystem, or `None` when it has none.
+/// Symlinks are not followed, since they would resolve against the host.
+fn read_account_file(rootfs: &Path, name: &str) -> Result<Option<String>, ShimError> {
+    let etc = rootfs.join("etc");
+    let path = etc.join(name);
+    for path in [&etc, &path] {
+        match std::fs::symlink_metadata(path) {
+            Ok(metadata) if !metadata.file_type().is_symlink() => {}
+            Ok(_) => {
+                tracing::warn!("Not reading {}: it is a symlink", path.display());
+                return Ok(None);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(Some(std::fs::read_to_string(path)?))
+}
+
+/// Entries of a passwd file (`name:password:uid:gid:gecos:home:shell`),
+/// skipping malformed lines.
+fn parse_passwd(content: &str) -> impl Iterator<Item = PasswdEntry> + '_ {
+    content.lines().filter_map(|line| {
+        let mut fields = line.split(':');
+        let name = fields.next().filter(|name| !name.is_empty())?;
+        let uid = fields.nth(1)?.parse().ok()?;
+        let gid = fields.next()?.parse().ok()?;
+        Some(PasswdEntry {
+            name: name.to_string(),
+            uid,
+            gid,
+        })
+    })
+}
+
+/// Entries of a group file (`name:password:gid:member,...`), skipping
+/// malformed lines.
+fn parse_group(content: &str) -> impl Iterator<Item = GroupEntry> + '_ {
+    content.lines().filter_map(|line| {
+        let mut fields = line.split(':');
+        let name = fields.next().filter(|name| !name.is_empty())?;
+        let gid = fields.nth(1)?.parse().ok()?;
+        let members = fields
+            .next()
+            .unwrap_or_default()
+            .split(',')
+            .filter(|member| !member.is_empty())
+            .map(str::to_string)
+            .collect();
+        Some(GroupEntry {
+            name: name.to_string(),
+            gid,
+            members,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn rootfs() -> TempDir {
+        let temp_dir = TempDir::new().unwrap();
+        let etc = temp_dir.path().join("etc");
+        std::fs::create_dir(&etc).unwrap();
+        std::fs::write(
+            etc.join("passwd"),
+            "root:x:0:0:root:/root:/bin/sh\n\
+             nginx:x:101:101:nginx:/var/cache/nginx:/sbin/nologin\n\
+             broken line\n",
+        )
+        .unwrap();
+        std::fs::write(
+            etc.join("group"),
+            "root:x:0:\nnginx:x:101:\nwww-data:x:33:nginx\nadm:x:4:root,nginx\n",
+        )
+        .unwrap();
+        temp_dir
+    }
+
+    fn user(uid: u32, gid: u32, additional_gids: &[u32]) -> User {
+        User {
+            uid,
+            gid,
+            additional_gids: additional_gids.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_resolve_names() {
+        let rootfs = rootfs();
+        let path = rootfs.path();
+        assert_eq!(resolve_user(path, "").unwrap(), user(0, 0, &[]));
+        assert_eq!(
+            resolve_user(path, "nginx").unwrap(),
+            user(101, 101, &[33, 4])
+        );
+        assert_eq!(
+            resolve_user(path, "nginx:adm").unwrap(),
+            user(101, 4, &[33])
+        );
+        assert_eq!(resolve_user(path, "101:33").unwrap(), user(101, 33, &[4]));
+        assert_eq!(
+            resolve_user(path, "root:nginx").unwrap(),
+            user(0, 101, &[4])
+        );
+    }
+
+    #[test]
+    fn test_resolve_numeric_fallback() {
+        let rootfs = rootfs();
+        assert_eq!(
+            resolve_user(rootfs.path(), "1000").unwrap(),
+            user(1000, 1000, &[])
+        );
+        assert_eq!(
+            resolve_user(rootfs.path(), "1000:50").unwrap(),
+            user(1000, 50, &[])
+        );
+
+        let empty = TempDir::new().unwrap();
+        assert_eq!(
+            resolve_user(empty.path(), "1000:50").unwrap(),
+            user(1000, 50, &[])
+        );
+        assert!(resolve_user(empty.path(), "nginx").is_err());
+    }
+
+    #[test]
+    fn test_resolve_unknown() {
+        let rootfs = rootfs();
+        for spec in ["postgres", "nginx:postgres", ":101", "nginx:"] {
+            assert!(
+                matches!(
+                    resolve_user(rootfs.path(), spec),
+                    Err(ShimError::InvalidUser(_))
+                ),
+                "{} should be rejected",
+                spec
+            );
+        }
+    }
+}
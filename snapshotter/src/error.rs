@@ -20,6 +20,9 @@ pub enum SnapshotterError {
     #[error("layer extraction failed: {0}")]
     ExtractionFailed(String),
 
+    #[error("unsupported layer media type: {0}")]
+    UnsupportedMediaType(String),
+
     #[error("mount failed: {0}")]
     MountFailed(String),
 
@@ -3,5 +3,5 @@ mod overlay;
 mod types;
 
 pub use error::SnapshotterError;
-pub use overlay::OverlaySnapshotter;
-pub use types::{Mount, SnapshotInfo, SnapshotKind, Usage};
+pub use overlay::{OverlaySnapshotter, chain_ids};
+pub use types::{Change, ChangeKind, Layer, Mount, SnapshotInfo, SnapshotKind, Usage};
@@ -1,18 +1,48 @@
 use crate::error::SnapshotterError;
-use crate::types::{Mount, SnapshotInfo, SnapshotKind, Usage};
+use crate::types::{Change, ChangeKind, Layer, Mount, SnapshotInfo, SnapshotKind, Usage};
+use flate2::Compression;
 use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use futures::StreamExt;
+use futures::stream::FuturesOrdered;
+use ross_mount::{escape_overlay_path, lowerdir_option};
 use ross_store::FileSystemStore;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::path::{Path, PathBuf};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::ffi::{CString, OsString};
+use std::io::{BufReader, Read, Seek, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Component, Path, PathBuf};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use tar::Archive;
 use tokio::fs;
-use tokio::sync::RwLock;
+use tokio::sync::{RwLock, Semaphore, mpsc};
 
 const SNAPSHOTS_DIR: &str = "snapshots";
 const METADATA_FILE: &str = "metadata.json";
 
+const LAYER_DIGEST_LABEL: &str = "containerd.io/snapshot/layer.digest";
+const UNCOMPRESSED_LABEL: &str = "containerd.io/uncompressed";
+const LAYER_MEDIA_TYPE: &str = "application/vnd.oci.image.layer.v1.tar+gzip";
+/// Marks the active snapshot a layer is unpacked into until it is committed.
+const EXTRACTING_LABEL: &str = "ross.snapshot/extracting";
+
+const DEFAULT_CONCURRENT_UNPACKS: usize = 3;
+
+/// The gzipped layer `commit_diff` builds, next to its unpacked copy.
+const STAGED_LAYER_FILE: &str = "layer.tar.gz";
+
+const WHITEOUT_PREFIX: &[u8] = b".wh.";
+const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+/// Overlayfs marks opaque directories with one of these, depending on
+/// whether it was mounted with `userxattr`.
+#[cfg(target_os = "linux")]
+const OVERLAY_OPAQUE_XATTRS: [&std::ffi::CStr; 2] =
+    [c"trusted.overlay.opaque", c"user.overlay.opaque"];
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct SnapshotMetadata {
     info: SnapshotInfo,
@@ -22,6 +52,8 @@ pub struct OverlaySnapshotter {
     root: PathBuf,
     store: Arc<FileSystemStore>,
     snapshots: RwLock<HashMap<String, SnapshotInfo>>,
+    /// Bounds the layers unpacked at once, across all pulls.
+    unpacks: Arc<Semaphore>,
 }
 
 impl OverlaySnapshotter {
@@ -37,6 +69,7 @@ impl OverlaySnapshotter {
             root,
             store,
             snapshots: RwLock::new(HashMap::new()),
+            unpacks: Arc::new(Semaphore::new(DEFAULT_CONCURRENT_UNPACKS)),
         };
 
         snapshotter.load_snapshots().await?;
@@ -44,6 +77,12 @@ impl OverlaySnapshotter {
         Ok(snapshotter)
     }
 
+    /// Sets how many layers may be unpacked at once.
+    pub fn with_max_concurrent_unpacks(mut self, limit: usize) -> Self {
+        self.unpacks = Arc::new(Semaphore::new(limit.max(1)));
+        self
+    }
+
     async fn load_snapshots(&self) -> Result<(), SnapshotterError> {
         let snapshots_dir = self.root.join(SNAPSHOTS_DIR);
         let mut snapshots = self.snapshots.write().await;
@@ -66,6 +105,11 @@ impl OverlaySnapshotter {
 
             let content = fs::read_to_string(&meta_path).await?;
             let metadata: SnapshotMetadata = serde_json::from_str(&content)?;
+            // Left behind by an extraction that was interrupted.
+            if metadata.info.labels.contains_key(EXTRACTING_LABEL) {
+                fs::remove_dir_all(entry.path()).await?;
+                continue;
+            }
             snapshots.insert(metadata.info.key.clone(), metadata.info);
         }
 
@@ -139,11 +183,19 @@ impl OverlaySnapshotter {
             .map(|k| self.fs_dir(k).to_string_lossy().to_string())
             .collect();
 
-        let mut options = vec![format!("lowerdir={}", lower_dirs.join(":"))];
+        // The paths are escaped, as the data directory may contain the
+        // characters that separate overlay options and layers.
+        let mut options = vec![lowerdir_option(&lower_dirs)];
 
         if !readonly {
-            options.push(format!("upperdir={}", self.fs_dir(key).to_string_lossy()));
-            options.push(format!("workdir={}", self.work_dir(key).to_string_lossy()));
+            options.push(format!(
+                "upperdir={}",
+                escape_overlay_path(&self.fs_dir(key).to_string_lossy())
+            ));
+            options.push(format!(
+                "workdir={}",
+                escape_overlay_path(&self.work_dir(key).to_string_lossy())
+            ));
         }
 
         vec![Mount {
@@ -332,6 +384,157 @@ impl OverlaySnapshotter {
         Ok(())
     }
 
+    /// Saves the changes made in the active snapshot `active_key` as a new
+    /// layer.
+    ///
+    /// The upperdir is archived as an OCI layer, with deletions and opaque
+    /// directories turned into whiteout files, and stored as a blob. The
+    /// committed snapshot `new_key` holds the same changes on top of the
+    /// active snapshot's parent and records the blob digest in its labels.
+    /// Unlike [`commit`](Self::commit), the active snapshot is left in place
+    /// so a running container keeps its filesystem.
+    pub async fn commit_diff(
+        &self,
+        active_key: &str,
+        new_key: &str,
+        labels: HashMap<String, String>,
+    ) -> Result<SnapshotInfo, SnapshotterError> {
+        static NEXT_COMMIT: AtomicU64 = AtomicU64::new(0);
+
+        let active_info = {
+            let snapshots = self.snapshots.read().await;
+
+            if snapshots.contains_key(new_key) {
+                return Err(SnapshotterError::AlreadyExists(new_key.to_string()));
+            }
+
+            let active_info = snapshots
+                .get(active_key)
+                .ok_or_else(|| SnapshotterError::NotFound(active_key.to_string()))?
+                .clone();
+
+            if active_info.kind != SnapshotKind::Active {
+                return Err(SnapshotterError::InvalidState {
+                    expected: "active".to_string(),
+                    actual: active_info.kind.to_string(),
+                });
+            }
+            active_info
+        };
+
+        // The layer is built and unpacked in a directory of its own, without
+        // holding the snapshots, and only moved into place once complete.
+        let staging = self.snapshot_dir(&format!(
+            "{}-commit-{}",
+            new_key,
+            NEXT_COMMIT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let result = self.stage_diff(&self.fs_dir(active_key), &staging).await;
+        let (digest, diff_id) = match result {
+            Ok(layer) => layer,
+            Err(e) => {
+                let _ = fs::remove_dir_all(&staging).await;
+                return Err(e);
+            }
+        };
+
+        let mut snapshots = self.snapshots.write().await;
+        if snapshots.contains_key(new_key) {
+            let _ = fs::remove_dir_all(&staging).await;
+            return Err(SnapshotterError::AlreadyExists(new_key.to_string()));
+        }
+        if let Err(e) = fs::rename(&staging, self.snapshot_dir(new_key)).await {
+            let _ = fs::remove_dir_all(&staging).await;
+            return Err(e.into());
+        }
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        let mut new_labels = labels;
+        new_labels.insert(
+            LAYER_DIGEST_LABEL.to_string(),
+            format!("{}:{}", digest.algorithm, digest.hash),
+        );
+        new_labels.insert(UNCOMPRESSED_LABEL.to_string(), diff_id);
+
+        let info = SnapshotInfo {
+            key: new_key.to_string(),
+            parent: active_info.parent,
+            kind: SnapshotKind::Committed,
+            created_at: now,
+            updated_at: now,
+            labels: new_labels,
+        };
+
+        self.save_metadata(&info).await?;
+        snapshots.insert(new_key.to_string(), info.clone());
+
+        Ok(info)
+    }
+
+    /// Archives the upperdir `upper` as a gzipped layer, streamed into the
+    /// store, and unpacks it as an overlay lowerdir into `staging`'s `fs`.
+    /// Returns the layer's digest and its diff ID.
+    async fn stage_diff(
+        &self,
+        upper: &Path,
+        staging: &Path,
+    ) -> Result<(ross_store::Digest, String), SnapshotterError> {
+        fs::create_dir_all(staging.join("fs")).await?;
+        let layer_path = staging.join(STAGED_LAYER_FILE);
+
+        let (upper, staged) = (upper.to_path_buf(), staging.to_path_buf());
+        let diff_id = tokio::task::spawn_blocking(move || -> Result<_, SnapshotterError> {
+            let diff_id = write_layer(&upper, &staged.join(STAGED_LAYER_FILE))?;
+            let layer = std::fs::File::open(staged.join(STAGED_LAYER_FILE))?;
+            let tar = GzDecoder::new(std::io::BufReader::new(layer));
+            unpack_overlay_layer(tar, &staged.join("fs"))?;
+            Ok(diff_id)
+        })
+        .await
+        .map_err(|e| SnapshotterError::ExtractionFailed(e.to_string()))??;
+
+        let mut writer = self.store.blob_writer(LAYER_MEDIA_TYPE, None).await?;
+        let mut layer = fs::File::open(&layer_path).await?;
+        tokio::io::copy(&mut layer, &mut writer).await?;
+        let (digest, _) = writer.commit().await?;
+        fs::remove_file(&layer_path).await?;
+
+        Ok((digest, diff_id))
+    }
+
+    /// Lists the paths changed in snapshot `key` relative to its parent.
+    ///
+    /// A snapshot's directory only holds its own changes, so paths that
+    /// exist in an ancestor are reported as modified and the rest as added.
+    pub async fn diff(&self, key: &str) -> Result<Vec<Change>, SnapshotterError> {
+        let snapshots = self.snapshots.read().await;
+
+        let info = snapshots
+            .get(key)
+            .ok_or_else(|| SnapshotterError::NotFound(key.to_string()))?;
+
+        let lowers: Vec<PathBuf> = info
+            .parent
+            .as_ref()
+            .map(|p| self.get_parent_chain(&snapshots, p))
+            .unwrap_or_default()
+            .iter()
+            .map(|k| self.fs_dir(k))
+            .collect();
+
+        let mut changes = Vec::new();
+        let fs_dir = self.fs_dir(key);
+        if fs_dir.exists() {
+            collect_changes(&fs_dir, Path::new(""), &lowers, &mut changes)?;
+        }
+
+        Ok(changes)
+    }
+
     pub async fn remove(&self, key: &str) -> Result<(), SnapshotterError> {
         let mut snapshots = self.snapshots.write().await;
 
@@ -385,6 +588,8 @@ impl OverlaySnapshotter {
         Ok(result)
     }
 
+    /// Disk usage of the changes held by snapshot `key` itself, excluding
+    /// its parents.
     pub async fn usage(&self, key: &str) -> Result<Usage, SnapshotterError> {
         let snapshots = self.snapshots.read().await;
 
@@ -421,6 +626,70 @@ impl OverlaySnapshotter {
         Ok(reclaimed)
     }
 
+    /// Removes every snapshot that is neither in `keep` nor an ancestor of a
+    /// kept snapshot. Returns the removed keys and the bytes reclaimed.
+    pub async fn prune(
+        &self,
+        keep: &HashSet<String>,
+    ) -> Result<(Vec<String>, i64), SnapshotterError> {
+        let mut snapshots = self.snapshots.write().await;
+
+        let mut retained = HashSet::new();
+        for key in keep {
+            retained.extend(self.get_parent_chain(&snapshots, key));
+        }
+
+        // Everything depending on a removed snapshot is removed with it, so
+        // the order does not matter.
+        let orphaned: Vec<String> = snapshots
+            .keys()
+            .filter(|key| !retained.contains(*key))
+            .cloned()
+            .collect();
+
+        let mut reclaimed = 0i64;
+        for key in &orphaned {
+            let snapshot_dir = self.snapshot_dir(key);
+            if snapshot_dir.exists() {
+                let (size, _) = calculate_dir_usage(&snapshot_dir).await?;
+                fs::remove_dir_all(&snapshot_dir).await?;
+                reclaimed += size;
+            }
+            snapshots.remove(key);
+        }
+
+        Ok((orphaned, reclaimed))
+    }
+
+    /// Merges `labels` into the labels of an existing snapshot.
+    pub async fn update_labels(
+        &self,
+        key: &str,
+        labels: HashMap<String, String>,
+    ) -> Result<SnapshotInfo, SnapshotterError> {
+        let mut snapshots = self.snapshots.write().await;
+
+        let info = snapshots
+            .get_mut(key)
+            .ok_or_else(|| SnapshotterError::NotFound(key.to_string()))?;
+        info.labels.extend(labels);
+        info.updated_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        let info = info.clone();
+        self.save_metadata(&info).await?;
+
+        Ok(info)
+    }
+
+    /// Unpacks layer blob `digest` into committed snapshot `key` on top of
+    /// `parent_key`, returning the key and the unpacked size.
+    ///
+    /// Layer snapshots are shared by every image built on the layer: if `key`
+    /// already exists, possibly unpacked by a concurrent pull, it is reused
+    /// as is.
     pub async fn extract_layer(
         &self,
         digest: &str,
@@ -428,36 +697,200 @@ impl OverlaySnapshotter {
         key: &str,
         labels: HashMap<String, String>,
     ) -> Result<(String, i64), SnapshotterError> {
-        let store_digest = parse_digest(digest)?;
+        let layer = Layer {
+            digest: digest.to_string(),
+            media_type: String::new(),
+            key: key.to_string(),
+            labels,
+        };
+        let mut extracted = self
+            .extract_layers(parent_key, vec![layer], |_, _| {})
+            .await?;
+        Ok(extracted.remove(0))
+    }
 
-        let blob_data = self
-            .store
-            .get_blob(&store_digest, 0, -1)
-            .await
-            .map_err(|e| {
-                SnapshotterError::ExtractionFailed(format!("failed to get blob: {}", e))
-            })?;
+    /// Extracts `layers`, each on top of the one before it and the first on
+    /// top of `parent_key`, returning their keys and unpacked sizes.
+    ///
+    /// The contents of a layer do not depend on its parents, so layers are
+    /// unpacked concurrently, as many at once as the snapshotter allows, and
+    /// committed in order as they are ready; `on_committed` is called with
+    /// the index and size of each. Existing snapshots are reused as with
+    /// [`Self::extract_layer`].
+    pub async fn extract_layers(
+        &self,
+        parent_key: Option<&str>,
+        layers: Vec<Layer>,
+        mut on_committed: impl FnMut(usize, i64),
+    ) -> Result<Vec<(String, i64)>, SnapshotterError> {
+        // Unpacking runs alongside the commits rather than between them: an
+        // unpack left unpolled while a commit waits could hold the lock the
+        // commit waits for.
+        let (unpacked_tx, mut unpacked_rx) = mpsc::unbounded_channel();
+        let unpack_all = {
+            let layers = &layers;
+            async move {
+                let mut unpacking: FuturesOrdered<_> =
+                    layers.iter().map(|layer| self.unpack(layer)).collect();
+                while let Some(unpacked) = unpacking.next().await {
+                    let _ = unpacked_tx.send(unpacked);
+                }
+            }
+        };
 
-        let active_key = format!("{}-extract", key);
-        self.prepare(&active_key, parent_key, HashMap::new())
-            .await?;
+        let commit_all = async {
+            let mut parent = parent_key.map(String::from);
+            let mut extracted = Vec::with_capacity(layers.len());
+            let mut failed = None;
+            for layer in &layers {
+                let Some(unpacked) = unpacked_rx.recv().await else {
+                    break;
+                };
+                let committed = match unpacked {
+                    Ok(unpacked) if failed.is_none() => {
+                        self.commit_layer(layer, parent.as_deref(), unpacked).await
+                    }
+                    Ok(Some((active_key, _))) => {
+                        let _ = self.remove(&active_key).await;
+                        continue;
+                    }
+                    Ok(None) => continue,
+                    Err(e) => Err(e),
+                };
+                match committed {
+                    Ok(size) => {
+                        on_committed(extracted.len(), size);
+                        extracted.push((layer.key.clone(), size));
+                        parent = Some(layer.key.clone());
+                    }
+                    // The remaining layers are still unpacked, then discarded.
+                    Err(e) => {
+                        failed.get_or_insert(e);
+                    }
+                }
+            }
 
-        let extract_dir = self.fs_dir(&active_key);
-        let size = extract_tar_gz(&blob_data, &extract_dir)?;
+            match failed {
+                Some(e) => Err(e),
+                None => Ok(extracted),
+            }
+        };
+
+        let ((), extracted) = tokio::join!(unpack_all, commit_all);
+        extracted
+    }
 
-        let mut final_labels = labels;
-        final_labels.insert(
-            "containerd.io/snapshot/layer.digest".to_string(),
-            digest.to_string(),
+    /// Unpacks `layer` into a new active snapshot, returning its key and the
+    /// unpacked size, or `None` if the layer's snapshot already exists.
+    async fn unpack(&self, layer: &Layer) -> Result<Option<(String, i64)>, SnapshotterError> {
+        static NEXT_EXTRACT: AtomicU64 = AtomicU64::new(0);
+
+        let _permit = self.unpacks.acquire().await.expect("semaphore closed");
+        if self.stat(&layer.key).await.is_ok() {
+            return Ok(None);
+        }
+
+        let store_digest = parse_digest(&layer.digest)?;
+        let media_type = match layer.media_type.as_str() {
+            "" => self
+                .store
+                .stat_blob(&store_digest)
+                .await?
+                .map(|info| info.media_type)
+                .unwrap_or_default(),
+            media_type => media_type.to_string(),
+        };
+        // The blob is streamed through the decompressor, never held whole.
+        let (blob, _) = self.store.open_blob(&store_digest).await.map_err(|e| {
+            SnapshotterError::ExtractionFailed(format!("failed to get blob: {}", e))
+        })?;
+        let mut blob = blob.into_std().await;
+        let compression = tokio::task::spawn_blocking(move || {
+            let mut magic = Vec::with_capacity(4);
+            (&mut blob).take(4).read_to_end(&mut magic)?;
+            blob.rewind()?;
+            Ok::<_, SnapshotterError>((LayerCompression::detect(&media_type, &magic)?, blob))
+        })
+        .await
+        .map_err(|e| SnapshotterError::ExtractionFailed(e.to_string()))
+        .and_then(|result| result);
+        let (compression, blob) = compression?;
+
+        let active_key = format!(
+            "{}-extract-{}",
+            layer.key,
+            NEXT_EXTRACT.fetch_add(1, Ordering::Relaxed)
         );
+        let labels = HashMap::from([(EXTRACTING_LABEL.to_string(), layer.digest.clone())]);
+        self.prepare(&active_key, None, labels).await?;
+
+        let extract_dir = self.fs_dir(&active_key);
+        let unpacked = tokio::task::spawn_blocking(move || {
+            extract_layer_tar(BufReader::new(blob), compression, &extract_dir)
+        })
+        .await
+        .map_err(|e| SnapshotterError::ExtractionFailed(e.to_string()))
+        .and_then(|result| result);
+        match unpacked {
+            Ok(size) => Ok(Some((active_key, size))),
+            Err(e) => {
+                let _ = self.remove(&active_key).await;
+                Err(e)
+            }
+        }
+    }
 
-        if let Err(e) = self.commit(key, &active_key, final_labels).await {
-            let _ = self.remove(&active_key).await;
-            return Err(e);
+    /// Commits an unpacked layer as `layer.key` on top of `parent`, or, if a
+    /// concurrent pull committed it first, discards it for the existing one.
+    /// Returns the layer's size.
+    async fn commit_layer(
+        &self,
+        layer: &Layer,
+        parent: Option<&str>,
+        unpacked: Option<(String, i64)>,
+    ) -> Result<i64, SnapshotterError> {
+        let Some((active_key, size)) = unpacked else {
+            return Ok(self.usage(&layer.key).await?.size);
+        };
+
+        if let Some(info) = self.snapshots.write().await.get_mut(&active_key) {
+            info.parent = parent.map(String::from);
+            info.labels.remove(EXTRACTING_LABEL);
+        }
+        let mut labels = layer.labels.clone();
+        labels.insert(LAYER_DIGEST_LABEL.to_string(), layer.digest.clone());
+
+        match self.commit(&layer.key, &active_key, labels).await {
+            Ok(()) => Ok(size),
+            Err(SnapshotterError::AlreadyExists(_)) => {
+                let _ = self.remove(&active_key).await;
+                Ok(self.usage(&layer.key).await?.size)
+            }
+            Err(e) => {
+                let _ = self.remove(&active_key).await;
+                Err(e)
+            }
         }
+    }
+}
 
-        Ok((key.to_string(), size))
+/// Chain IDs of an image's layers, given their diff IDs, to key the layers'
+/// snapshots by, as containerd does: the first layer's is its diff ID, each
+/// next one's is `sha256(parent chain ID + " " + diff ID)`. A layer shared by
+/// images on different bases thus gets a snapshot per base.
+pub fn chain_ids(diff_ids: &[String]) -> Vec<String> {
+    let mut chain: Vec<String> = Vec::with_capacity(diff_ids.len());
+    for diff_id in diff_ids {
+        let id = match chain.last() {
+            Some(parent) => format!(
+                "sha256:{}",
+                hex::encode(Sha256::digest(format!("{} {}", parent, diff_id)))
+            ),
+            None => diff_id.clone(),
+        };
+        chain.push(id);
     }
+    chain
 }
 
 fn sanitize_key(key: &str) -> String {
@@ -479,9 +912,54 @@ fn parse_digest(digest: &str) -> Result<ross_store::Digest, SnapshotterError> {
     })
 }
 
-fn extract_tar_gz(data: &[u8], target_dir: &Path) -> Result<i64, SnapshotterError> {
-    let decoder = GzDecoder::new(data);
-    let mut archive = Archive::new(decoder);
+/// How a layer blob is compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LayerCompression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl LayerCompression {
+    /// Reads the compression off a Docker or OCI layer media type. Blobs
+    /// stored without one are recognized by their magic number.
+    fn detect(media_type: &str, data: &[u8]) -> Result<Self, SnapshotterError> {
+        // OCI puts the compression after a `+`, Docker after a `.`.
+        if media_type.ends_with("+gzip") || media_type.ends_with(".tar.gzip") {
+            Ok(LayerCompression::Gzip)
+        } else if media_type.ends_with("+zstd") || media_type.ends_with(".tar.zstd") {
+            Ok(LayerCompression::Zstd)
+        } else if media_type.ends_with(".tar") {
+            Ok(LayerCompression::None)
+        } else if media_type.is_empty() || media_type == "application/octet-stream" {
+            Ok(match data {
+                [0x1f, 0x8b, ..] => LayerCompression::Gzip,
+                [0x28, 0xb5, 0x2f, 0xfd, ..] => LayerCompression::Zstd,
+                _ => LayerCompression::None,
+            })
+        } else {
+            Err(SnapshotterError::UnsupportedMediaType(
+                media_type.to_string(),
+            ))
+        }
+    }
+}
+
+fn extract_layer_tar(
+    data: impl Read,
+    compression: LayerCompression,
+    target_dir: &Path,
+) -> Result<i64, SnapshotterError> {
+    let reader: Box<dyn Read + '_> = match compression {
+        LayerCompression::None => Box::new(data),
+        LayerCompression::Gzip => Box::new(GzDecoder::new(data)),
+        LayerCompression::Zstd => {
+            Box::new(zstd::stream::read::Decoder::new(data).map_err(|e| {
+                SnapshotterError::ExtractionFailed(format!("failed to read zstd layer: {}", e))
+            })?)
+        }
+    };
+    let mut archive = Archive::new(reader);
     archive.set_overwrite(true);
 
     // On macOS, we can't preserve Linux-specific permissions/ownerships
@@ -493,6 +971,7 @@ fn extract_tar_gz(data: &[u8], target_dir: &Path) -> Result<i64, SnapshotterErro
     }
 
     let mut total_size = 0i64;
+    let root = target_dir.canonicalize()?;
 
     for entry in archive.entries().map_err(|e| {
         SnapshotterError::ExtractionFailed(format!("failed to read tar entries: {}", e))
@@ -507,32 +986,51 @@ fn extract_tar_gz(data: &[u8], target_dir: &Path) -> Result<i64, SnapshotterErro
                 SnapshotterError::ExtractionFailed(format!("failed to get entry path: {}", e))
             })?
             .into_owned();
+        let path = layer_path(&path)?;
+        if entry.header().entry_type().is_hard_link()
+            && let Some(target) = entry.link_name().map_err(|e| {
+                SnapshotterError::ExtractionFailed(format!("failed to get link target: {}", e))
+            })?
+        {
+            layer_path(&target)?;
+        }
 
-        // Handle whiteout files (OCI layer deletion markers)
+        // Whiteouts are stored as overlayfs reads them, as in
+        // `unpack_overlay_layer`, so that they hide what the layers below
+        // have once this one is a lowerdir. Elsewhere the markers are kept
+        // as they are, for the layers to be flattened.
+        #[cfg(target_os = "linux")]
         if let Some(name) = path.file_name() {
-            let name_str = name.to_string_lossy();
-            if name_str.starts_with(".wh.") {
-                let original_name = name_str.strip_prefix(".wh.").unwrap();
-                let whiteout_target = target_dir
-                    .join(path.parent().unwrap_or(Path::new("")))
-                    .join(original_name);
-                if whiteout_target.exists() {
-                    if whiteout_target.is_dir() {
-                        std::fs::remove_dir_all(&whiteout_target).map_err(|e| {
-                            SnapshotterError::ExtractionFailed(format!(
-                                "failed to remove whiteout target: {}",
-                                e
-                            ))
-                        })?;
-                    } else {
-                        std::fs::remove_file(&whiteout_target).map_err(|e| {
-                            SnapshotterError::ExtractionFailed(format!(
-                                "failed to remove whiteout target: {}",
-                                e
-                            ))
-                        })?;
-                    }
+            if name == OPAQUE_WHITEOUT {
+                let dir = resolve_in_layer(&root, &path)?;
+                let dir = dir.parent().unwrap_or(&root);
+                std::fs::create_dir_all(dir)?;
+                mark_opaque(dir).map_err(|e| {
+                    SnapshotterError::ExtractionFailed(format!(
+                        "failed to mark opaque directory: {}",
+                        e
+                    ))
+                })?;
+                continue;
+            }
+            if let Some(original) = name.as_bytes().strip_prefix(WHITEOUT_PREFIX) {
+                if matches!(original, b"" | b"." | b"..") {
+                    continue;
+                }
+                let original = std::ffi::OsStr::from_bytes(original);
+                let whiteout = resolve_in_layer(&root, &path.with_file_name(original))?;
+                if let Some(parent) = whiteout.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                // Only this layer's own entry can be there.
+                match std::fs::symlink_metadata(&whiteout) {
+                    Ok(meta) if meta.is_dir() => std::fs::remove_dir_all(&whiteout)?,
+                    Ok(_) => std::fs::remove_file(&whiteout)?,
+                    Err(_) => {}
                 }
+                make_whiteout(&whiteout).map_err(|e| {
+                    SnapshotterError::ExtractionFailed(format!("failed to create whiteout: {}", e))
+                })?;
                 continue;
             }
         }
@@ -547,13 +1045,44 @@ fn extract_tar_gz(data: &[u8], target_dir: &Path) -> Result<i64, SnapshotterErro
             }
         }
 
+        // The layer's root itself, `./` in most layers.
+        if path.as_os_str().is_empty() {
+            continue;
+        }
+        let dst = resolve_in_layer(&root, &path)?;
+        if let Some(parent) = dst.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
         total_size += entry.size() as i64;
 
+        // Hard links are made here: `tar` would take the target as is.
+        if entry.header().entry_type().is_hard_link() {
+            let target = entry.link_name()?.map(|target| layer_path(&target));
+            let target = match target {
+                Some(target) => resolve_in_layer(&root, &target?)?,
+                None => continue,
+            };
+            if let Err(e) = std::fs::remove_file(&dst)
+                && e.kind() != std::io::ErrorKind::NotFound
+            {
+                return Err(e.into());
+            }
+            std::fs::hard_link(&target, &dst).map_err(|e| {
+                SnapshotterError::ExtractionFailed(format!(
+                    "failed to link {} to {}: {}",
+                    path.display(),
+                    target.display(),
+                    e
+                ))
+            })?;
+            continue;
+        }
+
         // Try to unpack, but on macOS handle failures gracefully for special files
         #[cfg(target_os = "macos")]
         {
             let entry_type = entry.header().entry_type();
-            if let Err(e) = entry.unpack_in(target_dir) {
+            if let Err(e) = entry.unpack(&dst) {
                 // Only error for regular files/dirs, skip special files
                 if entry_type == tar::EntryType::Regular
                     || entry_type == tar::EntryType::Directory
@@ -571,7 +1100,7 @@ fn extract_tar_gz(data: &[u8], target_dir: &Path) -> Result<i64, SnapshotterErro
 
         #[cfg(not(target_os = "macos"))]
         {
-            entry.unpack_in(target_dir).map_err(|e| {
+            entry.unpack(&dst).map_err(|e| {
                 SnapshotterError::ExtractionFailed(format!("failed to unpack entry: {}", e))
             })?;
         }
@@ -580,9 +1109,358 @@ fn extract_tar_gz(data: &[u8], target_dir: &Path) -> Result<i64, SnapshotterErro
     Ok(total_size)
 }
 
+/// The path of a layer entry relative to the layer's root. Absolute paths
+/// and `..` are rejected rather than resolved: an untrusted image could
+/// otherwise write or delete files anywhere on the host.
+fn layer_path(path: &Path) -> Result<PathBuf, SnapshotterError> {
+    let mut relative = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                return Err(SnapshotterError::ExtractionFailed(format!(
+                    "layer entry {} is outside the layer",
+                    path.display()
+                )));
+            }
+        }
+    }
+    Ok(relative)
+}
+
+/// Most symlinks followed resolving one path, as with Linux's `ELOOP`.
+const MAX_SYMLINKS: usize = 40;
+
+/// Where `path`, relative to the canonical `root`, lands on the host. The
+/// symlinks unpacked so far on the way to its last component are followed
+/// as if `root` were `/`: `var/run -> /run` leads to `root/run`, and `..`
+/// stops at `root`, so no entry reaches outside the layer.
+fn resolve_in_layer(root: &Path, path: &Path) -> Result<PathBuf, SnapshotterError> {
+    let Some(name) = path.file_name() else {
+        return Ok(root.to_path_buf());
+    };
+
+    // Components still to resolve, the next one last; `None` is `..`.
+    let mut pending: Vec<Option<OsString>> = Vec::new();
+    let push_path = |pending: &mut Vec<Option<OsString>>, path: &Path| {
+        for component in path.components().rev() {
+            match component {
+                Component::Normal(part) => pending.push(Some(part.to_os_string())),
+                Component::ParentDir => pending.push(None),
+                Component::CurDir | Component::RootDir | Component::Prefix(_) => {}
+            }
+        }
+    };
+    push_path(&mut pending, path.parent().unwrap_or(Path::new("")));
+
+    let mut current = root.to_path_buf();
+    let mut followed = 0;
+    while let Some(part) = pending.pop() {
+        let Some(part) = part else {
+            if current != root {
+                current.pop();
+            }
+            continue;
+        };
+        let next = current.join(&part);
+        match std::fs::symlink_metadata(&next) {
+            Ok(meta) if meta.file_type().is_symlink() => {
+                followed += 1;
+                if followed > MAX_SYMLINKS {
+                    return Err(SnapshotterError::ExtractionFailed(format!(
+                        "too many symlinks on the way to layer entry {}",
+                        path.display()
+                    )));
+                }
+                let target = std::fs::read_link(&next)?;
+                if target.has_root() {
+                    current = root.to_path_buf();
+                }
+                push_path(&mut pending, &target);
+            }
+            // What is missing is created as plain directories.
+            _ => current = next,
+        }
+    }
+    Ok(current.join(name))
+}
+
+/// Whether `meta` is an overlayfs whiteout: a character device numbered 0/0.
+fn is_whiteout(meta: &std::fs::Metadata) -> bool {
+    meta.file_type().is_char_device() && meta.rdev() == 0
+}
+
+fn c_path(path: &Path) -> std::io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))
+}
+
+#[cfg(target_os = "linux")]
+fn is_opaque_dir(path: &Path) -> bool {
+    let Ok(path) = c_path(path) else {
+        return false;
+    };
+    OVERLAY_OPAQUE_XATTRS.iter().any(|name| {
+        let mut value = [0u8; 1];
+        let len = unsafe {
+            libc::lgetxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                value.as_mut_ptr().cast(),
+                value.len(),
+            )
+        };
+        len == 1 && value[0] == b'y'
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_opaque_dir(_path: &Path) -> bool {
+    false
+}
+
+#[cfg(target_os = "linux")]
+fn mark_opaque(path: &Path) -> std::io::Result<()> {
+    let path = c_path(path)?;
+    let ret = unsafe {
+        libc::lsetxattr(
+            path.as_ptr(),
+            OVERLAY_OPAQUE_XATTRS[0].as_ptr(),
+            b"y".as_ptr().cast(),
+            1,
+            0,
+        )
+    };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn mark_opaque(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+fn make_whiteout(path: &Path) -> std::io::Result<()> {
+    let path = c_path(path)?;
+    if unsafe { libc::mknod(path.as_ptr(), libc::S_IFCHR, 0) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Archives an overlay upperdir as a gzipped OCI layer at `path`, returning
+/// the digest of the uncompressed tar (its diff ID).
+fn write_layer(upper: &Path, path: &Path) -> Result<String, SnapshotterError> {
+    let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    let mut tar = HashingWriter {
+        inner: GzEncoder::new(file, Compression::default()),
+        hasher: Sha256::new(),
+    };
+
+    let mut builder = tar::Builder::new(&mut tar);
+    builder.follow_symlinks(false);
+    append_layer_dir(&mut builder, upper, Path::new(""))?;
+    builder.finish()?;
+    drop(builder);
+
+    let diff_id = format!("sha256:{}", hex::encode(tar.hasher.finalize()));
+    tar.inner
+        .finish()?
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .sync_all()?;
+    Ok(diff_id)
+}
+
+/// Hashes what is written through it.
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn append_layer_dir<W: Write>(
+    builder: &mut tar::Builder<W>,
+    upper: &Path,
+    rel: &Path,
+) -> std::io::Result<()> {
+    // Sorted so committing the same changes yields the same digest.
+    let mut entries = std::fs::read_dir(upper.join(rel))?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let name = entry.file_name();
+        let path = entry.path();
+        let rel_path = rel.join(&name);
+        let meta = std::fs::symlink_metadata(&path)?;
+
+        if is_whiteout(&meta) {
+            let mut whiteout = OsString::from(".wh.");
+            whiteout.push(&name);
+            append_whiteout(builder, &rel.join(whiteout))?;
+            continue;
+        }
+
+        builder.append_path_with_name(&path, &rel_path)?;
+
+        if meta.is_dir() {
+            if is_opaque_dir(&path) {
+                append_whiteout(builder, &rel_path.join(OPAQUE_WHITEOUT))?;
+            }
+            append_layer_dir(builder, upper, &rel_path)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn append_whiteout<W: Write>(builder: &mut tar::Builder<W>, path: &Path) -> std::io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Regular);
+    header.set_size(0);
+    header.set_mode(0o644);
+    header.set_mtime(0);
+    builder.append_data(&mut header, path, std::io::empty())
+}
+
+/// Unpacks an uncompressed OCI layer into `target_dir` so it can serve as an
+/// overlay lowerdir: whiteout files become overlayfs whiteouts instead of
+/// being applied.
+fn unpack_overlay_layer(data: impl Read, target_dir: &Path) -> Result<(), SnapshotterError> {
+    let mut archive = Archive::new(data);
+    archive.set_overwrite(true);
+
+    #[cfg(not(target_os = "macos"))]
+    {
+        archive.set_preserve_permissions(true);
+        archive.set_preserve_ownerships(true);
+        archive.set_unpack_xattrs(true);
+    }
+
+    let failed = |what: &str, e: std::io::Error| {
+        SnapshotterError::ExtractionFailed(format!("failed to {}: {}", what, e))
+    };
+
+    for entry in archive
+        .entries()
+        .map_err(|e| failed("read tar entries", e))?
+    {
+        let mut entry = entry.map_err(|e| failed("read tar entry", e))?;
+        let path = entry
+            .path()
+            .map_err(|e| failed("get entry path", e))?
+            .into_owned();
+        let parent = target_dir.join(path.parent().unwrap_or(Path::new("")));
+
+        if let Some(name) = path.file_name() {
+            if name == OPAQUE_WHITEOUT {
+                mark_opaque(&parent).map_err(|e| failed("mark opaque directory", e))?;
+                continue;
+            }
+            if let Some(original) = name.as_bytes().strip_prefix(WHITEOUT_PREFIX) {
+                let original = std::ffi::OsStr::from_bytes(original);
+                make_whiteout(&parent.join(original)).map_err(|e| failed("create whiteout", e))?;
+                continue;
+            }
+        }
+
+        entry
+            .unpack_in(target_dir)
+            .map_err(|e| failed("unpack entry", e))?;
+    }
+
+    Ok(())
+}
+
+/// Appends the changes under `root/rel` to `changes`, looking paths up in
+/// `lowers` (nearest first) to tell additions from modifications.
+fn collect_changes(
+    root: &Path,
+    rel: &Path,
+    lowers: &[PathBuf],
+    changes: &mut Vec<Change>,
+) -> std::io::Result<()> {
+    let mut entries = std::fs::read_dir(root.join(rel))?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let name = entry.file_name();
+        if name == OPAQUE_WHITEOUT {
+            continue;
+        }
+
+        let path = entry.path();
+        let meta = std::fs::symlink_metadata(&path)?;
+
+        let deleted = if is_whiteout(&meta) {
+            Some(name.clone())
+        } else {
+            name.as_bytes()
+                .strip_prefix(WHITEOUT_PREFIX)
+                .map(|original| std::ffi::OsStr::from_bytes(original).to_os_string())
+        };
+        if let Some(original) = deleted {
+            changes.push(Change {
+                path: absolute(&rel.join(original)),
+                kind: ChangeKind::Deleted,
+            });
+            continue;
+        }
+
+        let rel_path = rel.join(&name);
+        let kind = if exists_in_lower(lowers, &rel_path) {
+            ChangeKind::Modified
+        } else {
+            ChangeKind::Added
+        };
+        changes.push(Change {
+            path: absolute(&rel_path),
+            kind,
+        });
+
+        if meta.is_dir() {
+            // Nothing below an opaque directory shows through from the lowers.
+            let lowers: &[PathBuf] = if is_opaque_dir(&path) { &[] } else { lowers };
+            collect_changes(root, &rel_path, lowers, changes)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn exists_in_lower(lowers: &[PathBuf], rel: &Path) -> bool {
+    for lower in lowers {
+        if let Ok(meta) = std::fs::symlink_metadata(lower.join(rel)) {
+            return !is_whiteout(&meta);
+        }
+    }
+    false
+}
+
+fn absolute(rel: &Path) -> String {
+    Path::new("/").join(rel).to_string_lossy().into_owned()
+}
+
+/// Sums the on-disk usage (allocated blocks) and inode count of everything
+/// under `dir`. Hardlinked files are counted once.
 async fn calculate_dir_usage(dir: &Path) -> Result<(i64, i64), SnapshotterError> {
     let mut size = 0i64;
     let mut inodes = 0i64;
+    let mut seen_links = HashSet::new();
 
     if !dir.exists() {
         return Ok((0, 0));
@@ -593,13 +1471,16 @@ async fn calculate_dir_usage(dir: &Path) -> Result<(i64, i64), SnapshotterError>
     while let Some(current) = stack.pop() {
         let mut entries = fs::read_dir(&current).await?;
         while let Some(entry) = entries.next_entry().await? {
-            inodes += 1;
+            // Does not follow symlinks.
             let metadata = entry.metadata().await?;
             if metadata.is_dir() {
                 stack.push(entry.path());
-            } else {
-                size += metadata.len() as i64;
+            } else if metadata.nlink() > 1 && !seen_links.insert((metadata.dev(), metadata.ino())) {
+                continue;
             }
+
+            inodes += 1;
+            size += metadata.blocks() as i64 * 512;
         }
     }
 
@@ -682,6 +1563,49 @@ mod tests {
         assert!(lowerdir.contains("layer1"));
     }
 
+    #[tokio::test]
+    async fn test_overlay_options_escape_paths() {
+        let snap_dir = tempfile::Builder::new()
+            .prefix("snap:shots,a=b")
+            .tempdir()
+            .unwrap();
+        let store_dir = TempDir::new().unwrap();
+        let store = Arc::new(FileSystemStore::new(store_dir.path()).await.unwrap());
+        let snapshotter = OverlaySnapshotter::new(snap_dir.path(), store)
+            .await
+            .unwrap();
+
+        for (key, parent) in [("layer1", None), ("layer2", Some("layer1"))] {
+            let active = format!("{}-active", key);
+            snapshotter
+                .prepare(&active, parent, HashMap::new())
+                .await
+                .unwrap();
+            snapshotter
+                .commit(key, &active, HashMap::new())
+                .await
+                .unwrap();
+        }
+        let mounts = snapshotter
+            .prepare("container", Some("layer2"), HashMap::new())
+            .await
+            .unwrap();
+
+        let fs_dir = |key: &str| snapshotter.fs_dir(key).to_string_lossy().to_string();
+        let options = &mounts[0].options;
+        let lowerdir = options[0].strip_prefix("lowerdir=").unwrap();
+        assert_eq!(
+            ross_mount::split_lowerdir(lowerdir),
+            vec![fs_dir("layer2"), fs_dir("layer1")]
+        );
+        let upperdir = options[1].strip_prefix("upperdir=").unwrap();
+        assert!(upperdir.contains("snap\\:shots\\,a=b"));
+        assert_eq!(
+            ross_mount::unescape_overlay_path(upperdir),
+            fs_dir("container")
+        );
+    }
+
     #[tokio::test]
     async fn test_remove_with_dependents() {
         let (snapshotter, _snap_dir, _store_dir) = create_test_snapshotter().await;
@@ -704,6 +1628,36 @@ mod tests {
         assert!(matches!(result, Err(SnapshotterError::HasDependents(_))));
     }
 
+    #[tokio::test]
+    async fn test_prune_keeps_ancestors() {
+        let (snapshotter, _snap_dir, _store_dir) = create_test_snapshotter().await;
+
+        for (key, parent) in [("base", None), ("top", Some("base")), ("other", None)] {
+            let active = format!("{}-active", key);
+            snapshotter
+                .prepare(&active, parent, HashMap::new())
+                .await
+                .unwrap();
+            snapshotter
+                .commit(key, &active, HashMap::new())
+                .await
+                .unwrap();
+        }
+        snapshotter
+            .prepare("container", Some("other"), HashMap::new())
+            .await
+            .unwrap();
+
+        let keep = HashSet::from(["top".to_string()]);
+        let (mut removed, _) = snapshotter.prune(&keep).await.unwrap();
+        removed.sort();
+
+        assert_eq!(removed, vec!["container".to_string(), "other".to_string()]);
+        assert!(snapshotter.stat("base").await.is_ok());
+        assert!(snapshotter.stat("top").await.is_ok());
+        assert!(!snapshotter.snapshot_dir("other").exists());
+    }
+
     #[tokio::test]
     async fn test_view() {
         let (snapshotter, _snap_dir, _store_dir) = create_test_snapshotter().await;
@@ -727,4 +1681,433 @@ mod tests {
 
         assert!(!mounts.is_empty());
     }
+
+    #[tokio::test]
+    async fn test_commit_diff_keeps_active_and_stores_layer() {
+        let (snapshotter, _snap_dir, _store_dir) = create_test_snapshotter().await;
+
+        snapshotter
+            .prepare("base-active", None, HashMap::new())
+            .await
+            .unwrap();
+        let base = snapshotter.fs_dir("base-active");
+        std::fs::create_dir_all(base.join("etc")).unwrap();
+        std::fs::write(base.join("etc/config"), "old").unwrap();
+        snapshotter
+            .commit("base", "base-active", HashMap::new())
+            .await
+            .unwrap();
+
+        snapshotter
+            .prepare("container", Some("base"), HashMap::new())
+            .await
+            .unwrap();
+        let upper = snapshotter.fs_dir("container");
+        std::fs::create_dir_all(upper.join("etc")).unwrap();
+        std::fs::write(upper.join("etc/config"), "new").unwrap();
+        std::fs::create_dir_all(upper.join("data")).unwrap();
+        std::fs::write(upper.join("data/file"), "hello").unwrap();
+
+        let info = snapshotter
+            .commit_diff("container", "saved", HashMap::new())
+            .await
+            .unwrap();
+        assert_eq!(info.kind, SnapshotKind::Committed);
+        assert_eq!(info.parent.as_deref(), Some("base"));
+        assert_eq!(
+            snapshotter.stat("container").await.unwrap().kind,
+            SnapshotKind::Active
+        );
+
+        let digest = parse_digest(&info.labels[LAYER_DIGEST_LABEL]).unwrap();
+        let blob = snapshotter.store.get_blob(&digest, 0, -1).await.unwrap();
+        let mut archive = Archive::new(GzDecoder::new(blob.as_slice()));
+        let mut paths: Vec<String> = archive
+            .entries()
+            .unwrap()
+            .map(|e| {
+                let path = e.unwrap().path().unwrap().to_string_lossy().into_owned();
+                path.trim_end_matches('/').to_string()
+            })
+            .collect();
+        paths.sort();
+        assert_eq!(paths, vec!["data", "data/file", "etc", "etc/config"]);
+
+        let mut tar = Vec::new();
+        GzDecoder::new(blob.as_slice())
+            .read_to_end(&mut tar)
+            .unwrap();
+        assert_eq!(
+            info.labels[UNCOMPRESSED_LABEL],
+            format!("sha256:{}", hex::encode(Sha256::digest(&tar)))
+        );
+        // Nothing is left of the staging directory the layer was built in.
+        let dirs: Vec<_> = std::fs::read_dir(snapshotter.root.join(SNAPSHOTS_DIR))
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.contains("commit"))
+            .collect();
+        assert!(dirs.is_empty(), "{:?}", dirs);
+        assert!(
+            !snapshotter
+                .snapshot_dir("saved")
+                .join(STAGED_LAYER_FILE)
+                .exists()
+        );
+
+        let changes = snapshotter.diff("saved").await.unwrap();
+        let changes: Vec<(&str, ChangeKind)> =
+            changes.iter().map(|c| (c.path.as_str(), c.kind)).collect();
+        assert_eq!(
+            changes,
+            vec![
+                ("/data", ChangeKind::Added),
+                ("/data/file", ChangeKind::Added),
+                ("/etc", ChangeKind::Modified),
+                ("/etc/config", ChangeKind::Modified),
+            ]
+        );
+        assert_eq!(
+            std::fs::read_to_string(snapshotter.fs_dir("saved").join("etc/config")).unwrap(),
+            "new"
+        );
+    }
+
+    /// An uncompressed layer of `(path, symlink target)` entries, files when
+    /// there is no target. Headers are filled in by hand since `tar::Builder`
+    /// refuses the paths some tests need.
+    fn layer_tar(entries: &[(&str, Option<&str>)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, target) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+            let data: &[u8] = match target {
+                Some(target) => {
+                    header.as_old_mut().linkname[..target.len()].copy_from_slice(target.as_bytes());
+                    header.set_entry_type(tar::EntryType::Symlink);
+                    b""
+                }
+                None => {
+                    header.set_entry_type(tar::EntryType::Regular);
+                    path.as_bytes()
+                }
+            };
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_uid(0);
+            header.set_gid(0);
+            header.set_mtime(0);
+            header.set_cksum();
+            builder.append(&header, data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    /// A gzipped [`layer_tar`].
+    fn layer(entries: &[(&str, Option<&str>)]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&layer_tar(entries)).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    async fn put_layer(snapshotter: &OverlaySnapshotter, blob: &[u8]) -> String {
+        let (digest, _) = snapshotter
+            .store
+            .put_blob(LAYER_MEDIA_TYPE, blob, None)
+            .await
+            .unwrap();
+        format!("{}:{}", digest.algorithm, digest.hash)
+    }
+
+    #[tokio::test]
+    async fn test_extract_layer_shared_by_concurrent_pulls() {
+        let (snapshotter, _snap_dir, _store_dir) = create_test_snapshotter().await;
+        let digest = put_layer(&snapshotter, &layer(&[("etc/hostname", None)])).await;
+
+        let (first, second) = tokio::join!(
+            snapshotter.extract_layer(&digest, None, &digest, HashMap::new()),
+            snapshotter.extract_layer(&digest, None, &digest, HashMap::new()),
+        );
+        assert_eq!(first.unwrap().0, digest);
+        assert_eq!(second.unwrap().0, digest);
+
+        let snapshots = snapshotter.list(None).await.unwrap();
+        assert_eq!(snapshots.len(), 1);
+        assert_eq!(snapshots[0].kind, SnapshotKind::Committed);
+        assert_eq!(
+            std::fs::read_to_string(snapshotter.fs_dir(&digest).join("etc/hostname")).unwrap(),
+            "etc/hostname"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_extract_layer_by_media_type() {
+        let (snapshotter, _snap_dir, _store_dir) = create_test_snapshotter().await;
+        let zstd = |path| zstd::encode_all(layer_tar(&[(path, None)]).as_slice(), 0).unwrap();
+
+        let blobs = [
+            (
+                "application/vnd.oci.image.layer.v1.tar+zstd",
+                "etc/zstd",
+                zstd("etc/zstd"),
+            ),
+            (
+                "application/vnd.oci.image.layer.v1.tar",
+                "etc/tar",
+                layer_tar(&[("etc/tar", None)]),
+            ),
+            // Stored without a media type, told apart by its magic number.
+            (
+                "application/octet-stream",
+                "etc/sniffed",
+                zstd("etc/sniffed"),
+            ),
+        ];
+        for (media_type, path, blob) in blobs {
+            let (digest, _) = snapshotter
+                .store
+                .put_blob(media_type, &blob, None)
+                .await
+                .unwrap();
+            let digest = format!("{}:{}", digest.algorithm, digest.hash);
+            snapshotter
+                .extract_layer(&digest, None, &digest, HashMap::new())
+                .await
+                .unwrap();
+            assert_eq!(
+                std::fs::read_to_string(snapshotter.fs_dir(&digest).join(path)).unwrap(),
+                path
+            );
+        }
+
+        // The descriptor's media type is the one that counts.
+        let digest = put_layer(&snapshotter, &layer(&[("etc/os-release", None)])).await;
+        let encrypted = Layer {
+            digest: digest.clone(),
+            media_type: "application/vnd.oci.image.layer.v1.tar+gzip+encrypted".to_string(),
+            key: digest,
+            labels: HashMap::new(),
+        };
+        let result = snapshotter
+            .extract_layers(None, vec![encrypted], |_, _| {})
+            .await;
+        assert!(matches!(
+            result,
+            Err(SnapshotterError::UnsupportedMediaType(_))
+        ));
+        assert_eq!(snapshotter.list(None).await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_extract_layers_commits_in_order() {
+        let (snapshotter, _snap_dir, _store_dir) = create_test_snapshotter().await;
+        let snapshotter = snapshotter.with_max_concurrent_unpacks(2);
+
+        let mut layers = Vec::new();
+        for name in ["base", "lib", "app", "config"] {
+            let digest = put_layer(&snapshotter, &layer(&[(name, None)])).await;
+            layers.push(Layer {
+                digest: digest.clone(),
+                media_type: LAYER_MEDIA_TYPE.to_string(),
+                key: digest,
+                labels: HashMap::new(),
+            });
+        }
+        let keys: Vec<String> = layers.iter().map(|l| l.key.clone()).collect();
+        // The second layer is already there, shared with another image.
+        snapshotter
+            .extract_layer(&keys[0], None, &keys[0], HashMap::new())
+            .await
+            .unwrap();
+        snapshotter
+            .extract_layer(&keys[1], Some(&keys[0]), &keys[1], HashMap::new())
+            .await
+            .unwrap();
+
+        let mut committed = Vec::new();
+        let extracted = snapshotter
+            .extract_layers(None, layers, |index, _| committed.push(index))
+            .await
+            .unwrap();
+
+        assert_eq!(committed, vec![0, 1, 2, 3]);
+        assert_eq!(
+            extracted
+                .iter()
+                .map(|(key, _)| key.clone())
+                .collect::<Vec<_>>(),
+            keys
+        );
+        for (i, key) in keys.iter().enumerate().skip(1) {
+            let info = snapshotter.stat(key).await.unwrap();
+            assert_eq!(info.kind, SnapshotKind::Committed);
+            assert_eq!(info.parent.as_deref(), Some(keys[i - 1].as_str()));
+            assert!(!info.labels.contains_key(EXTRACTING_LABEL));
+        }
+        assert_eq!(snapshotter.list(None).await.unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_extract_layer_rejects_entries_outside_the_layer() {
+        let (snapshotter, snap_dir, _store_dir) = create_test_snapshotter().await;
+        let outside = TempDir::new().unwrap();
+        let victim = outside.path().join("victim");
+        std::fs::write(&victim, "keep").unwrap();
+        let outside = outside.path().to_str().unwrap();
+
+        let escaping = [
+            vec![("../escape", None)],
+            vec![("etc/../../escape", None)],
+            vec![("/escape", None)],
+        ];
+        for entries in escaping {
+            let digest = put_layer(&snapshotter, &layer(&entries)).await;
+            let result = snapshotter
+                .extract_layer(&digest, None, &digest, HashMap::new())
+                .await;
+            assert!(
+                matches!(result, Err(SnapshotterError::ExtractionFailed(_))),
+                "{:?}",
+                entries
+            );
+        }
+
+        assert!(snapshotter.list(None).await.unwrap().is_empty());
+        assert_eq!(std::fs::read_to_string(&victim).unwrap(), "keep");
+        assert!(!Path::new(outside).join("escape").exists());
+        assert!(!snap_dir.path().join("escape").exists());
+        assert!(!snap_dir.path().join(SNAPSHOTS_DIR).join("escape").exists());
+
+        // Symlinks out of the layer lead back into it, as if its root were
+        // `/`, so entries through them stay inside.
+        let through_symlinks = [
+            (
+                vec![("var/run", Some("/run")), ("var/run/lock", None)],
+                "run/lock".to_string(),
+            ),
+            (
+                vec![("link", Some(outside)), ("link/escape", None)],
+                format!("{}/escape", outside),
+            ),
+            (
+                vec![("link", Some(outside)), ("link/.wh.victim", None)],
+                String::new(),
+            ),
+            (
+                vec![("link", Some("../../..")), ("link/escape", None)],
+                "escape".to_string(),
+            ),
+        ];
+        for (entries, landed) in through_symlinks {
+            let digest = put_layer(&snapshotter, &layer(&entries)).await;
+            snapshotter
+                .extract_layer(&digest, None, &digest, HashMap::new())
+                .await
+                .unwrap();
+            if !landed.is_empty() {
+                let landed = landed.trim_start_matches('/');
+                assert!(
+                    snapshotter.fs_dir(&digest).join(landed).is_file(),
+                    "{:?}",
+                    entries
+                );
+            }
+        }
+        assert_eq!(std::fs::read_to_string(&victim).unwrap(), "keep");
+        assert!(!Path::new(outside).join("escape").exists());
+        assert!(!snap_dir.path().join("escape").exists());
+
+        // Symlinks that stay inside the layer are followed.
+        let digest = put_layer(
+            &snapshotter,
+            &layer(&[
+                ("usr/lib/.keep", None),
+                ("lib", Some("usr/lib")),
+                ("lib/libc.so", None),
+            ]),
+        )
+        .await;
+        snapshotter
+            .extract_layer(&digest, None, &digest, HashMap::new())
+            .await
+            .unwrap();
+        assert!(snapshotter.fs_dir(&digest).join("usr/lib/libc.so").exists());
+    }
+
+    #[tokio::test]
+    async fn test_extract_layer_hard_links() {
+        let (snapshotter, _snap_dir, _store_dir) = create_test_snapshotter().await;
+        let mut tar = layer_tar(&[("bin/busybox", None), ("usr", Some("/bin"))]);
+        // A hard link through the symlink, and one to the file through it.
+        tar.truncate(tar.len() - 1024);
+        let mut builder = tar::Builder::new(tar);
+        for (path, target) in [("bin/sh", "usr/busybox"), ("usr/ls", "bin/busybox")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(tar::EntryType::Link);
+            header.set_size(0);
+            header.set_mode(0o755);
+            header.set_uid(0);
+            header.set_gid(0);
+            header.set_mtime(0);
+            builder.append_link(&mut header, path, target).unwrap();
+        }
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&builder.into_inner().unwrap()).unwrap();
+        let digest = put_layer(&snapshotter, &encoder.finish().unwrap()).await;
+
+        snapshotter
+            .extract_layer(&digest, None, &digest, HashMap::new())
+            .await
+            .unwrap();
+        let dir = snapshotter.fs_dir(&digest);
+        let inode = |path: &str| std::fs::symlink_metadata(dir.join(path)).unwrap().ino();
+        assert_eq!(inode("bin/sh"), inode("bin/busybox"));
+        assert_eq!(inode("bin/ls"), inode("bin/busybox"));
+    }
+
+    #[tokio::test]
+    async fn test_usage_counts_blocks_and_hardlinks_once() {
+        let (snapshotter, _snap_dir, _store_dir) = create_test_snapshotter().await;
+
+        snapshotter
+            .prepare("layer", None, HashMap::new())
+            .await
+            .unwrap();
+        let dir = snapshotter.fs_dir("layer");
+        std::fs::write(dir.join("data"), vec![0xa5u8; 10000]).unwrap();
+        std::fs::hard_link(dir.join("data"), dir.join("link")).unwrap();
+        std::os::unix::fs::symlink("data", dir.join("sym")).unwrap();
+        std::fs::create_dir(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("sub/small"), "x").unwrap();
+
+        let expected: i64 = ["data", "sym", "sub", "sub/small"]
+            .iter()
+            .map(|p| std::fs::symlink_metadata(dir.join(p)).unwrap().blocks() as i64 * 512)
+            .sum();
+
+        let usage = snapshotter.usage("layer").await.unwrap();
+        assert_eq!(usage.inodes, 4);
+        assert_eq!(usage.size, expected);
+        assert!(usage.size >= 10000);
+    }
+
+    #[test]
+    fn test_chain_ids() {
+        let a = format!("sha256:{}", "a".repeat(64));
+        let b = format!("sha256:{}", "b".repeat(64));
+        let c = format!("sha256:{}", "c".repeat(64));
+
+        let chain = chain_ids(&[a.clone(), b.clone(), c.clone()]);
+        assert_eq!(chain.len(), 3);
+        assert_eq!(chain[0], a);
+        let expected = format!(
+            "sha256:{}",
+            hex::encode(Sha256::digest(format!("{} {}", a, b)))
+        );
+        assert_eq!(chain[1], expected);
+
+        // The same layer on another base is another snapshot.
+        assert_ne!(chain_ids(&[a, c.clone()])[1], chain_ids(&[b, c])[1]);
+        assert!(chain_ids(&[]).is_empty());
+    }
 }
@@ -28,6 +28,19 @@ pub struct SnapshotInfo {
     pub labels: HashMap<String, String>,
 }
 
+/// A layer blob to unpack into a snapshot.
+#[derive(Debug, Clone)]
+pub struct Layer {
+    /// Digest of the blob in the store.
+    pub digest: String,
+    /// Media type of the blob's descriptor, which says how it is
+    /// compressed; empty for the one the blob was stored with.
+    pub media_type: String,
+    /// Key of the committed snapshot.
+    pub key: String,
+    pub labels: HashMap<String, String>,
+}
+
 #[derive(Debug, Clone)]
 pub struct Mount {
     pub mount_type: String,
@@ -41,3 +54,18 @@ pub struct Usage {
     pub size: i64,
     pub inodes: i64,
 }
+
+/// How a path differs from the snapshot's parent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Modified,
+    Deleted,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    /// Absolute path inside the snapshot.
+    pub path: String,
+    pub kind: ChangeKind,
+}
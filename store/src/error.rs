@@ -17,6 +17,9 @@ pub enum StoreError {
     #[error("invalid digest format: {0}")]
     InvalidDigest(String),
 
+    #[error("range out of bounds: offset {offset}, length {length}, blob size {size}")]
+    OutOfRange { offset: i64, length: i64, size: i64 },
+
     #[error("io error: {0}")]
     Io(#[from] std::io::Error),
 
@@ -33,6 +36,7 @@ impl From<StoreError> for tonic::Status {
             StoreError::DigestMismatch { .. } | StoreError::InvalidDigest(_) => {
                 tonic::Status::invalid_argument(err.to_string())
             }
+            StoreError::OutOfRange { .. } => tonic::Status::out_of_range(err.to_string()),
             StoreError::Io(_) | StoreError::Serialization(_) => {
                 tonic::Status::internal(err.to_string())
             }
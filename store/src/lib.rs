@@ -11,4 +11,4 @@ pub use proto::store_service_client::StoreServiceClient;
 pub use proto::store_service_server::{StoreService, StoreServiceServer};
 pub use proto::*;
 pub use service::StoreServiceImpl;
-pub use storage::FileSystemStore;
+pub use storage::{BlobWriter, FileSystemStore};
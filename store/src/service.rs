@@ -70,33 +70,35 @@ impl StoreService for StoreServiceImpl {
     ) -> Result<Response<PutBlobResponse>, Status> {
         let mut stream = request.into_inner();
 
-        let mut media_type = String::new();
-        let mut expected_digest = None;
-        let mut data = Vec::new();
+        let mut writer = None;
 
         use tokio_stream::StreamExt;
         while let Some(req) = stream.next().await {
             let req = req?;
             match req.content {
                 Some(crate::put_blob_request::Content::Init(init)) => {
-                    media_type = init.media_type;
-                    expected_digest = init.expected_digest;
-                    if init.expected_size > 0 {
-                        data.reserve(init.expected_size as usize);
+                    if writer.is_some() {
+                        return Err(Status::invalid_argument("duplicate init message"));
                     }
+                    writer = Some(
+                        self.store
+                            .blob_writer(&init.media_type, init.expected_digest.as_ref())
+                            .await
+                            .map_err(Status::from)?,
+                    );
                 }
                 Some(crate::put_blob_request::Content::Data(chunk)) => {
-                    data.extend_from_slice(&chunk);
+                    let Some(writer) = writer.as_mut() else {
+                        return Err(Status::invalid_argument("data sent before init"));
+                    };
+                    writer.write(&chunk).await.map_err(Status::from)?;
                 }
                 None => {}
             }
         }
 
-        let (digest, size) = self
-            .store
-            .put_blob(&media_type, &data, expected_digest.as_ref())
-            .await
-            .map_err(Status::from)?;
+        let writer = writer.ok_or_else(|| Status::invalid_argument("init message required"))?;
+        let (digest, size) = writer.commit().await.map_err(Status::from)?;
 
         Ok(Response::new(PutBlobResponse {
             digest: Some(digest),
@@ -2,11 +2,16 @@ use crate::error::StoreError;
 use crate::{BlobInfo, Digest, ManifestInfo, TagInfo};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest as Sha2Digest, Sha256};
+use std::collections::HashSet;
 use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll, ready};
 use tokio::fs;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 const BLOBS_DIR: &str = "blobs";
+const INGEST_DIR: &str = "ingest";
 const MANIFESTS_DIR: &str = "manifests";
 const INDEXES_DIR: &str = "indexes";
 const TAGS_DIR: &str = "tags";
@@ -34,6 +39,26 @@ pub struct TagMetadata {
     pub updated_at: i64,
 }
 
+impl Digest {
+    /// Checks that `content` hashes to this digest.
+    pub fn verify(&self, content: &[u8]) -> Result<(), StoreError> {
+        if self.algorithm != "sha256" {
+            return Err(StoreError::InvalidDigest(format!(
+                "unsupported digest algorithm: {}",
+                self.algorithm
+            )));
+        }
+        let actual = hex::encode(Sha256::digest(content));
+        if actual != self.hash {
+            return Err(StoreError::DigestMismatch {
+                expected: format_digest(self),
+                actual: format!("sha256:{}", actual),
+            });
+        }
+        Ok(())
+    }
+}
+
 pub struct FileSystemStore {
     root: PathBuf,
 }
@@ -47,6 +72,10 @@ impl FileSystemStore {
         fs::create_dir_all(root.join(INDEXES_DIR)).await?;
         fs::create_dir_all(root.join(TAGS_DIR)).await?;
 
+        // Anything left in the ingest directory is from an interrupted write.
+        let _ = fs::remove_dir_all(root.join(INGEST_DIR)).await;
+        fs::create_dir_all(root.join(INGEST_DIR)).await?;
+
         Ok(Self { root })
     }
 
@@ -97,6 +126,9 @@ impl FileSystemStore {
         self.blob_path(digest).exists()
     }
 
+    /// Reads `length` bytes of a blob starting at `offset`, or everything
+    /// from `offset` to the end when `length` is 0 or negative. Ranges that
+    /// reach past the end of the blob are rejected rather than cut short.
     pub async fn get_blob(
         &self,
         digest: &Digest,
@@ -109,76 +141,96 @@ impl FileSystemStore {
         }
 
         let mut file = fs::File::open(&path).await?;
-        let metadata = file.metadata().await?;
-        let file_size = metadata.len() as i64;
+        let size = file.metadata().await?.len() as i64;
 
-        if offset > 0 {
-            use tokio::io::AsyncSeekExt;
-            file.seek(std::io::SeekFrom::Start(offset as u64)).await?;
+        let out_of_range = StoreError::OutOfRange {
+            offset,
+            length,
+            size,
+        };
+        if offset < 0 || offset > size {
+            return Err(out_of_range);
         }
-
         let read_len = if length <= 0 {
-            (file_size - offset) as usize
+            size - offset
+        } else if length <= size - offset {
+            length
         } else {
-            length as usize
+            return Err(out_of_range);
         };
 
-        let mut buf = vec![0u8; read_len];
+        if offset > 0 {
+            use tokio::io::AsyncSeekExt;
+            file.seek(std::io::SeekFrom::Start(offset as u64)).await?;
+        }
+
+        let mut buf = vec![0u8; read_len as usize];
         file.read_exact(&mut buf).await?;
 
         Ok(buf)
     }
 
-    pub async fn put_blob(
+    /// Opens a blob for reading, for callers that stream it rather than hold
+    /// it in memory. Returns the file and its size.
+    pub async fn open_blob(&self, digest: &Digest) -> Result<(fs::File, u64), StoreError> {
+        let path = self.blob_path(digest);
+        let file = match fs::File::open(&path).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(StoreError::BlobNotFound(format_digest(digest)));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let size = file.metadata().await?.len();
+        Ok((file, size))
+    }
+
+    /// Starts writing a blob. The content is hashed as it is written and only
+    /// lands in the store once [`BlobWriter::commit`] verifies it against
+    /// `expected_digest`.
+    pub async fn blob_writer(
         &self,
         media_type: &str,
-        data: &[u8],
         expected_digest: Option<&Digest>,
-    ) -> Result<(Digest, i64), StoreError> {
-        let mut hasher = Sha256::new();
-        hasher.update(data);
-        let hash = hex::encode(hasher.finalize());
-
-        let digest = Digest {
-            algorithm: "sha256".to_string(),
-            hash,
-        };
-
+    ) -> Result<BlobWriter<'_>, StoreError> {
         if let Some(expected) = expected_digest
-            && expected.algorithm == digest.algorithm
-            && expected.hash != digest.hash
+            && expected.algorithm != "sha256"
         {
-            return Err(StoreError::DigestMismatch {
-                expected: format_digest(expected),
-                actual: format_digest(&digest),
-            });
-        }
-
-        let blob_path = self.blob_path(&digest);
-        if let Some(parent) = blob_path.parent() {
-            fs::create_dir_all(parent).await?;
+            return Err(StoreError::InvalidDigest(format!(
+                "unsupported digest algorithm: {}",
+                expected.algorithm
+            )));
         }
 
-        let mut file = fs::File::create(&blob_path).await?;
-        file.write_all(data).await?;
-
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64;
+        static NEXT_INGEST: AtomicU64 = AtomicU64::new(0);
+        let temp_path = self.root.join(INGEST_DIR).join(format!(
+            "{}-{}",
+            std::process::id(),
+            NEXT_INGEST.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = fs::File::create(&temp_path).await?;
 
-        let meta = BlobMetadata {
+        Ok(BlobWriter {
+            store: self,
             media_type: media_type.to_string(),
-            size: data.len() as i64,
-            created_at: now,
-            accessed_at: now,
-        };
-
-        let meta_path = self.blob_meta_path(&digest);
-        let meta_json = serde_json::to_string(&meta)?;
-        fs::write(&meta_path, meta_json).await?;
+            expected: expected_digest.cloned(),
+            temp_path,
+            file,
+            hasher: Sha256::new(),
+            size: 0,
+            committed: false,
+        })
+    }
 
-        Ok((digest, data.len() as i64))
+    pub async fn put_blob(
+        &self,
+        media_type: &str,
+        data: &[u8],
+        expected_digest: Option<&Digest>,
+    ) -> Result<(Digest, i64), StoreError> {
+        let mut writer = self.blob_writer(media_type, expected_digest).await?;
+        writer.write(data).await?;
+        writer.commit().await
     }
 
     pub async fn stat_blob(&self, digest: &Digest) -> Result<Option<BlobInfo>, StoreError> {
@@ -572,52 +624,94 @@ impl FileSystemStore {
         Ok(tags)
     }
 
+    /// Deletes content that no tag reaches.
+    ///
+    /// Tags are followed through image indexes to manifests, and manifests to
+    /// their config and layer blobs; every blob outside that set is removed.
+    /// Blobs are stored once per digest, so a layer shared by several images
+    /// lives as long as any manifest referencing it.
+    /// Untagged manifests are only removed with `delete_untagged`, otherwise
+    /// they keep their blobs alive. Blobs of a pull that has not set its tag
+    /// yet are not reachable, so this must not run concurrently with a pull.
     pub async fn garbage_collect(
         &self,
         dry_run: bool,
         delete_untagged: bool,
     ) -> Result<(i64, i64, i64, Vec<Digest>), StoreError> {
-        let mut referenced_digests: std::collections::HashSet<String> =
-            std::collections::HashSet::new();
+        let mut live_manifests: HashSet<String> = HashSet::new();
 
         let tags_dir = self.root.join(TAGS_DIR);
-        if tags_dir.exists() {
-            let mut repo_entries = fs::read_dir(&tags_dir).await?;
-            while let Some(repo_entry) = repo_entries.next_entry().await? {
-                if !repo_entry.file_type().await?.is_dir() {
+        let mut dirs = vec![tags_dir];
+        while let Some(dir) = dirs.pop() {
+            let mut entries = match fs::read_dir(&dir).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            while let Some(entry) = entries.next_entry().await? {
+                if entry.file_type().await?.is_dir() {
+                    dirs.push(entry.path());
                     continue;
                 }
-                let mut tag_entries = fs::read_dir(repo_entry.path()).await?;
-                while let Some(tag_entry) = tag_entries.next_entry().await? {
-                    if let Ok(content) = fs::read_to_string(tag_entry.path()).await
-                        && let Ok(meta) = serde_json::from_str::<TagMetadata>(&content)
-                    {
-                        referenced_digests
-                            .insert(format!("{}:{}", meta.digest_algorithm, meta.digest_hash));
-                    }
+                let Ok(content) = fs::read_to_string(entry.path()).await else {
+                    continue;
+                };
+                let Ok(meta) = serde_json::from_str::<TagMetadata>(&content) else {
+                    continue;
+                };
+                let digest = Digest {
+                    algorithm: meta.digest_algorithm,
+                    hash: meta.digest_hash,
+                };
+
+                // An index is kept by its tag and keeps every manifest it lists.
+                if let Ok(index) = self.get_index(&digest).await {
+                    live_manifests.extend(referenced_digests(&index, "manifests"));
                 }
+                live_manifests.insert(format_digest(&digest));
             }
         }
 
         let mut removed_digests = Vec::new();
-        let blobs_removed = 0i64;
         let mut manifests_removed = 0i64;
         let mut bytes_freed = 0i64;
+        let mut live_blobs: HashSet<String> = HashSet::new();
 
-        if delete_untagged {
-            for manifest in self.list_manifests(None).await? {
-                if let Some(digest) = &manifest.digest {
-                    let key = format!("{}:{}", digest.algorithm, digest.hash);
-                    if !referenced_digests.contains(&key) {
-                        if !dry_run {
-                            self.delete_manifest(digest).await?;
-                        }
-                        bytes_freed += manifest.size;
-                        manifests_removed += 1;
-                        removed_digests.push(digest.clone());
-                    }
+        for manifest in self.list_manifests(None).await? {
+            let Some(digest) = &manifest.digest else {
+                continue;
+            };
+
+            if delete_untagged && !live_manifests.contains(&format_digest(digest)) {
+                if !dry_run {
+                    self.delete_manifest(digest).await?;
                 }
+                bytes_freed += manifest.size;
+                manifests_removed += 1;
+                removed_digests.push(digest.clone());
+                continue;
+            }
+
+            let (content, _) = self.get_manifest(digest).await?;
+            live_blobs.extend(referenced_digests(&content, "config"));
+            live_blobs.extend(referenced_digests(&content, "layers"));
+        }
+
+        let mut blobs_removed = 0i64;
+        for blob in self.list_blobs(None).await? {
+            let Some(digest) = &blob.digest else {
+                continue;
+            };
+            if live_blobs.contains(&format_digest(digest)) {
+                continue;
+            }
+
+            if !dry_run {
+                self.delete_blob(digest).await?;
             }
+            bytes_freed += blob.size;
+            blobs_removed += 1;
+            removed_digests.push(digest.clone());
         }
 
         Ok((
@@ -695,6 +789,331 @@ impl FileSystemStore {
     }
 }
 
+/// An in-progress blob write; see [`FileSystemStore::blob_writer`].
+///
+/// The partial file is removed if the writer is dropped without committing.
+pub struct BlobWriter<'a> {
+    store: &'a FileSystemStore,
+    media_type: String,
+    expected: Option<Digest>,
+    temp_path: PathBuf,
+    file: fs::File,
+    hasher: Sha256,
+    size: i64,
+    committed: bool,
+}
+
+impl BlobWriter<'_> {
+    pub async fn write(&mut self, data: &[u8]) -> Result<(), StoreError> {
+        self.file.write_all(data).await?;
+        self.hasher.update(data);
+        self.size += data.len() as i64;
+        Ok(())
+    }
+
+    /// Verifies the written content and moves it into the store.
+    pub async fn commit(mut self) -> Result<(Digest, i64), StoreError> {
+        self.file.flush().await?;
+        self.file.sync_all().await?;
+
+        let digest = Digest {
+            algorithm: "sha256".to_string(),
+            hash: hex::encode(std::mem::take(&mut self.hasher).finalize()),
+        };
+
+        if let Some(expected) = &self.expected
+            && expected.hash != digest.hash
+        {
+            return Err(StoreError::DigestMismatch {
+                expected: format_digest(expected),
+                actual: format_digest(&digest),
+            });
+        }
+
+        // Blobs are content-addressed: one stored under this digest, by an
+        // image sharing the layer, already holds the same bytes.
+        let blob_path = self.store.blob_path(&digest);
+        if blob_path.exists() && self.store.blob_meta_path(&digest).exists() {
+            return Ok((digest, self.size));
+        }
+        if let Some(parent) = blob_path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::rename(&self.temp_path, &blob_path).await?;
+        self.committed = true;
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        let meta = BlobMetadata {
+            media_type: self.media_type.clone(),
+            size: self.size,
+            created_at: now,
+            accessed_at: now,
+        };
+
+        let meta_path = self.store.blob_meta_path(&digest);
+        let meta_json = serde_json::to_string(&meta)?;
+        fs::write(&meta_path, meta_json).await?;
+
+        Ok((digest, self.size))
+    }
+}
+
+/// Lets a blob be streamed straight from a download; everything written is
+/// hashed and verified on [`BlobWriter::commit`] as with [`BlobWriter::write`].
+impl AsyncWrite for BlobWriter<'_> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = &mut *self;
+        let n = ready!(Pin::new(&mut this.file).poll_write(cx, buf))?;
+        this.hasher.update(&buf[..n]);
+        this.size += n as i64;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.file).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.file).poll_shutdown(cx)
+    }
+}
+
+impl Drop for BlobWriter<'_> {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = std::fs::remove_file(&self.temp_path);
+        }
+    }
+}
+
 fn format_digest(digest: &Digest) -> String {
     format!("{}:{}", digest.algorithm, digest.hash)
 }
+
+/// Returns the descriptor digests found under `field` of an OCI manifest or
+/// index, which is either a single descriptor or a list of them.
+fn referenced_digests(content: &[u8], field: &str) -> Vec<String> {
+    let Ok(value) = serde_json::from_slice::<serde_json::Value>(content) else {
+        return Vec::new();
+    };
+
+    let descriptors = match &value[field] {
+        serde_json::Value::Array(items) => items.iter().collect(),
+        descriptor @ serde_json::Value::Object(_) => vec![descriptor],
+        _ => Vec::new(),
+    };
+
+    descriptors
+        .into_iter()
+        .filter_map(|d| d["digest"].as_str().map(String::from))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sha256_digest(data: &[u8]) -> Digest {
+        Digest {
+            algorithm: "sha256".to_string(),
+            hash: hex::encode(Sha256::digest(data)),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_put_blob_verifies_digest() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileSystemStore::new(dir.path()).await.unwrap();
+
+        let expected = sha256_digest(b"hello world");
+        let (digest, size) = store
+            .put_blob("application/octet-stream", b"hello world", Some(&expected))
+            .await
+            .unwrap();
+        assert_eq!(digest.hash, expected.hash);
+        assert_eq!(size, 11);
+        assert!(store.has_blob(&digest).await);
+
+        let truncated = store
+            .put_blob("application/octet-stream", b"hello", Some(&expected))
+            .await;
+        assert!(matches!(truncated, Err(StoreError::DigestMismatch { .. })));
+        assert!(!store.has_blob(&sha256_digest(b"hello")).await);
+
+        let mut ingest = std::fs::read_dir(dir.path().join(INGEST_DIR)).unwrap();
+        assert!(ingest.next().is_none());
+    }
+
+    #[test]
+    fn test_digest_verify() {
+        let digest = sha256_digest(b"{}");
+        assert!(digest.verify(b"{}").is_ok());
+        assert!(matches!(
+            digest.verify(b"{ }"),
+            Err(StoreError::DigestMismatch { .. })
+        ));
+
+        let sha512 = Digest {
+            algorithm: "sha512".to_string(),
+            hash: digest.hash.clone(),
+        };
+        assert!(matches!(
+            sha512.verify(b"{}"),
+            Err(StoreError::InvalidDigest(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_garbage_collect_removes_unreferenced_blobs() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileSystemStore::new(dir.path()).await.unwrap();
+
+        let (config, _) = store.put_blob("config", b"{}", None).await.unwrap();
+        let (layer, _) = store.put_blob("layer", b"layer", None).await.unwrap();
+        let (orphan, _) = store.put_blob("layer", b"orphan", None).await.unwrap();
+
+        let manifest = format!(
+            r#"{{"config":{{"digest":"{}"}},"layers":[{{"digest":"{}"}}]}}"#,
+            format_digest(&config),
+            format_digest(&layer)
+        );
+        let (manifest, _) = store
+            .put_manifest(
+                manifest.as_bytes(),
+                "application/vnd.oci.image.manifest.v1+json",
+            )
+            .await
+            .unwrap();
+        store
+            .set_tag("library/alpine", "latest", &manifest)
+            .await
+            .unwrap();
+
+        let (blobs_removed, manifests_removed, bytes_freed, removed) =
+            store.garbage_collect(false, true).await.unwrap();
+
+        assert_eq!((blobs_removed, manifests_removed, bytes_freed), (1, 0, 6));
+        assert_eq!(removed[0].hash, orphan.hash);
+        assert!(store.has_blob(&config).await);
+        assert!(store.has_blob(&layer).await);
+        assert!(!store.has_blob(&orphan).await);
+    }
+
+    #[tokio::test]
+    async fn test_shared_layer_outlives_one_of_its_images() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileSystemStore::new(dir.path()).await.unwrap();
+
+        let (base, _) = store.put_blob("layer", b"base", None).await.unwrap();
+        let mut images = Vec::new();
+        for name in ["app", "web"] {
+            let (config, _) = store
+                .put_blob("config", name.as_bytes(), None)
+                .await
+                .unwrap();
+            let (top, _) = store
+                .put_blob("layer", format!("{} layer", name).as_bytes(), None)
+                .await
+                .unwrap();
+            // Pulling the second image stores the shared layer again.
+            let (digest, _) = store.put_blob("layer", b"base", None).await.unwrap();
+            assert_eq!(digest.hash, base.hash);
+            let manifest = format!(
+                r#"{{"config":{{"digest":"{}"}},"layers":[{{"digest":"{}"}},{{"digest":"{}"}}]}}"#,
+                format_digest(&config),
+                format_digest(&base),
+                format_digest(&top)
+            );
+            let (manifest, _) = store
+                .put_manifest(
+                    manifest.as_bytes(),
+                    "application/vnd.oci.image.manifest.v1+json",
+                )
+                .await
+                .unwrap();
+            store.set_tag(name, "latest", &manifest).await.unwrap();
+            images.push((config, top));
+        }
+
+        assert_eq!(store.list_blobs(None).await.unwrap().len(), 5);
+
+        store.delete_tag("app", "latest").await.unwrap();
+        let (blobs_removed, manifests_removed, _, _) =
+            store.garbage_collect(false, true).await.unwrap();
+
+        assert_eq!((blobs_removed, manifests_removed), (2, 1));
+        assert!(store.has_blob(&base).await);
+        assert!(!store.has_blob(&images[0].1).await);
+        assert!(store.has_blob(&images[1].1).await);
+    }
+
+    #[tokio::test]
+    async fn test_blob_writer_streams_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileSystemStore::new(dir.path()).await.unwrap();
+
+        let expected = sha256_digest(b"chunked blob");
+        let mut writer = store
+            .blob_writer("text/plain", Some(&expected))
+            .await
+            .unwrap();
+        writer.write(b"chunked ").await.unwrap();
+        writer.write(b"blob").await.unwrap();
+        let (digest, _) = writer.commit().await.unwrap();
+
+        assert_eq!(
+            store.get_blob(&digest, 0, 0).await.unwrap(),
+            b"chunked blob".to_vec()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_blob_ranges() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileSystemStore::new(dir.path()).await.unwrap();
+        let (digest, _) = store
+            .put_blob("text/plain", b"0123456789", None)
+            .await
+            .unwrap();
+
+        assert_eq!(store.get_blob(&digest, 0, -1).await.unwrap(), b"0123456789");
+        assert_eq!(store.get_blob(&digest, 3, 4).await.unwrap(), b"3456");
+        assert_eq!(store.get_blob(&digest, 7, -1).await.unwrap(), b"789");
+        assert_eq!(store.get_blob(&digest, 6, 4).await.unwrap(), b"6789");
+        assert!(store.get_blob(&digest, 10, -1).await.unwrap().is_empty());
+
+        for (offset, length) in [(11, -1), (6, 5), (-1, 2)] {
+            assert!(matches!(
+                store.get_blob(&digest, offset, length).await,
+                Err(StoreError::OutOfRange { size: 10, .. })
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_blob_writer_async_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileSystemStore::new(dir.path()).await.unwrap();
+
+        let expected = sha256_digest(b"streamed blob");
+        let mut writer = store
+            .blob_writer("text/plain", Some(&expected))
+            .await
+            .unwrap();
+        AsyncWriteExt::write_all(&mut writer, b"streamed blob")
+            .await
+            .unwrap();
+        let (digest, size) = writer.commit().await.unwrap();
+
+        assert_eq!(digest.hash, expected.hash);
+        assert_eq!(size, 13);
+    }
+}